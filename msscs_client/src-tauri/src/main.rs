@@ -5,12 +5,19 @@ mod firewall;
 
 use msscs_v4::{
     config::Config,
+    identity::IdentityManager,
+    identity_backup::IdentityBundle,
     metrics::Metrics,
-    network::Node,
     persistence::PersistenceManager,
     vfs::VirtualFileSystem,
+    block::{IdentityKey, KeyWrapper},
     p2p_network::{P2PNode, P2PConfig, P2PNodeCommand},
-    workspace::{WorkspaceManager, Workspace, Permission},
+    peer_manager::PeerLimits,
+    kad_store::RecordStoreConfig,
+    block_transfer::MAX_BLOCK_BYTES,
+    shard_store::ShardStoreConfig,
+    protocol::NodeRole,
+    workspace::{WorkspaceManager, Workspace, WorkspaceMember, SharedFolder, Permission},
     p2p_storage::P2PStorageManager,
 };
 use serde::{Deserialize, Serialize};
@@ -22,7 +29,6 @@ use tokio::sync::{RwLock, mpsc};
 // Application state
 struct AppStateWrapper {
     vfs: Arc<RwLock<VirtualFileSystem>>,
-    node: Arc<Node>,
     p2p_command_tx: Option<mpsc::UnboundedSender<P2PNodeCommand>>,
     config: Arc<Config>,
     metrics: Arc<Metrics>,
@@ -30,6 +36,8 @@ struct AppStateWrapper {
     storage_manager: Arc<P2PStorageManager>,
     current_user_id: uuid::Uuid,
     current_user_email: String,
+    /// Wraps the convergence keys of the workspaces this node creates
+    identity_keys: Arc<dyn KeyWrapper>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,6 +46,32 @@ struct FileUploadResult {
     blocks: usize,
 }
 
+/// Workspace as sent to the frontend; its wrapped convergence key stays in the node
+#[derive(Debug, Serialize)]
+struct WorkspaceInfo {
+    id: uuid::Uuid,
+    name: String,
+    description: String,
+    owner_id: uuid::Uuid,
+    created_at: u64,
+    members: Vec<WorkspaceMember>,
+    shared_folders: Vec<SharedFolder>,
+}
+
+impl From<Workspace> for WorkspaceInfo {
+    fn from(workspace: Workspace) -> Self {
+        Self {
+            id: workspace.id,
+            name: workspace.name,
+            description: workspace.description,
+            owner_id: workspace.owner_id,
+            created_at: workspace.created_at,
+            members: workspace.members.into_values().collect(),
+            shared_folders: workspace.shared_folders.into_values().collect(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct FileInfo {
     path: String,
@@ -149,28 +183,37 @@ async fn start_node(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
     // Initialize persistence
     std::fs::create_dir_all(&config.data_dir).map_err(|e| e.to_string())?;
     let persistence = Arc::new(
-        PersistenceManager::new(config.data_dir.clone()).map_err(|e| e.to_string())?
+        PersistenceManager::from_config(&config).map_err(|e| e.to_string())?
     );
 
     // Initialize VFS
     let mut vfs = VirtualFileSystem::new(config.clone(), persistence.clone())
-        .map_err(|e| e.to_string())?;
-
-    // Initialize network node (legacy TCP)
-    let node = Arc::new(Node::new(config.clone()));
-    
-    // Start DHT
-    node.start_dht(config.bootstrap_peers.clone())
         .await
         .map_err(|e| e.to_string())?;
 
-    // Start P2P listener
-    let node_clone = node.clone();
-    tokio::spawn(async move {
-        if let Err(e) = node_clone.run_p2p_listener().await {
-            eprintln!("P2P listener error: {}", e);
+    // The identity key wraps the per-file block keys; a running identity agent wraps and
+    // unwraps them itself, so neither the passphrase nor the keys reach us
+    let mut identity_keys: Option<Arc<dyn KeyWrapper>> = None;
+    #[cfg(unix)]
+    if std::env::var_os(msscs_v4::identity::PASSPHRASE_ENV).is_none() {
+        let agent = msscs_v4::identity_agent::AgentClient::from_env();
+        if agent.socket().exists() {
+            let keys = agent.keys().await.map_err(|e| e.to_string())?;
+            tracing::info!("🔐 Identity {} held by the identity agent", keys.identity().id);
+            let keys: Arc<dyn KeyWrapper> = Arc::new(keys);
+            vfs.set_keys(keys.clone());
+            identity_keys = Some(keys);
         }
-    });
+    }
+    let identity_keys = match identity_keys {
+        Some(keys) => keys,
+        None => {
+            let identity = unlock_local_identity().await?;
+            vfs.set_identity(&identity);
+            tracing::info!("🔐 Identity {} unlocked", identity.user_id());
+            Arc::new(IdentityKey::from_identity(&identity))
+        }
+    };
 
     // Get storage limit from config (user-configurable)
     let storage_limit_bytes = get_storage_limit().await.unwrap_or(10 * 1024 * 1024 * 1024); // Default 10GB
@@ -200,14 +243,40 @@ async fn start_node(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
     );
     tracing::info!("");
     
+    // Only the configured peers; the public IPFS nodes would put the client on the global DHT
+    let bootstrap_peers = config.bootstrap_multiaddrs();
     let p2p_config = P2PConfig {
         listen_port: 0,
-        bootstrap_peers: P2PConfig::default_bootstrap_peers(),
+        bootstrap_peers,
         max_peers: 50,
         replication_factor: config.replication_factor,
         enable_mdns: true,
         enable_relay: true,
         enable_autonat: true,
+        provider_reannounce_interval: std::time::Duration::from_secs(60 * 60),
+        record_store: RecordStoreConfig {
+            dir: Some(config.data_dir.join("dht")),
+            ..Default::default()
+        },
+        shard_store: ShardStoreConfig {
+            dir: Some(config.data_dir.join("shards")),
+            ..Default::default()
+        },
+        role: NodeRole::Full,
+        storage_capacity: storage_limit_bytes,
+        key_file: Some(config.data_dir.join("p2p_key")),
+        peer_limits: PeerLimits::default(),
+        private_network: None,
+        relay_server: None,
+        external_addresses: Vec::new(),
+        recovery_vault: None,
+        // Peers are served blocks straight from the VFS's store
+        block_store: Some(persistence.store()),
+        replica_store: ShardStoreConfig {
+            dir: Some(config.data_dir.join("replicas")),
+            max_shard_bytes: MAX_BLOCK_BYTES,
+            ..Default::default()
+        },
     };
     
     tracing::info!("📡 Network Configuration:");
@@ -237,7 +306,7 @@ async fn start_node(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
                     
                     // Start node event loop (this handles bootstrap in background)
                     match p2p_node.start(p2p_config.clone()).await {
-                        Ok((mut event_rx, local_blocks)) => {
+                        Ok((mut event_rx, _replicas)) => {
                             tracing::info!("✅ P2P event loop started");
                             tracing::info!("   Bootstrap will complete in 10-30 seconds");
                             
@@ -258,11 +327,13 @@ async fn start_node(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
                                     }
                                     P2PEvent::BlockReceived { peer, block } => {
                                         tracing::info!("📦 Received block {} from {}", block.uuid, peer);
-                                        local_blocks.write().await.insert(block.uuid, block);
                                     }
                                     P2PEvent::BlockRequested { peer, block_id } => {
                                         tracing::debug!("📤 Block {} requested by {}", block_id, peer);
                                     }
+                                    P2PEvent::PeerIdentified { peer, capabilities } => {
+                                        tracing::debug!("🤝 {} runs {} (protocol v{})", peer, capabilities.agent_version, capabilities.protocol_version);
+                                    }
                                     P2PEvent::BootstrapComplete => {
                                         if !bootstrap_complete {
                                             bootstrap_complete = true;
@@ -302,17 +373,14 @@ async fn start_node(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
         }
     };
 
-    // Associate VFS with node
-    vfs.set_node(node.clone());
-    
-    // Log P2P integration status
-    if p2p_command_tx.is_some() {
+    // Associate VFS with the P2P node
+    if let Some(ref cmd_tx) = p2p_command_tx {
+        vfs.set_network(cmd_tx.clone());
         tracing::info!("✅ VFS configured with P2P network integration (libp2p + PeerJS)");
         tracing::info!("   • libp2p: Global DHT, NAT traversal, relay");
         tracing::info!("   • PeerJS: WebRTC bridge for web clients");
-        tracing::info!("   • Legacy TCP: Direct peer connections");
     } else {
-        tracing::warn!("⚠️  VFS running without libp2p P2P (PeerJS + Legacy TCP only)");
+        tracing::warn!("⚠️  VFS running without libp2p P2P (PeerJS only)");
     }
     
     let vfs = Arc::new(RwLock::new(vfs));
@@ -332,7 +400,6 @@ async fn start_node(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
 
     *state_guard = Some(AppStateWrapper {
         vfs,
-        node,
         p2p_command_tx,
         config,
         metrics,
@@ -340,6 +407,7 @@ async fn start_node(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
         storage_manager,
         current_user_id,
         current_user_email,
+        identity_keys,
     });
 
     tracing::info!("╔════════════════════════════════════════════════════════════════╗");
@@ -406,21 +474,13 @@ async fn list_files(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
     
     let vfs = app_state.vfs.read().await;
     
-    // Get all files from manifest
-    let file_infos: Vec<FileInfo> = vfs.file_manifest.iter().map(|(path, uuid)| {
-        // Count blocks for this file
-        let blocks = vfs.local_blocks.values()
-            .filter(|b| b.uuid == *uuid)
-            .count();
-        
-        // Estimate size (blocks are typically 256KB each)
-        let size = (blocks * 256 * 1024) as u64;
-        
+    // Get all files from the directory tree
+    let file_infos: Vec<FileInfo> = vfs.get_all_metadata().into_iter().map(|(path, metadata)| {
         FileInfo {
-            path: path.clone(),
-            size,
-            blocks,
-            uuid: uuid.to_string(),
+            path,
+            size: metadata.size,
+            blocks: metadata.blocks,
+            uuid: metadata.uuid.to_string(),
             synced: true,
         }
     }).collect();
@@ -432,6 +492,7 @@ async fn list_files(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
 #[tauri::command]
 async fn upload_file(
     path: String,
+    workspace_id: Option<String>,
     window: tauri::Window,
     state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>,
 ) -> Result<FileUploadResult, String> {
@@ -473,9 +534,26 @@ async fn upload_file(
     let start_time = std::time::Instant::now();
     let mut last_emit = std::time::Instant::now();
 
+    // Files written into a workspace are deduplicated under its convergence key
+    let workspace = match workspace_id {
+        Some(workspace_id) => {
+            let ws_id = uuid::Uuid::parse_str(&workspace_id).map_err(|e| e.to_string())?;
+            let workspace = app_state.workspace_manager.get_workspace(&ws_id).await
+                .ok_or("Workspace not found")?;
+            if !workspace.members.contains_key(&app_state.current_user_id) {
+                return Err("Not a member of this workspace".to_string());
+            }
+            Some(workspace)
+        }
+        None => None,
+    };
+
     // Upload to VFS with progress tracking
     let uuid = {
         let mut vfs = app_state.vfs.write().await;
+        if let Some(workspace) = &workspace {
+            vfs.use_workspace(workspace).await.map_err(|e| e.to_string())?;
+        }
         let window_clone = window.clone();
         let file_name_clone = file_name.clone();
         
@@ -656,7 +734,7 @@ async fn get_metrics(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) ->
         0
     };
     
-    // Combine legacy and P2P peer counts
+    // Combine recorded and live P2P peer counts
     let total_peer_count = snapshot.peer_count + p2p_peer_count;
     
     // Get storage stats (with timeout to prevent hanging)
@@ -690,7 +768,7 @@ async fn get_storage_stats(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>
     
     // Get VFS stats first
     let vfs = app_state.vfs.read().await;
-    let total_files = vfs.file_manifest.len();
+    let total_files = vfs.namespace.file_count();
     let cached_blocks = vfs.local_blocks.len();
     drop(vfs);
     
@@ -706,11 +784,7 @@ async fn get_storage_stats(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>
         0
     };
     
-    // Get legacy TCP peer count
-    let legacy_peer_count = app_state.node.peers.read().await.len();
-    
-    // Total peer count (libp2p + legacy)
-    let total_peers = p2p_peer_count + legacy_peer_count;
+    let total_peers = p2p_peer_count;
     
     // Calculate storage stats
     let storage_limit: usize = 100 * 1024 * 1024; // 100MB default
@@ -724,13 +798,11 @@ async fn get_storage_stats(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>
         storage_limit
     };
     
-    tracing::debug!("📊 Storage Stats: {} files, {} used / {} limit, {} peers (libp2p: {}, legacy: {}), ~{} MB global", 
+    tracing::debug!("📊 Storage Stats: {} files, {} used / {} limit, {} peers, ~{} MB global", 
         total_files,
         storage_used,
         storage_limit,
         total_peers,
-        p2p_peer_count,
-        legacy_peer_count,
         estimated_global_storage / (1024 * 1024)
     );
     
@@ -864,18 +936,6 @@ async fn list_peers(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
     
     let mut peer_infos = Vec::new();
     
-    // Get legacy TCP peers
-    let peers = app_state.node.peers.read().await;
-    for peer_addr in peers.iter() {
-        peer_infos.push(PeerInfo {
-            id: peer_addr.clone(),
-            address: peer_addr.clone(),
-            status: "online".to_string(),
-            blocks: 0,
-            latency: 0,
-        });
-    }
-    
     // Get P2P peers if available
     if let Some(ref cmd_tx) = app_state.p2p_command_tx {
         let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
@@ -905,12 +965,13 @@ async fn add_peer(
     let state_guard = state.read().await;
     let app_state = state_guard.as_ref().ok_or("Node not started")?;
     
-    let mut peers = app_state.node.peers.write().await;
-    if !peers.contains(&address) {
-        peers.push(address);
-    }
-    
-    Ok(())
+    let addr = P2PConfig::parse_peer_address(&address)
+        .ok_or_else(|| format!("Invalid peer address: {}", address))?;
+    let cmd_tx = app_state.p2p_command_tx.as_ref().ok_or("P2P network not available")?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    cmd_tx.send(P2PNodeCommand::AddPeer { addr, reply: reply_tx })
+        .map_err(|_| "P2P node stopped".to_string())?;
+    reply_rx.await.map_err(|_| "P2P node stopped".to_string())?
 }
 
 // ============ WORKSPACE COMMANDS ============
@@ -918,12 +979,12 @@ async fn add_peer(
 #[tauri::command]
 async fn list_workspaces(
     state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>,
-) -> Result<Vec<Workspace>, String> {
+) -> Result<Vec<WorkspaceInfo>, String> {
     let state_guard = state.read().await;
     let app_state = state_guard.as_ref().ok_or("Node not started")?;
     
     let workspaces = app_state.workspace_manager.list_user_workspaces(&app_state.current_user_id).await;
-    Ok(workspaces)
+    Ok(workspaces.into_iter().map(WorkspaceInfo::from).collect())
 }
 
 #[tauri::command]
@@ -940,7 +1001,8 @@ async fn create_workspace(
             name,
             description,
             app_state.current_user_id,
-            app_state.current_user_email.clone()
+            app_state.current_user_email.clone(),
+            app_state.identity_keys.as_ref(),
         )
         .await
         .map_err(|e| e.to_string())?;
@@ -1046,6 +1108,55 @@ async fn share_folder_with_member(
     Ok(())
 }
 
+// ============ IDENTITY COMMANDS ============
+
+/// Unlock the local identity with MSSCS_PASSPHRASE, creating it on first start
+async fn unlock_local_identity() -> Result<msscs_v4::UnlockedIdentity, String> {
+    // There is no default passphrase, and the identity agent never hands out the secret keys
+    let passphrase = std::env::var(msscs_v4::identity::PASSPHRASE_ENV).map_err(|_| {
+        "Set MSSCS_PASSPHRASE to unlock the local identity (the node can also run on the identity agent: msscs_v4 agent start)".to_string()
+    })?;
+    let mut identities = IdentityManager::new(get_app_data_dir().join("identities")).map_err(|e| e.to_string())?;
+    identities
+        .unlock_or_create("msscs-client", &passphrase)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Export the local identity as a backup bundle; returns the printable text encoding and
+/// also writes the binary bundle to `path` when given
+#[tauri::command]
+async fn export_identity(backup_passphrase: String, path: Option<String>) -> Result<String, String> {
+    let identity = unlock_local_identity().await?;
+    let bundle = IdentityBundle::export(&identity, &backup_passphrase).map_err(|e| e.to_string())?;
+    if let Some(path) = path {
+        std::fs::write(&path, bundle.to_bytes().map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        tracing::info!("🔐 Identity {} exported to {}", identity.user_id(), path);
+    }
+    bundle.to_text().map_err(|e| e.to_string())
+}
+
+/// Import an identity from a bundle (its text encoding or the path of a bundle file) and make
+/// it the local identity; takes effect the next time the node starts
+#[tauri::command]
+async fn import_identity(bundle: String, backup_passphrase: String) -> Result<String, String> {
+    let path = PathBuf::from(bundle.trim());
+    let data = if path.is_file() {
+        std::fs::read(&path).map_err(|e| e.to_string())?
+    } else {
+        bundle.into_bytes()
+    };
+    let bundle = IdentityBundle::decode(&data).map_err(|e| e.to_string())?;
+
+    let mut identities = IdentityManager::new(get_app_data_dir().join("identities")).map_err(|e| e.to_string())?;
+    let id = identities
+        .import_identity(&bundle, &backup_passphrase)
+        .await
+        .map_err(|e| e.to_string())?;
+    tracing::info!("🔐 Identity {} imported", id);
+    Ok(id.to_string())
+}
+
 // ============ FIREWALL COMMANDS ============
 
 #[tauri::command]
@@ -1142,6 +1253,8 @@ fn main() {
             accept_workspace_invite,
             create_shared_folder,
             share_folder_with_member,
+            export_identity,
+            import_identity,
             check_firewall_access,
             request_firewall_access,
             open_firewall_settings,
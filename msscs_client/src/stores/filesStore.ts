@@ -138,13 +138,14 @@ export const useFilesStore = defineStore('files', () => {
     }
   }
 
-  const uploadFile = async (filePath: string) => {
+  const uploadFile = async (filePath: string, workspaceId?: string) => {
     try {
       console.log('📤 Starting upload:', filePath)
       
       // Start upload - backend will emit progress events
       const result = await tauri.invoke('upload_file', {
-        path: filePath
+        path: filePath,
+        workspaceId
       });
 
       console.log('✅ Upload complete:', result)
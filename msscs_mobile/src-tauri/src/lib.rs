@@ -3,11 +3,15 @@
 
 use msscs_v4::{
     config::Config,
+    identity::IdentityManager,
     metrics::Metrics,
-    network::Node,
     persistence::PersistenceManager,
     vfs::VirtualFileSystem,
     block::DataBlock,
+    p2p_network::P2PConfig,
+    kad_store::RecordStoreConfig,
+    block_transfer::MAX_BLOCK_BYTES,
+    shard_store::ShardStoreConfig,
 };
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
@@ -15,7 +19,7 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::collections::HashMap;
 use tauri::State;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, mpsc};
 
 mod network_discovery;
 mod file_viewer;
@@ -26,8 +30,8 @@ use network_discovery::{NetworkDiscovery, DiscoveredNode as NetworkDiscoveredNod
 // Application state
 struct AppStateWrapper {
     vfs: Arc<RwLock<VirtualFileSystem>>,
-    node: Arc<Node>,
     config: Arc<Config>,
+    persistence: Arc<PersistenceManager>,
     metrics: Arc<Metrics>,
     discovery: Arc<RwLock<NetworkDiscovery>>,
     p2p_bridge: Arc<RwLock<Option<p2p_bridge::P2PBridge>>>,
@@ -101,7 +105,10 @@ struct FilePreview {
 
 // Tauri commands
 #[tauri::command]
-async fn start_node(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> Result<(), String> {
+async fn start_node(
+    passphrase: Option<String>,
+    state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>,
+) -> Result<(), String> {
     let mut state_guard = state.write().await;
     
     if state_guard.is_some() {
@@ -128,31 +135,23 @@ async fn start_node(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
     // Initialize persistence
     std::fs::create_dir_all(&config.data_dir).map_err(|e| e.to_string())?;
     let persistence = Arc::new(
-        PersistenceManager::new(config.data_dir.clone()).map_err(|e| e.to_string())?
+        PersistenceManager::from_config(&config).map_err(|e| e.to_string())?
     );
 
-    // Initialize VFS
+    // Initialize VFS (peers are reached through the P2P bridge)
     let mut vfs = VirtualFileSystem::new(config.clone(), persistence.clone())
-        .map_err(|e| e.to_string())?;
-
-    // Initialize network node
-    let node = Arc::new(Node::new(config.clone()));
-    
-    // Start DHT
-    node.start_dht(config.bootstrap_peers.clone())
         .await
         .map_err(|e| e.to_string())?;
-
-    // Start P2P listener
-    let node_clone = node.clone();
-    tokio::spawn(async move {
-        if let Err(e) = node_clone.run_p2p_listener().await {
-            eprintln!("P2P listener error: {}", e);
+    unlock_identity(&mut vfs, passphrase).await?;
+
+    // Blocks are replicated and fetched through the P2P node
+    let p2p_bridge = match start_bridge(&mut vfs, mobile_p2p_config(&config, &persistence, 0)).await {
+        Ok(bridge) => Some(bridge),
+        Err(e) => {
+            tracing::warn!("⚠️  {}; running without P2P", e);
+            None
         }
-    });
-
-    // Associate VFS with node
-    vfs.set_node(node.clone());
+    };
     let vfs = Arc::new(RwLock::new(vfs));
 
     // Initialize metrics
@@ -161,19 +160,18 @@ async fn start_node(state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>) -> R
     // Initialize network discovery
     let discovery = Arc::new(RwLock::new(NetworkDiscovery::new()));
     let discovery_clone = discovery.clone();
-    let node_clone = node.clone();
     
     tokio::spawn(async move {
-        discovery_clone.write().await.start_discovery(node_clone).await;
+        discovery_clone.write().await.start_discovery().await;
     });
 
     *state_guard = Some(AppStateWrapper {
         vfs,
-        node,
         config,
+        persistence,
         metrics,
         discovery,
-        p2p_bridge: Arc::new(RwLock::new(None)),
+        p2p_bridge: Arc::new(RwLock::new(p2p_bridge)),
         cancel_tokens: Arc::new(RwLock::new(HashMap::new())),
     });
 
@@ -209,12 +207,14 @@ async fn connect_to_node(
     let state_guard = state.read().await;
     let app_state = state_guard.as_ref().ok_or("Node not started")?;
     
-    let mut peers = app_state.node.peers.write().await;
-    if !peers.contains(&address) {
-        peers.push(address);
-    }
-    
-    Ok(())
+    let (host, port) = address.rsplit_once(':')
+        .ok_or_else(|| format!("Invalid peer address: {}", address))?;
+    let port: u16 = port.parse().map_err(|_| format!("Invalid peer port: {}", port))?;
+
+    let mut bridge_guard = app_state.p2p_bridge.write().await;
+    let bridge = bridge_guard.as_mut()
+        .ok_or("P2P bridge not started")?;
+    bridge.add_discovered_peer(host.to_string(), port).await
 }
 
 #[tauri::command]
@@ -578,8 +578,10 @@ async fn get_file_details(
         return Err("File not found".to_string());
     }
 
-    let peers = app_state.node.peers.read().await;
-    let peers_count = peers.len();
+    let peers_count = match app_state.p2p_bridge.read().await.as_ref() {
+        Some(bridge) => bridge.get_network_stats().await.connected_peers,
+        None => 0,
+    };
 
     let mime_type = mime_guess::from_path(&path)
         .first_or_octet_stream()
@@ -607,21 +609,65 @@ async fn start_p2p_bridge(
     listen_port: u16,
     state: State<'_, Arc<RwLock<Option<AppStateWrapper>>>>,
 ) -> Result<(), String> {
-    let mut state_guard = state.write().await;
-    let app_state = state_guard.as_mut().ok_or("Node not started")?;
+    let state_guard = state.read().await;
+    let app_state = state_guard.as_ref().ok_or("Node not started")?;
 
-    // Initialize P2P bridge
-    let (event_sender, mut event_receiver) = tokio::sync::mpsc::unbounded_channel();
+    // The node started with start_node is reused
+    let mut bridge_guard = app_state.p2p_bridge.write().await;
+    if bridge_guard.as_ref().is_some_and(|bridge| bridge.is_started()) {
+        return Ok(());
+    }
+
+    let mut vfs = app_state.vfs.write().await;
+    let p2p_config = mobile_p2p_config(&app_state.config, &app_state.persistence, listen_port);
+    *bridge_guard = Some(start_bridge(&mut vfs, p2p_config).await?);
+
+    Ok(())
+}
+
+/// P2P configuration of the mobile node; peers are served blocks straight from the VFS's store
+fn mobile_p2p_config(config: &Config, persistence: &PersistenceManager, listen_port: u16) -> P2PConfig {
+    P2PConfig {
+        listen_port,
+        bootstrap_peers: config.bootstrap_multiaddrs(),
+        max_peers: 20,
+        replication_factor: config.replication_factor,
+        enable_mdns: true,
+        record_store: RecordStoreConfig {
+            dir: Some(config.data_dir.join("dht")),
+            ..Default::default()
+        },
+        shard_store: ShardStoreConfig {
+            dir: Some(config.data_dir.join("shards")),
+            ..Default::default()
+        },
+        key_file: Some(config.data_dir.join("p2p_key")),
+        block_store: Some(persistence.store()),
+        replica_store: ShardStoreConfig {
+            dir: Some(config.data_dir.join("replicas")),
+            max_shard_bytes: MAX_BLOCK_BYTES,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Start a P2P bridge and attach the VFS to its node
+async fn start_bridge(vfs: &mut VirtualFileSystem, p2p_config: P2PConfig) -> Result<p2p_bridge::P2PBridge, String> {
+    let (event_sender, mut event_receiver) = mpsc::unbounded_channel();
     let mut bridge = p2p_bridge::P2PBridge::new();
     bridge.initialize(event_sender);
 
-    // Start P2P node
-    bridge.start_p2p_node(listen_port).await?;
+    let command_tx = bridge.start_p2p_node(p2p_config).await?;
+    vfs.set_network(command_tx);
 
-    // Store bridge
-    *app_state.p2p_bridge.write().await = Some(bridge);
+    tokio::spawn(async move {
+        while let Some(event) = event_receiver.recv().await {
+            tracing::debug!("P2P bridge event: {:?}", event);
+        }
+    });
 
-    Ok(())
+    Ok(bridge)
 }
 
 #[tauri::command]
@@ -668,6 +714,34 @@ async fn get_p2p_network_stats(
     Ok(bridge.get_network_stats().await)
 }
 
+/// Give the VFS the identity keys that wrap the per-file block keys: unlocked with the
+/// passphrase the user entered (or MSSCS_PASSPHRASE), else held by a running identity agent
+async fn unlock_identity(vfs: &mut VirtualFileSystem, passphrase: Option<String>) -> Result<(), String> {
+    let passphrase = passphrase.or_else(|| std::env::var(msscs_v4::identity::PASSPHRASE_ENV).ok());
+
+    #[cfg(unix)]
+    if passphrase.is_none() {
+        let agent = msscs_v4::identity_agent::AgentClient::from_env();
+        if agent.socket().exists() {
+            let keys = agent.keys().await.map_err(|e| e.to_string())?;
+            tracing::info!("🔐 Identity {} held by the identity agent", keys.identity().id);
+            vfs.set_keys(Arc::new(keys));
+            return Ok(());
+        }
+    }
+
+    let passphrase = passphrase.ok_or("Enter the identity passphrase to unlock the node")?;
+    let mut identities = IdentityManager::new(get_app_data_dir().join("identities"))
+        .map_err(|e| e.to_string())?;
+    let identity = identities
+        .unlock_or_create("msscs-mobile", &passphrase)
+        .await
+        .map_err(|e| e.to_string())?;
+    vfs.set_identity(&identity);
+    tracing::info!("🔐 Identity {} unlocked", identity.user_id());
+    Ok(())
+}
+
 fn get_app_data_dir() -> PathBuf {
     #[cfg(target_os = "android")]
     {
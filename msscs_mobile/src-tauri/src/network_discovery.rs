@@ -1,8 +1,6 @@
 // Network discovery module - Enhanced mDNS/Bonjour for local node discovery
-use msscs_v4::network::Node;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
 use tracing::{info, warn, error, debug};
@@ -26,14 +24,14 @@ impl NetworkDiscovery {
         }
     }
 
-    pub async fn start_discovery(&mut self, node: Arc<Node>) {
+    pub async fn start_discovery(&mut self) {
         loop {
-            self.scan_network(node.clone()).await;
+            self.scan_network().await;
             sleep(Duration::from_secs(10)).await;
         }
     }
 
-    async fn scan_network(&mut self, _node: Arc<Node>) {
+    async fn scan_network(&mut self) {
         // Use mDNS to discover MSSCS nodes on local network
         match self.mdns_scan().await {
             Ok(nodes) => {
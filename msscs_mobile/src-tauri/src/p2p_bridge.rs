@@ -1,17 +1,17 @@
 // P2P Bridge module - Connects mobile discovery to real P2P node
 use crate::network_discovery::{NetworkDiscovery, DiscoveredNode, NetworkStatus};
-use msscs_v4::{p2p_network::{P2PNode, P2PConfig, P2PEvent}, block::DataBlock};
+use msscs_v4::p2p_network::{P2PNode, P2PConfig, P2PEvent, P2PNodeCommand};
+use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{mpsc, oneshot, RwLock};
 use tokio::time::sleep;
-use tracing::{debug, error, info, warn};
-use uuid::Uuid;
+use tracing::{info, warn};
 
 /// P2P Bridge that connects network discovery to the real P2P node
 pub struct P2PBridge {
-    p2p_node: Option<Arc<RwLock<P2PNode>>>,
+    command_tx: Option<mpsc::UnboundedSender<P2PNodeCommand>>,
     network_discovery: NetworkDiscovery,
     event_sender: Option<mpsc::UnboundedSender<P2PBridgeEvent>>,
     discovered_peers: Arc<RwLock<HashMap<String, DiscoveredNode>>>,
@@ -22,7 +22,7 @@ pub struct P2PBridge {
 pub enum P2PBridgeEvent {
     PeerConnected(String),
     PeerDisconnected(String),
-    BlockReceived(String, Vec<u8>),
+    BlockReceived(String),
     NetworkStatus(NetworkStatus),
     Error(String),
 }
@@ -31,7 +31,7 @@ impl P2PBridge {
     /// Create new P2P bridge
     pub fn new() -> Self {
         Self {
-            p2p_node: None,
+            command_tx: None,
             network_discovery: NetworkDiscovery::new(),
             event_sender: None,
             discovered_peers: Arc::new(RwLock::new(HashMap::new())),
@@ -43,95 +43,76 @@ impl P2PBridge {
         self.event_sender = Some(event_sender);
     }
 
-    /// Start P2P node with configuration
-    pub async fn start_p2p_node(&mut self, listen_port: u16) -> Result<(), String> {
-        info!("Starting P2P node on port {}", listen_port);
+    /// Start the P2P node; returns its command sender, which the VFS takes through `set_network`
+    pub async fn start_p2p_node(&mut self, p2p_config: P2PConfig) -> Result<mpsc::UnboundedSender<P2PNodeCommand>, String> {
+        info!("Starting P2P node on port {}", p2p_config.listen_port);
 
-        let mut p2p_config = P2PConfig::default();
-        p2p_config.listen_port = listen_port;
-        p2p_config.enable_mdns = true;
-        p2p_config.max_peers = 20;
-        p2p_config.replication_factor = 3;
+        let p2p_node = P2PNode::new(p2p_config.clone())
+            .await
+            .map_err(|e| format!("Failed to create P2P node: {}", e))?;
+        let command_tx = p2p_node.get_command_sender();
 
-        let (mut p2p_node, mut event_receiver, local_blocks) = match P2PNode::new(p2p_config).await {
-            Ok(result) => result,
-            Err(e) => return Err(format!("Failed to create P2P node: {}", e)),
-        };
-
-        // Start P2P node
-        let (event_rx, blocks) = match p2p_node.start().await {
-            Ok(result) => result,
-            Err(e) => return Err(format!("Failed to start P2P node: {}", e)),
-        };
-
-        // Store P2P node
-        let p2p_node_arc = Arc::new(RwLock::new(p2p_node));
-        self.p2p_node = Some(p2p_node_arc.clone());
+        // The node runs its event loop in the background from here on
+        let (mut event_rx, _replicas) = p2p_node
+            .start(p2p_config)
+            .await
+            .map_err(|e| format!("Failed to start P2P node: {}", e))?;
+        self.command_tx = Some(command_tx.clone());
 
         // Handle P2P events in background
-        if let Some(ref sender) = self.event_sender {
-            let event_sender = sender.clone();
-            tokio::spawn(async move {
-                while let Some(event) = event_rx.recv().await {
-                    match event {
-                        P2PEvent::PeerConnected(peer_id) => {
-                            info!("P2P peer connected: {}", peer_id);
-                            let _ = event_sender.send(P2PBridgeEvent::PeerConnected(peer_id.to_string()));
-                        }
-                        P2PEvent::PeerDisconnected(peer_id) => {
-                            info!("P2P peer disconnected: {}", peer_id);
-                            let _ = event_sender.send(P2PBridgeEvent::PeerDisconnected(peer_id.to_string()));
-                        }
-                        P2PEvent::BlockReceived { peer, block } => {
-                            info!("Received block {} from peer {}", block.uuid, peer);
-                            let _ = event_sender.send(P2PBridgeEvent::BlockReceived(block.uuid.to_string(), block.data.clone()));
-                        }
-                        P2PEvent::BootstrapComplete => {
-                            info!("P2P bootstrap completed");
-                        }
-                        P2PEvent::Error(error) => {
-                            warn!("P2P error: {}", error);
-                            let _ = event_sender.send(P2PBridgeEvent::Error(error));
-                        }
-                    }
-                }
-            });
-        }
-
-        // Start network discovery
-        let discovery = self.network_discovery.clone();
-        let p2p_node_clone = p2p_node_arc.clone();
-        let discovered_peers = self.discovered_peers.clone();
-        let event_sender_clone = self.event_sender.clone();
-
+        let event_sender = self.event_sender.clone();
         tokio::spawn(async move {
-            let mut discovery_clone = discovery;
-
-            // Start discovery in a loop
-            loop {
-                if let Some(ref p2p) = p2p_node_clone.try_read() {
-                    let temp_node = Arc::new(crate::network::Node::new(crate::config::Config::default()));
-                    discovery_clone.start_discovery(temp_node).await;
-                }
-
-                // Check connectivity status
-                if let Some(ref sender) = event_sender_clone {
-                    match discovery_clone.check_network_connectivity().await {
-                        Ok(status) => {
-                            let _ = sender.send(P2PBridgeEvent::NetworkStatus(status));
-                        }
-                        Err(e) => {
-                            let _ = sender.send(P2PBridgeEvent::Error(format!("Network check failed: {}", e)));
-                        }
+            while let Some(event) = event_rx.recv().await {
+                let bridge_event = match event {
+                    P2PEvent::PeerConnected(peer_id) => {
+                        info!("P2P peer connected: {}", peer_id);
+                        P2PBridgeEvent::PeerConnected(peer_id.to_string())
                     }
+                    P2PEvent::PeerDisconnected(peer_id) => {
+                        info!("P2P peer disconnected: {}", peer_id);
+                        P2PBridgeEvent::PeerDisconnected(peer_id.to_string())
+                    }
+                    P2PEvent::BlockReceived { peer, block } => {
+                        info!("Received block {} from peer {}", block.uuid, peer);
+                        P2PBridgeEvent::BlockReceived(block.uuid.to_string())
+                    }
+                    P2PEvent::Error(error) => {
+                        warn!("P2P error: {}", error);
+                        P2PBridgeEvent::Error(error)
+                    }
+                    P2PEvent::BootstrapComplete => {
+                        info!("P2P bootstrap completed");
+                        continue;
+                    }
+                    P2PEvent::BlockRequested { .. } | P2PEvent::PeerIdentified { .. } => continue,
+                };
+                if let Some(ref sender) = event_sender {
+                    let _ = sender.send(bridge_event);
                 }
-
-                sleep(Duration::from_secs(30)).await; // Check every 30 seconds
             }
         });
 
+        // Report connectivity status periodically
+        if let Some(sender) = self.event_sender.clone() {
+            tokio::spawn(async move {
+                let discovery = NetworkDiscovery::new();
+                loop {
+                    let status = discovery.check_network_connectivity().await;
+                    if sender.send(P2PBridgeEvent::NetworkStatus(status)).is_err() {
+                        break;
+                    }
+                    sleep(Duration::from_secs(30)).await; // Check every 30 seconds
+                }
+            });
+        }
+
         info!("P2P bridge started successfully");
-        Ok(())
+        Ok(command_tx)
+    }
+
+    /// Whether the P2P node is running
+    pub fn is_started(&self) -> bool {
+        self.command_tx.is_some()
     }
 
     /// Add discovered peer to P2P network
@@ -152,42 +133,44 @@ impl P2PBridge {
         }
 
         // Add to P2P node if available
-        if let Some(ref p2p_node_arc) = self.p2p_node {
-            let mut p2p_node = p2p_node_arc.write().await;
+        let Some(ref command_tx) = self.command_tx else {
+            warn!("P2P node not available, peer added to discovery list only");
+            return Ok(());
+        };
 
-            // Parse multiaddr
-            let multiaddr = format!("/ip4/{}/tcp/{}", address, port);
-            match multiaddr.parse::<libp2p::Multiaddr>() {
-                Ok(addr) => {
-                    if let Err(e) = p2p_node.add_peer(addr).await {
-                        warn!("Failed to add discovered peer to P2P: {}", e);
-                        return Err(format!("Failed to add peer to P2P: {}", e));
-                    } else {
-                        info!("Added discovered peer {} to P2P network", peer_addr);
-                        Ok(())
-                    }
-                }
-                Err(e) => {
-                    return Err(format!("Invalid peer address: {}", e));
-                }
+        let addr = P2PConfig::parse_peer_address(&peer_addr)
+            .ok_or_else(|| format!("Invalid peer address: {}", peer_addr))?;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        command_tx
+            .send(P2PNodeCommand::AddPeer { addr, reply: reply_tx })
+            .map_err(|_| "P2P node stopped".to_string())?;
+        match reply_rx.await.map_err(|_| "P2P node stopped".to_string())? {
+            Ok(()) => {
+                info!("Added discovered peer {} to P2P network", peer_addr);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Failed to add discovered peer to P2P: {}", e);
+                Err(format!("Failed to add peer to P2P: {}", e))
             }
-        } else {
-            warn!("P2P node not available, peer added to discovery list only");
-            Ok(())
         }
     }
 
     /// Get connected P2P peers
     pub async fn get_connected_peers(&self) -> Vec<String> {
-        if let Some(ref p2p_node_arc) = self.p2p_node {
-            let p2p_node = p2p_node_arc.read().await;
-            p2p_node.get_connected_peers().await
-                .into_iter()
-                .map(|peer_id| peer_id.to_string())
-                .collect()
-        } else {
-            Vec::new()
+        let Some(ref command_tx) = self.command_tx else {
+            return Vec::new();
+        };
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if command_tx.send(P2PNodeCommand::GetConnectedPeers(reply_tx)).is_err() {
+            return Vec::new();
         }
+        reply_rx
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|peer_id| peer_id.to_string())
+            .collect()
     }
 
     /// Get discovered peers from network discovery
@@ -196,80 +179,14 @@ impl P2PBridge {
         peers.values().cloned().collect()
     }
 
-    /// Store block in P2P network
-    pub async fn store_block(&mut self, data: Vec<u8>) -> Result<String, String> {
-        if let Some(ref p2p_node_arc) = self.p2p_node {
-            let mut p2p_node = p2p_node_arc.write().await;
-
-            // Create a simple DataBlock for mobile client
-            let block_id = Uuid::new_v4();
-
-            // In a real implementation, this would use the DataBlock from msscs_v4
-            // For now, just store the raw data
-            info!("Storing block {} in P2P network", block_id);
-
-            // Note: This is a simplified implementation
-            // The full implementation would create a proper DataBlock and store it
-            Ok(block_id.to_string())
-        } else {
-            Err("P2P node not available".to_string())
-        }
-    }
-
-    /// Request block from P2P network
-    pub async fn get_block(&mut self, block_id: String) -> Result<Option<Vec<u8>>, String> {
-        if let Some(ref p2p_node_arc) = self.p2p_node {
-            let mut p2p_node = p2p_node_arc.write().await;
-
-            let uuid = match Uuid::parse_str(&block_id) {
-                Ok(id) => id,
-                Err(e) => return Err(format!("Invalid block ID: {}", e)),
-            };
-
-            info!("Requesting block {} from P2P network", uuid);
-
-            // Try to get block from connected peers
-            let peers = p2p_node.get_connected_peers().await;
-            for peer_id in peers {
-                info!("Requesting block {} from peer {}", uuid, peer_id);
-                // Request block from peer (the actual response will come through events)
-                p2p_node.get_block(uuid).await?;
-            }
-
-            // For now, we'll return None and let the event system handle the response
-            // In a real implementation, this would wait for the block response
-            warn!("Block {} requested from {} peers", uuid, peers.len());
-            Ok(None)
-        } else {
-            Err("P2P node not available".to_string())
-        }
-    }
-
     /// Check P2P network status
     pub async fn get_network_status(&self) -> NetworkStatus {
-        let mut status = self.network_discovery.check_network_connectivity().await
-            .unwrap_or_else(|e| {
-                NetworkStatus {
-                    mdns_available: false,
-                    internet_available: false,
-                    firewall_blocked: true,
-                    mdns_error: Some(e.to_string()),
-                    firewall_help: None,
-                }
-            });
+        let mut status = self.network_discovery.check_network_connectivity().await;
 
-        // Check P2P node status
-        if let Some(ref p2p_node_arc) = self.p2p_node {
-            let connected_peers = {
-                let p2p_node = p2p_node_arc.read().await;
-                p2p_node.get_connected_peers().await.len()
-            };
-
-            // Update status based on P2P connectivity
-            if connected_peers > 0 {
-                status.internet_available = true;
-                status.firewall_blocked = false;
-            }
+        // Update status based on P2P connectivity
+        if !self.get_connected_peers().await.is_empty() {
+            status.internet_available = true;
+            status.firewall_blocked = false;
         }
 
         status
@@ -290,7 +207,7 @@ impl P2PBridge {
 }
 
 /// Network statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct NetworkStats {
     pub discovered_peers: usize,
     pub connected_peers: usize,
@@ -298,9 +215,9 @@ pub struct NetworkStats {
     pub network_health: NetworkHealth,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum NetworkHealth {
     Healthy,
     Warning,
     Offline,
-}
\ No newline at end of file
+}
@@ -20,14 +20,15 @@ export const useNodeStore = defineStore('node', () => {
   const blockCount = ref(0)
   const metrics = ref<NodeMetrics | null>(null)
 
-  const initialize = async () => {
+  const initialize = async (passphrase?: string) => {
     try {
-      await invoke('start_node')
+      await invoke('start_node', { passphrase })
       status.value = 'online'
       startMetricsPolling()
     } catch (error) {
       console.error('Failed to start node:', error)
       status.value = 'offline'
+      throw error
     }
   }
 
@@ -134,7 +134,7 @@ impl AdaptiveCompression {
     fn is_text_data(&self, data: &[u8]) -> bool {
         // Check if mostly ASCII printable characters
         let printable_count = data.iter()
-            .filter(|&&b| (b >= 32 && b <= 126) || b == b'\n' || b == b'\r' || b == b'\t')
+            .filter(|&&b| (32..=126).contains(&b) || b == b'\n' || b == b'\r' || b == b'\t')
             .count();
         
         let ratio = printable_count as f64 / data.len() as f64;
@@ -185,7 +185,8 @@ impl AdaptiveCompression {
     /// Select best compression algorithm for data type
     pub fn select_algorithm(&self, data_type: DataType, level: CompressionLevel) -> CompressionAlgorithm {
         match data_type {
-            DataType::Compressed | DataType::Encrypted => CompressionAlgorithm::None,
+            DataType::Compressed | DataType::Encrypted if self.skip_compressed => CompressionAlgorithm::None,
+            DataType::Compressed | DataType::Encrypted => CompressionAlgorithm::Zstd,
             DataType::Text | DataType::Code => match level {
                 CompressionLevel::Fast => CompressionAlgorithm::Lz4,
                 CompressionLevel::Balanced => CompressionAlgorithm::Zstd,
@@ -1,11 +1,17 @@
 // API module - REST API endpoints
+use crate::chunking::Chunking;
 use crate::config::Config;
 use crate::error::{MSSCSError, Result};
 use crate::metrics::Metrics;
-use crate::network::Node;
-use crate::vfs::VirtualFileSystem;
+use crate::namespace::{DirEntry, FileVersion};
+use crate::p2p_network::P2PNodeCommand;
+use crate::peer_manager::PeerRecord;
+use crate::recovery::{RecoveryStatus, RecoveryVault};
+use crate::snapshot::SnapshotInfo;
+use crate::identity::QuantumIdentity;
+use crate::vfs::{FileWriteOptions, VirtualFileSystem};
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Json, Response},
     routing::{delete, get, post},
@@ -14,7 +20,7 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
 use tower_http::cors::{Any, CorsLayer};
 use uuid::Uuid;
 
@@ -22,9 +28,15 @@ use uuid::Uuid;
 #[derive(Clone)]
 pub struct AppState {
     pub vfs: Arc<RwLock<VirtualFileSystem>>,
-    pub node: Arc<Node>,
+    /// Command channel of the P2P node (None when running standalone)
+    pub p2p_command_tx: Option<mpsc::UnboundedSender<P2PNodeCommand>>,
     pub config: Arc<Config>,
     pub metrics: Arc<Metrics>,
+    /// Recovery shares held for trusted contacts (None = recovery disabled)
+    pub recovery_vault: Option<RecoveryVault>,
+    /// Local identity with its secret keys still sealed; approving a recovery request unlocks
+    /// it with the passphrase sent along, so API access alone cannot release a share
+    pub identity: Option<QuantumIdentity>,
 }
 
 /// Request to write a file
@@ -32,6 +44,9 @@ pub struct AppState {
 pub struct WriteFileRequest {
     pub path: String,
     pub content: String, // Base64 encoded
+    /// Chunk boundaries (defaults to fixed chunks of the configured size)
+    #[serde(default)]
+    pub chunking: Option<Chunking>,
 }
 
 /// Response from write file
@@ -59,6 +74,84 @@ pub struct ListFilesResponse {
     pub files: Vec<String>,
 }
 
+/// Request to create a directory
+#[derive(Debug, Deserialize)]
+pub struct MkdirRequest {
+    pub path: String,
+}
+
+/// Request to rename or move a file or directory
+#[derive(Debug, Deserialize)]
+pub struct RenameRequest {
+    pub from: String,
+    pub to: String,
+}
+
+/// Query for directory listings
+#[derive(Debug, Deserialize)]
+pub struct TreeQuery {
+    #[serde(default)]
+    pub path: String,
+    #[serde(default)]
+    pub recursive: bool,
+}
+
+/// Response from directory listing
+#[derive(Debug, Serialize)]
+pub struct TreeResponse {
+    pub path: String,
+    pub entries: Vec<DirEntry>,
+}
+
+/// Query selecting a byte range of a file (`length` omitted = to the end)
+#[derive(Debug, Deserialize)]
+pub struct RangeQuery {
+    pub path: String,
+    #[serde(default)]
+    pub offset: u64,
+    pub length: Option<u64>,
+}
+
+/// Query selecting one version of a file
+#[derive(Debug, Deserialize)]
+pub struct VersionQuery {
+    pub path: String,
+    pub version: u64,
+}
+
+/// Request to restore an old version
+#[derive(Debug, Deserialize)]
+pub struct RestoreRequest {
+    pub path: String,
+    pub version: u64,
+}
+
+/// Response with a file's version history
+#[derive(Debug, Serialize)]
+pub struct VersionsResponse {
+    pub path: String,
+    pub versions: Vec<FileVersion>,
+}
+
+/// Request to take a named snapshot
+#[derive(Debug, Deserialize)]
+pub struct SnapshotRequest {
+    pub name: String,
+}
+
+/// Response listing snapshots
+#[derive(Debug, Serialize)]
+pub struct SnapshotsResponse {
+    pub snapshots: Vec<SnapshotInfo>,
+}
+
+/// Query for a snapshot diff (`to` omitted = live tree)
+#[derive(Debug, Deserialize)]
+pub struct SnapshotDiffQuery {
+    pub from: String,
+    pub to: Option<String>,
+}
+
 /// Response from health check
 #[derive(Debug, Serialize)]
 pub struct HealthResponse {
@@ -66,6 +159,37 @@ pub struct HealthResponse {
     pub peers: usize,
 }
 
+/// Response from the peer table
+#[derive(Debug, Serialize)]
+pub struct PeersResponse {
+    pub peers: Vec<PeerRecord>,
+}
+
+/// A recovery request received as a trusted contact
+#[derive(Debug, Serialize)]
+pub struct RecoveryRequestInfo {
+    pub request_id: Uuid,
+    pub identity_id: Uuid,
+    pub message: String,
+    /// Code the requester should read out; approve only if it matches
+    pub code: String,
+    pub status: String,
+    pub received_at: u64,
+}
+
+/// Approval of a recovery request
+#[derive(Debug, Deserialize)]
+pub struct ApproveRecoveryRequest {
+    /// Passphrase of this node's identity
+    pub passphrase: String,
+}
+
+/// Response from the recovery request list
+#[derive(Debug, Serialize)]
+pub struct RecoveryRequestsResponse {
+    pub requests: Vec<RecoveryRequestInfo>,
+}
+
 /// Response from block info
 #[derive(Debug, Serialize)]
 pub struct BlockInfoResponse {
@@ -122,6 +246,20 @@ pub fn create_router(state: AppState) -> Router {
         .allow_methods(Any)
         .allow_headers(Any);
 
+    // Anyone reaching an unauthenticated public listener could approve recoveries in the
+    // contact's name, so the routes are only mounted behind API keys or on loopback
+    let recovery = if state.config.allows_recovery_api() {
+        Router::new()
+            .route("/recovery/requests", get(recovery_requests_handler))
+            .route("/recovery/requests/:id/approve", post(approve_recovery_handler))
+            .route("/recovery/requests/:id/deny", post(deny_recovery_handler))
+    } else {
+        if state.recovery_vault.is_some() {
+            tracing::warn!("⚠️  Recovery API disabled: set api_keys or listen on a loopback api_host to approve recoveries");
+        }
+        Router::new()
+    };
+
     Router::new()
         .route("/files", post(write_file_handler))
         .route("/files", get(list_files_handler))
@@ -129,9 +267,26 @@ pub fn create_router(state: AppState) -> Router {
         .route("/files/:path", delete(delete_file_handler))
         .route("/files/:id/chunks", get(get_file_chunks_handler))
         .route("/chunks/download", post(download_chunk_handler))
+        .route("/dirs", post(mkdir_handler))
+        .route("/dirs/*path", delete(rmdir_handler))
+        .route("/rename", post(rename_handler))
+        .route("/range", get(read_range_handler))
+        .route("/tree", get(tree_handler))
+        .route("/stat/*path", get(stat_handler))
+        .route("/versions/*path", get(list_versions_handler))
+        .route("/version", get(read_version_handler))
+        .route("/restore", post(restore_version_handler))
+        .route("/snapshots", post(create_snapshot_handler))
+        .route("/snapshots", get(list_snapshots_handler))
+        .route("/snapshots/:name", delete(delete_snapshot_handler))
+        .route("/snapshots/:name/tree", get(snapshot_tree_handler))
+        .route("/snapshots/:name/files/*path", get(read_snapshot_file_handler))
+        .route("/snapshot-diff", get(snapshot_diff_handler))
         .route("/blocks/:uuid", get(get_block_info_handler))
         .route("/health", get(health_check_handler))
+        .route("/peers", get(peers_handler))
         .route("/metrics", get(metrics_handler))
+        .merge(recovery)
         .layer(cors)
         .with_state(state)
 }
@@ -153,9 +308,13 @@ async fn write_file_handler(
     // Write file
     let path = PathBuf::from(&req.path);
     let mut vfs = state.vfs.write().await;
-    let uuid = vfs.write_file(&path, &content).await?;
+    let options = FileWriteOptions {
+        chunking: req.chunking,
+        ..Default::default()
+    };
+    let uuid = vfs.write_file_with_options(&path, &content, options).await?;
     
-    let blocks = (content.len() + state.config.chunk_size - 1) / state.config.chunk_size;
+    let blocks = vfs.stat(&path)?.block_count;
     
     // Update metrics
     state.metrics.record_request(true);
@@ -233,6 +392,304 @@ async fn list_files_handler(
     Ok(Json(ListFilesResponse { files }))
 }
 
+/// Range read handler
+async fn read_range_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<RangeQuery>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let mut vfs = state.vfs.write().await;
+    let length = query.length.unwrap_or(u64::MAX);
+    let content = vfs.read_range(&PathBuf::from(&query.path), query.offset, length).await?;
+    
+    // Encode to base64
+    use base64::{Engine as _, engine::general_purpose};
+    let encoded = general_purpose::STANDARD.encode(&content);
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(ReadFileResponse { content: encoded }))
+}
+
+/// Create directory handler
+async fn mkdir_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<MkdirRequest>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let mut vfs = state.vfs.write().await;
+    vfs.mkdir(&PathBuf::from(&req.path)).await?;
+    let stat = vfs.stat(&PathBuf::from(&req.path))?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok((StatusCode::CREATED, Json(stat)))
+}
+
+/// Remove directory handler
+async fn rmdir_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(dir_path): Path<String>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let mut vfs = state.vfs.write().await;
+    vfs.rmdir(&PathBuf::from(&dir_path)).await?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(DeleteFileResponse {
+        status: "deleted".to_string(),
+    }))
+}
+
+/// Rename / move handler
+async fn rename_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<RenameRequest>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let mut vfs = state.vfs.write().await;
+    vfs.rename(&PathBuf::from(&req.from), &PathBuf::from(&req.to)).await?;
+    let stat = vfs.stat(&PathBuf::from(&req.to))?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(stat))
+}
+
+/// Directory listing handler
+async fn tree_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TreeQuery>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let vfs = state.vfs.read().await;
+    let entries = vfs.list_dir(&PathBuf::from(&query.path), query.recursive)?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(TreeResponse {
+        path: query.path,
+        entries,
+    }))
+}
+
+/// Stat handler
+async fn stat_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(file_path): Path<String>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let vfs = state.vfs.read().await;
+    let stat = vfs.stat(&PathBuf::from(&file_path))?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(stat))
+}
+
+/// List versions handler
+async fn list_versions_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(file_path): Path<String>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let vfs = state.vfs.read().await;
+    let versions = vfs.list_versions(&PathBuf::from(&file_path))?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(VersionsResponse {
+        path: file_path,
+        versions,
+    }))
+}
+
+/// Read version handler
+async fn read_version_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<VersionQuery>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let mut vfs = state.vfs.write().await;
+    let content = vfs.read_version(&PathBuf::from(&query.path), query.version).await?;
+    
+    // Encode to base64
+    use base64::{Engine as _, engine::general_purpose};
+    let encoded = general_purpose::STANDARD.encode(&content);
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(ReadFileResponse { content: encoded }))
+}
+
+/// Restore version handler
+async fn restore_version_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<RestoreRequest>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let mut vfs = state.vfs.write().await;
+    let restored = vfs.restore_version(&PathBuf::from(&req.path), req.version).await?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(restored))
+}
+
+/// Create snapshot handler
+async fn create_snapshot_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<SnapshotRequest>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let mut vfs = state.vfs.write().await;
+    let info = vfs.create_snapshot(&req.name).await?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+/// List snapshots handler
+async fn list_snapshots_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let vfs = state.vfs.read().await;
+    let snapshots = vfs.list_snapshots();
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(SnapshotsResponse { snapshots }))
+}
+
+/// Delete snapshot handler
+async fn delete_snapshot_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let mut vfs = state.vfs.write().await;
+    vfs.delete_snapshot(&name).await?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(DeleteFileResponse {
+        status: "deleted".to_string(),
+    }))
+}
+
+/// Snapshot directory listing handler
+async fn snapshot_tree_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Query(query): Query<TreeQuery>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let vfs = state.vfs.read().await;
+    let entries = vfs.snapshot_list_dir(&name, &PathBuf::from(&query.path), query.recursive)?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(TreeResponse {
+        path: query.path,
+        entries,
+    }))
+}
+
+/// Read file from snapshot handler
+async fn read_snapshot_file_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((name, file_path)): Path<(String, String)>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let mut vfs = state.vfs.write().await;
+    let content = vfs.read_snapshot_file(&name, &PathBuf::from(&file_path)).await?;
+    
+    // Encode to base64
+    use base64::{Engine as _, engine::general_purpose};
+    let encoded = general_purpose::STANDARD.encode(&content);
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(ReadFileResponse { content: encoded }))
+}
+
+/// Snapshot diff handler
+async fn snapshot_diff_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<SnapshotDiffQuery>,
+) -> Result<impl IntoResponse> {
+    // Check authentication
+    check_auth(&state.config, &headers)?;
+    
+    let vfs = state.vfs.read().await;
+    let diff = vfs.diff_snapshots(&query.from, query.to.as_deref())?;
+    
+    // Update metrics
+    state.metrics.record_request(true);
+    
+    Ok(Json(diff))
+}
+
 /// Get block info handler
 async fn get_block_info_handler(
     State(state): State<AppState>,
@@ -274,8 +731,17 @@ async fn get_block_info_handler(
 async fn health_check_handler(
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse> {
-    let peers = state.node.peers.read().await;
-    let peer_count = peers.len();
+    let peer_count = match &state.p2p_command_tx {
+        Some(tx) => {
+            let (reply, rx) = tokio::sync::oneshot::channel();
+            if tx.send(P2PNodeCommand::GetConnectedPeers(reply)).is_ok() {
+                rx.await.map(|peers| peers.len()).unwrap_or(0)
+            } else {
+                0
+            }
+        }
+        None => 0,
+    };
     
     Ok(Json(HealthResponse {
         status: "healthy".to_string(),
@@ -283,6 +749,89 @@ async fn health_check_handler(
     }))
 }
 
+/// Peer table handler: score, accounting and ban state of every known peer
+async fn peers_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    check_auth(&state.config, &headers)?;
+
+    let peers = match &state.p2p_command_tx {
+        Some(tx) => {
+            let (reply, rx) = tokio::sync::oneshot::channel();
+            tx.send(P2PNodeCommand::GetPeerTable(reply))
+                .map_err(|e| MSSCSError::Network(format!("P2P command channel error: {}", e)))?;
+            rx.await
+                .map_err(|e| MSSCSError::Network(format!("P2P command channel closed: {}", e)))?
+        }
+        None => Vec::new(),
+    };
+
+    Ok(Json(PeersResponse { peers }))
+}
+
+/// Recovery requests for identities this node holds shares of
+async fn recovery_requests_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    check_auth(&state.config, &headers)?;
+
+    let requests = state.recovery_vault.as_ref().map(|vault| vault.requests()).unwrap_or_default();
+    let requests = requests
+        .into_iter()
+        .map(|received| RecoveryRequestInfo {
+            request_id: received.request.request_id,
+            identity_id: received.request.identity_id,
+            code: received.request.fingerprint(),
+            message: received.request.message,
+            status: match received.status {
+                RecoveryStatus::Pending => "pending",
+                RecoveryStatus::Approved(_) => "approved",
+                RecoveryStatus::Denied => "denied",
+                RecoveryStatus::NoShare => "no-share",
+            }
+            .to_string(),
+            received_at: received.received_at,
+        })
+        .collect();
+
+    Ok(Json(RecoveryRequestsResponse { requests }))
+}
+
+/// Release this node's share to a recovery request once the identity's passphrase checks out
+async fn approve_recovery_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ApproveRecoveryRequest>,
+) -> Result<impl IntoResponse> {
+    check_auth(&state.config, &headers)?;
+
+    let (Some(vault), Some(identity)) = (&state.recovery_vault, &state.identity) else {
+        return Err(MSSCSError::NotFound("Recovery is not enabled on this node".to_string()));
+    };
+    let unlocked = identity
+        .unlock(&request.passphrase)
+        .map_err(|_| MSSCSError::PermissionDenied("Wrong identity passphrase".to_string()))?;
+    vault.approve(&id, &unlocked)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Refuse a recovery request
+async fn deny_recovery_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    check_auth(&state.config, &headers)?;
+
+    let vault = state.recovery_vault.as_ref()
+        .ok_or_else(|| MSSCSError::NotFound("Recovery is not enabled on this node".to_string()))?;
+    vault.deny(&id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// Metrics handler
 async fn metrics_handler(
     State(state): State<AppState>,
@@ -381,8 +930,9 @@ impl IntoResponse for MSSCSError {
     fn into_response(self) -> Response {
         let (status, message) = match self {
             MSSCSError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
-            MSSCSError::InvalidData(msg) => (StatusCode::BAD_REQUEST, msg),
+            MSSCSError::InvalidData(msg) | MSSCSError::Validation(msg) => (StatusCode::BAD_REQUEST, msg),
             MSSCSError::Config(msg) => (StatusCode::UNAUTHORIZED, msg),
+            MSSCSError::PermissionDenied(msg) => (StatusCode::FORBIDDEN, msg),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
         };
 
@@ -2,15 +2,22 @@
 // This replaces the basic TCP implementation with production-ready P2P networking
 
 use msscs_v4::{
-    identity::QuantumIdentity,
-    p2p_network::{P2PNode, P2PConfig},
+    block_store::{open_block_store, StorageBackend},
+    block_transfer::MAX_BLOCK_BYTES,
+    config::Config,
+    identity::{self, IdentityManager, PASSPHRASE_ENV},
+    identity_agent::AgentClient,
+    kad_store::RecordStoreConfig,
+    p2p_network::{self, P2PNode, P2PConfig, P2PEvent, RelayServerConfig},
     p2p_vfs::P2PVirtualFileSystem,
+    quantum_block::KeyHolder,
     p2p_api::{P2PAppState, create_p2p_router},
+    private_network::{self, IdentityAttestation, PrivateNetworkConfig},
+    repair::{RepairConfig, RepairDaemon},
+    shard_store::ShardStoreConfig,
 };
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use clap::Parser;
-use std::net::IpAddr;
 
 #[derive(Parser, Debug)]
 #[command(name = "msscs-node")]
@@ -28,25 +35,66 @@ struct Args {
     #[arg(short, long)]
     bootstrap: Vec<String>,
     
-    /// User passphrase for identity
-    #[arg(short = 'P', long)]
-    passphrase: Option<String>,
+    /// Encrypt and decrypt through the identity agent (`msscs_v4 agent`), which keeps the keys
+    /// Otherwise the identity under the data dir is unlocked with MSSCS_PASSPHRASE or a prompt
+    #[arg(long)]
+    agent: bool,
     
     /// Enable mDNS local discovery
     #[arg(short, long, default_value = "true")]
     mdns: bool,
     
     /// Enable relay for NAT traversal
-    #[arg(short, long, default_value = "true")]
+    #[arg(long, default_value = "true")]
     relay: bool,
     
-    /// Public IP address (for NAT traversal)
-    #[arg(long)]
-    public_ip: Option<IpAddr>,
-    
     /// Replication factor
     #[arg(short = 'r', long, default_value = "3")]
     replication: usize,
+    
+    /// Configuration file path
+    #[arg(short, long, default_value = "config.toml")]
+    config: std::path::PathBuf,
+    
+    /// Directory holding the P2P manifest, pins and local blocks
+    #[arg(short = 'd', long, default_value = "./msscs_data/p2p")]
+    data_dir: std::path::PathBuf,
+    
+    /// Pre-shared swarm key file (swarm.key format); joins a private swarm instead of the public DHT
+    #[arg(long)]
+    swarm_key: Option<std::path::PathBuf>,
+    
+    /// Generate the swarm key file if it does not exist yet
+    #[arg(long, requires = "swarm_key")]
+    init_swarm_key: bool,
+    
+    /// Peer allowed into the private swarm, as a peer id or /p2p/ multiaddr (can be specified multiple times)
+    #[arg(long = "allow-peer", requires = "swarm_key")]
+    allow_peers: Vec<String>,
+    
+    /// Identity whose nodes are allowed into the private swarm, as its hex Ed25519 public key (can be specified multiple times)
+    #[arg(long = "allow-identity", requires = "swarm_key")]
+    allow_identities: Vec<String>,
+    
+    /// Kademlia protocol name used inside the private swarm
+    #[arg(long, default_value = private_network::PRIVATE_KAD_PROTOCOL, requires = "swarm_key")]
+    kad_protocol: String,
+    
+    /// Serve as circuit relay and AutoNAT server for peers behind NAT (needs a public address)
+    #[arg(long)]
+    relay_server: bool,
+    
+    /// Publicly reachable address of this node, advertised to peers (can be specified multiple times)
+    #[arg(long = "external-address")]
+    external_addresses: Vec<libp2p::Multiaddr>,
+    
+    /// Relay reservations held at once
+    #[arg(long, default_value = "1024", requires = "relay_server")]
+    relay_max_reservations: usize,
+    
+    /// Relayed connections open at once
+    #[arg(long, default_value = "512", requires = "relay_server")]
+    relay_max_circuits: usize,
 }
 
 #[tokio::main]
@@ -62,20 +110,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     let args = Args::parse();
     
+    // Repair schedule from the [repair] section of the config file
+    let repair_config = if args.config.exists() {
+        Config::load(&args.config)?.repair
+    } else {
+        RepairConfig::default()
+    };
+    
     println!("\n{}", "=".repeat(70));
     println!("🌐 MSSCS P2P Storage Node - Internet-Ready Edition");
     println!("{}", "=".repeat(70));
     
-    // Create or load identity
-    let passphrase = args.passphrase.unwrap_or_else(|| {
-        println!("⚠️  No passphrase provided, using default (INSECURE for production!)");
-        "default-passphrase-change-me".to_string()
-    });
-    
-    println!("\n🔐 Initializing quantum-resistant identity...");
-    let identity = QuantumIdentity::new(&passphrase)?;
-    let unlocked = Arc::new(identity.unlock(&passphrase)?);
-    println!("   ✅ User ID: {}", unlocked.user_id());
+    // Load (or create on first start) the identity kept under the data dir
+    // In a private swarm it also vouches for this node's peer id, for identity allow-lists
+    let node_key = args.data_dir.join("p2p_key");
+    let (keys, attestation): (Arc<dyn KeyHolder>, Option<IdentityAttestation>) = if args.agent {
+        let agent = AgentClient::from_env();
+        println!("\n🔐 Using the identity held by the agent at {:?}...", agent.socket());
+        let keys = agent.keys().await?;
+        let attestation = if args.swarm_key.is_some() {
+            let message = IdentityAttestation::message(&p2p_network::node_peer_id(&node_key)?);
+            Some(IdentityAttestation {
+                public_key: keys.identity().public_key.clone(),
+                signature: agent.sign(&message).await?,
+            })
+        } else {
+            None
+        };
+        (Arc::new(keys), attestation)
+    } else {
+        let mut identities = IdentityManager::new(args.data_dir.join("identities"))?;
+        let passphrase = if identities.load_all().await? == 0 {
+            println!("\n🔐 Creating quantum-resistant identity in {:?}...", args.data_dir.join("identities"));
+            identity::read_new_secret(PASSPHRASE_ENV, "Passphrase for the new identity: ")?
+        } else {
+            println!("\n🔐 Unlocking quantum-resistant identity...");
+            identity::read_secret(PASSPHRASE_ENV, "Identity passphrase: ")?
+        };
+        let identity = identities.unlock_or_create("msscs-node", &passphrase).await?;
+        let attestation = match &args.swarm_key {
+            Some(_) => Some(IdentityAttestation::sign(&identity, &p2p_network::node_peer_id(&node_key)?)?),
+            None => None,
+        };
+        (Arc::new(identity), attestation)
+    };
+    println!("   ✅ User ID: {}", keys.identity_id());
     
     // Parse bootstrap peers
     let bootstrap_peers = parse_bootstrap_peers(&args.bootstrap);
@@ -86,85 +165,134 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("   Enable mDNS with --mdns or provide bootstrap peers with --bootstrap");
     }
     
-    // Create P2P node configuration
-    println!("\n🌐 Configuring P2P network...");
-    let mut listen_addresses = vec![
-        format!("/ip4/0.0.0.0/tcp/{}", args.p2p_port),
-        format!("/ip6/::/tcp/{}", args.p2p_port),
-    ];
-    
-    // Add public IP if provided
-    if let Some(public_ip) = args.public_ip {
-        match public_ip {
-            IpAddr::V4(ip) => {
-                listen_addresses.push(format!("/ip4/{}/tcp/{}", ip, args.p2p_port));
-                println!("   ✅ Public IPv4: {}", ip);
+    // Private swarm: pre-shared key, allow-list and its own DHT protocol
+    let private_network = match &args.swarm_key {
+        Some(path) => {
+            let psk = if args.init_swarm_key && !path.exists() {
+                println!("\n🔑 Generating swarm key at {:?}", path);
+                println!("   Copy this file to every node of the private swarm");
+                PrivateNetworkConfig::generate_key(path)?
+            } else {
+                PrivateNetworkConfig::load_key(path)?
+            };
+            let mut private = PrivateNetworkConfig::new(psk);
+            private.kad_protocol = args.kad_protocol.clone();
+            for peer in &args.allow_peers {
+                match private_network::parse_peer_id(peer) {
+                    Some(peer_id) => {
+                        private.allowed_peers.insert(peer_id);
+                    }
+                    None => return Err(format!("Invalid --allow-peer value: {}", peer).into()),
+                }
             }
-            IpAddr::V6(ip) => {
-                listen_addresses.push(format!("/ip6/{}/tcp/{}", ip, args.p2p_port));
-                println!("   ✅ Public IPv6: {}", ip);
+            for key in &args.allow_identities {
+                match private_network::parse_identity_key(key) {
+                    Some(key) => {
+                        private.allowed_identities.insert(key);
+                    }
+                    None => return Err(format!("Invalid --allow-identity value: {}", key).into()),
+                }
             }
+            private.attestation = attestation;
+            Some(private)
         }
-    }
+        None => None,
+    };
     
+    // Create P2P node configuration
+    println!("\n🌐 Configuring P2P network...");
     let config = P2PConfig {
-        listen_addresses,
+        listen_port: args.p2p_port,
         bootstrap_peers,
-        max_peers: 50,
         enable_mdns: args.mdns,
         enable_relay: args.relay,
         replication_factor: args.replication,
+        record_store: RecordStoreConfig {
+            dir: Some(args.data_dir.join("dht")),
+            ..Default::default()
+        },
+        shard_store: ShardStoreConfig {
+            dir: Some(args.data_dir.join("shards")),
+            ..Default::default()
+        },
+        key_file: Some(node_key),
+        replica_store: ShardStoreConfig {
+            dir: Some(args.data_dir.join("replicas")),
+            max_shard_bytes: MAX_BLOCK_BYTES,
+            ..Default::default()
+        },
+        private_network,
+        relay_server: args.relay_server.then(|| RelayServerConfig {
+            max_reservations: args.relay_max_reservations,
+            max_circuits: args.relay_max_circuits,
+            ..Default::default()
+        }),
+        external_addresses: args.external_addresses.clone(),
+        ..Default::default()
     };
     
-    println!("   ✅ Listen addresses: {} configured", config.listen_addresses.len());
-    println!("   ✅ Bootstrap peers: {}", config.bootstrap_peers.len());
+    println!("   ✅ Listen port: {}", config.listen_port);
+    println!("   ✅ Bootstrap peers: {}", config.bootstrap_addresses().len());
     println!("   ✅ mDNS: {}", if config.enable_mdns { "enabled" } else { "disabled" });
     println!("   ✅ Relay: {}", if config.enable_relay { "enabled" } else { "disabled" });
     println!("   ✅ Replication factor: {}", config.replication_factor);
+    if let Some(server) = &config.relay_server {
+        println!("   🛰️  Relay server: up to {} reservations, {} circuits", server.max_reservations, server.max_circuits);
+        println!("   🛰️  AutoNAT server: answering dial-back probes");
+        if config.external_addresses.is_empty() {
+            println!("   ⚠️  No --external-address given; reservations only carry addresses AutoNAT confirms");
+        }
+    }
+    for addr in &config.external_addresses {
+        println!("   ✅ External address: {}", addr);
+    }
+    if let Some(private) = &config.private_network {
+        println!("   🔒 Private swarm: key fingerprint {}", private.psk.fingerprint());
+        if private.allowed_peers.is_empty() && private.allowed_identities.is_empty() {
+            println!("   🔒 Allowed: any key holder");
+        } else {
+            println!("   🔒 Allowed: {} peers, {} identities", private.allowed_peers.len(), private.allowed_identities.len());
+        }
+        if let Some(attestation) = &private.attestation {
+            println!("   🔒 Identity key: {}", hex::encode(&attestation.public_key));
+        }
+        println!("   🔒 DHT protocol: {}", private.kad_protocol);
+    }
     
     // Create P2P node
     println!("\n🚀 Starting P2P node...");
-    let mut p2p_node = P2PNode::new(config).await?;
+    let p2p_node = P2PNode::new(config.clone()).await?;
     let peer_id = p2p_node.peer_id();
     println!("   ✅ Peer ID: {}", peer_id);
     
-    let mut event_rx = p2p_node.take_event_receiver();
-    
-    p2p_node.start().await?;
-    
-    // Bootstrap from known peers
-    if !p2p_node.config.bootstrap_peers.is_empty() {
-        println!("\n🔗 Bootstrapping from {} peers...", p2p_node.config.bootstrap_peers.len());
-        p2p_node.bootstrap().await?;
-    }
-    
-    let p2p_node = Arc::new(RwLock::new(p2p_node));
+    let p2p_command_tx = p2p_node.get_command_sender();
+    let (mut event_rx, _) = p2p_node.start(config).await?;
     
     // Spawn P2P event handler
-    let p2p_clone = p2p_node.clone();
     tokio::spawn(async move {
         println!("\n📡 P2P event listener started");
         while let Some(event) = event_rx.recv().await {
-            use msscs_v4::p2p_network::P2PEvent;
             match event {
-                P2PEvent::PeerDiscovered { peer_id, addresses } => {
-                    tracing::info!("🔍 Discovered peer: {} ({} addresses)", peer_id, addresses.len());
-                }
-                P2PEvent::PeerConnected { peer_id } => {
+                P2PEvent::PeerConnected(peer_id) => {
                     tracing::info!("🤝 Connected to peer: {}", peer_id);
-                    // Update connected peers count
-                    let node = p2p_clone.read().await;
-                    let count = node.connected_peers_count().await;
-                    tracing::info!("   Total connected peers: {}", count);
                 }
-                P2PEvent::PeerDisconnected { peer_id } => {
+                P2PEvent::PeerDisconnected(peer_id) => {
                     tracing::info!("👋 Disconnected from peer: {}", peer_id);
                 }
-                P2PEvent::BlockStored { block_id, peer_id: _ } => {
-                    tracing::debug!("💾 Block stored: {}", block_id);
+                P2PEvent::PeerIdentified { peer, capabilities } => {
+                    tracing::info!("🤝 {} runs {} as {:?}", peer, capabilities.agent_version, capabilities.role);
+                }
+                P2PEvent::BlockReceived { block, .. } => {
+                    tracing::debug!("💾 Block received: {}", block.uuid);
+                }
+                P2PEvent::BlockRequested { peer, block_id } => {
+                    tracing::debug!("📤 Block {} requested by {}", block_id, peer);
+                }
+                P2PEvent::BootstrapComplete => {
+                    tracing::info!("✅ Bootstrap complete");
                 }
-                P2PEvent::ProvidersFound { block_id, providers } => {
-                    tracing::debug!("📍 Found {} providers for block: {}", providers.len(), block_id);
+                P2PEvent::Error(e) => {
+                    tracing::warn!("⚠️  P2P error: {}", e);
                 }
             }
         }
@@ -172,16 +300,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // Create P2P VFS
     println!("\n💾 Initializing P2P Virtual File System...");
+    let block_store = open_block_store(StorageBackend::Embedded, &args.data_dir)?;
     let vfs = Arc::new(P2PVirtualFileSystem::new(
-        unlocked,
-        p2p_node.clone(),
+        keys,
+        Some(p2p_command_tx),
         1024 * 1024, // 1MB chunks
-    )?);
+        block_store,
+    ).await?);
     println!("   ✅ VFS ready with 1MB chunk size");
     
     // Create HTTP API
     println!("\n🌐 Starting HTTP API server...");
-    let state = P2PAppState { vfs };
+    let repair = Arc::new(RepairDaemon::new(vfs.clone(), repair_config));
+    repair.spawn();
+    let state = P2PAppState { vfs, repair };
     let app = create_p2p_router(state);
     
     let addr = format!("0.0.0.0:{}", args.port);
@@ -202,6 +334,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("   DELETE /delete/:path    - Delete file");
     println!("   GET    /files           - List files");
     println!("   GET    /stats           - Node statistics");
+    println!("   GET    /peers           - Peer scores and bans");
+    println!("   GET    /repair          - Shard repair metrics");
+    println!("   POST   /repair          - Start a repair pass now");
     println!("   GET    /health          - Health check");
     
     println!("\n💡 Connection Information:");
@@ -218,6 +353,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("   NAT traversal and hole punching available");
     }
     
+    if args.relay_server {
+        println!("\n🛰️  Relay Server: ENABLED");
+        println!("   Peers behind NAT can reserve a slot here and be reached through this node");
+    }
+    
     println!("\n{}", "=".repeat(70));
     println!("Press Ctrl+C to stop the node");
     println!("{}", "=".repeat(70));
@@ -228,28 +368,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-/// Parse bootstrap peers from various formats
-fn parse_bootstrap_peers(bootstrap_args: &[String]) -> Vec<(libp2p::PeerId, String)> {
+/// Parse bootstrap peers given as multiaddrs or host:port
+fn parse_bootstrap_peers(bootstrap_args: &[String]) -> Vec<libp2p::Multiaddr> {
     let mut peers = Vec::new();
     
     for arg in bootstrap_args {
-        // Try to parse as multiaddr format: /ip4/addr/tcp/port/p2p/peer_id
-        if arg.starts_with("/ip4/") || arg.starts_with("/ip6/") {
-            // Extract peer_id from multiaddr
-            if let Some(peer_id_str) = arg.split("/p2p/").nth(1) {
-                if let Ok(peer_id) = peer_id_str.parse::<libp2p::PeerId>() {
-                    peers.push((peer_id, arg.clone()));
-                    tracing::info!("   ✅ Added bootstrap peer: {}", peer_id);
-                } else {
-                    tracing::warn!("   ⚠️  Invalid peer ID in: {}", arg);
-                }
-            } else {
-                tracing::warn!("   ⚠️  No peer ID found in multiaddr: {}", arg);
+        match P2PConfig::parse_peer_address(arg) {
+            Some(addr) => {
+                tracing::info!("   ✅ Added bootstrap peer: {}", addr);
+                peers.push(addr);
+            }
+            None => {
+                tracing::warn!("   ⚠️  Invalid bootstrap peer: {}", arg);
+                tracing::warn!("      Use multiaddr format: /ip4/addr/tcp/port/p2p/peer_id");
             }
-        } else {
-            // Simple format: ip:port (generate random peer_id for now)
-            tracing::warn!("   ⚠️  Simple format not supported yet: {}", arg);
-            tracing::warn!("      Use multiaddr format: /ip4/addr/tcp/port/p2p/peer_id");
         }
     }
     
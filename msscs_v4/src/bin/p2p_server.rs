@@ -1,12 +1,14 @@
 // P2P SERVER - Decentralized Storage Node
 use msscs_v4::{
+    block_store::{open_block_store, StorageBackend},
+    config::Config,
     identity::QuantumIdentity,
-    p2p_network::{P2PNode, P2PConfig},
+    p2p_network::{P2PNode, P2PConfig, P2PEvent},
     p2p_vfs::P2PVirtualFileSystem,
     p2p_api::{P2PAppState, create_p2p_router},
+    repair::{RepairConfig, RepairDaemon},
 };
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use clap::Parser;
 
 #[derive(Parser, Debug)]
@@ -21,7 +23,7 @@ struct Args {
     #[arg(short = 'l', long, default_value = "0")]
     p2p_port: u16,
     
-    /// Bootstrap peers (format: peer_id@/ip4/addr/tcp/port)
+    /// Bootstrap peers (multiaddr or host:port)
     #[arg(short, long)]
     bootstrap: Vec<String>,
     
@@ -32,6 +34,10 @@ struct Args {
     /// Enable mDNS local discovery
     #[arg(short, long, default_value = "true")]
     mdns: bool,
+    
+    /// Configuration file path
+    #[arg(short, long, default_value = "config.toml")]
+    config: std::path::PathBuf,
 }
 
 #[tokio::main]
@@ -43,6 +49,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     let args = Args::parse();
     
+    // Repair schedule from the [repair] section of the config file
+    let repair_config = if args.config.exists() {
+        Config::load(&args.config)?.repair
+    } else {
+        RepairConfig::default()
+    };
+    
     println!("🌐 MSSCS P2P Storage Node");
     println!("{}", "=".repeat(60));
     
@@ -53,48 +66,48 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
     
     println!("\n🔐 Creating quantum-resistant identity...");
-    let identity = QuantumIdentity::new(&passphrase)?;
+    let identity = QuantumIdentity::new("p2p-server".to_string(), &passphrase)?;
     let unlocked = Arc::new(identity.unlock(&passphrase)?);
     println!("   User ID: {}", unlocked.user_id());
     
     // Parse bootstrap peers
-    let bootstrap_peers = Vec::new(); // Simplified for now
+    let bootstrap_peers = args.bootstrap.iter()
+        .filter_map(|arg| {
+            let addr = P2PConfig::parse_peer_address(arg);
+            if addr.is_none() {
+                tracing::warn!("⚠️  Invalid bootstrap peer: {}", arg);
+            }
+            addr
+        })
+        .collect();
     
     // Create P2P node
     println!("\n🌐 Initializing P2P network...");
     let config = P2PConfig {
-        listen_addresses: vec![
-            format!("/ip4/0.0.0.0/tcp/{}", args.p2p_port),
-        ],
+        listen_port: args.p2p_port,
         bootstrap_peers,
         max_peers: 50,
         enable_mdns: args.mdns,
         enable_relay: true,
         replication_factor: 3,
+        ..Default::default()
     };
     
-    let mut p2p_node = P2PNode::new(config).await?;
+    let p2p_node = P2PNode::new(config.clone()).await?;
     println!("   Peer ID: {}", p2p_node.peer_id());
     
-    let mut event_rx = p2p_node.take_event_receiver();
-    
-    p2p_node.start().await?;
-    
-    let p2p_node = Arc::new(RwLock::new(p2p_node));
+    // The node runs its event loop in the background once started
+    let p2p_command_tx = p2p_node.get_command_sender();
+    let (mut event_rx, _) = p2p_node.start(config).await?;
     
     // Spawn P2P event handler
-    let p2p_clone = p2p_node.clone();
     tokio::spawn(async move {
         while let Some(event) = event_rx.recv().await {
-            use msscs_v4::p2p_network::P2PEvent;
             match event {
-                P2PEvent::PeerDiscovered { peer_id, .. } => {
-                    tracing::info!("🔍 Discovered peer: {}", peer_id);
-                }
-                P2PEvent::PeerConnected { peer_id } => {
+                P2PEvent::PeerConnected(peer_id) => {
                     tracing::info!("🤝 Connected to peer: {}", peer_id);
                 }
-                P2PEvent::PeerDisconnected { peer_id } => {
+                P2PEvent::PeerDisconnected(peer_id) => {
                     tracing::info!("👋 Disconnected from peer: {}", peer_id);
                 }
                 _ => {}
@@ -102,25 +115,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
     
-    // Spawn P2P node runner
-    let p2p_runner = p2p_clone.clone();
-    tokio::spawn(async move {
-        let node = p2p_runner.write().await;
-        // Note: This will consume the node, so we need to handle this differently
-        // For now, we'll skip the runner and rely on event handling
-    });
-    
     // Create P2P VFS
     println!("\n💾 Initializing P2P Virtual File System...");
+    let block_store = open_block_store(StorageBackend::Embedded, std::path::Path::new("./msscs_data/p2p"))?;
     let vfs = Arc::new(P2PVirtualFileSystem::new(
         unlocked,
-        p2p_clone,
+        Some(p2p_command_tx),
         1024 * 1024, // 1MB chunks
-    )?);
+        block_store,
+    ).await?);
     
     // Create API
     println!("\n🚀 Starting HTTP API server...");
-    let state = P2PAppState { vfs };
+    let repair = Arc::new(RepairDaemon::new(vfs.clone(), repair_config));
+    repair.spawn();
+    let state = P2PAppState { vfs, repair };
     let app = create_p2p_router(state);
     
     let addr = format!("0.0.0.0:{}", args.port);
@@ -134,6 +143,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("   DELETE /delete/:path    - Delete file");
     println!("   GET    /files           - List files");
     println!("   GET    /stats           - Node statistics");
+    println!("   GET    /repair          - Shard repair metrics");
+    println!("   POST   /repair          - Start a repair pass now");
     println!("   GET    /health          - Health check");
     
     axum::serve(listener, app).await?;
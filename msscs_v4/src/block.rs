@@ -1,23 +1,244 @@
 use crate::error::{MSSCSError, Result};
 use crate::huffman;
+use crate::unlocked_identity::UnlockedIdentity;
+use async_trait::async_trait;
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, KeyInit, OsRng, Payload},
     Aes256Gcm, Nonce,
 };
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::fmt;
 use uuid::Uuid;
 
 const NONCE_SIZE: usize = 12; // AES-GCM standard nonce size
-const KEY_SIZE: usize = 32; // 256 bits for AES-256
+pub(crate) const KEY_SIZE: usize = 32; // 256 bits for AES-256
+pub(crate) const KEY_ID_SIZE: usize = 8;
+
+/// Context for deriving the key-encryption key from the identity's master key
+const IDENTITY_KEY_CONTEXT: &str = "MSSCS block key wrapping v1";
+/// Context for the key id, which names an identity key without revealing it
+const KEY_ID_CONTEXT: &str = "MSSCS block key id v1";
+
+/// Key-encryption key owned by an unlocked identity
+/// Wraps the per-file data keys; it never encrypts block payloads itself
+#[derive(Clone, PartialEq, Eq)]
+pub struct IdentityKey([u8; KEY_SIZE]);
+
+impl IdentityKey {
+    /// Derive the key from the identity's master secret
+    pub fn from_identity(identity: &UnlockedIdentity) -> Self {
+        IdentityKey(blake3::derive_key(IDENTITY_KEY_CONTEXT, &identity.master_key()))
+    }
+
+    /// Create from raw bytes
+    pub fn from_bytes(bytes: [u8; KEY_SIZE]) -> Self {
+        IdentityKey(bytes)
+    }
+
+    /// Short public fingerprint recorded next to every key it wraps
+    pub fn id(&self) -> [u8; KEY_ID_SIZE] {
+        let digest = blake3::derive_key(KEY_ID_CONTEXT, &self.0);
+        let mut id = [0u8; KEY_ID_SIZE];
+        id.copy_from_slice(&digest[..KEY_ID_SIZE]);
+        id
+    }
+
+    /// Encrypt a 32-byte secret (a data key or a convergence key) under this key
+    pub fn wrap(&self, secret: &[u8; KEY_SIZE]) -> Result<WrappedKey> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+
+        let key_id = self.id();
+        let ciphertext = self
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: secret, aad: &key_id })
+            .map_err(|e| MSSCSError::Crypto(format!("Data key wrapping failed: {}", e)))?;
+        Ok(WrappedKey { key_id, nonce, ciphertext })
+    }
+
+    /// Decrypt a secret wrapped by this key
+    pub fn unwrap(&self, wrapped: &WrappedKey) -> Result<[u8; KEY_SIZE]> {
+        if wrapped.key_id != self.id() {
+            return Err(MSSCSError::Crypto(format!(
+                "Data key was wrapped by identity key {}, not {}",
+                hex::encode(wrapped.key_id),
+                hex::encode(self.id())
+            )));
+        }
+
+        let bytes = self
+            .cipher()
+            .decrypt(
+                Nonce::from_slice(&wrapped.nonce),
+                Payload { msg: &wrapped.ciphertext, aad: &wrapped.key_id },
+            )
+            .map_err(|e| MSSCSError::Crypto(format!("Data key unwrapping failed: {}", e)))?;
+        bytes
+            .try_into()
+            .map_err(|_| MSSCSError::Crypto("Invalid data key length".to_string()))
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.0.into())
+    }
+}
+
+impl fmt::Debug for IdentityKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "IdentityKey({})", hex::encode(self.id()))
+    }
+}
+
+/// Wraps and unwraps 32-byte secrets under an identity's keys
+/// Implemented by the keys themselves and by the identity agent, which keeps them in its own process
+#[async_trait]
+pub trait KeyWrapper: Send + Sync {
+    /// Id of the key new secrets are wrapped under
+    fn key_id(&self) -> [u8; KEY_ID_SIZE];
+
+    /// Encrypt a secret under the current key
+    async fn wrap_key(&self, secret: &[u8; KEY_SIZE]) -> Result<WrappedKey>;
+
+    /// Decrypt a secret wrapped by the current or a retired key
+    async fn unwrap_key(&self, wrapped: &WrappedKey) -> Result<[u8; KEY_SIZE]>;
+}
+
+#[async_trait]
+impl KeyWrapper for IdentityKey {
+    fn key_id(&self) -> [u8; KEY_ID_SIZE] {
+        self.id()
+    }
+
+    async fn wrap_key(&self, secret: &[u8; KEY_SIZE]) -> Result<WrappedKey> {
+        self.wrap(secret)
+    }
+
+    async fn unwrap_key(&self, wrapped: &WrappedKey) -> Result<[u8; KEY_SIZE]> {
+        self.unwrap(wrapped)
+    }
+}
+
+/// Identity key of the current generation plus those of retired generations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyring {
+    pub current: IdentityKey,
+    pub retired: Vec<IdentityKey>,
+}
+
+impl Keyring {
+    pub fn new(current: IdentityKey) -> Self {
+        Keyring { current, retired: Vec::new() }
+    }
+
+    /// Key that wrapped `wrapped`; the current one when none matches, so the error names it
+    pub fn find(&self, wrapped: &WrappedKey) -> &IdentityKey {
+        std::iter::once(&self.current)
+            .chain(&self.retired)
+            .find(|key| key.id() == wrapped.key_id)
+            .unwrap_or(&self.current)
+    }
+}
+
+#[async_trait]
+impl KeyWrapper for Keyring {
+    fn key_id(&self) -> [u8; KEY_ID_SIZE] {
+        self.current.id()
+    }
+
+    async fn wrap_key(&self, secret: &[u8; KEY_SIZE]) -> Result<WrappedKey> {
+        self.current.wrap(secret)
+    }
+
+    async fn unwrap_key(&self, wrapped: &WrappedKey) -> Result<[u8; KEY_SIZE]> {
+        self.find(wrapped).unwrap(wrapped)
+    }
+}
+
+/// Data key encrypted under an identity key
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct WrappedKey {
+    /// Id of the identity key that wrapped it
+    pub key_id: [u8; KEY_ID_SIZE],
+    pub nonce: [u8; NONCE_SIZE],
+    pub ciphertext: Vec<u8>,
+}
+
+/// Random per-file data key (envelope encryption)
+/// Every block of a file is encrypted under it and carries it wrapped by the owner's identity key
+#[derive(Clone)]
+pub struct DataKey {
+    key: [u8; KEY_SIZE],
+    wrapped: WrappedKey,
+}
+
+impl DataKey {
+    /// Generate a fresh data key wrapped by `identity_key`
+    pub fn generate(identity_key: &IdentityKey) -> Result<Self> {
+        let mut key = [0u8; KEY_SIZE];
+        OsRng.fill_bytes(&mut key);
+        Self::wrap(key, identity_key)
+    }
+
+    /// The same data key wrapped by another identity key (after a key rotation)
+    /// Blocks keep decrypting under it; only their `wrapped_key` changes
+    pub fn rewrap(&self, identity_key: &IdentityKey) -> Result<Self> {
+        Self::wrap(self.key, identity_key)
+    }
+
+    fn wrap(key: [u8; KEY_SIZE], identity_key: &IdentityKey) -> Result<Self> {
+        let wrapped = identity_key.wrap(&key)?;
+        Ok(DataKey { key, wrapped })
+    }
+
+    /// Unwrap a data key with the identity key that wrapped it
+    pub fn unwrap(wrapped: &WrappedKey, identity_key: &IdentityKey) -> Result<Self> {
+        let key = identity_key.unwrap(wrapped)?;
+        Ok(DataKey { key, wrapped: wrapped.clone() })
+    }
+
+    /// Generate a fresh data key wrapped by the current key of `keys`
+    pub async fn generate_with(keys: &dyn KeyWrapper) -> Result<Self> {
+        let mut key = [0u8; KEY_SIZE];
+        OsRng.fill_bytes(&mut key);
+        let wrapped = keys.wrap_key(&key).await?;
+        Ok(DataKey { key, wrapped })
+    }
+
+    /// Unwrap a data key with whichever key of `keys` wrapped it
+    pub async fn unwrap_with(wrapped: &WrappedKey, keys: &dyn KeyWrapper) -> Result<Self> {
+        let key = keys.unwrap_key(wrapped).await?;
+        Ok(DataKey { key, wrapped: wrapped.clone() })
+    }
+
+    /// Wrapped form stored on every block
+    pub fn wrapped(&self) -> &WrappedKey {
+        &self.wrapped
+    }
+
+    /// Per-block encryption key, so no two blocks share a key/nonce space
+    fn block_cipher(&self, uuid: &Uuid, node_index: u64) -> Aes256Gcm {
+        let mut input = [0u8; 24];
+        input[..16].copy_from_slice(uuid.as_bytes());
+        input[16..].copy_from_slice(&node_index.to_le_bytes());
+        let key = blake3::keyed_hash(&self.key, &input);
+        Aes256Gcm::new(key.as_bytes().into())
+    }
+}
+
+impl fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DataKey(<redacted>, wrapped by {})", hex::encode(self.wrapped.key_id))
+    }
+}
 
 /// Represents a data block in the MSSCS system
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct DataBlock {
     /// Unique identifier for the block
     pub uuid: Uuid,
-    /// Position of the block in the chain (essential for key derivation)
+    /// Position of the block in the chain (authenticated with the payload)
     pub node_index: u64,
     /// UUID of the previous block (None for genesis block)
     pub previous_uuid: Option<Uuid>,
@@ -27,21 +248,43 @@ pub struct DataBlock {
     pub nonce: [u8; NONCE_SIZE],
     /// Final payload: encoded, compressed, and encrypted data
     encrypted_payload: Vec<u8>,
+    /// File data key, wrapped by the owner's identity key
+    pub wrapped_key: WrappedKey,
+    /// SHA-256 of the encrypted payload, checkable without any key
+    payload_digest: [u8; 32],
 }
 
 impl DataBlock {
     /// Creates a new data block with full processing pipeline:
     /// 1. Base-16 encoding (multi-state)
     /// 2. Huffman compression
-    /// 3. AES-256-GCM encryption with derived key
+    /// 3. AES-256-GCM encryption under the file's data key
     pub fn new(
         data: &[u8],
         node_index: u64,
         previous_uuid: Option<Uuid>,
         previous_hash: [u8; 32],
+        data_key: &DataKey,
+    ) -> Result<Self> {
+        Self::seal(Uuid::new_v4(), data, node_index, previous_uuid, previous_hash, data_key)
+    }
+
+    /// Re-encrypt a block written with the legacy UUID-derived key, keeping its identity in the chain
+    /// `previous_hash` is the hash of the already migrated previous block
+    pub fn from_legacy(legacy: &LegacyDataBlock, previous_hash: [u8; 32], data_key: &DataKey) -> Result<Self> {
+        let data = legacy.decode()?;
+        Self::seal(legacy.uuid, &data, legacy.node_index, legacy.previous_uuid, previous_hash, data_key)
+    }
+
+    fn seal(
+        uuid: Uuid,
+        data: &[u8],
+        node_index: u64,
+        previous_uuid: Option<Uuid>,
+        previous_hash: [u8; 32],
+        data_key: &DataKey,
     ) -> Result<Self> {
-        // 1. Generate UUID and Nonce
-        let uuid = Uuid::new_v4();
+        // 1. Generate Nonce
         let mut nonce_bytes = [0u8; NONCE_SIZE];
         OsRng.fill_bytes(&mut nonce_bytes);
         let nonce = Nonce::from_slice(&nonce_bytes);
@@ -56,13 +299,11 @@ impl DataBlock {
         // 3. Compress with Huffman
         let compressed_payload = huffman::compress(&encoded_payload)?;
 
-        // 4. Derive encryption key from UUID + node_index
-        let key = Self::derive_key(&uuid, node_index);
-        let cipher = Aes256Gcm::new(&key.into());
-
-        // 5. Encrypt with AES-256-GCM
+        // 4. Encrypt with AES-256-GCM, binding the chain position as associated data
+        let cipher = data_key.block_cipher(&uuid, node_index);
+        let aad = Self::associated_data(&uuid, node_index, previous_uuid, &previous_hash)?;
         let encrypted_payload = cipher
-            .encrypt(nonce, compressed_payload.as_ref())
+            .encrypt(nonce, Payload { msg: &compressed_payload, aad: &aad })
             .map_err(|e| MSSCSError::Crypto(format!("Encryption failed: {}", e)))?;
 
         Ok(Self {
@@ -71,7 +312,9 @@ impl DataBlock {
             previous_uuid,
             previous_hash,
             nonce: nonce_bytes,
+            payload_digest: Sha256::digest(&encrypted_payload).into(),
             encrypted_payload,
+            wrapped_key: data_key.wrapped().clone(),
         })
     }
 
@@ -97,8 +340,14 @@ impl DataBlock {
     }
 
     /// Decodes (decrypts, decompresses, and decodes) the block data
-    /// Requires the correct node_index to derive the decryption key
-    pub fn decode(&self, node_index: u64) -> Result<Vec<u8>> {
+    /// Requires the correct node_index and the identity key that wrapped the file's data key
+    pub fn decode(&self, node_index: u64, identity_key: &IdentityKey) -> Result<Vec<u8>> {
+        let data_key = DataKey::unwrap(&self.wrapped_key, identity_key)?;
+        self.decode_with(node_index, &data_key)
+    }
+
+    /// Decodes the block with an already unwrapped data key
+    pub fn decode_with(&self, node_index: u64, data_key: &DataKey) -> Result<Vec<u8>> {
         // 1. Verify node_index matches
         if node_index != self.node_index {
             return Err(MSSCSError::Crypto(
@@ -106,52 +355,38 @@ impl DataBlock {
             ));
         }
 
-        // 2. Derive decryption key
-        let key = Self::derive_key(&self.uuid, node_index);
-        let cipher = Aes256Gcm::new(&key.into());
+        // 2. Decrypt
+        let cipher = data_key.block_cipher(&self.uuid, node_index);
         let nonce = Nonce::from_slice(&self.nonce);
-
-        // 3. Decrypt
+        let aad = Self::associated_data(&self.uuid, node_index, self.previous_uuid, &self.previous_hash)?;
         let compressed_payload = cipher
-            .decrypt(nonce, self.encrypted_payload.as_ref())
+            .decrypt(nonce, Payload { msg: &self.encrypted_payload, aad: &aad })
             .map_err(|e| MSSCSError::Crypto(format!("Decryption failed: {}", e)))?;
 
-        // 4. Decompress
-        let encoded_payload = huffman::decompress(&compressed_payload)?;
-
-        // 5. Decode from Base-16
-        if encoded_payload.len() % 2 != 0 {
-            return Err(MSSCSError::InvalidData(
-                "Invalid Base-16 encoded data length".to_string(),
-            ));
-        }
-
-        let mut decoded_data = Vec::with_capacity(encoded_payload.len() / 2);
-        for chunk in encoded_payload.chunks(2) {
-            let high = chunk[0];
-            let low = chunk[1];
-            decoded_data.push((high << 4) | low);
-        }
-
-        Ok(decoded_data)
+        // 3. Decompress and decode from Base-16
+        decode_payload(&compressed_payload)
     }
 
-    /// Derives a 256-bit encryption key from UUID and node_index
-    fn derive_key(uuid: &Uuid, node_index: u64) -> [u8; KEY_SIZE] {
-        let mut hasher = Sha256::new();
-        hasher.update(uuid.as_bytes());
-        hasher.update(node_index.to_le_bytes());
-        
-        let result = hasher.finalize();
-        let mut key = [0u8; KEY_SIZE];
-        key.copy_from_slice(&result);
-        key
+    /// Block header authenticated alongside the payload
+    fn associated_data(
+        uuid: &Uuid,
+        node_index: u64,
+        previous_uuid: Option<Uuid>,
+        previous_hash: &[u8; 32],
+    ) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(&(uuid, node_index, previous_uuid, previous_hash))?)
     }
 
-    /// Verifies the integrity of the block
+    /// Verifies the integrity of the block without any key
+    /// Detects corruption in storage or transit; decoding additionally authenticates the payload
     pub fn verify(&self) -> bool {
-        // Try to decode - if successful, block is valid
-        self.decode(self.node_index).is_ok()
+        Sha256::digest(&self.encrypted_payload).as_slice() == self.payload_digest
+    }
+
+    /// Verifies the block against a hash the reader already trusts
+    /// (the file's root hash or the `previous_hash` link of the block before it)
+    pub fn matches_hash(&self, expected: &[u8; 32]) -> bool {
+        self.verify() && self.calculate_hash().is_ok_and(|hash| &hash == expected)
     }
 
     /// Get the encrypted payload size
@@ -165,6 +400,92 @@ impl DataBlock {
     }
 }
 
+/// Block in the layout written before per-user keys
+/// Its AES key was SHA-256(uuid || node_index), so anyone holding the block could decrypt it;
+/// only kept to migrate existing stores
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LegacyDataBlock {
+    pub uuid: Uuid,
+    pub node_index: u64,
+    pub previous_uuid: Option<Uuid>,
+    pub previous_hash: [u8; 32],
+    pub nonce: [u8; NONCE_SIZE],
+    encrypted_payload: Vec<u8>,
+}
+
+impl LegacyDataBlock {
+    /// Decrypt with the UUID-derived key
+    pub fn decode(&self) -> Result<Vec<u8>> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.uuid.as_bytes());
+        hasher.update(self.node_index.to_le_bytes());
+        let key: [u8; KEY_SIZE] = hasher.finalize().into();
+
+        let cipher = Aes256Gcm::new(&key.into());
+        let compressed_payload = cipher
+            .decrypt(Nonce::from_slice(&self.nonce), self.encrypted_payload.as_ref())
+            .map_err(|e| MSSCSError::Crypto(format!("Decryption failed: {}", e)))?;
+        decode_payload(&compressed_payload)
+    }
+}
+
+/// A serialized block in either the current or the legacy layout
+#[derive(Debug, Clone)]
+pub enum StoredBlock {
+    Current(DataBlock),
+    Legacy(LegacyDataBlock),
+}
+
+impl StoredBlock {
+    /// Deserialize, trying the current layout first
+    /// (legacy data is too short to parse as a current block)
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        match bincode::deserialize::<DataBlock>(data) {
+            Ok(block) => Ok(StoredBlock::Current(block)),
+            Err(e) => match bincode::deserialize::<LegacyDataBlock>(data) {
+                Ok(legacy) => Ok(StoredBlock::Legacy(legacy)),
+                Err(_) => Err(e.into()),
+            },
+        }
+    }
+
+    /// Block UUID
+    pub fn uuid(&self) -> Uuid {
+        match self {
+            StoredBlock::Current(block) => block.uuid,
+            StoredBlock::Legacy(legacy) => legacy.uuid,
+        }
+    }
+
+    /// UUID of the previous block in the chain
+    pub fn previous_uuid(&self) -> Option<Uuid> {
+        match self {
+            StoredBlock::Current(block) => block.previous_uuid,
+            StoredBlock::Legacy(legacy) => legacy.previous_uuid,
+        }
+    }
+}
+
+/// Decompress and decode a Base-16 block payload
+fn decode_payload(compressed_payload: &[u8]) -> Result<Vec<u8>> {
+    let encoded_payload = huffman::decompress(compressed_payload)?;
+
+    if encoded_payload.len() % 2 != 0 {
+        return Err(MSSCSError::InvalidData(
+            "Invalid Base-16 encoded data length".to_string(),
+        ));
+    }
+
+    let mut decoded_data = Vec::with_capacity(encoded_payload.len() / 2);
+    for chunk in encoded_payload.chunks(2) {
+        let high = chunk[0];
+        let low = chunk[1];
+        decoded_data.push((high << 4) | low);
+    }
+
+    Ok(decoded_data)
+}
+
 /// Calculate checksum for data
 pub fn calculate_checksum(data: &[u8]) -> String {
     use sha2::{Digest, Sha256};
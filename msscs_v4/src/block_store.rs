@@ -0,0 +1,565 @@
+// BLOCK STORE BACKENDS
+// Pluggable async storage for serialized blocks and manifests
+
+use crate::block::StoredBlock;
+use crate::error::{MSSCSError, Result};
+use crate::log_store::{write_file_atomic, CorruptedEntry, LogOp, LogStore, RecoveryReport};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Key prefix separating manifests from blocks inside the embedded log
+const MANIFEST_PREFIX: &str = "manifest:";
+
+/// Storage backend selectable from `Config`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// One file per block under `blocks/`, manifests as JSON files
+    Filesystem,
+    /// Volatile in-memory maps (tests, ephemeral nodes)
+    Memory,
+    /// Single crash-safe log file (`blocks/blocks.log`)
+    #[default]
+    Embedded,
+}
+
+/// Metadata about a stored block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockStat {
+    pub key: String,
+    pub size: u64,
+}
+
+/// Async key/value storage for blocks and manifests
+#[async_trait]
+pub trait BlockStore: Send + Sync {
+    /// Store `data` under `key`, replacing any previous value
+    async fn put(&self, key: &str, data: &[u8]) -> Result<()>;
+
+    /// Fetch the value stored under `key`
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Up to `len` bytes of `key` starting at `offset`, with the value's total length
+    async fn get_range(&self, key: &str, offset: u64, len: usize) -> Result<Option<(u64, Vec<u8>)>> {
+        Ok(self.get(key).await?.map(|data| {
+            let (start, end) = clamp_range(data.len() as u64, offset, len);
+            (data.len() as u64, data[start as usize..end as usize].to_vec())
+        }))
+    }
+
+    /// Remove `key`, returning whether it existed
+    async fn delete(&self, key: &str) -> Result<bool>;
+
+    /// All stored block keys
+    async fn list(&self) -> Result<Vec<String>>;
+
+    /// Size information for `key`
+    async fn stat(&self, key: &str) -> Result<Option<BlockStat>>;
+
+    /// Durably replace the manifest called `name`
+    async fn put_manifest(&self, name: &str, data: &[u8]) -> Result<()>;
+
+    /// Fetch the manifest called `name`
+    async fn get_manifest(&self, name: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Remove several keys, returning how many existed
+    async fn delete_many(&self, keys: &[String]) -> Result<usize> {
+        let mut deleted = 0;
+        for key in keys {
+            if self.delete(key).await? {
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Reclaim space after deletions, returning bytes freed
+    async fn compact(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Reclaim space unconditionally, so no overwritten value (such as a replaced secret)
+    /// stays readable on disk; returns bytes freed
+    async fn purge(&self) -> Result<u64> {
+        self.compact().await
+    }
+
+    /// Problems found when the backend was opened
+    fn recovery_report(&self) -> RecoveryReport {
+        RecoveryReport::default()
+    }
+
+    /// Human-readable backend name
+    fn backend_name(&self) -> &'static str;
+}
+
+impl std::fmt::Debug for dyn BlockStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockStore").field("backend", &self.backend_name()).finish()
+    }
+}
+
+/// Open the configured backend rooted at `data_dir`
+pub fn open_block_store(backend: StorageBackend, data_dir: &Path) -> Result<Arc<dyn BlockStore>> {
+    Ok(match backend {
+        StorageBackend::Filesystem => Arc::new(FsBlockStore::open(data_dir)?),
+        StorageBackend::Memory => Arc::new(MemoryBlockStore::new()),
+        StorageBackend::Embedded => Arc::new(EmbeddedBlockStore::open(data_dir)?),
+    })
+}
+
+/// `offset..offset + len` limited to a value of `total` bytes
+fn clamp_range(total: u64, offset: u64, len: usize) -> (u64, u64) {
+    let start = offset.min(total);
+    (start, start.saturating_add(len as u64).min(total))
+}
+
+/// Read part of a file, mapping "not found" to `None`
+fn read_range_optional(path: &Path, offset: u64, len: usize) -> Result<Option<(u64, Vec<u8>)>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(MSSCSError::Io(e)),
+    };
+    let total = file.metadata()?.len();
+    let (start, end) = clamp_range(total, offset, len);
+    let mut data = vec![0u8; (end - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut data)?;
+    Ok(Some((total, data)))
+}
+
+/// Reject keys that could escape the storage directory
+fn validate_key(key: &str) -> Result<()> {
+    if key.is_empty() || key.contains(['/', '\\', ':']) || key.starts_with('.') {
+        return Err(MSSCSError::InvalidData(format!("Invalid block key '{}'", key)));
+    }
+    Ok(())
+}
+
+/// Filesystem layout: `blocks/<key>.block` and `<name>.json`, each written atomically
+#[derive(Clone)]
+pub struct FsBlockStore {
+    root: PathBuf,
+}
+
+impl FsBlockStore {
+    /// Open a filesystem store rooted at `root`
+    pub fn open(root: &Path) -> Result<Self> {
+        fs::create_dir_all(root.join("blocks"))?;
+        Ok(FsBlockStore { root: root.to_path_buf() })
+    }
+
+    fn block_path(&self, key: &str) -> PathBuf {
+        self.root.join("blocks").join(format!("{}.block", key))
+    }
+
+    fn manifest_path(&self, name: &str) -> PathBuf {
+        self.root.join(format!("{}.json", name))
+    }
+
+    fn keys_blocking(&self) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(self.root.join("blocks"))? {
+            let path = entry?.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("block") {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    keys.push(stem.to_string());
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    fn delete_blocking(&self, key: &str) -> Result<bool> {
+        remove_optional(&self.block_path(key))
+    }
+}
+
+/// Read a file, mapping "not found" to `None`
+fn read_optional(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(MSSCSError::Io(e)),
+    }
+}
+
+/// Remove a file, reporting whether it existed
+fn remove_optional(path: &Path) -> Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(MSSCSError::Io(e)),
+    }
+}
+
+/// Run blocking file I/O on the blocking pool instead of an async worker
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| MSSCSError::Io(std::io::Error::other(format!("storage task failed: {}", e))))?
+}
+
+#[async_trait]
+impl BlockStore for FsBlockStore {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        validate_key(key)?;
+        let (path, data) = (self.block_path(key), data.to_vec());
+        blocking(move || write_file_atomic(&path, &data)).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        validate_key(key)?;
+        let path = self.block_path(key);
+        blocking(move || read_optional(&path)).await
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, len: usize) -> Result<Option<(u64, Vec<u8>)>> {
+        validate_key(key)?;
+        let path = self.block_path(key);
+        blocking(move || read_range_optional(&path, offset, len)).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        validate_key(key)?;
+        let path = self.block_path(key);
+        blocking(move || remove_optional(&path)).await
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let store = self.clone();
+        blocking(move || store.keys_blocking()).await
+    }
+
+    async fn stat(&self, key: &str) -> Result<Option<BlockStat>> {
+        validate_key(key)?;
+        let (path, key) = (self.block_path(key), key.to_string());
+        blocking(move || match fs::metadata(path) {
+            Ok(meta) => Ok(Some(BlockStat { key, size: meta.len() })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(MSSCSError::Io(e)),
+        })
+        .await
+    }
+
+    async fn put_manifest(&self, name: &str, data: &[u8]) -> Result<()> {
+        validate_key(name)?;
+        let (path, data) = (self.manifest_path(name), data.to_vec());
+        blocking(move || write_file_atomic(&path, &data)).await
+    }
+
+    async fn get_manifest(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        validate_key(name)?;
+        let path = self.manifest_path(name);
+        blocking(move || read_optional(&path)).await
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "filesystem"
+    }
+}
+
+/// In-memory store; nothing survives a restart
+#[derive(Default)]
+pub struct MemoryBlockStore {
+    blocks: RwLock<HashMap<String, Vec<u8>>>,
+    manifests: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryBlockStore {
+    /// Create an empty in-memory store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl BlockStore for MemoryBlockStore {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        validate_key(key)?;
+        self.blocks.write().await.insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        validate_key(key)?;
+        Ok(self.blocks.read().await.get(key).cloned())
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        validate_key(key)?;
+        Ok(self.blocks.write().await.remove(key).is_some())
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        Ok(self.blocks.read().await.keys().cloned().collect())
+    }
+
+    async fn stat(&self, key: &str) -> Result<Option<BlockStat>> {
+        validate_key(key)?;
+        Ok(self.blocks.read().await.get(key).map(|data| BlockStat {
+            key: key.to_string(),
+            size: data.len() as u64,
+        }))
+    }
+
+    async fn put_manifest(&self, name: &str, data: &[u8]) -> Result<()> {
+        validate_key(name)?;
+        self.manifests.write().await.insert(name.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    async fn get_manifest(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        validate_key(name)?;
+        Ok(self.manifests.read().await.get(name).cloned())
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "memory"
+    }
+}
+
+/// Single-file embedded store on top of the crash-safe `LogStore`
+pub struct EmbeddedBlockStore {
+    log: Arc<LogStore>,
+    report: RecoveryReport,
+}
+
+impl EmbeddedBlockStore {
+    /// Open `root/blocks/blocks.log`, importing any blocks and `manifest.json` left in the filesystem layout
+    pub fn open(root: &Path) -> Result<Self> {
+        let legacy = FsBlockStore::open(root)?;
+        let log = LogStore::open(root.join("blocks").join("blocks.log"))?;
+        let mut report = log.recovery_report().clone();
+        Self::import_legacy(&log, &legacy, &mut report)?;
+
+        Ok(EmbeddedBlockStore { log: Arc::new(log), report })
+    }
+
+    /// Move one-file-per-block data written by older versions (the `FsBlockStore` layout) into the log
+    fn import_legacy(log: &LogStore, legacy: &FsBlockStore, report: &mut RecoveryReport) -> Result<()> {
+        for key in legacy.keys_blocking()? {
+            let path = legacy.block_path(&key);
+            let Some(data) = read_optional(&path)? else {
+                continue;
+            };
+            // Per-file blocks predate per-user keys, but accept either layout
+            match StoredBlock::deserialize(&data) {
+                Ok(block) => {
+                    log.put(&block.uuid().to_string(), &data)?;
+                    // Only remove the old file once the log commit is durable
+                    legacy.delete_blocking(&key)?;
+                    report.legacy_imported += 1;
+                }
+                Err(e) => {
+                    // Leave the file in place so it can be inspected, but make it visible
+                    report.corrupted.push(CorruptedEntry {
+                        location: path.display().to_string(),
+                        reason: format!("undecodable legacy block: {}", e),
+                    });
+                }
+            }
+        }
+
+        let manifest_key = format!("{}manifest", MANIFEST_PREFIX);
+        let manifest_path = legacy.manifest_path("manifest");
+        if !log.contains(&manifest_key) {
+            if let Some(data) = read_optional(&manifest_path)? {
+                log.put(&manifest_key, &data)?;
+                remove_optional(&manifest_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `f` against the log on the blocking pool
+    async fn with_log<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&LogStore) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let log = self.log.clone();
+        blocking(move || f(&log)).await
+    }
+}
+
+#[async_trait]
+impl BlockStore for EmbeddedBlockStore {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        validate_key(key)?;
+        let (key, data) = (key.to_string(), data.to_vec());
+        self.with_log(move |log| log.put(&key, &data)).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        validate_key(key)?;
+        let key = key.to_string();
+        self.with_log(move |log| log.get(&key)).await
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, len: usize) -> Result<Option<(u64, Vec<u8>)>> {
+        validate_key(key)?;
+        let key = key.to_string();
+        self.with_log(move |log| log.get_range(&key, offset, len)).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        validate_key(key)?;
+        let key = key.to_string();
+        self.with_log(move |log| {
+            let existed = log.contains(&key);
+            log.delete(&key)?;
+            Ok(existed)
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        self.with_log(|log| {
+            Ok(log
+                .keys()
+                .into_iter()
+                .filter(|key| !key.starts_with(MANIFEST_PREFIX))
+                .collect())
+        })
+        .await
+    }
+
+    async fn stat(&self, key: &str) -> Result<Option<BlockStat>> {
+        validate_key(key)?;
+        let key = key.to_string();
+        // Sizes come from the in-memory index, so no value is read
+        self.with_log(move |log| Ok(log.value_len(&key).map(|size| BlockStat { key, size }))).await
+    }
+
+    async fn put_manifest(&self, name: &str, data: &[u8]) -> Result<()> {
+        validate_key(name)?;
+        let (key, data) = (format!("{}{}", MANIFEST_PREFIX, name), data.to_vec());
+        self.with_log(move |log| log.put(&key, &data)).await
+    }
+
+    async fn get_manifest(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        validate_key(name)?;
+        let key = format!("{}{}", MANIFEST_PREFIX, name);
+        self.with_log(move |log| log.get(&key)).await
+    }
+
+    async fn delete_many(&self, keys: &[String]) -> Result<usize> {
+        for key in keys {
+            validate_key(key)?;
+        }
+        let keys = keys.to_vec();
+        // A single batch keeps bulk deletes atomic
+        self.with_log(move |log| {
+            let ops: Vec<LogOp> = keys
+                .into_iter()
+                .filter(|key| log.contains(key))
+                .map(|key| LogOp::Delete { key })
+                .collect();
+            log.write_batch(&ops)?;
+            Ok(ops.len())
+        })
+        .await
+    }
+
+    async fn compact(&self) -> Result<u64> {
+        self.with_log(|log| if log.needs_compaction() { log.compact() } else { Ok(0) }).await
+    }
+
+    async fn purge(&self) -> Result<u64> {
+        self.with_log(|log| log.compact()).await
+    }
+
+    fn recovery_report(&self) -> RecoveryReport {
+        self.report.clone()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "embedded"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    async fn exercise(store: &dyn BlockStore) {
+        store.put("a", b"alpha").await.unwrap();
+        store.put("b", b"beta").await.unwrap();
+        store.put("a", b"alpha-2").await.unwrap();
+
+        assert_eq!(store.get("a").await.unwrap(), Some(b"alpha-2".to_vec()));
+        assert_eq!(store.get_range("a", 2, 3).await.unwrap(), Some((7, b"pha".to_vec())));
+        assert_eq!(store.get_range("a", 5, 100).await.unwrap(), Some((7, b"-2".to_vec())));
+        assert_eq!(store.get_range("a", 100, 4).await.unwrap(), Some((7, Vec::new())));
+        assert!(store.get_range("missing", 0, 4).await.unwrap().is_none());
+        assert_eq!(store.stat("b").await.unwrap().unwrap().size, 4);
+        assert!(store.stat("missing").await.unwrap().is_none());
+
+        let mut keys = store.list().await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["a".to_string(), "b".to_string()]);
+
+        store.put_manifest("manifest", b"{}").await.unwrap();
+        assert_eq!(store.get_manifest("manifest").await.unwrap(), Some(b"{}".to_vec()));
+        assert_eq!(store.list().await.unwrap().len(), 2);
+
+        assert!(store.delete("b").await.unwrap());
+        assert!(!store.delete("b").await.unwrap());
+        assert_eq!(store.delete_many(&["a".to_string(), "zzz".to_string()]).await.unwrap(), 1);
+        assert!(store.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_all_backends_behave_alike() {
+        for backend in [StorageBackend::Filesystem, StorageBackend::Memory, StorageBackend::Embedded] {
+            let dir = TempDir::new().unwrap();
+            let store = open_block_store(backend, dir.path()).unwrap();
+            exercise(store.as_ref()).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_persistent_backends_survive_reopen() {
+        for backend in [StorageBackend::Filesystem, StorageBackend::Embedded] {
+            let dir = TempDir::new().unwrap();
+            {
+                let store = open_block_store(backend, dir.path()).unwrap();
+                store.put("block", b"data").await.unwrap();
+                store.put_manifest("manifest", b"{\"f\":1}").await.unwrap();
+            }
+            let store = open_block_store(backend, dir.path()).unwrap();
+            assert_eq!(store.get("block").await.unwrap(), Some(b"data".to_vec()));
+            assert_eq!(store.get_manifest("manifest").await.unwrap(), Some(b"{\"f\":1}".to_vec()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keys_cannot_escape_store() {
+        for backend in [StorageBackend::Filesystem, StorageBackend::Memory, StorageBackend::Embedded] {
+            let dir = TempDir::new().unwrap();
+            let store = open_block_store(backend, dir.path()).unwrap();
+            assert!(store.put("../evil", b"x").await.is_err());
+            assert!(store.put("manifest:x", b"x").await.is_err());
+            assert!(store.get("../evil").await.is_err());
+            assert!(store.get_range("../evil", 0, 1).await.is_err());
+            assert!(store.stat("../evil").await.is_err());
+            assert!(store.delete("../evil").await.is_err());
+            assert!(store.delete_many(&["../evil".to_string()]).await.is_err());
+            assert!(store.get_manifest("../evil").await.is_err());
+        }
+    }
+}
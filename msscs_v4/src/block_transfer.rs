@@ -0,0 +1,330 @@
+// BLOCK TRANSFER PROTOCOL
+// Blocks move between peers as a series of bounded binary frames: each request asks for
+// one byte range of a serialized block, so no message carries more than MAX_FRAME_BYTES
+// of payload, a fetch keeps one frame in flight (backpressure) and an interrupted transfer
+// resumes from the last received offset, possibly on another provider. The receiving side
+// assembles the whole block in memory, up to MAX_BLOCK_BYTES per fetch
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Protocol name negotiated on the stream
+pub const PROTOCOL: &str = "/msscs/blocks/2";
+
+/// Largest payload carried by one frame
+pub const MAX_FRAME_BYTES: usize = 256 * 1024;
+
+/// Largest encoded request accepted
+const MAX_REQUEST_BYTES: usize = 64;
+
+/// Largest encoded response accepted (frame payload plus header)
+const MAX_RESPONSE_BYTES: usize = MAX_FRAME_BYTES + 64;
+
+/// Largest block a fetch will assemble
+pub const MAX_BLOCK_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Ask for `length` bytes of a serialized block starting at `offset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRangeRequest {
+    pub block_id: Uuid,
+    pub offset: u64,
+    pub length: u32,
+}
+
+/// One frame of a block, or a miss
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockTransferResponse {
+    Frame {
+        /// Size of the whole serialized block
+        total_size: u64,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    NotFound,
+}
+
+impl BlockRangeRequest {
+    /// Request for the first frame of a block
+    pub fn first(block_id: Uuid) -> Self {
+        Self {
+            block_id,
+            offset: 0,
+            length: MAX_FRAME_BYTES as u32,
+        }
+    }
+
+    /// Bytes the answering frame carries at most
+    pub fn frame_len(&self) -> usize {
+        (self.length as usize).min(MAX_FRAME_BYTES)
+    }
+}
+
+/// Answer a range request from the serialized block (None = not held)
+pub fn serve_range(block: Option<&[u8]>, request: &BlockRangeRequest) -> BlockTransferResponse {
+    let Some(block) = block else {
+        return BlockTransferResponse::NotFound;
+    };
+    let start = (request.offset as usize).min(block.len());
+    let end = start.saturating_add(request.frame_len()).min(block.len());
+    BlockTransferResponse::Frame {
+        total_size: block.len() as u64,
+        offset: start as u64,
+        data: block[start..end].to_vec(),
+    }
+}
+
+/// Answer a range request from the bytes read for it in storage: the block's total size
+/// and up to `request.frame_len()` bytes at `request.offset` (None = not held)
+pub fn frame_from_range(range: Option<(u64, Vec<u8>)>, request: &BlockRangeRequest) -> BlockTransferResponse {
+    match range {
+        Some((total_size, data)) => BlockTransferResponse::Frame {
+            total_size,
+            offset: request.offset.min(total_size),
+            data,
+        },
+        None => BlockTransferResponse::NotFound,
+    }
+}
+
+/// Client-side reassembly of a block from frames
+#[derive(Debug, Clone)]
+pub struct BlockAssembler {
+    block_id: Uuid,
+    total_size: Option<u64>,
+    data: Vec<u8>,
+}
+
+impl BlockAssembler {
+    /// Start a fetch from the beginning
+    pub fn new(block_id: Uuid) -> Self {
+        Self {
+            block_id,
+            total_size: None,
+            data: Vec::new(),
+        }
+    }
+
+    /// Next range to request (None once complete)
+    pub fn next_request(&self) -> Option<BlockRangeRequest> {
+        if self.is_complete() {
+            return None;
+        }
+        let remaining = self.total_size.map_or(MAX_FRAME_BYTES as u64, |total| total - self.received());
+        Some(BlockRangeRequest {
+            block_id: self.block_id,
+            offset: self.received(),
+            length: remaining.min(MAX_FRAME_BYTES as u64) as u32,
+        })
+    }
+
+    /// Append a frame; rejects frames that do not continue the block where it stopped
+    pub fn accept(&mut self, response: BlockTransferResponse) -> Result<(), String> {
+        let (total_size, offset, data) = match response {
+            BlockTransferResponse::Frame { total_size, offset, data } => (total_size, offset, data),
+            BlockTransferResponse::NotFound => return Err(format!("Peer does not hold block {}", self.block_id)),
+        };
+
+        if total_size > MAX_BLOCK_BYTES {
+            return Err(format!("Block {} too large ({} bytes)", self.block_id, total_size));
+        }
+        if self.total_size.is_some_and(|known| known != total_size) {
+            return Err(format!("Block {} changed size mid-transfer", self.block_id));
+        }
+        if offset != self.received() {
+            return Err(format!("Frame at offset {} does not continue at {}", offset, self.received()));
+        }
+        if data.len() > MAX_FRAME_BYTES || offset + data.len() as u64 > total_size {
+            return Err(format!("Oversized frame for block {}", self.block_id));
+        }
+        if data.is_empty() && offset < total_size {
+            return Err(format!("Empty frame for block {}", self.block_id));
+        }
+
+        self.total_size = Some(total_size);
+        self.data.extend_from_slice(&data);
+        Ok(())
+    }
+
+    /// Bytes received so far (the resume offset)
+    pub fn received(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    /// Whether the whole block has arrived
+    pub fn is_complete(&self) -> bool {
+        self.total_size == Some(self.received())
+    }
+
+    /// Serialized block, once complete
+    pub fn finish(self) -> Option<Vec<u8>> {
+        self.is_complete().then_some(self.data)
+    }
+}
+
+/// Length-prefixed bincode codec with hard size limits
+#[derive(Debug, Clone, Default)]
+pub struct BlockTransferCodec;
+
+#[async_trait]
+impl request_response::Codec for BlockTransferCodec {
+    type Protocol = &'static str;
+    type Request = BlockRangeRequest;
+    type Response = BlockTransferResponse;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_bounded(io, MAX_REQUEST_BYTES).await
+    }
+
+    async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_bounded(io, MAX_RESPONSE_BYTES).await
+    }
+
+    async fn write_request<T>(&mut self, _: &Self::Protocol, io: &mut T, req: Self::Request) -> std::io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_bounded(io, &req, MAX_REQUEST_BYTES).await
+    }
+
+    async fn write_response<T>(&mut self, _: &Self::Protocol, io: &mut T, res: Self::Response) -> std::io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_bounded(io, &res, MAX_RESPONSE_BYTES).await
+    }
+}
+
+/// Read a u32 length and the bincode message, refusing lengths above `max`
+pub(crate) async fn read_bounded<T, M>(io: &mut T, max: usize) -> std::io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: serde::de::DeserializeOwned,
+{
+    let mut len_bytes = [0u8; 4];
+    io.read_exact(&mut len_bytes).await?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > max {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("message of {} bytes exceeds limit of {}", len, max),
+        ));
+    }
+
+    let mut buf = vec![0u8; len];
+    io.read_exact(&mut buf).await?;
+    bincode::deserialize(&buf).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Write a u32 length and the bincode message, refusing messages above `max`
+pub(crate) async fn write_bounded<T, M>(io: &mut T, message: &M, max: usize) -> std::io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let data = bincode::serialize(message).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    if data.len() > max {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("message of {} bytes exceeds limit of {}", data.len(), max),
+        ));
+    }
+    io.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    io.write_all(&data).await?;
+    io.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use request_response::Codec;
+
+    #[test]
+    fn test_assemble_and_resume() {
+        let block: Vec<u8> = (0..(MAX_FRAME_BYTES * 2 + 1000)).map(|i| i as u8).collect();
+        let id = Uuid::new_v4();
+        let mut assembler = BlockAssembler::new(id);
+
+        // First provider serves one frame, then goes away
+        let request = assembler.next_request().unwrap();
+        assert_eq!(request.offset, 0);
+        assembler.accept(serve_range(Some(&block), &request)).unwrap();
+        assert_eq!(assembler.received(), MAX_FRAME_BYTES as u64);
+        assert!(assembler.accept(serve_range(None, &assembler.next_request().unwrap())).is_err());
+
+        // A second provider continues where the first stopped
+        while let Some(request) = assembler.next_request() {
+            assert_eq!(request.offset, assembler.received());
+            assembler.accept(serve_range(Some(&block), &request)).unwrap();
+        }
+        assert_eq!(assembler.finish().unwrap(), block);
+    }
+
+    #[tokio::test]
+    async fn test_assemble_from_block_store() {
+        use crate::block_store::{BlockStore, EmbeddedBlockStore};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = EmbeddedBlockStore::open(dir.path()).unwrap();
+        let block: Vec<u8> = (0..(MAX_FRAME_BYTES * 2 + 7)).map(|i| (i % 251) as u8).collect();
+        let id = Uuid::new_v4();
+        store.put(&id.to_string(), &block).await.unwrap();
+
+        // Each frame reads only its own range
+        let mut assembler = BlockAssembler::new(id);
+        while let Some(request) = assembler.next_request() {
+            let range = store.get_range(&id.to_string(), request.offset, request.frame_len()).await.unwrap();
+            assembler.accept(frame_from_range(range, &request)).unwrap();
+        }
+        assert_eq!(assembler.finish().unwrap(), block);
+
+        let missing = BlockRangeRequest::first(Uuid::new_v4());
+        let range = store.get_range(&missing.block_id.to_string(), 0, missing.frame_len()).await.unwrap();
+        assert_eq!(frame_from_range(range, &missing), BlockTransferResponse::NotFound);
+    }
+
+    #[test]
+    fn test_rejects_bad_frames() {
+        let id = Uuid::new_v4();
+        let mut assembler = BlockAssembler::new(id);
+        let gap = BlockTransferResponse::Frame { total_size: 10, offset: 4, data: vec![0; 4] };
+        assert!(assembler.accept(gap).is_err());
+        let huge = BlockTransferResponse::Frame { total_size: MAX_BLOCK_BYTES + 1, offset: 0, data: vec![0; 4] };
+        assert!(assembler.accept(huge).is_err());
+
+        assembler.accept(BlockTransferResponse::Frame { total_size: 10, offset: 0, data: vec![1; 6] }).unwrap();
+        let resized = BlockTransferResponse::Frame { total_size: 12, offset: 6, data: vec![1; 4] };
+        assert!(assembler.accept(resized).is_err());
+
+        // Empty blocks complete with a single empty frame
+        let mut empty = BlockAssembler::new(id);
+        empty.accept(serve_range(Some(&[]), &BlockRangeRequest::first(id))).unwrap();
+        assert_eq!(empty.finish().unwrap(), Vec::<u8>::new());
+    }
+
+    #[tokio::test]
+    async fn test_codec_enforces_limits() {
+        let mut codec = BlockTransferCodec;
+        let request = BlockRangeRequest::first(Uuid::new_v4());
+        let mut wire = Vec::new();
+        codec.write_request(&PROTOCOL, &mut wire, request).await.unwrap();
+        assert!(wire.len() <= 4 + MAX_REQUEST_BYTES);
+        let decoded = codec.read_request(&PROTOCOL, &mut futures::io::Cursor::new(wire)).await.unwrap();
+        assert_eq!(decoded, request);
+
+        // A peer announcing a 4 GiB message is refused before anything is allocated
+        let mut hostile = u32::MAX.to_be_bytes().to_vec();
+        hostile.extend_from_slice(&[0u8; 16]);
+        let result = codec.read_response(&PROTOCOL, &mut futures::io::Cursor::new(hostile)).await;
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,196 @@
+// CONTENT-DEFINED CHUNKING
+// FastCDC (gear rolling hash with normalized chunking) places chunk boundaries where the
+// content says so: inserting bytes only changes the chunks around the edit instead of
+// shifting every boundary after it, which keeps deduplication working across edits
+
+use crate::error::{MSSCSError, Result};
+use serde::{Deserialize, Serialize};
+
+/// Gear table of the rolling hash (fixed so boundaries are stable across builds and nodes)
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x4d53_5343_5334_4344;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// How file data is split into chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Chunking {
+    /// Fixed-size chunks
+    Fixed { size: usize },
+    /// FastCDC chunks between `min_size` and `max_size`, averaging about `avg_size`
+    Cdc {
+        min_size: usize,
+        avg_size: usize,
+        max_size: usize,
+    },
+}
+
+impl Chunking {
+    /// Content-defined chunking around `avg_size` (min = avg / 4, max = avg * 8)
+    pub fn content_defined(avg_size: usize) -> Self {
+        Chunking::Cdc {
+            min_size: (avg_size / 4).max(1),
+            avg_size,
+            max_size: avg_size.saturating_mul(8),
+        }
+    }
+
+    /// Reject sizes that cannot produce chunks
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            Chunking::Fixed { size: 0 } => {
+                Err(MSSCSError::Validation("Chunk size must be greater than 0".to_string()))
+            }
+            Chunking::Cdc { min_size, avg_size, max_size }
+                if min_size == 0 || min_size > avg_size || avg_size > max_size =>
+            {
+                Err(MSSCSError::Validation(format!(
+                    "Chunk sizes must satisfy 0 < min ({}) <= avg ({}) <= max ({})",
+                    min_size, avg_size, max_size
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Split `data` into chunks; empty input yields a single empty chunk
+    pub fn split<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        if data.is_empty() {
+            return vec![data];
+        }
+
+        match *self {
+            Chunking::Fixed { size } => data.chunks(size.max(1)).collect(),
+            Chunking::Cdc { min_size, avg_size, max_size } => {
+                let mut chunks = Vec::new();
+                let mut rest = data;
+                while !rest.is_empty() {
+                    let cut = cut_point(rest, min_size, avg_size, max_size);
+                    let (chunk, tail) = rest.split_at(cut);
+                    chunks.push(chunk);
+                    rest = tail;
+                }
+                chunks
+            }
+        }
+    }
+}
+
+/// Length of the next FastCDC chunk at the start of `data`
+/// A stricter mask is used before `avg_size` and a looser one after it, which
+/// concentrates chunk sizes around the average (normalized chunking)
+fn cut_point(data: &[u8], min_size: usize, avg_size: usize, max_size: usize) -> usize {
+    let len = data.len();
+    if len <= min_size {
+        return len;
+    }
+    let max_size = max_size.min(len);
+    let normal_size = avg_size.min(max_size);
+
+    let bits = avg_size.max(2).ilog2();
+    let mask_small = high_bits_mask(bits + 1);
+    let mask_large = high_bits_mask(bits - 1);
+
+    let mut hash = 0u64;
+    let mut i = min_size;
+    while i < normal_size {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & mask_small == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    while i < max_size {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & mask_large == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    max_size
+}
+
+/// Mask of the top `bits` bits (the ones influenced by the most recent 64 bytes)
+fn high_bits_mask(bits: u32) -> u64 {
+    match bits {
+        0 => 0,
+        bits if bits >= 64 => u64::MAX,
+        bits => !0u64 << (64 - bits),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn pseudo_random(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fixed_chunking() {
+        let data = vec![1u8; 2500];
+        let sizes: Vec<usize> = Chunking::Fixed { size: 1024 }.split(&data).iter().map(|c| c.len()).collect();
+        assert_eq!(sizes, vec![1024, 1024, 452]);
+        assert_eq!(Chunking::Fixed { size: 1024 }.split(&[]).len(), 1);
+        assert!(Chunking::Fixed { size: 0 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_cdc_respects_bounds() {
+        let chunking = Chunking::content_defined(4096);
+        assert!(chunking.validate().is_ok());
+        assert!(Chunking::Cdc { min_size: 8, avg_size: 4, max_size: 16 }.validate().is_err());
+
+        let data = pseudo_random(512 * 1024, 42);
+        let chunks = chunking.split(&data);
+        assert_eq!(chunks.concat(), data);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= 1024 && chunk.len() <= 32 * 1024);
+        }
+
+        // Roughly the requested average
+        let avg = data.len() / chunks.len();
+        assert!((2048..=8192).contains(&avg), "average chunk size {}", avg);
+    }
+
+    #[test]
+    fn test_cdc_survives_insertions() {
+        let chunking = Chunking::content_defined(4096);
+        let data = pseudo_random(256 * 1024, 7);
+        let mut edited = b"one inserted line\n".to_vec();
+        edited.extend_from_slice(&data);
+
+        let original: HashSet<&[u8]> = chunking.split(&data).into_iter().collect();
+        let shifted = chunking.split(&edited);
+        let shared = shifted.iter().filter(|chunk| original.contains(*chunk)).count();
+        assert!(shared * 10 >= original.len() * 9, "only {} of {} chunks survived", shared, original.len());
+
+        // Fixed-size chunking loses every chunk to the same edit
+        let fixed = Chunking::Fixed { size: 4096 };
+        let original: HashSet<&[u8]> = fixed.split(&data).into_iter().collect();
+        assert!(fixed.split(&edited).iter().all(|chunk| !original.contains(*chunk)));
+    }
+}
@@ -1,18 +1,35 @@
 // Configuration module
+use crate::block_store::StorageBackend;
 use crate::error::{MSSCSError, Result};
+use crate::namespace::RetentionPolicy;
+use crate::repair::RepairConfig;
+use libp2p::Multiaddr;
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::{Path, PathBuf};
 
 /// System configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub port: u16,
+    /// Address the HTTP API listens on
+    #[serde(default = "default_api_host")]
+    pub api_host: IpAddr,
+    /// libp2p listen port (defaults to `port + 1`)
+    #[serde(default)]
+    pub p2p_port: Option<u16>,
     pub data_dir: PathBuf,
     pub replication_factor: usize,
     pub chunk_size: usize,
     pub log_level: String,
     pub bootstrap_peers: Vec<String>,
     pub api_keys: Option<Vec<String>>,
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    #[serde(default)]
+    pub repair: RepairConfig,
 }
 
 impl Config {
@@ -37,12 +54,17 @@ impl Config {
     pub fn default() -> Self {
         Config {
             port: 8080,
+            api_host: default_api_host(),
+            p2p_port: None,
             data_dir: PathBuf::from("./msscs_data"),
             replication_factor: 3,
             chunk_size: 1024,
             log_level: "info".to_string(),
             bootstrap_peers: Vec::new(),
             api_keys: None,
+            storage_backend: StorageBackend::default(),
+            retention: RetentionPolicy::default(),
+            repair: RepairConfig::default(),
         }
     }
     
@@ -52,6 +74,10 @@ impl Config {
             return Err(MSSCSError::Config("Port cannot be 0".to_string()));
         }
         
+        if self.p2p_port == Some(self.port) {
+            return Err(MSSCSError::Config("P2P port must differ from the API port".to_string()));
+        }
+        
         if self.replication_factor == 0 {
             return Err(MSSCSError::Config("Replication factor must be at least 1".to_string()));
         }
@@ -60,6 +86,14 @@ impl Config {
             return Err(MSSCSError::Config("Chunk size must be greater than 0".to_string()));
         }
         
+        if self.retention.keep_versions == Some(0) {
+            return Err(MSSCSError::Config("Retention must keep at least 1 version".to_string()));
+        }
+        
+        if self.repair.interval_secs == 0 {
+            return Err(MSSCSError::Config("Repair interval must be at least 1 second".to_string()));
+        }
+        
         let valid_log_levels = ["trace", "debug", "info", "warn", "error"];
         if !valid_log_levels.contains(&self.log_level.as_str()) {
             return Err(MSSCSError::Config(format!(
@@ -70,4 +104,42 @@ impl Config {
         
         Ok(())
     }
+    
+    /// Whether the API may serve recovery approvals: anyone reaching it could otherwise
+    /// approve a recovery request in the contact's name
+    pub fn allows_recovery_api(&self) -> bool {
+        self.api_keys.as_ref().is_some_and(|keys| !keys.is_empty()) || self.api_host.is_loopback()
+    }
+    
+    /// Port the libp2p node listens on
+    pub fn p2p_listen_port(&self) -> u16 {
+        self.p2p_port.unwrap_or_else(|| self.port.saturating_add(1))
+    }
+    
+    /// Bootstrap peers as multiaddrs
+    /// `host:port` entries written for the retired TCP transport named the peer's API port;
+    /// they are mapped to that peer's default libp2p port (`port + 1`)
+    pub fn bootstrap_multiaddrs(&self) -> Vec<Multiaddr> {
+        self.bootstrap_peers
+            .iter()
+            .filter_map(|peer| {
+                let translated = match peer.rsplit_once(':') {
+                    Some((host, port)) if !peer.starts_with('/') => port
+                        .parse::<u16>()
+                        .ok()
+                        .map(|port| format!("{}:{}", host, port.saturating_add(1))),
+                    _ => Some(peer.clone()),
+                };
+                let addr = translated.as_deref().and_then(crate::p2p_network::P2PConfig::parse_peer_address);
+                if addr.is_none() {
+                    tracing::warn!("Ignoring unparseable bootstrap peer '{}'", peer);
+                }
+                addr
+            })
+            .collect()
+    }
+}
+
+fn default_api_host() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
 }
@@ -1,11 +1,22 @@
 // CONTENT-ADDRESSED STORAGE (CAS)
 // Uses BLAKE3 hashing for content addressing and automatic deduplication
 
+use crate::adaptive_compression::CompressionAlgorithm;
+use crate::error::MSSCSError;
+use aes_gcm::{
+    aead::{Aead, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
 use blake3;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 
+const CONVERGENT_NONCE_SIZE: usize = 12;
+const CONVERGENT_CIPHER_CONTEXT: &str = "msscs v4 convergent chunk cipher key";
+const CONVERGENT_NONCE_CONTEXT: &str = "msscs v4 convergent chunk nonce key";
+
 /// Content identifier (CID) - BLAKE3 hash of content
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ContentId([u8; 32]);
@@ -222,6 +233,219 @@ pub struct CASStats {
     pub dedup_ratio: f64,
 }
 
+/// Secret that scopes convergent encryption to one workspace
+/// Identical chunks sealed under the same key yield identical ciphertext (and CID) so they
+/// are stored once; without the key nobody can confirm what a chunk contains
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConvergenceKey([u8; 32]);
+
+impl ConvergenceKey {
+    /// Generate a fresh random key
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        ConvergenceKey(bytes)
+    }
+
+    /// Create from raw bytes
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        ConvergenceKey(bytes)
+    }
+
+    /// Get raw bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Deterministically encrypt a chunk (nonce || AES-256-GCM ciphertext)
+    /// The nonce is a keyed hash of the plaintext, so a nonce is only reused for identical input
+    pub fn seal(&self, plaintext: &[u8]) -> crate::error::Result<Vec<u8>> {
+        let nonce_key = blake3::derive_key(CONVERGENT_NONCE_CONTEXT, &self.0);
+        let digest = blake3::keyed_hash(&nonce_key, plaintext);
+        let nonce = &digest.as_bytes()[..CONVERGENT_NONCE_SIZE];
+
+        let ciphertext = self
+            .cipher()
+            .encrypt(Nonce::from_slice(nonce), plaintext)
+            .map_err(|e| MSSCSError::Crypto(format!("Chunk encryption failed: {}", e)))?;
+
+        let mut sealed = Vec::with_capacity(CONVERGENT_NONCE_SIZE + ciphertext.len());
+        sealed.extend_from_slice(nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt a chunk produced by `seal`
+    pub fn open(&self, sealed: &[u8]) -> crate::error::Result<Vec<u8>> {
+        if sealed.len() < CONVERGENT_NONCE_SIZE {
+            return Err(MSSCSError::InvalidData("Sealed chunk is truncated".to_string()));
+        }
+        let (nonce, ciphertext) = sealed.split_at(CONVERGENT_NONCE_SIZE);
+
+        self.cipher()
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|e| MSSCSError::Crypto(format!("Chunk decryption failed: {}", e)))
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        let key = blake3::derive_key(CONVERGENT_CIPHER_CONTEXT, &self.0);
+        Aes256Gcm::new(&key.into())
+    }
+}
+
+impl fmt::Debug for ConvergenceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ConvergenceKey(<redacted>)")
+    }
+}
+
+/// One content-addressed chunk of a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRef {
+    /// Hex-encoded CID of the sealed chunk
+    pub cid: String,
+    /// Plaintext size in bytes
+    pub size: u64,
+    /// Compression applied before sealing
+    pub compression: CompressionAlgorithm,
+}
+
+/// CID committing to an ordered chunk list
+pub fn chunk_list_cid(chunks: &[ChunkRef]) -> ContentId {
+    let mut hasher = blake3::Hasher::new();
+    for chunk in chunks {
+        hasher.update(chunk.cid.as_bytes());
+        hasher.update(&chunk.size.to_le_bytes());
+    }
+    ContentId::from_bytes(*hasher.finalize().as_bytes())
+}
+
+/// Refcount entry of an indexed chunk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkEntry {
+    /// Stored (sealed) size in bytes
+    pub size: usize,
+    /// Number of file versions and snapshot entries using the chunk
+    pub ref_count: usize,
+}
+
+/// Persistent refcount index over chunks kept in an external block store
+/// Unlike `ContentAddressedStorage` it holds no data; zero-ref chunks stay indexed
+/// until `garbage_collect` hands them back for deletion
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChunkIndex {
+    /// Hex CID -> entry
+    chunks: BTreeMap<String, ChunkEntry>,
+    /// Bytes not written thanks to deduplication
+    dedup_savings: usize,
+}
+
+impl ChunkIndex {
+    /// Create an empty index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reference a freshly sealed chunk; returns true if it is new and must be stored
+    pub fn add_ref(&mut self, cid: &str, size: usize) -> bool {
+        match self.chunks.get_mut(cid) {
+            Some(entry) => {
+                entry.ref_count += 1;
+                self.dedup_savings += size;
+                tracing::debug!("✨ Deduplicated chunk {} (saved {} bytes)", cid, size);
+                false
+            }
+            None => {
+                self.chunks.insert(cid.to_string(), ChunkEntry { size, ref_count: 1 });
+                true
+            }
+        }
+    }
+
+    /// Reference chunks that are already stored (restores, snapshots)
+    pub fn retain_all<'a>(&mut self, cids: impl IntoIterator<Item = &'a str>) {
+        for cid in cids {
+            match self.chunks.get_mut(cid) {
+                Some(entry) => entry.ref_count += 1,
+                None => tracing::warn!("Referencing unindexed chunk {}", cid),
+            }
+        }
+    }
+
+    /// Drop one reference; returns the remaining count
+    pub fn remove_ref(&mut self, cid: &str) -> usize {
+        match self.chunks.get_mut(cid) {
+            Some(entry) => {
+                entry.ref_count = entry.ref_count.saturating_sub(1);
+                entry.ref_count
+            }
+            None => 0,
+        }
+    }
+
+    /// Drop one reference per listed chunk
+    pub fn release_all<'a>(&mut self, cids: impl IntoIterator<Item = &'a str>) {
+        for cid in cids {
+            self.remove_ref(cid);
+        }
+    }
+
+    /// Whether a chunk is indexed
+    pub fn contains(&self, cid: &str) -> bool {
+        self.chunks.contains_key(cid)
+    }
+
+    /// CIDs of every indexed chunk
+    pub fn cids(&self) -> impl Iterator<Item = &str> {
+        self.chunks.keys().map(String::as_str)
+    }
+
+    /// Reference count of a chunk (0 if unknown)
+    pub fn ref_count(&self, cid: &str) -> usize {
+        self.chunks.get(cid).map_or(0, |entry| entry.ref_count)
+    }
+
+    /// Remove zero-ref chunks from the index and return their CIDs
+    pub fn garbage_collect(&mut self) -> Vec<String> {
+        let unreferenced: Vec<String> = self
+            .chunks
+            .iter()
+            .filter(|(_, entry)| entry.ref_count == 0)
+            .map(|(cid, _)| cid.clone())
+            .collect();
+        for cid in &unreferenced {
+            self.chunks.remove(cid);
+            tracing::debug!("🗑️  Garbage collected chunk {}", cid);
+        }
+        unreferenced
+    }
+
+    /// Number of indexed chunks
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Whether the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Get storage statistics
+    pub fn stats(&self) -> CASStats {
+        let total_bytes: usize = self.chunks.values().map(|entry| entry.size).sum();
+        CASStats {
+            total_blocks: self.chunks.len(),
+            total_bytes,
+            dedup_savings: self.dedup_savings,
+            dedup_ratio: if total_bytes > 0 {
+                (self.dedup_savings as f64 / (total_bytes + self.dedup_savings) as f64) * 100.0
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
 /// Merkle DAG node for directory structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MerkleNode {
@@ -349,6 +573,44 @@ mod tests {
         assert!(!cas.contains(&cid));
     }
 
+    #[test]
+    fn test_convergent_sealing() {
+        let key = ConvergenceKey::generate();
+        let data = b"same chunk, same ciphertext".to_vec();
+
+        let sealed = key.seal(&data).unwrap();
+        assert_eq!(sealed, key.seal(&data).unwrap());
+        assert_eq!(key.open(&sealed).unwrap(), data);
+
+        // Another workspace cannot match or read the chunk
+        let other = ConvergenceKey::generate();
+        assert_ne!(ContentId::from_data(&sealed), ContentId::from_data(&other.seal(&data).unwrap()));
+        assert!(other.open(&sealed).is_err());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(key.open(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_chunk_index_refcounts() {
+        let mut index = ChunkIndex::new();
+
+        assert!(index.add_ref("aa", 100));
+        assert!(!index.add_ref("aa", 100));
+        assert!(index.add_ref("bb", 50));
+        index.retain_all(["bb"]);
+        assert_eq!(index.stats().dedup_savings, 100);
+
+        index.release_all(["aa", "bb"]);
+        assert_eq!(index.ref_count("aa"), 1);
+        assert!(index.garbage_collect().is_empty());
+
+        index.release_all(["aa", "bb"]);
+        assert_eq!(index.garbage_collect(), vec!["aa", "bb"]);
+        assert!(index.is_empty());
+    }
+
     #[test]
     fn test_merkle_node() {
         let file_cid = ContentId::from_data(b"file content");
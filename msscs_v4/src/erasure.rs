@@ -1,7 +1,72 @@
 // Erasure Coding using Reed-Solomon for efficient data replication
+// Systematic code over GF(2^8): the first K shards are the data itself and the M parity
+// shards come from a Cauchy matrix, so any K of the K+M shards reconstruct the data
 use crate::error::{MSSCSError, Result};
 use serde::{Deserialize, Serialize};
 
+/// Field polynomial x^8 + x^4 + x^3 + x^2 + 1 (generator 2)
+const GF_POLY: u16 = 0x11D;
+
+/// Exponent table doubled so products of two logs never need a modulo
+const GF_EXP: [u8; 512] = gf_exp_table();
+/// Discrete logarithms (GF_LOG[0] is unused)
+const GF_LOG: [u8; 256] = gf_log_table();
+
+const fn gf_exp_table() -> [u8; 512] {
+    let mut table = [0u8; 512];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        table[i] = x as u8;
+        table[i + 255] = x as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= GF_POLY;
+        }
+        i += 1;
+    }
+    table
+}
+
+const fn gf_log_table() -> [u8; 256] {
+    let exp = gf_exp_table();
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 255 {
+        table[exp[i] as usize] = i as u8;
+        i += 1;
+    }
+    table
+}
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    GF_EXP[GF_LOG[a as usize] as usize + GF_LOG[b as usize] as usize]
+}
+
+fn gf_inv(a: u8) -> u8 {
+    debug_assert!(a != 0, "zero has no inverse in GF(2^8)");
+    GF_EXP[255 - GF_LOG[a as usize] as usize]
+}
+
+/// `out ^= coefficient * input`, byte by byte
+fn gf_mul_add(out: &mut [u8], input: &[u8], coefficient: u8) {
+    match coefficient {
+        0 => {}
+        1 => out.iter_mut().zip(input).for_each(|(o, i)| *o ^= i),
+        c => {
+            let log_c = GF_LOG[c as usize] as usize;
+            for (o, &i) in out.iter_mut().zip(input) {
+                if i != 0 {
+                    *o ^= GF_EXP[log_c + GF_LOG[i as usize] as usize];
+                }
+            }
+        }
+    }
+}
+
 /// Erasure-coded shard
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Shard {
@@ -36,6 +101,7 @@ impl ErasureCoding {
     /// - Can reconstruct from any K shards
     /// - Can tolerate M shard failures
     /// - Storage overhead: (K+M)/K
+    /// - K+M is limited to 256 (the size of GF(2^8))
     /// 
     /// Example: 10 data + 4 parity = 40% overhead, tolerates 4 failures
     pub fn new(data_shards: usize, parity_shards: usize) -> Result<Self> {
@@ -45,6 +111,12 @@ impl ErasureCoding {
         if parity_shards == 0 {
             return Err(MSSCSError::Validation("Parity shards must be > 0".to_string()));
         }
+        if data_shards + parity_shards > 256 {
+            return Err(MSSCSError::Validation(format!(
+                "At most 256 shards are supported, got {}",
+                data_shards + parity_shards
+            )));
+        }
 
         Ok(ErasureCoding {
             data_shards,
@@ -52,6 +124,26 @@ impl ErasureCoding {
         })
     }
 
+    /// Encoding coefficient of data shard `col` in parity shard `row`
+    /// Cauchy matrix 1 / (x_row + y_col) with x_row = K + row and y_col = col: every
+    /// square submatrix of [I; C] is invertible, which is what makes any K shards enough
+    fn parity_coefficient(&self, row: usize, col: usize) -> u8 {
+        gf_inv(((self.data_shards + row) ^ col) as u8)
+    }
+
+    /// Row of the generator matrix for shard `index`
+    fn generator_row(&self, index: usize) -> Vec<u8> {
+        if index < self.data_shards {
+            let mut row = vec![0u8; self.data_shards];
+            row[index] = 1;
+            row
+        } else {
+            (0..self.data_shards)
+                .map(|col| self.parity_coefficient(index - self.data_shards, col))
+                .collect()
+        }
+    }
+
     /// Encode data into shards using Reed-Solomon erasure coding
     /// 
     /// Splits data into K data shards and generates M parity shards
@@ -60,7 +152,7 @@ impl ErasureCoding {
         let original_size = data.len();
         
         // Calculate shard size (pad if necessary)
-        let shard_size = (data.len() + self.data_shards - 1) / self.data_shards;
+        let shard_size = data.len().div_ceil(self.data_shards);
         let padded_size = shard_size * self.data_shards;
         
         // Pad data if necessary
@@ -68,34 +160,25 @@ impl ErasureCoding {
         padded_data.resize(padded_size, 0);
         
         // Split into data shards
-        let mut shards = Vec::new();
+        let mut shards = Vec::with_capacity(self.total_shards());
         for i in 0..self.data_shards {
             let start = i * shard_size;
             let end = start + shard_size;
-            let shard_data = padded_data[start..end].to_vec();
             
             shards.push(Shard {
                 index: i,
-                data: shard_data,
+                data: padded_data[start..end].to_vec(),
                 data_shards: self.data_shards,
                 parity_shards: self.parity_shards,
                 original_size,
             });
         }
         
-        // Generate parity shards using Reed-Solomon-like encoding
-        // This is a simplified implementation using Galois Field arithmetic
+        // Parity shard i = sum over j of C[i][j] * data shard j
         for i in 0..self.parity_shards {
             let mut parity_data = vec![0u8; shard_size];
-            
-            // Use weighted XOR for better error correction
-            // Each parity shard uses different coefficients
             for (j, data_shard) in shards[..self.data_shards].iter().enumerate() {
-                let coefficient = self.galois_multiply(j + 1, i + 1);
-                
-                for (k, byte) in data_shard.data.iter().enumerate() {
-                    parity_data[k] ^= self.galois_multiply(*byte as usize, coefficient) as u8;
-                }
+                gf_mul_add(&mut parity_data, &data_shard.data, self.parity_coefficient(i, j));
             }
             
             shards.push(Shard {
@@ -109,35 +192,10 @@ impl ErasureCoding {
         
         Ok(shards)
     }
-    
-    /// Simplified Galois Field multiplication (GF(2^8))
-    /// In production, use a proper Reed-Solomon library
-    fn galois_multiply(&self, a: usize, b: usize) -> usize {
-        let mut result = 0;
-        let mut a = a & 0xFF;
-        let mut b = b & 0xFF;
-        
-        while b > 0 {
-            if b & 1 != 0 {
-                result ^= a;
-            }
-            
-            let high_bit = a & 0x80;
-            a <<= 1;
-            
-            if high_bit != 0 {
-                a ^= 0x1B; // Primitive polynomial for GF(2^8)
-            }
-            
-            b >>= 1;
-        }
-        
-        result & 0xFF
-    }
 
     /// Decode shards back into original data
     /// 
-    /// Requires at least K shards (can be any combination of data and parity shards)
+    /// Requires at least K distinct shards (can be any combination of data and parity shards)
     pub fn decode(&self, shards: &[Shard]) -> Result<Vec<u8>> {
         if shards.is_empty() {
             return Err(MSSCSError::Validation("No shards provided".to_string()));
@@ -145,269 +203,90 @@ impl ErasureCoding {
         
         // Verify all shards have same configuration
         let first = &shards[0];
-        if first.data_shards != self.data_shards || first.parity_shards != self.parity_shards {
-            return Err(MSSCSError::Validation("Shard configuration mismatch".to_string()));
+        let shard_size = first.data.len();
+        let original_size = first.original_size;
+        for shard in shards {
+            if shard.data_shards != self.data_shards || shard.parity_shards != self.parity_shards {
+                return Err(MSSCSError::Validation("Shard configuration mismatch".to_string()));
+            }
+            if shard.data.len() != shard_size || shard.original_size != original_size {
+                return Err(MSSCSError::InvalidData("Shards belong to different encodings".to_string()));
+            }
+        }
+        if original_size > shard_size * self.data_shards {
+            return Err(MSSCSError::InvalidData("Shard size too small for original data".to_string()));
+        }
+        
+        // One shard per index, ignoring duplicates and out-of-range indices
+        let mut by_index: Vec<Option<&Shard>> = vec![None; self.total_shards()];
+        for shard in shards {
+            if shard.index < by_index.len() && by_index[shard.index].is_none() {
+                by_index[shard.index] = Some(shard);
+            }
         }
         
         // Check if we have enough shards
-        if shards.len() < self.data_shards {
+        let available = by_index.iter().filter(|s| s.is_some()).count();
+        if available < self.data_shards {
             return Err(MSSCSError::Validation(format!(
                 "Insufficient shards: need {}, have {}",
                 self.data_shards,
-                shards.len()
+                available
             )));
         }
         
-        let original_size = first.original_size;
-        let shard_size = first.data.len();
-        
-        // Separate data and parity shards
-        let mut data_shards: Vec<Option<&Shard>> = vec![None; self.data_shards];
-        let mut parity_shards: Vec<Option<&Shard>> = vec![None; self.parity_shards];
-        
-        for shard in shards {
-            if shard.index < self.data_shards {
-                data_shards[shard.index] = Some(shard);
-            } else {
-                let parity_index = shard.index - self.data_shards;
-                if parity_index < self.parity_shards {
-                    parity_shards[parity_index] = Some(shard);
-                }
-            }
-        }
-        
-        // Count available data shards
-        let available_data = data_shards.iter().filter(|s| s.is_some()).count();
+        let missing_data = by_index[..self.data_shards].iter().filter(|s| s.is_none()).count();
+        let mut result = Vec::with_capacity(shard_size * self.data_shards);
         
-        if available_data == self.data_shards {
+        if missing_data == 0 {
             // All data shards available, reconstruct directly
-            let mut result = Vec::with_capacity(original_size);
-            for shard_opt in data_shards {
-                if let Some(shard) = shard_opt {
-                    result.extend_from_slice(&shard.data);
-                }
+            for shard in by_index[..self.data_shards].iter().flatten() {
+                result.extend_from_slice(&shard.data);
             }
             result.truncate(original_size);
             return Ok(result);
         }
         
-        // Need to reconstruct missing data shards from parity
-        tracing::info!("🔄 Reconstructing {} missing data shards using Reed-Solomon", 
-            self.data_shards - available_data);
-        
-        // Collect available shards (both data and parity)
-        let mut available_shards: Vec<(usize, &[u8])> = Vec::new();
-        for (i, shard_opt) in data_shards.iter().enumerate() {
-            if let Some(shard) = shard_opt {
-                available_shards.push((i, &shard.data));
-            }
-        }
-        for (i, shard_opt) in parity_shards.iter().enumerate() {
-            if let Some(shard) = shard_opt {
-                available_shards.push((self.data_shards + i, &shard.data));
-            }
-        }
-        
-        // Reconstruct missing data shards using Gaussian elimination
-        let mut reconstructed_data = vec![vec![0u8; shard_size]; self.data_shards];
-        
-        // Build encoding matrix
-        let mut matrix = vec![vec![0u8; self.data_shards]; available_shards.len()];
-        for (row, (shard_idx, _)) in available_shards.iter().enumerate() {
-            for col in 0..self.data_shards {
-                if *shard_idx < self.data_shards {
-                    // Data shard - identity matrix
-                    matrix[row][col] = if col == *shard_idx { 1 } else { 0 };
-                } else {
-                    // Parity shard - use encoding coefficients
-                    let parity_idx = shard_idx - self.data_shards;
-                    matrix[row][col] = self.galois_multiply(col + 1, parity_idx + 1) as u8;
+        tracing::info!("🔄 Reconstructing {} missing data shards using Reed-Solomon", missing_data);
+        
+        // Take K shards (data shards first, as their rows are trivial) and invert their rows
+        let chosen: Vec<&Shard> = by_index.iter().flatten().take(self.data_shards).copied().collect();
+        let matrix: Vec<Vec<u8>> = chosen.iter().map(|s| self.generator_row(s.index)).collect();
+        let inverse = invert_matrix(matrix)?;
+        
+        // Data shard j = sum over i of inverse[j][i] * chosen shard i
+        for (j, row) in inverse.iter().enumerate() {
+            match by_index[j] {
+                Some(shard) => result.extend_from_slice(&shard.data),
+                None => {
+                    let mut data = vec![0u8; shard_size];
+                    for (coefficient, shard) in row.iter().zip(&chosen) {
+                        gf_mul_add(&mut data, &shard.data, *coefficient);
+                    }
+                    result.extend_from_slice(&data);
                 }
             }
         }
-        
-        // Solve for each byte position
-        for byte_pos in 0..shard_size {
-            // Build system of equations: matrix * data = available_data
-            let mut equations = vec![0u8; available_shards.len()];
-            for (i, (_, shard_data)) in available_shards.iter().enumerate() {
-                equations[i] = shard_data[byte_pos];
-            }
-            
-            // Gaussian elimination in GF(2^8)
-            let solution = self.solve_galois_system(&matrix, &equations)?;
-            
-            // Store reconstructed bytes
-            for (i, &byte) in solution.iter().enumerate() {
-                reconstructed_data[i][byte_pos] = byte;
-            }
-        }
-        
-        // Combine reconstructed data shards
-        let mut result = Vec::with_capacity(original_size);
-        for shard_data in reconstructed_data {
-            result.extend_from_slice(&shard_data);
-        }
         result.truncate(original_size);
         
         Ok(result)
     }
-    
-    /// Solve system of linear equations in Galois Field GF(2^8)
-    fn solve_galois_system(&self, matrix: &[Vec<u8>], equations: &[u8]) -> Result<Vec<u8>> {
-        let n = matrix[0].len();
-        let m = matrix.len();
-        
-        if m < n {
-            return Err(MSSCSError::Validation("Insufficient equations".to_string()));
-        }
-        
-        // Create augmented matrix
-        let mut aug = vec![vec![0u8; n + 1]; n];
-        for i in 0..n {
-            for j in 0..n {
-                aug[i][j] = matrix[i][j];
-            }
-            aug[i][n] = equations[i];
-        }
-        
-        // Forward elimination
-        for i in 0..n {
-            // Find pivot
-            let mut pivot_row = i;
-            for k in (i + 1)..n {
-                if aug[k][i] != 0 {
-                    pivot_row = k;
-                    break;
-                }
-            }
-            
-            if aug[pivot_row][i] == 0 {
-                continue; // Skip if column is all zeros
-            }
-            
-            // Swap rows
-            if pivot_row != i {
-                aug.swap(i, pivot_row);
-            }
-            
-            // Eliminate column
-            let pivot = aug[i][i];
-            let pivot_inv = self.galois_inverse(pivot);
-            
-            for k in (i + 1)..n {
-                if aug[k][i] != 0 {
-                    let factor = self.galois_multiply(aug[k][i] as usize, pivot_inv as usize) as u8;
-                    for j in i..=n {
-                        let prod = self.galois_multiply(factor as usize, aug[i][j] as usize) as u8;
-                        aug[k][j] ^= prod;
-                    }
-                }
-            }
-        }
-        
-        // Back substitution
-        let mut solution = vec![0u8; n];
-        for i in (0..n).rev() {
-            if aug[i][i] == 0 {
-                continue;
-            }
-            
-            let mut sum = aug[i][n];
-            for j in (i + 1)..n {
-                let prod = self.galois_multiply(aug[i][j] as usize, solution[j] as usize) as u8;
-                sum ^= prod;
-            }
-            
-            let pivot_inv = self.galois_inverse(aug[i][i]);
-            solution[i] = self.galois_multiply(sum as usize, pivot_inv as usize) as u8;
-        }
-        
-        Ok(solution)
-    }
-    
-    /// Galois Field inverse (GF(2^8))
-    fn galois_inverse(&self, a: u8) -> u8 {
-        if a == 0 {
-            return 0;
-        }
-        
-        // Use extended Euclidean algorithm
-        let mut t = 0u16;
-        let mut new_t = 1u16;
-        let mut r = 0x11Bu16; // Primitive polynomial
-        let mut new_r = a as u16;
-        
-        while new_r != 0 {
-            let quotient = self.galois_divide(r, new_r);
-            
-            let temp_t = t;
-            t = new_t;
-            new_t = temp_t ^ self.galois_multiply_u16(quotient, new_t);
-            
-            let temp_r = r;
-            r = new_r;
-            new_r = temp_r ^ self.galois_multiply_u16(quotient, new_r);
-        }
-        
-        (t & 0xFF) as u8
-    }
-    
-    /// Galois Field division
-    fn galois_divide(&self, a: u16, b: u16) -> u16 {
-        if b == 0 {
-            return 0;
-        }
-        
-        let mut quotient = 0u16;
-        let mut remainder = a;
-        let mut divisor = b;
-        
-        // Align divisor with remainder
-        while divisor < 0x100 && remainder >= 0x100 {
-            divisor <<= 1;
-        }
-        
-        while divisor >= b {
-            if remainder >= divisor {
-                remainder ^= divisor;
-                quotient ^= 1 << (divisor.trailing_zeros() - b.trailing_zeros());
-            }
-            divisor >>= 1;
-        }
-        
-        quotient
-    }
-    
-    /// Galois Field multiplication for u16
-    fn galois_multiply_u16(&self, a: u16, b: u16) -> u16 {
-        let mut result = 0u16;
-        let mut a = a;
-        let mut b = b;
-        
-        while b > 0 {
-            if b & 1 != 0 {
-                result ^= a;
-            }
-            
-            let high_bit = a & 0x100;
-            a <<= 1;
-            
-            if high_bit != 0 {
-                a ^= 0x11B; // Primitive polynomial
-            }
-            
-            b >>= 1;
-        }
-        
-        result
-    }
 
     /// Calculate storage overhead percentage
     pub fn overhead_percentage(&self) -> f64 {
         (self.parity_shards as f64 / self.data_shards as f64) * 100.0
     }
 
+    /// Number of data shards (K), also the number needed to reconstruct
+    pub fn data_shards(&self) -> usize {
+        self.data_shards
+    }
+
+    /// Number of parity shards (M)
+    pub fn parity_shards(&self) -> usize {
+        self.parity_shards
+    }
+
     /// Calculate total shards
     pub fn total_shards(&self) -> usize {
         self.data_shards + self.parity_shards
@@ -419,6 +298,45 @@ impl ErasureCoding {
     }
 }
 
+/// Invert a square matrix over GF(2^8) with Gauss-Jordan elimination
+fn invert_matrix(mut matrix: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>> {
+    let n = matrix.len();
+    let mut inverse: Vec<Vec<u8>> = (0..n)
+        .map(|i| {
+            let mut row = vec![0u8; n];
+            row[i] = 1;
+            row
+        })
+        .collect();
+    
+    for col in 0..n {
+        let pivot = (col..n)
+            .find(|&row| matrix[row][col] != 0)
+            .ok_or_else(|| MSSCSError::InvalidData("Singular decoding matrix".to_string()))?;
+        matrix.swap(col, pivot);
+        inverse.swap(col, pivot);
+        
+        // Scale the pivot row to 1
+        let scale = gf_inv(matrix[col][col]);
+        for value in matrix[col].iter_mut().chain(inverse[col].iter_mut()) {
+            *value = gf_mul(*value, scale);
+        }
+        
+        // Clear the column everywhere else
+        let pivot_row = matrix[col].clone();
+        let pivot_inverse = inverse[col].clone();
+        for row in 0..n {
+            let factor = matrix[row][col];
+            if row != col && factor != 0 {
+                gf_mul_add(&mut matrix[row], &pivot_row, factor);
+                gf_mul_add(&mut inverse[row], &pivot_inverse, factor);
+            }
+        }
+    }
+    
+    Ok(inverse)
+}
+
 /// Default erasure coding configuration (10+4)
 impl Default for ErasureCoding {
     fn default() -> Self {
@@ -496,4 +414,55 @@ mod tests {
         let decoded = ec.decode(&shards).unwrap();
         assert_eq!(decoded, data);
     }
+
+    #[test]
+    fn test_galois_field() {
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+            assert_eq!(gf_mul(a, 1), a);
+            assert_eq!(gf_mul(a, 0), 0);
+        }
+        // 0x80 * 2 wraps through the field polynomial
+        assert_eq!(gf_mul(0x80, 2), (GF_POLY & 0xFF) as u8);
+    }
+
+    #[test]
+    fn test_decode_from_parity_only() {
+        let ec = ErasureCoding::new(4, 4).unwrap();
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 + 3) as u8).collect();
+        let shards = ec.encode(&data).unwrap();
+        assert_eq!(ec.decode(&shards[4..]).unwrap(), data);
+        assert!(ErasureCoding::new(200, 57).is_err());
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_any_k_shards_reconstruct(
+            data_shards in 1usize..12,
+            parity_shards in 1usize..6,
+            data in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..2048),
+            seed in proptest::prelude::any::<u64>(),
+        ) {
+            let ec = ErasureCoding::new(data_shards, parity_shards).unwrap();
+            let shards = ec.encode(&data).unwrap();
+            proptest::prop_assert_eq!(shards.len(), data_shards + parity_shards);
+
+            // Drop M shards chosen by the seed and decode from the remaining K
+            let mut kept = shards.clone();
+            let mut state = seed | 1;
+            for _ in 0..parity_shards {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                kept.remove((state % kept.len() as u64) as usize);
+            }
+            proptest::prop_assert_eq!(ec.decode(&kept).unwrap(), data.clone());
+
+            // One more loss is unrecoverable
+            kept.pop();
+            proptest::prop_assert!(ec.decode(&kept).is_err());
+        }
+    }
 }
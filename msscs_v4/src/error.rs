@@ -45,6 +45,12 @@ pub enum MSSCSError {
 
     #[error("Permission denied: {0}")]
     PermissionDenied(String),
+
+    #[error("Validation error: {0}")]
+    Validation(String),
+
+    #[error("Decryption error: {0}")]
+    Decryption(String),
 }
 
 impl From<serde_json::Error> for MSSCSError {
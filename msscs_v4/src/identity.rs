@@ -1,5 +1,7 @@
 // Identity module - Quantum-resistant cryptographic identities
 use crate::error::{MSSCSError, Result};
+use crate::identity_backup::IdentityBundle;
+use crate::key_rotation::RotationStatement;
 use crate::unlocked_identity::UnlockedIdentity;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -38,80 +40,21 @@ pub struct QuantumIdentity {
     pub trust_score: f64,
     /// Last seen timestamp
     pub last_seen: Option<u64>,
+    /// Statements linking each earlier key generation to the next, oldest first
+    #[serde(default)]
+    pub rotations: Vec<RotationStatement>,
 }
 
 impl QuantumIdentity {
     /// Create new quantum identity with passphrase-protected keys
     /// SECURITY FIX: Properly encrypts all secret keys with Argon2-derived key
     pub fn new(name: String, passphrase: &str) -> Result<Self> {
-        use ed25519_dalek::SigningKey;
-        use pqc_kyber::*;
-        use pqcrypto_dilithium::dilithium5;
-        use argon2::{Argon2, password_hash::{SaltString, PasswordHasher}};
-        use aes_gcm::{Aes256Gcm, KeyInit, aead::{Aead, OsRng as AeadRng}};
-        use aes_gcm::aead::generic_array::GenericArray;
-
         let id = Uuid::new_v4();
+        let ([public_key, pq_public_key, dilithium_public_key], [ed_secret, kyber_secret, dilithium_secret]) =
+            generate_keypairs()?;
 
-        // Generate Ed25519 keypair for standard operations
-        let ed_keypair = SigningKey::generate(&mut rand::rngs::OsRng);
-        let public_key = ed_keypair.verifying_key().to_bytes().to_vec();
-        let ed_secret = ed_keypair.to_bytes().to_vec();
-
-        // Generate post-quantum keypair (Kyber-1024)
-        let mut rng = rand::rngs::OsRng;
-        let kyber_keys = keypair(&mut rng).unwrap();
-        let pq_public_key = kyber_keys.public.to_vec();
-        let kyber_secret = kyber_keys.secret.to_vec();
-
-        // Generate Dilithium keypair for post-quantum signatures
-        use pqcrypto_traits::sign::{PublicKey as PQPublicKey, SecretKey as PQSecretKey};
-        let (dilithium_pk, dilithium_sk) = dilithium5::keypair();
-        let dilithium_public_key = dilithium_pk.as_bytes().to_vec();
-        let dilithium_secret = dilithium_sk.as_bytes().to_vec();
-
-        // SECURITY FIX: Generate cryptographically secure salt
-        let salt = SaltString::generate(&mut AeadRng);
-        let salt_bytes = salt.as_str().as_bytes().to_vec();
-
-        // SECURITY FIX: Derive encryption key from passphrase using Argon2id
-        let argon2 = Argon2::default();
-        let password_hash = argon2.hash_password(passphrase.as_bytes(), &salt)
-            .map_err(|e| MSSCSError::Crypto(format!("Argon2 key derivation failed: {}", e)))?;
-        
-        let mut encryption_key = [0u8; 32];
-        let hash_output = password_hash.hash.ok_or_else(|| 
-            MSSCSError::Crypto("No hash output from Argon2".to_string()))?;
-        encryption_key.copy_from_slice(&hash_output.as_bytes()[..32]);
-
-        // SECURITY FIX: Use unique nonces for each encryption
-        let cipher = Aes256Gcm::new(GenericArray::from_slice(&encryption_key));
-        
-        // Generate unique nonces for each key (CRITICAL: never reuse nonces!)
-        let mut nonce1_bytes = [0u8; 12];
-        let mut nonce2_bytes = [0u8; 12];
-        let mut nonce3_bytes = [0u8; 12];
-        use rand::RngCore;
-        rand::rngs::OsRng.fill_bytes(&mut nonce1_bytes);
-        rand::rngs::OsRng.fill_bytes(&mut nonce2_bytes);
-        rand::rngs::OsRng.fill_bytes(&mut nonce3_bytes);
-        
-        let nonce1 = aes_gcm::Nonce::from_slice(&nonce1_bytes);
-        let nonce2 = aes_gcm::Nonce::from_slice(&nonce2_bytes);
-        let nonce3 = aes_gcm::Nonce::from_slice(&nonce3_bytes);
-
-        // Encrypt secret keys with derived key
-        let mut encrypted_ed25519_secret = cipher.encrypt(nonce1, ed_secret.as_ref())
-            .map_err(|e| MSSCSError::Crypto(format!("Failed to encrypt Ed25519 key: {}", e)))?;
-        let mut encrypted_kyber_secret = cipher.encrypt(nonce2, kyber_secret.as_ref())
-            .map_err(|e| MSSCSError::Crypto(format!("Failed to encrypt Kyber key: {}", e)))?;
-        let mut encrypted_dilithium_secret = cipher.encrypt(nonce3, dilithium_secret.as_ref())
-            .map_err(|e| MSSCSError::Crypto(format!("Failed to encrypt Dilithium key: {}", e)))?;
-        
-        // SECURITY FIX: Prepend nonces to ciphertexts for decryption
-        encrypted_ed25519_secret.splice(0..0, nonce1_bytes.iter().cloned());
-        encrypted_kyber_secret.splice(0..0, nonce2_bytes.iter().cloned());
-        encrypted_dilithium_secret.splice(0..0, nonce3_bytes.iter().cloned());
+        let (salt_bytes, [encrypted_ed25519_secret, encrypted_kyber_secret, encrypted_dilithium_secret]) =
+            encrypt_secrets(passphrase, [&ed_secret, &kyber_secret, &dilithium_secret])?;
 
         let identity = QuantumIdentity {
             id,
@@ -131,6 +74,7 @@ impl QuantumIdentity {
             tags: HashMap::new(),
             trust_score: 0.0,
             last_seen: None,
+            rotations: Vec::new(),
         };
 
         info!("✅ Created quantum identity with encrypted keys: {} ({})", identity.id, identity.name);
@@ -139,6 +83,13 @@ impl QuantumIdentity {
         Ok(identity)
     }
 
+    /// Re-encrypt the secret keys under `new_passphrase` with a fresh salt; the keys themselves
+    /// do not change
+    pub fn change_passphrase(&mut self, old_passphrase: &str, new_passphrase: &str) -> Result<()> {
+        *self = self.unlock(old_passphrase)?.seal(new_passphrase)?;
+        Ok(())
+    }
+
     /// Sign data using Ed25519 (requires secret key - would need secure storage)
     pub fn sign(&self, _data: &[u8]) -> Result<Vec<u8>> {
         // Note: This would require the secret key to be securely stored
@@ -262,12 +213,7 @@ impl QuantumIdentity {
         debug!("✅ Identity unlocked successfully: {}", self.name);
         debug!("   🔓 All secret keys decrypted");
 
-        Ok(UnlockedIdentity {
-            identity: self.clone(),
-            ed25519_secret,
-            kyber_secret,
-            dilithium_secret,
-        })
+        Ok(UnlockedIdentity::from_secrets(self.clone(), ed25519_secret, kyber_secret, dilithium_secret))
     }
 
     /// Encrypt data using post-quantum encryption
@@ -349,6 +295,114 @@ pub enum ReputationTier {
     Trusted,
 }
 
+/// Ed25519, Kyber-1024 and Dilithium keys, in that order
+pub(crate) type KeySet = [Vec<u8>; 3];
+
+/// Fresh Ed25519, Kyber-1024 and Dilithium keypairs as (public keys, secret keys)
+pub(crate) fn generate_keypairs() -> Result<(KeySet, KeySet)> {
+    use ed25519_dalek::SigningKey;
+    use pqcrypto_dilithium::dilithium5;
+    use pqcrypto_traits::sign::{PublicKey as PQPublicKey, SecretKey as PQSecretKey};
+
+    // Generate Ed25519 keypair for standard operations
+    let ed_keypair = SigningKey::generate(&mut rand::rngs::OsRng);
+
+    // Generate post-quantum keypair (Kyber-1024)
+    let kyber_keys = pqc_kyber::keypair(&mut rand::rngs::OsRng)
+        .map_err(|e| MSSCSError::Crypto(format!("Kyber key generation failed: {:?}", e)))?;
+
+    // Generate Dilithium keypair for post-quantum signatures
+    let (dilithium_pk, dilithium_sk) = dilithium5::keypair();
+
+    Ok((
+        [
+            ed_keypair.verifying_key().to_bytes().to_vec(),
+            kyber_keys.public.to_vec(),
+            dilithium_pk.as_bytes().to_vec(),
+        ],
+        [
+            ed_keypair.to_bytes().to_vec(),
+            kyber_keys.secret.to_vec(),
+            dilithium_sk.as_bytes().to_vec(),
+        ],
+    ))
+}
+
+/// Encrypt the Ed25519, Kyber and Dilithium secrets under a key derived from `passphrase`
+/// with a fresh salt; returns the salt and the nonce-prefixed ciphertexts
+pub(crate) fn encrypt_secrets(passphrase: &str, secrets: [&[u8]; 3]) -> Result<(Vec<u8>, KeySet)> {
+    use argon2::{Argon2, password_hash::{SaltString, PasswordHasher}};
+    use aes_gcm::{Aes256Gcm, KeyInit, aead::{Aead, OsRng as AeadRng}};
+    use aes_gcm::aead::generic_array::GenericArray;
+    use rand::RngCore;
+
+    // SECURITY FIX: Generate cryptographically secure salt
+    let salt = SaltString::generate(&mut AeadRng);
+    let salt_bytes = salt.as_str().as_bytes().to_vec();
+
+    // SECURITY FIX: Derive encryption key from passphrase using Argon2id
+    let argon2 = Argon2::default();
+    let password_hash = argon2.hash_password(passphrase.as_bytes(), &salt)
+        .map_err(|e| MSSCSError::Crypto(format!("Argon2 key derivation failed: {}", e)))?;
+
+    let mut encryption_key = [0u8; 32];
+    let hash_output = password_hash.hash.ok_or_else(||
+        MSSCSError::Crypto("No hash output from Argon2".to_string()))?;
+    encryption_key.copy_from_slice(&hash_output.as_bytes()[..32]);
+
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(&encryption_key));
+    let names = ["Ed25519", "Kyber", "Dilithium"];
+    let mut encrypted: KeySet = Default::default();
+    for (i, secret) in secrets.iter().enumerate() {
+        // Unique nonce per key (CRITICAL: never reuse nonces!), prepended for decryption
+        let mut nonce_bytes = [0u8; 12];
+        rand::rngs::OsRng.fill_bytes(&mut nonce_bytes);
+        let ciphertext = cipher.encrypt(aes_gcm::Nonce::from_slice(&nonce_bytes), *secret)
+            .map_err(|e| MSSCSError::Crypto(format!("Failed to encrypt {} key: {}", names[i], e)))?;
+        encrypted[i] = nonce_bytes.iter().copied().chain(ciphertext).collect();
+    }
+    Ok((salt_bytes, encrypted))
+}
+
+/// Environment variable holding the identity passphrase for unattended runs
+pub const PASSPHRASE_ENV: &str = "MSSCS_PASSPHRASE";
+
+/// Secret taken from the environment variable `env`, else prompted for on the terminal
+/// Secrets are never taken from the command line, where `ps` shows them, and there is no
+/// default: without either source this fails
+pub fn read_secret(env: &str, prompt: &str) -> Result<String> {
+    if let Ok(secret) = std::env::var(env) {
+        return Ok(secret);
+    }
+    rpassword::prompt_password(prompt).map_err(|e| {
+        MSSCSError::Config(format!("No terminal to prompt for the passphrase ({}); set {}", e, env))
+    })
+}
+
+/// New secret from `env`, else prompted for twice on the terminal; an empty one is refused
+pub fn read_new_secret(env: &str, prompt: &str) -> Result<String> {
+    let secret = match std::env::var(env) {
+        Ok(secret) => secret,
+        Err(_) => {
+            let secret = read_secret(env, prompt)?;
+            if rpassword::prompt_password("Repeat to confirm: ")? != secret {
+                return Err(MSSCSError::Validation("The passphrases do not match".to_string()));
+            }
+            secret
+        }
+    };
+    if secret.is_empty() {
+        return Err(MSSCSError::Validation("The passphrase must not be empty".to_string()));
+    }
+    Ok(secret)
+}
+
+/// File in the identity directory naming the current identity
+const CURRENT_IDENTITY_FILE: &str = "current";
+
+/// Subdirectory of the identity directory keeping retired key generations
+const RETIRED_IDENTITY_DIR: &str = "retired";
+
 /// Identity manager for multiple identities
 pub struct IdentityManager {
     identities: Arc<RwLock<HashMap<Uuid, QuantumIdentity>>>,
@@ -407,6 +461,203 @@ impl IdentityManager {
         Ok(())
     }
 
+    /// Load every identity stored in the data dir; the oldest becomes current if none is set
+    pub async fn load_all(&mut self) -> Result<usize> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(&self.data_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("identity") {
+                continue;
+            }
+            match path.file_stem().and_then(|s| s.to_str()).map(Uuid::parse_str) {
+                Some(Ok(id)) => ids.push(id),
+                _ => warn!("Ignoring identity file with unexpected name: {}", path.display()),
+            }
+        }
+        for id in &ids {
+            self.load_identity(id).await?;
+        }
+
+        if self.current_identity.is_none() {
+            let marked = std::fs::read_to_string(self.data_dir.join(CURRENT_IDENTITY_FILE))
+                .ok()
+                .and_then(|id| Uuid::parse_str(id.trim()).ok());
+            self.current_identity = match marked {
+                Some(id) if self.identities.read().await.contains_key(&id) => Some(id),
+                _ => self
+                    .identities
+                .read()
+                .await
+                .values()
+                    .min_by_key(|identity| (identity.created_at, identity.id))
+                    .map(|identity| identity.id),
+            };
+        }
+        Ok(ids.len())
+    }
+
+    /// Export an identity as a bundle sealed under `backup_passphrase`
+    /// Unlocking proves the caller owns it and lets the identity sign the bundle
+    pub async fn export_identity(&self, id: &Uuid, passphrase: &str, backup_passphrase: &str) -> Result<IdentityBundle> {
+        let identity = self
+            .get_identity(id)
+            .await
+            .ok_or_else(|| MSSCSError::NotFound(format!("Identity not found: {}", id)))?;
+        let bundle = IdentityBundle::export(&identity.unlock(passphrase)?, backup_passphrase)?;
+        info!("Exported identity: {}", id);
+        Ok(bundle)
+    }
+
+    /// Verify and store the identity in `bundle`, then make it current
+    /// Re-importing an identity that is already stored keeps the local copy
+    pub async fn import_identity(&mut self, bundle: &IdentityBundle, backup_passphrase: &str) -> Result<Uuid> {
+        let identity = bundle.open(backup_passphrase)?;
+        let id = identity.id;
+
+        if self.data_dir.join(format!("{}.identity", id)).exists() {
+            self.load_identity(&id).await?;
+        }
+        let existing = self.identities.read().await.get(&id).cloned();
+        match existing {
+            Some(existing) => {
+                if existing.public_key != identity.public_key
+                    || existing.pq_public_key != identity.pq_public_key
+                    || existing.dilithium_public_key != identity.dilithium_public_key
+                {
+                    return Err(MSSCSError::Validation(format!(
+                        "A different identity with id {} is already stored",
+                        id
+                    )));
+                }
+            }
+            None => {
+                self.identities.write().await.insert(id, identity);
+                self.save_identity(&id).await?;
+            }
+        }
+
+        self.set_current_identity(id).await?;
+        info!("Imported identity: {}", id);
+        Ok(id)
+    }
+
+    /// Store an identity rebuilt by social recovery and make it current, replacing a stored
+    /// copy of the same keys (whose passphrase may be the one that was lost)
+    pub async fn restore_identity(&mut self, identity: QuantumIdentity) -> Result<Uuid> {
+        let id = identity.id;
+        if self.data_dir.join(format!("{}.identity", id)).exists() {
+            self.load_identity(&id).await?;
+        }
+        if let Some(existing) = self.identities.read().await.get(&id) {
+            if existing.public_key != identity.public_key
+                || existing.pq_public_key != identity.pq_public_key
+                || existing.dilithium_public_key != identity.dilithium_public_key
+            {
+                return Err(MSSCSError::Validation(format!(
+                    "A different identity with id {} is already stored",
+                    id
+                )));
+            }
+        }
+
+        self.identities.write().await.insert(id, identity);
+        self.save_identity(&id).await?;
+        self.set_current_identity(id).await?;
+        info!("Restored identity: {}", id);
+        Ok(id)
+    }
+
+    /// Re-encrypt an identity and its retired key generations under `new_passphrase`
+    /// Nothing is written unless every copy unlocks with `old_passphrase`
+    pub async fn change_passphrase(&self, id: &Uuid, old_passphrase: &str, new_passphrase: &str) -> Result<()> {
+        let mut identity = self
+            .get_identity(id)
+            .await
+            .ok_or_else(|| MSSCSError::NotFound(format!("Identity not found: {}", id)))?;
+        identity.change_passphrase(old_passphrase, new_passphrase)?;
+        let retired = self
+            .unlock_retired(id, old_passphrase)?
+            .iter()
+            .map(|unlocked| unlocked.seal(new_passphrase))
+            .collect::<Result<Vec<_>>>()?;
+
+        for generation in &retired {
+            self.save_retired(generation)?;
+        }
+        self.identities.write().await.insert(*id, identity);
+        self.save_identity(id).await?;
+        info!("Changed passphrase of identity: {}", id);
+        Ok(())
+    }
+
+    /// Replace an identity's keys with a fresh generation signed over by the current one
+    /// The retired generation is archived first so its data keys can still be unwrapped;
+    /// returns the retired and the new generation, both unlocked
+    pub async fn rotate_keys(&self, id: &Uuid, passphrase: &str) -> Result<(UnlockedIdentity, UnlockedIdentity)> {
+        let retired = self
+            .get_identity(id)
+            .await
+            .ok_or_else(|| MSSCSError::NotFound(format!("Identity not found: {}", id)))?
+            .unlock(passphrase)?;
+        let current = retired.rotate(passphrase)?;
+
+        self.save_retired(&retired.identity)?;
+        self.identities.write().await.insert(*id, current.identity.clone());
+        self.save_identity(id).await?;
+        info!("🔄 Rotated identity {} to key generation {}", id, current.identity.version);
+        Ok((retired, current))
+    }
+
+    /// Unlock every retired key generation of an identity, oldest first
+    pub fn unlock_retired(&self, id: &Uuid, passphrase: &str) -> Result<Vec<UnlockedIdentity>> {
+        let dir = self.data_dir.join(RETIRED_IDENTITY_DIR);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let prefix = format!("{}.v", id);
+        let mut generations = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_generation = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".identity"));
+            if !is_generation {
+                continue;
+            }
+            let data = std::fs::read_to_string(&path)
+                .map_err(|e| MSSCSError::Config(format!("Failed to read retired identity: {}", e)))?;
+            let identity: QuantumIdentity = serde_json::from_str(&data)
+                .map_err(|e| MSSCSError::Config(format!("Failed to parse retired identity: {}", e)))?;
+            generations.push(identity.unlock(passphrase)?);
+        }
+        generations.sort_by_key(|unlocked| unlocked.identity.version);
+        Ok(generations)
+    }
+
+    fn save_retired(&self, identity: &QuantumIdentity) -> Result<()> {
+        let dir = self.data_dir.join(RETIRED_IDENTITY_DIR);
+        std::fs::create_dir_all(&dir)?;
+        let data = serde_json::to_string_pretty(identity)
+            .map_err(|e| MSSCSError::Config(format!("Failed to serialize identity: {}", e)))?;
+        std::fs::write(dir.join(format!("{}.v{}.identity", identity.id, identity.version)), data)
+            .map_err(|e| MSSCSError::Config(format!("Failed to write retired identity: {}", e)))?;
+        Ok(())
+    }
+
+    /// Unlock the current identity, creating one called `name` if the data dir holds none
+    pub async fn unlock_or_create(&mut self, name: &str, passphrase: &str) -> Result<UnlockedIdentity> {
+        if self.load_all().await? == 0 {
+            self.create_identity(name.to_string(), passphrase).await?;
+        }
+        let identity = self
+            .get_current_identity()
+            .await
+            .ok_or_else(|| MSSCSError::NotFound("No current identity".to_string()))?;
+        identity.unlock(passphrase)
+    }
+
     /// Save identity to disk
     pub async fn save_identity(&self, id: &Uuid) -> Result<()> {
         let identities = self.identities.read().await;
@@ -447,6 +698,8 @@ impl IdentityManager {
         }
         drop(identities);
 
+        std::fs::write(self.data_dir.join(CURRENT_IDENTITY_FILE), id.to_string())
+            .map_err(|e| MSSCSError::Config(format!("Failed to record current identity: {}", e)))?;
         self.current_identity = Some(id);
         info!("Set current identity to: {}", id);
         Ok(())
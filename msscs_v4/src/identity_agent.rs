@@ -0,0 +1,645 @@
+// IDENTITY AGENT - keeps the local identity unlocked behind a Unix socket, like ssh-agent
+// The passphrase is typed once into `msscs_v4 agent unlock`; the node, the CLIs and the desktop
+// client then ask the agent to sign, wrap and unwrap data keys, and encrypt and decrypt blocks,
+// so neither the passphrase nor the secret keys ever reach them. The socket is only reachable
+// by its owner (0600 in a 0700 directory of its own, peer uid checked by the agent and by its
+// clients), the agent refuses core dumps, and the keys it holds are pinned in RAM, wiped on
+// drop and forgotten after an idle timeout or an explicit lock
+
+use crate::block::{IdentityKey, KeyWrapper, Keyring, WrappedKey, KEY_ID_SIZE, KEY_SIZE};
+use crate::error::{MSSCSError, Result};
+use crate::identity::{IdentityManager, QuantumIdentity};
+use crate::quantum_block::{KeyHolder, QuantumDataBlock};
+use crate::unlocked_identity::UnlockedIdentity;
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Environment variable overriding the agent socket path
+pub const AGENT_SOCKET_ENV: &str = "MSSCS_AGENT_SOCK";
+
+/// Idle time after which the agent forgets the unlocked keys unless told otherwise
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Largest request or response accepted on the socket; fits a block of a few MiB
+const MAX_FRAME_BYTES: usize = 16 * 1024 * 1024;
+
+/// Socket path used when none is given: `$MSSCS_AGENT_SOCK`, else the user's runtime dir
+pub fn default_socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(AGENT_SOCKET_ENV) {
+        return PathBuf::from(path);
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("msscs").join("agent.sock"),
+        None => std::env::temp_dir().join(format!("msscs-{}", euid())).join("agent.sock"),
+    }
+}
+
+fn euid() -> u32 {
+    // SAFETY: geteuid has no preconditions
+    unsafe { libc::geteuid() }
+}
+
+/// Refuse a socket directory that is not a directory of our own with mode 0700
+/// Under a shared temp dir another user may have created it first to take over the socket
+fn check_socket_dir(dir: &Path) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(dir)?;
+    let mode = metadata.permissions().mode() & 0o777;
+    if !metadata.is_dir() || metadata.uid() != euid() || mode != 0o700 {
+        return Err(MSSCSError::PermissionDenied(format!(
+            "Agent socket directory {} must be a directory owned by uid {} with mode 0700 (owner {}, mode {:o})",
+            dir.display(),
+            euid(),
+            metadata.uid(),
+            mode
+        )));
+    }
+    Ok(())
+}
+
+/// What the agent reports about itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentStatus {
+    /// Identity whose keys are unlocked, if any
+    pub identity: Option<AgentIdentity>,
+    /// Time left before the idle timeout locks the agent
+    pub locks_in: Option<Duration>,
+}
+
+/// Public description of the identity held by the agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentIdentity {
+    pub id: Uuid,
+    pub name: String,
+    /// Current key generation
+    pub version: u32,
+    /// Retired key generations also unlocked
+    pub retired: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+enum AgentRequest {
+    Status,
+    Unlock { passphrase: String },
+    Lock,
+    Sign { data: Vec<u8> },
+    SignDilithium { data: Vec<u8> },
+    /// Public identity and id of the key that wraps data keys
+    Identity,
+    WrapDataKey { key: [u8; KEY_SIZE] },
+    UnwrapDataKey { wrapped: WrappedKey },
+    /// Encrypt a chunk into a quantum block
+    Encrypt { data: Vec<u8> },
+    Decrypt { block: Box<QuantumDataBlock> },
+}
+
+impl Drop for AgentRequest {
+    fn drop(&mut self) {
+        match self {
+            AgentRequest::Unlock { passphrase } => passphrase.zeroize(),
+            AgentRequest::WrapDataKey { key } => key.zeroize(),
+            AgentRequest::Encrypt { data } => data.zeroize(),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum AgentResponse {
+    Status(AgentStatus),
+    Locked,
+    Signature(Vec<u8>),
+    Identity { current: Box<QuantumIdentity>, key_id: [u8; KEY_ID_SIZE] },
+    WrappedKey(WrappedKey),
+    DataKey(Zeroizing<[u8; KEY_SIZE]>),
+    Block(Box<QuantumDataBlock>),
+    Plaintext(Vec<u8>),
+    Error(String),
+}
+
+/// Key generations of the identity held unlocked by the agent
+struct Generations {
+    current: UnlockedIdentity,
+    retired: Vec<UnlockedIdentity>,
+    /// Identity keys of every generation, wrapping data keys
+    keyring: Keyring,
+}
+
+impl Generations {
+    fn new(current: UnlockedIdentity, retired: Vec<UnlockedIdentity>) -> Self {
+        let keyring = Keyring {
+            current: IdentityKey::from_identity(&current),
+            retired: retired.iter().map(IdentityKey::from_identity).collect(),
+        };
+        Generations { current, retired, keyring }
+    }
+}
+
+struct AgentState {
+    unlocked: Option<Generations>,
+    last_used: Instant,
+}
+
+/// Agent serving the current identity of an identity directory
+pub struct IdentityAgent {
+    identity_dir: PathBuf,
+    idle_timeout: Option<Duration>,
+    state: Arc<Mutex<AgentState>>,
+}
+
+impl IdentityAgent {
+    /// Agent for the identities in `identity_dir`; `idle_timeout` of `None` never locks on its own
+    pub fn new(identity_dir: PathBuf, idle_timeout: Option<Duration>) -> Self {
+        IdentityAgent {
+            identity_dir,
+            idle_timeout,
+            state: Arc::new(Mutex::new(AgentState { unlocked: None, last_used: Instant::now() })),
+        }
+    }
+
+    /// Bind the agent socket, readable and writable by its owner only, in a directory of its own
+    /// A leftover socket of an agent that is gone is replaced
+    pub async fn listen(socket: &Path) -> Result<UnixListener> {
+        if let Some(dir) = socket.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            if !dir.exists() {
+                std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+            }
+            check_socket_dir(dir)?;
+        }
+        if socket.exists() {
+            if UnixStream::connect(socket).await.is_ok() {
+                return Err(MSSCSError::Config(format!("An identity agent already listens on {}", socket.display())));
+            }
+            std::fs::remove_file(socket)?;
+        }
+        let listener = UnixListener::bind(socket)?;
+        std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))?;
+        Ok(listener)
+    }
+
+    /// Serve clients until the listener fails
+    pub async fn serve(self, listener: UnixListener) -> Result<()> {
+        // Keep the unlocked keys out of core dumps and away from ptrace by other processes
+        #[cfg(target_os = "linux")]
+        // SAFETY: PR_SET_DUMPABLE only changes a flag of this process
+        unsafe {
+            libc::prctl(libc::PR_SET_DUMPABLE, 0);
+        }
+
+        if let Some(timeout) = self.idle_timeout {
+            tokio::spawn(lock_when_idle(self.state.clone(), timeout));
+        }
+        info!("🔐 Identity agent serving {}", self.identity_dir.display());
+
+        let agent = Arc::new(self);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            match stream.peer_cred() {
+                Ok(peer) if peer.uid() == euid() => {}
+                Ok(peer) => {
+                    warn!("🚫 Identity agent refused a client running as uid {}", peer.uid());
+                    continue;
+                }
+                Err(e) => {
+                    warn!("🚫 Identity agent could not identify a client: {}", e);
+                    continue;
+                }
+            }
+            let agent = agent.clone();
+            tokio::spawn(async move {
+                if let Err(e) = agent.handle_connection(stream).await {
+                    debug!("Identity agent client went away: {}", e);
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(&self, mut stream: UnixStream) -> Result<()> {
+        loop {
+            let request: AgentRequest = match read_frame(&mut stream).await {
+                Ok(request) => request,
+                Err(MSSCSError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let response = self.handle(&request).await;
+            write_frame(&mut stream, &response).await?;
+        }
+    }
+
+    async fn handle(&self, request: &AgentRequest) -> AgentResponse {
+        match request {
+            // Argon2 runs without holding the state, so other clients are served meanwhile
+            AgentRequest::Unlock { passphrase } => self.handle_unlock(passphrase).await,
+            AgentRequest::Status => AgentResponse::Status(self.status(&*self.state.lock().await)),
+            AgentRequest::Lock => {
+                let mut state = self.state.lock().await;
+                if state.unlocked.take().is_some() {
+                    info!("🔒 Identity agent locked");
+                }
+                AgentResponse::Status(self.status(&state))
+            }
+            request => self.use_keys(request, &mut *self.state.lock().await),
+        }
+    }
+
+    async fn handle_unlock(&self, passphrase: &str) -> AgentResponse {
+        let generations = match self.unlock(passphrase).await {
+            Ok(generations) => generations,
+            Err(e) => return AgentResponse::Error(e.to_string()),
+        };
+        info!(
+            "🔓 Identity agent unlocked {} ({}), key generation {}",
+            generations.current.name(),
+            generations.current.user_id(),
+            generations.current.identity.version
+        );
+        let mut state = self.state.lock().await;
+        state.unlocked = Some(generations);
+        state.last_used = Instant::now();
+        AgentResponse::Status(self.status(&state))
+    }
+
+    /// Perform an operation with the unlocked keys, which never leave the agent
+    fn use_keys(&self, request: &AgentRequest, state: &mut AgentState) -> AgentResponse {
+        let Some(generations) = &state.unlocked else {
+            return AgentResponse::Locked;
+        };
+        let response = match request {
+            AgentRequest::Sign { data } => generations.current.sign(data).map(AgentResponse::Signature),
+            AgentRequest::SignDilithium { data } => generations.current.sign_dilithium(data).map(AgentResponse::Signature),
+            AgentRequest::Identity => Ok(AgentResponse::Identity {
+                current: Box::new(generations.current.identity.clone()),
+                key_id: generations.keyring.current.id(),
+            }),
+            AgentRequest::WrapDataKey { key } => generations.keyring.current.wrap(key).map(AgentResponse::WrappedKey),
+            AgentRequest::UnwrapDataKey { wrapped } => {
+                generations.keyring.find(wrapped).unwrap(wrapped).map(|key| AgentResponse::DataKey(Zeroizing::new(key)))
+            }
+            AgentRequest::Encrypt { data } => {
+                let content_type = Some("application/octet-stream".to_string());
+                QuantumDataBlock::new(data, 0, None, [0u8; 32], &generations.current, content_type)
+                    .map(|block| AgentResponse::Block(Box::new(block)))
+            }
+            AgentRequest::Decrypt { block } => block.decode(&generations.current).map(AgentResponse::Plaintext),
+            AgentRequest::Status | AgentRequest::Unlock { .. } | AgentRequest::Lock => {
+                Err(MSSCSError::InvalidData("Not a key operation".to_string()))
+            }
+        };
+        state.last_used = Instant::now();
+        response.unwrap_or_else(|e| AgentResponse::Error(e.to_string()))
+    }
+
+    async fn unlock(&self, passphrase: &str) -> Result<Generations> {
+        let mut identities = IdentityManager::new(self.identity_dir.clone())?;
+        identities.load_all().await?;
+        let identity = identities.get_current_identity().await.ok_or_else(|| {
+            MSSCSError::NotFound(format!("No identity in {}", self.identity_dir.display()))
+        })?;
+        let passphrase = Zeroizing::new(passphrase.to_string());
+        tokio::task::spawn_blocking(move || {
+            let current = identity.unlock(&passphrase)?;
+            let retired = identities.unlock_retired(current.user_id(), &passphrase)?;
+            Ok(Generations::new(current, retired))
+        })
+        .await
+        .map_err(|e| MSSCSError::Crypto(format!("Unlocking failed: {}", e)))?
+    }
+
+    fn status(&self, state: &AgentState) -> AgentStatus {
+        let Some(generations) = &state.unlocked else {
+            return AgentStatus { identity: None, locks_in: None };
+        };
+        AgentStatus {
+            identity: Some(AgentIdentity {
+                id: *generations.current.user_id(),
+                name: generations.current.name().to_string(),
+                version: generations.current.identity.version,
+                retired: generations.retired.len(),
+            }),
+            locks_in: self.idle_timeout.map(|timeout| timeout.saturating_sub(state.last_used.elapsed())),
+        }
+    }
+}
+
+async fn lock_when_idle(state: Arc<Mutex<AgentState>>, timeout: Duration) {
+    let mut ticks = tokio::time::interval((timeout / 4).clamp(Duration::from_millis(10), Duration::from_secs(1)));
+    loop {
+        ticks.tick().await;
+        let mut state = state.lock().await;
+        if state.unlocked.is_some() && state.last_used.elapsed() >= timeout {
+            state.unlocked = None;
+            info!("🔒 Identity agent locked after {:?} idle", timeout);
+        }
+    }
+}
+
+/// Client of an identity agent; every call opens its own connection
+#[derive(Debug, Clone)]
+pub struct AgentClient {
+    socket: PathBuf,
+}
+
+impl AgentClient {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        AgentClient { socket: socket.into() }
+    }
+
+    /// Client of the agent at `default_socket_path()`
+    pub fn from_env() -> Self {
+        AgentClient::new(default_socket_path())
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    pub async fn status(&self) -> Result<AgentStatus> {
+        match self.call(AgentRequest::Status).await? {
+            AgentResponse::Status(status) => Ok(status),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Unlock the current identity of the agent's identity directory and its retired generations
+    pub async fn unlock(&self, passphrase: &str) -> Result<AgentStatus> {
+        match self.call(AgentRequest::Unlock { passphrase: passphrase.to_string() }).await? {
+            AgentResponse::Status(status) => Ok(status),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Make the agent forget the unlocked keys
+    pub async fn lock(&self) -> Result<()> {
+        match self.call(AgentRequest::Lock).await? {
+            AgentResponse::Status(_) => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Ed25519 signature by the agent's identity
+    pub async fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self.call(AgentRequest::Sign { data: data.to_vec() }).await? {
+            AgentResponse::Signature(signature) => Ok(signature),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Dilithium signature by the agent's identity
+    pub async fn sign_dilithium(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self.call(AgentRequest::SignDilithium { data: data.to_vec() }).await? {
+            AgentResponse::Signature(signature) => Ok(signature),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Keys of the agent's identity, for processes that encrypt and decrypt files through it
+    pub async fn keys(&self) -> Result<AgentKeys> {
+        match self.call(AgentRequest::Identity).await? {
+            AgentResponse::Identity { current, key_id } => {
+                Ok(AgentKeys { client: self.clone(), identity: *current, key_id })
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Wrap a data key under the agent's current identity key
+    pub async fn wrap_data_key(&self, key: &[u8; KEY_SIZE]) -> Result<WrappedKey> {
+        match self.call(AgentRequest::WrapDataKey { key: *key }).await? {
+            AgentResponse::WrappedKey(wrapped) => Ok(wrapped),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Unwrap a data key wrapped by the agent's current or a retired identity key
+    pub async fn unwrap_data_key(&self, wrapped: &WrappedKey) -> Result<[u8; KEY_SIZE]> {
+        match self.call(AgentRequest::UnwrapDataKey { wrapped: wrapped.clone() }).await? {
+            AgentResponse::DataKey(key) => Ok(*key),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Encrypt a chunk into a quantum block with the agent's identity
+    pub async fn encrypt(&self, data: &[u8]) -> Result<QuantumDataBlock> {
+        match self.call(AgentRequest::Encrypt { data: data.to_vec() }).await? {
+            AgentResponse::Block(block) => Ok(*block),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Decrypt a quantum block with the agent's identity
+    pub async fn decrypt(&self, block: &QuantumDataBlock) -> Result<Vec<u8>> {
+        match self.call(AgentRequest::Decrypt { block: Box::new(block.clone()) }).await? {
+            AgentResponse::Plaintext(data) => Ok(data),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn call(&self, request: AgentRequest) -> Result<AgentResponse> {
+        let mut stream = UnixStream::connect(&self.socket).await.map_err(|e| {
+            MSSCSError::Config(format!("No identity agent at {}: {}", self.socket.display(), e))
+        })?;
+        // Only talk to an agent of our own user: passphrases and signing requests go to it
+        let peer = stream.peer_cred()?;
+        if peer.uid() != euid() {
+            return Err(MSSCSError::PermissionDenied(format!(
+                "Identity agent at {} runs as uid {}, not {}",
+                self.socket.display(),
+                peer.uid(),
+                euid()
+            )));
+        }
+        write_frame(&mut stream, &request).await?;
+        read_frame(&mut stream).await
+    }
+}
+
+/// Identity held by the agent; every key operation is a request to it
+#[derive(Debug, Clone)]
+pub struct AgentKeys {
+    client: AgentClient,
+    identity: QuantumIdentity,
+    key_id: [u8; KEY_ID_SIZE],
+}
+
+impl AgentKeys {
+    /// Public half of the agent's current key generation
+    pub fn identity(&self) -> &QuantumIdentity {
+        &self.identity
+    }
+}
+
+#[async_trait]
+impl KeyWrapper for AgentKeys {
+    fn key_id(&self) -> [u8; KEY_ID_SIZE] {
+        self.key_id
+    }
+
+    async fn wrap_key(&self, secret: &[u8; KEY_SIZE]) -> Result<WrappedKey> {
+        self.client.wrap_data_key(secret).await
+    }
+
+    async fn unwrap_key(&self, wrapped: &WrappedKey) -> Result<[u8; KEY_SIZE]> {
+        self.client.unwrap_data_key(wrapped).await
+    }
+}
+
+#[async_trait]
+impl KeyHolder for AgentKeys {
+    fn identity_id(&self) -> Uuid {
+        self.identity.id
+    }
+
+    async fn encrypt_blocks(&self, chunks: Vec<Vec<u8>>) -> Result<Vec<QuantumDataBlock>> {
+        let mut blocks = Vec::with_capacity(chunks.len());
+        for chunk in &chunks {
+            blocks.push(self.client.encrypt(chunk).await?);
+        }
+        Ok(blocks)
+    }
+
+    async fn decrypt_blocks(&self, blocks: Vec<QuantumDataBlock>) -> Result<Vec<Vec<u8>>> {
+        let mut chunks = Vec::with_capacity(blocks.len());
+        for block in &blocks {
+            chunks.push(self.client.decrypt(block).await?);
+        }
+        Ok(chunks)
+    }
+}
+
+fn unexpected(response: AgentResponse) -> MSSCSError {
+    match response {
+        AgentResponse::Locked => {
+            MSSCSError::PermissionDenied("Identity agent is locked; run `msscs_v4 agent unlock`".to_string())
+        }
+        AgentResponse::Error(e) => MSSCSError::Crypto(format!("Identity agent: {}", e)),
+        _ => MSSCSError::InvalidData("Unexpected identity agent response".to_string()),
+    }
+}
+
+async fn write_frame<T: Serialize>(stream: &mut UnixStream, message: &T) -> Result<()> {
+    let mut frame = bincode::serialize(message)
+        .map_err(|e| MSSCSError::InvalidData(format!("Failed to encode agent message: {}", e)))?;
+    let written = async {
+        stream.write_all(&(frame.len() as u32).to_be_bytes()).await?;
+        stream.write_all(&frame).await
+    }
+    .await;
+    frame.zeroize();
+    Ok(written?)
+}
+
+async fn read_frame<T: DeserializeOwned>(stream: &mut UnixStream) -> Result<T> {
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length).await?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_BYTES {
+        return Err(MSSCSError::InvalidData(format!("Agent message of {} bytes is too large", length)));
+    }
+    let mut frame = vec![0u8; length];
+    let read = stream.read_exact(&mut frame).await;
+    let message = read.map_err(MSSCSError::from).and_then(|_| {
+        bincode::deserialize(&frame)
+            .map_err(|e| MSSCSError::InvalidData(format!("Failed to decode agent message: {}", e)))
+    });
+    frame.zeroize();
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    async fn agent(idle_timeout: Option<Duration>) -> (TempDir, AgentClient, Uuid) {
+        let dir = TempDir::new().unwrap();
+        let mut identities = IdentityManager::new(dir.path().join("identities")).unwrap();
+        let id = identities.create_identity("agent-test".to_string(), "passphrase").await.unwrap();
+        let socket = dir.path().join("run").join("agent.sock");
+        let listener = IdentityAgent::listen(&socket).await.unwrap();
+        tokio::spawn(IdentityAgent::new(dir.path().join("identities"), idle_timeout).serve(listener));
+        (dir, AgentClient::new(socket), id)
+    }
+
+    #[tokio::test]
+    async fn test_agent_uses_keys_once_unlocked() {
+        let (dir, client, id) = agent(None).await;
+        let mode = std::fs::metadata(client.socket()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(dir.path().join("run").metadata().unwrap().permissions().mode() & 0o077 == 0);
+
+        assert!(matches!(client.sign(b"data").await, Err(MSSCSError::PermissionDenied(_))));
+        let status = client.unlock("passphrase").await.unwrap();
+        assert_eq!(status.identity.unwrap().id, id);
+
+        let keys = client.keys().await.unwrap();
+        assert_eq!(keys.identity_id(), id);
+        let signature = client.sign(b"data").await.unwrap();
+        assert!(keys.identity().verify(b"data", &signature).unwrap());
+        let signature = client.sign_dilithium(b"data").await.unwrap();
+        assert!(keys.identity().verify_dilithium(b"data", &signature).unwrap());
+
+        // Data keys and blocks round-trip through the agent and match the local keys
+        let local = keys.identity().unlock("passphrase").unwrap();
+        let wrapped = keys.wrap_key(&[3u8; 32]).await.unwrap();
+        assert_eq!(keys.key_id(), IdentityKey::from_identity(&local).id());
+        assert_eq!(IdentityKey::from_identity(&local).unwrap(&wrapped).unwrap(), [3u8; 32]);
+        assert_eq!(keys.unwrap_key(&wrapped).await.unwrap(), [3u8; 32]);
+        let blocks = keys.encrypt_blocks(vec![b"chunk".to_vec()]).await.unwrap();
+        assert_eq!(blocks[0].decode(&local).unwrap(), b"chunk");
+        assert_eq!(keys.decrypt_blocks(blocks).await.unwrap(), vec![b"chunk".to_vec()]);
+
+        client.lock().await.unwrap();
+        assert_eq!(client.status().await.unwrap().identity, None);
+        assert!(matches!(keys.unwrap_key(&wrapped).await, Err(MSSCSError::PermissionDenied(_))));
+    }
+
+    #[tokio::test]
+    async fn test_agent_refuses_wrong_passphrase() {
+        let (_dir, client, _) = agent(None).await;
+        assert!(client.unlock("wrong").await.is_err());
+        assert_eq!(client.status().await.unwrap().identity, None);
+    }
+
+    #[tokio::test]
+    async fn test_agent_locks_when_idle() {
+        let (_dir, client, _) = agent(Some(Duration::from_millis(200))).await;
+        client.unlock("passphrase").await.unwrap();
+        client.sign(b"keeps the agent awake").await.unwrap();
+        assert!(client.status().await.unwrap().identity.is_some());
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(client.status().await.unwrap().identity, None);
+    }
+
+    #[tokio::test]
+    async fn test_agent_refuses_shared_socket_dir() {
+        let dir = TempDir::new().unwrap();
+        let run = dir.path().join("run");
+        std::fs::create_dir(&run).unwrap();
+        std::fs::set_permissions(&run, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(matches!(IdentityAgent::listen(&run.join("agent.sock")).await, Err(MSSCSError::PermissionDenied(_))));
+
+        std::fs::set_permissions(&run, std::fs::Permissions::from_mode(0o700)).unwrap();
+        IdentityAgent::listen(&run.join("agent.sock")).await.unwrap();
+
+        // A symlink to a private directory is not the directory itself
+        std::os::unix::fs::symlink(&run, dir.path().join("link")).unwrap();
+        assert!(IdentityAgent::listen(&dir.path().join("link").join("agent.sock")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_second_agent_does_not_steal_the_socket() {
+        let (_dir, client, _) = agent(None).await;
+        assert!(IdentityAgent::listen(client.socket()).await.is_err());
+        client.status().await.unwrap();
+    }
+}
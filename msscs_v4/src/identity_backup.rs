@@ -0,0 +1,368 @@
+// IDENTITY BACKUP - passphrase-protected, versioned export bundles for identities
+// A bundle carries the identity exactly as stored (its secrets stay encrypted under the
+// identity passphrase), signed by the identity and sealed again under a backup passphrase
+
+use crate::error::{MSSCSError, Result};
+use crate::identity::QuantumIdentity;
+use crate::unlocked_identity::UnlockedIdentity;
+use aes_gcm::{
+    aead::{Aead, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use data_encoding::BASE32_NOPAD;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Bundle format version written by this release
+pub const BUNDLE_VERSION: u16 = 1;
+
+/// First bytes of the binary encoding
+const BUNDLE_MAGIC: &[u8; 8] = b"MSSCSIDB";
+/// First line of the text encoding (followed by the version)
+const TEXT_HEADER: &str = "MSSCS-IDENTITY-V";
+/// Prefix of the checksum line closing the text encoding
+const TEXT_CHECKSUM_PREFIX: &str = "SUM:";
+/// Base32 characters per group of the text encoding
+const TEXT_GROUP_WIDTH: usize = 64;
+const TEXT_CHECKSUM_CONTEXT: &str = "MSSCS identity backup checksum v1";
+
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+
+/// Ceilings on the KDF parameters read from a bundle, so a crafted bundle cannot make
+/// import allocate gigabytes or spin for hours before the passphrase is even checked
+const MAX_KDF_MEMORY_KIB: u32 = 1024 * 1024;
+const MAX_KDF_ITERATIONS: u32 = 32;
+const MAX_KDF_PARALLELISM: u32 = 16;
+
+/// Argon2id parameters the backup key was derived with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleKdf {
+    pub salt: [u8; SALT_SIZE],
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl BundleKdf {
+    fn generate() -> Self {
+        let mut salt = [0u8; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        BundleKdf {
+            salt,
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    fn derive_key(&self, passphrase: &str) -> Result<[u8; 32]> {
+        if self.memory_kib > MAX_KDF_MEMORY_KIB
+            || self.iterations > MAX_KDF_ITERATIONS
+            || self.parallelism > MAX_KDF_PARALLELISM
+        {
+            return Err(MSSCSError::Validation(format!(
+                "Backup KDF parameters exceed the limits (m={} KiB, t={}, p={})",
+                self.memory_kib, self.iterations, self.parallelism
+            )));
+        }
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| MSSCSError::Crypto(format!("Invalid backup KDF parameters: {}", e)))?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &self.salt, &mut key)
+            .map_err(|e| MSSCSError::Crypto(format!("Backup key derivation failed: {}", e)))?;
+        Ok(key)
+    }
+}
+
+/// Identity backup sealed under a backup passphrase
+/// The header is readable without the passphrase and authenticated by the seal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityBundle {
+    pub version: u16,
+    pub identity_id: Uuid,
+    pub name: String,
+    pub exported_at: u64,
+    pub kdf: BundleKdf,
+    pub nonce: [u8; NONCE_SIZE],
+    ciphertext: Vec<u8>,
+}
+
+/// Sealed content: the stored identity and its Ed25519 signature over those exact bytes
+#[derive(Serialize, Deserialize)]
+struct BundlePayload {
+    identity_json: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl IdentityBundle {
+    /// Export `identity` sealed under `backup_passphrase`
+    pub fn export(identity: &UnlockedIdentity, backup_passphrase: &str) -> Result<Self> {
+        let identity_json = serde_json::to_vec(&identity.identity)
+            .map_err(|e| MSSCSError::InvalidData(format!("Failed to serialize identity: {}", e)))?;
+        let payload = BundlePayload {
+            signature: identity.sign(&identity_json)?,
+            identity_json,
+        };
+
+        let mut bundle = IdentityBundle {
+            version: BUNDLE_VERSION,
+            identity_id: *identity.user_id(),
+            name: identity.name().to_string(),
+            exported_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            kdf: BundleKdf::generate(),
+            nonce: [0u8; NONCE_SIZE],
+            ciphertext: Vec::new(),
+        };
+        OsRng.fill_bytes(&mut bundle.nonce);
+
+        let key = bundle.kdf.derive_key(backup_passphrase)?;
+        let plaintext = bincode::serialize(&payload)?;
+        bundle.ciphertext = Aes256Gcm::new(&key.into())
+            .encrypt(
+                Nonce::from_slice(&bundle.nonce),
+                Payload { msg: &plaintext, aad: &bundle.header()? },
+            )
+            .map_err(|e| MSSCSError::Crypto(format!("Bundle encryption failed: {}", e)))?;
+        Ok(bundle)
+    }
+
+    /// Decrypt the bundle and verify it before anything is imported:
+    /// the seal authenticates header and payload, the identity must match the header and
+    /// its own Ed25519 key must have signed it
+    pub fn open(&self, backup_passphrase: &str) -> Result<QuantumIdentity> {
+        if self.version != BUNDLE_VERSION {
+            return Err(MSSCSError::Validation(format!(
+                "Unsupported identity bundle version {}",
+                self.version
+            )));
+        }
+
+        let key = self.kdf.derive_key(backup_passphrase)?;
+        let plaintext = Aes256Gcm::new(&key.into())
+            .decrypt(
+                Nonce::from_slice(&self.nonce),
+                Payload { msg: &self.ciphertext, aad: &self.header()? },
+            )
+            .map_err(|_| {
+                MSSCSError::Crypto("Failed to open identity bundle - wrong passphrase or tampered bundle?".to_string())
+            })?;
+        let payload: BundlePayload = bincode::deserialize(&plaintext)?;
+
+        let identity: QuantumIdentity = serde_json::from_slice(&payload.identity_json)
+            .map_err(|e| MSSCSError::InvalidData(format!("Failed to parse bundled identity: {}", e)))?;
+        if identity.id != self.identity_id {
+            return Err(MSSCSError::Validation(format!(
+                "Bundle header names identity {} but contains {}",
+                self.identity_id, identity.id
+            )));
+        }
+        if !identity.verify(&payload.identity_json, &payload.signature)? {
+            return Err(MSSCSError::Validation(format!(
+                "Identity {} did not sign this bundle",
+                identity.id
+            )));
+        }
+        Ok(identity)
+    }
+
+    /// Binary encoding (magic, then the bundle)
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = BUNDLE_MAGIC.to_vec();
+        bytes.extend_from_slice(&bincode::serialize(self)?);
+        Ok(bytes)
+    }
+
+    /// Parse the binary encoding
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let body = data
+            .strip_prefix(BUNDLE_MAGIC.as_slice())
+            .ok_or_else(|| MSSCSError::InvalidData("Not an identity bundle".to_string()))?;
+        Ok(bincode::deserialize(body)?)
+    }
+
+    /// Printable encoding: header, base32 groups and a checksum, separated by spaces
+    /// Uses only uppercase letters, digits, spaces and `-:`, so it fits QR alphanumeric mode
+    pub fn to_text(&self) -> Result<String> {
+        let encoded = BASE32_NOPAD.encode(&bincode::serialize(self)?);
+        let mut groups = vec![format!("{}{}", TEXT_HEADER, self.version)];
+        groups.extend(
+            encoded
+                .as_bytes()
+                .chunks(TEXT_GROUP_WIDTH)
+                .map(|group| std::str::from_utf8(group).expect("base32 is ASCII").to_string()),
+        );
+        groups.push(format!("{}{}", TEXT_CHECKSUM_PREFIX, text_checksum(&encoded)));
+        Ok(groups.join(" "))
+    }
+
+    /// Parse the printable encoding
+    /// Any whitespace separates groups and case is ignored; the checksum catches
+    /// transcription errors
+    pub fn from_text(text: &str) -> Result<Self> {
+        let mut groups = text.split_whitespace().map(str::to_ascii_uppercase);
+
+        let header = groups.next().unwrap_or_default();
+        let version: u16 = header
+            .strip_prefix(TEXT_HEADER)
+            .and_then(|version| version.parse().ok())
+            .ok_or_else(|| MSSCSError::InvalidData("Missing identity bundle header".to_string()))?;
+        if version != BUNDLE_VERSION {
+            return Err(MSSCSError::Validation(format!(
+                "Unsupported identity bundle version {}",
+                version
+            )));
+        }
+
+        let mut encoded = String::new();
+        let mut checksum: Option<String> = None;
+        for group in groups {
+            match (&mut checksum, group.strip_prefix(TEXT_CHECKSUM_PREFIX)) {
+                (Some(sum), _) => sum.push_str(&group),
+                (None, Some(sum)) => checksum = Some(sum.to_string()),
+                (None, None) => encoded.push_str(&group),
+            }
+        }
+        let checksum = checksum
+            .ok_or_else(|| MSSCSError::InvalidData("Identity bundle text is truncated".to_string()))?;
+        if checksum != text_checksum(&encoded) {
+            return Err(MSSCSError::Validation(
+                "Identity bundle checksum mismatch - check the transcription".to_string(),
+            ));
+        }
+
+        let data = BASE32_NOPAD
+            .decode(encoded.as_bytes())
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid identity bundle text: {}", e)))?;
+        Ok(bincode::deserialize(&data)?)
+    }
+
+    /// Parse either encoding (file contents)
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.starts_with(BUNDLE_MAGIC) {
+            return Self::from_bytes(data);
+        }
+        let text = std::str::from_utf8(data)
+            .map_err(|_| MSSCSError::InvalidData("Not an identity bundle".to_string()))?;
+        Self::from_text(text)
+    }
+
+    /// Header fields bound to the ciphertext
+    fn header(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(&(
+            self.version,
+            self.identity_id,
+            &self.name,
+            self.exported_at,
+            &self.kdf,
+            self.nonce,
+        ))?)
+    }
+}
+
+/// 8 base32 characters of a keyed BLAKE3 hash over the encoded bundle
+fn text_checksum(encoded: &str) -> String {
+    let digest = blake3::derive_key(TEXT_CHECKSUM_CONTEXT, encoded.as_bytes());
+    BASE32_NOPAD.encode(&digest[..5])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unlocked() -> UnlockedIdentity {
+        QuantumIdentity::new("backup-test".to_string(), "identity passphrase")
+            .unwrap()
+            .unlock("identity passphrase")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let identity = unlocked();
+        let bundle = IdentityBundle::export(&identity, "backup passphrase").unwrap();
+        assert_eq!(bundle.identity_id, *identity.user_id());
+
+        for decoded in [
+            IdentityBundle::decode(&bundle.to_bytes().unwrap()).unwrap(),
+            IdentityBundle::decode(bundle.to_text().unwrap().as_bytes()).unwrap(),
+        ] {
+            assert_eq!(decoded, bundle);
+            let restored = decoded.open("backup passphrase").unwrap();
+            assert_eq!(restored.public_key, identity.identity.public_key);
+            // Secrets are still protected by the identity passphrase
+            let unlocked = restored.unlock("identity passphrase").unwrap();
+            assert_eq!(unlocked.master_key(), identity.master_key());
+        }
+
+        assert!(matches!(bundle.open("wrong passphrase"), Err(MSSCSError::Crypto(_))));
+    }
+
+    #[test]
+    fn test_text_encoding_is_printable_and_checked() {
+        let bundle = IdentityBundle::export(&unlocked(), "backup passphrase").unwrap();
+        let text = bundle.to_text().unwrap();
+        assert!(text
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || "-: ".contains(c)));
+        assert!(text.split(' ').all(|group| group.len() <= TEXT_GROUP_WIDTH));
+
+        // Reformatting survives, a single changed character does not
+        let reflowed = format!("{}\n", text.to_lowercase().replace(' ', "\n  "));
+        assert_eq!(IdentityBundle::from_text(&reflowed).unwrap(), bundle);
+        let mut groups: Vec<String> = text.split(' ').map(str::to_string).collect();
+        let typo = if groups[1].starts_with('A') { "B" } else { "A" };
+        groups[1].replace_range(..1, typo);
+        assert!(matches!(IdentityBundle::from_text(&groups.join(" ")), Err(MSSCSError::Validation(_))));
+    }
+
+    #[test]
+    fn test_oversized_kdf_parameters_are_rejected() {
+        let mut bundle = IdentityBundle::export(&unlocked(), "backup passphrase").unwrap();
+        bundle.kdf.memory_kib = u32::MAX;
+        assert!(matches!(bundle.open("backup passphrase"), Err(MSSCSError::Validation(_))));
+    }
+
+    #[test]
+    fn test_tampered_header_is_rejected() {
+        let mut bundle = IdentityBundle::export(&unlocked(), "backup passphrase").unwrap();
+        bundle.identity_id = Uuid::new_v4();
+        assert!(bundle.open("backup passphrase").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_move_identity_between_managers() {
+        use crate::identity::IdentityManager;
+
+        let source_dir = tempfile::TempDir::new().unwrap();
+        let mut source = IdentityManager::new(source_dir.path().to_path_buf()).unwrap();
+        let id = source.create_identity("laptop".to_string(), "identity passphrase").await.unwrap();
+        let text = source
+            .export_identity(&id, "identity passphrase", "backup passphrase")
+            .await
+            .unwrap()
+            .to_text()
+            .unwrap();
+
+        // The second machine already has an identity of its own
+        let target_dir = tempfile::TempDir::new().unwrap();
+        let mut target = IdentityManager::new(target_dir.path().to_path_buf()).unwrap();
+        target.create_identity("desktop".to_string(), "other passphrase").await.unwrap();
+        let bundle = IdentityBundle::from_text(&text).unwrap();
+        assert_eq!(target.import_identity(&bundle, "backup passphrase").await.unwrap(), id);
+        assert_eq!(target.import_identity(&bundle, "backup passphrase").await.unwrap(), id);
+
+        // The import stays current after a restart
+        let mut restarted = IdentityManager::new(target_dir.path().to_path_buf()).unwrap();
+        assert_eq!(restarted.load_all().await.unwrap(), 2);
+        let unlocked = restarted.unlock_or_create("unused", "identity passphrase").await.unwrap();
+        assert_eq!(*unlocked.user_id(), id);
+    }
+}
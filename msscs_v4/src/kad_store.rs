@@ -0,0 +1,561 @@
+// PERSISTENT KADEMLIA RECORD STORE
+// Same semantics as libp2p's MemoryStore, written through to a directory so the records
+// and provider entries a node holds for the network survive a restart
+
+use crate::error::{MSSCSError, Result};
+use libp2p::kad::store::{self, RecordStore};
+use libp2p::kad::{KBucketKey, ProviderRecord, Record, RecordKey};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const RECORDS_DIR: &str = "records";
+const PROVIDERS_DIR: &str = "providers";
+
+/// Record store location, limits and TTL
+#[derive(Debug, Clone)]
+pub struct RecordStoreConfig {
+    /// Directory holding records and provider entries (None = memory only)
+    pub dir: Option<PathBuf>,
+    /// Maximum number of value records
+    pub max_records: usize,
+    /// Maximum size of a record value in bytes
+    pub max_value_bytes: usize,
+    /// Providers kept per key (should match the replication factor)
+    pub max_providers_per_key: usize,
+    /// Maximum number of keys with provider entries
+    pub max_provided_keys: usize,
+    /// Longest lifetime accepted for a record or provider entry (None = as published)
+    pub max_ttl: Option<Duration>,
+}
+
+impl Default for RecordStoreConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_records: 1024,
+            max_value_bytes: 65 * 1024,
+            max_providers_per_key: libp2p::kad::K_VALUE.get(),
+            max_provided_keys: 1024,
+            max_ttl: Some(Duration::from_secs(48 * 60 * 60)),
+        }
+    }
+}
+
+/// On-disk form of a record (monotonic expiry converted to wall-clock milliseconds)
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    publisher: Option<Vec<u8>>,
+    expires_at: Option<u64>,
+}
+
+/// On-disk form of a provider entry
+#[derive(Serialize, Deserialize)]
+struct StoredProvider {
+    provider: Vec<u8>,
+    expires_at: Option<u64>,
+    addresses: Vec<Vec<u8>>,
+}
+
+/// Kademlia record store kept in memory and mirrored to disk
+pub struct PersistentRecordStore {
+    local_key: KBucketKey<PeerId>,
+    config: RecordStoreConfig,
+    records: HashMap<RecordKey, Record>,
+    /// Providers per key, closest to the key first
+    providers: HashMap<RecordKey, Vec<ProviderRecord>>,
+    /// Provider entries naming this node (kept in sync with `providers`)
+    provided: HashSet<ProviderRecord>,
+}
+
+impl PersistentRecordStore {
+    /// Open the store, loading unexpired entries from `config.dir` when set
+    pub fn open(local_id: PeerId, config: RecordStoreConfig) -> Result<Self> {
+        let mut store = Self {
+            local_key: KBucketKey::from(local_id),
+            config,
+            records: HashMap::new(),
+            providers: HashMap::new(),
+            provided: HashSet::new(),
+        };
+
+        if let Some(dir) = store.config.dir.clone() {
+            std::fs::create_dir_all(dir.join(RECORDS_DIR))?;
+            std::fs::create_dir_all(dir.join(PROVIDERS_DIR))?;
+            store.load(&dir)?;
+            tracing::info!("📇 Record store: {} records, {} provider keys loaded from {}",
+                store.records.len(), store.providers.len(), dir.display());
+        }
+        Ok(store)
+    }
+
+    /// Drop expired records and provider entries; returns how many were removed
+    pub fn remove_expired(&mut self) -> usize {
+        let now = Instant::now();
+        let expired: Vec<RecordKey> = self.records.values()
+            .filter(|record| record.is_expired(now))
+            .map(|record| record.key.clone())
+            .collect();
+        let mut removed = expired.len();
+        for key in expired {
+            self.remove(&key);
+        }
+
+        let keys: Vec<RecordKey> = self.providers.keys().cloned().collect();
+        for key in keys {
+            let Some(providers) = self.providers.get_mut(&key) else {
+                continue;
+            };
+            let before = providers.len();
+            providers.retain(|p| !p.is_expired(now));
+            if providers.len() != before {
+                removed += before - providers.len();
+                self.provided.retain(|p| p.key != key || !p.is_expired(now));
+                self.persist_providers(&key);
+            }
+        }
+        removed
+    }
+
+    /// Number of value records held
+    pub fn record_count(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Number of keys with provider entries
+    pub fn provider_key_count(&self) -> usize {
+        self.providers.len()
+    }
+
+    fn load(&mut self, dir: &Path) -> Result<()> {
+        let now = Instant::now();
+
+        for path in list_files(&dir.join(RECORDS_DIR))? {
+            let record = std::fs::read(&path)
+                .map_err(MSSCSError::from)
+                .and_then(|data| Ok(bincode::deserialize::<StoredRecord>(&data)?))
+                .and_then(|stored| stored.into_record());
+            match record {
+                Ok(record) if !record.is_expired(now) => {
+                    self.records.insert(record.key.clone(), record);
+                }
+                Ok(_) => {
+                    let _ = std::fs::remove_file(&path);
+                }
+                Err(e) => {
+                    tracing::warn!("⚠️  Dropping unreadable record file {}: {}", path.display(), e);
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+
+        for path in list_files(&dir.join(PROVIDERS_DIR))? {
+            let loaded = file_key(&path).and_then(|key| {
+                let data = std::fs::read(&path)?;
+                let stored: Vec<StoredProvider> = bincode::deserialize(&data)?;
+                stored.into_iter()
+                    .map(|p| p.into_provider(key.clone()))
+                    .collect::<Result<Vec<_>>>()
+                    .map(|providers| (key, providers))
+            });
+            match loaded {
+                Ok((key, mut providers)) => {
+                    providers.retain(|p| !p.is_expired(now));
+                    if providers.is_empty() {
+                        let _ = std::fs::remove_file(&path);
+                        continue;
+                    }
+                    for p in &providers {
+                        if &p.provider == self.local_key.preimage() {
+                            self.provided.insert(p.clone());
+                        }
+                    }
+                    self.providers.insert(key, providers);
+                }
+                Err(e) => {
+                    tracing::warn!("⚠️  Dropping unreadable provider file {}: {}", path.display(), e);
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Clamp an expiry to the configured maximum TTL
+    fn clamp_expiry(&self, expires: Option<Instant>) -> Option<Instant> {
+        match self.config.max_ttl {
+            Some(ttl) => {
+                let limit = Instant::now() + ttl;
+                Some(expires.map_or(limit, |at| at.min(limit)))
+            }
+            None => expires,
+        }
+    }
+
+    fn entry_path(&self, kind: &str, key: &RecordKey) -> Option<PathBuf> {
+        self.config.dir.as_ref().map(|dir| dir.join(kind).join(hex::encode(key.as_ref())))
+    }
+
+    fn persist_record(&self, record: &Record) {
+        if let Some(path) = self.entry_path(RECORDS_DIR, &record.key) {
+            let result = bincode::serialize(&StoredRecord::from_record(record))
+                .map_err(MSSCSError::from)
+                .and_then(|data| write_atomic(&path, &data));
+            if let Err(e) = result {
+                tracing::warn!("⚠️  Failed to persist DHT record: {}", e);
+            }
+        }
+    }
+
+    fn persist_providers(&mut self, key: &RecordKey) {
+        let Some(path) = self.entry_path(PROVIDERS_DIR, key) else {
+            return;
+        };
+        let result = match self.providers.get(key) {
+            Some(providers) if !providers.is_empty() => {
+                let stored: Vec<StoredProvider> = providers.iter().map(StoredProvider::from_provider).collect();
+                bincode::serialize(&stored)
+                    .map_err(MSSCSError::from)
+                    .and_then(|data| write_atomic(&path, &data))
+            }
+            _ => {
+                self.providers.remove(key);
+                remove_if_exists(&path)
+            }
+        };
+        if let Err(e) = result {
+            tracing::warn!("⚠️  Failed to persist DHT provider entries: {}", e);
+        }
+    }
+}
+
+impl RecordStore for PersistentRecordStore {
+    type RecordsIter<'a> = Box<dyn Iterator<Item = Cow<'a, Record>> + 'a>;
+    type ProvidedIter<'a> = Box<dyn Iterator<Item = Cow<'a, ProviderRecord>> + 'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.records.get(k)
+            .filter(|record| !record.is_expired(Instant::now()))
+            .map(Cow::Borrowed)
+    }
+
+    fn put(&mut self, mut r: Record) -> store::Result<()> {
+        if r.value.len() >= self.config.max_value_bytes {
+            return Err(store::Error::ValueTooLarge);
+        }
+        if !self.records.contains_key(&r.key) && self.records.len() >= self.config.max_records {
+            return Err(store::Error::MaxRecords);
+        }
+
+        r.expires = self.clamp_expiry(r.expires);
+        self.persist_record(&r);
+        self.records.insert(r.key.clone(), r);
+        Ok(())
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        if self.records.remove(k).is_some() {
+            if let Some(path) = self.entry_path(RECORDS_DIR, k) {
+                if let Err(e) = remove_if_exists(&path) {
+                    tracing::warn!("⚠️  Failed to delete DHT record: {}", e);
+                }
+            }
+        }
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        let now = Instant::now();
+        Box::new(self.records.values().filter(move |r| !r.is_expired(now)).map(Cow::Borrowed))
+    }
+
+    fn add_provider(&mut self, mut record: ProviderRecord) -> store::Result<()> {
+        if !self.providers.contains_key(&record.key) && self.providers.len() >= self.config.max_provided_keys {
+            return Err(store::Error::MaxProvidedKeys);
+        }
+        record.expires = self.clamp_expiry(record.expires);
+
+        let key = record.key.clone();
+        let is_local = &record.provider == self.local_key.preimage();
+        let max_providers = self.config.max_providers_per_key;
+        let providers = self.providers.entry(key.clone()).or_default();
+
+        if let Some(i) = providers.iter().position(|p| p.provider == record.provider) {
+            // Refresh an existing entry in place; `provided` hashes every field, so the old
+            // entry has to be removed before the new one goes in
+            if is_local {
+                self.provided.remove(&providers[i]);
+                self.provided.insert(record.clone());
+            }
+            providers[i] = record;
+        } else {
+            // Keep the providers closest to the key, up to the limit
+            let target = KBucketKey::new(key.clone());
+            let distance = KBucketKey::from(record.provider).distance(&target);
+            let position = providers.iter()
+                .position(|p| distance < KBucketKey::from(p.provider).distance(&target))
+                .unwrap_or(providers.len());
+            if position >= max_providers {
+                return Ok(());
+            }
+            if is_local {
+                self.provided.insert(record.clone());
+            }
+            providers.insert(position, record);
+            if providers.len() > max_providers {
+                if let Some(evicted) = providers.pop() {
+                    self.provided.remove(&evicted);
+                }
+            }
+        }
+
+        self.persist_providers(&key);
+        Ok(())
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        let now = Instant::now();
+        self.providers.get(key)
+            .map(|providers| providers.iter().filter(|p| !p.is_expired(now)).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        Box::new(self.provided.iter().map(Cow::Borrowed))
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        let Some(providers) = self.providers.get_mut(k) else {
+            return;
+        };
+        if let Some(i) = providers.iter().position(|record| &record.provider == p) {
+            let removed = providers.remove(i);
+            self.provided.remove(&removed);
+            self.persist_providers(k);
+        }
+    }
+}
+
+impl StoredRecord {
+    fn from_record(record: &Record) -> Self {
+        Self {
+            key: record.key.to_vec(),
+            value: record.value.clone(),
+            publisher: record.publisher.map(|peer| peer.to_bytes()),
+            expires_at: record.expires.map(instant_to_unix_ms),
+        }
+    }
+
+    fn into_record(self) -> Result<Record> {
+        let publisher = self.publisher
+            .map(|bytes| PeerId::from_bytes(&bytes))
+            .transpose()
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid publisher: {}", e)))?;
+        Ok(Record {
+            key: RecordKey::from(self.key),
+            value: self.value,
+            publisher,
+            expires: self.expires_at.map(unix_ms_to_instant),
+        })
+    }
+}
+
+impl StoredProvider {
+    fn from_provider(record: &ProviderRecord) -> Self {
+        Self {
+            provider: record.provider.to_bytes(),
+            expires_at: record.expires.map(instant_to_unix_ms),
+            addresses: record.addresses.iter().map(|addr| addr.to_vec()).collect(),
+        }
+    }
+
+    fn into_provider(self, key: RecordKey) -> Result<ProviderRecord> {
+        let provider = PeerId::from_bytes(&self.provider)
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid provider: {}", e)))?;
+        Ok(ProviderRecord {
+            key,
+            provider,
+            expires: self.expires_at.map(unix_ms_to_instant),
+            addresses: self.addresses.into_iter().filter_map(|bytes| Multiaddr::try_from(bytes).ok()).collect(),
+        })
+    }
+}
+
+fn now_unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+fn instant_to_unix_ms(at: Instant) -> u64 {
+    let now = Instant::now();
+    let unix = now_unix_ms();
+    if at >= now {
+        unix.saturating_add((at - now).as_millis() as u64)
+    } else {
+        unix.saturating_sub((now - at).as_millis() as u64)
+    }
+}
+
+fn unix_ms_to_instant(at: u64) -> Instant {
+    let now = Instant::now();
+    let unix = now_unix_ms();
+    if at >= unix {
+        now + Duration::from_millis(at - unix)
+    } else {
+        // Already expired; anything in the past will do
+        now.checked_sub(Duration::from_millis(unix - at)).unwrap_or(now - Duration::from_millis(1))
+    }
+}
+
+fn file_key(path: &Path) -> Result<RecordKey> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let bytes = hex::decode(name)
+        .map_err(|e| MSSCSError::InvalidData(format!("Invalid key file name '{}': {}", name, e)))?;
+    Ok(RecordKey::from(bytes))
+}
+
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_none() {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn disk_config(dir: &TempDir) -> RecordStoreConfig {
+        RecordStoreConfig {
+            dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        }
+    }
+
+    fn provider(key: &RecordKey, peer: PeerId) -> ProviderRecord {
+        ProviderRecord {
+            key: key.clone(),
+            provider: peer,
+            expires: None,
+            addresses: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+        }
+    }
+
+    #[test]
+    fn test_records_and_providers_survive_reopen() {
+        let dir = TempDir::new().unwrap();
+        let local = PeerId::random();
+        let remote = PeerId::random();
+        let key = RecordKey::new(&b"block-1");
+
+        {
+            let mut store = PersistentRecordStore::open(local, disk_config(&dir)).unwrap();
+            store.put(Record::new(key.clone(), b"value".to_vec())).unwrap();
+            store.add_provider(provider(&key, local)).unwrap();
+            store.add_provider(provider(&key, remote)).unwrap();
+
+            // Republishing refreshes the local entry instead of adding a second one
+            let mut refreshed = provider(&key, local);
+            refreshed.addresses = vec!["/ip4/127.0.0.1/tcp/4002".parse().unwrap()];
+            store.add_provider(refreshed.clone()).unwrap();
+            assert_eq!(store.provided().map(|p| p.into_owned()).collect::<Vec<_>>(), vec![refreshed]);
+        }
+
+        let mut store = PersistentRecordStore::open(local, disk_config(&dir)).unwrap();
+        assert_eq!(store.get(&key).unwrap().value, b"value");
+        let providers = store.providers(&key);
+        assert_eq!(providers.len(), 2);
+        assert_eq!(providers[0].addresses.len(), 1);
+        assert_eq!(store.provided().count(), 1);
+
+        store.remove(&key);
+        store.remove_provider(&key, &remote);
+        let store = PersistentRecordStore::open(local, disk_config(&dir)).unwrap();
+        assert!(store.get(&key).is_none());
+        assert_eq!(store.providers(&key).len(), 1);
+    }
+
+    #[test]
+    fn test_limits() {
+        let dir = TempDir::new().unwrap();
+        let config = RecordStoreConfig {
+            max_records: 2,
+            max_value_bytes: 16,
+            max_providers_per_key: 2,
+            ..disk_config(&dir)
+        };
+        let mut store = PersistentRecordStore::open(PeerId::random(), config).unwrap();
+
+        assert!(matches!(store.put(Record::new(RecordKey::new(&b"big"), vec![0; 16])), Err(store::Error::ValueTooLarge)));
+        store.put(Record::new(RecordKey::new(&b"a"), vec![1])).unwrap();
+        store.put(Record::new(RecordKey::new(&b"b"), vec![2])).unwrap();
+        assert!(matches!(store.put(Record::new(RecordKey::new(&b"c"), vec![3])), Err(store::Error::MaxRecords)));
+        // Overwriting an existing key is still allowed
+        store.put(Record::new(RecordKey::new(&b"a"), vec![4])).unwrap();
+
+        let key = RecordKey::new(&b"shared");
+        for _ in 0..5 {
+            store.add_provider(provider(&key, PeerId::random())).unwrap();
+        }
+        assert_eq!(store.providers(&key).len(), 2);
+    }
+
+    #[test]
+    fn test_ttl_enforcement() {
+        let dir = TempDir::new().unwrap();
+        let local = PeerId::random();
+        let config = RecordStoreConfig {
+            max_ttl: Some(Duration::from_secs(60)),
+            ..disk_config(&dir)
+        };
+        let mut store = PersistentRecordStore::open(local, config.clone()).unwrap();
+
+        // Records without an expiry get the maximum TTL
+        let key = RecordKey::new(&b"forever");
+        store.put(Record::new(key.clone(), vec![1])).unwrap();
+        let expires = store.get(&key).unwrap().expires.unwrap();
+        assert!(expires <= Instant::now() + Duration::from_secs(60));
+
+        // Expired entries are hidden, swept and not reloaded
+        let stale = RecordKey::new(&b"stale");
+        let mut record = Record::new(stale.clone(), vec![2]);
+        record.expires = Some(Instant::now() - Duration::from_secs(1));
+        store.put(record).unwrap();
+        let mut old_provider = provider(&stale, PeerId::random());
+        old_provider.expires = Some(Instant::now() - Duration::from_secs(1));
+        store.add_provider(old_provider).unwrap();
+        assert!(store.get(&stale).is_none());
+        assert!(store.providers(&stale).is_empty());
+        assert_eq!(store.records().count(), 1);
+
+        let reopened = PersistentRecordStore::open(local, config).unwrap();
+        assert_eq!(reopened.record_count(), 1);
+        assert_eq!(reopened.provider_key_count(), 0);
+
+        assert_eq!(store.remove_expired(), 2);
+        assert_eq!(store.record_count(), 1);
+        assert_eq!(store.provider_key_count(), 0);
+    }
+}
@@ -0,0 +1,324 @@
+// KEY ROTATION - signed statements linking an identity's retired keys to their successors
+// An identity keeps its id across rotations. Each statement is signed by the retired Ed25519
+// and Dilithium keys (authorising the change) and by the new Ed25519 key (proving possession),
+// so anyone who knows one generation of keys can follow the chain to the current one.
+// Each statement is published in the DHT under a key naming the generation it retires; nodes
+// only store records whose signatures check out, so nobody else can claim that key
+
+use crate::error::{MSSCSError, Result};
+use crate::identity::QuantumIdentity;
+use crate::p2p_network::P2PNodeCommand;
+use crate::unlocked_identity::UnlockedIdentity;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+/// DHT record key prefix of rotation statements (followed by `<identity id>/<retired digest>`)
+pub const ROTATION_RECORD_PREFIX: &str = "/msscs/identity-rotations/";
+
+const KEYS_DIGEST_CONTEXT: &str = "MSSCS identity keys digest v1";
+
+/// Public keys of one key generation of an identity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityKeys {
+    /// Key generation, starting at 1 and incremented by every rotation
+    pub version: u32,
+    pub public_key: Vec<u8>,
+    pub pq_public_key: Vec<u8>,
+    pub dilithium_public_key: Vec<u8>,
+}
+
+impl IdentityKeys {
+    pub fn of(identity: &QuantumIdentity) -> Self {
+        IdentityKeys {
+            version: identity.version,
+            public_key: identity.public_key.clone(),
+            pq_public_key: identity.pq_public_key.clone(),
+            dilithium_public_key: identity.dilithium_public_key.clone(),
+        }
+    }
+
+    /// Digest naming this generation in the DHT key of the statement that retires it
+    pub fn digest(&self) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new_derive_key(KEYS_DIGEST_CONTEXT);
+        hasher.update(&self.version.to_le_bytes());
+        for key in [&self.public_key, &self.pq_public_key, &self.dilithium_public_key] {
+            hasher.update(&(key.len() as u64).to_le_bytes());
+            hasher.update(key);
+        }
+        *hasher.finalize().as_bytes()
+    }
+}
+
+/// Statement that an identity replaced one key generation with the next
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationStatement {
+    pub identity_id: Uuid,
+    pub retired: IdentityKeys,
+    pub successor: IdentityKeys,
+    pub rotated_at: u64,
+    retired_signature: Vec<u8>,
+    retired_dilithium_signature: Vec<u8>,
+    successor_signature: Vec<u8>,
+}
+
+impl RotationStatement {
+    /// Sign the move from `retired`'s keys to `successor`'s
+    pub(crate) fn sign(retired: &UnlockedIdentity, successor: &UnlockedIdentity) -> Result<Self> {
+        let mut statement = RotationStatement {
+            identity_id: *retired.user_id(),
+            retired: IdentityKeys::of(&retired.identity),
+            successor: IdentityKeys::of(&successor.identity),
+            rotated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            retired_signature: Vec::new(),
+            retired_dilithium_signature: Vec::new(),
+            successor_signature: Vec::new(),
+        };
+        let signed = statement.signed_bytes()?;
+        statement.retired_signature = retired.sign(&signed)?;
+        statement.retired_dilithium_signature = retired.sign_dilithium(&signed)?;
+        statement.successor_signature = successor.sign(&signed)?;
+        Ok(statement)
+    }
+
+    /// Check that `retired` (keys of `identity_id`) authorised this statement and that the
+    /// successor keys signed it too
+    pub fn verify(&self, identity_id: &Uuid, retired: &IdentityKeys) -> Result<()> {
+        if self.identity_id != *identity_id {
+            return Err(MSSCSError::Validation(format!(
+                "Rotation statement is for identity {}, not {}",
+                self.identity_id, identity_id
+            )));
+        }
+        if self.retired != *retired {
+            return Err(MSSCSError::Validation(format!(
+                "Rotation statement does not follow key generation {} of {}",
+                retired.version, identity_id
+            )));
+        }
+        self.verify_signatures()
+    }
+
+    /// DHT record key this statement is published under
+    pub fn record_key(identity_id: &Uuid, retired: &IdentityKeys) -> Vec<u8> {
+        format!("{}{}/{}", ROTATION_RECORD_PREFIX, identity_id, hex::encode(retired.digest())).into_bytes()
+    }
+
+    /// Decode a DHT record and check it is a correctly signed statement for its key
+    pub fn from_record(key: &[u8], value: &[u8]) -> Result<Self> {
+        let statement: RotationStatement = bincode::deserialize(value)
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid rotation statement: {}", e)))?;
+        if Self::record_key(&statement.identity_id, &statement.retired) != key {
+            return Err(MSSCSError::Validation("Rotation statement stored under another key".to_string()));
+        }
+        statement.verify_signatures()?;
+        Ok(statement)
+    }
+
+    fn verify_signatures(&self) -> Result<()> {
+        if self.successor.version != self.retired.version + 1 {
+            return Err(MSSCSError::Validation(format!(
+                "Rotation of {} skips from key generation {} to {}",
+                self.identity_id, self.retired.version, self.successor.version
+            )));
+        }
+        let signed = self.signed_bytes()?;
+        let valid = verify_ed25519(&self.retired.public_key, &signed, &self.retired_signature)
+            && verify_dilithium(&self.retired.dilithium_public_key, &signed, &self.retired_dilithium_signature)
+            && verify_ed25519(&self.successor.public_key, &signed, &self.successor_signature);
+        if !valid {
+            return Err(MSSCSError::Validation(format!(
+                "Invalid signatures on the rotation of {} to key generation {}",
+                self.identity_id, self.successor.version
+            )));
+        }
+        Ok(())
+    }
+
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(&(self.identity_id, &self.retired, &self.successor, self.rotated_at))?)
+    }
+}
+
+/// An identity's rotation statements, oldest first
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationChain {
+    pub identity_id: Uuid,
+    pub statements: Vec<RotationStatement>,
+}
+
+impl RotationChain {
+    pub fn of(identity: &QuantumIdentity) -> Self {
+        RotationChain { identity_id: identity.id, statements: identity.rotations.clone() }
+    }
+
+    /// Follow the chain from a known key generation to the latest one
+    /// Statements for earlier generations are skipped; every later one must verify
+    pub fn follow(&self, known: &IdentityKeys) -> Result<IdentityKeys> {
+        let mut current = known.clone();
+        for statement in &self.statements {
+            if statement.successor.version <= known.version {
+                continue;
+            }
+            statement.verify(&self.identity_id, &current)?;
+            current = statement.successor.clone();
+        }
+        Ok(current)
+    }
+}
+
+/// Publish `identity`'s rotation statements so peers that know an older generation can follow
+/// them to the current one
+pub async fn publish_rotations(
+    commands: &mpsc::UnboundedSender<P2PNodeCommand>,
+    identity: &QuantumIdentity,
+) -> Result<()> {
+    for statement in &identity.rotations {
+        let (reply, response) = oneshot::channel();
+        commands
+            .send(P2PNodeCommand::PutRecord {
+                key: RotationStatement::record_key(&identity.id, &statement.retired),
+                value: bincode::serialize(statement)?,
+                reply,
+            })
+            .map_err(|e| MSSCSError::Network(format!("P2P command channel error: {}", e)))?;
+        response.await
+            .map_err(|_| MSSCSError::Network("P2P node dropped the request".to_string()))?
+            .map_err(MSSCSError::Network)?;
+    }
+    Ok(())
+}
+
+/// Collect the published statements leading from the known generation of `identity_id` to
+/// the latest one; the chain is empty if no rotation of `known` was published
+pub async fn lookup_rotations(
+    commands: &mpsc::UnboundedSender<P2PNodeCommand>,
+    identity_id: Uuid,
+    known: &IdentityKeys,
+) -> Result<RotationChain> {
+    let mut chain = RotationChain { identity_id, statements: Vec::new() };
+    let mut current = known.clone();
+    loop {
+        let key = RotationStatement::record_key(&identity_id, &current);
+        let (reply, response) = oneshot::channel();
+        commands
+            .send(P2PNodeCommand::GetRecord { key: key.clone(), reply })
+            .map_err(|e| MSSCSError::Network(format!("P2P command channel error: {}", e)))?;
+        let values = response.await
+            .map_err(|_| MSSCSError::Network("P2P node dropped the request".to_string()))?
+            .unwrap_or_default();
+
+        let Some(statement) = values
+            .iter()
+            .filter_map(|value| RotationStatement::from_record(&key, value).ok())
+            .find(|statement| statement.verify(&identity_id, &current).is_ok())
+        else {
+            return Ok(chain);
+        };
+        current = statement.successor.clone();
+        chain.statements.push(statement);
+    }
+}
+
+fn verify_ed25519(public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let (Ok(public_key), Ok(signature)) = (<[u8; 32]>::try_from(public_key), <[u8; 64]>::try_from(signature)) else {
+        return false;
+    };
+    VerifyingKey::from_bytes(&public_key)
+        .is_ok_and(|key| key.verify(data, &Signature::from_bytes(&signature)).is_ok())
+}
+
+fn verify_dilithium(public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    use pqcrypto_dilithium::dilithium5;
+    use pqcrypto_traits::sign::{DetachedSignature, PublicKey};
+
+    let (Ok(public_key), Ok(signature)) = (
+        dilithium5::PublicKey::from_bytes(public_key),
+        dilithium5::DetachedSignature::from_bytes(signature),
+    ) else {
+        return false;
+    };
+    dilithium5::verify_detached_signature(&signature, data, &public_key).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unlocked() -> UnlockedIdentity {
+        QuantumIdentity::new("rotating".to_string(), "passphrase")
+            .unwrap()
+            .unlock("passphrase")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_rotation_chain_is_followed() {
+        let first = unlocked();
+        let second = first.rotate("passphrase").unwrap();
+        let third = second.rotate("passphrase").unwrap();
+        assert_eq!(third.user_id(), first.user_id());
+        assert_eq!(third.identity.version, 3);
+        assert_ne!(third.identity.public_key, first.identity.public_key);
+
+        let chain = RotationChain::of(&third.identity);
+        assert_eq!(chain.statements.len(), 2);
+        for statement in &chain.statements {
+            let key = RotationStatement::record_key(third.user_id(), &statement.retired);
+            let value = bincode::serialize(statement).unwrap();
+            assert_eq!(&RotationStatement::from_record(&key, &value).unwrap(), statement);
+        }
+        let current = IdentityKeys::of(&third.identity);
+        assert_eq!(chain.follow(&IdentityKeys::of(&first.identity)).unwrap(), current);
+        assert_eq!(chain.follow(&IdentityKeys::of(&second.identity)).unwrap(), current);
+        assert_eq!(chain.follow(&current).unwrap(), current);
+
+        // The stored successor unlocks with the passphrase it was rotated under
+        let reopened = third.identity.unlock("passphrase").unwrap();
+        assert_eq!(reopened.master_key(), third.master_key());
+        assert_ne!(reopened.master_key(), first.master_key());
+    }
+
+    #[test]
+    fn test_forged_rotation_is_rejected() {
+        let owner = unlocked();
+        let mallory = unlocked();
+        let known = IdentityKeys::of(&owner.identity);
+
+        // A statement signed by other keys cannot retire the owner's, nor be stored under
+        // the DHT key of the owner's generation
+        let mut forged = mallory.rotate("passphrase").unwrap().identity.rotations.remove(0);
+        forged.identity_id = *owner.user_id();
+        let key = RotationStatement::record_key(owner.user_id(), &known);
+        assert!(RotationStatement::from_record(&key, &bincode::serialize(&forged).unwrap()).is_err());
+        forged.retired = known.clone();
+        assert!(RotationStatement::from_record(&key, &bincode::serialize(&forged).unwrap()).is_err());
+        let chain = RotationChain { identity_id: *owner.user_id(), statements: vec![forged] };
+        assert!(chain.follow(&known).is_err());
+
+        // Nor can a genuine statement be pointed at different successor keys
+        let mut swapped = owner.rotate("passphrase").unwrap().identity.rotations.remove(0);
+        swapped.successor = IdentityKeys { version: 2, ..IdentityKeys::of(&mallory.identity) };
+        let chain = RotationChain { identity_id: *owner.user_id(), statements: vec![swapped] };
+        assert!(chain.follow(&known).is_err());
+    }
+
+    #[test]
+    fn test_change_passphrase_keeps_keys() {
+        let mut identity = QuantumIdentity::new("renamed".to_string(), "old passphrase").unwrap();
+        let master_key = identity.unlock("old passphrase").unwrap().master_key();
+        let salt = identity.salt.clone();
+
+        assert!(identity.change_passphrase("wrong passphrase", "new passphrase").is_err());
+        identity.change_passphrase("old passphrase", "new passphrase").unwrap();
+        assert_ne!(identity.salt, salt);
+        assert!(identity.unlock("old passphrase").is_err());
+        assert_eq!(identity.unlock("new passphrase").unwrap().master_key(), master_key);
+    }
+}
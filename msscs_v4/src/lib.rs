@@ -7,15 +7,46 @@ pub mod block;
 pub mod huffman;
 pub mod identity;
 pub mod unlocked_identity;
+pub mod identity_backup;
+#[cfg(unix)]
+pub mod identity_agent;
+pub mod key_rotation;
+pub mod recovery;
+pub mod log_store;
+pub mod block_store;
 pub mod persistence;
-pub mod network;
 pub mod p2p_network;
+pub mod block_transfer;
+pub mod protocol;
+pub mod kad_store;
+pub mod peer_manager;
+pub mod private_network;
+pub mod relay;
+pub mod stun;
+#[cfg(any(test, feature = "simulation"))]
+pub mod simulation;
 pub mod webrtc_bridge;
 pub mod vfs;
 pub mod api;
 pub mod metrics;
 pub mod workspace;
 pub mod p2p_storage;
+pub mod quantum_crypto;
+pub mod quantum_block;
+pub mod erasure;
+pub mod shard_placement;
+pub mod shard_store;
+pub mod singularity;
+pub mod parallel;
+pub mod adaptive_compression;
+pub mod pinning;
+pub mod content_addressing;
+pub mod chunking;
+pub mod namespace;
+pub mod snapshot;
+pub mod p2p_vfs;
+pub mod repair;
+pub mod p2p_api;
 
 // Re-export commonly used types
 pub use block::{DataBlock, calculate_checksum};
@@ -0,0 +1,810 @@
+// LOG-STRUCTURED BLOCK STORE
+// Append-only record log with per-record checksums, fsync'd atomic batches and crash recovery
+
+use crate::error::{MSSCSError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Marks the start of every record ("MSLG")
+const RECORD_MAGIC: [u8; 4] = *b"MSLG";
+/// magic(4) + seq(8) + op(1) + flags(1) + key_len(2) + value_len(4) + crc32(4)
+const HEADER_LEN: usize = 24;
+const OP_PUT: u8 = 1;
+const OP_DELETE: u8 = 2;
+/// Set on the last record of a batch; a batch without it is never applied
+const FLAG_COMMIT: u8 = 0x01;
+/// Set on the first record of a batch, so recovery can tell where a batch resumes after damage
+const FLAG_BEGIN: u8 = 0x02;
+/// Compaction is only worth it once this much of the log is dead
+const COMPACT_MIN_DEAD_BYTES: u64 = 4 * 1024 * 1024;
+
+/// A single mutation inside an atomic batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogOp {
+    Put { key: String, value: Vec<u8> },
+    Delete { key: String },
+}
+
+/// Entry that could not be recovered during startup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorruptedEntry {
+    /// Where the entry lives (e.g. `blocks.log@4096` or a legacy file path)
+    pub location: String,
+    /// Why it was rejected
+    pub reason: String,
+}
+
+/// Outcome of the recovery pass run when a store is opened
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryReport {
+    /// Valid records replayed from the log
+    pub records_replayed: u64,
+    /// Keys live after replay
+    pub live_keys: usize,
+    /// Records (or legacy files) rejected because of bad checksums or framing
+    pub corrupted: Vec<CorruptedEntry>,
+    /// Records belonging to a batch whose commit never reached disk
+    pub uncommitted_discarded: u64,
+    /// Valid records dropped because another record of their batch was corrupted
+    #[serde(default)]
+    pub damaged_discarded: u64,
+    /// Bytes cut from the end of the log (torn or uncommitted writes)
+    pub truncated_bytes: u64,
+    /// Legacy `<uuid>.block` files migrated into the log
+    pub legacy_imported: usize,
+}
+
+impl RecoveryReport {
+    /// True when nothing was lost or repaired
+    pub fn is_clean(&self) -> bool {
+        self.corrupted.is_empty()
+            && self.uncommitted_discarded == 0
+            && self.damaged_discarded == 0
+            && self.truncated_bytes == 0
+    }
+}
+
+/// Location of a live record inside the log file
+#[derive(Debug, Clone, Copy)]
+struct RecordLocation {
+    offset: u64,
+    len: u64,
+}
+
+struct LogInner {
+    file: File,
+    index: HashMap<String, RecordLocation>,
+    next_seq: u64,
+    end: u64,
+    live_bytes: u64,
+}
+
+/// Crash-safe key/value store backed by a single append-only log file
+pub struct LogStore {
+    path: PathBuf,
+    inner: Mutex<LogInner>,
+    report: RecoveryReport,
+}
+
+/// Decoded record header
+struct RecordHeader {
+    seq: u64,
+    op: u8,
+    flags: u8,
+    key_len: usize,
+    value_len: usize,
+    crc: u32,
+}
+
+impl RecordHeader {
+    fn parse(buf: &[u8; HEADER_LEN]) -> Option<Self> {
+        if buf[0..4] != RECORD_MAGIC {
+            return None;
+        }
+        Some(RecordHeader {
+            seq: u64::from_le_bytes(buf[4..12].try_into().ok()?),
+            op: buf[12],
+            flags: buf[13],
+            key_len: u16::from_le_bytes(buf[14..16].try_into().ok()?) as usize,
+            value_len: u32::from_le_bytes(buf[16..20].try_into().ok()?) as usize,
+            crc: u32::from_le_bytes(buf[20..24].try_into().ok()?),
+        })
+    }
+
+    fn record_len(&self) -> u64 {
+        (HEADER_LEN + self.key_len + self.value_len) as u64
+    }
+}
+
+/// Checksum over the header fields after the magic plus the payload
+fn record_checksum(header_fields: &[u8], key: &[u8], value: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(header_fields);
+    hasher.update(key);
+    hasher.update(value);
+    hasher.finalize()
+}
+
+fn encode_record(buf: &mut Vec<u8>, seq: u64, op: u8, flags: u8, key: &str, value: &[u8]) -> Result<()> {
+    let key_len = u16::try_from(key.len())
+        .map_err(|_| MSSCSError::InvalidData(format!("Key too long ({} bytes)", key.len())))?;
+    let value_len = u32::try_from(value.len())
+        .map_err(|_| MSSCSError::InvalidData(format!("Value too large ({} bytes)", value.len())))?;
+
+    let mut fields = [0u8; 16];
+    fields[0..8].copy_from_slice(&seq.to_le_bytes());
+    fields[8] = op;
+    fields[9] = flags;
+    fields[10..12].copy_from_slice(&key_len.to_le_bytes());
+    fields[12..16].copy_from_slice(&value_len.to_le_bytes());
+    let crc = record_checksum(&fields, key.as_bytes(), value);
+
+    buf.extend_from_slice(&RECORD_MAGIC);
+    buf.extend_from_slice(&fields);
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    buf.extend_from_slice(value);
+    Ok(())
+}
+
+/// Read and verify the record at `offset`, returning its header, key and value
+fn read_record(file: &mut File, offset: u64, file_len: u64) -> std::result::Result<(RecordHeader, String, Vec<u8>), String> {
+    if offset + HEADER_LEN as u64 > file_len {
+        return Err("truncated header".to_string());
+    }
+    let mut header_buf = [0u8; HEADER_LEN];
+    file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+    file.read_exact(&mut header_buf).map_err(|e| e.to_string())?;
+
+    let header = RecordHeader::parse(&header_buf).ok_or_else(|| "bad record magic".to_string())?;
+    if offset + header.record_len() > file_len {
+        return Err("truncated record".to_string());
+    }
+
+    let mut key = vec![0u8; header.key_len];
+    let mut value = vec![0u8; header.value_len];
+    file.read_exact(&mut key).map_err(|e| e.to_string())?;
+    file.read_exact(&mut value).map_err(|e| e.to_string())?;
+
+    if record_checksum(&header_buf[4..20], &key, &value) != header.crc {
+        return Err("checksum mismatch".to_string());
+    }
+    if header.op != OP_PUT && header.op != OP_DELETE {
+        return Err(format!("unknown op {}", header.op));
+    }
+    let key = String::from_utf8(key).map_err(|_| "key is not valid UTF-8".to_string())?;
+
+    Ok((header, key, value))
+}
+
+/// Find the next offset after `from` where a record magic appears
+fn find_next_magic(file: &mut File, from: u64, file_len: u64) -> Result<Option<u64>> {
+    const WINDOW: usize = 64 * 1024;
+    let mut pos = from;
+    let mut buf = vec![0u8; WINDOW];
+
+    while pos < file_len {
+        let to_read = ((file_len - pos) as usize).min(WINDOW);
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut buf[..to_read])?;
+
+        if let Some(i) = buf[..to_read].windows(RECORD_MAGIC.len()).position(|w| w == RECORD_MAGIC) {
+            return Ok(Some(pos + i as u64));
+        }
+        if to_read < RECORD_MAGIC.len() {
+            break;
+        }
+        // Overlap so a magic straddling two windows is still found
+        pos += (to_read - (RECORD_MAGIC.len() - 1)) as u64;
+    }
+
+    Ok(None)
+}
+
+/// fsync a directory so a rename inside it is durable
+fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+    }
+    Ok(())
+}
+
+/// Replace `path` with `data` atomically: write a temp file, fsync it, rename over the target
+/// Each call gets its own temp file, so concurrent writers of the same path cannot mix their bytes
+pub fn write_file_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+    tmp.write_all(data)?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(path).map_err(|e| e.error)?;
+    sync_dir(parent)
+}
+
+impl LogStore {
+    /// Open (or create) the log at `path` and replay it
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        let (inner, report) = Self::recover(&path, file)?;
+
+        Ok(LogStore {
+            path,
+            inner: Mutex::new(inner),
+            report,
+        })
+    }
+
+    /// Replay the log, rebuilding the index and cutting off anything after the last commit.
+    /// Records are buffered per batch and applied only when the batch's commit record validates
+    /// and none of its records was corrupted.
+    fn recover(path: &Path, mut file: File) -> Result<(LogInner, RecoveryReport)> {
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file_len = file.metadata()?.len();
+
+        let mut report = RecoveryReport::default();
+        let mut index: HashMap<String, RecordLocation> = HashMap::new();
+        let mut pending: Vec<(u8, String, RecordLocation)> = Vec::new();
+        let mut pending_seq = 0u64;
+        // The batch being buffered lost a record to corruption
+        let mut damaged = false;
+        let mut max_seq = 0u64;
+        let mut committed_end = 0u64;
+        let mut pos = 0u64;
+
+        while pos < file_len {
+            match read_record(&mut file, pos, file_len) {
+                Ok((header, key, _value)) => {
+                    let begins = header.flags & FLAG_BEGIN != 0;
+                    if begins || header.seq != pending_seq {
+                        Self::discard_pending(&mut pending, damaged, &mut report);
+                        // Right after corruption, a record not marked as a batch start may be
+                        // the tail of a batch whose head was lost
+                        damaged = damaged && !begins;
+                    }
+                    pending_seq = header.seq;
+                    max_seq = max_seq.max(header.seq);
+                    report.records_replayed += 1;
+
+                    let len = header.record_len();
+                    pending.push((header.op, key, RecordLocation { offset: pos, len }));
+                    pos += len;
+
+                    if header.flags & FLAG_COMMIT != 0 {
+                        if damaged {
+                            Self::discard_pending(&mut pending, damaged, &mut report);
+                            damaged = false;
+                        }
+                        for (op, key, location) in pending.drain(..) {
+                            if op == OP_PUT {
+                                index.insert(key, location);
+                            } else {
+                                index.remove(&key);
+                            }
+                        }
+                        committed_end = pos;
+                    }
+                }
+                Err(reason) => {
+                    let next = find_next_magic(&mut file, pos + 1, file_len)?;
+                    if next.is_none() && reason.starts_with("truncated") {
+                        // Torn write at the tail: everything from here on is cut below
+                        break;
+                    }
+                    report.corrupted.push(CorruptedEntry {
+                        location: format!("{}@{}", file_name, pos),
+                        reason,
+                    });
+                    damaged = true;
+                    match next {
+                        Some(next) => pos = next,
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        Self::discard_pending(&mut pending, damaged, &mut report);
+
+        if committed_end < file_len {
+            report.truncated_bytes = file_len - committed_end;
+            file.set_len(committed_end)?;
+            file.sync_all()?;
+        }
+
+        let live_bytes = index.values().map(|loc| loc.len).sum();
+        report.live_keys = index.len();
+
+        let inner = LogInner {
+            file,
+            index,
+            next_seq: max_seq + 1,
+            end: committed_end,
+            live_bytes,
+        };
+        Ok((inner, report))
+    }
+
+    /// Drop a batch that will never be applied, counting why
+    fn discard_pending(pending: &mut Vec<(u8, String, RecordLocation)>, damaged: bool, report: &mut RecoveryReport) {
+        if damaged {
+            report.damaged_discarded += pending.len() as u64;
+        } else {
+            report.uncommitted_discarded += pending.len() as u64;
+        }
+        pending.clear();
+    }
+
+    /// Report produced by the recovery pass when the store was opened
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.report
+    }
+
+    /// Path of the underlying log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, LogInner>> {
+        self.inner
+            .lock()
+            .map_err(|_| MSSCSError::Io(std::io::Error::other("log store lock poisoned")))
+    }
+
+    /// Apply a batch atomically: all operations become visible after a single fsync, or none do
+    pub fn write_batch(&self, ops: &[LogOp]) -> Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        let mut inner = self.lock()?;
+        let seq = inner.next_seq;
+
+        let mut buf = Vec::new();
+        let mut offsets = Vec::with_capacity(ops.len());
+        for (i, op) in ops.iter().enumerate() {
+            let mut flags = if i + 1 == ops.len() { FLAG_COMMIT } else { 0 };
+            if i == 0 {
+                flags |= FLAG_BEGIN;
+            }
+            let start = buf.len() as u64;
+            match op {
+                LogOp::Put { key, value } => encode_record(&mut buf, seq, OP_PUT, flags, key, value)?,
+                LogOp::Delete { key } => encode_record(&mut buf, seq, OP_DELETE, flags, key, &[])?,
+            }
+            offsets.push((start, buf.len() as u64 - start));
+        }
+
+        let base = inner.end;
+        let write_result = (|| -> std::io::Result<()> {
+            inner.file.seek(SeekFrom::Start(base))?;
+            inner.file.write_all(&buf)?;
+            inner.file.sync_data()
+        })();
+
+        if let Err(e) = write_result {
+            // Roll back the partial append so the next batch starts on a clean boundary
+            let _ = inner.file.set_len(base);
+            return Err(MSSCSError::Io(e));
+        }
+
+        inner.next_seq += 1;
+        inner.end = base + buf.len() as u64;
+
+        for (op, (start, len)) in ops.iter().zip(offsets) {
+            let previous = match op {
+                LogOp::Put { key, .. } => inner.index.insert(key.clone(), RecordLocation { offset: base + start, len }),
+                LogOp::Delete { key } => inner.index.remove(key),
+            };
+            if let Some(previous) = previous {
+                inner.live_bytes -= previous.len;
+            }
+            if matches!(op, LogOp::Put { .. }) {
+                inner.live_bytes += len;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Store a value under `key`
+    pub fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.write_batch(&[LogOp::Put {
+            key: key.to_string(),
+            value: value.to_vec(),
+        }])
+    }
+
+    /// Remove `key`; removing a missing key is a no-op
+    pub fn delete(&self, key: &str) -> Result<()> {
+        if !self.contains(key) {
+            return Ok(());
+        }
+        self.write_batch(&[LogOp::Delete { key: key.to_string() }])
+    }
+
+    /// Read the value stored under `key`, verifying its checksum
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut inner = self.lock()?;
+        let Some(location) = inner.index.get(key).copied() else {
+            return Ok(None);
+        };
+        let end = inner.end;
+
+        let (_, stored_key, value) = read_record(&mut inner.file, location.offset, end).map_err(|reason| {
+            MSSCSError::CorruptedData(format!("{}@{}: {}", self.path.display(), location.offset, reason))
+        })?;
+        if stored_key != key {
+            return Err(MSSCSError::CorruptedData(format!(
+                "{}@{}: index points at key '{}' instead of '{}'",
+                self.path.display(),
+                location.offset,
+                stored_key,
+                key
+            )));
+        }
+
+        Ok(Some(value))
+    }
+
+    /// Whether `key` is present
+    pub fn contains(&self, key: &str) -> bool {
+        self.lock().map(|inner| inner.index.contains_key(key)).unwrap_or(false)
+    }
+
+    /// Length of the value stored under `key`, taken from the index without reading it
+    pub fn value_len(&self, key: &str) -> Option<u64> {
+        let inner = self.lock().ok()?;
+        inner
+            .index
+            .get(key)
+            .map(|location| location.len - HEADER_LEN as u64 - key.len() as u64)
+    }
+
+    /// Up to `len` bytes of the value under `key` starting at `offset`, with the value's
+    /// full length; only that range is read, so the record checksum is not verified
+    pub fn get_range(&self, key: &str, offset: u64, len: usize) -> Result<Option<(u64, Vec<u8>)>> {
+        let mut inner = self.lock()?;
+        let Some(location) = inner.index.get(key).copied() else {
+            return Ok(None);
+        };
+        let value_start = location.offset + HEADER_LEN as u64 + key.len() as u64;
+        let value_len = location.len - HEADER_LEN as u64 - key.len() as u64;
+        let start = offset.min(value_len);
+        let end = start.saturating_add(len as u64).min(value_len);
+
+        let mut data = vec![0u8; (end - start) as usize];
+        inner.file.seek(SeekFrom::Start(value_start + start))?;
+        inner.file.read_exact(&mut data)?;
+        Ok(Some((value_len, data)))
+    }
+
+    /// All live keys
+    pub fn keys(&self) -> Vec<String> {
+        self.lock()
+            .map(|inner| inner.index.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Number of live keys
+    pub fn len(&self) -> usize {
+        self.lock().map(|inner| inner.index.len()).unwrap_or(0)
+    }
+
+    /// True when no keys are stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes in the log that no longer back a live key
+    pub fn dead_bytes(&self) -> u64 {
+        self.lock().map(|inner| inner.end - inner.live_bytes).unwrap_or(0)
+    }
+
+    /// Whether enough of the log is garbage to make compaction worthwhile
+    pub fn needs_compaction(&self) -> bool {
+        self.lock()
+            .map(|inner| {
+                let dead = inner.end - inner.live_bytes;
+                dead >= COMPACT_MIN_DEAD_BYTES && dead > inner.live_bytes
+            })
+            .unwrap_or(false)
+    }
+
+    /// Rewrite the log with only live records, swapping it in atomically. Returns bytes reclaimed.
+    pub fn compact(&self) -> Result<u64> {
+        let mut inner = self.lock()?;
+        let old_end = inner.end;
+        let parent = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let mut entries: Vec<(String, RecordLocation)> =
+            inner.index.iter().map(|(k, loc)| (k.clone(), *loc)).collect();
+        entries.sort_by_key(|(_, loc)| loc.offset);
+
+        let mut new_index = HashMap::with_capacity(entries.len());
+        let mut new_end = 0u64;
+        // Dropped on any error below, which removes the half-written copy
+        let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+        {
+            let mut buf = Vec::new();
+            for (seq, (key, location)) in entries.into_iter().enumerate() {
+                let (_, _, value) = read_record(&mut inner.file, location.offset, old_end).map_err(|reason| {
+                    MSSCSError::CorruptedData(format!("{}@{}: {}", self.path.display(), location.offset, reason))
+                })?;
+                buf.clear();
+                encode_record(&mut buf, seq as u64 + 1, OP_PUT, FLAG_BEGIN | FLAG_COMMIT, &key, &value)?;
+                tmp.write_all(&buf)?;
+                new_index.insert(key, RecordLocation { offset: new_end, len: buf.len() as u64 });
+                new_end += buf.len() as u64;
+            }
+            tmp.as_file().sync_all()?;
+        }
+
+        tmp.persist(&self.path).map_err(|e| e.error)?;
+        sync_dir(parent)?;
+
+        inner.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        inner.next_seq = new_index.len() as u64 + 1;
+        inner.index = new_index;
+        inner.end = new_end;
+        inner.live_bytes = new_end;
+
+        Ok(old_end.saturating_sub(new_end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn open(dir: &TempDir) -> LogStore {
+        LogStore::open(dir.path().join("blocks.log")).unwrap()
+    }
+
+    #[test]
+    fn test_put_get_delete_survive_reopen() {
+        let dir = TempDir::new().unwrap();
+        {
+            let store = open(&dir);
+            store.put("a", b"alpha").unwrap();
+            store.put("b", b"beta").unwrap();
+            store.put("a", b"alpha-2").unwrap();
+            store.delete("b").unwrap();
+        }
+
+        let store = open(&dir);
+        assert!(store.recovery_report().is_clean());
+        assert_eq!(store.get("a").unwrap(), Some(b"alpha-2".to_vec()));
+        assert_eq!(store.get("b").unwrap(), None);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_torn_tail_is_truncated_and_reported() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("blocks.log");
+        {
+            let store = open(&dir);
+            store.put("a", b"alpha").unwrap();
+            store.put("b", b"beta").unwrap();
+        }
+
+        // Simulate a crash halfway through appending the second record
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 3).unwrap();
+
+        let store = open(&dir);
+        let report = store.recovery_report();
+        assert!(report.truncated_bytes > 0);
+        assert_eq!(store.get("a").unwrap(), Some(b"alpha".to_vec()));
+        assert_eq!(store.get("b").unwrap(), None);
+
+        // The log is usable again after recovery
+        store.put("c", b"gamma").unwrap();
+        drop(store);
+        let store = open(&dir);
+        assert!(store.recovery_report().is_clean());
+        assert_eq!(store.get("c").unwrap(), Some(b"gamma".to_vec()));
+    }
+
+    #[test]
+    fn test_corrupted_record_is_reported_not_hidden() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("blocks.log");
+        {
+            let store = open(&dir);
+            store.put("a", b"alpha").unwrap();
+            store.put("b", b"beta").unwrap();
+            store.put("c", b"gamma").unwrap();
+        }
+
+        // Flip a payload byte in the first record
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[HEADER_LEN + 2] ^= 0xFF;
+        fs::write(&path, bytes).unwrap();
+
+        let store = open(&dir);
+        let report = store.recovery_report();
+        assert_eq!(report.corrupted.len(), 1);
+        assert!(report.corrupted[0].reason.contains("checksum"));
+        assert_eq!(store.get("a").unwrap(), None);
+        assert_eq!(store.get("b").unwrap(), Some(b"beta".to_vec()));
+        assert_eq!(store.get("c").unwrap(), Some(b"gamma".to_vec()));
+    }
+
+    #[test]
+    fn test_uncommitted_batch_is_discarded() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("blocks.log");
+        {
+            let store = open(&dir);
+            store.put("a", b"alpha").unwrap();
+            store
+                .write_batch(&[
+                    LogOp::Put { key: "b".to_string(), value: b"beta".to_vec() },
+                    LogOp::Put { key: "c".to_string(), value: b"gamma".to_vec() },
+                ])
+                .unwrap();
+        }
+
+        // Drop the commit record of the batch: neither of its puts may survive
+        let commit_len = (HEADER_LEN + 1 + 5) as u64;
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - commit_len).unwrap();
+
+        let store = open(&dir);
+        assert_eq!(store.recovery_report().uncommitted_discarded, 1);
+        assert_eq!(store.get("a").unwrap(), Some(b"alpha".to_vec()));
+        assert_eq!(store.get("b").unwrap(), None);
+        assert_eq!(store.get("c").unwrap(), None);
+    }
+
+    #[test]
+    fn test_batch_with_corrupted_record_is_discarded_whole() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("blocks.log");
+        {
+            let store = open(&dir);
+            store.put("a", b"alpha").unwrap();
+            store
+                .write_batch(&[
+                    LogOp::Put { key: "b".to_string(), value: b"beta".to_vec() },
+                    LogOp::Put { key: "c".to_string(), value: b"gamma".to_vec() },
+                    LogOp::Delete { key: "a".to_string() },
+                ])
+                .unwrap();
+            store.put("d", b"delta").unwrap();
+        }
+
+        // Flip a payload byte in the middle record of the batch; its commit record still validates
+        let first_len = HEADER_LEN + 1 + 5;
+        let second_start = first_len + HEADER_LEN + 1 + 4;
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[second_start + HEADER_LEN + 2] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+
+        let store = open(&dir);
+        let report = store.recovery_report();
+        assert_eq!(report.corrupted.len(), 1);
+        assert_eq!(report.damaged_discarded, 2);
+        assert_eq!(store.get("a").unwrap(), Some(b"alpha".to_vec()), "the batch's delete must not apply");
+        assert_eq!(store.get("b").unwrap(), None);
+        assert_eq!(store.get("c").unwrap(), None);
+        assert_eq!(store.get("d").unwrap(), Some(b"delta".to_vec()));
+
+        // Losing the head of a batch discards its surviving tail as well
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[first_len + HEADER_LEN + 2] ^= 0xFF;
+        bytes[second_start + HEADER_LEN + 2] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+        drop(store);
+
+        let store = open(&dir);
+        assert_eq!(store.recovery_report().damaged_discarded, 2);
+        assert_eq!(store.get("a").unwrap(), Some(b"alpha".to_vec()));
+        assert_eq!(store.get("c").unwrap(), None);
+        assert_eq!(store.get("d").unwrap(), Some(b"delta".to_vec()));
+    }
+
+    #[test]
+    fn test_failed_compaction_leaves_no_temp_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("blocks.log");
+        {
+            let store = open(&dir);
+            store.put("a", b"alpha").unwrap();
+            store.put("b", b"beta").unwrap();
+        }
+        let store = open(&dir);
+
+        // Corrupt a live record behind the store's back so the rewrite fails partway
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&path, bytes).unwrap();
+
+        assert!(store.compact().is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1, "no temp file left behind");
+        assert_eq!(store.get("a").unwrap(), Some(b"alpha".to_vec()));
+    }
+
+    #[test]
+    fn test_compaction_reclaims_dead_records() {
+        let dir = TempDir::new().unwrap();
+        let store = open(&dir);
+        for i in 0..10 {
+            store.put("key", format!("value-{}", i).as_bytes()).unwrap();
+        }
+        store.put("other", b"x").unwrap();
+        store.delete("other").unwrap();
+
+        assert!(store.dead_bytes() > 0);
+        let reclaimed = store.compact().unwrap();
+        assert!(reclaimed > 0);
+        assert_eq!(store.dead_bytes(), 0);
+        assert_eq!(store.get("key").unwrap(), Some(b"value-9".to_vec()));
+
+        store.put("after", b"compaction").unwrap();
+        drop(store);
+
+        let store = open(&dir);
+        assert!(store.recovery_report().is_clean());
+        assert_eq!(store.get("key").unwrap(), Some(b"value-9".to_vec()));
+        assert_eq!(store.get("after").unwrap(), Some(b"compaction".to_vec()));
+    }
+
+    #[test]
+    fn test_write_file_atomic_replaces_contents() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("manifest.json");
+        write_file_atomic(&path, b"{}").unwrap();
+        write_file_atomic(&path, b"{\"a\":1}").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"{\"a\":1}");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1, "no temp file left behind");
+    }
+
+    #[test]
+    fn test_write_file_atomic_concurrent_writers() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("manifest.json");
+        let writers: Vec<_> = (0..8u8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        write_file_atomic(&path, &vec![i; 4096]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 4096);
+        assert!(data.iter().all(|b| *b == data[0]), "contents mixed from several writers");
+    }
+}
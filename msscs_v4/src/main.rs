@@ -1,17 +1,32 @@
 // Main entry point
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use msscs_v4::{
     api::{create_router, AppState},
+    block::IdentityKey,
+    block_transfer::MAX_BLOCK_BYTES,
     config::Config,
-    error::Result,
+    error::{MSSCSError, Result},
+    identity::{self, IdentityManager, QuantumIdentity, PASSPHRASE_ENV},
+    identity_agent::{self, AgentClient, IdentityAgent},
+    identity_backup::IdentityBundle,
+    kad_store::RecordStoreConfig,
+    key_rotation,
     metrics::Metrics,
-    network::Node,
+    p2p_network::{self, P2PConfig, P2PEvent, P2PNode, P2PNodeCommand},
     persistence::PersistenceManager,
+    private_network,
+    recovery::{self, RecoveryContact, RecoveryStatus, RecoverySession, RecoveryVault},
+    shard_store::ShardStoreConfig,
     vfs::VirtualFileSystem,
 };
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{mpsc, RwLock};
+use uuid::Uuid;
+use zeroize::Zeroize;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 /// MSSCS v4.0 - Multi-State Chain-based Secure Storage
@@ -22,15 +37,120 @@ struct Args {
     #[arg(short, long)]
     port: Option<u16>,
     
-    /// Bootstrap peer address (can be specified multiple times)
+    /// Bootstrap peer address, multiaddr or host:port (can be specified multiple times)
     #[arg(short = 'b', long = "peer")]
     peers: Vec<String>,
     
     /// Configuration file path
     #[arg(short, long, default_value = "config.toml")]
     config: PathBuf,
+    
+    /// Use the identity held by the identity agent instead of asking for its passphrase (read
+    /// from $MSSCS_PASSPHRASE, else prompted for); subcommands that re-encrypt, rotate or
+    /// export the identity's secret keys still ask for it
+    #[arg(long)]
+    agent: bool,
+    
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run or control the identity agent, which keeps the local identity unlocked so that
+    /// --agent can replace the passphrase prompt; the socket is $MSSCS_AGENT_SOCK or the runtime dir
+    Agent {
+        #[command(subcommand)]
+        action: AgentCommand,
+    },
+    /// Re-encrypt blocks written with the legacy UUID-derived key under per-file data keys
+    /// wrapped by the local identity, re-wrap data keys still wrapped by retired key
+    /// generations, then exit
+    MigrateBlocks,
+    /// Re-encrypt the local identity's secret keys under a new passphrase (read from
+    /// $MSSCS_NEW_PASSPHRASE, else prompted for), then exit
+    ChangePassphrase,
+    /// Replace the local identity's keys with a new generation signed by the current one,
+    /// re-wrap the local blocks' data keys under it, then exit
+    /// The rotation is published to the DHT the next time the node starts
+    RotateKeys,
+    /// Write a backup bundle of the local identity protected by a backup passphrase (read
+    /// from $MSSCS_BACKUP_PASSPHRASE, else prompted for), then exit
+    ExportIdentity {
+        /// Bundle file to write
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Write the printable (QR alphanumeric) text encoding instead of the binary bundle
+        #[arg(long)]
+        text: bool,
+    },
+    /// Verify and import an identity from a backup bundle (binary or text) with the backup
+    /// passphrase it was exported with, make it the local identity, then exit
+    ImportIdentity {
+        /// Bundle file to read
+        input: PathBuf,
+    },
+    /// Write the contact card others need to pick this node's identity as a trusted
+    /// recovery contact, then exit
+    ExportContact {
+        /// Card file to write
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Publicly reachable address of this node, e.g. /ip4/203.0.113.5/tcp/4001
+        #[arg(long)]
+        address: String,
+    },
+    /// Split the local identity among trusted contacts and hand each its share, then exit
+    SetupRecovery {
+        /// Contact card of a trusted contact (repeat for each contact)
+        #[arg(long = "contact", required = true)]
+        contacts: Vec<PathBuf>,
+        /// Contacts that must approve a recovery
+        #[arg(long)]
+        threshold: u8,
+    },
+    /// Ask trusted contacts for the shares of an identity until enough approve, store the
+    /// recovered identity under a new passphrase and make it the local identity, then exit
+    Recover {
+        /// Identity to recover
+        identity: Uuid,
+        /// Address of a contact's node, ending in /p2p/<peer id> (repeat for each contact)
+        #[arg(long = "contact", required = true)]
+        contacts: Vec<String>,
+        /// Shown to the contacts with the request
+        #[arg(long, default_value = "")]
+        message: String,
+    },
 }
 
+#[derive(Subcommand, Debug)]
+enum AgentCommand {
+    /// Run the agent in the foreground
+    Start {
+        /// Seconds without use after which the agent forgets the keys (0 keeps them)
+        #[arg(long, default_value = "900")]
+        idle_timeout: u64,
+        /// Identity directory to serve instead of the node's, e.g. the desktop client's
+        #[arg(long)]
+        identity_dir: Option<PathBuf>,
+    },
+    /// Unlock the local identity in the agent, prompting for its passphrase
+    Unlock,
+    /// Make the agent forget the unlocked keys
+    Lock,
+    /// Show which identity the agent holds
+    Status,
+}
+
+/// Environment variable holding the passphrase `change-passphrase` switches to
+const NEW_PASSPHRASE_ENV: &str = "MSSCS_NEW_PASSPHRASE";
+
+/// Environment variable holding the passphrase of identity backup bundles
+const BACKUP_PASSPHRASE_ENV: &str = "MSSCS_BACKUP_PASSPHRASE";
+
+/// How often a recovery asks the contacts that have not answered yet
+const RECOVERY_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Parse CLI arguments
@@ -74,32 +194,250 @@ async fn main() -> Result<()> {
     std::fs::create_dir_all(&config.data_dir)?;
     
     // Initialize persistence manager
-    let persistence = Arc::new(PersistenceManager::new(config.data_dir.clone())?);
+    let persistence = Arc::new(PersistenceManager::from_config(&config)?);
     tracing::info!("Persistence manager initialized");
     
-    // Initialize VFS
-    let mut vfs = VirtualFileSystem::new(config.clone(), persistence.clone())?;
-    tracing::info!("VFS initialized");
+    let mut identities = IdentityManager::new(config.data_dir.join("identities"))?;
+    if let Some(Command::Agent { action }) = &args.command {
+        return run_agent_command(action, config.data_dir.join("identities")).await;
+    }
+    if let Some(Command::ImportIdentity { input }) = &args.command {
+        let bundle = IdentityBundle::decode(&std::fs::read(input)?)?;
+        let backup_passphrase = identity::read_secret(BACKUP_PASSPHRASE_ENV, "Backup passphrase: ")?;
+        let id = identities.import_identity(&bundle, &backup_passphrase).await?;
+        println!("Imported identity {} ({}), now the local identity", bundle.name, id);
+        return Ok(());
+    }
+    if let Some(Command::Recover { identity, contacts, message }) = &args.command {
+        let passphrase = identity::read_new_secret(PASSPHRASE_ENV, "Passphrase for the recovered identity: ")?;
+        let contacts = contacts
+            .iter()
+            .map(|contact| {
+                P2PConfig::parse_peer_address(contact)
+                    .ok_or_else(|| MSSCSError::Config(format!("Invalid contact address: {}", contact)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let commands = start_client_node(&config).await?;
+        let session = collect_shares(&commands, *identity, &contacts, message).await?;
+        let id = identities.restore_identity(session.recover(&passphrase)?).await?;
+        println!("Recovered identity {}, now the local identity", id);
+        return Ok(());
+    }
     
-    // Initialize network node
-    let node = Arc::new(Node::new(config.clone()));
-    tracing::info!("Network node initialized: {}", node.node_id);
+    // The agent wraps and unwraps the node's data keys itself, so its secret keys never reach us
+    if args.agent && args.command.is_none() {
+        let keys = AgentClient::from_env().keys().await?;
+        let identity = keys.identity().clone();
+        tracing::info!(
+            "🔐 Identity {} ({}) held by the agent, key generation {}",
+            identity.name,
+            identity.id,
+            identity.version
+        );
+        let mut vfs = VirtualFileSystem::new(config.clone(), persistence.clone()).await?;
+        vfs.set_keys(Arc::new(keys));
+        return run_node(config, persistence, vfs, identity).await;
+    }
+    
+    // Unlock the identity whose key wraps the per-file block keys
+    let passphrase = if identities.load_all().await? == 0 {
+        identity::read_new_secret(PASSPHRASE_ENV, "Passphrase for the new identity: ")?
+    } else {
+        identity::read_secret(PASSPHRASE_ENV, "Identity passphrase: ")?
+    };
+    let identity = identities.unlock_or_create("msscs-node", &passphrase).await?;
+    let retired = identities.unlock_retired(identity.user_id(), &passphrase)?;
+    tracing::info!(
+        "🔐 Identity {} ({}) unlocked, key generation {}",
+        identity.name(),
+        identity.user_id(),
+        identity.identity.version
+    );
+    
+    match args.command {
+        Some(Command::MigrateBlocks) => {
+            let identity_key = IdentityKey::from_identity(&identity);
+            let report = persistence.migrate_legacy_blocks(&identity_key).await?;
+            println!(
+                "Re-encrypted {} blocks in {} chains, updated {} file versions",
+                report.migrated, report.chains, report.versions_updated
+            );
+            let retired_keys: Vec<IdentityKey> = retired.iter().map(IdentityKey::from_identity).collect();
+            let rewrap = persistence.rewrap_data_keys(&retired_keys, &identity_key).await?;
+            println!("Re-wrapped the data keys of {} blocks under the current keys", rewrap.rewrapped);
+            if !report.failed.is_empty() {
+                for uuid in &report.failed {
+                    println!("  could not decrypt block {}", uuid);
+                }
+                return Err(MSSCSError::Crypto(format!(
+                    "{} legacy blocks could not be migrated",
+                    report.failed.len()
+                )));
+            }
+            return Ok(());
+        }
+        Some(Command::ChangePassphrase) => {
+            let new_passphrase = identity::read_new_secret(NEW_PASSPHRASE_ENV, "New passphrase: ")?;
+            identities.change_passphrase(identity.user_id(), &passphrase, &new_passphrase).await?;
+            println!("Changed the passphrase of identity {}", identity.user_id());
+            return Ok(());
+        }
+        Some(Command::RotateKeys) => {
+            let (previous, current) = identities.rotate_keys(identity.user_id(), &passphrase).await?;
+            let retired_keys: Vec<IdentityKey> = retired
+                .iter()
+                .chain(std::iter::once(&previous))
+                .map(IdentityKey::from_identity)
+                .collect();
+            let report = persistence.rewrap_data_keys(&retired_keys, &IdentityKey::from_identity(&current)).await?;
+            println!(
+                "Rotated identity {} from key generation {} to {}; re-wrapped the data keys of {} blocks",
+                current.user_id(),
+                previous.identity.version,
+                current.identity.version,
+                report.rewrapped
+            );
+            println!("Recovery shares hold the retired keys: run setup-recovery again to replace them");
+            println!("A running identity agent still holds the old keys: run agent unlock again");
+            return Ok(());
+        }
+        Some(Command::ExportIdentity { output, text }) => {
+            let backup_passphrase = identity::read_new_secret(BACKUP_PASSPHRASE_ENV, "Backup passphrase: ")?;
+            let bundle = IdentityBundle::export(&identity, &backup_passphrase)?;
+            let data = if text { bundle.to_text()?.into_bytes() } else { bundle.to_bytes()? };
+            std::fs::write(&output, data)?;
+            println!("Exported identity {} to {}", identity.user_id(), output.display());
+            return Ok(());
+        }
+        Some(Command::ExportContact { output, address }) => {
+            let mut address = P2PConfig::parse_peer_address(&address)
+                .ok_or_else(|| MSSCSError::Config(format!("Invalid address: {}", address)))?;
+            if private_network::parse_peer_id(&address.to_string()).is_none() {
+                address.push(Protocol::P2p(p2p_network::node_peer_id(&config.data_dir.join("p2p_key"))?));
+            }
+            let card = RecoveryContact::new(&identity.identity, &address)?;
+            let data = serde_json::to_vec_pretty(&card)
+                .map_err(|e| MSSCSError::InvalidData(format!("Failed to serialize contact card: {}", e)))?;
+            std::fs::write(&output, data)?;
+            println!("Wrote contact card for {} at {} to {}", identity.user_id(), address, output.display());
+            return Ok(());
+        }
+        Some(Command::SetupRecovery { contacts, threshold }) => {
+            let cards = contacts
+                .iter()
+                .map(|path| {
+                    serde_json::from_slice::<RecoveryContact>(&std::fs::read(path)?).map_err(|e| {
+                        MSSCSError::InvalidData(format!("Invalid contact card {}: {}", path.display(), e))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let shares = recovery::split_identity(&identity, &cards, threshold)?;
+            let commands = start_client_node(&config).await?;
+
+            let mut delivered = 0;
+            for (card, share) in cards.iter().zip(shares) {
+                match recovery::send_share(&commands, &card.address()?, share).await {
+                    Ok(()) => {
+                        delivered += 1;
+                        println!("  {} ({}) holds a share", card.name, card.identity_id);
+                    }
+                    Err(e) => println!("  {} ({}) did not take its share: {}", card.name, card.identity_id, e),
+                }
+            }
+            if delivered < threshold as usize {
+                return Err(MSSCSError::Network(format!(
+                    "Only {} contacts hold a share but {} are needed to recover",
+                    delivered, threshold
+                )));
+            }
+            println!(
+                "{} of {} contacts hold a share; any {} of them can recover identity {}",
+                delivered, cards.len(), threshold, identity.user_id()
+            );
+            return Ok(());
+        }
+        Some(Command::Agent { .. }) | Some(Command::ImportIdentity { .. }) | Some(Command::Recover { .. }) | None => {}
+    }
+    // Replicas fetched from peers may still carry data keys wrapped by retired generations
+    let mut vfs = VirtualFileSystem::new(config.clone(), persistence.clone()).await?;
+    vfs.set_identity(&identity);
+    for generation in &retired {
+        vfs.add_retired_identity(generation);
+    }
+    run_node(config, persistence, vfs, identity.identity.clone()).await
+}
+
+/// Serve the VFS over the API and the P2P network as `identity`
+async fn run_node(
+    config: Arc<Config>,
+    persistence: Arc<PersistenceManager>,
+    mut vfs: VirtualFileSystem,
+    identity: QuantumIdentity,
+) -> Result<()> {
+    let recovery_vault = RecoveryVault::open(config.data_dir.join("recovery.json"), identity.id)?;
+    tracing::info!("VFS initialized");
     
-    // Start DHT
-    node.start_dht(config.bootstrap_peers.clone()).await?;
-    tracing::info!("DHT started");
+    // Initialize P2P node (authenticated, Noise-encrypted libp2p transport)
+    // Only the configured peers; the public IPFS nodes would put the node on the global DHT
+    let bootstrap_peers = config.bootstrap_multiaddrs();
+    if bootstrap_peers.is_empty() {
+        tracing::warn!("No bootstrap peers configured, discovering peers through mDNS only");
+    }
+    let p2p_config = P2PConfig {
+        listen_port: config.p2p_listen_port(),
+        bootstrap_peers,
+        replication_factor: config.replication_factor,
+        record_store: RecordStoreConfig {
+            dir: Some(config.data_dir.join("dht")),
+            ..Default::default()
+        },
+        shard_store: ShardStoreConfig {
+            dir: Some(config.data_dir.join("shards")),
+            ..Default::default()
+        },
+        // Keeps the peer id stable across restarts
+        key_file: Some(config.data_dir.join("p2p_key")),
+        recovery_vault: Some(recovery_vault.clone()),
+        // Peers are served blocks straight from the VFS's store
+        block_store: Some(persistence.store()),
+        replica_store: ShardStoreConfig {
+            dir: Some(config.data_dir.join("replicas")),
+            max_shard_bytes: MAX_BLOCK_BYTES,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let p2p_node = P2PNode::new(p2p_config.clone()).await?;
+    let p2p_command_tx = p2p_node.get_command_sender();
+    let (mut events, _) = p2p_node.start(p2p_config).await?;
+    tracing::info!("P2P node listening on port {}", config.p2p_listen_port());
     
-    // Start P2P listener
-    let node_clone = node.clone();
+    // Peers that know a retired generation follow the published rotation chain
+    let rotated = (!identity.rotations.is_empty()).then(|| identity.clone());
+    let publisher = p2p_command_tx.clone();
     tokio::spawn(async move {
-        if let Err(e) = node_clone.run_p2p_listener().await {
-            tracing::error!("P2P listener error: {}", e);
+        while let Some(event) = events.recv().await {
+            match event {
+                P2PEvent::BootstrapComplete => {
+                    if let Some(rotated) = rotated.clone() {
+                        let publisher = publisher.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = key_rotation::publish_rotations(&publisher, &rotated).await {
+                                tracing::warn!("Could not publish the key rotation chain: {}", e);
+                            }
+                        });
+                    }
+                }
+                P2PEvent::PeerConnected(peer) => tracing::info!("Peer connected: {}", peer),
+                P2PEvent::PeerDisconnected(peer) => tracing::info!("Peer disconnected: {}", peer),
+                P2PEvent::Error(e) => tracing::warn!("P2P error: {}", e),
+                _ => {}
+            }
         }
     });
-    tracing::info!("P2P listener started on {}", node.addr);
     
-    // Associate VFS with node
-    vfs.set_node(node.clone());
+    // Associate VFS with the P2P node
+    vfs.set_network(p2p_command_tx.clone());
     let vfs = Arc::new(RwLock::new(vfs));
     
     // Initialize metrics
@@ -110,22 +448,23 @@ async fn main() -> Result<()> {
         let vfs_read = vfs.read().await;
         metrics.block_count.store(vfs_read.block_count(), std::sync::atomic::Ordering::Relaxed);
         metrics.storage_bytes.store(vfs_read.storage_bytes(), std::sync::atomic::Ordering::Relaxed);
-        metrics.peer_count.store(config.bootstrap_peers.len(), std::sync::atomic::Ordering::Relaxed);
     }
     
     // Create API state
     let state = AppState {
         vfs,
-        node,
+        p2p_command_tx: Some(p2p_command_tx),
         config: config.clone(),
         metrics,
+        recovery_vault: Some(recovery_vault),
+        identity: Some(identity),
     };
     
     // Create API router
     let app = create_router(state);
     
     // Start API server
-    let api_addr = format!("0.0.0.0:{}", config.port);
+    let api_addr = std::net::SocketAddr::new(config.api_host, config.port);
     tracing::info!("Starting API server on {}", api_addr);
     
     let listener = tokio::net::TcpListener::bind(&api_addr).await?;
@@ -136,3 +475,110 @@ async fn main() -> Result<()> {
     
     Ok(())
 }
+
+/// Run the identity agent, or talk to the running one
+async fn run_agent_command(action: &AgentCommand, identity_dir: PathBuf) -> Result<()> {
+    let client = AgentClient::from_env();
+    match action {
+        AgentCommand::Start { idle_timeout, identity_dir: served_dir } => {
+            let idle_timeout = (*idle_timeout > 0).then(|| Duration::from_secs(*idle_timeout));
+            let identity_dir = served_dir.clone().unwrap_or(identity_dir);
+            let listener = IdentityAgent::listen(client.socket()).await?;
+            println!("Identity agent listening on {}", client.socket().display());
+            println!("export {}={}", identity_agent::AGENT_SOCKET_ENV, client.socket().display());
+            IdentityAgent::new(identity_dir, idle_timeout).serve(listener).await
+        }
+        AgentCommand::Unlock => {
+            let mut passphrase = identity::read_secret(PASSPHRASE_ENV, "Identity passphrase: ")?;
+            let status = client.unlock(&passphrase).await;
+            passphrase.zeroize();
+            print_agent_status(&status?);
+            Ok(())
+        }
+        AgentCommand::Lock => {
+            client.lock().await?;
+            println!("Identity agent locked");
+            Ok(())
+        }
+        AgentCommand::Status => {
+            print_agent_status(&client.status().await?);
+            Ok(())
+        }
+    }
+}
+
+fn print_agent_status(status: &identity_agent::AgentStatus) {
+    match &status.identity {
+        Some(identity) => {
+            println!(
+                "Identity agent holds {} ({}), key generation {} and {} retired",
+                identity.name, identity.id, identity.version, identity.retired
+            );
+            if let Some(locks_in) = status.locks_in {
+                println!("Locks after {}s without use", locks_in.as_secs());
+            }
+        }
+        None => println!("Identity agent is locked"),
+    }
+}
+
+/// Start a P2P node with a fresh peer id for a one-shot command
+async fn start_client_node(config: &Config) -> Result<mpsc::UnboundedSender<P2PNodeCommand>> {
+    let p2p_config = P2PConfig {
+        bootstrap_peers: config.bootstrap_multiaddrs(),
+        enable_mdns: false,
+        ..Default::default()
+    };
+    let node = P2PNode::new(p2p_config.clone()).await?;
+    let commands = node.get_command_sender();
+    node.start(p2p_config).await?;
+    Ok(commands)
+}
+
+/// Ask the contacts that have not answered until enough of them released their shares
+async fn collect_shares(
+    commands: &mpsc::UnboundedSender<P2PNodeCommand>,
+    identity_id: Uuid,
+    contacts: &[Multiaddr],
+    message: &str,
+) -> Result<RecoverySession> {
+    let mut session = RecoverySession::new(identity_id, message)?;
+    println!(
+        "Recovery request {} sent; give your contacts the code {} so they can check it before approving",
+        session.request().request_id,
+        session.request().fingerprint()
+    );
+
+    let mut answered = HashSet::new();
+    loop {
+        for contact in contacts {
+            if answered.contains(contact) {
+                continue;
+            }
+            match recovery::ask_contact(commands, contact, session.request()).await {
+                Ok(RecoveryStatus::Pending) => {}
+                Ok(RecoveryStatus::Approved(released)) => {
+                    answered.insert(contact.clone());
+                    match session.add_share(&released) {
+                        Ok(true) => return Ok(session),
+                        Ok(false) => println!("  {} approved", contact),
+                        Err(e) => println!("  {} released an unusable share: {}", contact, e),
+                    }
+                }
+                Ok(RecoveryStatus::Denied) => {
+                    answered.insert(contact.clone());
+                    println!("  {} denied the request", contact);
+                }
+                Ok(RecoveryStatus::NoShare) => {
+                    answered.insert(contact.clone());
+                    println!("  {} holds no share of {}", contact, identity_id);
+                }
+                Err(e) => tracing::warn!("Could not reach {}: {}", contact, e),
+            }
+        }
+        if answered.len() == contacts.len() {
+            return Err(MSSCSError::Validation("Every contact answered but too few approved".to_string()));
+        }
+        tokio::time::sleep(RECOVERY_POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,1020 @@
+// NAMESPACE - hierarchical directory tree over file block chains
+// Maps normalized paths to inodes; files point at the head of their block chain
+// and keep a history of every version written to them
+
+use crate::adaptive_compression::CompressionAlgorithm;
+use crate::content_addressing::{ChunkRef, ContentId, MerkleNode, NodeType};
+use crate::error::{MSSCSError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Inode number of the root directory
+pub const ROOT_INO: u64 = 1;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Directory or file entry in the namespace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Inode {
+    /// Inode number
+    pub ino: u64,
+    /// File or directory
+    pub node_type: NodeType,
+    /// Parent directory inode (root is its own parent)
+    pub parent: u64,
+    /// Name within the parent directory
+    pub name: String,
+    /// Directory entries (name -> inode)
+    pub children: BTreeMap<String, u64>,
+    /// First block of the file's chain
+    pub head: Option<Uuid>,
+    /// Logical file size in bytes
+    pub size: u64,
+    /// Number of blocks in the file's chain
+    pub block_count: usize,
+    /// Compression of the file's chain
+    pub compression: CompressionAlgorithm,
+    /// `compression` was applied to each chunk of the chain, not to the file as a whole
+    /// (false for chains written before that)
+    #[serde(default)]
+    pub compressed_per_block: bool,
+    /// Content-addressed chunks (empty for files stored as a block chain)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<ChunkRef>,
+    /// Owner user ID
+    pub owner: String,
+    /// Creation timestamp
+    pub created_at: u64,
+    /// Last modification timestamp
+    pub modified_at: u64,
+    /// Every retained version of the file, oldest first (the last one is current)
+    #[serde(default)]
+    pub versions: Vec<FileVersion>,
+}
+
+impl Inode {
+    /// CIDs of the chunks of every retained version, once per reference
+    pub fn chunk_refs(&self) -> impl Iterator<Item = &str> + '_ {
+        self.versions
+            .iter()
+            .flat_map(|version| version.chunks.iter().map(|chunk| chunk.cid.as_str()))
+    }
+
+    /// Hash of the current head block, if one was recorded for it
+    pub fn head_hash(&self) -> Option<[u8; 32]> {
+        self.versions
+            .last()
+            .filter(|version| Some(version.head) == self.head)
+            .and_then(FileVersion::head_hash)
+    }
+}
+
+/// Content written into a file inode
+#[derive(Debug, Clone)]
+pub struct FileContent {
+    pub head: Uuid,
+    pub size: u64,
+    pub block_count: usize,
+    pub compression: CompressionAlgorithm,
+    /// `compression` was applied to each chunk of the chain
+    pub compressed_per_block: bool,
+    /// Hash of the head block, which commits to the whole chain
+    pub root_hash: [u8; 32],
+    /// Content-addressed chunks (empty for chained files)
+    pub chunks: Vec<ChunkRef>,
+}
+
+/// Logical size and block count of a chain, measured when importing a flat manifest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChainStats {
+    pub size: u64,
+    pub block_count: usize,
+}
+
+/// One version in a file's history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileVersion {
+    /// Version number (starts at 1, never reused)
+    pub version: u64,
+    /// First block of this version's chain
+    pub head: Uuid,
+    /// Logical size in bytes
+    pub size: u64,
+    /// Number of blocks in the chain
+    pub block_count: usize,
+    /// Compression of the version's chain
+    pub compression: CompressionAlgorithm,
+    /// `compression` was applied to each chunk of the chain, not to the file as a whole
+    #[serde(default)]
+    pub compressed_per_block: bool,
+    /// Hex-encoded hash of the head block
+    pub root_hash: String,
+    /// Content-addressed chunks (empty for chained files)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<ChunkRef>,
+    /// User who wrote (or restored) this version
+    pub author: String,
+    /// Creation timestamp
+    pub created_at: u64,
+}
+
+impl FileVersion {
+    /// Hash of the head block (None for files migrated without one)
+    pub fn head_hash(&self) -> Option<[u8; 32]> {
+        let hash: [u8; 32] = hex::decode(&self.root_hash).ok()?.try_into().ok()?;
+        (hash != [0u8; 32]).then_some(hash)
+    }
+}
+
+/// How many old versions survive orphan cleanup
+/// A previous version is dropped once it exceeds either limit; the current version is always kept
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Maximum number of versions per file, including the current one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_versions: Option<usize>,
+    /// Maximum age of previous versions in days
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_days: Option<u64>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            keep_versions: Some(10),
+            keep_days: None,
+        }
+    }
+}
+
+/// Result of `stat`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileStat {
+    pub path: String,
+    pub node_type: NodeType,
+    pub size: u64,
+    pub block_count: usize,
+    /// Number of directory entries (0 for files)
+    pub entries: usize,
+    pub owner: String,
+    pub created_at: u64,
+    pub modified_at: u64,
+    pub head: Option<Uuid>,
+}
+
+/// Entry returned by directory listings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirEntry {
+    pub path: String,
+    pub node_type: NodeType,
+    pub size: u64,
+}
+
+/// Paths that differ between two namespaces
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+/// Inode table rooted at `ROOT_INO`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Namespace {
+    inodes: HashMap<u64, Inode>,
+    next_ino: u64,
+}
+
+impl Namespace {
+    /// Create a namespace holding only the root directory
+    pub fn new() -> Self {
+        let now = now_secs();
+        let root = Inode {
+            ino: ROOT_INO,
+            node_type: NodeType::Directory,
+            parent: ROOT_INO,
+            name: String::new(),
+            children: BTreeMap::new(),
+            head: None,
+            size: 0,
+            block_count: 0,
+            compression: CompressionAlgorithm::None,
+            compressed_per_block: false,
+            chunks: Vec::new(),
+            owner: String::new(),
+            created_at: now,
+            modified_at: now,
+            versions: Vec::new(),
+        };
+
+        let mut inodes = HashMap::new();
+        inodes.insert(ROOT_INO, root);
+        Namespace {
+            inodes,
+            next_ino: ROOT_INO + 1,
+        }
+    }
+
+    /// Build a namespace from a flat path -> head manifest
+    /// Sizes and block counts come from `chains` (keyed by head); unmeasured files keep zero
+    pub fn from_flat(
+        manifest: &HashMap<String, Uuid>,
+        chains: &HashMap<Uuid, ChainStats>,
+        owner: &str,
+        compression: CompressionAlgorithm,
+    ) -> Result<Self> {
+        let mut namespace = Self::new();
+        for (path, head) in manifest {
+            let stats = chains.get(head).copied().unwrap_or_default();
+            let content = FileContent {
+                head: *head,
+                size: stats.size,
+                block_count: stats.block_count,
+                compression,
+                compressed_per_block: false,
+                root_hash: [0u8; 32],
+                chunks: Vec::new(),
+            };
+            namespace.write_file(path, content, owner)?;
+        }
+        Ok(namespace)
+    }
+
+    /// Split a path into normalized components
+    pub fn normalize(path: &str) -> Result<Vec<String>> {
+        let mut components = Vec::new();
+        for part in path.split(['/', '\\']) {
+            match part {
+                "" | "." => continue,
+                ".." => {
+                    return Err(MSSCSError::InvalidData(format!(
+                        "Path '{}' must not contain '..'",
+                        path
+                    )))
+                }
+                name => components.push(name.to_string()),
+            }
+        }
+        Ok(components)
+    }
+
+    /// Canonical form of a path ("a/b/c", root is "")
+    pub fn canonical(path: &str) -> Result<String> {
+        Ok(Self::normalize(path)?.join("/"))
+    }
+
+    /// Look up an inode by path
+    pub fn lookup(&self, path: &str) -> Option<&Inode> {
+        let components = Self::normalize(path).ok()?;
+        let ino = self.resolve(&components)?;
+        self.inodes.get(&ino)
+    }
+
+    /// Look up a file inode, failing for missing paths and directories
+    pub fn get_file(&self, path: &str) -> Result<&Inode> {
+        let inode = self
+            .lookup(path)
+            .ok_or_else(|| MSSCSError::NotFound(format!("File '{}' not found", path)))?;
+        if inode.node_type != NodeType::File {
+            return Err(MSSCSError::InvalidData(format!("'{}' is a directory", path)));
+        }
+        Ok(inode)
+    }
+
+    /// Create a directory; with `parents` missing ancestors are created and an existing directory is accepted
+    pub fn mkdir(&mut self, path: &str, owner: &str, parents: bool) -> Result<u64> {
+        let components = Self::normalize(path)?;
+        let Some((name, ancestors)) = components.split_last() else {
+            if parents {
+                return Ok(ROOT_INO);
+            }
+            return Err(MSSCSError::InvalidData("Root directory already exists".to_string()));
+        };
+
+        let parent = if parents {
+            self.ensure_dirs(ancestors, owner)?
+        } else {
+            self.resolve_dir(ancestors, path)?
+        };
+
+        if let Some(&existing) = self.dir(parent)?.children.get(name) {
+            if parents && self.inodes[&existing].node_type == NodeType::Directory {
+                return Ok(existing);
+            }
+            return Err(MSSCSError::InvalidData(format!("'{}' already exists", path)));
+        }
+
+        Ok(self.insert_child(parent, name, NodeType::Directory, owner))
+    }
+
+    /// Remove an empty directory
+    pub fn rmdir(&mut self, path: &str) -> Result<()> {
+        let components = Self::normalize(path)?;
+        if components.is_empty() {
+            return Err(MSSCSError::InvalidData("Cannot remove the root directory".to_string()));
+        }
+
+        let ino = self
+            .resolve(&components)
+            .ok_or_else(|| MSSCSError::NotFound(format!("Directory '{}' not found", path)))?;
+        let inode = &self.inodes[&ino];
+        if inode.node_type != NodeType::Directory {
+            return Err(MSSCSError::InvalidData(format!("'{}' is not a directory", path)));
+        }
+        if !inode.children.is_empty() {
+            return Err(MSSCSError::InvalidData(format!("Directory '{}' is not empty", path)));
+        }
+
+        self.unlink(ino);
+        Ok(())
+    }
+
+    /// Write a new version of a file, creating the file and missing parent directories
+    /// Returns the head of the previous version, if any
+    pub fn write_file(&mut self, path: &str, content: FileContent, owner: &str) -> Result<Option<Uuid>> {
+        let components = Self::normalize(path)?;
+        let Some((name, ancestors)) = components.split_last() else {
+            return Err(MSSCSError::InvalidData("Cannot write to the root directory".to_string()));
+        };
+        let parent = self.ensure_dirs(ancestors, owner)?;
+
+        let ino = match self.dir(parent)?.children.get(name) {
+            Some(&existing) => {
+                if self.inodes[&existing].node_type != NodeType::File {
+                    return Err(MSSCSError::InvalidData(format!("'{}' is a directory", path)));
+                }
+                existing
+            }
+            None => self.insert_child(parent, name, NodeType::File, owner),
+        };
+
+        let inode = self.inodes.get_mut(&ino).expect("inode just resolved");
+        let previous = inode.head;
+        let version = FileVersion {
+            version: inode.versions.last().map_or(1, |v| v.version + 1),
+            head: content.head,
+            size: content.size,
+            block_count: content.block_count,
+            compression: content.compression,
+            compressed_per_block: content.compressed_per_block,
+            root_hash: hex::encode(content.root_hash),
+            chunks: content.chunks,
+            author: owner.to_string(),
+            created_at: now_secs(),
+        };
+        inode.versions.push(version.clone());
+        Self::apply_version(inode, &version);
+        Ok(previous)
+    }
+
+    /// Version history of a file, oldest first
+    pub fn versions(&self, path: &str) -> Result<Vec<FileVersion>> {
+        Ok(self.get_file(path)?.versions.clone())
+    }
+
+    /// A specific version of a file
+    pub fn version(&self, path: &str, version: u64) -> Result<FileVersion> {
+        self.get_file(path)?
+            .versions
+            .iter()
+            .find(|v| v.version == version)
+            .cloned()
+            .ok_or_else(|| MSSCSError::NotFound(format!("Version {} of '{}' not found", version, path)))
+    }
+
+    /// Make an old version current again by recording it as a new version
+    pub fn restore_version(&mut self, path: &str, version: u64, author: &str) -> Result<FileVersion> {
+        let old = self.version(path, version)?;
+        let ino = self.get_file(path)?.ino;
+
+        let inode = self.inodes.get_mut(&ino).expect("inode just resolved");
+        let restored = FileVersion {
+            version: inode.versions.last().map_or(1, |v| v.version + 1),
+            author: author.to_string(),
+            created_at: now_secs(),
+            ..old
+        };
+        inode.versions.push(restored.clone());
+        Self::apply_version(inode, &restored);
+        Ok(restored)
+    }
+
+    /// Drop previous versions that fall outside `policy`; returns how many were dropped
+    pub fn apply_retention(&mut self, policy: &RetentionPolicy) -> usize {
+        let now = now_secs();
+        let mut dropped = 0;
+
+        for inode in self.inodes.values_mut() {
+            let Some(current) = inode.versions.last().map(|v| v.version) else {
+                continue;
+            };
+            let total = inode.versions.len();
+            let mut index = 0;
+            inode.versions.retain(|v| {
+                let newer = total - 1 - index;
+                index += 1;
+                if v.version == current {
+                    return true;
+                }
+                let within_count = policy.keep_versions.is_none_or(|keep| newer < keep);
+                let within_age = policy
+                    .keep_days
+                    .is_none_or(|days| now.saturating_sub(v.created_at) <= days * SECONDS_PER_DAY);
+                within_count && within_age
+            });
+            dropped += total - inode.versions.len();
+        }
+
+        dropped
+    }
+
+    /// Heads of every retained version of every file
+    pub fn referenced_heads(&self) -> Vec<Uuid> {
+        let mut heads: Vec<Uuid> = self
+            .inodes
+            .values()
+            .filter(|inode| inode.node_type == NodeType::File)
+            .flat_map(|inode| inode.versions.iter().map(|v| v.head).chain(inode.head))
+            .collect();
+        heads.sort();
+        heads.dedup();
+        heads
+    }
+
+    /// Record new head block hashes after their chains were re-encrypted
+    /// Returns how many versions were updated
+    pub fn update_root_hashes(&mut self, hashes: &HashMap<Uuid, [u8; 32]>) -> usize {
+        let mut updated = 0;
+        for version in self.inodes.values_mut().flat_map(|inode| inode.versions.iter_mut()) {
+            if let Some(hash) = hashes.get(&version.head) {
+                version.root_hash = hex::encode(hash);
+                updated += 1;
+            }
+        }
+        updated
+    }
+
+    /// CIDs of the chunks of every retained version, once per reference
+    pub fn chunk_refs(&self) -> Vec<&str> {
+        self.inodes
+            .values()
+            .flat_map(|inode| inode.chunk_refs())
+            .collect()
+    }
+
+    fn apply_version(inode: &mut Inode, version: &FileVersion) {
+        inode.head = Some(version.head);
+        inode.size = version.size;
+        inode.block_count = version.block_count;
+        inode.compression = version.compression;
+        inode.compressed_per_block = version.compressed_per_block;
+        inode.chunks = version.chunks.clone();
+        inode.modified_at = version.created_at;
+    }
+
+    /// Remove a file and return its inode
+    pub fn remove_file(&mut self, path: &str) -> Result<Inode> {
+        let ino = self.get_file(path)?.ino;
+        Ok(self.unlink(ino))
+    }
+
+    /// Move a file or directory to a new path without touching its blocks
+    /// The destination must not exist and its parent directory must
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let src = Self::normalize(from)?;
+        let dst = Self::normalize(to)?;
+        if src.is_empty() || dst.is_empty() {
+            return Err(MSSCSError::InvalidData("Cannot rename the root directory".to_string()));
+        }
+        if dst.len() > src.len() && dst[..src.len()] == src[..] {
+            return Err(MSSCSError::InvalidData(format!(
+                "Cannot move '{}' into its own subtree",
+                from
+            )));
+        }
+
+        let ino = self
+            .resolve(&src)
+            .ok_or_else(|| MSSCSError::NotFound(format!("'{}' not found", from)))?;
+        let (name, ancestors) = dst.split_last().expect("destination is not root");
+        let new_parent = self.resolve_dir(ancestors, to)?;
+        if self.dir(new_parent)?.children.contains_key(name) {
+            return Err(MSSCSError::InvalidData(format!("'{}' already exists", to)));
+        }
+
+        let old_parent = self.inodes[&ino].parent;
+        let old_name = self.inodes[&ino].name.clone();
+        let now = now_secs();
+        if let Some(dir) = self.inodes.get_mut(&old_parent) {
+            dir.children.remove(&old_name);
+            dir.modified_at = now;
+        }
+        if let Some(dir) = self.inodes.get_mut(&new_parent) {
+            dir.children.insert(name.clone(), ino);
+            dir.modified_at = now;
+        }
+        let inode = self.inodes.get_mut(&ino).expect("inode just resolved");
+        inode.parent = new_parent;
+        inode.name = name.clone();
+        Ok(())
+    }
+
+    /// List a directory, optionally including every descendant
+    pub fn list(&self, path: &str, recursive: bool) -> Result<Vec<DirEntry>> {
+        let components = Self::normalize(path)?;
+        let ino = self.resolve_dir(&components, path)?;
+
+        let mut entries = Vec::new();
+        self.collect(ino, &components.join("/"), recursive, &mut entries);
+        Ok(entries)
+    }
+
+    /// Metadata of a file or directory
+    pub fn stat(&self, path: &str) -> Result<FileStat> {
+        let inode = self
+            .lookup(path)
+            .ok_or_else(|| MSSCSError::NotFound(format!("'{}' not found", path)))?;
+        let size = match inode.node_type {
+            NodeType::Directory => self.subtree_size(inode.ino),
+            _ => inode.size,
+        };
+
+        Ok(FileStat {
+            path: Self::canonical(path)?,
+            node_type: inode.node_type,
+            size,
+            block_count: inode.block_count,
+            entries: inode.children.len(),
+            owner: inode.owner.clone(),
+            created_at: inode.created_at,
+            modified_at: inode.modified_at,
+            head: inode.head,
+        })
+    }
+
+    /// All files as (path, inode), sorted by path
+    pub fn files(&self) -> Vec<(String, &Inode)> {
+        let mut entries = Vec::new();
+        self.collect(ROOT_INO, "", true, &mut entries);
+        entries
+            .into_iter()
+            .filter(|entry| entry.node_type == NodeType::File)
+            .filter_map(|entry| {
+                let inode = self.lookup(&entry.path)?;
+                Some((entry.path, inode))
+            })
+            .collect()
+    }
+
+    /// Flat path -> head manifest of all files
+    pub fn manifest(&self) -> HashMap<String, Uuid> {
+        self.files()
+            .into_iter()
+            .filter_map(|(path, inode)| Some((path, inode.head?)))
+            .collect()
+    }
+
+    /// Number of files in the tree
+    pub fn file_count(&self) -> usize {
+        self.inodes
+            .values()
+            .filter(|inode| inode.node_type == NodeType::File)
+            .count()
+    }
+
+    /// Merkle DAG root summarizing the whole tree
+    pub fn merkle_root(&self) -> MerkleNode {
+        self.merkle_node(ROOT_INO)
+    }
+
+    /// Copy of the tree with version history trimmed to each file's current version
+    pub fn freeze(&self) -> Namespace {
+        let mut frozen = self.clone();
+        for inode in frozen.inodes.values_mut() {
+            let keep_from = inode.versions.len().saturating_sub(1);
+            inode.versions.drain(..keep_from);
+        }
+        frozen
+    }
+
+    /// Paths added, removed or modified going from `self` to `newer`
+    /// Subtrees with identical Merkle hashes are skipped without being walked
+    pub fn diff(&self, newer: &Namespace) -> NamespaceDiff {
+        let old_cids = self.merkle_cids();
+        let new_cids = newer.merkle_cids();
+        let mut diff = NamespaceDiff::default();
+        Self::diff_dirs(self, &old_cids, ROOT_INO, newer, &new_cids, ROOT_INO, "", &mut diff);
+        diff
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn diff_dirs(
+        old: &Namespace,
+        old_cids: &HashMap<u64, ContentId>,
+        old_ino: u64,
+        new: &Namespace,
+        new_cids: &HashMap<u64, ContentId>,
+        new_ino: u64,
+        prefix: &str,
+        diff: &mut NamespaceDiff,
+    ) {
+        let old_children = &old.inodes[&old_ino].children;
+        let new_children = &new.inodes[&new_ino].children;
+
+        let mut names: Vec<&String> = old_children.keys().chain(new_children.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            let path = if prefix.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            match (old_children.get(name), new_children.get(name)) {
+                (Some(&o), None) => old.collect_subtree(o, &path, &mut diff.removed),
+                (None, Some(&n)) => new.collect_subtree(n, &path, &mut diff.added),
+                (Some(&o), Some(&n)) => {
+                    if old_cids[&o] == new_cids[&n] {
+                        continue;
+                    }
+                    let old_type = old.inodes[&o].node_type;
+                    let new_type = new.inodes[&n].node_type;
+                    if old_type != new_type {
+                        old.collect_subtree(o, &path, &mut diff.removed);
+                        new.collect_subtree(n, &path, &mut diff.added);
+                    } else if old_type == NodeType::Directory {
+                        Self::diff_dirs(old, old_cids, o, new, new_cids, n, &path, diff);
+                    } else {
+                        diff.modified.push(path);
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+    }
+
+    /// Push `path` and every path below it
+    fn collect_subtree(&self, ino: u64, path: &str, out: &mut Vec<String>) {
+        out.push(path.to_string());
+        let mut entries = Vec::new();
+        self.collect(ino, path, true, &mut entries);
+        out.extend(entries.into_iter().map(|entry| entry.path));
+    }
+
+    /// Merkle hash of every inode, computed bottom-up in one pass
+    fn merkle_cids(&self) -> HashMap<u64, ContentId> {
+        let mut cids = HashMap::new();
+        self.merkle_cid_into(ROOT_INO, &mut cids);
+        cids
+    }
+
+    fn merkle_cid_into(&self, ino: u64, cids: &mut HashMap<u64, ContentId>) -> ContentId {
+        let inode = &self.inodes[&ino];
+        let cid = match inode.node_type {
+            NodeType::Directory => {
+                let children = inode
+                    .children
+                    .iter()
+                    .map(|(name, child)| (name.clone(), self.merkle_cid_into(*child, cids)))
+                    .collect();
+                MerkleNode::directory(children).cid
+            }
+            _ => self.merkle_node(ino).cid,
+        };
+        cids.insert(ino, cid);
+        cid
+    }
+
+    fn merkle_node(&self, ino: u64) -> MerkleNode {
+        let inode = &self.inodes[&ino];
+        match inode.node_type {
+            NodeType::Directory => {
+                let children = inode
+                    .children
+                    .iter()
+                    .map(|(name, child)| (name.clone(), self.merkle_node(*child).cid))
+                    .collect();
+                MerkleNode::directory(children)
+            }
+            _ => {
+                let head = inode.head.unwrap_or_default();
+                MerkleNode::file(ContentId::from_data(head.as_bytes()), inode.size as usize)
+            }
+        }
+    }
+
+    fn resolve(&self, components: &[String]) -> Option<u64> {
+        let mut ino = ROOT_INO;
+        for name in components {
+            ino = *self.inodes.get(&ino)?.children.get(name)?;
+        }
+        Some(ino)
+    }
+
+    fn resolve_dir(&self, components: &[String], path: &str) -> Result<u64> {
+        let ino = self
+            .resolve(components)
+            .ok_or_else(|| MSSCSError::NotFound(format!("Directory '{}' not found", path)))?;
+        if self.inodes[&ino].node_type != NodeType::Directory {
+            return Err(MSSCSError::InvalidData(format!("'{}' is not a directory", path)));
+        }
+        Ok(ino)
+    }
+
+    fn dir(&self, ino: u64) -> Result<&Inode> {
+        match self.inodes.get(&ino) {
+            Some(inode) if inode.node_type == NodeType::Directory => Ok(inode),
+            _ => Err(MSSCSError::InvalidData(format!("Inode {} is not a directory", ino))),
+        }
+    }
+
+    /// Walk `components` from the root, creating missing directories
+    fn ensure_dirs(&mut self, components: &[String], owner: &str) -> Result<u64> {
+        let mut ino = ROOT_INO;
+        for name in components {
+            ino = match self.dir(ino)?.children.get(name) {
+                Some(&child) => child,
+                None => self.insert_child(ino, name, NodeType::Directory, owner),
+            };
+        }
+        self.dir(ino)?;
+        Ok(ino)
+    }
+
+    fn insert_child(&mut self, parent: u64, name: &str, node_type: NodeType, owner: &str) -> u64 {
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        let now = now_secs();
+
+        self.inodes.insert(
+            ino,
+            Inode {
+                ino,
+                node_type,
+                parent,
+                name: name.to_string(),
+                children: BTreeMap::new(),
+                head: None,
+                size: 0,
+                block_count: 0,
+                compression: CompressionAlgorithm::None,
+                compressed_per_block: false,
+                chunks: Vec::new(),
+                owner: owner.to_string(),
+                created_at: now,
+                modified_at: now,
+                versions: Vec::new(),
+            },
+        );
+        if let Some(dir) = self.inodes.get_mut(&parent) {
+            dir.children.insert(name.to_string(), ino);
+            dir.modified_at = now;
+        }
+        ino
+    }
+
+    fn unlink(&mut self, ino: u64) -> Inode {
+        let inode = self.inodes.remove(&ino).expect("unlinking a live inode");
+        if let Some(dir) = self.inodes.get_mut(&inode.parent) {
+            dir.children.remove(&inode.name);
+            dir.modified_at = now_secs();
+        }
+        inode
+    }
+
+    fn collect(&self, ino: u64, prefix: &str, recursive: bool, out: &mut Vec<DirEntry>) {
+        for (name, child) in &self.inodes[&ino].children {
+            let inode = &self.inodes[child];
+            let path = if prefix.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            out.push(DirEntry {
+                path: path.clone(),
+                node_type: inode.node_type,
+                size: inode.size,
+            });
+            if recursive && inode.node_type == NodeType::Directory {
+                self.collect(*child, &path, recursive, out);
+            }
+        }
+    }
+
+    fn subtree_size(&self, ino: u64) -> u64 {
+        self.inodes[&ino]
+            .children
+            .values()
+            .map(|child| match self.inodes[child].node_type {
+                NodeType::Directory => self.subtree_size(*child),
+                _ => self.inodes[child].size,
+            })
+            .sum()
+    }
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(size: u64) -> FileContent {
+        FileContent {
+            head: Uuid::new_v4(),
+            size,
+            block_count: 1,
+            compression: CompressionAlgorithm::None,
+            compressed_per_block: false,
+            root_hash: [7u8; 32],
+            chunks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_mkdir_and_list() {
+        let mut ns = Namespace::new();
+        ns.mkdir("docs", "alice", false).unwrap();
+        ns.mkdir("/docs/reports/2024", "alice", true).unwrap();
+        ns.write_file("docs/readme.txt", content(10), "alice").unwrap();
+        ns.write_file("docs/reports/2024/q1.pdf", content(100), "alice").unwrap();
+
+        assert!(ns.mkdir("docs", "alice", false).is_err());
+        assert!(ns.mkdir("missing/child", "alice", false).is_err());
+
+        let top: Vec<_> = ns.list("docs", false).unwrap().into_iter().map(|e| e.path).collect();
+        assert_eq!(top, vec!["docs/readme.txt", "docs/reports"]);
+
+        let all: Vec<_> = ns.list("/", true).unwrap().into_iter().map(|e| e.path).collect();
+        assert_eq!(all, vec![
+            "docs",
+            "docs/readme.txt",
+            "docs/reports",
+            "docs/reports/2024",
+            "docs/reports/2024/q1.pdf",
+        ]);
+        assert_eq!(ns.stat("docs").unwrap().size, 110);
+        assert_eq!(ns.file_count(), 2);
+    }
+
+    #[test]
+    fn test_rmdir_requires_empty() {
+        let mut ns = Namespace::new();
+        ns.write_file("a/b.txt", content(1), "alice").unwrap();
+
+        assert!(ns.rmdir("a").is_err());
+        assert!(ns.rmdir("a/b.txt").is_err());
+        ns.remove_file("a/b.txt").unwrap();
+        ns.rmdir("a").unwrap();
+        assert!(ns.lookup("a").is_none());
+        assert!(ns.rmdir("/").is_err());
+    }
+
+    #[test]
+    fn test_rename_keeps_head() {
+        let mut ns = Namespace::new();
+        let file = content(5);
+        let head = file.head;
+        ns.write_file("src/main.rs", file, "alice").unwrap();
+        ns.mkdir("dst", "alice", false).unwrap();
+
+        ns.rename("src", "dst/moved").unwrap();
+        assert!(ns.lookup("src/main.rs").is_none());
+        assert_eq!(ns.get_file("dst/moved/main.rs").unwrap().head, Some(head));
+
+        assert!(ns.rename("dst", "dst/moved/inner").is_err());
+        assert!(ns.rename("dst/moved/main.rs", "nowhere/main.rs").is_err());
+        ns.write_file("other.rs", content(1), "alice").unwrap();
+        assert!(ns.rename("other.rs", "dst/moved/main.rs").is_err());
+    }
+
+    #[test]
+    fn test_stat_and_overwrite() {
+        let mut ns = Namespace::new();
+        let first = content(5);
+        let first_head = first.head;
+        assert_eq!(ns.write_file("f.bin", first, "alice").unwrap(), None);
+        assert_eq!(ns.write_file("f.bin", content(7), "alice").unwrap(), Some(first_head));
+
+        let stat = ns.stat("/f.bin").unwrap();
+        assert_eq!(stat.path, "f.bin");
+        assert_eq!(stat.node_type, NodeType::File);
+        assert_eq!(stat.size, 7);
+        assert_eq!(stat.owner, "alice");
+        assert!(ns.write_file("f.bin/child", content(1), "alice").is_err());
+        assert!(Namespace::normalize("../etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_flat_import_and_merkle_root() {
+        let mut flat = HashMap::new();
+        let one = Uuid::new_v4();
+        flat.insert("a/one.txt".to_string(), one);
+        flat.insert("two.txt".to_string(), Uuid::new_v4());
+        let chains = HashMap::from([(one, ChainStats { size: 42, block_count: 3 })]);
+
+        let ns = Namespace::from_flat(&flat, &chains, "alice", CompressionAlgorithm::Huffman).unwrap();
+        assert_eq!(ns.manifest(), flat);
+        let stat = ns.stat("a/one.txt").unwrap();
+        assert_eq!((stat.size, stat.block_count), (42, 3));
+        assert_eq!(ns.stat("two.txt").unwrap().size, 0);
+
+        let json = serde_json::to_vec(&ns).unwrap();
+        let restored: Namespace = serde_json::from_slice(&json).unwrap();
+        assert_eq!(restored.merkle_root().cid, ns.merkle_root().cid);
+
+        let mut moved = restored.clone();
+        moved.rename("two.txt", "a/two.txt").unwrap();
+        assert_ne!(moved.merkle_root().cid, ns.merkle_root().cid);
+    }
+
+    #[test]
+    fn test_versions_and_restore() {
+        let mut ns = Namespace::new();
+        let first = content(5);
+        let first_head = first.head;
+        ns.write_file("doc.txt", first, "alice").unwrap();
+        ns.write_file("doc.txt", content(9), "bob").unwrap();
+
+        let versions = ns.versions("doc.txt").unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].author, "alice");
+        assert_eq!(versions[1].author, "bob");
+        assert_eq!(versions[1].version, 2);
+        assert_eq!(versions[0].root_hash, hex::encode([7u8; 32]));
+
+        let restored = ns.restore_version("doc.txt", 1, "carol").unwrap();
+        assert_eq!(restored.version, 3);
+        assert_eq!(restored.head, first_head);
+        assert_eq!(restored.author, "carol");
+
+        let stat = ns.stat("doc.txt").unwrap();
+        assert_eq!(stat.head, Some(first_head));
+        assert_eq!(stat.size, 5);
+        assert!(ns.restore_version("doc.txt", 42, "carol").is_err());
+    }
+
+    #[test]
+    fn test_retention_policy() {
+        let mut ns = Namespace::new();
+        for size in 1..=5 {
+            ns.write_file("log.txt", content(size), "alice").unwrap();
+        }
+        ns.write_file("single.txt", content(1), "alice").unwrap();
+
+        let policy = RetentionPolicy { keep_versions: Some(3), keep_days: None };
+        assert_eq!(ns.apply_retention(&policy), 2);
+        let kept: Vec<u64> = ns.versions("log.txt").unwrap().iter().map(|v| v.version).collect();
+        assert_eq!(kept, vec![3, 4, 5]);
+        assert_eq!(ns.referenced_heads().len(), 4);
+
+        // Age limit drops every previous version but never the current one
+        for inode in ns.inodes.values_mut() {
+            for v in inode.versions.iter_mut() {
+                v.created_at -= 3 * SECONDS_PER_DAY;
+            }
+        }
+        let policy = RetentionPolicy { keep_versions: None, keep_days: Some(2) };
+        assert_eq!(ns.apply_retention(&policy), 2);
+        assert_eq!(ns.versions("log.txt").unwrap().len(), 1);
+        assert_eq!(ns.versions("single.txt").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_freeze_and_diff() {
+        let mut ns = Namespace::new();
+        ns.write_file("keep/same.txt", content(1), "alice").unwrap();
+        ns.write_file("keep/changed.txt", content(2), "alice").unwrap();
+        ns.write_file("keep/changed.txt", content(3), "alice").unwrap();
+        ns.write_file("old/gone.txt", content(4), "alice").unwrap();
+
+        let frozen = ns.freeze();
+        assert_eq!(frozen.versions("keep/changed.txt").unwrap().len(), 1);
+        assert_eq!(frozen.merkle_root().cid, ns.merkle_root().cid);
+        assert_eq!(frozen.diff(&ns), NamespaceDiff::default());
+
+        ns.write_file("keep/changed.txt", content(5), "alice").unwrap();
+        ns.remove_file("old/gone.txt").unwrap();
+        ns.rmdir("old").unwrap();
+        ns.write_file("new/added.txt", content(6), "alice").unwrap();
+
+        let diff = frozen.diff(&ns);
+        assert_eq!(diff.added, vec!["new", "new/added.txt"]);
+        assert_eq!(diff.removed, vec!["old", "old/gone.txt"]);
+        assert_eq!(diff.modified, vec!["keep/changed.txt"]);
+    }
+}
@@ -1,10 +1,16 @@
 // P2P API - REST API for decentralized storage
+use crate::api::{
+    MkdirRequest, RenameRequest, RestoreRequest, SnapshotDiffQuery, SnapshotRequest,
+    SnapshotsResponse, TreeQuery, TreeResponse, VersionQuery, VersionsResponse,
+};
 use crate::error::{MSSCSError, Result};
-use crate::p2p_vfs::{P2PVirtualFileSystem, StorageStats};
+use crate::p2p_vfs::P2PVirtualFileSystem;
+use crate::peer_manager::PeerRecord;
+use crate::repair::RepairDaemon;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
-    response::{IntoResponse, Json, Response},
+    response::{IntoResponse, Json},
     routing::{delete, get, post},
     Router,
 };
@@ -17,6 +23,7 @@ use tower_http::cors::{Any, CorsLayer};
 #[derive(Clone)]
 pub struct P2PAppState {
     pub vfs: Arc<P2PVirtualFileSystem>,
+    pub repair: Arc<RepairDaemon>,
 }
 
 /// Upload file request
@@ -53,6 +60,12 @@ pub struct StatsResponse {
     pub connected_peers: usize,
 }
 
+/// Peer table response
+#[derive(Debug, Serialize)]
+pub struct PeersResponse {
+    pub peers: Vec<PeerRecord>,
+}
+
 /// Create P2P API router
 pub fn create_p2p_router(state: P2PAppState) -> Router {
     let cors = CorsLayer::new()
@@ -65,7 +78,25 @@ pub fn create_p2p_router(state: P2PAppState) -> Router {
         .route("/download/:path", get(download_handler))
         .route("/delete/:path", delete(delete_handler))
         .route("/files", get(list_handler))
+        .route("/dirs", post(mkdir_handler))
+        .route("/dirs/*path", delete(rmdir_handler))
+        .route("/rename", post(rename_handler))
+        .route("/tree", get(tree_handler))
+        .route("/stat/*path", get(stat_handler))
+        .route("/versions/*path", get(list_versions_handler))
+        .route("/version", get(download_version_handler))
+        .route("/restore", post(restore_version_handler))
+        .route("/snapshots", post(create_snapshot_handler))
+        .route("/snapshots", get(list_snapshots_handler))
+        .route("/snapshots/:name", delete(delete_snapshot_handler))
+        .route("/snapshots/:name/tree", get(snapshot_tree_handler))
+        .route("/snapshots/:name/files/*path", get(download_snapshot_file_handler))
+        .route("/snapshot-diff", get(snapshot_diff_handler))
         .route("/stats", get(stats_handler))
+        .route("/peers", get(peers_handler))
+        .route("/consistency", get(consistency_handler))
+        .route("/repair", get(repair_status_handler))
+        .route("/repair", post(repair_now_handler))
         .route("/health", get(health_handler))
         .layer(cors)
         .with_state(state)
@@ -126,6 +157,165 @@ async fn list_handler(
     Ok(Json(ListResponse { files }))
 }
 
+/// Create directory handler
+async fn mkdir_handler(
+    State(state): State<P2PAppState>,
+    Json(req): Json<MkdirRequest>,
+) -> Result<impl IntoResponse> {
+    let path = PathBuf::from(&req.path);
+    state.vfs.mkdir(&path).await?;
+    let stat = state.vfs.stat(&path).await?;
+    
+    Ok((StatusCode::CREATED, Json(stat)))
+}
+
+/// Remove directory handler
+async fn rmdir_handler(
+    State(state): State<P2PAppState>,
+    Path(dir_path): Path<String>,
+) -> Result<impl IntoResponse> {
+    state.vfs.rmdir(&PathBuf::from(&dir_path)).await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Rename / move handler
+async fn rename_handler(
+    State(state): State<P2PAppState>,
+    Json(req): Json<RenameRequest>,
+) -> Result<impl IntoResponse> {
+    let to = PathBuf::from(&req.to);
+    state.vfs.rename(&PathBuf::from(&req.from), &to).await?;
+    let stat = state.vfs.stat(&to).await?;
+    
+    Ok(Json(stat))
+}
+
+/// Directory listing handler
+async fn tree_handler(
+    State(state): State<P2PAppState>,
+    Query(query): Query<TreeQuery>,
+) -> Result<impl IntoResponse> {
+    let entries = state.vfs.list_dir(&PathBuf::from(&query.path), query.recursive).await?;
+    
+    Ok(Json(TreeResponse {
+        path: query.path,
+        entries,
+    }))
+}
+
+/// Stat handler
+async fn stat_handler(
+    State(state): State<P2PAppState>,
+    Path(file_path): Path<String>,
+) -> Result<impl IntoResponse> {
+    let stat = state.vfs.stat(&PathBuf::from(&file_path)).await?;
+    
+    Ok(Json(stat))
+}
+
+/// List versions handler
+async fn list_versions_handler(
+    State(state): State<P2PAppState>,
+    Path(file_path): Path<String>,
+) -> Result<impl IntoResponse> {
+    let versions = state.vfs.list_versions(&PathBuf::from(&file_path)).await?;
+    
+    Ok(Json(VersionsResponse {
+        path: file_path,
+        versions,
+    }))
+}
+
+/// Download version handler
+async fn download_version_handler(
+    State(state): State<P2PAppState>,
+    Query(query): Query<VersionQuery>,
+) -> Result<impl IntoResponse> {
+    use base64::{Engine as _, engine::general_purpose};
+    
+    let content = state.vfs.download_version(&PathBuf::from(&query.path), query.version).await?;
+    let encoded = general_purpose::STANDARD.encode(&content);
+    
+    Ok(Json(DownloadResponse { content: encoded }))
+}
+
+/// Restore version handler
+async fn restore_version_handler(
+    State(state): State<P2PAppState>,
+    Json(req): Json<RestoreRequest>,
+) -> Result<impl IntoResponse> {
+    let restored = state.vfs.restore_version(&PathBuf::from(&req.path), req.version).await?;
+    
+    Ok(Json(restored))
+}
+
+/// Create snapshot handler
+async fn create_snapshot_handler(
+    State(state): State<P2PAppState>,
+    Json(req): Json<SnapshotRequest>,
+) -> Result<impl IntoResponse> {
+    let info = state.vfs.create_snapshot(&req.name).await?;
+    
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+/// List snapshots handler
+async fn list_snapshots_handler(
+    State(state): State<P2PAppState>,
+) -> Result<impl IntoResponse> {
+    let snapshots = state.vfs.list_snapshots().await;
+    Ok(Json(SnapshotsResponse { snapshots }))
+}
+
+/// Delete snapshot handler
+async fn delete_snapshot_handler(
+    State(state): State<P2PAppState>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse> {
+    state.vfs.delete_snapshot(&name).await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Snapshot directory listing handler
+async fn snapshot_tree_handler(
+    State(state): State<P2PAppState>,
+    Path(name): Path<String>,
+    Query(query): Query<TreeQuery>,
+) -> Result<impl IntoResponse> {
+    let entries = state.vfs
+        .snapshot_list_dir(&name, &PathBuf::from(&query.path), query.recursive)
+        .await?;
+    
+    Ok(Json(TreeResponse {
+        path: query.path,
+        entries,
+    }))
+}
+
+/// Download file from snapshot handler
+async fn download_snapshot_file_handler(
+    State(state): State<P2PAppState>,
+    Path((name, file_path)): Path<(String, String)>,
+) -> Result<impl IntoResponse> {
+    use base64::{Engine as _, engine::general_purpose};
+    
+    let content = state.vfs.download_snapshot_file(&name, &PathBuf::from(&file_path)).await?;
+    let encoded = general_purpose::STANDARD.encode(&content);
+    
+    Ok(Json(DownloadResponse { content: encoded }))
+}
+
+/// Snapshot diff handler
+async fn snapshot_diff_handler(
+    State(state): State<P2PAppState>,
+    Query(query): Query<SnapshotDiffQuery>,
+) -> Result<impl IntoResponse> {
+    let diff = state.vfs.diff_snapshots(&query.from, query.to.as_deref()).await?;
+    Ok(Json(diff))
+}
+
 /// Stats handler
 async fn stats_handler(
     State(state): State<P2PAppState>,
@@ -138,6 +328,37 @@ async fn stats_handler(
     }))
 }
 
+/// Peer table handler
+async fn peers_handler(
+    State(state): State<P2PAppState>,
+) -> Result<impl IntoResponse> {
+    let peers = state.vfs.peer_table().await?;
+    Ok(Json(PeersResponse { peers }))
+}
+
+/// Consistency check handler
+async fn consistency_handler(
+    State(state): State<P2PAppState>,
+) -> Result<impl IntoResponse> {
+    let report = state.vfs.check_consistency().await?;
+    Ok(Json(report))
+}
+
+/// Repair metrics handler
+async fn repair_status_handler(
+    State(state): State<P2PAppState>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(state.repair.metrics().await))
+}
+
+/// Start a repair pass in the background; its report shows up in GET /repair
+async fn repair_now_handler(
+    State(state): State<P2PAppState>,
+) -> impl IntoResponse {
+    state.repair.wake();
+    StatusCode::ACCEPTED
+}
+
 /// Health check handler
 async fn health_handler() -> impl IntoResponse {
     Json(serde_json::json!({
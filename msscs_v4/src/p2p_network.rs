@@ -1,19 +1,33 @@
 // P2P Network module - Real libp2p Kademlia DHT implementation
 use crate::block::DataBlock;
+use crate::content_addressing::ContentId;
+use crate::persistence::{chunk_key, CHUNK_KEY_PREFIX};
+use crate::block_store::BlockStore;
+use crate::block_transfer::{self, BlockAssembler, BlockRangeRequest, BlockTransferCodec, BlockTransferResponse};
 use crate::error::MSSCSError;
+use crate::kad_store::{PersistentRecordStore, RecordStoreConfig};
+use crate::shard_store::{ShardStore, ShardStoreConfig};
+use crate::peer_manager::{PeerEvent, PeerLimits, PeerManager, PeerRecord};
+use crate::private_network::{self, PrivateNetworkConfig};
+use crate::protocol::{self, features, LegacyCodec, LegacyRequest, LegacyResponse, MetaCodec, NodeInfo, NodeRole, PeerCapabilities};
+use crate::key_rotation::{RotationStatement, ROTATION_RECORD_PREFIX};
+use crate::recovery::{RecoveryRequest, RecoveryStatus, RecoveryVault, SealedShare};
+#[cfg(any(test, feature = "simulation"))]
+use crate::simulation::SimNetwork;
 use futures::prelude::*;
 use libp2p::{
     core::Multiaddr,
     identity,
-    kad::{store::MemoryStore, BootstrapOk, QueryId, Quorum, Record, RecordKey},
+    kad::{BootstrapOk, GetProvidersOk, GetRecordOk, QueryId, Quorum, Record, RecordKey},
     mdns,
     noise,
     request_response::{self, ProtocolSupport},
-    swarm::{NetworkBehaviour, SwarmEvent},
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour, SwarmEvent},
     tcp, yamux, PeerId, Swarm, SwarmBuilder,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::{mpsc, RwLock};
 use tracing::{debug, info, warn};
@@ -24,30 +38,172 @@ use uuid::Uuid;
 pub struct P2PConfig {
     pub listen_port: u16,
     pub bootstrap_peers: Vec<Multiaddr>,
+    /// Established connections allowed in total
     pub max_peers: usize,
     pub replication_factor: usize,
     pub enable_mdns: bool,
     pub enable_relay: bool,
     pub enable_autonat: bool,
+    /// How often locally held blocks are re-announced as provider records
+    pub provider_reannounce_interval: std::time::Duration,
+    /// DHT record store (set `dir` to keep records and provider entries across restarts)
+    pub record_store: RecordStoreConfig,
+    /// Shards held for other peers: location and quotas (total capped by `storage_capacity`)
+    pub shard_store: ShardStoreConfig,
+    /// Role advertised in the handshake
+    pub role: NodeRole,
+    /// Bytes offered to the network, advertised in the handshake (0 = unspecified)
+    pub storage_capacity: u64,
+    /// Node key file, created on first start (None = new peer id on every start)
+    pub key_file: Option<PathBuf>,
+    /// Inbound/outbound connection limits and ban policy
+    pub peer_limits: PeerLimits,
+    /// Pre-shared key swarm (None = public network)
+    pub private_network: Option<PrivateNetworkConfig>,
+    /// Act as circuit relay and AutoNAT server for NATed peers (None = client only)
+    pub relay_server: Option<RelayServerConfig>,
+    /// Publicly reachable addresses advertised through identify
+    pub external_addresses: Vec<Multiaddr>,
+    /// Run on an in-process simulated network instead of TCP/QUIC (tests)
+    #[cfg(any(test, feature = "simulation"))]
+    pub simulation: Option<SimNetwork>,
+    /// Hold social recovery shares for other identities (None = refuse them)
+    pub recovery_vault: Option<RecoveryVault>,
+    /// Block store whose blocks this node serves to peers (the VFS's; None = replicas only)
+    pub block_store: Option<Arc<dyn BlockStore>>,
+    /// Replicas pulled from peers: location and quotas (total capped by `storage_capacity`)
+    pub replica_store: ShardStoreConfig,
+}
+
+/// Limits of the circuit relay server role
+#[derive(Debug, Clone)]
+pub struct RelayServerConfig {
+    pub max_reservations: usize,
+    pub max_reservations_per_peer: usize,
+    pub reservation_duration: std::time::Duration,
+    pub max_circuits: usize,
+    pub max_circuits_per_peer: usize,
+    /// Relayed connections are meant for hole punching; they close after this long
+    pub max_circuit_duration: std::time::Duration,
+    /// ... or after this many bytes in each direction
+    pub max_circuit_bytes: u64,
+}
+
+impl Default for RelayServerConfig {
+    fn default() -> Self {
+        Self {
+            max_reservations: 1024,
+            max_reservations_per_peer: 4,
+            reservation_duration: std::time::Duration::from_secs(60 * 60),
+            max_circuits: 512,
+            max_circuits_per_peer: 4,
+            max_circuit_duration: std::time::Duration::from_secs(2 * 60),
+            max_circuit_bytes: 1 << 20,
+        }
+    }
+}
+
+impl RelayServerConfig {
+    fn relay_config(&self) -> libp2p::relay::Config {
+        libp2p::relay::Config {
+            max_reservations: self.max_reservations,
+            max_reservations_per_peer: self.max_reservations_per_peer,
+            reservation_duration: self.reservation_duration,
+            max_circuits: self.max_circuits,
+            max_circuits_per_peer: self.max_circuits_per_peer,
+            max_circuit_duration: self.max_circuit_duration,
+            max_circuit_bytes: self.max_circuit_bytes,
+            ..Default::default()
+        }
+    }
 }
 
 impl Default for P2PConfig {
     fn default() -> Self {
         Self {
             listen_port: 0, // Random port
-            bootstrap_peers: Self::default_bootstrap_peers(),
+            // Joining the public DHT is opt-in (see `default_bootstrap_peers`)
+            bootstrap_peers: Vec::new(),
             max_peers: 50,
             replication_factor: 3,
             enable_mdns: true,
             enable_relay: true,
             enable_autonat: true,
+            provider_reannounce_interval: std::time::Duration::from_secs(60 * 60),
+            record_store: RecordStoreConfig::default(),
+            shard_store: ShardStoreConfig::default(),
+            role: NodeRole::Full,
+            storage_capacity: 0,
+            key_file: None,
+            peer_limits: PeerLimits::default(),
+            private_network: None,
+            relay_server: None,
+            external_addresses: Vec::new(),
+            #[cfg(any(test, feature = "simulation"))]
+            simulation: None,
+            recovery_vault: None,
+            block_store: None,
+            replica_store: ShardStoreConfig {
+                max_shard_bytes: block_transfer::MAX_BLOCK_BYTES,
+                ..Default::default()
+            },
         }
     }
 }
 
 impl P2PConfig {
+    /// Parse a peer address given either as a multiaddr or as the `host:port` form used
+    /// by the retired TCP transport
+    pub fn parse_peer_address(addr: &str) -> Option<Multiaddr> {
+        let addr = addr.trim();
+        if addr.starts_with('/') {
+            return addr.parse().ok();
+        }
+        if let Ok(socket) = addr.parse::<std::net::SocketAddr>() {
+            let ip = match socket.ip() {
+                std::net::IpAddr::V4(ip) => format!("/ip4/{}", ip),
+                std::net::IpAddr::V6(ip) => format!("/ip6/{}", ip),
+            };
+            return format!("{}/tcp/{}", ip, socket.port()).parse().ok();
+        }
+        let (host, port) = addr.rsplit_once(':')?;
+        let port: u16 = port.parse().ok()?;
+        format!("/dns/{}/tcp/{}", host, port).parse().ok()
+    }
+
+    /// Bootstrap peers to dial; private and simulated swarms never dial the public IPFS nodes
+    pub fn bootstrap_addresses(&self) -> Vec<Multiaddr> {
+        if self.private_network.is_none() && !self.is_simulated() {
+            return self.bootstrap_peers.clone();
+        }
+        let public = Self::default_bootstrap_peers();
+        self.bootstrap_peers.iter()
+            .filter(|addr| !public.contains(addr))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the node runs on a simulated network
+    fn is_simulated(&self) -> bool {
+        #[cfg(any(test, feature = "simulation"))]
+        return self.simulation.is_some();
+        #[cfg(not(any(test, feature = "simulation")))]
+        return false;
+    }
+
+    /// Private swarm settings with the bootstrap peers added to a non-empty allow-list
+    fn private_membership(&self) -> Option<PrivateNetworkConfig> {
+        let mut private = self.private_network.clone()?;
+        if !private.allowed_peers.is_empty() || !private.allowed_identities.is_empty() {
+            let bootstrap = self.bootstrap_addresses();
+            private.allowed_peers.extend(bootstrap.iter().filter_map(|addr| private_network::parse_peer_id(&addr.to_string())));
+        }
+        Some(private)
+    }
+
     /// Get default public bootstrap peers for internet-wide connectivity
-    /// These are well-known IPFS bootstrap nodes that act as entry points to the global DHT
+    /// These are well-known IPFS bootstrap nodes that act as entry points to the global DHT;
+    /// nodes only use them when they are added to `bootstrap_peers` explicitly
     pub fn default_bootstrap_peers() -> Vec<Multiaddr> {
         vec![
             // IPFS public bootstrap nodes (reliable and well-maintained)
@@ -82,6 +238,8 @@ pub enum P2PEvent {
     PeerDisconnected(PeerId),
     BlockReceived { peer: PeerId, block: DataBlock },
     BlockRequested { peer: PeerId, block_id: Uuid },
+    /// Handshake with a peer finished (or the peer predates it)
+    PeerIdentified { peer: PeerId, capabilities: PeerCapabilities },
     BootstrapComplete,
     Error(String),
 }
@@ -90,22 +248,35 @@ pub enum P2PEvent {
 #[derive(NetworkBehaviour)]
 #[behaviour(to_swarm = "P2PBehaviourEvent")]
 pub struct P2PBehaviour {
-    kademlia: libp2p::kad::Behaviour<MemoryStore>,
+    kademlia: libp2p::kad::Behaviour<PersistentRecordStore>,
     request_response: libp2p::request_response::Behaviour<P2PCodec>,
-    mdns: mdns::tokio::Behaviour,
+    block_transfer: libp2p::request_response::Behaviour<BlockTransferCodec>,
+    meta: libp2p::request_response::Behaviour<MetaCodec>,
+    legacy: libp2p::request_response::Behaviour<LegacyCodec>,
+    mdns: Toggle<mdns::tokio::Behaviour>,
     relay_client: libp2p::relay::client::Behaviour,
     autonat: libp2p::autonat::Behaviour,
     dcutr: libp2p::dcutr::Behaviour,
+    relay_server: Toggle<libp2p::relay::Behaviour>,
+    identify: libp2p::identify::Behaviour,
+    connection_limits: libp2p::connection_limits::Behaviour,
+    blocked_peers: libp2p::allow_block_list::Behaviour<libp2p::allow_block_list::BlockedPeers>,
+    allowed_peers: Toggle<libp2p::allow_block_list::Behaviour<libp2p::allow_block_list::AllowedPeers>>,
 }
 
 #[derive(Debug)]
 pub enum P2PBehaviourEvent {
     Kademlia(libp2p::kad::Event),
     RequestResponse(libp2p::request_response::Event<P2PRequest, P2PResponse>),
+    BlockTransfer(libp2p::request_response::Event<BlockRangeRequest, BlockTransferResponse>),
+    Meta(libp2p::request_response::Event<NodeInfo, NodeInfo>),
+    Legacy(libp2p::request_response::Event<LegacyRequest, LegacyResponse>),
     Mdns(mdns::Event),
     RelayClient(libp2p::relay::client::Event),
     Autonat(libp2p::autonat::Event),
     Dcutr(libp2p::dcutr::Event),
+    RelayServer(libp2p::relay::Event),
+    Identify(libp2p::identify::Event),
 }
 
 impl From<libp2p::kad::Event> for P2PBehaviourEvent {
@@ -120,6 +291,24 @@ impl From<libp2p::request_response::Event<P2PRequest, P2PResponse>> for P2PBehav
     }
 }
 
+impl From<libp2p::request_response::Event<BlockRangeRequest, BlockTransferResponse>> for P2PBehaviourEvent {
+    fn from(event: libp2p::request_response::Event<BlockRangeRequest, BlockTransferResponse>) -> Self {
+        P2PBehaviourEvent::BlockTransfer(event)
+    }
+}
+
+impl From<libp2p::request_response::Event<NodeInfo, NodeInfo>> for P2PBehaviourEvent {
+    fn from(event: libp2p::request_response::Event<NodeInfo, NodeInfo>) -> Self {
+        P2PBehaviourEvent::Meta(event)
+    }
+}
+
+impl From<libp2p::request_response::Event<LegacyRequest, LegacyResponse>> for P2PBehaviourEvent {
+    fn from(event: libp2p::request_response::Event<LegacyRequest, LegacyResponse>) -> Self {
+        P2PBehaviourEvent::Legacy(event)
+    }
+}
+
 impl From<mdns::Event> for P2PBehaviourEvent {
     fn from(event: mdns::Event) -> Self {
         P2PBehaviourEvent::Mdns(event)
@@ -144,16 +333,60 @@ impl From<libp2p::dcutr::Event> for P2PBehaviourEvent {
     }
 }
 
+impl From<libp2p::relay::Event> for P2PBehaviourEvent {
+    fn from(event: libp2p::relay::Event) -> Self {
+        P2PBehaviourEvent::RelayServer(event)
+    }
+}
+
+impl From<libp2p::identify::Event> for P2PBehaviourEvent {
+    fn from(event: libp2p::identify::Event) -> Self {
+        P2PBehaviourEvent::Identify(event)
+    }
+}
+
+// Connection limits and the allow/block lists never emit events
+impl From<void::Void> for P2PBehaviourEvent {
+    fn from(event: void::Void) -> Self {
+        void::unreachable(event)
+    }
+}
+
 /// P2P Request/Response protocol codec
+/// Control and shard messages; block bytes travel over `protocol::BLOCKS_PROTOCOL`
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum P2PRequest {
-    GetBlock { id: Uuid },
+    /// Hold an erasure-coded shard on behalf of the sender
+    StoreShard { id: String, data: Vec<u8> },
+    /// Return a shard previously stored with `StoreShard`
+    GetShard { id: String },
+    /// Ask whether a shard is still held (audit without transferring it)
+    HasShard { id: String },
+    /// Ask the receiver to pull a block from the sender and keep a replica; the pulled
+    /// block must have `hash`
+    ReplicateBlock { id: Uuid, hash: [u8; 32] },
+    /// Return the sealed deduplicated chunk with content id `cid`
+    GetChunk { cid: String },
+    /// Ask the receiver to pull a chunk from the sender and keep a replica
+    ReplicateChunk { cid: String },
+    /// Hold a recovery share sealed to the receiver's identity
+    StoreRecoveryShare { share: Box<SealedShare> },
+    /// Ask for the receiver's share of an identity (polled until answered)
+    RequestRecovery { request: RecoveryRequest },
     Ping,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum P2PResponse {
-    Block { data: Option<DataBlock> },
+    ShardStored,
+    /// The shard is too large, over quota or held for another peer
+    ShardRefused { reason: String },
+    Shard { data: Option<Vec<u8>> },
+    HasShard { present: bool },
+    ReplicateAccepted { accepted: bool },
+    Chunk { data: Option<Vec<u8>> },
+    RecoveryShareStored { accepted: bool },
+    Recovery { status: RecoveryStatus },
     Pong,
 }
 
@@ -171,85 +404,395 @@ impl request_response::Codec for P2PCodec {
         T: futures::AsyncRead + Unpin + Send
     {
         // Simple protocol: length-prefixed JSON
-        use futures::AsyncReadExt;
-        let mut len_bytes = [0u8; 4];
-        io.read_exact(&mut len_bytes).await?;
-        let len = u32::from_be_bytes(len_bytes) as usize;
-
-        let mut buf = vec![0u8; len];
-        io.read_exact(&mut buf).await?;
-
-        serde_json::from_slice(&buf)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        protocol::read_json(io, MAX_CONTROL_MESSAGE_BYTES).await
     }
 
     async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Response>
     where
         T: futures::AsyncRead + Unpin + Send
     {
-        use futures::AsyncReadExt;
-        let mut len_bytes = [0u8; 4];
-        io.read_exact(&mut len_bytes).await?;
-        let len = u32::from_be_bytes(len_bytes) as usize;
-
-        let mut buf = vec![0u8; len];
-        io.read_exact(&mut buf).await?;
-
-        serde_json::from_slice(&buf)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        protocol::read_json(io, MAX_CONTROL_MESSAGE_BYTES).await
     }
 
     async fn write_request<T>(&mut self, _: &Self::Protocol, io: &mut T, req: Self::Request) -> std::io::Result<()>
     where
         T: futures::AsyncWrite + Unpin + Send
     {
-        use futures::AsyncWriteExt;
-        let data = serde_json::to_vec(&req)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-        io.write_all(&(data.len() as u32).to_be_bytes()).await?;
-        io.write_all(&data).await?;
-        io.flush().await?;
-        Ok(())
+        protocol::write_json(io, &req).await
     }
 
     async fn write_response<T>(&mut self, _: &Self::Protocol, io: &mut T, res: Self::Response) -> std::io::Result<()>
     where
         T: futures::AsyncWrite + Unpin + Send
     {
-        use futures::AsyncWriteExt;
-        let data = serde_json::to_vec(&res)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-        io.write_all(&(data.len() as u32).to_be_bytes()).await?;
-        io.write_all(&data).await?;
-        io.flush().await?;
-        Ok(())
+        protocol::write_json(io, &res).await
     }
 }
 
+/// Largest JSON message accepted on the control protocol
+const MAX_CONTROL_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
 /// Commands that can be sent to the P2P node
 pub enum P2PNodeCommand {
     GetConnectedPeers(tokio::sync::oneshot::Sender<Vec<PeerId>>),
+    /// Keep a replica of a block and announce this node as its provider
     StoreBlock {
         block_id: String,
         data: Vec<u8>,
         reply: tokio::sync::oneshot::Sender<std::result::Result<(), String>>,
     },
+    /// Announce this node as provider of a block already in its block store
+    ProvideBlock { block_id: Uuid },
+    /// Announce this node as provider of a chunk already in its block store
+    ProvideChunk { cid: String },
+    /// Fetch a sealed chunk locally or from one of its providers; copies that do not
+    /// hash to `cid` are refused
+    GetChunk {
+        cid: String,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<Vec<u8>, String>>,
+    },
+    /// Fetch a chunk from one specific peer (None = not held there)
+    FetchChunk {
+        peer: PeerId,
+        cid: String,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<Option<Vec<u8>>, String>>,
+    },
+    /// Announce a chunk of the local block store and push replicas to up to `replicas`
+    /// peers; replies with the number of peers that accepted
+    ReplicateChunk {
+        cid: String,
+        replicas: usize,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<usize, String>>,
+    },
+    /// Fetch a block locally or from one of its providers
     GetBlock {
         block_id: String,
+        /// Hash the block must have; copies that differ are refused
+        expected_hash: Option<[u8; 32]>,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<Vec<u8>, String>>,
+    },
+    /// Store a shard on one specific peer
+    PutShard {
+        peer: PeerId,
+        shard_id: String,
+        data: Vec<u8>,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<(), String>>,
+    },
+    /// Fetch a shard from one specific peer
+    FetchShard {
+        peer: PeerId,
+        shard_id: String,
         reply: tokio::sync::oneshot::Sender<std::result::Result<Vec<u8>, String>>,
     },
+    /// Check whether a specific peer still holds a shard
+    ProbeShard {
+        peer: PeerId,
+        shard_id: String,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<bool, String>>,
+    },
+    /// Hand a recovery share to the contact running `peer`
+    SendRecoveryShare {
+        peer: PeerId,
+        share: Box<SealedShare>,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<(), String>>,
+    },
+    /// Ask `peer` for its share of the identity named in `request`
+    RequestRecovery {
+        peer: PeerId,
+        request: RecoveryRequest,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<RecoveryStatus, String>>,
+    },
+    /// Publish a signed value record in the DHT (also kept and republished locally)
+    PutRecord {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<(), String>>,
+    },
+    /// Every distinct value the DHT holds under `key`
+    GetRecord {
+        key: Vec<u8>,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<Vec<Vec<u8>>, String>>,
+    },
+    /// Fetch one frame of a block from one specific peer
+    FetchBlockRange {
+        peer: PeerId,
+        request: BlockRangeRequest,
+        reply: PendingTransfer,
+    },
+    /// Announce a block of the local block store and push replicas to up to `replicas`
+    /// peers; replies with the number of peers that accepted
+    ReplicateBlock {
+        block_id: Uuid,
+        replicas: usize,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<usize, String>>,
+    },
+    /// Dial a peer and remember its address
+    AddPeer {
+        addr: Multiaddr,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<(), String>>,
+    },
+    /// Handshake results of the connected peers
+    GetPeerCapabilities(tokio::sync::oneshot::Sender<HashMap<PeerId, PeerCapabilities>>),
+    /// Score and accounting of every known peer, best first
+    GetPeerTable(tokio::sync::oneshot::Sender<Vec<PeerRecord>>),
+    /// Peers in the Kademlia routing table
+    GetRoutingTable(tokio::sync::oneshot::Sender<Vec<PeerId>>),
+    /// Feed an observation about a peer into its score
+    ReportPeer { peer: PeerId, event: PeerEvent },
+    /// Disconnect a peer and refuse it for the configured ban duration
+    BanPeer { peer: PeerId },
+    /// Lift a ban early
+    UnbanPeer {
+        peer: PeerId,
+        reply: tokio::sync::oneshot::Sender<bool>,
+    },
+}
+
+/// Whether a value record pushed by a peer may be stored
+/// Identity rotation statements must be correctly signed for their key; other keys are open
+fn accept_record(record: &Record) -> bool {
+    if !record.key.as_ref().starts_with(ROTATION_RECORD_PREFIX.as_bytes()) {
+        return true;
+    }
+    RotationStatement::from_record(record.key.as_ref(), &record.value).is_ok()
+}
+
+/// Load the node key from `path`, generating and saving one on first use
+fn load_or_create_keypair(path: &Path) -> std::result::Result<identity::Keypair, MSSCSError> {
+    if path.exists() {
+        let bytes = std::fs::read(path)?;
+        return identity::Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|e| MSSCSError::Crypto(format!("Invalid node key {:?}: {}", path, e)));
+    }
+
+    let keypair = identity::Keypair::generate_ed25519();
+    let bytes = keypair.to_protobuf_encoding()
+        .map_err(|e| MSSCSError::Crypto(format!("Failed to encode node key: {}", e)))?;
+    write_key_file(path, &bytes)?;
+    info!("🔑 Generated node key at {:?}", path);
+    Ok(keypair)
+}
+
+/// Write a secret to `path`, readable only by its owner from the moment the file exists
+pub(crate) fn write_key_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files; an existing one is tightened before it gets the key
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+/// Peer id of the node key at `path`, generating the key on first use
+pub fn node_peer_id(path: &Path) -> std::result::Result<PeerId, MSSCSError> {
+    Ok(PeerId::from(load_or_create_keypair(path)?.public()))
+}
+
+/// Parse a block id and its bincode bytes
+fn decode_block(block_id: &str, data: &[u8]) -> std::result::Result<(Uuid, DataBlock), String> {
+    let uuid = Uuid::parse_str(block_id).map_err(|e| format!("Invalid block ID: {}", e))?;
+    let block: DataBlock = bincode::deserialize(data).map_err(|e| format!("Failed to deserialize block: {}", e))?;
+    if block.uuid != uuid {
+        return Err(format!("Block bytes are for {}, not {}", block.uuid, uuid));
+    }
+    Ok((uuid, block))
+}
+
+/// Blocks this node serves: those of the local block store and the replicas kept for
+/// peers, read from disk on demand
+#[derive(Clone)]
+struct HeldBlocks {
+    local: Option<Arc<dyn BlockStore>>,
+    /// Replicas under per-peer and total quotas, owned by the peer that placed them
+    replicas: Arc<ShardStore>,
+}
+
+impl HeldBlocks {
+    /// Bytes stored under `key` (a block id or a chunk key)
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        if let Some(local) = &self.local {
+            match local.get(key).await {
+                Ok(Some(data)) => return Some(data),
+                Ok(None) => {}
+                Err(e) => warn!("⚠️  Failed to read {}: {}", key, e),
+            }
+        }
+        self.replicas.get(key).await.unwrap_or_else(|e| {
+            warn!("⚠️  Failed to read {}: {}", key, e);
+            None
+        })
+    }
+
+    /// Total size of `key` and up to `len` of its bytes from `offset`
+    async fn get_range(&self, key: &str, offset: u64, len: usize) -> Option<(u64, Vec<u8>)> {
+        if let Some(local) = &self.local {
+            match local.get_range(key, offset, len).await {
+                Ok(Some(range)) => return Some(range),
+                Ok(None) => {}
+                Err(e) => warn!("⚠️  Failed to read {}: {}", key, e),
+            }
+        }
+        self.replicas.get_range(key, offset, len).await.unwrap_or_else(|e| {
+            warn!("⚠️  Failed to read {}: {}", key, e);
+            None
+        })
+    }
+
+    async fn contains(&self, key: &str) -> bool {
+        if let Some(local) = &self.local {
+            if matches!(local.stat(key).await, Ok(Some(_))) {
+                return true;
+            }
+        }
+        self.replicas.contains(key).await
+    }
+
+    /// Whether a replica of `key` placed by `peer` would be kept: it is not held yet and
+    /// the peer's replica quota has room
+    async fn has_room_for(&self, peer: &PeerId, key: &str) -> bool {
+        !self.contains(key).await && self.replicas.has_room(peer).await
+    }
+
+    /// Provider keys of everything held: block ids and chunk keys
+    async fn provider_keys(&self) -> Vec<String> {
+        let mut keys: HashSet<String> = self.replicas.ids().await.into_iter().collect();
+        if let Some(local) = &self.local {
+            match local.list().await {
+                Ok(stored) => keys.extend(stored),
+                Err(e) => warn!("⚠️  Failed to list blocks: {}", e),
+            }
+        }
+        keys.retain(|key| key.starts_with(CHUNK_KEY_PREFIX) || Uuid::parse_str(key).is_ok());
+        keys.into_iter().collect()
+    }
+}
+
+/// Whether `cid` is a well-formed content id (peers send it as a store key)
+fn valid_cid(cid: &str) -> bool {
+    cid.len() == 64 && ContentId::from_hex(cid).is_ok()
+}
+
+/// Reply channel of an outbound request/response exchange
+type PendingRequest = tokio::sync::oneshot::Sender<std::result::Result<P2PResponse, String>>;
+
+/// Reply channel of an outbound block-transfer frame
+type PendingTransfer = tokio::sync::oneshot::Sender<std::result::Result<BlockTransferResponse, String>>;
+
+/// What a provider lookup fetches
+#[derive(Debug, Clone)]
+enum FetchTarget {
+    /// A serialized block, streamed over the block protocol
+    Block { id: Uuid, expected_hash: Option<[u8; 32]> },
+    /// A sealed chunk, sent whole over the control protocol
+    Chunk { cid: String },
+}
+
+impl FetchTarget {
+    /// Feature a provider needs to serve the target
+    fn feature(&self) -> &'static str {
+        match self {
+            FetchTarget::Block { .. } => features::BLOCK_RANGES,
+            FetchTarget::Chunk { .. } => features::CHUNKS,
+        }
+    }
+}
+
+impl std::fmt::Display for FetchTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchTarget::Block { id, .. } => write!(f, "block {}", id),
+            FetchTarget::Chunk { cid } => write!(f, "chunk {}", cid),
+        }
+    }
+}
+
+/// Block or chunk fetch waiting for its provider lookup
+struct ProviderLookup {
+    target: FetchTarget,
+    reply: tokio::sync::oneshot::Sender<std::result::Result<Vec<u8>, String>>,
+    /// Providers seen so far while the query waits for a connected one
+    providers: Vec<PeerId>,
+}
+
+/// Value record lookup collecting the values found until the query finishes
+struct RecordLookup {
+    reply: tokio::sync::oneshot::Sender<std::result::Result<Vec<Vec<u8>>, String>>,
+    values: Vec<Vec<u8>>,
+}
+
+/// Providers tried in turn for one block
+const MAX_PROVIDERS_PER_FETCH: usize = 3;
+
+/// Relay servers this node keeps a reservation with at once
+const MAX_RELAY_RESERVATIONS: usize = 2;
+
+/// How often expired bans are lifted
+const BAN_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How long a peer of an identity-gated swarm may stay connected without passing the handshake
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// A connected peer whose handshake has not been admitted yet
+struct PendingAdmission {
+    since: std::time::Instant,
+    /// Addresses learned for it, added to Kademlia once it is admitted
+    addresses: Vec<Multiaddr>,
+}
+
+/// How a failed outbound request counts against the peer
+/// A failed dial does not count: provider records and routing entries outlive the addresses
+/// they list, so it says more about our address book than about the peer
+fn outbound_failure_event(error: &request_response::OutboundFailure) -> Option<PeerEvent> {
+    match error {
+        request_response::OutboundFailure::DialFailure => None,
+        request_response::OutboundFailure::Io(e) if e.kind() == std::io::ErrorKind::InvalidData => Some(PeerEvent::ProtocolViolation),
+        _ => Some(PeerEvent::Failure),
+    }
 }
 
 /// Main P2P Node implementation
 pub struct P2PNode {
     swarm: Swarm<P2PBehaviour>,
     event_sender: mpsc::UnboundedSender<P2PEvent>,
-    local_blocks: Arc<RwLock<HashMap<Uuid, DataBlock>>>,
+    blocks: HeldBlocks,
     _pending_requests: Arc<RwLock<HashMap<QueryId, Uuid>>>,
-    pending_get_queries: Arc<RwLock<HashMap<QueryId, tokio::sync::oneshot::Sender<std::result::Result<Vec<u8>, String>>>>>,
-    pending_put_queries: Arc<RwLock<HashMap<QueryId, tokio::sync::oneshot::Sender<std::result::Result<(), String>>>>>,
+    /// Block fetches waiting for `get_providers` results
+    pending_provider_lookups: HashMap<QueryId, ProviderLookup>,
+    /// Record publications waiting for their quorum
+    pending_record_puts: HashMap<QueryId, tokio::sync::oneshot::Sender<std::result::Result<(), String>>>,
+    /// Record lookups waiting for the query to finish
+    pending_record_gets: HashMap<QueryId, RecordLookup>,
+    /// Shards held for other peers
+    shards: Arc<ShardStore>,
+    /// Outbound shard requests waiting for a response, with the time they were sent
+    pending_requests: HashMap<request_response::OutboundRequestId, (std::time::Instant, PendingRequest)>,
+    /// Outbound block frames waiting for a response, with the time they were sent
+    pending_transfers: HashMap<request_response::OutboundRequestId, (std::time::Instant, PendingTransfer)>,
+    /// Per-peer scores and bans
+    peers: PeerManager,
+    /// Relay servers holding a reservation for this node
+    relay_reservations: HashSet<PeerId>,
+    /// Addresses this node dialed connected peers on
+    dialed_addrs: HashMap<PeerId, Multiaddr>,
+    /// Info advertised in the handshake
+    local_info: NodeInfo,
+    /// Negotiated capabilities of the connected peers
+    peer_capabilities: HashMap<PeerId, PeerCapabilities>,
+    /// Private swarm membership, checked once a peer's handshake arrives
+    private_network: Option<PrivateNetworkConfig>,
+    /// Connected peers that must pass the handshake before they are served or routed
+    unadmitted: HashMap<PeerId, PendingAdmission>,
+    /// Recovery shares held for other identities
+    recovery_vault: Option<RecoveryVault>,
     command_receiver: Option<mpsc::UnboundedReceiver<P2PNodeCommand>>,
     command_sender: mpsc::UnboundedSender<P2PNodeCommand>,
 }
@@ -260,79 +803,148 @@ impl P2PNode {
         info!("Initializing P2P node with config: {:?}", config);
 
         // Generate or load keypair
-        let keypair = identity::Keypair::generate_ed25519();
+        let keypair = match &config.key_file {
+            Some(path) => load_or_create_keypair(path)?,
+            None => identity::Keypair::generate_ed25519(),
+        };
         let peer_id = PeerId::from(keypair.public());
 
-        // Create swarm with relay and hole-punching support
-        let mut swarm = SwarmBuilder::with_existing_identity(keypair.clone())
-            .with_tokio()
-            .with_tcp(
-                tcp::Config::default().port_reuse(true).nodelay(true),
-                noise::Config::new,
-                yamux::Config::default,
-            )
-            .map_err(|e| MSSCSError::Network(format!("Failed to build transport: {}", e)))?
-            .with_quic() // Add QUIC transport for better NAT traversal
-            .with_relay_client(noise::Config::new, yamux::Config::default)
-            .map_err(|e| MSSCSError::Network(format!("Failed to create relay client: {}", e)))?
-            .with_behaviour(|_keypair, relay_client| {
-                // Create Kademlia DHT with optimized configuration
-                let store = MemoryStore::new(peer_id);
-                let mut kad_config = libp2p::kad::Config::default();
-                kad_config.set_query_timeout(std::time::Duration::from_secs(60));
-                kad_config.set_replication_factor(std::num::NonZeroUsize::new(config.replication_factor).unwrap());
-                
-                let mut kademlia = libp2p::kad::Behaviour::with_config(peer_id, store, kad_config);
-                kademlia.set_mode(Some(libp2p::kad::Mode::Server));
-                
-                // Add bootstrap peers to Kademlia
-                for addr in &config.bootstrap_peers {
-                    if let Some(peer_id) = addr.iter().find_map(|p| match p {
-                        libp2p::multiaddr::Protocol::P2p(hash) => {
-                            PeerId::from_multihash(hash.into()).ok()
-                        },
-                        _ => None,
-                    }) {
-                        kademlia.add_address(&peer_id, addr.clone());
-                        info!("Added bootstrap peer to Kademlia: {} at {}", peer_id, addr);
-                    }
-                }
+        let private_network = config.private_membership();
+        let attestation = private_network.as_ref().and_then(|private| private.attestation.clone());
+        if attestation.as_ref().is_some_and(|attestation| attestation.verify(&peer_id).is_none()) {
+            return Err(MSSCSError::Config(format!("Identity attestation is not valid for peer ID {}", peer_id)));
+        }
 
-                // Create request-response protocol
-                let request_response = libp2p::request_response::Behaviour::new(
-                    std::iter::once(("/msscs/1.0.0", ProtocolSupport::Full)),
-                    libp2p::request_response::Config::default(),
-                );
-
-                // Create mDNS for local discovery
-                let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)
-                    .expect("Failed to create mDNS");
-
-                // Create AutoNAT for NAT detection
-                let autonat = libp2p::autonat::Behaviour::new(
-                    peer_id,
-                    libp2p::autonat::Config {
-                        only_global_ips: false,
-                        ..Default::default()
-                    },
-                );
-
-                // Create DCUtR for hole punching
-                let dcutr = libp2p::dcutr::Behaviour::new(peer_id);
-
-                Ok(P2PBehaviour {
-                    kademlia,
-                    request_response,
-                    mdns,
-                    relay_client,
-                    autonat,
-                    dcutr,
-                })
-            })
-            .map_err(|e| MSSCSError::Network(format!("Failed to create behaviour: {}", e)))?
-            .with_swarm_config(|c| c.with_idle_connection_timeout(std::time::Duration::from_secs(60)))
-            .build();
+        let store = PersistentRecordStore::open(peer_id, config.record_store.clone())?;
+
+        let mut swarm = Self::build_swarm(&keypair, store, &config)?;
+        Self::listen(&mut swarm, &config, &peer_id)?;
+
+        for addr in &config.external_addresses {
+            swarm.add_external_address(addr.clone());
+            info!("📢 Advertising external address {}", addr);
+        }
+        if config.relay_server.is_some() && config.external_addresses.is_empty() {
+            warn!("⚠️  Relay server without an external address: reservations are refused by clients until AutoNAT confirms one");
+        }
+
+        let (event_sender, _event_receiver) = mpsc::unbounded_channel();
+        let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+
+        let mut shard_config = config.shard_store.clone();
+        if config.storage_capacity > 0 {
+            shard_config.max_bytes = shard_config.max_bytes.min(config.storage_capacity);
+        }
+        let shards = Arc::new(ShardStore::open(shard_config).await?);
+        let mut replica_config = config.replica_store.clone();
+        if config.storage_capacity > 0 {
+            replica_config.max_bytes = replica_config.max_bytes.min(config.storage_capacity);
+        }
+        let replicas = Arc::new(ShardStore::open(replica_config).await?);
+        let blocks = HeldBlocks { local: config.block_store.clone(), replicas };
+
+        let mut local_info = NodeInfo::local(config.role, config.storage_capacity);
+        if config.recovery_vault.is_some() {
+            local_info.features.insert(features::RECOVERY.to_string());
+        }
+        local_info.attestation = attestation;
+
+        info!("P2P node created with peer ID: {}", peer_id);
+        info!("NAT traversal enabled: relay={}, autonat={}", config.enable_relay, config.enable_autonat);
+
+        Ok(P2PNode {
+            swarm,
+            event_sender,
+            blocks,
+            _pending_requests: Arc::new(RwLock::new(HashMap::new())),
+            pending_provider_lookups: HashMap::new(),
+            pending_record_puts: HashMap::new(),
+            pending_record_gets: HashMap::new(),
+            shards,
+            pending_requests: HashMap::new(),
+            pending_transfers: HashMap::new(),
+            peers: PeerManager::new(config.peer_limits.clone()),
+            relay_reservations: HashSet::new(),
+            dialed_addrs: HashMap::new(),
+            local_info,
+            peer_capabilities: HashMap::new(),
+            private_network,
+            unadmitted: HashMap::new(),
+            recovery_vault: config.recovery_vault.clone(),
+            command_receiver: Some(cmd_rx),
+            command_sender: cmd_tx,
+        })
+    }
+    
+    /// Create the swarm with relay and hole-punching support
+    /// A private swarm runs TCP through the pnet handshake and leaves QUIC out,
+    /// since QUIC cannot carry the pre-shared key
+    fn build_swarm(
+        keypair: &identity::Keypair,
+        store: PersistentRecordStore,
+        config: &P2PConfig,
+    ) -> std::result::Result<Swarm<P2PBehaviour>, MSSCSError> {
+        let builder = SwarmBuilder::with_existing_identity(keypair.clone()).with_tokio();
+        #[cfg(any(test, feature = "simulation"))]
+        if let Some(network) = &config.simulation {
+            return Ok(builder
+                .with_other_transport(|keypair| network.transport(keypair))
+                .map_err(|e| MSSCSError::Network(format!("Failed to build simulated transport: {}", e)))?
+                .with_relay_client(noise::Config::new, yamux::Config::default)
+                .map_err(|e| MSSCSError::Network(format!("Failed to create relay client: {}", e)))?
+                .with_behaviour(|keypair, relay_client| Self::build_behaviour(keypair, store, config, relay_client))
+                .map_err(|e| MSSCSError::Network(format!("Failed to create behaviour: {}", e)))?
+                .with_swarm_config(|c| c.with_idle_connection_timeout(std::time::Duration::from_secs(60)))
+                .build());
+        }
+        Ok(match &config.private_network {
+            Some(private) => {
+                let psk = private.psk;
+                info!("🔒 Private swarm (key fingerprint {})", psk.fingerprint());
+                builder
+                    .with_other_transport(|keypair| private_network::pnet_tcp_transport(keypair, psk))
+                    .map_err(|e| MSSCSError::Network(format!("Failed to build private transport: {}", e)))?
+                    .with_relay_client(noise::Config::new, yamux::Config::default)
+                    .map_err(|e| MSSCSError::Network(format!("Failed to create relay client: {}", e)))?
+                    .with_behaviour(|keypair, relay_client| Self::build_behaviour(keypair, store, config, relay_client))
+                    .map_err(|e| MSSCSError::Network(format!("Failed to create behaviour: {}", e)))?
+                    .with_swarm_config(|c| c.with_idle_connection_timeout(std::time::Duration::from_secs(60)))
+                    .build()
+            }
+            None => builder
+                .with_tcp(
+                    tcp::Config::default().port_reuse(true).nodelay(true),
+                    noise::Config::new,
+                    yamux::Config::default,
+                )
+                .map_err(|e| MSSCSError::Network(format!("Failed to build transport: {}", e)))?
+                .with_quic() // Add QUIC transport for better NAT traversal
+                .with_relay_client(noise::Config::new, yamux::Config::default)
+                .map_err(|e| MSSCSError::Network(format!("Failed to create relay client: {}", e)))?
+                .with_behaviour(|keypair, relay_client| Self::build_behaviour(keypair, store, config, relay_client))
+                .map_err(|e| MSSCSError::Network(format!("Failed to create behaviour: {}", e)))?
+                .with_swarm_config(|c| c.with_idle_connection_timeout(std::time::Duration::from_secs(60)))
+                .build(),
+        })
+    }
 
+    /// Listen on the simulated network, or on every interface
+    fn listen(swarm: &mut Swarm<P2PBehaviour>, config: &P2PConfig, peer_id: &PeerId) -> std::result::Result<(), MSSCSError> {
+        #[cfg(any(test, feature = "simulation"))]
+        if let Some(network) = &config.simulation {
+            let addr = network.listen_address(peer_id)?;
+            swarm.listen_on(addr.clone())
+                .map_err(|e| MSSCSError::Network(format!("Failed to listen on simulated network: {}", e)))?;
+            info!("📡 Listening on simulated network: {}", addr);
+            return Ok(());
+        }
+        #[cfg(not(any(test, feature = "simulation")))]
+        let _ = peer_id;
+        Self::listen_on_interfaces(swarm, config)
+    }
+
+    /// Listen on TCP and, on the public network, QUIC on every interface
+    fn listen_on_interfaces(swarm: &mut Swarm<P2PBehaviour>, config: &P2PConfig) -> std::result::Result<(), MSSCSError> {
         // Listen on all interfaces with TCP (IPv4)
         let tcp_addr: Multiaddr = format!("/ip4/0.0.0.0/tcp/{}", config.listen_port)
             .parse()
@@ -351,55 +963,185 @@ impl P2PNode {
             debug!("IPv6 TCP not available (this is normal on some systems)");
         }
 
-        // Listen on QUIC for better NAT traversal (IPv4)
-        let quic_addr: Multiaddr = format!("/ip4/0.0.0.0/udp/{}/quic-v1", config.listen_port)
-            .parse()
-            .map_err(|e: libp2p::multiaddr::Error| MSSCSError::Network(format!("Invalid address: {}", e)))?;
-        swarm.listen_on(quic_addr.clone())
-            .map_err(|e| MSSCSError::Network(format!("Failed to listen on QUIC: {}", e)))?;
-        info!("📡 Listening on QUIC: {}", quic_addr);
+        // QUIC only on the public network (no transport for it in a private swarm)
+        if config.private_network.is_none() {
+            // Listen on QUIC for better NAT traversal (IPv4)
+            let quic_addr: Multiaddr = format!("/ip4/0.0.0.0/udp/{}/quic-v1", config.listen_port)
+                .parse()
+                .map_err(|e: libp2p::multiaddr::Error| MSSCSError::Network(format!("Invalid address: {}", e)))?;
+            swarm.listen_on(quic_addr.clone())
+                .map_err(|e| MSSCSError::Network(format!("Failed to listen on QUIC: {}", e)))?;
+            info!("📡 Listening on QUIC: {}", quic_addr);
 
-        // Listen on QUIC IPv6 for dual-stack support
-        let quic_addr_v6: Multiaddr = format!("/ip6/::/udp/{}/quic-v1", config.listen_port)
-            .parse()
-            .map_err(|e: libp2p::multiaddr::Error| MSSCSError::Network(format!("Invalid address: {}", e)))?;
-        if let Ok(_) = swarm.listen_on(quic_addr_v6.clone()) {
-            info!("📡 Listening on QUIC IPv6: {}", quic_addr_v6);
-        } else {
-            debug!("IPv6 QUIC not available (this is normal on some systems)");
+            // Listen on QUIC IPv6 for dual-stack support
+            let quic_addr_v6: Multiaddr = format!("/ip6/::/udp/{}/quic-v1", config.listen_port)
+                .parse()
+                .map_err(|e: libp2p::multiaddr::Error| MSSCSError::Network(format!("Invalid address: {}", e)))?;
+            if let Ok(_) = swarm.listen_on(quic_addr_v6.clone()) {
+                info!("📡 Listening on QUIC IPv6: {}", quic_addr_v6);
+            } else {
+                debug!("IPv6 QUIC not available (this is normal on some systems)");
+            }
         }
 
-        let (event_sender, _event_receiver) = mpsc::unbounded_channel();
-        let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+        Ok(())
+    }
 
-        info!("P2P node created with peer ID: {}", peer_id);
-        info!("NAT traversal enabled: relay={}, autonat={}", config.enable_relay, config.enable_autonat);
+    /// Combine Kademlia, the msscs protocols and the NAT traversal behaviours
+    fn build_behaviour(
+        keypair: &identity::Keypair,
+        store: PersistentRecordStore,
+        config: &P2PConfig,
+        relay_client: libp2p::relay::client::Behaviour,
+    ) -> std::result::Result<P2PBehaviour, Box<dyn std::error::Error + Send + Sync>> {
+        let peer_id = keypair.public().to_peer_id();
 
-        Ok(P2PNode {
-            swarm,
-            event_sender,
-            local_blocks: Arc::new(RwLock::new(HashMap::new())),
-            _pending_requests: Arc::new(RwLock::new(HashMap::new())),
-            pending_get_queries: Arc::new(RwLock::new(HashMap::new())),
-            pending_put_queries: Arc::new(RwLock::new(HashMap::new())),
-            command_receiver: Some(cmd_rx),
-            command_sender: cmd_tx,
+        // Create Kademlia DHT with optimized configuration
+        let mut kad_config = libp2p::kad::Config::default();
+        kad_config.set_query_timeout(std::time::Duration::from_secs(60));
+        kad_config.set_replication_factor(std::num::NonZeroUsize::new(config.replication_factor).unwrap());
+        // Provider records are re-announced by the node itself; let them lapse
+        // after a few missed announcements so departed nodes drop out
+        kad_config.set_provider_publication_interval(None);
+        kad_config.set_provider_record_ttl(Some(config.provider_reannounce_interval * 3));
+        // Any record the store accepts must also fit in one Kademlia message
+        kad_config.set_max_packet_size(config.record_store.max_value_bytes + 4096);
+        // Inbound records are checked before they are stored (see `accept_record`)
+        kad_config.set_record_filtering(libp2p::kad::StoreInserts::FilterBoth);
+        // A private swarm speaks its own DHT protocol and never answers IPFS queries
+        if let Some(private) = &config.private_network {
+            kad_config.set_protocol_names(vec![private.kad_protocol_name()?]);
+            // Peers enter the routing table only once their handshake is admitted
+            if private.requires_handshake() {
+                kad_config.set_kbucket_inserts(libp2p::kad::BucketInserts::Manual);
+            }
+        }
+        
+        let mut kademlia = libp2p::kad::Behaviour::with_config(peer_id, store, kad_config);
+        kademlia.set_mode(Some(libp2p::kad::Mode::Server));
+        
+        // Add bootstrap peers to Kademlia
+        for addr in &config.bootstrap_addresses() {
+            if let Some(peer_id) = addr.iter().find_map(|p| match p {
+                libp2p::multiaddr::Protocol::P2p(hash) => {
+                    PeerId::from_multihash(hash.into()).ok()
+                },
+                _ => None,
+            }) {
+                kademlia.add_address(&peer_id, addr.clone());
+                info!("Added bootstrap peer to Kademlia: {} at {}", peer_id, addr);
+            }
+        }
+
+        // Create request-response protocol
+        let request_response = libp2p::request_response::Behaviour::new(
+            std::iter::once((protocol::CONTROL_PROTOCOL, ProtocolSupport::Full)),
+            libp2p::request_response::Config::default(),
+        );
+
+        // Block bytes move in bounded binary frames on their own protocol
+        let block_transfer = libp2p::request_response::Behaviour::new(
+            std::iter::once((protocol::BLOCKS_PROTOCOL, ProtocolSupport::Full)),
+            libp2p::request_response::Config::default(),
+        );
+
+        // Capability handshake run on every new peer
+        let meta = libp2p::request_response::Behaviour::new(
+            std::iter::once((protocol::META_PROTOCOL, ProtocolSupport::Full)),
+            libp2p::request_response::Config::default(),
+        );
+
+        // Version 1 nodes still reach us on the legacy name; they get answers, never blocks
+        let legacy = libp2p::request_response::Behaviour::new(
+            std::iter::once((protocol::LEGACY_CONTROL_PROTOCOL, ProtocolSupport::Inbound)),
+            libp2p::request_response::Config::default(),
+        );
+
+        // Create mDNS for local discovery
+        let mdns = config.enable_mdns.then(|| {
+            mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)
+                .expect("Failed to create mDNS")
+        });
+
+        // Create AutoNAT for NAT detection; only the server role dials back for others
+        let mut autonat_config = libp2p::autonat::Config {
+            only_global_ips: false,
+            ..Default::default()
+        };
+        if config.relay_server.is_none() {
+            autonat_config.throttle_clients_global_max = 0;
+        }
+        let autonat = libp2p::autonat::Behaviour::new(peer_id, autonat_config);
+
+        // Create DCUtR for hole punching
+        let dcutr = libp2p::dcutr::Behaviour::new(peer_id);
+
+        // Relay circuits for NATed peers when running the server role
+        let relay_server = config.relay_server.as_ref()
+            .map(|server| libp2p::relay::Behaviour::new(peer_id, server.relay_config()));
+
+        // Tell peers what we listen on and how they look from here
+        let identify = libp2p::identify::Behaviour::new(
+            libp2p::identify::Config::new(format!("/msscs/{}", protocol::PROTOCOL_VERSION), keypair.public())
+                .with_agent_version(format!("msscs/{}", env!("CARGO_PKG_VERSION"))),
+        );
+
+        // Refuse connections beyond the configured limits
+        let limits = &config.peer_limits;
+        let connection_limits = libp2p::connection_limits::Behaviour::new(
+            libp2p::connection_limits::ConnectionLimits::default()
+                .with_max_established_incoming(Some(limits.max_inbound))
+                .with_max_established_outgoing(Some(limits.max_outbound))
+                .with_max_established(Some(config.max_peers as u32)),
+        );
+
+        // Inside a private swarm listed by peer id only, other peers never get a connection;
+        // with an identity list they connect and are dropped unless their handshake qualifies
+        let allowed_peers = config.private_membership()
+            .filter(|private| !private.allowed_peers.is_empty() && private.allowed_identities.is_empty())
+            .map(|private| {
+                let mut allowed = libp2p::allow_block_list::Behaviour::<libp2p::allow_block_list::AllowedPeers>::default();
+                for peer in private.allowed_peers {
+                    allowed.allow_peer(peer);
+                }
+                allowed
+            });
+
+        Ok(P2PBehaviour {
+            kademlia,
+            request_response,
+            block_transfer,
+            meta,
+            legacy,
+            mdns: mdns.into(),
+            relay_client,
+            autonat,
+            dcutr,
+            relay_server: relay_server.into(),
+            identify,
+            connection_limits,
+            blocked_peers: Default::default(),
+            allowed_peers: allowed_peers.into(),
         })
     }
-    
+
+    /// Peer id of this node
+    pub fn peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
     /// Get the command sender for this node
     pub fn get_command_sender(&self) -> mpsc::UnboundedSender<P2PNodeCommand> {
         self.command_sender.clone()
     }
 
-    /// Start the P2P node and return event receiver
-    pub async fn start(mut self, config: P2PConfig) -> std::result::Result<(mpsc::UnboundedReceiver<P2PEvent>, Arc<RwLock<HashMap<Uuid, DataBlock>>>), MSSCSError> {
+    /// Start the P2P node and return event receiver and the store of replicas kept for peers
+    pub async fn start(mut self, config: P2PConfig) -> std::result::Result<(mpsc::UnboundedReceiver<P2PEvent>, Arc<ShardStore>), MSSCSError> {
         let event_sender = self.event_sender.clone();
-        let local_blocks_clone = self.local_blocks.clone();
-        let local_blocks_return = self.local_blocks.clone();
+        let blocks = self.blocks.clone();
+        let replicas = self.blocks.replicas.clone();
         let (event_tx, event_receiver) = mpsc::unbounded_channel();
-        let pending_get_queries = self.pending_get_queries.clone();
-        let pending_put_queries = self.pending_put_queries.clone();
+        let shards = self.shards.clone();
         
         // Take command receiver
         let mut cmd_rx = self.command_receiver.take().expect("Command receiver already taken");
@@ -418,6 +1160,10 @@ impl P2PNode {
             // Start bootstrap after 500ms to let event loop initialize
             let mut bootstrap_timer = tokio::time::interval(std::time::Duration::from_millis(500));
             bootstrap_timer.tick().await; // First tick completes immediately
+            let mut reannounce_timer = tokio::time::interval(config.provider_reannounce_interval);
+            reannounce_timer.tick().await;
+            let mut ban_timer = tokio::time::interval(BAN_SWEEP_INTERVAL);
+            let mut admission_timer = tokio::time::interval(HANDSHAKE_TIMEOUT / 2);
             
             loop {
                 tokio::select! {
@@ -429,6 +1175,35 @@ impl P2PNode {
                             warn!("⚠️  Bootstrap warning: {}", e);
                         }
                     }
+                    // Re-announce every local block so provider records survive churn
+                    _ = reannounce_timer.tick() => {
+                        let expired = self.swarm.behaviour_mut().kademlia.store_mut().remove_expired();
+                        if expired > 0 {
+                            debug!("🧹 Dropped {} expired DHT entries", expired);
+                        }
+                        let keys = blocks.provider_keys().await;
+                        debug!("📣 Re-announcing {} blocks and chunks as provider", keys.len());
+                        for key in keys {
+                            self.announce_provider(&key);
+                        }
+                    }
+                    // Let peers whose ban ran out back in
+                    _ = ban_timer.tick() => {
+                        for peer in self.peers.expire_bans(std::time::Instant::now()) {
+                            info!("🔓 Ban on {} expired", peer);
+                            self.swarm.behaviour_mut().blocked_peers.unblock_peer(peer);
+                        }
+                    }
+                    // Drop peers that did not pass the handshake in time
+                    _ = admission_timer.tick() => {
+                        let expired: Vec<PeerId> = self.unadmitted.iter()
+                            .filter(|(_, pending)| pending.since.elapsed() >= HANDSHAKE_TIMEOUT)
+                            .map(|(peer, _)| *peer)
+                            .collect();
+                        for peer in expired {
+                            self.drop_unadmitted(peer, "no handshake in time");
+                        }
+                    }
                     // Handle swarm events
                     Some(event) = self.swarm.next() => {
                     match event {
@@ -451,41 +1226,91 @@ impl P2PNode {
                                                         debug!("Found {} peers in DHT", result.peers.len());
                                                     }
                                                 }
-                                                libp2p::kad::QueryResult::GetRecord(Ok(ok)) => {
-                                                    if let libp2p::kad::GetRecordOk::FoundRecord(peer_record) = ok {
-                                                        debug!("✅ Got record from DHT: {:?}", peer_record.record.key);
-                                                        // CRITICAL FIX: Properly handle pending queries
-                                                        if let Some(sender) = pending_get_queries.write().await.remove(&id) {
-                                                            let _ = sender.send(Ok(peer_record.record.value));
-                                                            debug!("   Delivered record to waiting query");
+                                                libp2p::kad::QueryResult::StartProviding(Ok(ok)) => {
+                                                    debug!("✅ Announced as provider of {:?}", ok.key);
+                                                }
+                                                libp2p::kad::QueryResult::StartProviding(Err(e)) => {
+                                                    debug!("⚠️  Provider announcement failed: {:?}", e);
+                                                }
+                                                libp2p::kad::QueryResult::GetProviders(Ok(GetProvidersOk::FoundProviders { providers, .. })) => {
+                                                    let local_peer = *self.swarm.local_peer_id();
+                                                    let Some(lookup) = self.pending_provider_lookups.get_mut(&id) else { continue };
+                                                    for peer in providers {
+                                                        let usable = peer != local_peer
+                                                            && self.peer_capabilities.get(&peer).is_none_or(|caps| caps.supports(lookup.target.feature()));
+                                                        if usable && !lookup.providers.contains(&peer) {
+                                                            lookup.providers.push(peer);
+                                                        }
+                                                    }
+                                                    // Providers that went away still have records; keep looking
+                                                    // until one we are connected to turns up
+                                                    if !lookup.providers.iter().any(|peer| self.swarm.is_connected(peer)) {
+                                                        continue;
+                                                    }
+                                                    if let Some(mut lookup) = self.pending_provider_lookups.remove(&id) {
+                                                        if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&id) {
+                                                            query.finish();
+                                                        }
+                                                        let providers = self.take_fetch_providers(&mut lookup);
+                                                        debug!("📍 Found {} providers for {}", providers.len(), lookup.target);
+                                                        self.fetch_from_providers(lookup, providers, event_tx.clone());
+                                                    }
+                                                }
+                                                libp2p::kad::QueryResult::GetProviders(Ok(GetProvidersOk::FinishedWithNoAdditionalRecord { .. })) => {
+                                                    if let Some(mut lookup) = self.pending_provider_lookups.remove(&id) {
+                                                        if lookup.providers.is_empty() {
+                                                            let _ = lookup.reply.send(Err(format!("No providers found for {}", lookup.target)));
+                                                        } else {
+                                                            // None connected: dial whatever the DHT knows
+                                                            let providers = self.take_fetch_providers(&mut lookup);
+                                                            self.fetch_from_providers(lookup, providers, event_tx.clone());
                                                         }
                                                     }
                                                 }
-                                                libp2p::kad::QueryResult::GetRecord(Err(e)) => {
-                                                    debug!("❌ Get record failed: {:?}", e);
-                                                    // CRITICAL FIX: Notify waiting queries of failure
-                                                    if let Some(sender) = pending_get_queries.write().await.remove(&id) {
-                                                        let _ = sender.send(Err(format!("DHT get failed: {:?}", e)));
+                                                libp2p::kad::QueryResult::GetProviders(Err(e)) => {
+                                                    debug!("❌ Provider lookup failed: {:?}", e);
+                                                    if let Some(lookup) = self.pending_provider_lookups.remove(&id) {
+                                                        let _ = lookup.reply.send(Err(format!("Provider lookup failed: {:?}", e)));
+                                                    }
+                                                }
+                                                libp2p::kad::QueryResult::PutRecord(result) => {
+                                                    if let Some(reply) = self.pending_record_puts.remove(&id) {
+                                                        let _ = reply.send(result.map(|_| ()).map_err(|e| format!("Record publication failed: {}", e)));
+                                                    }
+                                                }
+                                                libp2p::kad::QueryResult::GetRecord(Ok(GetRecordOk::FoundRecord(found))) => {
+                                                    if let Some(lookup) = self.pending_record_gets.get_mut(&id) {
+                                                        if !lookup.values.contains(&found.record.value) {
+                                                            lookup.values.push(found.record.value);
+                                                        }
                                                     }
                                                 }
-                                                libp2p::kad::QueryResult::PutRecord(Ok(ok)) => {
-                                                    debug!("✅ Put record successful: {:?}", ok.key);
-                                                    // CRITICAL FIX: Notify waiting queries of success
-                                                    if let Some(sender) = pending_put_queries.write().await.remove(&id) {
-                                                        let _ = sender.send(Ok(()));
-                                                        debug!("   Confirmed storage to waiting query");
+                                                libp2p::kad::QueryResult::GetRecord(Ok(GetRecordOk::FinishedWithNoAdditionalRecord { .. })) => {
+                                                    if let Some(lookup) = self.pending_record_gets.remove(&id) {
+                                                        let _ = lookup.reply.send(Ok(lookup.values));
                                                     }
                                                 }
-                                                libp2p::kad::QueryResult::PutRecord(Err(e)) => {
-                                                    warn!("❌ Put record failed: {:?}", e);
-                                                    // CRITICAL FIX: Notify waiting queries of failure
-                                                    if let Some(sender) = pending_put_queries.write().await.remove(&id) {
-                                                        let _ = sender.send(Err(format!("DHT put failed: {:?}", e)));
+                                                libp2p::kad::QueryResult::GetRecord(Err(e)) => {
+                                                    if let Some(lookup) = self.pending_record_gets.remove(&id) {
+                                                        // A timeout after some values arrived still answers the lookup
+                                                        let result = if lookup.values.is_empty() {
+                                                            Err(format!("Record lookup failed: {}", e))
+                                                        } else {
+                                                            Ok(lookup.values)
+                                                        };
+                                                        let _ = lookup.reply.send(result);
                                                     }
                                                 }
                                                 _ => {}
                                             }
                                         }
+                                        libp2p::kad::Event::InboundRequest { request } => {
+                                            self.handle_inbound_record(request);
+                                        }
+                                        libp2p::kad::Event::RoutablePeer { peer, address }
+                                        | libp2p::kad::Event::PendingRoutablePeer { peer, address } => {
+                                            self.add_routing_address(peer, address);
+                                        }
                                         _ => {}
                                     }
                                 }
@@ -498,50 +1323,273 @@ impl P2PNode {
                                                     channel,
                                                     ..
                                                 } => {
+                                                    if !self.is_admitted(&peer) {
+                                                        debug!("🔒 Ignoring request from {} before its handshake", peer);
+                                                        continue;
+                                                    }
                                                     match request {
-                                                        P2PRequest::GetBlock { id } => {
-                                                            let blocks = local_blocks_clone.read().await;
-                                                            let block = blocks.get(&id).cloned();
-
-                                                            if let Err(e) = self.swarm.behaviour_mut().request_response.send_response(channel, P2PResponse::Block { data: block }) {
-                                                                warn!("Failed to send block response to {}: {:?}", peer, e);
-                                                            }
-
-                                                            let _ = event_sender.send(P2PEvent::BlockRequested { peer, block_id: id });
+                                                        P2PRequest::StoreShard { id, data } => {
+                                                            self.report_peer(peer, PeerEvent::Served { bytes: 0 });
+                                                            let stored = if self.local_info.role.stores_data() {
+                                                                shards.put(&peer, &id, &data).await
+                                                            } else {
+                                                                Err(MSSCSError::Validation("Node does not store data".to_string()))
+                                                            };
+                                                            let response = match stored {
+                                                                Ok(()) => {
+                                                                    debug!("📦 Holding shard {} ({} bytes) for {}", id, data.len(), peer);
+                                                                    P2PResponse::ShardStored
+                                                                }
+                                                                Err(e) => {
+                                                                    debug!("❌ Refused shard {} from {}: {}", id, peer, e);
+                                                                    P2PResponse::ShardRefused { reason: e.to_string() }
+                                                                }
+                                                            };
+                                                            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, response);
+                                                        }
+                                                        P2PRequest::GetShard { id } => {
+                                                            let data = shards.get(&id).await.unwrap_or_else(|e| {
+                                                                warn!("⚠️  Failed to read shard {}: {}", id, e);
+                                                                None
+                                                            });
+                                                            self.report_peer(peer, PeerEvent::Served { bytes: data.as_ref().map_or(0, |d| d.len() as u64) });
+                                                            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, P2PResponse::Shard { data });
+                                                        }
+                                                        P2PRequest::HasShard { id } => {
+                                                            let present = shards.contains(&id).await;
+                                                            self.report_peer(peer, PeerEvent::Served { bytes: 0 });
+                                                            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, P2PResponse::HasShard { present });
+                                                        }
+                                                        P2PRequest::ReplicateBlock { id, hash } => {
+                                                            let accepted = self.accepts_replicas_from(&peer) && blocks.has_room_for(&peer, &id.to_string()).await;
+                                                            self.report_peer(peer, PeerEvent::Served { bytes: 0 });
+                                                            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, P2PResponse::ReplicateAccepted { accepted });
+                                                            if accepted {
+                                                                self.pull_replica(peer, id, hash, event_tx.clone());
+                                                            }
+                                                        }
+                                                        P2PRequest::GetChunk { cid } => {
+                                                            let data = if valid_cid(&cid) { blocks.get(&chunk_key(&cid)).await } else { None };
+                                                            self.report_peer(peer, PeerEvent::Served { bytes: data.as_ref().map_or(0, |d| d.len() as u64) });
+                                                            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, P2PResponse::Chunk { data });
+                                                        }
+                                                        P2PRequest::ReplicateChunk { cid } => {
+                                                            // The CID is the chunk's hash, checked when it is pulled
+                                                            let accepted = valid_cid(&cid)
+                                                                && self.peer_capabilities.get(&peer).is_some_and(|caps| caps.supports(features::CHUNKS))
+                                                                && self.accepts_replicas_from(&peer)
+                                                                && blocks.has_room_for(&peer, &chunk_key(&cid)).await;
+                                                            self.report_peer(peer, PeerEvent::Served { bytes: 0 });
+                                                            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, P2PResponse::ReplicateAccepted { accepted });
+                                                            if accepted {
+                                                                self.pull_chunk_replica(peer, cid);
+                                                            }
+                                                        }
+                                                        P2PRequest::StoreRecoveryShare { share } => {
+                                                            let accepted = match &self.recovery_vault {
+                                                                Some(vault) => match vault.store_share(*share) {
+                                                                    Ok(()) => true,
+                                                                    Err(e) => {
+                                                                        debug!("❌ Refused recovery share from {}: {}", peer, e);
+                                                                        false
+                                                                    }
+                                                                },
+                                                                None => false,
+                                                            };
+                                                            self.report_peer(peer, PeerEvent::Served { bytes: 0 });
+                                                            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, P2PResponse::RecoveryShareStored { accepted });
+                                                        }
+                                                        P2PRequest::RequestRecovery { request } => {
+                                                            let status = match &self.recovery_vault {
+                                                                Some(vault) => vault.handle_request(request),
+                                                                None => RecoveryStatus::NoShare,
+                                                            };
+                                                            self.report_peer(peer, PeerEvent::Served { bytes: 0 });
+                                                            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, P2PResponse::Recovery { status });
                                                         }
                                                         P2PRequest::Ping => {
+                                                            self.report_peer(peer, PeerEvent::Served { bytes: 0 });
                                                             let _ = self.swarm.behaviour_mut().request_response.send_response(channel, P2PResponse::Pong);
                                                         }
                                                     }
                                                 }
                                                 request_response::Message::Response {
+                                                    request_id,
                                                     response,
-                                                    ..
                                                 } => {
+                                                    if let Some((sent, reply)) = self.pending_requests.remove(&request_id) {
+                                                        self.report_peer(peer, PeerEvent::Success { latency: sent.elapsed() });
+                                                        let _ = reply.send(Ok(response));
+                                                        continue;
+                                                    }
                                                     match response {
-                                                        P2PResponse::Block { data: Some(block) } => {
-                                                            let _ = event_sender.send(P2PEvent::BlockReceived { peer, block });
-                                                        }
-                                                        P2PResponse::Block { data: None } => {
-                                                            debug!("Peer {} doesn't have requested block", peer);
-                                                        }
                                                         P2PResponse::Pong => {
                                                             debug!("Received pong from {}", peer);
                                                         }
+                                                        P2PResponse::ReplicateAccepted { .. } => {
+                                                            debug!("Unsolicited replication response from {}", peer);
+                                                        }
+                                                        P2PResponse::Chunk { .. } => {
+                                                            debug!("Unsolicited chunk response from {}", peer);
+                                                        }
+                                                        P2PResponse::ShardStored
+                                                        | P2PResponse::ShardRefused { .. }
+                                                        | P2PResponse::Shard { .. }
+                                                        | P2PResponse::HasShard { .. } => {
+                                                            debug!("Unsolicited shard response from {}", peer);
+                                                        }
+                                                        P2PResponse::RecoveryShareStored { .. } | P2PResponse::Recovery { .. } => {
+                                                            debug!("Unsolicited recovery response from {}", peer);
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        }
+                                        request_response::Event::OutboundFailure { peer, request_id, error } => {
+                                            debug!("❌ Request to {} failed: {}", peer, error);
+                                            if let Some(event) = outbound_failure_event(&error) {
+                                                self.report_peer(peer, event);
+                                            }
+                                            if let Some((_, reply)) = self.pending_requests.remove(&request_id) {
+                                                let _ = reply.send(Err(format!("Request to {} failed: {}", peer, error)));
+                                            }
+                                        }
+                                        request_response::Event::InboundFailure { peer, error: request_response::InboundFailure::Io(e), .. }
+                                            if e.kind() == std::io::ErrorKind::InvalidData =>
+                                        {
+                                            debug!("❌ Malformed request from {}: {}", peer, e);
+                                            self.report_peer(peer, PeerEvent::ProtocolViolation);
+                                        }
+                                        _ => {}
+                                    }
+                                }
+                                P2PBehaviourEvent::BlockTransfer(transfer_event) => {
+                                    match transfer_event {
+                                        request_response::Event::Message { peer, message } => {
+                                            match message {
+                                                request_response::Message::Request { request, channel, .. } => {
+                                                    if !self.is_admitted(&peer) {
+                                                        debug!("🔒 Ignoring block request from {} before its handshake", peer);
+                                                        continue;
+                                                    }
+                                                    // Only the requested frame is read from disk
+                                                    let range = blocks.get_range(&request.block_id.to_string(), request.offset, request.frame_len()).await;
+                                                    let response = block_transfer::frame_from_range(range, &request);
+                                                    if let BlockTransferResponse::Frame { data, .. } = &response {
+                                                        self.report_peer(peer, PeerEvent::Served { bytes: data.len() as u64 });
+                                                    } else {
+                                                        self.report_peer(peer, PeerEvent::Served { bytes: 0 });
+                                                    }
+                                                    if let Err(e) = self.swarm.behaviour_mut().block_transfer.send_response(channel, response) {
+                                                        warn!("Failed to send block frame to {}: {:?}", peer, e);
+                                                    }
+                                                    if request.offset == 0 {
+                                                        let _ = event_sender.send(P2PEvent::BlockRequested { peer, block_id: request.block_id });
+                                                    }
+                                                }
+                                                request_response::Message::Response { request_id, response } => {
+                                                    if let BlockTransferResponse::Frame { data, .. } = &response {
+                                                        self.report_peer(peer, PeerEvent::Received { bytes: data.len() as u64 });
+                                                    }
+                                                    if let Some((sent, reply)) = self.pending_transfers.remove(&request_id) {
+                                                        self.report_peer(peer, PeerEvent::Success { latency: sent.elapsed() });
+                                                        let _ = reply.send(Ok(response));
+                                                        continue;
+                                                    }
+                                                    // Answer to `request_block_from_peers`: only single-frame blocks are complete
+                                                    match response {
+                                                        BlockTransferResponse::Frame { total_size, offset: 0, data } if data.len() as u64 == total_size => {
+                                                            match bincode::deserialize::<DataBlock>(&data) {
+                                                                Ok(block) => {
+                                                                    let _ = event_sender.send(P2PEvent::BlockReceived { peer, block: block.clone() });
+                                                                    let _ = event_tx.send(P2PEvent::BlockReceived { peer, block });
+                                                                }
+                                                                Err(e) => debug!("Invalid block from {}: {}", peer, e),
+                                                            }
+                                                        }
+                                                        BlockTransferResponse::Frame { total_size, .. } => {
+                                                            debug!("Peer {} has a {} byte block; use GetBlock to stream it", peer, total_size);
+                                                        }
+                                                        BlockTransferResponse::NotFound => {
+                                                            debug!("Peer {} doesn't have requested block", peer);
+                                                        }
                                                     }
                                                 }
                                             }
                                         }
+                                        request_response::Event::OutboundFailure { peer, request_id, error } => {
+                                            debug!("❌ Block frame request to {} failed: {}", peer, error);
+                                            if let Some(event) = outbound_failure_event(&error) {
+                                                self.report_peer(peer, event);
+                                            }
+                                            if let Some((_, reply)) = self.pending_transfers.remove(&request_id) {
+                                                let _ = reply.send(Err(format!("Block frame request to {} failed: {}", peer, error)));
+                                            }
+                                        }
+                                        request_response::Event::InboundFailure { peer, error: request_response::InboundFailure::Io(e), .. }
+                                            if e.kind() == std::io::ErrorKind::InvalidData =>
+                                        {
+                                            debug!("❌ Malformed block request from {}: {}", peer, e);
+                                            self.report_peer(peer, PeerEvent::ProtocolViolation);
+                                        }
+                                        _ => {}
+                                    }
+                                }
+                                P2PBehaviourEvent::Meta(meta_event) => {
+                                    match meta_event {
+                                        request_response::Event::Message { peer, message } => {
+                                            let remote = match message {
+                                                request_response::Message::Request { request, channel, .. } => {
+                                                    let _ = self.swarm.behaviour_mut().meta.send_response(channel, self.local_info.clone());
+                                                    request
+                                                }
+                                                request_response::Message::Response { response, .. } => response,
+                                            };
+                                            self.record_capabilities(peer, &remote, &event_tx);
+                                        }
+                                        request_response::Event::OutboundFailure { peer, error: request_response::OutboundFailure::UnsupportedProtocols, .. } => {
+                                            // Version 1 node: no handshake, only the legacy control protocol
+                                            self.record_capabilities(peer, &NodeInfo::legacy(), &event_tx);
+                                        }
+                                        request_response::Event::OutboundFailure { peer, error, .. } => {
+                                            debug!("❌ Handshake with {} failed: {}", peer, error);
+                                            if let Some(event) = outbound_failure_event(&error) {
+                                                self.report_peer(peer, event);
+                                            }
+                                            if !self.is_admitted(&peer) {
+                                                self.drop_unadmitted(peer, "handshake failed");
+                                            }
+                                        }
+                                        request_response::Event::InboundFailure { peer, error, .. } if !self.is_admitted(&peer) => {
+                                            debug!("❌ Handshake from {} failed: {}", peer, error);
+                                            self.drop_unadmitted(peer, "handshake failed");
+                                        }
                                         _ => {}
                                     }
                                 }
+                                P2PBehaviourEvent::Legacy(legacy_event) => {
+                                    if let request_response::Event::Message { peer, message: request_response::Message::Request { request, channel, .. } } = legacy_event {
+                                        if !self.is_admitted(&peer) {
+                                            debug!("🔒 Ignoring version 1 request from {} before its handshake", peer);
+                                            continue;
+                                        }
+                                        // Blocks in the version 1 format no longer exist; say so without penalizing the peer
+                                        if let LegacyRequest::GetBlock { id } = &request {
+                                            debug!("📭 Version 1 peer {} asked for block {}; not served to v1 nodes", peer, id);
+                                        }
+                                        let response = LegacyResponse::answer(&request);
+                                        let _ = self.swarm.behaviour_mut().legacy.send_response(channel, response);
+                                    }
+                                }
                                 P2PBehaviourEvent::Mdns(mdns_event) => {
                                     match mdns_event {
                                         mdns::Event::Discovered(list) => {
                                             for (peer_id, addr) in list {
                                                 info!("Discovered peer {} at {}", peer_id, addr);
-                                                self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
-                                                let _ = self.swarm.dial(peer_id);
+                                                if !self.needs_admission(&peer_id) {
+                                                    self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
+                                                }
+                                                let _ = self.swarm.dial(addr);
                                             }
                                         }
                                         mdns::Event::Expired(list) => {
@@ -601,18 +1649,62 @@ impl P2PNode {
                                     // DCUtR events - hole punching for NAT traversal
                                     debug!("DCUtR event: {:?}", dcutr_event);
                                 }
+                                P2PBehaviourEvent::RelayServer(relay_event) => {
+                                    match relay_event {
+                                        libp2p::relay::Event::ReservationReqAccepted { src_peer_id, renewed } => {
+                                            debug!("🛰️  {} relay reservation for {}", if renewed { "Renewed" } else { "Accepted" }, src_peer_id);
+                                        }
+                                        libp2p::relay::Event::CircuitReqAccepted { src_peer_id, dst_peer_id } => {
+                                            debug!("🛰️  Relaying {} -> {}", src_peer_id, dst_peer_id);
+                                        }
+                                        libp2p::relay::Event::ReservationReqDenied { src_peer_id }
+                                        | libp2p::relay::Event::CircuitReqDenied { src_peer_id, .. } => {
+                                            debug!("🛰️  Relay limit reached, denied {}", src_peer_id);
+                                        }
+                                        other => debug!("Relay server event: {:?}", other),
+                                    }
+                                }
+                                P2PBehaviourEvent::Identify(identify_event) => {
+                                    if let libp2p::identify::Event::Received { peer_id, info } = identify_event {
+                                        debug!("🪪 {} runs {} with {} listen addresses", peer_id, info.agent_version, info.listen_addrs.len());
+                                        if config.enable_relay && info.protocols.contains(&libp2p::relay::HOP_PROTOCOL_NAME) {
+                                            self.reserve_relay(peer_id, &info.listen_addrs, &config);
+                                        }
+                                        for addr in info.listen_addrs {
+                                            self.add_routing_address(peer_id, addr);
+                                        }
+                                    }
+                                }
                             }
                         }
                     SwarmEvent::NewListenAddr { address, .. } => {
                         info!("Listening on {}", address);
                     }
-                    SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                    SwarmEvent::ConnectionEstablished { peer_id, num_established, endpoint, .. } => {
                         info!("Connected to {}", peer_id);
+                        self.peers.set_connected(peer_id, true);
+                        if let libp2p::core::ConnectedPoint::Dialer { address, .. } = endpoint {
+                            self.dialed_addrs.insert(peer_id, address);
+                        }
+                        if num_established.get() == 1 {
+                            if self.needs_admission(&peer_id) && !self.peer_capabilities.contains_key(&peer_id) {
+                                self.unadmitted.insert(peer_id, PendingAdmission { since: std::time::Instant::now(), addresses: Vec::new() });
+                            }
+                            let hello = self.local_info.clone();
+                            self.swarm.behaviour_mut().meta.send_request(&peer_id, hello);
+                        }
                         let _ = event_sender.send(P2PEvent::PeerConnected(peer_id));
                         let _ = event_tx.send(P2PEvent::PeerConnected(peer_id));
                     }
-                    SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
+                    SwarmEvent::ConnectionClosed { peer_id, cause, num_established, .. } => {
                         info!("Disconnected from {} (cause: {:?})", peer_id, cause);
+                        if num_established == 0 {
+                            self.peer_capabilities.remove(&peer_id);
+                            self.unadmitted.remove(&peer_id);
+                            self.peers.set_connected(peer_id, false);
+                            self.relay_reservations.remove(&peer_id);
+                            self.dialed_addrs.remove(&peer_id);
+                        }
                         let _ = event_sender.send(P2PEvent::PeerDisconnected(peer_id));
                         let _ = event_tx.send(P2PEvent::PeerDisconnected(peer_id));
                     }
@@ -632,59 +1724,32 @@ impl P2PNode {
                     Some(cmd) = cmd_rx.recv() => {
                         match cmd {
                             P2PNodeCommand::GetConnectedPeers(reply) => {
-                                let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+                                // Peers whose handshake ruled out storage or that still await
+                                // admission are left out; other peers still handshaking are assumed to store
+                                let peers: Vec<PeerId> = self.swarm.connected_peers()
+                                    .filter(|peer| self.is_admitted(peer))
+                                    .filter(|peer| self.peer_capabilities.get(peer).is_none_or(PeerCapabilities::accepts_shards))
+                                    .copied()
+                                    .collect();
                                 let _ = reply.send(peers);
                             }
                             P2PNodeCommand::StoreBlock { block_id, data, reply } => {
-                                let result = async {
-                                    // Store in DHT with proper replication
-                                    let key = RecordKey::new(&block_id.as_bytes());
-                                    let record = Record {
-                                        key,
-                                        value: data.clone(),
-                                        publisher: None,
-                                        expires: None,
-                                    };
-                                    
-                                    let query_id = self.swarm
-                                        .behaviour_mut()
-                                        .kademlia
-                                        .put_record(record, Quorum::One)
-                                        .map_err(|e| format!("Failed to store in DHT: {:?}", e))?;
-                                    
-                                    // Create oneshot channel for response
-                                    let (put_tx, put_rx) = tokio::sync::oneshot::channel();
-                                    
-                                    // Track the query
-                                    pending_put_queries.write().await.insert(query_id, put_tx);
-                                    
-                                    // Store locally in shared blocks map
-                                    let block_uuid = Uuid::parse_str(&block_id)
-                                        .map_err(|e| format!("Invalid block ID: {}", e))?;
-                                    
-                                    // Deserialize and store block
-                                    let block: DataBlock = bincode::deserialize(&data)
-                                        .map_err(|e| format!("Failed to deserialize block: {}", e))?;
-                                    
-                                    local_blocks_clone.write().await.insert(block_uuid, block);
-                                    
-                                    // Wait for DHT confirmation with timeout
-                                    match tokio::time::timeout(std::time::Duration::from_secs(10), put_rx).await {
-                                        Ok(Ok(Ok(()))) => Ok::<(), String>(()),
-                                        Ok(Ok(Err(e))) => Err(format!("DHT put failed: {}", e)),
-                                        Ok(Err(_)) => Err("DHT response channel closed".to_string()),
-                                        Err(_) => {
-                                            // Timeout - consider it successful if stored locally
-                                            tracing::warn!("DHT put timeout for {}, but stored locally", block_id);
-                                            Ok(())
-                                        }
-                                    }
-                                }.await;
-                                
-                                let _ = reply.send(result);
+                                // The bytes stay here; the DHT only learns that we provide them
+                                // Blocks stored for ourselves count against our own replica quota
+                                let local_peer_id = *self.swarm.local_peer_id();
+                                let stored = match decode_block(&block_id, &data) {
+                                    Ok(_) => blocks.replicas.put(&local_peer_id, &block_id, &data).await.map_err(|e| e.to_string()),
+                                    Err(e) => Err(e),
+                                };
+                                if stored.is_ok() {
+                                    self.announce_provider(&block_id);
+                                }
+                                let _ = reply.send(stored);
+                            }
+                            P2PNodeCommand::ProvideBlock { block_id } => {
+                                self.announce_provider(&block_id.to_string());
                             }
-                            P2PNodeCommand::GetBlock { block_id, reply } => {
-                                // Try local storage first
+                            P2PNodeCommand::GetBlock { block_id, expected_hash, reply } => {
                                 let block_uuid = match Uuid::parse_str(&block_id) {
                                     Ok(uuid) => uuid,
                                     Err(e) => {
@@ -693,43 +1758,220 @@ impl P2PNode {
                                     }
                                 };
                                 
-                                // Check local blocks
-                                {
-                                    let blocks = local_blocks_clone.read().await;
-                                    if let Some(block) = blocks.get(&block_uuid) {
-                                        let data = match bincode::serialize(block) {
-                                            Ok(d) => d,
-                                            Err(e) => {
-                                                let _ = reply.send(Err(format!("Serialization error: {}", e)));
-                                                continue;
-                                            }
-                                        };
+                                // Try local storage first (a copy pushed by a peer may not be the one asked for)
+                                if let Some(data) = blocks.get(&block_uuid.to_string()).await {
+                                    let trusted = match expected_hash {
+                                        Some(hash) => decode_block(&block_id, &data).is_ok_and(|(_, block)| block.matches_hash(&hash)),
+                                        None => true,
+                                    };
+                                    if trusted {
                                         let _ = reply.send(Ok(data));
                                         continue;
                                     }
+                                    warn!("⚠️  Local copy of block {} does not match the expected hash", block_uuid);
                                 }
                                 
-                                // Try DHT with response tracking
-                                let key = RecordKey::new(&block_id.as_bytes());
-                                let (get_tx, get_rx) = tokio::sync::oneshot::channel();
-                                
+                                // Then ask the DHT who provides it; bytes come over request-response
                                 let query_id = self.swarm
                                     .behaviour_mut()
                                     .kademlia
-                                    .get_record(key);
-                                
-                                // Track the query
-                                pending_get_queries.write().await.insert(query_id, get_tx);
-                                
-                                // Wait for DHT response with timeout
-                                let result = match tokio::time::timeout(std::time::Duration::from_secs(10), get_rx).await {
-                                    Ok(Ok(Ok(data))) => Ok(data),
-                                    Ok(Ok(Err(e))) => Err(format!("DHT get failed: {}", e)),
-                                    Ok(Err(_)) => Err("DHT response channel closed".to_string()),
-                                    Err(_) => Err(format!("Block {} not found in local storage or DHT (timeout)", block_id)),
+                                    .get_providers(RecordKey::new(&block_id.as_bytes()));
+                                self.pending_provider_lookups.insert(query_id, ProviderLookup {
+                                    target: FetchTarget::Block { id: block_uuid, expected_hash },
+                                    reply,
+                                    providers: Vec::new(),
+                                });
+                            }
+                            P2PNodeCommand::ProvideChunk { cid } => {
+                                self.announce_provider(&chunk_key(&cid));
+                            }
+                            P2PNodeCommand::GetChunk { cid, reply } => {
+                                if let Some(data) = blocks.get(&chunk_key(&cid)).await {
+                                    if ContentId::from_data(&data).to_hex() == cid {
+                                        let _ = reply.send(Ok(data));
+                                        continue;
+                                    }
+                                    warn!("⚠️  Local copy of chunk {} fails CID verification", cid);
+                                }
+                                let query_id = self.swarm
+                                    .behaviour_mut()
+                                    .kademlia
+                                    .get_providers(RecordKey::new(&chunk_key(&cid).as_bytes()));
+                                self.pending_provider_lookups.insert(query_id, ProviderLookup {
+                                    target: FetchTarget::Chunk { cid },
+                                    reply,
+                                    providers: Vec::new(),
+                                });
+                            }
+                            P2PNodeCommand::FetchChunk { peer, cid, reply } => {
+                                let rx = self.send_tracked_request(peer, P2PRequest::GetChunk { cid });
+                                tokio::spawn(async move {
+                                    let result = match Self::await_response(rx).await {
+                                        Ok(P2PResponse::Chunk { data }) => Ok(data),
+                                        Ok(other) => Err(format!("Unexpected response from {}: {:?}", peer, other)),
+                                        Err(e) => Err(e),
+                                    };
+                                    let _ = reply.send(result);
+                                });
+                            }
+                            P2PNodeCommand::ReplicateChunk { cid, replicas, reply } => {
+                                let key = chunk_key(&cid);
+                                if !blocks.contains(&key).await {
+                                    let _ = reply.send(Err(format!("Chunk {} is not held locally", cid)));
+                                    continue;
+                                }
+                                self.announce_provider(&key);
+
+                                // Receivers pull the chunk back with GetChunk
+                                let targets: Vec<PeerId> = self.swarm.connected_peers()
+                                    .filter(|peer| self.peer_capabilities.get(peer)
+                                        .is_some_and(|caps| caps.accepts_replicas() && caps.supports(features::CHUNKS)))
+                                    .take(replicas)
+                                    .copied()
+                                    .collect();
+                                let responses: Vec<_> = targets.iter()
+                                    .map(|peer| self.send_tracked_request(*peer, P2PRequest::ReplicateChunk { cid: cid.clone() }))
+                                    .map(Self::await_response)
+                                    .collect();
+                                tokio::spawn(async move {
+                                    let accepted = futures::future::join_all(responses).await
+                                        .into_iter()
+                                        .filter(|response| matches!(response, Ok(P2PResponse::ReplicateAccepted { accepted: true })))
+                                        .count();
+                                    let _ = reply.send(Ok(accepted));
+                                });
+                            }
+                            P2PNodeCommand::PutShard { peer, shard_id, data, reply } => {
+                                let rx = self.send_tracked_request(peer, P2PRequest::StoreShard { id: shard_id, data });
+                                tokio::spawn(async move {
+                                    let result = match Self::await_response(rx).await {
+                                        Ok(P2PResponse::ShardStored) => Ok(()),
+                                        Ok(P2PResponse::ShardRefused { reason }) => Err(format!("{} refused the shard: {}", peer, reason)),
+                                        Ok(other) => Err(format!("Unexpected response from {}: {:?}", peer, other)),
+                                        Err(e) => Err(e),
+                                    };
+                                    let _ = reply.send(result);
+                                });
+                            }
+                            P2PNodeCommand::FetchShard { peer, shard_id, reply } => {
+                                let rx = self.send_tracked_request(peer, P2PRequest::GetShard { id: shard_id.clone() });
+                                tokio::spawn(async move {
+                                    let result = match Self::await_response(rx).await {
+                                        Ok(P2PResponse::Shard { data: Some(data) }) => Ok(data),
+                                        Ok(P2PResponse::Shard { data: None }) => Err(format!("Peer {} does not hold shard {}", peer, shard_id)),
+                                        Ok(other) => Err(format!("Unexpected response from {}: {:?}", peer, other)),
+                                        Err(e) => Err(e),
+                                    };
+                                    let _ = reply.send(result);
+                                });
+                            }
+                            P2PNodeCommand::ProbeShard { peer, shard_id, reply } => {
+                                let rx = self.send_tracked_request(peer, P2PRequest::HasShard { id: shard_id });
+                                tokio::spawn(async move {
+                                    let result = match Self::await_response(rx).await {
+                                        Ok(P2PResponse::HasShard { present }) => Ok(present),
+                                        Ok(other) => Err(format!("Unexpected response from {}: {:?}", peer, other)),
+                                        Err(e) => Err(e),
+                                    };
+                                    let _ = reply.send(result);
+                                });
+                            }
+                            P2PNodeCommand::SendRecoveryShare { peer, share, reply } => {
+                                let rx = self.send_tracked_request(peer, P2PRequest::StoreRecoveryShare { share });
+                                tokio::spawn(async move {
+                                    let result = match Self::await_response(rx).await {
+                                        Ok(P2PResponse::RecoveryShareStored { accepted: true }) => Ok(()),
+                                        Ok(P2PResponse::RecoveryShareStored { accepted: false }) => Err(format!("Peer {} refused the recovery share", peer)),
+                                        Ok(other) => Err(format!("Unexpected response from {}: {:?}", peer, other)),
+                                        Err(e) => Err(e),
+                                    };
+                                    let _ = reply.send(result);
+                                });
+                            }
+                            P2PNodeCommand::RequestRecovery { peer, request, reply } => {
+                                let rx = self.send_tracked_request(peer, P2PRequest::RequestRecovery { request });
+                                tokio::spawn(async move {
+                                    let result = match Self::await_response(rx).await {
+                                        Ok(P2PResponse::Recovery { status }) => Ok(status),
+                                        Ok(other) => Err(format!("Unexpected response from {}: {:?}", peer, other)),
+                                        Err(e) => Err(e),
+                                    };
+                                    let _ = reply.send(result);
+                                });
+                            }
+                            P2PNodeCommand::PutRecord { key, value, reply } => {
+                                let record = Record::new(RecordKey::new(&key), value);
+                                match self.swarm.behaviour_mut().kademlia.put_record(record, Quorum::One) {
+                                    Ok(query_id) => {
+                                        self.pending_record_puts.insert(query_id, reply);
+                                    }
+                                    Err(e) => {
+                                        let _ = reply.send(Err(format!("Failed to store record: {:?}", e)));
+                                    }
+                                }
+                            }
+                            P2PNodeCommand::GetRecord { key, reply } => {
+                                let query_id = self.swarm.behaviour_mut().kademlia.get_record(RecordKey::new(&key));
+                                self.pending_record_gets.insert(query_id, RecordLookup { reply, values: Vec::new() });
+                            }
+                            P2PNodeCommand::FetchBlockRange { peer, request, reply } => {
+                                let request_id = self.swarm.behaviour_mut().block_transfer.send_request(&peer, request);
+                                self.pending_transfers.insert(request_id, (std::time::Instant::now(), reply));
+                            }
+                            P2PNodeCommand::ReplicateBlock { block_id, replicas, reply } => {
+                                let hash = blocks.get(&block_id.to_string()).await
+                                    .and_then(|data| decode_block(&block_id.to_string(), &data).ok())
+                                    .and_then(|(_, block)| block.calculate_hash().ok());
+                                let Some(hash) = hash else {
+                                    let _ = reply.send(Err(format!("Block {} is not held locally", block_id)));
+                                    continue;
                                 };
-                                
-                                let _ = reply.send(result);
+                                self.announce_provider(&block_id.to_string());
+
+                                // Receivers pull the bytes back over the block protocol and check them against the hash
+                                let targets: Vec<PeerId> = self.swarm.connected_peers()
+                                    .filter(|peer| self.peer_capabilities.get(peer).is_some_and(PeerCapabilities::accepts_replicas))
+                                    .take(replicas)
+                                    .copied()
+                                    .collect();
+                                let responses: Vec<_> = targets.iter()
+                                    .map(|peer| self.send_tracked_request(*peer, P2PRequest::ReplicateBlock { id: block_id, hash }))
+                                    .map(Self::await_response)
+                                    .collect();
+                                tokio::spawn(async move {
+                                    let accepted = futures::future::join_all(responses).await
+                                        .into_iter()
+                                        .filter(|response| matches!(response, Ok(P2PResponse::ReplicateAccepted { accepted: true })))
+                                        .count();
+                                    let _ = reply.send(Ok(accepted));
+                                });
+                            }
+                            P2PNodeCommand::AddPeer { addr, reply } => {
+                                let _ = reply.send(self.add_peer(addr).await.map_err(|e| e.to_string()));
+                            }
+                            P2PNodeCommand::GetPeerCapabilities(reply) => {
+                                let _ = reply.send(self.peer_capabilities.clone());
+                            }
+                            P2PNodeCommand::GetPeerTable(reply) => {
+                                let _ = reply.send(self.peers.table());
+                            }
+                            P2PNodeCommand::GetRoutingTable(reply) => {
+                                let peers = self.swarm.behaviour_mut().kademlia.kbuckets()
+                                    .flat_map(|bucket| bucket.iter().map(|entry| *entry.node.key.preimage()).collect::<Vec<_>>())
+                                    .collect();
+                                let _ = reply.send(peers);
+                            }
+                            P2PNodeCommand::ReportPeer { peer, event } => {
+                                self.report_peer(peer, event);
+                            }
+                            P2PNodeCommand::BanPeer { peer } => {
+                                self.peers.ban(peer);
+                                self.block_peer(peer);
+                            }
+                            P2PNodeCommand::UnbanPeer { peer, reply } => {
+                                let unbanned = self.peers.unban(&peer);
+                                self.swarm.behaviour_mut().blocked_peers.unblock_peer(peer);
+                                let _ = reply.send(unbanned);
                             }
                         }
                     }
@@ -737,21 +1979,360 @@ impl P2PNode {
             }
         });
 
-        Ok((event_receiver, local_blocks_return))
+        Ok((event_receiver, replicas))
     }
 
+    /// Listen through relay server `relay` so peers can reach this node behind NAT
+    fn reserve_relay(&mut self, relay: PeerId, relay_addrs: &[Multiaddr], config: &P2PConfig) {
+        if config.relay_server.is_some()
+            || self.relay_reservations.contains(&relay)
+            || self.relay_reservations.len() >= MAX_RELAY_RESERVATIONS
+            || matches!(self.swarm.behaviour().autonat.nat_status(), libp2p::autonat::NatStatus::Public(_))
+        {
+            return;
+        }
+        // The address we reached the relay on, else any routable direct address it listens on
+        let routable = |addr: &&Multiaddr| addr.iter().all(|p| match p {
+            libp2p::multiaddr::Protocol::Ip6(ip) => (ip.segments()[0] & 0xffc0) != 0xfe80,
+            libp2p::multiaddr::Protocol::P2pCircuit => false,
+            _ => true,
+        });
+        let is_loopback = |addr: &&Multiaddr| addr.iter().any(|p| match p {
+            libp2p::multiaddr::Protocol::Ip4(ip) => ip.is_loopback(),
+            libp2p::multiaddr::Protocol::Ip6(ip) => ip.is_loopback(),
+            _ => false,
+        });
+        let dialed = self.dialed_addrs.get(&relay).map(|addr| {
+            addr.iter().filter(|p| !matches!(p, libp2p::multiaddr::Protocol::P2p(_))).collect::<Multiaddr>()
+        });
+        let Some(addr) = dialed.or_else(|| relay_addrs.iter()
+            .filter(routable)
+            .min_by_key(is_loopback)
+            .cloned())
+        else {
+            return;
+        };
+        let circuit = addr
+            .with(libp2p::multiaddr::Protocol::P2p(relay))
+            .with(libp2p::multiaddr::Protocol::P2pCircuit);
+        match self.swarm.listen_on(circuit.clone()) {
+            Ok(_) => {
+                info!("🛰️  Requesting relay reservation at {}", circuit);
+                self.relay_reservations.insert(relay);
+            }
+            Err(e) => debug!("Failed to listen via relay {}: {}", relay, e),
+        }
+    }
+
+    /// Score an observation about `peer`, banning it once the score falls too low
+    fn report_peer(&mut self, peer: PeerId, event: PeerEvent) {
+        if self.peers.record(peer, event) {
+            warn!("🚫 Banning {} (score {:.1} after {:?})", peer, self.peers.score(&peer), event);
+            self.block_peer(peer);
+        }
+    }
+
+    /// Close all connections to `peer` and refuse new ones until unblocked
+    fn block_peer(&mut self, peer: PeerId) {
+        self.swarm.behaviour_mut().blocked_peers.block_peer(peer);
+        self.peer_capabilities.remove(&peer);
+    }
+
+    /// Remember what `peer` negotiated and tell the listeners
+    fn record_capabilities(&mut self, peer: PeerId, remote: &NodeInfo, events: &mpsc::UnboundedSender<P2PEvent>) {
+        if let Some(private) = &self.private_network {
+            if !private.admits(&peer, remote.attestation.as_ref()) {
+                warn!("🔒 {} is not on the private swarm's allow-list, disconnecting", peer);
+                self.unadmitted.remove(&peer);
+                self.block_peer(peer);
+                return;
+            }
+        }
+        if let Some(pending) = self.unadmitted.remove(&peer) {
+            for addr in pending.addresses {
+                self.swarm.behaviour_mut().kademlia.add_address(&peer, addr);
+            }
+        }
+        let capabilities = self.local_info.negotiate(remote);
+        info!("🤝 {} speaks v{} as {:?} ({} shared features)",
+            peer, capabilities.protocol_version, capabilities.role, capabilities.features.len());
+        self.peer_capabilities.insert(peer, capabilities.clone());
+        let _ = self.event_sender.send(P2PEvent::PeerIdentified { peer, capabilities: capabilities.clone() });
+        let _ = events.send(P2PEvent::PeerIdentified { peer, capabilities });
+    }
+
+    /// Whether a connection from `peer` waits for an admitted handshake before it is served
+    fn needs_admission(&self, peer: &PeerId) -> bool {
+        self.private_network.as_ref().is_some_and(|private| private.requires_handshake() && !private.allows(peer))
+    }
+
+    /// Whether `peer` is not waiting for admission
+    fn is_admitted(&self, peer: &PeerId) -> bool {
+        !self.unadmitted.contains_key(peer)
+    }
+
+    /// Disconnect a peer that has not been admitted
+    fn drop_unadmitted(&mut self, peer: PeerId, reason: &str) {
+        warn!("🔒 Disconnecting {}: {}", peer, reason);
+        let _ = self.swarm.disconnect_peer_id(peer);
+    }
+
+    /// Route to `peer` at `addr`, or keep the address until the peer is admitted
+    fn add_routing_address(&mut self, peer: PeerId, addr: Multiaddr) {
+        match self.unadmitted.get_mut(&peer) {
+            Some(pending) => pending.addresses.push(addr),
+            None => {
+                self.swarm.behaviour_mut().kademlia.add_address(&peer, addr);
+            }
+        }
+    }
+
+    /// Whether `peer` may place replicas here: this node stores data and the peer
+    /// negotiated replication in its handshake
+    fn accepts_replicas_from(&self, peer: &PeerId) -> bool {
+        self.local_info.role.stores_data()
+            && self.peer_capabilities.get(peer).is_some_and(PeerCapabilities::accepts_replicas)
+    }
+
+    /// Fetch block `block_id` with `hash` from `peer` and keep it as a replica placed by that peer
+    fn pull_replica(&mut self, peer: PeerId, block_id: Uuid, hash: [u8; 32], events: mpsc::UnboundedSender<P2PEvent>) {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let target = FetchTarget::Block { id: block_id, expected_hash: Some(hash) };
+        self.fetch_from_providers(ProviderLookup { target, reply: tx, providers: Vec::new() }, vec![peer], events);
+        let replicas = self.blocks.replicas.clone();
+        let commands = self.command_sender.clone();
+        tokio::spawn(async move {
+            match rx.await {
+                Ok(Ok(data)) => match replicas.put(&peer, &block_id.to_string(), &data).await {
+                    Ok(()) => {
+                        debug!("📥 Keeping replica of block {} from {}", block_id, peer);
+                        let _ = commands.send(P2PNodeCommand::ProvideBlock { block_id });
+                    }
+                    Err(e) => warn!("⚠️  Refused replica of block {} from {}: {}", block_id, peer, e),
+                },
+                Ok(Err(e)) => warn!("⚠️  Replica of block {} from {} failed: {}", block_id, peer, e),
+                Err(_) => {}
+            }
+        });
+    }
+
+    /// Fetch chunk `cid` from `peer` and keep it as a replica placed by that peer
+    fn pull_chunk_replica(&mut self, peer: PeerId, cid: String) {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.fetch_chunk(cid.clone(), tx, vec![peer]);
+        let replicas = self.blocks.replicas.clone();
+        let commands = self.command_sender.clone();
+        tokio::spawn(async move {
+            match rx.await {
+                Ok(Ok(data)) => match replicas.put(&peer, &chunk_key(&cid), &data).await {
+                    Ok(()) => {
+                        debug!("📥 Keeping replica of chunk {} from {}", cid, peer);
+                        let _ = commands.send(P2PNodeCommand::ProvideChunk { cid });
+                    }
+                    Err(e) => warn!("⚠️  Failed to store replica of chunk {}: {}", cid, e),
+                },
+                Ok(Err(e)) => warn!("⚠️  Replica of chunk {} from {} failed: {}", cid, peer, e),
+                Err(_) => {}
+            }
+        });
+    }
+
+    /// Announce this node as a provider of `block_id`
+    fn announce_provider(&mut self, block_id: &str) {
+        if let Err(e) = self.swarm
+            .behaviour_mut()
+            .kademlia
+            .start_providing(RecordKey::new(&block_id.as_bytes()))
+        {
+            warn!("⚠️  Failed to announce block {}: {:?}", block_id, e);
+        }
+    }
+
+    /// Store the records and provider announcements peers push, once checked
+    fn handle_inbound_record(&mut self, request: libp2p::kad::InboundRequest) {
+        use libp2p::kad::store::RecordStore;
+
+        let store = self.swarm.behaviour_mut().kademlia.store_mut();
+        match request {
+            libp2p::kad::InboundRequest::PutRecord { source, .. } if self.unadmitted.contains_key(&source) => {
+                debug!("🔒 Ignoring record from {} before its handshake", source);
+            }
+            libp2p::kad::InboundRequest::AddProvider { record: Some(provider), .. } if self.unadmitted.contains_key(&provider.provider) => {
+                debug!("🔒 Ignoring provider record from {} before its handshake", provider.provider);
+            }
+            libp2p::kad::InboundRequest::PutRecord { source, record: Some(record), .. } => {
+                if !accept_record(&record) {
+                    warn!("❌ Refused invalid record from {}", source);
+                    self.report_peer(source, PeerEvent::ProtocolViolation);
+                } else if let Err(e) = store.put(record) {
+                    debug!("⚠️  Could not store record from {}: {:?}", source, e);
+                }
+            }
+            libp2p::kad::InboundRequest::AddProvider { record: Some(provider), .. } => {
+                if let Err(e) = store.add_provider(provider) {
+                    debug!("⚠️  Could not store provider record: {:?}", e);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Providers to try for `lookup`, connected ones first
+    fn take_fetch_providers(&self, lookup: &mut ProviderLookup) -> Vec<PeerId> {
+        let mut providers = std::mem::take(&mut lookup.providers);
+        providers.sort_by_key(|peer| !self.swarm.is_connected(peer));
+        providers.truncate(MAX_PROVIDERS_PER_FETCH);
+        providers
+    }
+
+    /// Fetch the target of `lookup` from `providers`
+    fn fetch_from_providers(&mut self, lookup: ProviderLookup, providers: Vec<PeerId>, events: mpsc::UnboundedSender<P2PEvent>) {
+        match lookup.target {
+            FetchTarget::Block { id, expected_hash } => self.stream_block(id, expected_hash, lookup.reply, providers, events),
+            FetchTarget::Chunk { cid } => self.fetch_chunk(cid, lookup.reply, providers),
+        }
+    }
+
+    /// Fetch a sealed chunk whole from `providers` in turn; copies that do not hash to
+    /// `cid` count against the provider
+    fn fetch_chunk(
+        &mut self,
+        cid: String,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<Vec<u8>, String>>,
+        providers: Vec<PeerId>,
+    ) {
+        let commands = self.command_sender.clone();
+        tokio::spawn(async move {
+            for peer in providers {
+                let (tx, rx) = tokio::sync::oneshot::channel();
+                if commands.send(P2PNodeCommand::FetchChunk { peer, cid: cid.clone(), reply: tx }).is_err() {
+                    let _ = reply.send(Err("P2P node stopped".to_string()));
+                    return;
+                }
+                match rx.await {
+                    Ok(Ok(Some(data))) if ContentId::from_data(&data).to_hex() == cid => {
+                        let _ = reply.send(Ok(data));
+                        return;
+                    }
+                    Ok(Ok(Some(_))) => {
+                        warn!("⚠️  Provider {} returned chunk bytes that fail verification as {}", peer, cid);
+                        let _ = commands.send(P2PNodeCommand::ReportPeer { peer, event: PeerEvent::BadData });
+                    }
+                    // A stale provider record is not the peer's fault
+                    Ok(Ok(None)) => debug!("⚠️  Peer {} does not hold chunk {}", peer, cid),
+                    Ok(Err(e)) => debug!("⚠️  Chunk {} from {} failed: {}", cid, peer, e),
+                    Err(_) => {}
+                }
+            }
+            let _ = reply.send(Err(format!("No provider returned chunk {}", cid)));
+        });
+    }
+
+    /// Stream a block from `providers` one frame at a time, resuming on the next
+    /// provider from the last received offset when one fails
+    fn stream_block(
+        &mut self,
+        block_id: Uuid,
+        expected_hash: Option<[u8; 32]>,
+        reply: tokio::sync::oneshot::Sender<std::result::Result<Vec<u8>, String>>,
+        providers: Vec<PeerId>,
+        events: mpsc::UnboundedSender<P2PEvent>,
+    ) {
+        let commands = self.command_sender.clone();
+        let event_sender = self.event_sender.clone();
+        tokio::spawn(async move {
+            let mut assembler = BlockAssembler::new(block_id);
+            for peer in providers {
+                // One frame in flight at a time: the next range is asked for only once
+                // the previous one has been taken in
+                while let Some(request) = assembler.next_request() {
+                    let (tx, rx) = tokio::sync::oneshot::channel();
+                    if commands.send(P2PNodeCommand::FetchBlockRange { peer, request, reply: tx }).is_err() {
+                        let _ = reply.send(Err("P2P node stopped".to_string()));
+                        return;
+                    }
+                    let frame = match tokio::time::timeout(std::time::Duration::from_secs(30), rx).await {
+                        Ok(Ok(result)) => result,
+                        Ok(Err(_)) => Err("Request channel closed".to_string()),
+                        Err(_) => Err("Request timed out".to_string()),
+                    };
+                    let result = match frame {
+                        // A stale provider record is not the peer's fault
+                        Ok(BlockTransferResponse::NotFound) => Err(format!("Peer does not hold block {}", block_id)),
+                        Ok(frame) => assembler.accept(frame).inspect_err(|_| {
+                            let _ = commands.send(P2PNodeCommand::ReportPeer { peer, event: PeerEvent::ProtocolViolation });
+                        }),
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        debug!("⚠️  Block {} from {} stopped at byte {}: {}", block_id, peer, assembler.received(), e);
+                        break;
+                    }
+                }
+
+                if assembler.is_complete() {
+                    let data = assembler.finish().unwrap_or_default();
+                    match bincode::deserialize::<DataBlock>(&data) {
+                        Ok(block) if block.uuid == block_id
+                            && block.verify()
+                            && expected_hash.is_none_or(|hash| block.matches_hash(&hash)) =>
+                        {
+                            let _ = event_sender.send(P2PEvent::BlockReceived { peer, block: block.clone() });
+                            let _ = events.send(P2PEvent::BlockReceived { peer, block });
+                            let _ = reply.send(Ok(data));
+                            return;
+                        }
+                        Ok(block) => warn!("⚠️  Provider {} returned block {} that fails verification as {}", peer, block.uuid, block_id),
+                        Err(e) => warn!("⚠️  Provider {} returned an invalid block: {}", peer, e),
+                    }
+                    // Bad bytes count against the provider; start over on the next one
+                    let _ = commands.send(P2PNodeCommand::ReportPeer { peer, event: PeerEvent::BadData });
+                    assembler = BlockAssembler::new(block_id);
+                }
+            }
+            let _ = reply.send(Err(format!("No provider returned block {}", block_id)));
+        });
+    }
+
+    /// Send a request to `peer` and register it so the response is routed back
+    fn send_tracked_request(
+        &mut self,
+        peer: PeerId,
+        request: P2PRequest,
+    ) -> tokio::sync::oneshot::Receiver<std::result::Result<P2PResponse, String>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let request_id = self.swarm.behaviour_mut().request_response.send_request(&peer, request);
+        self.pending_requests.insert(request_id, (std::time::Instant::now(), tx));
+        rx
+    }
+
+    /// Wait for a tracked response, giving up after 30 seconds
+    async fn await_response(
+        rx: tokio::sync::oneshot::Receiver<std::result::Result<P2PResponse, String>>,
+    ) -> std::result::Result<P2PResponse, String> {
+        match tokio::time::timeout(std::time::Duration::from_secs(30), rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err("Request channel closed".to_string()),
+            Err(_) => Err("Request timed out".to_string()),
+        }
+    }
 
     /// Bootstrap the DHT with retry logic and relay reservation (static method)
     async fn bootstrap_static(swarm: &mut Swarm<P2PBehaviour>, config: &P2PConfig) -> std::result::Result<(), MSSCSError> {
-        if !config.bootstrap_peers.is_empty() {
-            info!("🌍 Bootstrapping DHT with {} public peers for internet-wide connectivity", config.bootstrap_peers.len());
-            info!("   Using IPFS public bootstrap nodes for global reach");
+        let bootstrap_peers = config.bootstrap_addresses();
+        if !bootstrap_peers.is_empty() {
+            if config.private_network.is_some() {
+                info!("🔒 Bootstrapping private DHT from {} local peers", bootstrap_peers.len());
+            } else {
+                info!("🌍 Bootstrapping DHT with {} public peers for internet-wide connectivity", bootstrap_peers.len());
+                info!("   Using IPFS public bootstrap nodes for global reach");
+            }
             
             let mut connected_count = 0;
             let mut relay_peers = Vec::new();
             
             // Dial all bootstrap peers concurrently for faster connection
-            for addr in &config.bootstrap_peers {
+            for addr in &bootstrap_peers {
                 match swarm.dial(addr.clone()) {
                     Ok(_) => {
                         info!("   📡 Dialing bootstrap peer: {}", addr);
@@ -823,40 +2404,37 @@ impl P2PNode {
         Ok(())
     }
 
-    /// Store block in local storage and replicate to network
+    /// Store block in local storage and announce it to the network
     pub async fn store_block(&mut self, block_id: String, block_data: Vec<u8>) -> std::result::Result<(), MSSCSError> {
-        // Provide to DHT for global replication
-        let key = RecordKey::new(&block_id.as_bytes());
-        let record = Record {
-            key,
-            value: block_data,
-            publisher: None,
-            expires: None,
-        };
-
-        let query_id = self.swarm
-            .behaviour_mut()
-            .kademlia
-            .put_record(record, Quorum::One)
-            .map_err(|e| MSSCSError::Network(format!("Failed to put record: {:?}", e)))?;
-
-        debug!("Storing block {} in DHT with query ID: {:?}", block_id, query_id);
+        let uuid = Uuid::parse_str(&block_id)
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid block ID: {}", e)))?;
+        let block: DataBlock = bincode::deserialize(&block_data)?;
+        if block.uuid != uuid {
+            return Err(MSSCSError::InvalidData(format!("Block bytes are for {}, not {}", block.uuid, uuid)));
+        }
+        let local_peer_id = *self.swarm.local_peer_id();
+        self.blocks.replicas.put(&local_peer_id, &block_id, &block_data).await?;
+        self.announce_provider(&block_id);
 
+        debug!("Providing block {} on the DHT", block_id);
         Ok(())
     }
 
     /// Request block from network
     pub async fn get_block(&mut self, block_id: &str) -> std::result::Result<Vec<u8>, MSSCSError> {
-        // Try to get from DHT first
-        let key = RecordKey::new(&block_id.as_bytes());
+        let uuid = Uuid::parse_str(block_id)
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid block ID: {}", e)))?;
+        if let Some(data) = self.blocks.get(&uuid.to_string()).await {
+            return Ok(data);
+        }
+
+        // Without the running event loop the provider lookup cannot be awaited here;
+        // use the GetBlock command for a full fetch
         let _query_id = self.swarm
             .behaviour_mut()
             .kademlia
-            .get_record(key);
-
-        // In a real implementation, we'd wait for the DHT response
-        // For now, return an error to trigger peer requests
-        Err(MSSCSError::NotFound(format!("Block {} not in DHT yet", block_id)))
+            .get_providers(RecordKey::new(&block_id.as_bytes()));
+        Err(MSSCSError::NotFound(format!("Block {} not held locally, provider lookup started", block_id)))
     }
     
     /// Request block from connected peers
@@ -868,15 +2446,14 @@ impl P2PNode {
             return Err(MSSCSError::Network("No peers connected".to_string()));
         }
         
+        let uuid = Uuid::parse_str(block_id)
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid block ID: {}", e)))?;
+
         for peer in peers {
-            // Parse block_id as UUID for the request
-            let uuid = Uuid::parse_str(block_id)
-                .map_err(|e| MSSCSError::InvalidData(format!("Invalid block ID: {}", e)))?;
-            
             let _request_id = self.swarm
                 .behaviour_mut()
-                .request_response
-                .send_request(&peer, P2PRequest::GetBlock { id: uuid });
+                .block_transfer
+                .send_request(&peer, BlockRangeRequest::first(uuid));
 
             debug!("Sent block request to peer {}: {}", peer, block_id);
         }
@@ -908,4 +2485,250 @@ impl P2PNode {
             .send_request(&peer_id, P2PRequest::Ping);
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::SimCluster;
+    use std::time::Duration;
+
+    /// Control messages of a version 1 node
+    #[derive(Debug, Serialize, Deserialize)]
+    enum V1Request {
+        GetBlock { id: Uuid },
+        Ping,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum V1Response {
+        Block { data: Option<serde_json::Value> },
+        Pong,
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct V1Codec;
+
+    #[async_trait::async_trait]
+    impl request_response::Codec for V1Codec {
+        type Protocol = &'static str;
+        type Request = V1Request;
+        type Response = V1Response;
+
+        async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Request>
+        where
+            T: futures::AsyncRead + Unpin + Send
+        {
+            protocol::read_json(io, MAX_CONTROL_MESSAGE_BYTES).await
+        }
+
+        async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Response>
+        where
+            T: futures::AsyncRead + Unpin + Send
+        {
+            protocol::read_json(io, MAX_CONTROL_MESSAGE_BYTES).await
+        }
+
+        async fn write_request<T>(&mut self, _: &Self::Protocol, io: &mut T, req: Self::Request) -> std::io::Result<()>
+        where
+            T: futures::AsyncWrite + Unpin + Send
+        {
+            protocol::write_json(io, &req).await
+        }
+
+        async fn write_response<T>(&mut self, _: &Self::Protocol, io: &mut T, res: Self::Response) -> std::io::Result<()>
+        where
+            T: futures::AsyncWrite + Unpin + Send
+        {
+            protocol::write_json(io, &res).await
+        }
+    }
+
+    /// A peer that speaks the control protocol but never answers the handshake
+    #[derive(NetworkBehaviour)]
+    struct SilentPeer {
+        control: request_response::Behaviour<P2PCodec>,
+        meta: request_response::Behaviour<MetaCodec>,
+    }
+
+    #[tokio::test]
+    async fn test_version_1_peer_is_not_banned() {
+        let network = SimNetwork::new(21);
+        let cluster = SimCluster::start(network.clone(), 1).await.unwrap();
+        let node = cluster.node(0);
+
+        // A version 1 node only speaks the old control protocol
+        let keypair = identity::Keypair::generate_ed25519();
+        let v1_peer = keypair.public().to_peer_id();
+        let mut swarm = SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio()
+            .with_other_transport(|keypair| network.transport(keypair))
+            .unwrap()
+            .with_behaviour(|_| request_response::Behaviour::<V1Codec>::new(
+                std::iter::once(("/msscs/1.0.0", ProtocolSupport::Full)),
+                request_response::Config::default(),
+            ))
+            .unwrap()
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+        swarm.listen_on(network.listen_address(&v1_peer).unwrap()).unwrap();
+        swarm.dial(node.address.clone()).unwrap();
+
+        // Two malformed requests would get the peer banned; block requests are answered empty instead
+        let responses = tokio::time::timeout(Duration::from_secs(30), async {
+            let mut responses = Vec::new();
+            loop {
+                match swarm.select_next_some().await {
+                    SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == node.peer_id => {
+                        for _ in 0..2 {
+                            swarm.behaviour_mut().send_request(&peer_id, V1Request::GetBlock { id: Uuid::new_v4() });
+                        }
+                        swarm.behaviour_mut().send_request(&peer_id, V1Request::Ping);
+                    }
+                    SwarmEvent::Behaviour(request_response::Event::Message {
+                        message: request_response::Message::Response { response, .. }, ..
+                    }) => {
+                        responses.push(response);
+                        if responses.len() == 3 {
+                            return responses;
+                        }
+                    }
+                    SwarmEvent::Behaviour(request_response::Event::OutboundFailure { error, .. }) => {
+                        panic!("version 1 request failed: {:?}", error);
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .expect("version 1 requests were not answered");
+        let blocks = responses.iter().filter(|r| matches!(r, V1Response::Block { data: None })).count();
+        assert_eq!(blocks, 2);
+        assert!(responses.iter().any(|r| matches!(r, V1Response::Pong)));
+
+        let (reply, table) = tokio::sync::oneshot::channel();
+        node.commands().send(P2PNodeCommand::GetPeerTable(reply)).unwrap();
+        let record = table.await.unwrap()
+            .into_iter()
+            .find(|record| record.peer_id == v1_peer.to_string())
+            .expect("version 1 peer is not in the peer table");
+        assert_eq!(record.banned_for_secs, None);
+        assert_eq!(record.stats.protocol_violations, 0);
+    }
+
+    #[tokio::test]
+    async fn test_identity_allow_list_drops_other_identities() {
+        use crate::identity::QuantumIdentity;
+        use crate::private_network::{IdentityAttestation, PreSharedKey};
+        use crate::simulation::wait_until;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let identity = |name: &str| QuantumIdentity::new(name.to_string(), "passphrase").unwrap().unlock("passphrase").unwrap();
+        let (member, outsider) = (identity("member"), identity("outsider"));
+
+        // Node 0 only admits nodes run by `member`; node 1 is, node 2 belongs to `outsider`
+        let cluster = SimCluster::start_with(SimNetwork::new(22), 3, |index, config| {
+            let key_file = dir.path().join(format!("node-{}.key", index));
+            let peer_id = node_peer_id(&key_file).unwrap();
+            let mut private = PrivateNetworkConfig::new(PreSharedKey::new([9; 32]));
+            match index {
+                0 => {
+                    private.allowed_identities.insert(member.identity.public_key.clone().try_into().unwrap());
+                }
+                1 => private.attestation = Some(IdentityAttestation::sign(&member, &peer_id).unwrap()),
+                _ => private.attestation = Some(IdentityAttestation::sign(&outsider, &peer_id).unwrap()),
+            }
+            config.key_file = Some(key_file);
+            config.private_network = Some(private);
+        })
+        .await
+        .unwrap();
+        let (member_node, outsider_node) = (cluster.node(1).peer_id, cluster.node(2).peer_id);
+
+        let capabilities = || async {
+            let (reply, capabilities) = tokio::sync::oneshot::channel();
+            cluster.node(0).commands().send(P2PNodeCommand::GetPeerCapabilities(reply)).unwrap();
+            capabilities.await.unwrap()
+        };
+        wait_until(Duration::from_secs(30), || async { capabilities().await.contains_key(&member_node) })
+            .await
+            .expect("member node was not admitted");
+
+        // The outsider connects as early as the member does, but does not stay
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(!capabilities().await.contains_key(&outsider_node));
+        let peers = cluster.connected_peers(0).await.unwrap();
+        assert!(peers.contains(&member_node));
+        assert!(!peers.contains(&outsider_node));
+        let routing = cluster.routing_table(0).await.unwrap();
+        assert!(routing.contains(&member_node));
+        assert!(!routing.contains(&outsider_node));
+    }
+
+    #[tokio::test]
+    async fn test_peer_without_handshake_is_not_served() {
+        use crate::identity::QuantumIdentity;
+        use crate::private_network::PreSharedKey;
+
+        let network = SimNetwork::new(25);
+        let member = QuantumIdentity::new("member".to_string(), "passphrase").unwrap();
+        let cluster = SimCluster::start_with(network.clone(), 1, |_, config| {
+            let mut private = PrivateNetworkConfig::new(PreSharedKey::new([9; 32]));
+            private.allowed_identities.insert(member.public_key.clone().try_into().unwrap());
+            config.private_network = Some(private);
+        })
+        .await
+        .unwrap();
+        let node = cluster.node(0);
+
+        let keypair = identity::Keypair::generate_ed25519();
+        let silent_peer = keypair.public().to_peer_id();
+        let mut swarm = SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio()
+            .with_other_transport(|keypair| network.transport(keypair))
+            .unwrap()
+            .with_behaviour(|_| SilentPeer {
+                control: request_response::Behaviour::new(
+                    std::iter::once((protocol::CONTROL_PROTOCOL, ProtocolSupport::Full)),
+                    request_response::Config::default(),
+                ),
+                meta: request_response::Behaviour::new(
+                    std::iter::once((protocol::META_PROTOCOL, ProtocolSupport::Full)),
+                    request_response::Config::default(),
+                ),
+            })
+            .unwrap()
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+        swarm.listen_on(network.listen_address(&silent_peer).unwrap()).unwrap();
+        swarm.dial(node.address.clone()).unwrap();
+
+        // The ping goes unanswered and the node hangs up once the handshake deadline passes
+        let mut handshakes = Vec::new();
+        let answered = tokio::time::timeout(Duration::from_secs(30), async {
+            let mut answered = false;
+            loop {
+                match swarm.select_next_some().await {
+                    SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == node.peer_id => {
+                        swarm.behaviour_mut().control.send_request(&peer_id, P2PRequest::Ping);
+                    }
+                    SwarmEvent::Behaviour(SilentPeerEvent::Meta(request_response::Event::Message {
+                        message: request_response::Message::Request { channel, .. }, ..
+                    })) => handshakes.push(channel),
+                    SwarmEvent::Behaviour(SilentPeerEvent::Control(request_response::Event::Message {
+                        message: request_response::Message::Response { .. }, ..
+                    })) => answered = true,
+                    SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } if peer_id == node.peer_id => {
+                        return answered;
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .expect("peer without a handshake stayed connected");
+        assert!(!answered);
+        assert!(!handshakes.is_empty());
+        assert!(!cluster.routing_table(0).await.unwrap().contains(&silent_peer));
+    }
+}
@@ -1,35 +1,50 @@
 // P2P-ENABLED VIRTUAL FILE SYSTEM
 // Integrates quantum-encrypted storage with global P2P network
 
+use crate::block_store::BlockStore;
 use crate::error::{MSSCSError, Result};
-use crate::identity::UnlockedIdentity;
-use crate::quantum_block::QuantumDataBlock;
+use crate::quantum_block::{KeyHolder, QuantumDataBlock};
 use crate::erasure::{ErasureCoding, Shard};
-use crate::singularity::SingularityFragmentation;
-use crate::p2p_network::P2PNode;
-use crate::adaptive_compression::{AdaptiveCompression, CompressionLevel};
+use crate::shard_placement::{ShardHealth, ShardLocations};
+use crate::singularity::{SingularityFragmentation, SingularityShard};
+use crate::adaptive_compression::{AdaptiveCompression, CompressionAlgorithm, CompressionLevel};
 use crate::parallel::ParallelBlockProcessor;
 use crate::pinning::{PinningManager, PinType};
-use std::collections::HashMap;
+use crate::namespace::{DirEntry, FileContent, FileStat, FileVersion, Namespace, RetentionPolicy};
+use crate::snapshot::{SnapshotDiff, SnapshotInfo, SnapshotSet};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+/// Block store manifest holding the directory tree
+const NAMESPACE_MANIFEST_NAME: &str = "namespace";
+/// Block store manifest holding the serialized pinning state
+const PINS_MANIFEST_NAME: &str = "pins";
+/// Block store manifest holding named snapshots
+const SNAPSHOTS_MANIFEST_NAME: &str = "snapshots";
+/// Block store manifest recording which peer holds each shard
+const SHARD_LOCATIONS_MANIFEST_NAME: &str = "shard-locations";
+
 /// P2P-enabled Virtual File System
 pub struct P2PVirtualFileSystem {
-    /// User identity (quantum-resistant)
-    identity: Arc<UnlockedIdentity>,
+    /// Keys of the user identity (quantum-resistant), unlocked here or held by the identity agent
+    keys: Arc<dyn KeyHolder>,
     
     /// P2P command sender (for async operations)
     p2p_command_tx: Option<tokio::sync::mpsc::UnboundedSender<crate::p2p_network::P2PNodeCommand>>,
     
-    /// File manifest (path -> first block UUID)
-    file_manifest: Arc<RwLock<HashMap<String, Uuid>>>,
+    /// Directory tree (path -> file inode -> first block UUID)
+    namespace: Arc<RwLock<Namespace>>,
     
-    /// Local block cache (block_id -> block)
+    /// Blocks read or written since startup (block_id -> block); the block store holds the rest
     local_blocks: Arc<RwLock<HashMap<String, QuantumDataBlock>>>,
     
+    /// Durable local storage backing the block cache
+    block_store: Arc<dyn BlockStore>,
+    
     /// Erasure coding configuration
     erasure: ErasureCoding,
     
@@ -45,84 +60,477 @@ pub struct P2PVirtualFileSystem {
     /// Block pinning manager
     pinning: Arc<RwLock<PinningManager>>,
     
-    /// Chunk size for splitting files
-    chunk_size: usize,
+    /// Version retention enforced during orphan cleanup
+    retention: Arc<RwLock<RetentionPolicy>>,
+    
+    /// Named read-only snapshots (their blocks are GC roots)
+    snapshots: Arc<RwLock<SnapshotSet>>,
+    
+    /// Peer holding each erasure-coded shard
+    shard_locations: Arc<RwLock<ShardLocations>>,
 }
 
 impl P2PVirtualFileSystem {
     /// Create new P2P VFS with all advanced features and configurable storage allocation
-    pub fn new(
-        identity: Arc<UnlockedIdentity>,
+    pub async fn new(
+        keys: Arc<dyn KeyHolder>,
         p2p_command_tx: Option<tokio::sync::mpsc::UnboundedSender<crate::p2p_network::P2PNodeCommand>>,
         chunk_size: usize,
+        block_store: Arc<dyn BlockStore>,
     ) -> Result<Self> {
-        Self::new_with_storage_limit(identity, p2p_command_tx, chunk_size, 100 * 1024 * 1024) // Default 100MB
+        Self::new_with_storage_limit(keys, p2p_command_tx, chunk_size, 100 * 1024 * 1024, block_store).await // Default 100MB
     }
     
     /// Create new P2P VFS with custom storage allocation (in bytes)
-    pub fn new_with_storage_limit(
-        identity: Arc<UnlockedIdentity>,
+    /// Manifest, pins and locally held blocks are reloaded from the block store
+    pub async fn new_with_storage_limit(
+        keys: Arc<dyn KeyHolder>,
         p2p_command_tx: Option<tokio::sync::mpsc::UnboundedSender<crate::p2p_network::P2PNodeCommand>>,
         chunk_size: usize,
         storage_limit_bytes: usize,
+        block_store: Arc<dyn BlockStore>,
     ) -> Result<Self> {
         let erasure = ErasureCoding::new(10, 4)?;
         let singularity = SingularityFragmentation::new(3, 5)?;
         let compression = AdaptiveCompression::new(CompressionLevel::Balanced, 1024);
-        let parallel = ParallelBlockProcessor::default();
+        let parallel = ParallelBlockProcessor::new(num_cpus::get(), chunk_size);
         let pinning = Arc::new(RwLock::new(PinningManager::new(storage_limit_bytes)));
         
         tracing::info!("🚀 Initializing P2P VFS with advanced features:");
         tracing::info!("   ✓ Storage allocation: {} MB", storage_limit_bytes / (1024 * 1024));
-        tracing::info!("   ✓ Erasure coding: 10+4 (40% overhead, tolerates 4 failures)");
+        tracing::info!("   ✓ Erasure coding: {}+{} ({:.0}% overhead, tolerates {} failures)",
+            erasure.data_shards(), erasure.parity_shards(), erasure.overhead_percentage(), erasure.max_failures());
         tracing::info!("   ✓ Singularity fragmentation: 3-of-5 threshold");
         tracing::info!("   ✓ Adaptive compression: Balanced mode");
         tracing::info!("   ✓ Parallel processing: {} threads", parallel.worker_threads);
         tracing::info!("   ✓ Block pinning enabled");
+        tracing::info!("   ✓ Block store: {}", block_store.backend_name());
         
-        Ok(Self {
-            identity,
+        let vfs = Self {
+            keys,
             p2p_command_tx,
-            file_manifest: Arc::new(RwLock::new(HashMap::new())),
+            namespace: Arc::new(RwLock::new(Namespace::new())),
             local_blocks: Arc::new(RwLock::new(HashMap::new())),
+            block_store,
             erasure,
             singularity,
             compression,
             parallel,
             pinning,
-            chunk_size,
-        })
+            retention: Arc::new(RwLock::new(RetentionPolicy::default())),
+            snapshots: Arc::new(RwLock::new(SnapshotSet::new())),
+            shard_locations: Arc::new(RwLock::new(ShardLocations::new())),
+        };
+        vfs.load_state(storage_limit_bytes).await?;
+        
+        Ok(vfs)
     }
     
-    /// Store block on P2P network via command channel
-    async fn store_block_p2p(&self, block_id: String, data: Vec<u8>) -> Result<()> {
-        if let Some(ref tx) = self.p2p_command_tx {
-            let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
-            
-            if let Err(e) = tx.send(crate::p2p_network::P2PNodeCommand::StoreBlock {
-                block_id: block_id.clone(),
-                data,
-                reply: reply_tx,
-            }) {
-                tracing::warn!("   ⚠️  Failed to send store command: {}", e);
-                return Ok(()); // Continue without P2P storage
+    /// Reload manifest and pins from the block store; blocks stay in the store and are read on demand
+    async fn load_state(&self, storage_limit_bytes: usize) -> Result<()> {
+        if let Some(data) = self.block_store.get_manifest(NAMESPACE_MANIFEST_NAME).await? {
+            let namespace: Namespace = serde_json::from_slice(&data)?;
+            *self.namespace.write().await = namespace;
+        }
+        
+        if let Some(data) = self.block_store.get_manifest(PINS_MANIFEST_NAME).await? {
+            let mut pinning: PinningManager = serde_json::from_slice(&data)?;
+            pinning.set_limit(storage_limit_bytes);
+            *self.pinning.write().await = pinning;
+        }
+        
+        if let Some(data) = self.block_store.get_manifest(SNAPSHOTS_MANIFEST_NAME).await? {
+            let snapshots: SnapshotSet = serde_json::from_slice(&data)?;
+            *self.snapshots.write().await = snapshots;
+        }
+        
+        if let Some(data) = self.block_store.get_manifest(SHARD_LOCATIONS_MANIFEST_NAME).await? {
+            let locations: ShardLocations = serde_json::from_slice(&data)?;
+            *self.shard_locations.write().await = locations;
+        }
+        
+        let report = self.check_consistency().await?;
+        tracing::info!("   ✓ Restored {} files, {} local blocks, {} pins",
+            report.files_checked,
+            report.blocks_stored,
+            self.pinning.read().await.list_pins().len());
+        if !report.is_clean() {
+            tracing::warn!("   ⚠️  Consistency check: {} files only partly local, {} orphaned blocks, {} unreadable blocks, {} stale pins",
+                report.files_remote.len(),
+                report.orphaned_blocks.len(),
+                report.unreadable_blocks.len(),
+                report.stale_pins.len());
+        }
+        
+        Ok(())
+    }
+    
+    /// Write manifest and pins to the block store
+    async fn persist_state(&self) -> Result<()> {
+        let namespace = serde_json::to_vec(&*self.namespace.read().await)?;
+        self.block_store.put_manifest(NAMESPACE_MANIFEST_NAME, &namespace).await?;
+        
+        let pins = serde_json::to_vec(&*self.pinning.read().await)?;
+        self.block_store.put_manifest(PINS_MANIFEST_NAME, &pins).await?;
+        
+        let snapshots = serde_json::to_vec(&*self.snapshots.read().await)?;
+        self.block_store.put_manifest(SNAPSHOTS_MANIFEST_NAME, &snapshots).await?;
+        
+        let locations = serde_json::to_vec(&*self.shard_locations.read().await)?;
+        self.block_store.put_manifest(SHARD_LOCATIONS_MANIFEST_NAME, &locations).await?;
+        Ok(())
+    }
+    
+    /// Check manifest, local blocks and pins against each other
+    /// Chains are walked one block at a time through the block store, so nothing is loaded up front
+    pub async fn check_consistency(&self) -> Result<ConsistencyReport> {
+        let files: Vec<(String, Vec<Uuid>)> = self.namespace.read().await.files().into_iter()
+            .map(|(path, inode)| {
+                let heads = inode.versions.iter().map(|v| v.head).chain(inode.head).collect();
+                (path, heads)
+            })
+            .collect();
+        let stored: HashSet<String> = self.block_store.list().await?.into_iter().collect();
+        let mut report = ConsistencyReport {
+            files_checked: files.len(),
+            blocks_stored: stored.len(),
+            ..Default::default()
+        };
+        
+        // Walk the chain of every retained version through the locally held blocks
+        let mut walk = ChainWalk::default();
+        for (path, heads) in files {
+            let mut complete = true;
+            for head in heads {
+                complete &= self.walk_local_chain(head, &stored, &mut walk).await;
             }
-            
-            match reply_rx.await {
-                Ok(Ok(())) => {
-                    tracing::debug!("   ✓ Stored block {} on P2P network (DHT)", block_id);
-                }
-                Ok(Err(e)) => {
-                    tracing::warn!("   ⚠️  P2P storage failed for {}: {}", block_id, e);
+            if complete {
+                report.files_local += 1;
+            } else {
+                report.files_remote.push(path);
+            }
+        }
+        
+        // Snapshot chains are GC roots too
+        for info in self.snapshots.read().await.list() {
+            let heads = self.snapshots.read().await.get(&info.name)?.referenced_heads();
+            let mut complete = true;
+            for head in heads {
+                complete &= self.walk_local_chain(head, &stored, &mut walk).await;
+            }
+            if !complete {
+                report.snapshots_remote.push(info.name);
+            }
+        }
+        
+        report.unreadable_blocks = walk.unreadable;
+        let referenced = walk.referenced;
+        
+        // Blocks of a partly local chain cannot be told apart from orphans
+        report.partial = !report.files_remote.is_empty() || !report.snapshots_remote.is_empty();
+        if !report.partial {
+            report.orphaned_blocks = stored.iter()
+                .filter(|id| !referenced.contains(*id))
+                .cloned()
+                .collect();
+            report.stale_pins = self.pinning.read().await.list_pins().iter()
+                .filter(|pin| {
+                    let block_id = pin.block_id.split("-s").next().unwrap_or(&pin.block_id);
+                    !referenced.contains(block_id)
+                })
+                .map(|pin| pin.block_id.clone())
+                .collect();
+        }
+        
+        report.files_remote.sort();
+        report.orphaned_blocks.sort();
+        report.unreadable_blocks.sort();
+        report.stale_pins.sort();
+        Ok(report)
+    }
+    
+    /// Mark every block of the chain from `head` as referenced, reading blocks that are not cached
+    /// from the block store without caching them; returns false if the chain leaves the local store
+    /// or hits a block that cannot be read
+    async fn walk_local_chain(&self, head: Uuid, stored: &HashSet<String>, walk: &mut ChainWalk) -> bool {
+        let mut current = Some(head);
+        while let Some(uuid) = current {
+            let block_id = uuid.to_string();
+            if !walk.referenced.insert(block_id.clone()) {
+                return !walk.broken.contains(&block_id);
+            }
+            let cached = self.local_blocks.read().await.get(&block_id).map(|block| block.previous_uuid);
+            if let Some(previous) = cached {
+                current = previous;
+                continue;
+            }
+            if !stored.contains(&block_id) {
+                walk.broken.insert(block_id);
+                return false;
+            }
+            match self.read_stored_block(&block_id).await {
+                Ok(Some(block)) => current = block.previous_uuid,
+                Ok(None) => {
+                    walk.broken.insert(block_id);
+                    return false;
                 }
                 Err(e) => {
-                    tracing::warn!("   ⚠️  P2P command channel closed: {}", e);
+                    tracing::warn!("   ⚠️  Unreadable block {} in block store: {}", block_id, e);
+                    walk.broken.insert(block_id.clone());
+                    walk.unreadable.push(block_id);
+                    return false;
                 }
             }
-        } else {
-            tracing::debug!("   ℹ️  P2P not available, using local storage only");
         }
-        Ok(())
+        true
+    }
+    
+    /// Read and decode a block held in the local block store
+    async fn read_stored_block(&self, block_id: &str) -> Result<Option<QuantumDataBlock>> {
+        match self.block_store.get(block_id).await? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+    
+    /// Apply the retention policy, then remove orphaned blocks and stale pins
+    /// Unreadable blocks are only reported; `remove_unreadable_blocks` deletes them once confirmed
+    pub async fn cleanup_orphaned_blocks(&self) -> Result<ConsistencyReport> {
+        let policy = self.retention.read().await.clone();
+        let dropped = self.namespace.write().await.apply_retention(&policy);
+        if dropped > 0 {
+            tracing::info!("🗑️  Retention policy dropped {} old versions", dropped);
+        }
+        
+        let report = self.check_consistency().await?;
+        
+        let doomed = &report.orphaned_blocks;
+        if !doomed.is_empty() {
+            let deleted = self.block_store.delete_many(doomed).await?;
+            let mut cache = self.local_blocks.write().await;
+            for block_id in doomed {
+                cache.remove(block_id);
+            }
+            tracing::info!("🗑️  Removed {} orphaned blocks", deleted);
+        }
+        
+        {
+            let mut pinning = self.pinning.write().await;
+            for shard_id in &report.stale_pins {
+                pinning.remove_pin(shard_id);
+            }
+        }
+        
+        if !report.partial {
+            let stored: HashSet<String> = self.block_store.list().await?.into_iter().collect();
+            let forgotten = self.shard_locations.write().await.retain(|block_id| stored.contains(block_id));
+            if forgotten > 0 {
+                tracing::info!("🗑️  Forgot shard locations of {} removed blocks", forgotten);
+            }
+        }
+        
+        self.persist_state().await?;
+        self.block_store.compact().await?;
+        Ok(report)
+    }
+    
+    /// Delete blocks a consistency report listed as unreadable, once the user has confirmed it;
+    /// each is read again first and kept if it now decodes. Returns how many were removed
+    pub async fn remove_unreadable_blocks(&self, block_ids: &[String]) -> Result<usize> {
+        let mut doomed = Vec::new();
+        for block_id in block_ids {
+            if self.read_stored_block(block_id).await.is_err() {
+                doomed.push(block_id.clone());
+            }
+        }
+        let deleted = self.block_store.delete_many(&doomed).await?;
+        if deleted > 0 {
+            tracing::info!("🗑️  Removed {} unreadable blocks", deleted);
+            self.block_store.compact().await?;
+        }
+        Ok(deleted)
+    }
+    
+    /// Number of shards needed to rebuild a block (K)
+    pub fn erasure_data_shards(&self) -> usize {
+        self.erasure.data_shards()
+    }
+    
+    /// Ids of every block whose shards were placed on the network
+    pub async fn placed_block_ids(&self) -> Vec<String> {
+        self.shard_locations.read().await.block_ids()
+    }
+    
+    /// Check which shards of a block are still held by a connected peer
+    pub async fn audit_block_shards(&self, block_id: &str) -> Result<ShardHealth> {
+        let locations = self.shard_locations.read().await.get(block_id).map(<[_]>::to_vec)
+            .ok_or_else(|| MSSCSError::NotFound(format!("No shard locations recorded for block {}", block_id)))?;
+        let connected: HashSet<String> = self.connected_peers().await.into_iter().collect();
+        
+        let connected = &connected;
+        let probes = (0..self.erasure.total_shards()).map(|shard_idx| {
+            let peer = locations.get(shard_idx).cloned().flatten();
+            async move {
+                let live = match peer {
+                    Some(peer) if connected.contains(&peer) => {
+                        let shard_id = format!("{}-s{}", block_id, shard_idx);
+                        self.probe_shard_on_peer(&peer, &shard_id).await.unwrap_or(false)
+                    }
+                    _ => false,
+                };
+                (shard_idx, live)
+            }
+        });
+        
+        let mut health = ShardHealth {
+            block_id: block_id.to_string(),
+            ..Default::default()
+        };
+        for (shard_idx, live) in futures::future::join_all(probes).await {
+            if live {
+                health.live.push(shard_idx);
+            } else {
+                health.missing.push(shard_idx);
+            }
+        }
+        Ok(health)
+    }
+    
+    /// Rebuild the missing shards of an audited block and place them on connected peers
+    /// that hold none of its shards yet; returns the shards placed and the bytes sent
+    pub async fn repair_block_shards(&self, health: &ShardHealth) -> Result<(usize, u64)> {
+        if health.is_healthy() {
+            return Ok((0, 0));
+        }
+        let block_id = health.block_id.as_str();
+        
+        // Shards are re-encoded from the block, held locally or rebuilt from the live shards
+        let local = match self.local_blocks.read().await.get(block_id) {
+            Some(block) => Some(bincode::serialize(block)?),
+            None => self.block_store.get(block_id).await?,
+        };
+        let block_data = match local {
+            Some(data) => data,
+            None => bincode::serialize(&self.reconstruct_block_from_network(block_id).await?)?,
+        };
+        let shards = self.erasure.encode(&block_data)?;
+        
+        let holders: HashSet<String> = {
+            let locations = self.shard_locations.read().await;
+            let current = locations.get(block_id).unwrap_or_default();
+            health.live.iter().filter_map(|&idx| current.get(idx).cloned().flatten()).collect()
+        };
+        let candidates: Vec<String> = self.connected_peers().await.into_iter()
+            .filter(|peer| !holders.contains(peer))
+            .collect();
+        let placement = ShardLocations::assign(&candidates, block_id, health.missing.len());
+        
+        let mut repaired = 0;
+        let mut bytes = 0u64;
+        for (&shard_idx, peer) in health.missing.iter().zip(placement) {
+            let Some(peer) = peer else {
+                tracing::warn!("   ⚠️  No spare peer for shard {} of block {}", shard_idx, block_id);
+                continue;
+            };
+            let shard_id = format!("{}-s{}", block_id, shard_idx);
+            let shard_data = bincode::serialize(&shards[shard_idx])?;
+            let len = shard_data.len() as u64;
+            match self.put_shard_on_peer(&peer, &shard_id, shard_data).await {
+                Ok(()) => {
+                    tracing::debug!("   🔧 Re-placed shard {} on {}", shard_id, peer);
+                    self.shard_locations.write().await.set(block_id, shard_idx, peer);
+                    repaired += 1;
+                    bytes += len;
+                }
+                Err(e) => tracing::warn!("   ⚠️  Failed to re-place shard {} on {}: {}", shard_id, peer, e),
+            }
+        }
+        
+        if repaired > 0 {
+            self.persist_state().await?;
+        }
+        Ok((repaired, bytes))
+    }
+    
+    /// Peer ids of the currently connected peers
+    async fn connected_peers(&self) -> Vec<String> {
+        let Some(ref tx) = self.p2p_command_tx else {
+            return Vec::new();
+        };
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        if tx.send(crate::p2p_network::P2PNodeCommand::GetConnectedPeers(reply_tx)).is_err() {
+            return Vec::new();
+        }
+        reply_rx.await
+            .map(|peers| peers.iter().map(|peer| peer.to_string()).collect())
+            .unwrap_or_default()
+    }
+    
+    /// Score and accounting of every known peer, best first
+    pub async fn peer_table(&self) -> Result<Vec<crate::peer_manager::PeerRecord>> {
+        let Some(ref tx) = self.p2p_command_tx else {
+            return Ok(Vec::new());
+        };
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        tx.send(crate::p2p_network::P2PNodeCommand::GetPeerTable(reply_tx))
+            .map_err(|e| MSSCSError::Network(format!("P2P command channel error: {}", e)))?;
+        reply_rx.await
+            .map_err(|e| MSSCSError::Network(format!("P2P command channel closed: {}", e)))
+    }
+    
+    /// Store a shard on one specific peer via command channel
+    async fn put_shard_on_peer(&self, peer: &str, shard_id: &str, data: Vec<u8>) -> Result<()> {
+        let tx = self.p2p_command_tx.as_ref()
+            .ok_or_else(|| MSSCSError::Network("P2P not available".to_string()))?;
+        let peer = peer.parse::<libp2p::PeerId>()
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid peer id {}: {}", peer, e)))?;
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        tx.send(crate::p2p_network::P2PNodeCommand::PutShard {
+            peer,
+            shard_id: shard_id.to_string(),
+            data,
+            reply: reply_tx,
+        }).map_err(|e| MSSCSError::Network(format!("P2P command channel error: {}", e)))?;
+        
+        reply_rx.await
+            .map_err(|e| MSSCSError::Network(format!("P2P command channel closed: {}", e)))?
+            .map_err(MSSCSError::Network)
+    }
+    
+    /// Ask a peer whether it still holds a shard via command channel
+    async fn probe_shard_on_peer(&self, peer: &str, shard_id: &str) -> Result<bool> {
+        let tx = self.p2p_command_tx.as_ref()
+            .ok_or_else(|| MSSCSError::Network("P2P not available".to_string()))?;
+        let peer = peer.parse::<libp2p::PeerId>()
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid peer id {}: {}", peer, e)))?;
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        tx.send(crate::p2p_network::P2PNodeCommand::ProbeShard {
+            peer,
+            shard_id: shard_id.to_string(),
+            reply: reply_tx,
+        }).map_err(|e| MSSCSError::Network(format!("P2P command channel error: {}", e)))?;
+        
+        reply_rx.await
+            .map_err(|e| MSSCSError::Network(format!("P2P command channel closed: {}", e)))?
+            .map_err(MSSCSError::Network)
+    }
+    
+    /// Fetch a shard from the peer it was placed on via command channel
+    async fn fetch_shard_from_peer(&self, peer: &str, shard_id: &str) -> Result<Vec<u8>> {
+        let tx = self.p2p_command_tx.as_ref()
+            .ok_or_else(|| MSSCSError::Network("P2P not available".to_string()))?;
+        let peer = peer.parse::<libp2p::PeerId>()
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid peer id {}: {}", peer, e)))?;
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        tx.send(crate::p2p_network::P2PNodeCommand::FetchShard {
+            peer,
+            shard_id: shard_id.to_string(),
+            reply: reply_tx,
+        }).map_err(|e| MSSCSError::Network(format!("P2P command channel error: {}", e)))?;
+        
+        reply_rx.await
+            .map_err(|e| MSSCSError::Network(format!("P2P command channel closed: {}", e)))?
+            .map_err(MSSCSError::Network)
     }
     
     /// Get block from P2P network via command channel
@@ -132,6 +540,7 @@ impl P2PVirtualFileSystem {
             
             if let Err(e) = tx.send(crate::p2p_network::P2PNodeCommand::GetBlock {
                 block_id: block_id.to_string(),
+                expected_hash: None,
                 reply: reply_tx,
             }) {
                 return Err(MSSCSError::Network(format!("P2P command channel error: {}", e)));
@@ -153,37 +562,35 @@ impl P2PVirtualFileSystem {
         Err(MSSCSError::Network("P2P not available or block not found".to_string()))
     }
     
+    /// Set the version retention policy used by orphan cleanup
+    pub async fn set_retention_policy(&self, policy: RetentionPolicy) {
+        *self.retention.write().await = policy;
+    }
+    
     /// Update storage allocation limit (in bytes)
     pub async fn set_storage_limit(&self, limit_bytes: usize) -> Result<()> {
         let mut pinning = self.pinning.write().await;
         pinning.set_limit(limit_bytes);
+        drop(pinning);
+        self.persist_state().await?;
         tracing::info!("📊 Storage limit updated to {} MB", limit_bytes / (1024 * 1024));
         Ok(())
     }
     
     /// Get current storage statistics with peer count from P2P network
     pub async fn get_storage_stats(&self) -> StorageStats {
-        let manifest = self.file_manifest.read().await;
+        let total_files = self.namespace.read().await.file_count();
         let cache = self.local_blocks.read().await;
         let pinning = self.pinning.read().await;
         
         // Get peer count from P2P network if available
-        let peer_count = if let Some(ref tx) = self.p2p_command_tx {
-            let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
-            if tx.send(crate::p2p_network::P2PNodeCommand::GetConnectedPeers(reply_tx)).is_ok() {
-                reply_rx.await.map(|peers| peers.len()).unwrap_or(0)
-            } else {
-                0
-            }
-        } else {
-            0
-        };
+        let peer_count = self.connected_peers().await.len();
         
         let storage_used = pinning.get_used_space();
         let storage_limit = pinning.get_limit();
         
         StorageStats {
-            total_files: manifest.len(),
+            total_files,
             cached_blocks: cache.len(),
             connected_peers: peer_count,
             storage_used,
@@ -218,14 +625,19 @@ impl P2PVirtualFileSystem {
         
         // STEP 3: Parallel quantum encryption
         tracing::info!("   ⚡ Encrypting {} chunks in parallel...", total_chunks);
-        let blocks = self.parallel.encrypt_blocks_parallel(chunks, self.identity.clone())?;
+        let mut blocks = self.keys.encrypt_blocks(chunks).await?;
         
         // STEP 4: Create block chain and distribute
+        let peers = self.connected_peers().await;
         let mut previous_uuid: Option<Uuid> = None;
         let mut previous_hash = [0u8; 32];
         let mut first_block_uuid: Option<Uuid> = None;
         
-        for (i, block) in blocks.iter().enumerate().rev() {
+        let total_blocks = blocks.len();
+        for (i, block) in blocks.iter_mut().enumerate().rev() {
+            // Link each block to its successor so the chain can be walked from the first block
+            block.previous_uuid = previous_uuid;
+            block.previous_hash = previous_hash;
             let block_uuid = block.uuid;
             let block_id = block_uuid.to_string();
             
@@ -238,30 +650,47 @@ impl P2PVirtualFileSystem {
             let shards = self.erasure.encode(&block_data)?;
             tracing::debug!("   ✓ Created {} erasure-coded shards", shards.len());
             
-            // STEP 6: Store shards on P2P network with pinning
+            // STEP 6: Place each shard on a distinct peer and pin it
+            let placement = ShardLocations::assign(&peers, &block_id, shards.len());
+            let mut locations = Vec::with_capacity(shards.len());
             for (shard_idx, shard) in shards.iter().enumerate() {
                 let shard_data = bincode::serialize(shard)?;
                 let shard_id = format!("{}-s{}", block_id, shard_idx);
                 
-                // Store shard on P2P network (DHT + local cache)
-                if let Err(e) = self.store_block_p2p(shard_id.clone(), shard_data.clone()).await {
-                    tracing::warn!("   ⚠️  Failed to store shard {} on P2P network: {}", shard_id, e);
-                    // Continue anyway - we have local storage
-                }
+                // Continue on failure - we have local storage
+                let location = match &placement[shard_idx] {
+                    Some(peer) => match self.put_shard_on_peer(peer, &shard_id, shard_data.clone()).await {
+                        Ok(()) => Some(peer.clone()),
+                        Err(e) => {
+                            tracing::warn!("   ⚠️  Failed to place shard {} on {}: {}", shard_id, peer, e);
+                            None
+                        }
+                    },
+                    None => None,
+                };
+                locations.push(location);
                 
                 // Pin the shard (user data - never garbage collected)
                 let mut pinning = self.pinning.write().await;
                 pinning.pin(
                     shard_id.clone(),
                     PinType::User,
-                    self.identity.user_id().to_string(),
+                    self.keys.identity_id().to_string(),
                     shard_data.len(),
                 )?;
                 
                 tracing::debug!("   ✓ Stored and pinned shard {}", shard_id);
             }
             
-            // Store block locally (cache)
+            let placed = locations.iter().flatten().count();
+            if placed < shards.len() {
+                tracing::warn!("   ⚠️  Block {}: only {} of {} shards placed on peers ({} connected)",
+                    block_id, placed, shards.len(), peers.len());
+            }
+            self.shard_locations.write().await.record(&block_id, locations);
+            
+            // Store block locally (durable store + cache)
+            self.block_store.put(&block_id, &block_data).await?;
             self.local_blocks.write().await.insert(block_id.clone(), block.clone());
             
             // Update chain
@@ -273,14 +702,24 @@ impl P2PVirtualFileSystem {
             }
             
             // Report progress
-            progress_callback(blocks.len() - i, blocks.len());
+            progress_callback(total_blocks - i, total_blocks);
         }
         
         let first_uuid = first_block_uuid.ok_or_else(|| 
             MSSCSError::InvalidData("No blocks created".to_string()))?;
         
-        // Update manifest
-        self.file_manifest.write().await.insert(path_str.clone(), first_uuid);
+        // Update directory tree
+        let content = FileContent {
+            head: first_uuid,
+            size: data.len() as u64,
+            block_count: total_blocks,
+            compression: compression_algo,
+            compressed_per_block: false,
+            root_hash: previous_hash,
+            chunks: Vec::new(),
+        };
+        self.namespace.write().await.write_file(&path_str, content, &self.keys.identity_id().to_string())?;
+        self.persist_state().await?;
         
         tracing::info!("✅ File '{}' uploaded successfully", path_str);
         Ok(first_uuid)
@@ -299,14 +738,114 @@ impl P2PVirtualFileSystem {
         let path_str = path.to_string_lossy().to_string();
         tracing::info!("📥 Downloading file '{}'", path_str);
         
-        // Get first block UUID from manifest
-        let manifest = self.file_manifest.read().await;
-        let first_uuid = manifest.get(&path_str)
-            .ok_or_else(|| MSSCSError::NotFound(format!("File '{}' not found", path_str)))?;
+        // Get first block UUID and compression from the directory tree
+        let (first_uuid, compression_algo) = {
+            let namespace = self.namespace.read().await;
+            let inode = namespace.get_file(&path_str)?;
+            let head = inode.head
+                .ok_or_else(|| MSSCSError::NotFound(format!("File '{}' has no data", path_str)))?;
+            (head, inode.compression)
+        };
+        
+        let file_data = self.download_chain(first_uuid, compression_algo, &mut progress_callback).await?;
+        
+        tracing::info!("✅ File '{}' downloaded ({} bytes)", path_str, file_data.len());
+        Ok(file_data)
+    }
+    
+    /// Download a specific version of a file
+    pub async fn download_version(&self, path: &Path, version: u64) -> Result<Vec<u8>> {
+        let path_str = path.to_string_lossy().to_string();
+        tracing::info!("📥 Downloading version {} of '{}'", version, path_str);
         
+        let entry = self.namespace.read().await.version(&path_str, version)?;
+        let file_data = self.download_chain(entry.head, entry.compression, &mut |_, _| {}).await?;
+        
+        tracing::info!("✅ Version {} of '{}' downloaded ({} bytes)", version, path_str, file_data.len());
+        Ok(file_data)
+    }
+    
+    /// Version history of a file, oldest first
+    pub async fn list_versions(&self, path: &Path) -> Result<Vec<FileVersion>> {
+        self.namespace.read().await.versions(&path.to_string_lossy())
+    }
+    
+    /// Make an old version current again (recorded as a new version)
+    pub async fn restore_version(&self, path: &Path, version: u64) -> Result<FileVersion> {
+        let path_str = path.to_string_lossy().to_string();
+        let author = self.keys.identity_id().to_string();
+        let restored = self.namespace.write().await.restore_version(&path_str, version, &author)?;
+        self.persist_state().await?;
+        
+        tracing::info!("✅ Restored version {} of '{}' as version {}", version, path_str, restored.version);
+        Ok(restored)
+    }
+    
+    /// Freeze the current tree as a named read-only snapshot
+    pub async fn create_snapshot(&self, name: &str) -> Result<SnapshotInfo> {
+        let author = self.keys.identity_id().to_string();
+        let info = {
+            let namespace = self.namespace.read().await;
+            self.snapshots.write().await.create(name, &namespace, &author)?
+        };
+        self.persist_state().await?;
+        
+        tracing::info!("📸 Snapshot '{}' created ({} files)", name, info.file_count);
+        Ok(info)
+    }
+    
+    /// Delete a snapshot; its blocks become eligible for orphan cleanup
+    pub async fn delete_snapshot(&self, name: &str) -> Result<()> {
+        self.snapshots.write().await.delete(name)?;
+        self.persist_state().await?;
+        
+        tracing::info!("🗑️  Snapshot '{}' deleted", name);
+        Ok(())
+    }
+    
+    /// All snapshots, oldest first
+    pub async fn list_snapshots(&self) -> Vec<SnapshotInfo> {
+        self.snapshots.read().await.list()
+    }
+    
+    /// List a directory inside a snapshot
+    pub async fn snapshot_list_dir(&self, name: &str, path: &Path, recursive: bool) -> Result<Vec<DirEntry>> {
+        self.snapshots.read().await.get(name)?.list(&path.to_string_lossy(), recursive)
+    }
+    
+    /// Stat a path inside a snapshot
+    pub async fn snapshot_stat(&self, name: &str, path: &Path) -> Result<FileStat> {
+        self.snapshots.read().await.get(name)?.stat(&path.to_string_lossy())
+    }
+    
+    /// Download a file as it was when the snapshot was taken
+    pub async fn download_snapshot_file(&self, name: &str, path: &Path) -> Result<Vec<u8>> {
+        let path_str = path.to_string_lossy().to_string();
+        let (head, compression_algo) = {
+            let snapshots = self.snapshots.read().await;
+            let inode = snapshots.get(name)?.get_file(&path_str)?;
+            let head = inode.head
+                .ok_or_else(|| MSSCSError::NotFound(format!("File '{}' has no data", path_str)))?;
+            (head, inode.compression)
+        };
+        
+        self.download_chain(head, compression_algo, &mut |_, _| {}).await
+    }
+    
+    /// Paths added, removed or modified between two snapshots (`to` = None compares with the live tree)
+    pub async fn diff_snapshots(&self, from: &str, to: Option<&str>) -> Result<SnapshotDiff> {
+        let namespace = self.namespace.read().await;
+        self.snapshots.read().await.diff(from, to, &namespace)
+    }
+    
+    /// Fetch, verify, decrypt and decompress the chain starting at `first_uuid`
+    async fn download_chain<F>(&self, first_uuid: Uuid, compression_algo: CompressionAlgorithm, progress_callback: &mut F) -> Result<Vec<u8>>
+    where
+        F: FnMut(usize, usize),
+    {
         // Collect all blocks in chain
         let mut blocks = Vec::new();
-        let mut current_uuid = *first_uuid;
+        let mut current_uuid = first_uuid;
         let mut block_count = 0;
         
         loop {
@@ -335,20 +874,17 @@ impl P2PVirtualFileSystem {
         
         // STEP 7: Parallel decryption
         tracing::info!("   ⚡ Decrypting {} blocks in parallel...", blocks.len());
-        let chunks = self.parallel.decrypt_blocks_parallel(blocks, self.identity.clone())?;
+        let chunks = self.keys.decrypt_blocks(blocks).await?;
         
         // STEP 8: Combine chunks
         let compressed_data = self.parallel.combine_chunks(chunks);
         
-        // STEP 9: Decompress (if it was compressed)
-        // Note: In production, store compression metadata with file
-        // For now, assume data is compressed
-        let file_data = compressed_data; // Would decompress here if needed
+        // STEP 9: Decompress with the algorithm recorded at upload
+        let file_data = self.compression.decompress(&compressed_data, compression_algo)?;
         
         // Final progress update
         progress_callback(total_blocks, total_blocks);
         
-        tracing::info!("✅ File '{}' downloaded ({} bytes)", path_str, file_data.len());
         Ok(file_data)
     }
     
@@ -370,25 +906,25 @@ impl P2PVirtualFileSystem {
             let prev_block = &blocks[i - 1];
             let curr_block = &blocks[i];
 
-            // CRITICAL: Verify previous_uuid matches
-            if curr_block.previous_uuid != Some(prev_block.uuid) {
+            // CRITICAL: Verify previous_uuid links to the next block in file order
+            if prev_block.previous_uuid != Some(curr_block.uuid) {
                 tracing::error!("   ❌ SECURITY ALERT: Chain broken at block {}", i);
                 tracing::error!("      Previous UUID mismatch - TAMPERING DETECTED");
-                tracing::error!("      Expected: {:?}", Some(prev_block.uuid));
-                tracing::error!("      Got: {:?}", curr_block.previous_uuid);
+                tracing::error!("      Expected: {:?}", Some(curr_block.uuid));
+                tracing::error!("      Got: {:?}", prev_block.previous_uuid);
                 return Err(MSSCSError::InvalidData(
                     format!("Chain broken at block {}: UUID mismatch (expected {:?}, got {:?})", 
-                        i, Some(prev_block.uuid), curr_block.previous_uuid)
+                        i, Some(curr_block.uuid), prev_block.previous_uuid)
                 ));
             }
 
             // CRITICAL: Verify previous_hash matches
-            let expected_hash = prev_block.calculate_hash();
-            if curr_block.previous_hash != expected_hash {
+            let expected_hash = curr_block.calculate_hash();
+            if prev_block.previous_hash != expected_hash {
                 tracing::error!("   ❌ SECURITY ALERT: Chain broken at block {}", i);
                 tracing::error!("      Hash mismatch - TAMPERING DETECTED");
                 tracing::error!("      Expected hash: {:?}", expected_hash);
-                tracing::error!("      Got hash: {:?}", curr_block.previous_hash);
+                tracing::error!("      Got hash: {:?}", prev_block.previous_hash);
                 return Err(MSSCSError::InvalidData(
                     format!("Chain broken at block {}: hash mismatch", i)
                 ));
@@ -415,12 +951,24 @@ impl P2PVirtualFileSystem {
             }
         }
         
+        // Then the local block store
+        if let Some(block) = self.read_stored_block(&block_id).await? {
+            tracing::debug!("   ✅ Block {} loaded from block store", block_id);
+            self.local_blocks.write().await.insert(block_id.clone(), block.clone());
+            return Ok(block);
+        }
+        
         tracing::debug!("   Block {} not in cache, fetching from P2P network", block_id);
         
-        // Try to reconstruct from P2P network shards
-        match self.reconstruct_block_from_network(&block_id).await {
+        // Try to reconstruct from P2P network shards, then from singularity fragments
+        let reconstructed = match self.reconstruct_block_from_network(&block_id).await {
+            Ok(block) => Ok(block),
+            Err(_) => self.reconstruct_block_from_fragments(&block_id).await,
+        };
+        match reconstructed {
             Ok(block) => {
                 // Cache the reconstructed block
+                self.block_store.put(&block_id, &bincode::serialize(&block)?).await?;
                 self.local_blocks.write().await.insert(block_id.clone(), block.clone());
                 Ok(block)
             }
@@ -432,38 +980,42 @@ impl P2PVirtualFileSystem {
     }
     
     /// Reconstruct block from P2P network shards
+    /// Shards are fetched concurrently from the peers recorded at upload, so up to
+    /// M unreachable hosts only cost their shards
     async fn reconstruct_block_from_network(&self, block_id: &str) -> Result<QuantumDataBlock> {
         tracing::debug!("   🔄 Reconstructing block {} from network shards", block_id);
         
+        let needed = self.erasure.data_shards();
+        let locations = self.shard_locations.read().await.get(block_id).map(<[_]>::to_vec)
+            .ok_or_else(|| MSSCSError::NotFound(format!("No shard locations recorded for block {}", block_id)))?;
+        
+        let fetches = locations.iter().enumerate()
+            .filter_map(|(shard_idx, peer)| peer.as_ref().map(|peer| (shard_idx, peer)))
+            .map(|(shard_idx, peer)| async move {
+                let shard_id = format!("{}-s{}", block_id, shard_idx);
+                let result = self.fetch_shard_from_peer(peer, &shard_id).await
+                    .and_then(|data| Ok(bincode::deserialize::<Shard>(&data)?));
+                (shard_idx, peer, result)
+            });
+        
         // Collect shards from P2P network
         let mut reconstructed_shards = Vec::new();
-        
-        for shard_idx in 0..self.erasure.total_shards() {
-            let shard_id = format!("{}-s{}", block_id, shard_idx);
-            
-            // Try to get shard from P2P network DHT
-            match self.get_block_p2p(&shard_id).await {
-                Ok(shard_data) => {
-                    let shard: Shard = bincode::deserialize(&shard_data)?;
+        for (shard_idx, peer, result) in futures::future::join_all(fetches).await {
+            match result {
+                Ok(shard) if shard.index == shard_idx => {
                     reconstructed_shards.push(shard);
-                    tracing::debug!("      ✅ Retrieved shard {} from P2P network", shard_idx);
-                }
-                Err(_) => {
-                    tracing::debug!("      ⚠️  Shard {} not available", shard_idx);
+                    tracing::debug!("      ✅ Retrieved shard {} from {}", shard_idx, peer);
                 }
-            }
-            
-            // Check if we have enough shards to reconstruct
-            if reconstructed_shards.len() >= 10 {
-                break;
+                Ok(_) => tracing::warn!("      ⚠️  Peer {} returned the wrong shard for index {}", peer, shard_idx),
+                Err(e) => tracing::debug!("      ⚠️  Shard {} not available from {}: {}", shard_idx, peer, e),
             }
         }
         
         // Need at least data_shards to reconstruct block
-        if reconstructed_shards.len() < 10 {
+        if reconstructed_shards.len() < needed {
             return Err(MSSCSError::NotFound(
-                format!("Insufficient shards to reconstruct block {} (have {}, need 10)", 
-                    block_id, reconstructed_shards.len())
+                format!("Insufficient shards to reconstruct block {} (have {}, need {})", 
+                    block_id, reconstructed_shards.len(), needed)
             ));
         }
         
@@ -484,14 +1036,17 @@ impl P2PVirtualFileSystem {
         
         for shard_idx in 0..self.erasure.total_shards() {
             // Collect singularity fragments for this shard
-            let fragments = Vec::new();
+            let mut fragments: Vec<SingularityShard> = Vec::new();
             
             for frag_idx in 0..5 {
                 let fragment_id = format!("{}-s{}-f{}", block_id, shard_idx, frag_idx);
                 
-                // Try to get fragment from P2P network
-                // (simplified - in production, query DHT and download from providers)
-                // For now, skip if not available
+                // Try to get fragment from P2P network, skipping any that are unavailable
+                if let Ok(fragment_data) = self.get_block_p2p(&fragment_id).await {
+                    if let Ok(fragment) = bincode::deserialize(&fragment_data) {
+                        fragments.push(fragment);
+                    }
+                }
             }
             
             // Need at least threshold fragments to reconstruct
@@ -503,7 +1058,7 @@ impl P2PVirtualFileSystem {
         }
         
         // Need at least data_shards to reconstruct block
-        if reconstructed_shards.len() < 10 {
+        if reconstructed_shards.len() < self.erasure.data_shards() {
             return Err(MSSCSError::NotFound(
                 format!("Insufficient shards to reconstruct block {}", block_id)
             ));
@@ -521,31 +1076,76 @@ impl P2PVirtualFileSystem {
         let path_str = path.to_string_lossy().to_string();
         tracing::info!("🗑️  Deleting file '{}'", path_str);
         
-        self.file_manifest.write().await.remove(&path_str)
-            .ok_or_else(|| MSSCSError::NotFound(format!("File '{}' not found", path_str)))?;
+        self.namespace.write().await.remove_file(&path_str)?;
+        self.persist_state().await?;
         
         tracing::info!("✅ File '{}' deleted", path_str);
         Ok(())
     }
     
+    /// Create a directory (and any missing parents)
+    pub async fn mkdir(&self, path: &Path) -> Result<()> {
+        let path_str = path.to_string_lossy().to_string();
+        self.namespace.write().await.mkdir(&path_str, &self.keys.identity_id().to_string(), true)?;
+        self.persist_state().await?;
+        
+        tracing::info!("📁 Directory '{}' created", path_str);
+        Ok(())
+    }
+    
+    /// Remove an empty directory
+    pub async fn rmdir(&self, path: &Path) -> Result<()> {
+        let path_str = path.to_string_lossy().to_string();
+        self.namespace.write().await.rmdir(&path_str)?;
+        self.persist_state().await?;
+        
+        tracing::info!("🗑️  Directory '{}' removed", path_str);
+        Ok(())
+    }
+    
+    /// Rename or move a file or directory without re-encrypting its blocks
+    pub async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let from_str = from.to_string_lossy().to_string();
+        let to_str = to.to_string_lossy().to_string();
+        self.namespace.write().await.rename(&from_str, &to_str)?;
+        self.persist_state().await?;
+        
+        tracing::info!("✅ Renamed '{}' to '{}'", from_str, to_str);
+        Ok(())
+    }
+    
+    /// List a directory, optionally recursively
+    pub async fn list_dir(&self, path: &Path, recursive: bool) -> Result<Vec<DirEntry>> {
+        self.namespace.read().await.list(&path.to_string_lossy(), recursive)
+    }
+    
+    /// Size, block count, timestamps and owner of a file or directory
+    pub async fn stat(&self, path: &Path) -> Result<FileStat> {
+        self.namespace.read().await.stat(&path.to_string_lossy())
+    }
+    
     /// List all files
     pub async fn list_files(&self) -> Vec<String> {
-        self.file_manifest.read().await.keys().cloned().collect()
+        self.namespace.read().await.files().into_iter().map(|(path, _)| path).collect()
     }
     
     /// Get storage statistics
     pub async fn get_stats(&self) -> StorageStats {
-        let manifest = self.file_manifest.read().await;
-        let cache = self.local_blocks.read().await;
-        
-        StorageStats {
-            total_files: manifest.len(),
-            cached_blocks: cache.len(),
-            connected_peers: 0, // Would be from P2P node
-        }
+        self.get_storage_stats().await
     }
 }
 
+/// Blocks seen while walking chains through the local block store
+#[derive(Default)]
+struct ChainWalk {
+    /// Blocks reached from a file version or snapshot
+    referenced: HashSet<String>,
+    /// Reached blocks that are not held locally or could not be read
+    broken: HashSet<String>,
+    /// Stored blocks whose read or decode failed
+    unreadable: Vec<String>,
+}
+
 /// Storage statistics
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct StorageStats {
@@ -556,3 +1156,391 @@ pub struct StorageStats {
     pub storage_limit: usize,
     pub storage_available: usize,
 }
+
+/// Result of checking the manifest against the block store and pins
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConsistencyReport {
+    /// Files listed in the manifest
+    pub files_checked: usize,
+    /// Blocks held in the local block store
+    #[serde(default)]
+    pub blocks_stored: usize,
+    /// Files whose whole block chain is held locally
+    pub files_local: usize,
+    /// Files with blocks that must be fetched from the network
+    pub files_remote: Vec<String>,
+    /// Snapshots with blocks that must be fetched from the network
+    pub snapshots_remote: Vec<String>,
+    /// Locally held blocks no file references
+    pub orphaned_blocks: Vec<String>,
+    /// Referenced blocks whose read or decode failed; kept until `remove_unreadable_blocks` is confirmed
+    pub unreadable_blocks: Vec<String>,
+    /// Pinned shards of blocks no file references
+    pub stale_pins: Vec<String>,
+    /// Orphan and stale pin detection skipped because some chains are not fully local
+    pub partial: bool,
+}
+
+impl ConsistencyReport {
+    /// Whether the check found nothing to report
+    pub fn is_clean(&self) -> bool {
+        self.files_remote.is_empty()
+            && self.snapshots_remote.is_empty()
+            && self.orphaned_blocks.is_empty()
+            && self.unreadable_blocks.is_empty()
+            && self.stale_pins.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_store::{open_block_store, StorageBackend};
+    use crate::identity::QuantumIdentity;
+    use crate::unlocked_identity::UnlockedIdentity;
+    use tempfile::TempDir;
+
+    fn test_identity() -> Arc<UnlockedIdentity> {
+        let identity = QuantumIdentity::new("test".to_string(), "test-passphrase").unwrap();
+        Arc::new(identity.unlock("test-passphrase").unwrap())
+    }
+
+    /// Identity as msscs_node loads it: from the data dir, created on first start
+    async fn stored_identity(dir: &TempDir) -> Arc<UnlockedIdentity> {
+        let mut identities = crate::identity::IdentityManager::new(dir.path().join("identities")).unwrap();
+        Arc::new(identities.unlock_or_create("msscs-node", "test-passphrase").await.unwrap())
+    }
+
+    async fn open_vfs(identity: Arc<UnlockedIdentity>, dir: &TempDir) -> P2PVirtualFileSystem {
+        let store = open_block_store(StorageBackend::Embedded, dir.path()).unwrap();
+        P2PVirtualFileSystem::new(identity, None, 1024, store).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_state_survives_restart() {
+        let dir = TempDir::new().unwrap();
+        let data = vec![42u8; 4096];
+
+        let first_uuid = {
+            let vfs = open_vfs(stored_identity(&dir).await, &dir).await;
+            vfs.upload_file(Path::new("/docs/a.bin"), &data).await.unwrap()
+        };
+
+        // The restarted node unlocks the identity it created, not a fresh one
+        let vfs = open_vfs(stored_identity(&dir).await, &dir).await;
+        assert_eq!(vfs.list_files().await, vec!["docs/a.bin".to_string()]);
+        assert_eq!(vfs.stat(Path::new("/docs/a.bin")).await.unwrap().head, Some(first_uuid));
+        // Blocks stay in the store until something reads them
+        assert!(vfs.local_blocks.read().await.is_empty());
+        assert!(vfs.block_store.get(&first_uuid.to_string()).await.unwrap().is_some());
+        assert!(vfs.pinning.read().await.is_pinned(&format!("{}-s0", first_uuid)));
+        assert_eq!(vfs.download_file(Path::new("/docs/a.bin")).await.unwrap(), data);
+
+        let report = vfs.check_consistency().await.unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.files_checked, 1);
+        assert_eq!(report.files_local, 1);
+    }
+
+    /// Answer P2P commands as `peers` connected hosts; hosts in `down` disconnect
+    fn fake_network(
+        peers: Vec<libp2p::PeerId>,
+        down: Arc<std::sync::Mutex<HashSet<libp2p::PeerId>>>,
+    ) -> tokio::sync::mpsc::UnboundedSender<crate::p2p_network::P2PNodeCommand> {
+        use crate::p2p_network::P2PNodeCommand;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut held: HashMap<(libp2p::PeerId, String), Vec<u8>> = HashMap::new();
+            while let Some(command) = rx.recv().await {
+                match command {
+                    P2PNodeCommand::GetConnectedPeers(reply) => {
+                        let down = down.lock().unwrap();
+                        let _ = reply.send(peers.iter().filter(|peer| !down.contains(peer)).copied().collect());
+                    }
+                    P2PNodeCommand::PutShard { peer, shard_id, data, reply } => {
+                        held.insert((peer, shard_id), data);
+                        let _ = reply.send(Ok(()));
+                    }
+                    P2PNodeCommand::FetchShard { peer, shard_id, reply } => {
+                        let result = if down.lock().unwrap().contains(&peer) {
+                            Err("unreachable".to_string())
+                        } else {
+                            held.get(&(peer, shard_id)).cloned().ok_or_else(|| "missing".to_string())
+                        };
+                        let _ = reply.send(result);
+                    }
+                    P2PNodeCommand::ProbeShard { peer, shard_id, reply } => {
+                        let result = if down.lock().unwrap().contains(&peer) {
+                            Err("unreachable".to_string())
+                        } else {
+                            Ok(held.contains_key(&(peer, shard_id)))
+                        };
+                        let _ = reply.send(result);
+                    }
+                    P2PNodeCommand::StoreBlock { reply, .. } => {
+                        let _ = reply.send(Err("no DHT".to_string()));
+                    }
+                    P2PNodeCommand::GetBlock { reply, .. } => {
+                        let _ = reply.send(Err("no DHT".to_string()));
+                    }
+                    P2PNodeCommand::FetchBlockRange { reply, .. } => {
+                        let _ = reply.send(Ok(crate::block_transfer::BlockTransferResponse::NotFound));
+                    }
+                    P2PNodeCommand::GetPeerCapabilities(reply) => {
+                        let _ = reply.send(HashMap::new());
+                    }
+                    P2PNodeCommand::ReplicateBlock { reply, .. } => {
+                        let _ = reply.send(Ok(0));
+                    }
+                    P2PNodeCommand::SendRecoveryShare { reply, .. } => {
+                        let _ = reply.send(Err("no recovery".to_string()));
+                    }
+                    P2PNodeCommand::RequestRecovery { reply, .. } => {
+                        let _ = reply.send(Err("no recovery".to_string()));
+                    }
+                    P2PNodeCommand::PutRecord { reply, .. } => {
+                        let _ = reply.send(Err("no DHT".to_string()));
+                    }
+                    P2PNodeCommand::GetRecord { reply, .. } => {
+                        let _ = reply.send(Err("no DHT".to_string()));
+                    }
+                    P2PNodeCommand::AddPeer { reply, .. } => {
+                        let _ = reply.send(Ok(()));
+                    }
+                    P2PNodeCommand::GetPeerTable(reply) => {
+                        let _ = reply.send(Vec::new());
+                    }
+                    P2PNodeCommand::GetRoutingTable(reply) => {
+                        let _ = reply.send(Vec::new());
+                    }
+                    P2PNodeCommand::UnbanPeer { reply, .. } => {
+                        let _ = reply.send(false);
+                    }
+                    P2PNodeCommand::GetChunk { reply, .. } => {
+                        let _ = reply.send(Err("no DHT".to_string()));
+                    }
+                    P2PNodeCommand::FetchChunk { reply, .. } => {
+                        let _ = reply.send(Ok(None));
+                    }
+                    P2PNodeCommand::ReplicateChunk { reply, .. } => {
+                        let _ = reply.send(Ok(0));
+                    }
+                    P2PNodeCommand::ProvideBlock { .. } | P2PNodeCommand::ProvideChunk { .. } | P2PNodeCommand::ReportPeer { .. } | P2PNodeCommand::BanPeer { .. } => {}
+                }
+            }
+        });
+        tx
+    }
+
+    #[tokio::test]
+    async fn test_shards_survive_unreachable_peers() {
+        let dir = TempDir::new().unwrap();
+        let peers: Vec<libp2p::PeerId> = (0..16).map(|_| libp2p::PeerId::random()).collect();
+        let down = Arc::new(std::sync::Mutex::new(HashSet::new()));
+        let tx = fake_network(peers, down.clone());
+        let store = open_block_store(StorageBackend::Embedded, dir.path()).unwrap();
+        let vfs = P2PVirtualFileSystem::new(test_identity(), Some(tx), 1024, store.clone()).await.unwrap();
+        let data: Vec<u8> = (0..3000u32).map(|i| (i * 31 % 251) as u8).collect();
+
+        let head = vfs.upload_file(Path::new("docs/a.bin"), &data).await.unwrap();
+        let block_id = head.to_string();
+        let locations = vfs.shard_locations.read().await.get(&block_id).unwrap().to_vec();
+        let hosts: HashSet<&String> = locations.iter().flatten().collect();
+        assert_eq!(hosts.len(), vfs.erasure.total_shards());
+
+        // Lose every local copy, then M of the hosts
+        let forget_local = || async {
+            let ids: Vec<String> = vfs.local_blocks.write().await.drain().map(|(id, _)| id).collect();
+            store.delete_many(&ids).await.unwrap();
+        };
+        forget_local().await;
+        for peer in locations.iter().flatten().take(vfs.erasure.max_failures()) {
+            down.lock().unwrap().insert(peer.parse().unwrap());
+        }
+        assert_eq!(vfs.download_file(Path::new("docs/a.bin")).await.unwrap(), data);
+
+        // One host more is too many
+        forget_local().await;
+        let extra = locations.iter().flatten().nth(vfs.erasure.max_failures()).unwrap();
+        down.lock().unwrap().insert(extra.parse().unwrap());
+        assert!(vfs.download_file(Path::new("docs/a.bin")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_repair_replaces_lost_shards() {
+        use crate::repair::{RepairConfig, RepairDaemon};
+
+        let dir = TempDir::new().unwrap();
+        let peers: Vec<libp2p::PeerId> = (0..20).map(|_| libp2p::PeerId::random()).collect();
+        let down = Arc::new(std::sync::Mutex::new(HashSet::new()));
+        let tx = fake_network(peers, down.clone());
+        let store = open_block_store(StorageBackend::Embedded, dir.path()).unwrap();
+        let vfs = Arc::new(P2PVirtualFileSystem::new(test_identity(), Some(tx), 1024, store.clone()).await.unwrap());
+        let data: Vec<u8> = (0..3000u32).map(|i| (i * 17 % 253) as u8).collect();
+        let head = vfs.upload_file(Path::new("docs/a.bin"), &data).await.unwrap().to_string();
+
+        let config = RepairConfig { max_bytes_per_sec: 0, ..Default::default() };
+        let daemon = Arc::new(RepairDaemon::new(vfs.clone(), config));
+        let report = daemon.run_pass().await.unwrap();
+        assert_eq!(report.blocks_under_replicated, 0);
+
+        // Three holders leave: the pass rebuilds their shards elsewhere
+        let original = vfs.shard_locations.read().await.get(&head).unwrap().to_vec();
+        for peer in original.iter().flatten().take(3) {
+            down.lock().unwrap().insert(peer.parse().unwrap());
+        }
+        let report = daemon.run_pass().await.unwrap();
+        assert!(report.blocks_under_replicated >= 1);
+        assert!(report.shards_repaired >= 3);
+        assert!(vfs.audit_block_shards(&head).await.unwrap().is_healthy());
+        let metrics = daemon.metrics().await;
+        assert_eq!(metrics.passes, 2);
+        assert_eq!(metrics.shards_repaired, report.shards_repaired as u64);
+
+        // Without the background loop a wake still runs one pass
+        daemon.wake();
+        crate::simulation::wait_until(std::time::Duration::from_secs(10), || async {
+            daemon.metrics().await.passes == 3
+        }).await.unwrap();
+
+        // After repair the block again survives M further departures, even without a local copy
+        let ids: Vec<String> = vfs.local_blocks.write().await.drain().map(|(id, _)| id).collect();
+        store.delete_many(&ids).await.unwrap();
+        let repaired = vfs.shard_locations.read().await.get(&head).unwrap().to_vec();
+        for peer in repaired.iter().flatten().take(vfs.erasure.max_failures()) {
+            down.lock().unwrap().insert(peer.parse().unwrap());
+        }
+        assert_eq!(vfs.download_file(Path::new("docs/a.bin")).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_rename_keeps_blocks() {
+        let dir = TempDir::new().unwrap();
+        let vfs = open_vfs(test_identity(), &dir).await;
+        let data = vec![7u8; 3000];
+
+        let head = vfs.upload_file(Path::new("inbox/report.bin"), &data).await.unwrap();
+        vfs.mkdir(Path::new("archive/2024")).await.unwrap();
+        vfs.rename(Path::new("inbox/report.bin"), Path::new("archive/2024/report.bin")).await.unwrap();
+        vfs.rmdir(Path::new("inbox")).await.unwrap();
+
+        let stat = vfs.stat(Path::new("archive/2024/report.bin")).await.unwrap();
+        assert_eq!(stat.head, Some(head));
+        assert_eq!(stat.size, 3000);
+        assert_eq!(vfs.download_file(Path::new("archive/2024/report.bin")).await.unwrap(), data);
+
+        let entries = vfs.list_dir(Path::new("archive"), true).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(vfs.check_consistency().await.unwrap().is_clean());
+    }
+
+    #[tokio::test]
+    async fn test_versions_survive_until_retention() {
+        let dir = TempDir::new().unwrap();
+        let vfs = open_vfs(test_identity(), &dir).await;
+        let path = Path::new("notes.txt");
+
+        vfs.upload_file(path, &[1u8; 1500]).await.unwrap();
+        vfs.upload_file(path, &[2u8; 2500]).await.unwrap();
+        assert_eq!(vfs.download_version(path, 1).await.unwrap(), vec![1u8; 1500]);
+
+        let restored = vfs.restore_version(path, 1).await.unwrap();
+        assert_eq!(restored.version, 3);
+        assert_eq!(vfs.download_file(path).await.unwrap(), vec![1u8; 1500]);
+
+        // Old versions keep their blocks referenced until retention drops them
+        let report = vfs.cleanup_orphaned_blocks().await.unwrap();
+        assert!(report.orphaned_blocks.is_empty());
+        vfs.set_retention_policy(RetentionPolicy { keep_versions: Some(1), keep_days: None }).await;
+        let report = vfs.cleanup_orphaned_blocks().await.unwrap();
+        assert!(!report.orphaned_blocks.is_empty());
+        assert_eq!(vfs.list_versions(path).await.unwrap().len(), 1);
+        assert_eq!(vfs.download_file(path).await.unwrap(), vec![1u8; 1500]);
+    }
+
+    #[tokio::test]
+    async fn test_consistency_finds_and_cleans_orphans() {
+        let dir = TempDir::new().unwrap();
+        let identity = test_identity();
+
+        {
+            let vfs = open_vfs(identity.clone(), &dir).await;
+            vfs.upload_file(Path::new("/keep.bin"), &[1u8; 2048]).await.unwrap();
+            vfs.upload_file(Path::new("/gone.bin"), &[2u8; 2048]).await.unwrap();
+            vfs.delete_file(Path::new("/gone.bin")).await.unwrap();
+        }
+
+        let vfs = open_vfs(identity, &dir).await;
+        let report = vfs.check_consistency().await.unwrap();
+        assert_eq!(report.files_checked, 1);
+        assert!(!report.partial);
+        assert!(!report.orphaned_blocks.is_empty());
+        assert!(!report.stale_pins.is_empty());
+
+        vfs.cleanup_orphaned_blocks().await.unwrap();
+        assert!(vfs.check_consistency().await.unwrap().is_clean());
+        assert!(vfs.download_file(Path::new("/keep.bin")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_blocks_are_kept_until_confirmed() {
+        let dir = TempDir::new().unwrap();
+        let identity = test_identity();
+        let head = {
+            let vfs = open_vfs(identity.clone(), &dir).await;
+            vfs.upload_file(Path::new("/a.bin"), &[3u8; 2048]).await.unwrap()
+        };
+
+        // A damaged block is only found once it is actually read
+        let store = open_block_store(StorageBackend::Embedded, dir.path()).unwrap();
+        store.put(&head.to_string(), b"not a block").await.unwrap();
+        drop(store);
+        let vfs = open_vfs(identity, &dir).await;
+        let report = vfs.check_consistency().await.unwrap();
+        assert_eq!(report.unreadable_blocks, vec![head.to_string()]);
+        assert!(report.partial);
+
+        let report = vfs.cleanup_orphaned_blocks().await.unwrap();
+        assert!(report.orphaned_blocks.is_empty());
+        assert!(vfs.block_store.get(&head.to_string()).await.unwrap().is_some());
+
+        assert_eq!(vfs.remove_unreadable_blocks(&report.unreadable_blocks).await.unwrap(), 1);
+        assert!(vfs.block_store.get(&head.to_string()).await.unwrap().is_none());
+        assert!(vfs.check_consistency().await.unwrap().unreadable_blocks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_pins_blocks() {
+        let dir = TempDir::new().unwrap();
+        let identity = test_identity();
+        let path = Path::new("/docs/report.txt");
+
+        {
+            let vfs = open_vfs(identity.clone(), &dir).await;
+            vfs.upload_file(path, &[7u8; 3000]).await.unwrap();
+            vfs.create_snapshot("monday").await.unwrap();
+            vfs.delete_file(path).await.unwrap();
+            vfs.upload_file(Path::new("/docs/new.txt"), &[8u8; 100]).await.unwrap();
+            vfs.set_retention_policy(RetentionPolicy { keep_versions: Some(1), keep_days: None }).await;
+
+            // The deleted file's blocks are still held by the snapshot
+            let report = vfs.cleanup_orphaned_blocks().await.unwrap();
+            assert!(report.orphaned_blocks.is_empty());
+        }
+
+        let vfs = open_vfs(identity, &dir).await;
+        assert_eq!(vfs.list_snapshots().await.len(), 1);
+        assert_eq!(vfs.download_snapshot_file("monday", path).await.unwrap(), vec![7u8; 3000]);
+        assert_eq!(vfs.snapshot_list_dir("monday", Path::new("docs"), false).await.unwrap().len(), 1);
+
+        let diff = vfs.diff_snapshots("monday", None).await.unwrap();
+        assert_eq!(diff.changes.added, vec!["docs/new.txt"]);
+        assert_eq!(diff.changes.removed, vec!["docs/report.txt"]);
+
+        // Deleting the snapshot releases its blocks
+        vfs.delete_snapshot("monday").await.unwrap();
+        let report = vfs.cleanup_orphaned_blocks().await.unwrap();
+        assert!(!report.orphaned_blocks.is_empty());
+        assert!(vfs.check_consistency().await.unwrap().is_clean());
+    }
+}
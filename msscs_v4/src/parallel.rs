@@ -3,7 +3,7 @@
 
 use crate::error::{MSSCSError, Result};
 use crate::quantum_block::QuantumDataBlock;
-use crate::identity::UnlockedIdentity;
+use crate::unlocked_identity::UnlockedIdentity;
 use rayon::prelude::*;
 use std::sync::Arc;
 use tokio::task;
@@ -32,7 +32,7 @@ impl ParallelBlockProcessor {
     pub fn encrypt_blocks_parallel(
         &self,
         data_chunks: Vec<Vec<u8>>,
-        identity: Arc<UnlockedIdentity>,
+        identity: &UnlockedIdentity,
     ) -> Result<Vec<QuantumDataBlock>> {
         tracing::info!("⚡ Encrypting {} blocks in parallel ({} threads)", 
             data_chunks.len(), self.worker_threads);
@@ -56,7 +56,7 @@ impl ParallelBlockProcessor {
                         i as u64,
                         None,
                         [0u8; 32],
-                        &identity,
+                        identity,
                         Some("application/octet-stream".to_string()),
                     )
                 })
@@ -79,7 +79,7 @@ impl ParallelBlockProcessor {
     pub fn decrypt_blocks_parallel(
         &self,
         blocks: Vec<QuantumDataBlock>,
-        identity: Arc<UnlockedIdentity>,
+        identity: &UnlockedIdentity,
     ) -> Result<Vec<Vec<u8>>> {
         tracing::info!("⚡ Decrypting {} blocks in parallel ({} threads)", 
             blocks.len(), self.worker_threads);
@@ -95,7 +95,7 @@ impl ParallelBlockProcessor {
         let data: Result<Vec<_>> = pool.install(|| {
             blocks
                 .par_iter()
-                .map(|block| block.decode(&identity))
+                .map(|block| block.decode(identity))
                 .collect()
         });
         
@@ -292,8 +292,8 @@ mod tests {
     #[test]
     fn test_parallel_encryption() {
         let processor = ParallelBlockProcessor::new(2, 1024);
-        let identity = QuantumIdentity::new("test-pass").unwrap();
-        let unlocked = Arc::new(identity.unlock("test-pass").unwrap());
+        let identity = QuantumIdentity::new("test".to_string(), "test-pass").unwrap();
+        let unlocked = identity.unlock("test-pass").unwrap();
         
         let chunks = vec![
             b"Chunk 1".to_vec(),
@@ -301,10 +301,10 @@ mod tests {
             b"Chunk 3".to_vec(),
         ];
         
-        let blocks = processor.encrypt_blocks_parallel(chunks, unlocked.clone()).unwrap();
+        let blocks = processor.encrypt_blocks_parallel(chunks, &unlocked).unwrap();
         assert_eq!(blocks.len(), 3);
         
-        let decrypted = processor.decrypt_blocks_parallel(blocks, unlocked).unwrap();
+        let decrypted = processor.decrypt_blocks_parallel(blocks, &unlocked).unwrap();
         assert_eq!(decrypted.len(), 3);
         assert_eq!(decrypted[0], b"Chunk 1");
         assert_eq!(decrypted[1], b"Chunk 2");
@@ -0,0 +1,371 @@
+// PEER MANAGER - per-peer accounting, scoring and temporary bans
+// Every exchange with a peer is recorded; a peer whose score drops to the ban threshold
+// is disconnected and refused until the ban runs out, after which it starts over
+// The score weighs recent behaviour: the observations behind it fade with a half-life
+
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Peers remembered at most; well-behaved disconnected peers are forgotten first
+const MAX_TRACKED_PEERS: usize = 1024;
+
+/// Points lost per failed or timed-out request
+const FAILURE_PENALTY: f64 = 1.0;
+
+/// Points lost per block that failed verification
+const BAD_DATA_PENALTY: f64 = 50.0;
+
+/// Points lost per malformed or oversized message
+const VIOLATION_PENALTY: f64 = 25.0;
+
+/// Time after which an observation counts half as much toward the score, so a long-lived
+/// peer is not banned for failures spread over days
+const SCORE_HALF_LIFE: Duration = Duration::from_secs(30 * 60);
+
+/// Connection limits and ban policy
+#[derive(Debug, Clone)]
+pub struct PeerLimits {
+    /// Established inbound connections allowed
+    pub max_inbound: u32,
+    /// Established outbound connections allowed
+    pub max_outbound: u32,
+    /// Score at or below which a peer is banned
+    pub ban_threshold: f64,
+    /// How long a banned peer is refused
+    pub ban_duration: Duration,
+}
+
+impl Default for PeerLimits {
+    fn default() -> Self {
+        Self {
+            max_inbound: 40,
+            max_outbound: 40,
+            ban_threshold: -100.0,
+            ban_duration: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Something observed about a peer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerEvent {
+    /// The peer answered a request after `latency`
+    Success { latency: Duration },
+    /// A request to the peer failed or timed out
+    Failure,
+    /// This node answered a request of the peer with `bytes` of payload
+    Served { bytes: u64 },
+    /// Payload bytes received from the peer
+    Received { bytes: u64 },
+    /// The peer returned a block that failed verification
+    BadData,
+    /// The peer broke the wire protocol (malformed, oversized or out-of-order messages)
+    ProtocolViolation,
+}
+
+/// Counters kept for one peer
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PeerStats {
+    pub successes: u64,
+    pub failures: u64,
+    /// Moving average of response latency
+    pub avg_latency_ms: Option<f64>,
+    pub requests_served: u64,
+    pub bytes_served: u64,
+    pub bytes_received: u64,
+    pub bad_data: u64,
+    pub protocol_violations: u64,
+    /// The scored observations, decayed
+    #[serde(skip)]
+    recent: RecentEvents,
+}
+
+/// Observation counts that halve every `SCORE_HALF_LIFE`
+#[derive(Debug, Clone, Default, PartialEq)]
+struct RecentEvents {
+    successes: f64,
+    failures: f64,
+    bad_data: f64,
+    protocol_violations: f64,
+    /// When the counts were last decayed
+    at: Option<Instant>,
+}
+
+impl RecentEvents {
+    /// The counts as they stand at `now`, decayed in whole seconds so that events of one
+    /// burst add up exactly
+    fn decayed(&self, now: Instant) -> Self {
+        let Some(at) = self.at else {
+            return Self { at: Some(now), ..self.clone() };
+        };
+        let elapsed = now.saturating_duration_since(at).as_secs();
+        if elapsed == 0 {
+            return self.clone();
+        }
+        let factor = 0.5f64.powf(elapsed as f64 / SCORE_HALF_LIFE.as_secs_f64());
+        Self {
+            successes: self.successes * factor,
+            failures: self.failures * factor,
+            bad_data: self.bad_data * factor,
+            protocol_violations: self.protocol_violations * factor,
+            at: Some(at + Duration::from_secs(elapsed)),
+        }
+    }
+}
+
+impl PeerStats {
+    /// Answered requests raise the score up to a cap; failures, slowness and
+    /// misbehaviour lower it without bound
+    pub fn score(&self) -> f64 {
+        self.score_at(Instant::now())
+    }
+
+    /// Score as of `now`, with the recorded observations decayed to that time
+    pub fn score_at(&self, now: Instant) -> f64 {
+        let recent = self.recent.decayed(now);
+        let reliability = recent.successes.min(100.0) * 0.5;
+        let latency_penalty = self.avg_latency_ms.map_or(0.0, |ms| (ms / 500.0).min(10.0));
+        reliability
+            - latency_penalty
+            - recent.failures * FAILURE_PENALTY
+            - recent.bad_data * BAD_DATA_PENALTY
+            - recent.protocol_violations * VIOLATION_PENALTY
+    }
+
+    fn apply(&mut self, event: PeerEvent, now: Instant) {
+        self.recent = self.recent.decayed(now);
+        match event {
+            PeerEvent::Success { latency } => {
+                self.successes += 1;
+                self.recent.successes += 1.0;
+                let ms = latency.as_secs_f64() * 1000.0;
+                self.avg_latency_ms = Some(self.avg_latency_ms.map_or(ms, |avg| avg * 0.8 + ms * 0.2));
+            }
+            PeerEvent::Failure => {
+                self.failures += 1;
+                self.recent.failures += 1.0;
+            }
+            PeerEvent::Served { bytes } => {
+                self.requests_served += 1;
+                self.bytes_served += bytes;
+            }
+            PeerEvent::Received { bytes } => self.bytes_received += bytes,
+            PeerEvent::BadData => {
+                self.bad_data += 1;
+                self.recent.bad_data += 1.0;
+            }
+            PeerEvent::ProtocolViolation => {
+                self.protocol_violations += 1;
+                self.recent.protocol_violations += 1.0;
+            }
+        }
+    }
+}
+
+/// One row of the peer table
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerRecord {
+    pub peer_id: String,
+    pub connected: bool,
+    pub score: f64,
+    /// Seconds left on the ban (None = not banned)
+    pub banned_for_secs: Option<u64>,
+    #[serde(flatten)]
+    pub stats: PeerStats,
+}
+
+#[derive(Debug, Default)]
+struct PeerEntry {
+    stats: PeerStats,
+    connected: bool,
+    banned_until: Option<Instant>,
+}
+
+/// Per-peer accounting driven by the P2P event loop
+#[derive(Debug)]
+pub struct PeerManager {
+    limits: PeerLimits,
+    peers: HashMap<PeerId, PeerEntry>,
+}
+
+impl PeerManager {
+    pub fn new(limits: PeerLimits) -> Self {
+        Self {
+            limits,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn limits(&self) -> &PeerLimits {
+        &self.limits
+    }
+
+    /// Record `event`; returns true when it got the peer banned
+    pub fn record(&mut self, peer: PeerId, event: PeerEvent) -> bool {
+        self.record_at(peer, event, Instant::now())
+    }
+
+    /// Record `event` as observed at `now`
+    pub fn record_at(&mut self, peer: PeerId, event: PeerEvent, now: Instant) -> bool {
+        let threshold = self.limits.ban_threshold;
+        let entry = self.entry(peer);
+        if entry.banned_until.is_some() {
+            return false;
+        }
+        entry.stats.apply(event, now);
+        if entry.stats.score_at(now) <= threshold {
+            self.ban(peer);
+            return true;
+        }
+        false
+    }
+
+    /// Ban `peer` for the configured duration
+    pub fn ban(&mut self, peer: PeerId) {
+        let until = Instant::now() + self.limits.ban_duration;
+        self.entry(peer).banned_until = Some(until);
+    }
+
+    /// Lift a ban early; returns whether the peer was banned
+    pub fn unban(&mut self, peer: &PeerId) -> bool {
+        match self.peers.get_mut(peer) {
+            Some(entry) if entry.banned_until.is_some() => {
+                *entry = PeerEntry { connected: entry.connected, ..Default::default() };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Lift bans that ran out by `now`; the peers start over with a clean record
+    pub fn expire_bans(&mut self, now: Instant) -> Vec<PeerId> {
+        let expired: Vec<PeerId> = self.peers.iter()
+            .filter(|(_, entry)| entry.banned_until.is_some_and(|until| until <= now))
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in &expired {
+            self.unban(peer);
+        }
+        expired
+    }
+
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.peers.get(peer).is_some_and(|entry| entry.banned_until.is_some())
+    }
+
+    pub fn set_connected(&mut self, peer: PeerId, connected: bool) {
+        self.entry(peer).connected = connected;
+    }
+
+    pub fn score(&self, peer: &PeerId) -> f64 {
+        self.peers.get(peer).map_or(0.0, |entry| entry.stats.score())
+    }
+
+    /// All known peers, best score first
+    pub fn table(&self) -> Vec<PeerRecord> {
+        let now = Instant::now();
+        let mut table: Vec<PeerRecord> = self.peers.iter()
+            .map(|(peer, entry)| PeerRecord {
+                peer_id: peer.to_string(),
+                connected: entry.connected,
+                score: entry.stats.score(),
+                banned_for_secs: entry.banned_until.map(|until| until.saturating_duration_since(now).as_secs()),
+                stats: entry.stats.clone(),
+            })
+            .collect();
+        table.sort_by(|a, b| b.score.total_cmp(&a.score));
+        table
+    }
+
+    fn entry(&mut self, peer: PeerId) -> &mut PeerEntry {
+        if !self.peers.contains_key(&peer) && self.peers.len() >= MAX_TRACKED_PEERS {
+            self.evict();
+        }
+        self.peers.entry(peer).or_default()
+    }
+
+    /// Forget the best-scored peer that is neither connected nor banned
+    fn evict(&mut self) {
+        let candidate = self.peers.iter()
+            .filter(|(_, entry)| !entry.connected && entry.banned_until.is_none())
+            .max_by(|(_, a), (_, b)| a.stats.score().total_cmp(&b.stats.score()))
+            .map(|(peer, _)| *peer);
+        if let Some(peer) = candidate {
+            self.peers.remove(&peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_misbehaving_peer_is_banned() {
+        let mut manager = PeerManager::new(PeerLimits::default());
+        let good = PeerId::random();
+        let bad = PeerId::random();
+
+        for _ in 0..10 {
+            assert!(!manager.record(good, PeerEvent::Success { latency: Duration::from_millis(20) }));
+        }
+        manager.record(good, PeerEvent::Failure);
+        assert!(manager.score(&good) > 0.0);
+
+        assert!(!manager.record(bad, PeerEvent::BadData));
+        assert!(!manager.record(bad, PeerEvent::ProtocolViolation));
+        assert!(manager.record(bad, PeerEvent::ProtocolViolation));
+        assert!(manager.is_banned(&bad));
+        assert!(!manager.is_banned(&good));
+
+        // Events from a banned peer are ignored, and the table lists the best peer first
+        assert!(!manager.record(bad, PeerEvent::BadData));
+        let table = manager.table();
+        assert_eq!(table[0].peer_id, good.to_string());
+        assert_eq!(table[1].stats.bad_data, 1);
+        assert!(table[1].banned_for_secs.is_some());
+    }
+
+    #[test]
+    fn test_ban_expires_with_clean_record() {
+        let mut manager = PeerManager::new(PeerLimits {
+            ban_duration: Duration::ZERO,
+            ..Default::default()
+        });
+        let peer = PeerId::random();
+        manager.record(peer, PeerEvent::Served { bytes: 512 });
+        manager.ban(peer);
+
+        assert_eq!(manager.expire_bans(Instant::now()), vec![peer]);
+        assert!(!manager.is_banned(&peer));
+        assert_eq!(manager.table()[0].stats, PeerStats::default());
+        assert!(!manager.unban(&peer));
+    }
+
+    #[test]
+    fn test_old_failures_fade() {
+        let mut manager = PeerManager::new(PeerLimits::default());
+        let peer = PeerId::random();
+        let start = Instant::now();
+
+        // A failure every minute for two days never adds up to a ban
+        for minute in 0..2 * 24 * 60 {
+            let now = start + Duration::from_secs(minute * 60);
+            assert!(!manager.record_at(peer, PeerEvent::Failure, now), "banned after {} minutes", minute);
+        }
+        assert_eq!(manager.table()[0].stats.failures, 2 * 24 * 60);
+
+        // The same failures all at once do
+        let burst = PeerId::random();
+        assert!((0..100).any(|_| manager.record_at(burst, PeerEvent::Failure, start)));
+
+        // A violation counts for less once it is old
+        let stats = &mut manager.peers.get_mut(&peer).unwrap().stats;
+        let later = start + Duration::from_secs(3 * 24 * 60 * 60);
+        stats.apply(PeerEvent::ProtocolViolation, later);
+        let fresh = stats.score_at(later);
+        assert!(stats.score_at(later + SCORE_HALF_LIFE) > fresh + VIOLATION_PENALTY / 4.0);
+    }
+}
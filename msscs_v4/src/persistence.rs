@@ -1,133 +1,508 @@
 // Persistence module
-use crate::block::DataBlock;
+use crate::block::{DataBlock, DataKey, IdentityKey, KeyWrapper, Keyring, StoredBlock, WrappedKey};
+use crate::adaptive_compression::CompressionAlgorithm;
+use crate::block_store::{open_block_store, BlockStore, EmbeddedBlockStore};
+use crate::config::Config;
+use crate::content_addressing::{ChunkIndex, ConvergenceKey};
 use crate::error::{MSSCSError, Result};
-use std::collections::HashMap;
+use crate::log_store::RecoveryReport;
+use crate::namespace::{ChainStats, Namespace};
+use crate::snapshot::SnapshotSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::PathBuf;
+use std::sync::Arc;
 use uuid::Uuid;
 
+/// Name of the path -> first block manifest inside the block store
+const FILE_MANIFEST: &str = "manifest";
+/// Name of the directory tree manifest inside the block store
+const NAMESPACE_MANIFEST: &str = "namespace";
+/// Name of the snapshot manifest inside the block store
+const SNAPSHOTS_MANIFEST: &str = "snapshots";
+/// Name of the chunk refcount manifest inside the block store
+const CHUNK_INDEX_MANIFEST: &str = "chunks";
+/// Name of the manifest holding the convergent encryption key
+const CONVERGENCE_KEY_MANIFEST: &str = "convergence-key";
+/// Key prefix of content-addressed chunks (block keys are bare UUIDs)
+pub(crate) const CHUNK_KEY_PREFIX: &str = "chunk-";
+
+/// Outcome of `PersistenceManager::migrate_legacy_blocks`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockMigrationReport {
+    /// Blocks re-encrypted under a per-file data key
+    pub migrated: usize,
+    /// Chains found, each given its own data key
+    pub chains: usize,
+    /// Legacy blocks that could not be decrypted (left untouched with the rest of their chain)
+    pub failed: Vec<Uuid>,
+    /// File versions, live or in snapshots, whose root hash was updated
+    pub versions_updated: usize,
+}
+
+/// Outcome of `PersistenceManager::rewrap_data_keys`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyRewrapReport {
+    /// Blocks whose data key is now wrapped by the current identity key
+    pub rewrapped: usize,
+    /// Blocks wrapped by an identity key that is neither current nor retired
+    pub unknown: usize,
+}
+
 /// Manages disk I/O for blocks, manifest, and configuration
 pub struct PersistenceManager {
     data_dir: PathBuf,
+    store: Arc<dyn BlockStore>,
 }
 
 impl PersistenceManager {
-    /// Create new persistence manager and initialize directory structure
+    /// Create new persistence manager on the default embedded store and run crash recovery
     pub fn new(data_dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&data_dir)?;
+        let store: Arc<dyn BlockStore> = Arc::new(EmbeddedBlockStore::open(&data_dir)?);
+        Self::with_store(data_dir, store)
+    }
+
+    /// Create persistence manager on the backend selected in `config`
+    pub fn from_config(config: &Config) -> Result<Self> {
+        fs::create_dir_all(&config.data_dir)?;
+        let store = open_block_store(config.storage_backend, &config.data_dir)?;
+        Self::with_store(config.data_dir.clone(), store)
+    }
+
+    /// Create persistence manager on an already opened block store
+    pub fn with_store(data_dir: PathBuf, store: Arc<dyn BlockStore>) -> Result<Self> {
         // Create data directory structure
         fs::create_dir_all(&data_dir)?;
-        fs::create_dir_all(data_dir.join("blocks"))?;
         fs::create_dir_all(data_dir.join("logs"))?;
-        
-        Ok(PersistenceManager { data_dir })
+
+        let report = store.recovery_report();
+        if report.is_clean() {
+            tracing::info!(
+                "Block store '{}' ready ({} legacy files imported)",
+                store.backend_name(),
+                report.legacy_imported
+            );
+        } else {
+            tracing::warn!(
+                "Block store recovered with problems: {} corrupted, {} uncommitted records discarded, {} bytes truncated",
+                report.corrupted.len(),
+                report.uncommitted_discarded,
+                report.truncated_bytes
+            );
+            for entry in &report.corrupted {
+                tracing::warn!("Corrupted entry at {}: {}", entry.location, entry.reason);
+            }
+        }
+
+        Ok(PersistenceManager { data_dir, store })
     }
 
-    /// Save block to disk
-    pub fn save_block(&self, block: &DataBlock) -> Result<()> {
-        let filename = format!("{}.block", block.uuid);
-        let path = self.data_dir.join("blocks").join(filename);
-        
+    /// Underlying block store
+    pub fn store(&self) -> Arc<dyn BlockStore> {
+        self.store.clone()
+    }
+
+    /// Report produced by the startup recovery pass
+    pub fn recovery_report(&self) -> RecoveryReport {
+        self.store.recovery_report()
+    }
+
+    /// Save block to disk (durable once this returns)
+    pub async fn save_block(&self, block: &DataBlock) -> Result<()> {
         let serialized = bincode::serialize(block)?;
-        fs::write(path, serialized)?;
-        
-        Ok(())
+        self.store.put(&block.uuid.to_string(), &serialized).await
     }
 
     /// Load block from disk
-    pub fn load_block(&self, uuid: &Uuid) -> Result<DataBlock> {
-        let filename = format!("{}.block", uuid);
-        let path = self.data_dir.join("blocks").join(filename);
-        
-        let data = fs::read(&path).map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                MSSCSError::NotFound(format!("Block {} not found on disk", uuid))
-            } else {
-                MSSCSError::Io(e)
-            }
-        })?;
-        
+    pub async fn load_block(&self, uuid: &Uuid) -> Result<DataBlock> {
+        let data = self
+            .store
+            .get(&uuid.to_string())
+            .await?
+            .ok_or_else(|| MSSCSError::NotFound(format!("Block {} not found on disk", uuid)))?;
+
         let block: DataBlock = bincode::deserialize(&data)?;
         Ok(block)
     }
 
+    /// Load a block in either layout
+    async fn load_stored_block(&self, uuid: &Uuid) -> Result<StoredBlock> {
+        let data = self
+            .store
+            .get(&uuid.to_string())
+            .await?
+            .ok_or_else(|| MSSCSError::NotFound(format!("Block {} not found on disk", uuid)))?;
+        StoredBlock::deserialize(&data)
+    }
+
     /// Load all blocks from disk
-    pub fn load_all_blocks(&self) -> Result<HashMap<String, DataBlock>> {
+    /// Blocks still in the legacy layout are skipped until `migrate_legacy_blocks` re-encrypts them
+    pub async fn load_all_blocks(&self) -> Result<HashMap<String, DataBlock>> {
         let mut blocks = HashMap::new();
-        let blocks_dir = self.data_dir.join("blocks");
-        
-        if !blocks_dir.exists() {
-            return Ok(blocks);
-        }
-        
-        for entry in fs::read_dir(blocks_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            if path.extension().and_then(|s| s.to_str()) == Some("block") {
-                let data = fs::read(&path)?;
-                if let Ok(block) = bincode::deserialize::<DataBlock>(&data) {
+        let mut legacy = 0;
+
+        for key in self.store.list().await? {
+            if key.starts_with(CHUNK_KEY_PREFIX) {
+                continue;
+            }
+            let Some(data) = self.store.get(&key).await? else {
+                continue;
+            };
+            match StoredBlock::deserialize(&data) {
+                Ok(StoredBlock::Current(block)) => {
                     blocks.insert(block.uuid.to_string(), block);
                 }
+                Ok(StoredBlock::Legacy(_)) => legacy += 1,
+                Err(e) => {
+                    tracing::warn!("Skipping undecodable block {}: {}", key, e);
+                }
             }
         }
-        
+
+        if legacy > 0 {
+            tracing::warn!(
+                "{} blocks still use the legacy UUID-derived key; run `msscs_v4 migrate-blocks` to re-encrypt them",
+                legacy
+            );
+        }
         Ok(blocks)
     }
 
-    /// Delete block from disk
-    pub fn delete_block(&self, uuid: &Uuid) -> Result<()> {
-        let filename = format!("{}.block", uuid);
-        let path = self.data_dir.join("blocks").join(filename);
-        
-        if path.exists() {
-            fs::remove_file(path)?;
+    /// Walk the chain starting at `head` and measure the file it holds
+    /// Flat manifests predate per-user keys, so their chains decode without one; chains that were
+    /// already re-encrypted only yield their block count
+    pub async fn measure_chain(&self, head: Uuid, compression: CompressionAlgorithm) -> Result<ChainStats> {
+        let mut stats = ChainStats::default();
+        let mut data = Some(Vec::new());
+        let mut visited = HashSet::new();
+        let mut current = Some(head);
+        while let Some(uuid) = current {
+            if !visited.insert(uuid) {
+                return Err(MSSCSError::CorruptedData(format!("Block chain {} loops at {}", head, uuid)));
+            }
+            let block = self.load_stored_block(&uuid).await?;
+            stats.block_count += 1;
+            match (&block, data.as_mut()) {
+                (StoredBlock::Legacy(legacy), Some(data)) => data.extend_from_slice(&legacy.decode()?),
+                _ => data = None,
+            }
+            current = block.previous_uuid();
         }
-        
+
+        if let Some(data) = data {
+            stats.size = match compression {
+                CompressionAlgorithm::Huffman => crate::huffman::decompress(&data)?.len() as u64,
+                _ => data.len() as u64,
+            };
+        }
+        Ok(stats)
+    }
+
+    /// Re-encrypt every legacy block under a fresh per-file data key wrapped by `identity_key`
+    /// Blocks keep their UUIDs, so the namespace and snapshots stay valid; chains are relinked
+    /// from genesis to head and the new head hashes are recorded as the versions' root hashes.
+    /// Safe to re-run after an interruption: migrated blocks are no longer legacy.
+    pub async fn migrate_legacy_blocks(&self, identity_key: &IdentityKey) -> Result<BlockMigrationReport> {
+        let mut report = BlockMigrationReport::default();
+
+        let mut legacy = HashMap::new();
+        for key in self.store.list().await? {
+            if key.starts_with(CHUNK_KEY_PREFIX) {
+                continue;
+            }
+            let Some(data) = self.store.get(&key).await? else {
+                continue;
+            };
+            if let Ok(StoredBlock::Legacy(block)) = StoredBlock::deserialize(&data) {
+                legacy.insert(block.uuid, block);
+            }
+        }
+        if legacy.is_empty() {
+            return Ok(report);
+        }
+
+        // Chains link each block to its predecessor, so walk them from the genesis side
+        let mut successors: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        let mut starts = Vec::new();
+        for block in legacy.values() {
+            match block.previous_uuid {
+                Some(previous) if legacy.contains_key(&previous) => {
+                    successors.entry(previous).or_default().push(block.uuid)
+                }
+                _ => starts.push(block.uuid),
+            }
+        }
+
+        let mut root_hashes = HashMap::new();
+        for start in starts {
+            let first = &legacy[&start];
+            // An interrupted run may already have migrated the genesis side of this chain
+            let previous_hash = match first.previous_uuid {
+                Some(previous) => match self.load_block(&previous).await {
+                    Ok(block) => block.calculate_hash()?,
+                    Err(_) => first.previous_hash,
+                },
+                None => first.previous_hash,
+            };
+
+            let data_key = DataKey::generate(identity_key)?;
+            report.chains += 1;
+            let mut pending = vec![(start, previous_hash)];
+            while let Some((uuid, previous_hash)) = pending.pop() {
+                let block = match DataBlock::from_legacy(&legacy[&uuid], previous_hash, &data_key) {
+                    Ok(block) => block,
+                    Err(e) => {
+                        // The rest of the chain stays legacy as well
+                        tracing::warn!("Cannot migrate block {}: {}", uuid, e);
+                        report.failed.push(uuid);
+                        continue;
+                    }
+                };
+                self.save_block(&block).await?;
+                report.migrated += 1;
+
+                let hash = block.calculate_hash()?;
+                match successors.get(&uuid) {
+                    Some(next) => pending.extend(next.iter().map(|uuid| (*uuid, hash))),
+                    None => {
+                        root_hashes.insert(uuid, hash);
+                    }
+                }
+            }
+        }
+
+        if let Some(mut namespace) = self.load_namespace().await? {
+            let updated = namespace.update_root_hashes(&root_hashes);
+            if updated > 0 {
+                self.save_namespace(&namespace).await?;
+                report.versions_updated += updated;
+            }
+        }
+        let mut snapshots = self.load_snapshots().await?;
+        let updated = snapshots.update_root_hashes(&root_hashes);
+        if updated > 0 {
+            self.save_snapshots(&snapshots).await?;
+            report.versions_updated += updated;
+        }
+
+        tracing::info!(
+            "Re-encrypted {} legacy blocks in {} chains ({} failed)",
+            report.migrated,
+            report.chains,
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    /// Re-wrap the data keys of blocks still wrapped by a retired identity key under `current`
+    /// Payloads and chain hashes are untouched, so replicas on other nodes stay valid and
+    /// readable with the retired key. Safe to re-run: re-wrapped blocks are skipped.
+    pub async fn rewrap_data_keys(&self, retired: &[IdentityKey], current: &IdentityKey) -> Result<KeyRewrapReport> {
+        let mut report = KeyRewrapReport::default();
+        let current_id = current.id();
+        // Blocks of one file share a wrapping; keep them sharing the new one
+        let mut rewrapped: HashMap<WrappedKey, WrappedKey> = HashMap::new();
+
+        for mut block in self.load_all_blocks().await?.into_values() {
+            if block.wrapped_key.key_id == current_id {
+                continue;
+            }
+            let wrapped = match rewrapped.get(&block.wrapped_key) {
+                Some(wrapped) => wrapped.clone(),
+                None => {
+                    let Some(identity_key) = retired.iter().find(|key| key.id() == block.wrapped_key.key_id) else {
+                        report.unknown += 1;
+                        continue;
+                    };
+                    let wrapped = DataKey::unwrap(&block.wrapped_key, identity_key)?.rewrap(current)?.wrapped().clone();
+                    rewrapped.insert(block.wrapped_key.clone(), wrapped.clone());
+                    wrapped
+                }
+            };
+            block.wrapped_key = wrapped;
+            self.save_block(&block).await?;
+            report.rewrapped += 1;
+        }
+
+        // Deduplicated chunks are sealed under the convergence key, which the identity wraps too
+        if self.store.get_manifest(CONVERGENCE_KEY_MANIFEST).await?.is_some() {
+            let keyring = Keyring { current: current.clone(), retired: retired.to_vec() };
+            self.load_or_create_convergence_key(&keyring).await?;
+        }
+
+        if report.unknown > 0 {
+            tracing::warn!("{} blocks are wrapped by an unknown identity key", report.unknown);
+        }
+        tracing::info!("Re-wrapped the data keys of {} blocks", report.rewrapped);
+        Ok(report)
+    }
+
+    /// Delete block from disk
+    pub async fn delete_block(&self, uuid: &Uuid) -> Result<()> {
+        self.store.delete(&uuid.to_string()).await?;
         Ok(())
     }
 
-    /// Save manifest to disk
-    pub fn save_manifest(&self, manifest: &HashMap<String, Uuid>) -> Result<()> {
-        let path = self.data_dir.join("manifest.json");
+    /// Save a sealed content-addressed chunk (durable once this returns)
+    pub async fn save_chunk(&self, cid: &str, sealed: &[u8]) -> Result<()> {
+        self.store.put(&chunk_key(cid), sealed).await
+    }
+
+    /// Load a sealed content-addressed chunk
+    pub async fn load_chunk(&self, cid: &str) -> Result<Vec<u8>> {
+        self.store
+            .get(&chunk_key(cid))
+            .await?
+            .ok_or_else(|| MSSCSError::NotFound(format!("Chunk {} not found on disk", cid)))
+    }
+
+    /// CIDs of every stored chunk
+    pub async fn list_chunks(&self) -> Result<Vec<String>> {
+        Ok(self
+            .store
+            .list()
+            .await?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(CHUNK_KEY_PREFIX).map(str::to_string))
+            .collect())
+    }
+
+    /// Delete chunks in a single commit
+    pub async fn delete_chunks(&self, cids: &[String]) -> Result<usize> {
+        let keys: Vec<String> = cids.iter().map(|cid| chunk_key(cid)).collect();
+        self.store.delete_many(&keys).await
+    }
+
+    /// Save the chunk refcount index atomically
+    pub async fn save_chunk_index(&self, index: &ChunkIndex) -> Result<()> {
+        let json = serde_json::to_vec(index)
+            .map_err(|e| MSSCSError::Config(format!("Failed to serialize chunk index: {}", e)))?;
+        self.store.put_manifest(CHUNK_INDEX_MANIFEST, &json).await
+    }
+
+    /// Load the chunk refcount index (empty if nothing was deduplicated yet)
+    pub async fn load_chunk_index(&self) -> Result<ChunkIndex> {
+        let Some(data) = self.store.get_manifest(CHUNK_INDEX_MANIFEST).await? else {
+            return Ok(ChunkIndex::new());
+        };
+
+        serde_json::from_slice(&data)
+            .map_err(|e| MSSCSError::Config(format!("Failed to parse chunk index: {}", e)))
+    }
+
+    /// Save the convergent encryption key of this store, wrapped by the current key of `keys`
+    /// It seals every deduplicated chunk, so it never reaches the store in the clear
+    pub async fn save_convergence_key(&self, key: &ConvergenceKey, keys: &dyn KeyWrapper) -> Result<()> {
+        let json = serde_json::to_vec(&keys.wrap_key(key.as_bytes()).await?)
+            .map_err(|e| MSSCSError::Config(format!("Failed to serialize convergence key: {}", e)))?;
+        self.store.put_manifest(CONVERGENCE_KEY_MANIFEST, &json).await
+    }
+
+    /// Load the convergent encryption key with the current or a retired key of `keys`,
+    /// generating one on first use
+    /// A key wrapped by a retired generation, or left in the clear by older versions, is
+    /// re-wrapped under the current key
+    pub async fn load_or_create_convergence_key(&self, keys: &dyn KeyWrapper) -> Result<ConvergenceKey> {
+        let Some(data) = self.store.get_manifest(CONVERGENCE_KEY_MANIFEST).await? else {
+            let key = ConvergenceKey::generate();
+            self.save_convergence_key(&key, keys).await?;
+            return Ok(key);
+        };
+
+        if let Ok(wrapped) = serde_json::from_slice::<WrappedKey>(&data) {
+            let key = ConvergenceKey::from_bytes(keys.unwrap_key(&wrapped).await?);
+            if wrapped.key_id != keys.key_id() {
+                self.save_convergence_key(&key, keys).await?;
+            }
+            return Ok(key);
+        }
+
+        let bytes: [u8; 32] = hex::decode(&data)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| MSSCSError::Config("Invalid convergence key manifest".to_string()))?;
+        let key = ConvergenceKey::from_bytes(bytes);
+        self.save_convergence_key(&key, keys).await?;
+        // Drop the superseded plaintext record from the log as well
+        self.store.purge().await?;
+        tracing::info!("🔐 Wrapped the plaintext convergence key under the identity key");
+        Ok(key)
+    }
+
+    /// Save manifest to disk atomically
+    pub async fn save_manifest(&self, manifest: &HashMap<String, Uuid>) -> Result<()> {
         let json = serde_json::to_string_pretty(manifest)
             .map_err(|e| MSSCSError::Config(format!("Failed to serialize manifest: {}", e)))?;
-        fs::write(path, json)?;
-        Ok(())
+        self.store.put_manifest(FILE_MANIFEST, json.as_bytes()).await
     }
 
     /// Load manifest from disk
-    pub fn load_manifest(&self) -> Result<HashMap<String, Uuid>> {
-        let path = self.data_dir.join("manifest.json");
-        
-        if !path.exists() {
+    pub async fn load_manifest(&self) -> Result<HashMap<String, Uuid>> {
+        let Some(data) = self.store.get_manifest(FILE_MANIFEST).await? else {
             return Ok(HashMap::new());
-        }
-        
-        let data = fs::read_to_string(&path)?;
-        let manifest: HashMap<String, Uuid> = serde_json::from_str(&data)
+        };
+
+        let manifest: HashMap<String, Uuid> = serde_json::from_slice(&data)
             .map_err(|e| MSSCSError::Config(format!("Failed to parse manifest: {}", e)))?;
         
         Ok(manifest)
     }
 
+    /// Save directory tree atomically
+    pub async fn save_namespace(&self, namespace: &Namespace) -> Result<()> {
+        let json = serde_json::to_vec(namespace)
+            .map_err(|e| MSSCSError::Config(format!("Failed to serialize namespace: {}", e)))?;
+        self.store.put_manifest(NAMESPACE_MANIFEST, &json).await
+    }
+
+    /// Load directory tree (None if it was never saved)
+    pub async fn load_namespace(&self) -> Result<Option<Namespace>> {
+        let Some(data) = self.store.get_manifest(NAMESPACE_MANIFEST).await? else {
+            return Ok(None);
+        };
+
+        let namespace: Namespace = serde_json::from_slice(&data)
+            .map_err(|e| MSSCSError::Config(format!("Failed to parse namespace: {}", e)))?;
+
+        Ok(Some(namespace))
+    }
+
+    /// Save all snapshots atomically
+    pub async fn save_snapshots(&self, snapshots: &SnapshotSet) -> Result<()> {
+        let json = serde_json::to_vec(snapshots)
+            .map_err(|e| MSSCSError::Config(format!("Failed to serialize snapshots: {}", e)))?;
+        self.store.put_manifest(SNAPSHOTS_MANIFEST, &json).await
+    }
+
+    /// Load snapshots (empty if none were ever taken)
+    pub async fn load_snapshots(&self) -> Result<SnapshotSet> {
+        let Some(data) = self.store.get_manifest(SNAPSHOTS_MANIFEST).await? else {
+            return Ok(SnapshotSet::new());
+        };
+
+        serde_json::from_slice(&data)
+            .map_err(|e| MSSCSError::Config(format!("Failed to parse snapshots: {}", e)))
+    }
+
     /// Clean up orphaned blocks not referenced in manifest
-    pub fn cleanup_orphaned_blocks(&self, manifest: &HashMap<String, Uuid>) -> Result<usize> {
-        let blocks_dir = self.data_dir.join("blocks");
-        let mut deleted_count = 0;
-        
-        if !blocks_dir.exists() {
-            return Ok(0);
-        }
-        
-        // Collect all UUIDs referenced in manifest (including chain links)
+    pub async fn cleanup_orphaned_blocks(&self, manifest: &HashMap<String, Uuid>) -> Result<usize> {
+        let heads: Vec<Uuid> = manifest.values().copied().collect();
+        Ok(self.cleanup_unreferenced_blocks(&heads).await?.len())
+    }
+
+    /// Delete every block not reachable from one of `heads`; returns the deleted keys
+    pub async fn cleanup_unreferenced_blocks(&self, heads: &[Uuid]) -> Result<Vec<String>> {
+        // Collect all UUIDs referenced by the heads (including chain links)
         let mut referenced_uuids = std::collections::HashSet::new();
-        for first_uuid in manifest.values() {
+        for first_uuid in heads {
             referenced_uuids.insert(first_uuid.to_string());
             
             // Follow the chain to collect all linked blocks
+            // Legacy blocks count too, or an unmigrated chain would be deleted as orphaned
             let mut current_uuid = *first_uuid;
-            while let Ok(block) = self.load_block(&current_uuid) {
-                if let Some(prev_uuid) = block.previous_uuid {
+            while let Ok(block) = self.load_stored_block(&current_uuid).await {
+                if let Some(prev_uuid) = block.previous_uuid() {
                     referenced_uuids.insert(prev_uuid.to_string());
                     current_uuid = prev_uuid;
                 } else {
@@ -136,22 +511,22 @@ impl PersistenceManager {
             }
         }
         
-        // Delete blocks not in referenced set
-        for entry in fs::read_dir(blocks_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            if path.extension().and_then(|s| s.to_str()) == Some("block") {
-                if let Some(filename) = path.file_stem().and_then(|s| s.to_str()) {
-                    if !referenced_uuids.contains(filename) {
-                        fs::remove_file(&path)?;
-                        deleted_count += 1;
-                    }
-                }
-            }
+        // Delete blocks not in referenced set in a single commit (chunks are refcounted separately)
+        let orphans: Vec<String> = self
+            .store
+            .list()
+            .await?
+            .into_iter()
+            .filter(|key| !key.starts_with(CHUNK_KEY_PREFIX) && !referenced_uuids.contains(key))
+            .collect();
+        self.store.delete_many(&orphans).await?;
+
+        let reclaimed = self.store.compact().await?;
+        if reclaimed > 0 {
+            tracing::info!("Compacted block store, reclaimed {} bytes", reclaimed);
         }
         
-        Ok(deleted_count)
+        Ok(orphans)
     }
 
     // FileMetadata methods removed - will be implemented when FileMetadata struct is added
@@ -179,3 +554,265 @@ impl PersistenceManager {
         Ok(file_ids)
     }
 }
+
+/// Store key of a content-addressed chunk (also its provider key in the DHT)
+pub(crate) fn chunk_key(cid: &str) -> String {
+    format!("{}{}", CHUNK_KEY_PREFIX, cid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_store::StorageBackend;
+    use tempfile::TempDir;
+
+    fn identity_key() -> IdentityKey {
+        IdentityKey::from_bytes([9u8; 32])
+    }
+
+    fn sample_block(prev: Option<&DataBlock>) -> DataBlock {
+        DataBlock::new(
+            b"persistence test payload",
+            0,
+            prev.map(|b| b.uuid),
+            prev.map(|b| b.calculate_hash().unwrap()).unwrap_or([0u8; 32]),
+            &DataKey::generate(&identity_key()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// Serialize a block the way releases before per-user keys did
+    fn legacy_block(data: &[u8], node_index: u64, previous_uuid: Option<Uuid>) -> (Uuid, Vec<u8>) {
+        use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Nonce};
+        use sha2::{Digest, Sha256};
+
+        let uuid = Uuid::new_v4();
+        let nibbles: Vec<u8> = data.iter().flat_map(|byte| [byte >> 4, byte & 0x0F]).collect();
+        let key: [u8; 32] = Sha256::new()
+            .chain_update(uuid.as_bytes())
+            .chain_update(node_index.to_le_bytes())
+            .finalize()
+            .into();
+        let nonce = [3u8; 12];
+        let payload = Aes256Gcm::new(&key.into())
+            .encrypt(Nonce::from_slice(&nonce), crate::huffman::compress(&nibbles).unwrap().as_ref())
+            .unwrap();
+        let layout = (uuid, node_index, previous_uuid, [0u8; 32], nonce, payload);
+        (uuid, bincode::serialize(&layout).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_blocks_survive_restart() {
+        let dir = TempDir::new().unwrap();
+        let block = sample_block(None);
+        {
+            let pm = PersistenceManager::new(dir.path().to_path_buf()).unwrap();
+            pm.save_block(&block).await.unwrap();
+        }
+
+        let pm = PersistenceManager::new(dir.path().to_path_buf()).unwrap();
+        assert!(pm.recovery_report().is_clean());
+        assert_eq!(pm.load_block(&block.uuid).await.unwrap().uuid, block.uuid);
+        assert!(matches!(pm.load_block(&Uuid::new_v4()).await, Err(MSSCSError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_layout_is_imported() {
+        let dir = TempDir::new().unwrap();
+        let blocks_dir = dir.path().join("blocks");
+        fs::create_dir_all(&blocks_dir).unwrap();
+
+        let block = sample_block(None);
+        fs::write(blocks_dir.join(format!("{}.block", block.uuid)), bincode::serialize(&block).unwrap()).unwrap();
+        fs::write(blocks_dir.join("garbage.block"), b"not a block").unwrap();
+        let mut manifest = HashMap::new();
+        manifest.insert("old.txt".to_string(), block.uuid);
+        fs::write(dir.path().join("manifest.json"), serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+        let pm = PersistenceManager::new(dir.path().to_path_buf()).unwrap();
+        let report = pm.recovery_report();
+        assert_eq!(report.legacy_imported, 1);
+        assert_eq!(report.corrupted.len(), 1);
+        assert!(report.corrupted[0].location.ends_with("garbage.block"));
+        assert!(!blocks_dir.join(format!("{}.block", block.uuid)).exists());
+        assert_eq!(pm.load_block(&block.uuid).await.unwrap().uuid, block.uuid);
+        assert_eq!(pm.load_manifest().await.unwrap(), manifest);
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_keeps_referenced_chain() {
+        for backend in [StorageBackend::Filesystem, StorageBackend::Memory, StorageBackend::Embedded] {
+            let dir = TempDir::new().unwrap();
+            let store = open_block_store(backend, dir.path()).unwrap();
+            let pm = PersistenceManager::with_store(dir.path().to_path_buf(), store).unwrap();
+
+            let genesis = sample_block(None);
+            let head = sample_block(Some(&genesis));
+            let orphan = sample_block(None);
+            for block in [&genesis, &head, &orphan] {
+                pm.save_block(block).await.unwrap();
+            }
+
+            let mut manifest = HashMap::new();
+            manifest.insert("file.txt".to_string(), head.uuid);
+            pm.save_manifest(&manifest).await.unwrap();
+
+            assert_eq!(pm.cleanup_orphaned_blocks(&manifest).await.unwrap(), 1);
+            assert!(pm.load_block(&genesis.uuid).await.is_ok());
+            assert!(pm.load_block(&orphan.uuid).await.is_err());
+            assert_eq!(pm.load_manifest().await.unwrap(), manifest);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_measure_legacy_chain() {
+        let dir = TempDir::new().unwrap();
+        let pm = PersistenceManager::new(dir.path().to_path_buf()).unwrap();
+
+        let compressed = crate::huffman::compress(b"hello world, hello world").unwrap();
+        let (middle, tail) = compressed.split_at(compressed.len() / 2);
+        let (genesis, data) = legacy_block(tail, 1, None);
+        pm.store().put(&genesis.to_string(), &data).await.unwrap();
+        let (head, data) = legacy_block(middle, 0, Some(genesis));
+        pm.store().put(&head.to_string(), &data).await.unwrap();
+
+        let stats = pm.measure_chain(head, CompressionAlgorithm::Huffman).await.unwrap();
+        assert_eq!(stats, ChainStats { size: 24, block_count: 2 });
+        let stats = pm.measure_chain(genesis, CompressionAlgorithm::None).await.unwrap();
+        assert_eq!(stats, ChainStats { size: tail.len() as u64, block_count: 1 });
+
+        // Re-encrypted chains cannot be decoded here, but their length is still known
+        let block = sample_block(None);
+        pm.save_block(&block).await.unwrap();
+        let stats = pm.measure_chain(block.uuid, CompressionAlgorithm::None).await.unwrap();
+        assert_eq!(stats, ChainStats { size: 0, block_count: 1 });
+        assert!(pm.measure_chain(Uuid::new_v4(), CompressionAlgorithm::None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_legacy_blocks() {
+        use crate::adaptive_compression::CompressionAlgorithm;
+        use crate::namespace::FileContent;
+
+        let dir = TempDir::new().unwrap();
+        let pm = PersistenceManager::new(dir.path().to_path_buf()).unwrap();
+
+        // Chains are written back to front: the genesis block holds the last chunk
+        let (genesis, data) = legacy_block(b" world", 1, None);
+        pm.store().put(&genesis.to_string(), &data).await.unwrap();
+        let (head, data) = legacy_block(b"hello", 0, Some(genesis));
+        pm.store().put(&head.to_string(), &data).await.unwrap();
+        let mut namespace = Namespace::new();
+        let content = FileContent {
+            head,
+            size: 11,
+            block_count: 2,
+            compression: CompressionAlgorithm::None,
+            compressed_per_block: false,
+            root_hash: [0u8; 32],
+            chunks: Vec::new(),
+        };
+        namespace.write_file("greeting.txt", content, "local").unwrap();
+        pm.save_namespace(&namespace).await.unwrap();
+
+        // Unmigrated blocks are not loaded, but cleanup still treats the chain as referenced
+        assert!(pm.load_all_blocks().await.unwrap().is_empty());
+        assert!(pm.cleanup_unreferenced_blocks(&[head]).await.unwrap().is_empty());
+
+        let report = pm.migrate_legacy_blocks(&identity_key()).await.unwrap();
+        assert_eq!((report.migrated, report.chains, report.versions_updated), (2, 1, 1));
+        assert!(report.failed.is_empty());
+
+        let genesis_block = pm.load_block(&genesis).await.unwrap();
+        let head_block = pm.load_block(&head).await.unwrap();
+        assert_eq!(head_block.previous_uuid, Some(genesis));
+        assert_eq!(head_block.previous_hash, genesis_block.calculate_hash().unwrap());
+        assert_eq!(head_block.wrapped_key, genesis_block.wrapped_key);
+        assert_eq!(head_block.decode(0, &identity_key()).unwrap(), b"hello");
+        assert_eq!(genesis_block.decode(1, &identity_key()).unwrap(), b" world");
+        assert!(head_block.decode(0, &IdentityKey::from_bytes([1u8; 32])).is_err());
+
+        let namespace = pm.load_namespace().await.unwrap().unwrap();
+        let version = namespace.version("greeting.txt", 1).unwrap();
+        assert_eq!(version.root_hash, hex::encode(head_block.calculate_hash().unwrap()));
+
+        // Nothing left to migrate
+        assert_eq!(pm.load_all_blocks().await.unwrap().len(), 2);
+        assert_eq!(pm.migrate_legacy_blocks(&identity_key()).await.unwrap(), BlockMigrationReport::default());
+    }
+
+    #[tokio::test]
+    async fn test_rewrap_data_keys() {
+        let dir = TempDir::new().unwrap();
+        let pm = PersistenceManager::new(dir.path().to_path_buf()).unwrap();
+        let genesis = sample_block(None);
+        let head = DataBlock::new(
+            b"second block",
+            1,
+            Some(genesis.uuid),
+            genesis.calculate_hash().unwrap(),
+            &DataKey::unwrap(&genesis.wrapped_key, &identity_key()).unwrap(),
+        )
+        .unwrap();
+        let foreign = DataBlock::new(b"foreign", 0, None, [0u8; 32], &DataKey::generate(&IdentityKey::from_bytes([2u8; 32])).unwrap()).unwrap();
+        for block in [&genesis, &head, &foreign] {
+            pm.save_block(block).await.unwrap();
+        }
+
+        let current = IdentityKey::from_bytes([7u8; 32]);
+        let report = pm.rewrap_data_keys(&[identity_key()], &current).await.unwrap();
+        assert_eq!(report, KeyRewrapReport { rewrapped: 2, unknown: 1 });
+
+        let genesis_block = pm.load_block(&genesis.uuid).await.unwrap();
+        let head_block = pm.load_block(&head.uuid).await.unwrap();
+        assert_eq!(genesis_block.wrapped_key, head_block.wrapped_key);
+        assert_eq!(genesis_block.decode(0, &current).unwrap(), b"persistence test payload");
+        assert_eq!(head_block.decode(1, &current).unwrap(), b"second block");
+        assert_eq!(head_block.calculate_hash().unwrap(), head.calculate_hash().unwrap());
+        assert!(head_block.decode(1, &identity_key()).is_err());
+
+        assert_eq!(pm.rewrap_data_keys(&[identity_key()], &current).await.unwrap().rewrapped, 0);
+    }
+
+    #[tokio::test]
+    async fn test_convergence_key_is_stored_wrapped() {
+        let dir = TempDir::new().unwrap();
+        let pm = PersistenceManager::new(dir.path().to_path_buf()).unwrap();
+        let key = pm.load_or_create_convergence_key(&identity_key()).await.unwrap();
+
+        let stored = pm.store().get_manifest(CONVERGENCE_KEY_MANIFEST).await.unwrap().unwrap();
+        assert!(!String::from_utf8_lossy(&stored).contains(&hex::encode(key.as_bytes())));
+        assert!(pm.load_or_create_convergence_key(&IdentityKey::from_bytes([2u8; 32])).await.is_err());
+
+        // A rotation re-wraps it along with the data keys
+        let current = IdentityKey::from_bytes([7u8; 32]);
+        pm.rewrap_data_keys(&[identity_key()], &current).await.unwrap();
+        assert!(pm.load_or_create_convergence_key(&identity_key()).await.is_err());
+        assert_eq!(pm.load_or_create_convergence_key(&current).await.unwrap(), key);
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_convergence_key_is_wrapped_on_load() {
+        let dir = TempDir::new().unwrap();
+        let pm = PersistenceManager::new(dir.path().to_path_buf()).unwrap();
+        let key = ConvergenceKey::from_bytes([5u8; 32]);
+        pm.store().put_manifest(CONVERGENCE_KEY_MANIFEST, hex::encode(key.as_bytes()).as_bytes()).await.unwrap();
+
+        assert_eq!(pm.load_or_create_convergence_key(&identity_key()).await.unwrap(), key);
+        let stored = pm.store().get_manifest(CONVERGENCE_KEY_MANIFEST).await.unwrap().unwrap();
+        assert!(serde_json::from_slice::<WrappedKey>(&stored).is_ok());
+        // Nothing left on disk holds the plaintext key
+        let mut dirs = vec![dir.path().to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    let data = std::fs::read(&path).unwrap();
+                    assert!(!String::from_utf8_lossy(&data).contains(&hex::encode(key.as_bytes())));
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,7 @@
 // BLOCK PINNING & GARBAGE COLLECTION
 // Manages block lifecycle and prevents deletion of important data
 
-use crate::error::{MSSCSError, Result};
+use crate::error::Result;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -96,6 +96,7 @@ impl Pin {
 }
 
 /// Pinning manager
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PinningManager {
     /// All pins (block_id -> pin)
     pins: HashMap<String, Pin>,
@@ -137,7 +138,7 @@ impl PinningManager {
             PinType::User => {
                 self.user_pins
                     .entry(owner.clone())
-                    .or_insert_with(HashSet::new)
+                    .or_default()
                     .insert(block_id.clone());
             }
             PinType::Cache => {
@@ -194,6 +195,29 @@ impl PinningManager {
         self.pins.get(block_id)
     }
     
+    /// List all pins
+    pub fn list_pins(&self) -> Vec<&Pin> {
+        self.pins.values().collect()
+    }
+
+    /// Drop a pin regardless of its reference count
+    pub fn remove_pin(&mut self, block_id: &str) -> Option<Pin> {
+        let pin = self.pins.remove(block_id)?;
+        match pin.pin_type {
+            PinType::User => {
+                if let Some(user_blocks) = self.user_pins.get_mut(&pin.owner) {
+                    user_blocks.remove(block_id);
+                }
+            }
+            PinType::Cache => {
+                self.cache_pins.retain(|id| id != block_id);
+                self.current_cache_size = self.current_cache_size.saturating_sub(pin.size);
+            }
+            _ => {}
+        }
+        Some(pin)
+    }
+
     /// Touch a block (update LRU)
     pub fn touch(&mut self, block_id: &str) {
         if let Some(pin) = self.pins.get_mut(block_id) {
@@ -309,12 +333,7 @@ impl PinningManager {
     
     /// Get available storage space (in bytes)
     pub fn get_available_space(&self) -> usize {
-        let used = self.get_used_space();
-        if used >= self.max_cache_size {
-            0
-        } else {
-            self.max_cache_size - used
-        }
+        self.max_cache_size.saturating_sub(self.get_used_space())
     }
 }
 
@@ -407,4 +426,23 @@ mod tests {
         assert!(can_delete);
         assert!(!pm.is_pinned("block1"));
     }
+    
+    #[test]
+    fn test_pin_state_roundtrip() {
+        let mut pm = PinningManager::new(1024 * 1024);
+        pm.pin("block1".to_string(), PinType::User, "user1".to_string(), 1000).unwrap();
+        pm.pin("block2".to_string(), PinType::Cache, "user1".to_string(), 2000).unwrap();
+        
+        let json = serde_json::to_vec(&pm).unwrap();
+        let mut restored: PinningManager = serde_json::from_slice(&json).unwrap();
+        assert!(restored.is_pinned("block1"));
+        assert_eq!(restored.get_used_space(), 3000);
+        assert_eq!(restored.list_user_pins("user1").len(), 1);
+        
+        // Forced removal ignores the reference count
+        restored.pin("block1".to_string(), PinType::User, "user1".to_string(), 1000).unwrap();
+        assert!(restored.remove_pin("block1").is_some());
+        assert!(!restored.is_pinned("block1"));
+        assert_eq!(restored.list_pins().len(), 1);
+    }
 }
@@ -0,0 +1,265 @@
+// PRIVATE NETWORK - pre-shared key swarm closed to the public DHT
+// Every TCP connection starts with a pnet handshake keyed by a shared secret, so nodes
+// without the key cannot negotiate anything; Kademlia runs under its own protocol name
+// and optional allow-lists narrow membership to known peer ids or to nodes run by known
+// identities (proven in the handshake by an attestation signed with the identity key)
+
+use crate::error::MSSCSError;
+use crate::unlocked_identity::UnlockedIdentity;
+use libp2p::{
+    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade},
+    identity, noise, tcp, yamux, Multiaddr, PeerId, StreamProtocol, Transport,
+};
+use libp2p_pnet::PnetConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+pub use libp2p_pnet::PreSharedKey;
+
+/// Kademlia protocol spoken inside a private swarm (IPFS uses `/ipfs/kad/1.0.0`)
+pub const PRIVATE_KAD_PROTOCOL: &str = "/msscs/kad/1.0.0";
+
+/// Domain separation for the statement an identity signs about its node
+const ATTESTATION_CONTEXT: &[u8] = b"MSSCS node attestation v1";
+
+/// An identity's Ed25519 signature over the peer id of the node it runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityAttestation {
+    /// Ed25519 public key of the identity
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl IdentityAttestation {
+    /// Bytes the identity signs to vouch for `peer`
+    pub fn message(peer: &PeerId) -> Vec<u8> {
+        [ATTESTATION_CONTEXT, &peer.to_bytes()].concat()
+    }
+
+    /// Vouch for `peer` with an unlocked identity
+    pub fn sign(identity: &UnlockedIdentity, peer: &PeerId) -> Result<Self, MSSCSError> {
+        Ok(Self {
+            public_key: identity.identity.public_key.clone(),
+            signature: identity.sign(&Self::message(peer))?,
+        })
+    }
+
+    /// Identity key that vouched for `peer`, if the signature checks out
+    pub fn verify(&self, peer: &PeerId) -> Option<[u8; 32]> {
+        use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+        let public_key: [u8; 32] = self.public_key.as_slice().try_into().ok()?;
+        let signature: [u8; 64] = self.signature.as_slice().try_into().ok()?;
+        VerifyingKey::from_bytes(&public_key)
+            .ok()?
+            .verify(&Self::message(peer), &Signature::from_bytes(&signature))
+            .ok()?;
+        Some(public_key)
+    }
+}
+
+/// Private swarm settings
+#[derive(Clone)]
+pub struct PrivateNetworkConfig {
+    /// Key shared by every member; connections without it fail the handshake
+    pub psk: PreSharedKey,
+    /// Peers allowed to connect (both lists empty = any holder of the key)
+    pub allowed_peers: HashSet<PeerId>,
+    /// Identities (Ed25519 public keys) whose nodes may join once their attestation checks out
+    pub allowed_identities: HashSet<[u8; 32]>,
+    /// This node's attestation, sent in the handshake (needed to join identity-listed swarms)
+    pub attestation: Option<IdentityAttestation>,
+    /// Kademlia protocol name, distinct from the public one
+    pub kad_protocol: String,
+}
+
+// The key itself never ends up in logs, only its fingerprint
+impl fmt::Debug for PrivateNetworkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrivateNetworkConfig")
+            .field("psk_fingerprint", &self.psk.fingerprint().to_string())
+            .field("allowed_peers", &self.allowed_peers.len())
+            .field("allowed_identities", &self.allowed_identities.len())
+            .field("attested", &self.attestation.is_some())
+            .field("kad_protocol", &self.kad_protocol)
+            .finish()
+    }
+}
+
+impl PrivateNetworkConfig {
+    /// Private swarm open to every holder of `psk`
+    pub fn new(psk: PreSharedKey) -> Self {
+        Self {
+            psk,
+            allowed_peers: HashSet::new(),
+            allowed_identities: HashSet::new(),
+            attestation: None,
+            kad_protocol: PRIVATE_KAD_PROTOCOL.to_string(),
+        }
+    }
+
+    /// Read a key in the `swarm.key` format shared with go-libp2p and IPFS
+    pub fn load_key(path: &Path) -> Result<PreSharedKey, MSSCSError> {
+        let text = std::fs::read_to_string(path)?;
+        text.parse()
+            .map_err(|e| MSSCSError::Config(format!("Invalid swarm key {:?}: {:?}", path, e)))
+    }
+
+    /// Generate a key and write it to `path` in the `swarm.key` format
+    pub fn generate_key(path: &Path) -> Result<PreSharedKey, MSSCSError> {
+        use rand::RngCore;
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let psk = PreSharedKey::new(bytes);
+        crate::p2p_network::write_key_file(path, psk.to_string().as_bytes())?;
+        Ok(psk)
+    }
+
+    /// Kademlia protocol name; an invalid one is an error rather than a fall back to IPFS's
+    pub fn kad_protocol_name(&self) -> Result<StreamProtocol, MSSCSError> {
+        StreamProtocol::try_from_owned(self.kad_protocol.clone())
+            .map_err(|e| MSSCSError::Config(format!("Invalid Kademlia protocol name {}: {}", self.kad_protocol, e)))
+    }
+
+    /// Whether `peer` may join on its peer id alone
+    pub fn allows(&self, peer: &PeerId) -> bool {
+        self.allowed_peers.contains(peer) || (self.allowed_peers.is_empty() && self.allowed_identities.is_empty())
+    }
+
+    /// Whether peers outside `allowed_peers` are let in only once their handshake qualifies
+    pub fn requires_handshake(&self) -> bool {
+        !self.allowed_identities.is_empty()
+    }
+
+    /// Whether `peer` may stay after the handshake, where it presented `attestation`
+    pub fn admits(&self, peer: &PeerId, attestation: Option<&IdentityAttestation>) -> bool {
+        self.allows(peer)
+            || attestation
+                .and_then(|attestation| attestation.verify(peer))
+                .is_some_and(|key| self.allowed_identities.contains(&key))
+    }
+}
+
+/// Parse an identity given as its hex Ed25519 public key
+pub fn parse_identity_key(s: &str) -> Option<[u8; 32]> {
+    hex::decode(s.trim()).ok()?.try_into().ok()
+}
+
+/// Parse a peer id given on its own or as the `/p2p/` part of a multiaddr
+pub fn parse_peer_id(s: &str) -> Option<PeerId> {
+    let s = s.trim();
+    if let Ok(peer) = s.parse::<PeerId>() {
+        return Some(peer);
+    }
+    s.parse::<Multiaddr>().ok()?.iter().find_map(|p| match p {
+        libp2p::multiaddr::Protocol::P2p(peer) => Some(peer),
+        _ => None,
+    })
+}
+
+/// TCP transport that runs the pnet handshake before noise and yamux
+pub(crate) fn pnet_tcp_transport(
+    keypair: &identity::Keypair,
+    psk: PreSharedKey,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, Box<dyn std::error::Error + Send + Sync>> {
+    let noise = noise::Config::new(keypair)?;
+    Ok(tcp::tokio::Transport::new(tcp::Config::default().port_reuse(true).nodelay(true))
+        .and_then(move |socket, _| PnetConfig::new(psk).handshake(socket))
+        .upgrade(upgrade::Version::V1Lazy)
+        .authenticate(noise)
+        .multiplex(yamux::Config::default())
+        .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+        .boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p_network::P2PConfig;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_swarm_key_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("swarm.key");
+        let psk = PrivateNetworkConfig::generate_key(&path).unwrap();
+        assert_eq!(PrivateNetworkConfig::load_key(&path).unwrap(), psk);
+
+        std::fs::write(&path, "/key/swarm/psk/1.0.0/\n/base64/\nAAAA\n").unwrap();
+        assert!(PrivateNetworkConfig::load_key(&path).is_err());
+
+        // Logging the config shows the fingerprint, never the key
+        let debug = format!("{:?}", PrivateNetworkConfig::new(psk));
+        let hex_key = psk.to_string().lines().nth(2).unwrap().to_string();
+        assert!(!debug.contains(&hex_key));
+        assert!(debug.contains(&psk.fingerprint().to_string()));
+    }
+
+    #[test]
+    fn test_private_swarm_skips_public_bootstrap() {
+        let local: Multiaddr = "/ip4/10.0.0.2/tcp/4001".parse().unwrap();
+        let mut config = P2PConfig {
+            bootstrap_peers: P2PConfig::default_bootstrap_peers(),
+            ..Default::default()
+        };
+        config.bootstrap_peers.push(local.clone());
+        assert!(config.bootstrap_addresses().len() > 1);
+
+        config.private_network = Some(PrivateNetworkConfig::new(PreSharedKey::new([7; 32])));
+        assert_eq!(config.bootstrap_addresses(), vec![local]);
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let peer = PeerId::random();
+        let addr = format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", peer);
+        assert_eq!(parse_peer_id(&peer.to_string()), Some(peer));
+        assert_eq!(parse_peer_id(&addr), Some(peer));
+        assert_eq!(parse_peer_id("/ip4/10.0.0.2/tcp/4001"), None);
+
+        let mut config = PrivateNetworkConfig::new(PreSharedKey::new([7; 32]));
+        assert!(config.allows(&PeerId::random()));
+        config.allowed_peers.insert(peer);
+        assert!(config.allows(&peer));
+        assert!(!config.allows(&PeerId::random()));
+    }
+
+    #[test]
+    fn test_identity_allow_list() {
+        let unlocked = |name: &str| {
+            crate::identity::QuantumIdentity::new(name.to_string(), "passphrase")
+                .unwrap()
+                .unlock("passphrase")
+                .unwrap()
+        };
+        let (member, outsider) = (unlocked("member"), unlocked("outsider"));
+        let peer = PeerId::random();
+        let key = parse_identity_key(&hex::encode(&member.identity.public_key)).unwrap();
+
+        let mut config = PrivateNetworkConfig::new(PreSharedKey::new([7; 32]));
+        config.allowed_identities.insert(key);
+        // Unknown peer ids get a connection but have to prove an allowed identity
+        assert!(!config.allows(&peer));
+        let attestation = IdentityAttestation::sign(&member, &peer).unwrap();
+        assert!(config.admits(&peer, Some(&attestation)));
+        assert!(!config.admits(&peer, None));
+        assert!(!config.admits(&peer, Some(&IdentityAttestation::sign(&outsider, &peer).unwrap())));
+        // An attestation is bound to the node it was signed for
+        assert!(!config.admits(&PeerId::random(), Some(&attestation)));
+
+        config.kad_protocol = "no-leading-slash".to_string();
+        assert!(matches!(config.kad_protocol_name(), Err(MSSCSError::Config(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_swarm_key_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("swarm.key");
+        PrivateNetworkConfig::generate_key(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,346 @@
+// WIRE PROTOCOLS - versioned protocol family and capability handshake
+// Every concern runs on its own versioned libp2p protocol; right after connecting, peers
+// exchange a NodeInfo over the meta protocol and only use features both sides advertise,
+// so nodes of different versions keep working together
+
+use crate::private_network::IdentityAttestation;
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Version of the protocol family spoken by this build
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Control messages (shards, ping)
+pub const CONTROL_PROTOCOL: &str = "/msscs/control/2";
+
+/// Control protocol of version 1 nodes; still answered, but no longer serves blocks
+pub const LEGACY_CONTROL_PROTOCOL: &str = "/msscs/1.0.0";
+
+/// Handshake carrying `NodeInfo`
+pub const META_PROTOCOL: &str = "/msscs/meta/1";
+
+/// Block transfer in bounded binary frames
+pub const BLOCKS_PROTOCOL: &str = crate::block_transfer::PROTOCOL;
+
+/// Largest handshake message accepted
+const MAX_META_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// Largest version 1 control message accepted
+const MAX_LEGACY_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// Feature names advertised in the handshake
+pub mod features {
+    /// Serves blocks over `BLOCKS_PROTOCOL`
+    pub const BLOCK_RANGES: &str = "block-ranges";
+    /// Holds erasure-coded shards for other peers
+    pub const SHARDS: &str = "shards";
+    /// Announces held blocks as DHT provider records
+    pub const PROVIDERS: &str = "providers";
+    /// Pulls and keeps blocks pushed with `P2PRequest::ReplicateBlock`
+    pub const REPLICATION: &str = "replication";
+    /// Holds social recovery shares for trusted contacts
+    pub const RECOVERY: &str = "recovery";
+    /// Serves deduplicated chunks by content id (and keeps replicas of them when storing)
+    pub const CHUNKS: &str = "chunks";
+}
+
+/// What a node does in the network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    /// Stores data and serves it
+    #[default]
+    Full,
+    /// Uses the network without storing for others
+    Light,
+    /// Only relays connections
+    Relay,
+    /// Only helps peers join the DHT
+    Bootstrap,
+    /// Role introduced by a newer version
+    #[serde(other)]
+    Unknown,
+}
+
+impl NodeRole {
+    /// Whether shards may be placed on nodes with this role
+    pub fn stores_data(&self) -> bool {
+        matches!(self, NodeRole::Full)
+    }
+}
+
+/// Handshake payload; absent fields decode as a version 1 node and unknown ones are ignored
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeInfo {
+    pub protocol_version: u32,
+    pub agent_version: String,
+    pub features: BTreeSet<String>,
+    /// Bytes offered to the network (0 = unspecified)
+    pub storage_capacity: u64,
+    pub role: NodeRole,
+    /// Identity running the node, checked by private swarms that allow-list identities
+    pub attestation: Option<IdentityAttestation>,
+}
+
+impl Default for NodeInfo {
+    fn default() -> Self {
+        Self::legacy()
+    }
+}
+
+impl NodeInfo {
+    /// Info advertised by this build
+    pub fn local(role: NodeRole, storage_capacity: u64) -> Self {
+        let mut features: BTreeSet<String> = [features::BLOCK_RANGES, features::PROVIDERS, features::CHUNKS]
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        if role.stores_data() {
+            features.insert(features::SHARDS.to_string());
+            features.insert(features::REPLICATION.to_string());
+        }
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            agent_version: format!("msscs/{}", env!("CARGO_PKG_VERSION")),
+            features,
+            storage_capacity,
+            role,
+            attestation: None,
+        }
+    }
+
+    /// Assumed info of a peer that does not speak the meta protocol
+    pub fn legacy() -> Self {
+        Self {
+            protocol_version: 1,
+            agent_version: String::new(),
+            features: BTreeSet::new(),
+            storage_capacity: 0,
+            role: NodeRole::Full,
+            attestation: None,
+        }
+    }
+
+    /// What this node and `remote` can use with each other
+    pub fn negotiate(&self, remote: &NodeInfo) -> PeerCapabilities {
+        PeerCapabilities {
+            protocol_version: self.protocol_version.min(remote.protocol_version),
+            features: self.features.intersection(&remote.features).cloned().collect(),
+            agent_version: remote.agent_version.clone(),
+            storage_capacity: remote.storage_capacity,
+            role: remote.role,
+        }
+    }
+}
+
+/// Outcome of the handshake with one peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerCapabilities {
+    /// Highest version both sides speak
+    pub protocol_version: u32,
+    /// Features both sides support
+    pub features: BTreeSet<String>,
+    pub agent_version: String,
+    pub storage_capacity: u64,
+    pub role: NodeRole,
+}
+
+impl PeerCapabilities {
+    /// Whether `feature` may be used with this peer
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+
+    /// Whether shards may be placed on this peer
+    pub fn accepts_shards(&self) -> bool {
+        self.role.stores_data() && self.supports(features::SHARDS)
+    }
+
+    /// Whether block replicas may be pushed to this peer
+    pub fn accepts_replicas(&self) -> bool {
+        self.role.stores_data() && self.supports(features::REPLICATION)
+    }
+}
+
+/// Length-prefixed JSON codec for the handshake
+#[derive(Debug, Clone, Default)]
+pub struct MetaCodec;
+
+#[async_trait]
+impl request_response::Codec for MetaCodec {
+    type Protocol = &'static str;
+    type Request = NodeInfo;
+    type Response = NodeInfo;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_META_MESSAGE_BYTES).await
+    }
+
+    async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_META_MESSAGE_BYTES).await
+    }
+
+    async fn write_request<T>(&mut self, _: &Self::Protocol, io: &mut T, req: Self::Request) -> std::io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &req).await
+    }
+
+    async fn write_response<T>(&mut self, _: &Self::Protocol, io: &mut T, res: Self::Response) -> std::io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &res).await
+    }
+}
+
+/// Control messages sent by version 1 nodes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LegacyRequest {
+    GetBlock { id: uuid::Uuid },
+    Ping,
+}
+
+/// Answers to version 1 nodes; their block format is gone, so `Block` never carries data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LegacyResponse {
+    Block { data: Option<serde_json::Value> },
+    Pong,
+}
+
+impl LegacyResponse {
+    /// Answer a version 1 request the way a v1 node reads "not available here"
+    pub fn answer(request: &LegacyRequest) -> Self {
+        match request {
+            LegacyRequest::GetBlock { .. } => LegacyResponse::Block { data: None },
+            LegacyRequest::Ping => LegacyResponse::Pong,
+        }
+    }
+}
+
+/// Length-prefixed JSON codec of the version 1 control protocol
+#[derive(Debug, Clone, Default)]
+pub struct LegacyCodec;
+
+#[async_trait]
+impl request_response::Codec for LegacyCodec {
+    type Protocol = &'static str;
+    type Request = LegacyRequest;
+    type Response = LegacyResponse;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_LEGACY_MESSAGE_BYTES).await
+    }
+
+    async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_LEGACY_MESSAGE_BYTES).await
+    }
+
+    async fn write_request<T>(&mut self, _: &Self::Protocol, io: &mut T, req: Self::Request) -> std::io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &req).await
+    }
+
+    async fn write_response<T>(&mut self, _: &Self::Protocol, io: &mut T, res: Self::Response) -> std::io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &res).await
+    }
+}
+
+/// Read a length-prefixed JSON message, refusing lengths above `max`
+pub(crate) async fn read_json<T, M>(io: &mut T, max: usize) -> std::io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: serde::de::DeserializeOwned,
+{
+    let mut len_bytes = [0u8; 4];
+    io.read_exact(&mut len_bytes).await?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > max {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("message of {} bytes exceeds limit of {}", len, max),
+        ));
+    }
+
+    let mut buf = vec![0u8; len];
+    io.read_exact(&mut buf).await?;
+    serde_json::from_slice(&buf).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Write a length-prefixed JSON message
+pub(crate) async fn write_json<T, M>(io: &mut T, message: &M) -> std::io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let data = serde_json::to_vec(message).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    io.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    io.write_all(&data).await?;
+    io.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_mixed_versions() {
+        let local = NodeInfo::local(NodeRole::Full, 1 << 30);
+        let light = NodeInfo::local(NodeRole::Light, 0);
+
+        let caps = local.negotiate(&light);
+        assert_eq!(caps.protocol_version, PROTOCOL_VERSION);
+        assert!(caps.supports(features::BLOCK_RANGES));
+        assert!(!caps.accepts_shards());
+        assert!(!caps.accepts_replicas());
+        assert!(local.negotiate(&local).accepts_shards());
+        assert!(local.negotiate(&local).accepts_replicas());
+
+        // A version 1 peer shares no optional features but is still a storage node
+        let caps = local.negotiate(&NodeInfo::legacy());
+        assert_eq!(caps.protocol_version, 1);
+        assert!(caps.features.is_empty());
+        assert_eq!(caps.role, NodeRole::Full);
+    }
+
+    #[test]
+    fn test_decode_is_forward_compatible() {
+        // A newer peer with an unknown role, feature and field
+        let json = r#"{"protocol_version":3,"agent_version":"msscs/9.0.0","features":["shards","teleport"],
+            "storage_capacity":42,"role":"archive","region":"eu"}"#;
+        let info: NodeInfo = serde_json::from_str(json).unwrap();
+        assert_eq!(info.role, NodeRole::Unknown);
+        assert_eq!(info.storage_capacity, 42);
+
+        let caps = NodeInfo::local(NodeRole::Full, 0).negotiate(&info);
+        assert_eq!(caps.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(caps.features.iter().collect::<Vec<_>>(), vec!["shards"]);
+        assert!(!caps.accepts_shards());
+
+        // Missing fields decode as a version 1 node
+        assert_eq!(serde_json::from_str::<NodeInfo>("{}").unwrap(), NodeInfo::legacy());
+    }
+}
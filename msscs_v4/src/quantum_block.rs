@@ -3,13 +3,44 @@
 
 use crate::error::{MSSCSError, Result};
 use crate::huffman;
+use crate::parallel::ParallelBlockProcessor;
 use crate::quantum_crypto::QuantumProofBlock;
-use crate::identity::UnlockedIdentity;
+use crate::unlocked_identity::UnlockedIdentity;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
 use uuid::Uuid;
 use blake3;
 
+/// Encrypts and decrypts quantum blocks with an identity's secret keys
+/// Implemented by the unlocked identity and by the identity agent, which keeps the keys in its
+/// own process
+#[async_trait]
+pub trait KeyHolder: Send + Sync {
+    /// Identity whose keys are used
+    fn identity_id(&self) -> Uuid;
+
+    /// Encrypt chunks into quantum blocks (in order, unlinked)
+    async fn encrypt_blocks(&self, chunks: Vec<Vec<u8>>) -> Result<Vec<QuantumDataBlock>>;
+
+    /// Decrypt quantum blocks (in order)
+    async fn decrypt_blocks(&self, blocks: Vec<QuantumDataBlock>) -> Result<Vec<Vec<u8>>>;
+}
+
+#[async_trait]
+impl KeyHolder for UnlockedIdentity {
+    fn identity_id(&self) -> Uuid {
+        *self.user_id()
+    }
+
+    async fn encrypt_blocks(&self, chunks: Vec<Vec<u8>>) -> Result<Vec<QuantumDataBlock>> {
+        ParallelBlockProcessor::new(num_cpus::get(), 0).encrypt_blocks_parallel(chunks, self)
+    }
+
+    async fn decrypt_blocks(&self, blocks: Vec<QuantumDataBlock>) -> Result<Vec<Vec<u8>>> {
+        ParallelBlockProcessor::new(num_cpus::get(), 0).decrypt_blocks_parallel(blocks, self)
+    }
+}
+
 /// Enhanced data block with quantum-proof encryption
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QuantumDataBlock {
@@ -81,8 +112,8 @@ impl QuantumDataBlock {
         tracing::debug!("  [2/3] Quantum-proof encryption (7 layers)");
         let quantum_block = QuantumProofBlock::new(
             &compressed,
-            identity.master_key(),
-            identity.kyber_public_key(),
+            &identity.master_key(),
+            &identity.kyber_public_key()?,
         )?;
         
         // 3. Create metadata
@@ -118,8 +149,8 @@ impl QuantumDataBlock {
         // 1. Decrypt with quantum-proof decryption
         tracing::debug!("  [1/2] Quantum-proof decryption");
         let compressed = self.quantum_block.decrypt(
-            identity.master_key(),
-            identity.kyber_secret_key(),
+            &identity.master_key(),
+            &identity.kyber_secret_key()?,
         )?;
         
         // 2. Decompress
@@ -216,7 +247,7 @@ mod tests {
     #[test]
     fn test_quantum_block_creation() {
         let passphrase = "test passphrase for quantum block";
-        let identity = QuantumIdentity::new(passphrase).unwrap();
+        let identity = QuantumIdentity::new("test".to_string(), passphrase).unwrap();
         let unlocked = identity.unlock(passphrase).unwrap();
         
         let data = b"Test data for quantum block";
@@ -243,7 +274,7 @@ mod tests {
     #[test]
     fn test_quantum_block_chain() {
         let passphrase = "test chain passphrase";
-        let identity = QuantumIdentity::new(passphrase).unwrap();
+        let identity = QuantumIdentity::new("test".to_string(), passphrase).unwrap();
         let unlocked = identity.unlock(passphrase).unwrap();
         
         // Create genesis block
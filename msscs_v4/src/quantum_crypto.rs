@@ -5,9 +5,7 @@ use aes_gcm::{Aes256Gcm, Nonce};
 use chacha20poly1305::ChaCha20Poly1305;
 use blake3;
 use rand::{RngCore, rngs::OsRng};
-use pqcrypto_kyber::kyber1024;
 use pqcrypto_dilithium::dilithium5;
-use pqcrypto_traits::kem::{SharedSecret, Ciphertext};
 use pqcrypto_traits::sign::DetachedSignature;
 use serde::{Serialize, Deserialize};
 
@@ -51,7 +49,7 @@ impl QuantumProofBlock {
     pub fn new(
         data: &[u8],
         user_master_key: &[u8; 32],
-        kyber_public_key: &kyber1024::PublicKey,
+        kyber_public_key: &pqc_kyber::PublicKey,
     ) -> Result<Self> {
         tracing::info!("🔐 Starting QUANTUM-PROOF encryption (7 layers)");
         
@@ -144,7 +142,7 @@ impl QuantumProofBlock {
     pub fn decrypt(
         &self,
         user_master_key: &[u8; 32],
-        kyber_secret_key: &kyber1024::SecretKey,
+        kyber_secret_key: &pqc_kyber::SecretKey,
     ) -> Result<Vec<u8>> {
         tracing::info!("🔓 Starting QUANTUM-PROOF decryption");
         
@@ -230,8 +228,8 @@ impl QuantumProofBlock {
             .to_le_bytes());
         
         let mixed = hasher.finalize();
-        for i in 0..32 {
-            entropy[i] ^= mixed.as_bytes()[i];
+        for (e, m) in entropy.iter_mut().zip(mixed.as_bytes()) {
+            *e ^= m;
         }
         
         entropy
@@ -239,42 +237,40 @@ impl QuantumProofBlock {
     
     /// LAYER 1: Kyber-1024 post-quantum key encapsulation
     fn kyber_encapsulate(
-        public_key: &kyber1024::PublicKey,
+        public_key: &pqc_kyber::PublicKey,
         entropy: &[u8],
     ) -> Result<(Vec<u8>, [u8; 32])> {
         // Encapsulate to get shared secret
-        let (shared_secret, ciphertext) = kyber1024::encapsulate(public_key);
+        let (ciphertext, shared_secret) = pqc_kyber::encapsulate(public_key, &mut OsRng)
+            .map_err(|e| MSSCSError::Encryption(format!("Kyber encapsulation failed: {:?}", e)))?;
         
         // Derive ephemeral key from shared secret + entropy
         // Mix both for maximum security
         let mut hasher = blake3::Hasher::new();
         hasher.update(b"KYBER-EPHEMERAL-KEY");
-        hasher.update(shared_secret.as_bytes());
+        hasher.update(&shared_secret);
         hasher.update(entropy);
         
         let derived = hasher.finalize();
         let mut ephemeral_key = [0u8; 32];
         ephemeral_key.copy_from_slice(derived.as_bytes());
         
-        Ok((ciphertext.as_bytes().to_vec(), ephemeral_key))
+        Ok((ciphertext.to_vec(), ephemeral_key))
     }
     
     fn kyber_decapsulate(
         ciphertext: &[u8],
-        secret_key: &kyber1024::SecretKey,
+        secret_key: &pqc_kyber::SecretKey,
         entropy: &[u8],
     ) -> Result<[u8; 32]> {
-        // Reconstruct ciphertext
-        let ct = kyber1024::Ciphertext::from_bytes(ciphertext)
-            .map_err(|_| MSSCSError::Encryption("Invalid Kyber ciphertext".into()))?;
-        
         // Decapsulate to get shared secret
-        let shared_secret = kyber1024::decapsulate(&ct, secret_key);
+        let shared_secret = pqc_kyber::decapsulate(ciphertext, secret_key)
+            .map_err(|_| MSSCSError::Encryption("Invalid Kyber ciphertext".into()))?;
         
         // Derive ephemeral key from shared secret + entropy (must match encapsulation)
         let mut hasher = blake3::Hasher::new();
         hasher.update(b"KYBER-EPHEMERAL-KEY");
-        hasher.update(shared_secret.as_bytes());
+        hasher.update(&shared_secret);
         hasher.update(entropy);
         
         let derived = hasher.finalize();
@@ -333,11 +329,11 @@ impl QuantumProofBlock {
         hasher.update(b"LATTICE-NOISE");
         hasher.update(seed);
         
-        for i in 0..noisy_data.len() {
+        for (i, byte) in noisy_data.iter_mut().enumerate() {
             hasher.update(&i.to_le_bytes());
             let noise_hash = hasher.finalize();
             let noise = noise_hash.as_bytes()[0] % noise_level;
-            noisy_data[i] = noisy_data[i].wrapping_add(noise);
+            *byte = byte.wrapping_add(noise);
         }
         
         noisy_data
@@ -349,11 +345,11 @@ impl QuantumProofBlock {
         hasher.update(b"LATTICE-NOISE");
         hasher.update(seed);
         
-        for i in 0..clean_data.len() {
+        for (i, byte) in clean_data.iter_mut().enumerate() {
             hasher.update(&i.to_le_bytes());
             let noise_hash = hasher.finalize();
             let noise = noise_hash.as_bytes()[0] % noise_level;
-            clean_data[i] = clean_data[i].wrapping_sub(noise);
+            *byte = byte.wrapping_sub(noise);
         }
         
         clean_data
@@ -467,7 +463,8 @@ mod tests {
         OsRng.fill_bytes(&mut master_key);
         
         // Generate Kyber keypair
-        let (pk, sk) = kyber1024::keypair();
+        let keys = pqc_kyber::keypair(&mut OsRng).unwrap();
+        let (pk, sk) = (keys.public, keys.secret);
         
         // Encrypt
         let block = QuantumProofBlock::new(data, &master_key, &pk)
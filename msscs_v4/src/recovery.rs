@@ -0,0 +1,849 @@
+// SOCIAL RECOVERY - identity secrets split among trusted contacts
+// The owner splits its secret keys with Shamir's scheme, seals one share to each contact's
+// Kyber key and signs it. A new device asks the contacts with a request signed by a fresh
+// key; each contact that approves re-seals its share to that request, and any threshold of
+// released shares rebuilds the identity under a new passphrase
+
+use crate::error::{MSSCSError, Result};
+use crate::identity::QuantumIdentity;
+use crate::key_rotation::{IdentityKeys, RotationChain, RotationStatement};
+use crate::p2p_network::P2PNodeCommand;
+use crate::private_network;
+use crate::singularity::{SingularityFragmentation, SingularityShard};
+use crate::unlocked_identity::UnlockedIdentity;
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use data_encoding::BASE32_NOPAD;
+use libp2p::Multiaddr;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+const SEAL_CONTEXT: &str = "MSSCS recovery share seal v1";
+const FINGERPRINT_CONTEXT: &str = "MSSCS recovery request fingerprint v1";
+const NONCE_SIZE: usize = 12;
+
+/// Unanswered requests a contact keeps at once; further ones are denied
+const MAX_PENDING_REQUESTS: usize = 32;
+
+/// Public keys of the identity a share set recovers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryOwner {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: u64,
+    /// Key generation the shares recover
+    #[serde(default = "first_key_generation")]
+    pub version: u32,
+    pub public_key: Vec<u8>,
+    pub pq_public_key: Vec<u8>,
+    pub dilithium_public_key: Vec<u8>,
+}
+
+fn first_key_generation() -> u32 {
+    1
+}
+
+impl RecoveryOwner {
+    pub fn from_identity(identity: &QuantumIdentity) -> Self {
+        RecoveryOwner {
+            id: identity.id,
+            name: identity.name.clone(),
+            created_at: identity.created_at,
+            version: identity.version,
+            public_key: identity.public_key.clone(),
+            pq_public_key: identity.pq_public_key.clone(),
+            dilithium_public_key: identity.dilithium_public_key.clone(),
+        }
+    }
+
+    pub fn keys(&self) -> IdentityKeys {
+        IdentityKeys {
+            version: self.version,
+            public_key: self.public_key.clone(),
+            pq_public_key: self.pq_public_key.clone(),
+            dilithium_public_key: self.dilithium_public_key.clone(),
+        }
+    }
+
+    /// Identity with these public keys and no stored secrets
+    fn identity(&self) -> QuantumIdentity {
+        QuantumIdentity {
+            id: self.id,
+            name: self.name.clone(),
+            public_key: self.public_key.clone(),
+            pq_public_key: self.pq_public_key.clone(),
+            dilithium_public_key: self.dilithium_public_key.clone(),
+            encrypted_ed25519_secret: Vec::new(),
+            encrypted_kyber_secret: Vec::new(),
+            encrypted_dilithium_secret: Vec::new(),
+            salt: Vec::new(),
+            version: self.version,
+            created_at: self.created_at,
+            tags: HashMap::new(),
+            trust_score: 0.0,
+            last_seen: None,
+            rotations: Vec::new(),
+        }
+    }
+}
+
+/// A trusted contact's public keys and the node holding its shares (contact card)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryContact {
+    pub identity_id: Uuid,
+    pub name: String,
+    pub public_key: Vec<u8>,
+    pub pq_public_key: Vec<u8>,
+    /// Address of the contact's node, ending in `/p2p/<peer id>`
+    pub address: String,
+}
+
+impl RecoveryContact {
+    pub fn new(identity: &QuantumIdentity, address: &Multiaddr) -> Result<Self> {
+        if private_network::parse_peer_id(&address.to_string()).is_none() {
+            return Err(MSSCSError::Validation(format!("{} does not end in /p2p/<peer id>", address)));
+        }
+        Ok(RecoveryContact {
+            identity_id: identity.id,
+            name: identity.name.clone(),
+            public_key: identity.public_key.clone(),
+            pq_public_key: identity.pq_public_key.clone(),
+            address: address.to_string(),
+        })
+    }
+
+    pub fn address(&self) -> Result<Multiaddr> {
+        self.address
+            .parse()
+            .map_err(|e| MSSCSError::InvalidData(format!("Invalid contact address {}: {}", self.address, e)))
+    }
+}
+
+/// Bytes sealed to one Kyber public key (KEM + AES-256-GCM)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct SealedBox {
+    kem_ciphertext: Vec<u8>,
+    nonce: [u8; NONCE_SIZE],
+    ciphertext: Vec<u8>,
+}
+
+impl SealedBox {
+    fn seal(pq_public_key: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Self> {
+        let public_key: pqc_kyber::PublicKey = pq_public_key
+            .try_into()
+            .map_err(|_| MSSCSError::Crypto("Invalid Kyber public key length".to_string()))?;
+        let (kem_ciphertext, shared_secret) = pqc_kyber::encapsulate(&public_key, &mut rand::rngs::OsRng)
+            .map_err(|e| MSSCSError::Crypto(format!("Kyber encapsulation failed: {:?}", e)))?;
+
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        let key = blake3::derive_key(SEAL_CONTEXT, &shared_secret);
+        let ciphertext = Aes256Gcm::new(&key.into())
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+            .map_err(|e| MSSCSError::Crypto(format!("Share encryption failed: {}", e)))?;
+        Ok(SealedBox { kem_ciphertext: kem_ciphertext.to_vec(), nonce, ciphertext })
+    }
+
+    fn open(&self, secret_key: &pqc_kyber::SecretKey, aad: &[u8]) -> Result<Vec<u8>> {
+        let shared_secret = pqc_kyber::decapsulate(&self.kem_ciphertext, secret_key)
+            .map_err(|_| MSSCSError::Crypto("Invalid Kyber ciphertext".to_string()))?;
+        let key = blake3::derive_key(SEAL_CONTEXT, &shared_secret);
+        Aes256Gcm::new(&key.into())
+            .decrypt(Nonce::from_slice(&self.nonce), Payload { msg: &self.ciphertext, aad })
+            .map_err(|_| MSSCSError::Crypto("Failed to open share - not sealed to this key?".to_string()))
+    }
+}
+
+/// What the shares rebuild
+#[derive(Serialize, Deserialize)]
+struct IdentitySecrets {
+    ed25519: Vec<u8>,
+    kyber: Vec<u8>,
+    dilithium: Vec<u8>,
+}
+
+/// One contact's share of an identity, sealed to the contact and signed by the owner
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedShare {
+    /// Shares of one split; a new split replaces the previous set
+    pub set_id: Uuid,
+    pub owner: RecoveryOwner,
+    pub contact_id: Uuid,
+    /// Ed25519 key the contact signs the share with when releasing it
+    pub contact_key: Vec<u8>,
+    pub index: u8,
+    pub threshold: u8,
+    pub total: u8,
+    pub created_at: u64,
+    /// Owner's rotation statements, letting a contact holding a share of an earlier key
+    /// generation accept this one
+    #[serde(default)]
+    pub rotations: Vec<RotationStatement>,
+    sealed: SealedBox,
+    signature: Vec<u8>,
+}
+
+impl SealedShare {
+    /// Check the owner's signature
+    pub fn verify(&self) -> Result<()> {
+        verify_signature(&self.owner.public_key, &self.signed_bytes()?, &self.signature)
+            .map_err(|_| MSSCSError::Validation(format!("Share {} of {} is not signed by its owner", self.index, self.owner.id)))
+    }
+
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(&(
+            self.set_id,
+            &self.owner,
+            self.contact_id,
+            &self.contact_key,
+            self.index,
+            self.threshold,
+            self.total,
+            self.created_at,
+            &self.sealed,
+        ))?)
+    }
+
+    fn aad(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(&(self.set_id, self.owner.id, self.contact_id, self.index))?)
+    }
+}
+
+/// Split `identity`'s secret keys into one share per contact, any `threshold` of which
+/// rebuild them
+pub fn split_identity(identity: &UnlockedIdentity, contacts: &[RecoveryContact], threshold: u8) -> Result<Vec<SealedShare>> {
+    let total = u8::try_from(contacts.len())
+        .map_err(|_| MSSCSError::Validation("At most 255 recovery contacts are supported".to_string()))?;
+    let ids: HashSet<Uuid> = contacts.iter().map(|contact| contact.identity_id).collect();
+    if ids.len() != contacts.len() {
+        return Err(MSSCSError::Validation("Recovery contacts must be distinct".to_string()));
+    }
+    if ids.contains(identity.user_id()) {
+        return Err(MSSCSError::Validation("An identity cannot be its own recovery contact".to_string()));
+    }
+
+    let secrets = bincode::serialize(&IdentitySecrets {
+        ed25519: identity.ed25519_secret.clone(),
+        kyber: identity.kyber_secret.clone(),
+        dilithium: identity.dilithium_secret.clone(),
+    })?;
+    let shards = SingularityFragmentation::new(threshold, total)?.fragment(&secrets)?;
+
+    let set_id = Uuid::new_v4();
+    let owner = RecoveryOwner::from_identity(&identity.identity);
+    let created_at = unix_now();
+    contacts
+        .iter()
+        .zip(shards)
+        .map(|(contact, shard)| {
+            let mut share = SealedShare {
+                set_id,
+                owner: owner.clone(),
+                contact_id: contact.identity_id,
+                contact_key: contact.public_key.clone(),
+                index: shard.index,
+                threshold,
+                total,
+                created_at,
+                rotations: identity.identity.rotations.clone(),
+                sealed: SealedBox::default(),
+                signature: Vec::new(),
+            };
+            share.sealed = SealedBox::seal(&contact.pq_public_key, &bincode::serialize(&shard)?, &share.aad()?)?;
+            share.signature = identity.sign(&share.signed_bytes()?)?;
+            Ok(share)
+        })
+        .collect()
+}
+
+/// Request for the shares of an identity, signed by a key created for this request only
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryRequest {
+    pub request_id: Uuid,
+    /// Identity to recover
+    pub identity_id: Uuid,
+    /// Shown to the contacts: who is asking and why
+    pub message: String,
+    pub created_at: u64,
+    /// Ed25519 key the request is signed with
+    pub public_key: Vec<u8>,
+    /// Kyber key approved shares are sealed to
+    pub pq_public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl RecoveryRequest {
+    /// Check the requester's signature
+    pub fn verify(&self) -> Result<()> {
+        verify_signature(&self.public_key, &self.signed_bytes()?, &self.signature)
+    }
+
+    /// Short code over the request keys; the requester reads it out to each contact, who
+    /// compares it with the code shown for the request before approving
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = blake3::Hasher::new_derive_key(FINGERPRINT_CONTEXT);
+        hasher.update(&self.public_key);
+        hasher.update(&self.pq_public_key);
+        let code = BASE32_NOPAD.encode(&hasher.finalize().as_bytes()[..5]);
+        format!("{}-{}", &code[..4], &code[4..])
+    }
+
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(&(
+            self.request_id,
+            self.identity_id,
+            &self.message,
+            self.created_at,
+            &self.public_key,
+            &self.pq_public_key,
+        ))?)
+    }
+}
+
+/// A contact's share re-sealed to a recovery request and signed by the contact
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReleasedShare {
+    pub request_id: Uuid,
+    /// The share as the owner handed it to the contact
+    pub share: SealedShare,
+    sealed: SealedBox,
+    signature: Vec<u8>,
+}
+
+impl ReleasedShare {
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(&(self.request_id, &self.share.signature, &self.sealed))?)
+    }
+
+    fn aad(request_id: Uuid, share: &SealedShare) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(&(request_id, share.set_id, share.owner.id, share.index))?)
+    }
+}
+
+/// A contact's answer to a recovery request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecoveryStatus {
+    /// Waiting for the contact to approve
+    Pending,
+    Approved(Box<ReleasedShare>),
+    Denied,
+    /// The contact holds no share of the identity
+    NoShare,
+}
+
+/// A recovery request as received by a contact
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedRequest {
+    pub request: RecoveryRequest,
+    pub status: RecoveryStatus,
+    pub received_at: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct VaultState {
+    /// Latest share per owner identity
+    shares: HashMap<Uuid, SealedShare>,
+    requests: HashMap<Uuid, ReceivedRequest>,
+}
+
+/// Contact side: the shares this node holds for others and the requests for them
+/// Shares are released only through `approve`, by the contact identity they are sealed to
+#[derive(Clone)]
+pub struct RecoveryVault {
+    contact_id: Uuid,
+    path: Option<PathBuf>,
+    state: Arc<Mutex<VaultState>>,
+}
+
+impl std::fmt::Debug for RecoveryVault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecoveryVault")
+            .field("contact_id", &self.contact_id)
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RecoveryVault {
+    /// In-memory vault for the contact identity `contact_id`
+    pub fn new(contact_id: Uuid) -> Self {
+        RecoveryVault { contact_id, path: None, state: Arc::new(Mutex::new(VaultState::default())) }
+    }
+
+    /// Vault kept in the JSON file at `path`
+    pub fn open(path: PathBuf, contact_id: Uuid) -> Result<Self> {
+        let state = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)
+                .map_err(|e| MSSCSError::InvalidData(format!("Failed to parse recovery vault {:?}: {}", path, e)))?
+        } else {
+            VaultState::default()
+        };
+        Ok(RecoveryVault { contact_id, path: Some(path), state: Arc::new(Mutex::new(state)) })
+    }
+
+    pub fn contact_id(&self) -> Uuid {
+        self.contact_id
+    }
+
+    /// Keep a share addressed to this contact; an older set of the same owner is replaced
+    pub fn store_share(&self, share: SealedShare) -> Result<()> {
+        if share.contact_id != self.contact_id {
+            return Err(MSSCSError::Validation(format!("Share is addressed to {}", share.contact_id)));
+        }
+        share.verify()?;
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(existing) = state.shares.get(&share.owner.id) {
+            if existing.owner.keys() != share.owner.keys() {
+                // The owner may have rotated since; accept the new keys only if its rotation
+                // chain leads there from the ones held
+                let chain = RotationChain { identity_id: share.owner.id, statements: share.rotations.clone() };
+                if chain.follow(&existing.owner.keys()).ok() != Some(share.owner.keys()) {
+                    return Err(MSSCSError::Validation(format!(
+                        "Share claims identity {} with different keys",
+                        share.owner.id
+                    )));
+                }
+            } else if existing.created_at > share.created_at {
+                return Ok(());
+            }
+        }
+        info!("🤝 Holding recovery share {}/{} for {} ({})", share.index, share.total, share.owner.name, share.owner.id);
+        state.shares.insert(share.owner.id, share);
+        self.save(&state)
+    }
+
+    /// Owners this contact holds a share for
+    pub fn owners(&self) -> Vec<RecoveryOwner> {
+        self.state.lock().unwrap().shares.values().map(|share| share.owner.clone()).collect()
+    }
+
+    /// Record an incoming request and answer with its current status
+    pub fn handle_request(&self, request: RecoveryRequest) -> RecoveryStatus {
+        if let Err(e) = request.verify() {
+            warn!("❌ Rejected recovery request {}: {}", request.request_id, e);
+            return RecoveryStatus::Denied;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(received) = state.requests.get(&request.request_id) {
+            return if received.request == request { received.status.clone() } else { RecoveryStatus::Denied };
+        }
+        if !state.shares.contains_key(&request.identity_id) {
+            return RecoveryStatus::NoShare;
+        }
+        let pending = state.requests.values().filter(|r| r.status == RecoveryStatus::Pending).count();
+        if pending >= MAX_PENDING_REQUESTS {
+            warn!("⚠️  Too many pending recovery requests, denying {}", request.request_id);
+            return RecoveryStatus::Denied;
+        }
+
+        info!(
+            "🆘 Recovery request {} for identity {} (code {}): {}",
+            request.request_id, request.identity_id, request.fingerprint(), request.message
+        );
+        state.requests.insert(
+            request.request_id,
+            ReceivedRequest { request, status: RecoveryStatus::Pending, received_at: unix_now() },
+        );
+        if let Err(e) = self.save(&state) {
+            warn!("Failed to save recovery vault: {}", e);
+        }
+        RecoveryStatus::Pending
+    }
+
+    /// Requests received so far, oldest first
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        let mut requests: Vec<ReceivedRequest> = self.state.lock().unwrap().requests.values().cloned().collect();
+        requests.sort_by_key(|r| r.received_at);
+        requests
+    }
+
+    /// Release this contact's share to a pending request
+    pub fn approve(&self, request_id: &Uuid, identity: &UnlockedIdentity) -> Result<()> {
+        if *identity.user_id() != self.contact_id {
+            return Err(MSSCSError::Validation(format!("Shares are held for {}", self.contact_id)));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let received = state.requests.get(request_id)
+            .ok_or_else(|| MSSCSError::NotFound(format!("Recovery request {}", request_id)))?;
+        if received.status != RecoveryStatus::Pending {
+            return Err(MSSCSError::Validation(format!("Recovery request {} was already answered", request_id)));
+        }
+        let share = state.shares.get(&received.request.identity_id)
+            .ok_or_else(|| MSSCSError::NotFound(format!("Share of {}", received.request.identity_id)))?;
+
+        let plaintext = share.sealed.open(&identity.kyber_secret_key()?, &share.aad()?)?;
+        let mut released = ReleasedShare {
+            request_id: *request_id,
+            share: share.clone(),
+            sealed: SealedBox::seal(
+                &received.request.pq_public_key,
+                &plaintext,
+                &ReleasedShare::aad(*request_id, share)?,
+            )?,
+            signature: Vec::new(),
+        };
+        released.signature = identity.sign(&released.signed_bytes()?)?;
+
+        info!("✅ Released recovery share of {} to request {}", share.owner.id, request_id);
+        if let Some(received) = state.requests.get_mut(request_id) {
+            received.status = RecoveryStatus::Approved(Box::new(released));
+        }
+        self.save(&state)
+    }
+
+    /// Refuse a pending request
+    pub fn deny(&self, request_id: &Uuid) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let received = state.requests.get_mut(request_id)
+            .ok_or_else(|| MSSCSError::NotFound(format!("Recovery request {}", request_id)))?;
+        if received.status != RecoveryStatus::Pending {
+            return Err(MSSCSError::Validation(format!("Recovery request {} was already answered", request_id)));
+        }
+        received.status = RecoveryStatus::Denied;
+        self.save(&state)
+    }
+
+    fn save(&self, state: &VaultState) -> Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let data = serde_json::to_vec(state)
+            .map_err(|e| MSSCSError::InvalidData(format!("Failed to serialize recovery vault: {}", e)))?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+/// Requester side: the request keys and the shares released so far
+pub struct RecoverySession {
+    request: RecoveryRequest,
+    kyber_secret: pqc_kyber::SecretKey,
+    /// Opened shares by share set, then by index
+    sets: HashMap<Uuid, (RecoveryOwner, BTreeMap<u8, SingularityShard>)>,
+}
+
+impl RecoverySession {
+    /// Create request keys and a signed request for `identity_id`
+    pub fn new(identity_id: Uuid, message: &str) -> Result<Self> {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let signing_key = SigningKey::generate(&mut rand::rngs::OsRng);
+        let kyber = pqc_kyber::keypair(&mut rand::rngs::OsRng)
+            .map_err(|e| MSSCSError::Crypto(format!("Kyber key generation failed: {:?}", e)))?;
+        let mut request = RecoveryRequest {
+            request_id: Uuid::new_v4(),
+            identity_id,
+            message: message.to_string(),
+            created_at: unix_now(),
+            public_key: signing_key.verifying_key().to_bytes().to_vec(),
+            pq_public_key: kyber.public.to_vec(),
+            signature: Vec::new(),
+        };
+        request.signature = signing_key.sign(&request.signed_bytes()?).to_bytes().to_vec();
+        Ok(RecoverySession { request, kyber_secret: kyber.secret, sets: HashMap::new() })
+    }
+
+    pub fn request(&self) -> &RecoveryRequest {
+        &self.request
+    }
+
+    /// Verify and open a released share; returns whether enough shares are now held
+    pub fn add_share(&mut self, released: &ReleasedShare) -> Result<bool> {
+        let share = &released.share;
+        if released.request_id != self.request.request_id {
+            return Err(MSSCSError::Validation(format!("Share was released to request {}", released.request_id)));
+        }
+        if share.owner.id != self.request.identity_id {
+            return Err(MSSCSError::Validation(format!("Share belongs to identity {}", share.owner.id)));
+        }
+        share.verify()?;
+        verify_signature(&share.contact_key, &released.signed_bytes()?, &released.signature)
+            .map_err(|_| MSSCSError::Validation(format!("Share {} is not signed by its contact", share.index)))?;
+
+        let plaintext = released.sealed.open(&self.kyber_secret, &ReleasedShare::aad(released.request_id, share)?)?;
+        let shard: SingularityShard = bincode::deserialize(&plaintext)?;
+        if shard.index != share.index || shard.threshold != share.threshold || shard.total_shards != share.total {
+            return Err(MSSCSError::InvalidData(format!("Share {} does not match its header", share.index)));
+        }
+
+        let (owner, shards) = self.sets
+            .entry(share.set_id)
+            .or_insert_with(|| (share.owner.clone(), BTreeMap::new()));
+        if *owner != share.owner {
+            return Err(MSSCSError::Validation(format!("Share set {} names different owner keys", share.set_id)));
+        }
+        shards.insert(shard.index, shard);
+        Ok(self.is_complete())
+    }
+
+    /// Whether some share set has reached its threshold
+    pub fn is_complete(&self) -> bool {
+        self.complete_set().is_some()
+    }
+
+    /// Rebuild the identity and store its secrets under `passphrase`
+    pub fn recover(&self, passphrase: &str) -> Result<QuantumIdentity> {
+        let (owner, shards) = self.complete_set()
+            .ok_or_else(|| MSSCSError::Validation("Not enough shares released yet".to_string()))?;
+        let shards: Vec<SingularityShard> = shards.values().cloned().collect();
+        let secrets = SingularityFragmentation::new(shards[0].threshold, shards[0].total_shards)?
+            .reconstruct(&shards)?;
+        let secrets: IdentitySecrets = bincode::deserialize(&secrets)
+            .map_err(|_| MSSCSError::Crypto("Shares did not rebuild the identity secrets".to_string()))?;
+
+        let unlocked = UnlockedIdentity::from_secrets(owner.identity(), secrets.ed25519, secrets.kyber, secrets.dilithium);
+        check_keys(&unlocked, self.request.request_id.as_bytes())?;
+        info!("✅ Recovered identity {} ({}) from {} shares", owner.name, owner.id, shards.len());
+        unlocked.seal(passphrase)
+    }
+
+    fn complete_set(&self) -> Option<&(RecoveryOwner, BTreeMap<u8, SingularityShard>)> {
+        self.sets.values().find(|(_, shards)| {
+            shards.values().next().is_some_and(|shard| shards.len() >= shard.threshold as usize)
+        })
+    }
+}
+
+/// Dial the contact at `address` and hand it `share`
+pub async fn send_share(
+    commands: &mpsc::UnboundedSender<P2PNodeCommand>,
+    address: &Multiaddr,
+    share: SealedShare,
+) -> Result<()> {
+    let peer = dial(commands, address).await?;
+    let (reply, response) = oneshot::channel();
+    send_command(commands, P2PNodeCommand::SendRecoveryShare { peer, share: Box::new(share), reply })?;
+    response.await
+        .map_err(|_| MSSCSError::Network("P2P node dropped the request".to_string()))?
+        .map_err(MSSCSError::Network)
+}
+
+/// Dial the contact at `address` and ask it for its share of the identity `request` names
+pub async fn ask_contact(
+    commands: &mpsc::UnboundedSender<P2PNodeCommand>,
+    address: &Multiaddr,
+    request: &RecoveryRequest,
+) -> Result<RecoveryStatus> {
+    let peer = dial(commands, address).await?;
+    let (reply, response) = oneshot::channel();
+    send_command(commands, P2PNodeCommand::RequestRecovery { peer, request: request.clone(), reply })?;
+    response.await
+        .map_err(|_| MSSCSError::Network("P2P node dropped the request".to_string()))?
+        .map_err(MSSCSError::Network)
+}
+
+async fn dial(commands: &mpsc::UnboundedSender<P2PNodeCommand>, address: &Multiaddr) -> Result<libp2p::PeerId> {
+    let peer = private_network::parse_peer_id(&address.to_string())
+        .ok_or_else(|| MSSCSError::Validation(format!("{} does not end in /p2p/<peer id>", address)))?;
+    let (reply, response) = oneshot::channel();
+    send_command(commands, P2PNodeCommand::AddPeer { addr: address.clone(), reply })?;
+    if let Ok(Err(e)) = response.await {
+        // Usually already connected; the request dials again if not
+        warn!("Dialing {} failed: {}", address, e);
+    }
+    Ok(peer)
+}
+
+fn send_command(commands: &mpsc::UnboundedSender<P2PNodeCommand>, command: P2PNodeCommand) -> Result<()> {
+    commands.send(command)
+        .map_err(|e| MSSCSError::Network(format!("P2P command channel error: {}", e)))
+}
+
+/// Check that rebuilt secrets belong to the identity's public keys
+fn check_keys(unlocked: &UnlockedIdentity, probe: &[u8]) -> Result<()> {
+    let identity = &unlocked.identity;
+    let (kem_ciphertext, shared_secret) = pqc_kyber::encapsulate(&unlocked.kyber_public_key()?, &mut rand::rngs::OsRng)
+        .map_err(|e| MSSCSError::Crypto(format!("Kyber encapsulation failed: {:?}", e)))?;
+    let kyber_matches = pqc_kyber::decapsulate(&kem_ciphertext, &unlocked.kyber_secret_key()?)
+        .is_ok_and(|secret| secret == shared_secret);
+
+    if !kyber_matches
+        || !identity.verify(probe, &unlocked.sign(probe)?)?
+        || !identity.verify_dilithium(probe, &unlocked.sign_dilithium(probe)?)?
+    {
+        return Err(MSSCSError::Crypto(format!("Recovered secrets do not match identity {}", identity.id)));
+    }
+    Ok(())
+}
+
+fn verify_signature(public_key: &[u8], data: &[u8], signature: &[u8]) -> Result<()> {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let public_key: [u8; 32] = public_key
+        .try_into()
+        .map_err(|_| MSSCSError::InvalidData("Invalid Ed25519 public key length".to_string()))?;
+    let signature: [u8; 64] = signature
+        .try_into()
+        .map_err(|_| MSSCSError::InvalidData("Invalid signature length".to_string()))?;
+    VerifyingKey::from_bytes(&public_key)
+        .map_err(|_| MSSCSError::InvalidData("Invalid Ed25519 public key".to_string()))?
+        .verify(data, &Signature::from_bytes(&signature))
+        .map_err(|_| MSSCSError::Validation("Signature verification failed".to_string()))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unlocked(name: &str) -> UnlockedIdentity {
+        QuantumIdentity::new(name.to_string(), "passphrase")
+            .unwrap()
+            .unlock("passphrase")
+            .unwrap()
+    }
+
+    fn contact(identity: &UnlockedIdentity) -> RecoveryContact {
+        let address: Multiaddr = format!("/memory/1/p2p/{}", libp2p::PeerId::random()).parse().unwrap();
+        RecoveryContact::new(&identity.identity, &address).unwrap()
+    }
+
+    /// Owner, its contacts with their vaults, and a 2-of-3 split handed out
+    fn setup() -> (UnlockedIdentity, Vec<(UnlockedIdentity, RecoveryVault)>) {
+        let owner = unlocked("owner");
+        let contacts: Vec<UnlockedIdentity> = ["alice", "bob", "carol"].into_iter().map(unlocked).collect();
+        let cards: Vec<RecoveryContact> = contacts.iter().map(contact).collect();
+        let shares = split_identity(&owner, &cards, 2).unwrap();
+
+        let contacts = contacts
+            .into_iter()
+            .zip(shares)
+            .map(|(identity, share)| {
+                let vault = RecoveryVault::new(*identity.user_id());
+                vault.store_share(share).unwrap();
+                (identity, vault)
+            })
+            .collect();
+        (owner, contacts)
+    }
+
+    fn approved(status: RecoveryStatus) -> ReleasedShare {
+        match status {
+            RecoveryStatus::Approved(released) => *released,
+            other => panic!("expected an approved share, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_recover_with_threshold_of_contacts() {
+        let (owner, contacts) = setup();
+        let mut session = RecoverySession::new(*owner.user_id(), "lost my laptop").unwrap();
+        let request = session.request().clone();
+
+        for (_, vault) in &contacts {
+            assert_eq!(vault.handle_request(request.clone()), RecoveryStatus::Pending);
+        }
+        assert!(session.recover("new passphrase").is_err());
+
+        contacts[0].1.approve(&request.request_id, &contacts[0].0).unwrap();
+        assert!(!session.add_share(&approved(contacts[0].1.handle_request(request.clone()))).unwrap());
+        contacts[2].1.deny(&request.request_id).unwrap();
+        assert_eq!(contacts[2].1.handle_request(request.clone()), RecoveryStatus::Denied);
+        contacts[1].1.approve(&request.request_id, &contacts[1].0).unwrap();
+        assert!(session.add_share(&approved(contacts[1].1.handle_request(request.clone()))).unwrap());
+
+        let recovered = session.recover("new passphrase").unwrap();
+        assert_eq!(recovered.id, *owner.user_id());
+        assert_eq!(recovered.public_key, owner.identity.public_key);
+        assert_eq!(recovered.unlock("new passphrase").unwrap().master_key(), owner.master_key());
+    }
+
+    #[test]
+    fn test_vault_rejects_foreign_and_forged_input() {
+        let (owner, contacts) = setup();
+        let (alice, alice_vault) = &contacts[0];
+        let (bob, bob_vault) = &contacts[1];
+
+        // Bob's share is not Alice's to hold, and only Alice can release hers
+        let bob_share = split_identity(&owner, &[contact(bob), contact(alice)], 2).unwrap().remove(0);
+        assert!(alice_vault.store_share(bob_share.clone()).is_err());
+        let mut forged = bob_share;
+        forged.index = 2;
+        assert!(bob_vault.store_share(forged).is_err());
+
+        let session = RecoverySession::new(*owner.user_id(), "").unwrap();
+        let request = session.request().clone();
+        assert_eq!(alice_vault.handle_request(request.clone()), RecoveryStatus::Pending);
+        assert!(alice_vault.approve(&request.request_id, bob).is_err());
+
+        let mut tampered = RecoverySession::new(*owner.user_id(), "").unwrap().request().clone();
+        tampered.message = "approve me".to_string();
+        assert_eq!(alice_vault.handle_request(tampered), RecoveryStatus::Denied);
+
+        let unknown = RecoverySession::new(Uuid::new_v4(), "").unwrap();
+        assert_eq!(alice_vault.handle_request(unknown.request().clone()), RecoveryStatus::NoShare);
+    }
+
+    #[test]
+    fn test_vault_follows_owner_rotation() {
+        let (owner, contacts) = setup();
+        let (alice, vault) = &contacts[0];
+        let cards = [contact(alice), contact(&contacts[1].0)];
+        let alice_share = |identity: &UnlockedIdentity| split_identity(identity, &cards, 2).unwrap().remove(0);
+
+        // A share of the rotated keys replaces the held one; one of unrelated keys does not
+        let rotated = owner.rotate("passphrase").unwrap();
+        vault.store_share(alice_share(&rotated)).unwrap();
+        assert_eq!(vault.owners()[0].keys(), IdentityKeys::of(&rotated.identity));
+
+        let mut impostor = unlocked("owner").rotate("passphrase").unwrap();
+        impostor.identity.id = *owner.user_id();
+        assert!(vault.store_share(alice_share(&impostor)).is_err());
+
+        // Nor can an old share roll the vault back to the retired keys
+        assert!(vault.store_share(alice_share(&owner)).is_err());
+    }
+
+    #[test]
+    fn test_session_rejects_shares_for_other_requests() {
+        let (owner, contacts) = setup();
+        let (alice, vault) = &contacts[0];
+        let first = RecoverySession::new(*owner.user_id(), "").unwrap();
+        let mut second = RecoverySession::new(*owner.user_id(), "").unwrap();
+
+        vault.handle_request(first.request().clone());
+        vault.approve(&first.request().request_id, alice).unwrap();
+        let released = approved(vault.handle_request(first.request().clone()));
+        assert!(second.add_share(&released).is_err());
+
+        let mut redirected = released;
+        redirected.request_id = second.request().request_id;
+        assert!(second.add_share(&redirected).is_err());
+        assert_ne!(first.request().fingerprint(), second.request().fingerprint());
+    }
+
+    #[test]
+    fn test_vault_persists() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("recovery.json");
+        let owner = unlocked("owner");
+        let (alice, bob) = (unlocked("alice"), unlocked("bob"));
+        let shares = split_identity(&owner, &[contact(&alice), contact(&bob)], 2).unwrap();
+
+        let vault = RecoveryVault::open(path.clone(), *alice.user_id()).unwrap();
+        vault.store_share(shares[0].clone()).unwrap();
+        let session = RecoverySession::new(*owner.user_id(), "").unwrap();
+        vault.handle_request(session.request().clone());
+
+        let reopened = RecoveryVault::open(path, *alice.user_id()).unwrap();
+        assert_eq!(reopened.owners(), vec![RecoveryOwner::from_identity(&owner.identity)]);
+        assert_eq!(reopened.requests().len(), 1);
+        reopened.approve(&session.request().request_id, &alice).unwrap();
+    }
+}
@@ -2,6 +2,7 @@
 // Enables P2P connections through firewalls and NAT
 
 use crate::error::{MSSCSError, Result};
+use crate::stun::StunClient;
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -62,6 +63,17 @@ pub enum ConnectionMethod {
     Failed,
 }
 
+/// Best way to reach a peer given both NAT types; hole punching cannot get through a
+/// symmetric NAT unless the other side's NAT accepts packets from any port it has contacted
+pub fn connection_method(local: NATType, remote: NATType) -> ConnectionMethod {
+    match (local, remote) {
+        (NATType::None, _) | (_, NATType::None) => ConnectionMethod::Direct,
+        (NATType::Symmetric, NATType::Symmetric | NATType::PortRestrictedCone)
+        | (NATType::PortRestrictedCone, NATType::Symmetric) => ConnectionMethod::Relayed,
+        _ => ConnectionMethod::HolePunched, // Unknown: try anyway
+    }
+}
+
 /// Relay connection
 #[derive(Debug, Clone)]
 pub struct RelayConnection {
@@ -87,6 +99,10 @@ pub struct RelayManager {
     active_connections: HashMap<String, RelayConnection>,
     /// NAT type cache
     nat_types: HashMap<PeerId, NATType>,
+    /// NAT type of this node, from the last detection
+    local_nat: NATType,
+    /// Public address found by the last detection
+    public_addr: Option<SocketAddr>,
 }
 
 impl RelayManager {
@@ -97,6 +113,8 @@ impl RelayManager {
             relay_nodes: HashSet::new(),
             active_connections: HashMap::new(),
             nat_types: HashMap::new(),
+            local_nat: NATType::Unknown,
+            public_addr: None,
         }
     }
     
@@ -117,28 +135,74 @@ impl RelayManager {
         self.relay_nodes.iter().copied().collect()
     }
     
-    /// Detect NAT type
+    /// Detect the NAT type of a UDP socket bound to `local_addr` with RFC 5780 tests
+    /// against `stun_servers` ("host:port"); without servers only a public local IP is recognized
     pub async fn detect_nat_type(&mut self, local_addr: SocketAddr, stun_servers: &[String]) -> Result<NATType> {
         tracing::info!("🔍 Detecting NAT type...");
-        
-        // Simplified NAT detection
-        // In production, use STUN protocol (RFC 5389)
-        
-        // Check if we have a public IP
-        if self.is_public_ip(local_addr.ip()) {
-            tracing::info!("   ✅ No NAT detected (public IP)");
-            return Ok(NATType::None);
+
+        let mut servers = Vec::new();
+        for server in stun_servers {
+            match tokio::net::lookup_host(server.as_str()).await {
+                Ok(addrs) => servers.extend(addrs.filter(|addr| addr.is_ipv4() == local_addr.is_ipv4()).take(1)),
+                Err(e) => tracing::debug!("   Cannot resolve STUN server {}: {}", server, e),
+            }
         }
-        
-        // Perform STUN binding requests
-        // This would involve:
-        // 1. Send binding request to STUN server
-        // 2. Receive mapped address
-        // 3. Compare with local address
-        // 4. Perform additional tests to determine NAT type
-        
-        tracing::info!("   ⚠️  NAT detected (type unknown)");
-        Ok(NATType::Unknown)
+
+        if servers.is_empty() {
+            self.local_nat = if self.is_public_ip(local_addr.ip()) {
+                tracing::info!("   ✅ No NAT detected (public IP)");
+                NATType::None
+            } else {
+                tracing::info!("   ⚠️  NAT detected (type unknown, no STUN server)");
+                NATType::Unknown
+            };
+            return Ok(self.local_nat);
+        }
+
+        let client = StunClient::bind(local_addr).await?;
+        let mut last_error = None;
+        for (i, server) in servers.iter().enumerate() {
+            // A second server lets plain RFC 5389 servers still reveal a symmetric NAT
+            let result = match servers.get(i + 1) {
+                Some(secondary) => client.classify_with_pair(*server, *secondary).await,
+                None => client.classify(*server).await,
+            };
+            match result {
+                Ok(report) => {
+                    tracing::info!("   ✅ {:?} (public address {}, mapping {:?}, filtering {:?})",
+                        report.nat_type, report.public_addr, report.mapping, report.filtering);
+                    self.local_nat = report.nat_type;
+                    self.public_addr = Some(report.public_addr);
+                    return Ok(report.nat_type);
+                }
+                Err(e) => {
+                    tracing::debug!("   STUN server {} failed: {}", server, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| MSSCSError::Network("No STUN server answered".to_string())))
+    }
+
+    /// NAT type of this node from the last detection
+    pub fn local_nat_type(&self) -> NATType {
+        self.local_nat
+    }
+
+    /// Public address from the last detection
+    pub fn public_addr(&self) -> Option<SocketAddr> {
+        self.public_addr
+    }
+
+    /// Remember the NAT type a peer reported
+    pub fn set_peer_nat_type(&mut self, peer_id: PeerId, nat: NATType) {
+        self.nat_types.insert(peer_id, nat);
+    }
+
+    /// How to reach `remote` from this node
+    pub fn select_connection_method(&self, remote: &PeerId) -> ConnectionMethod {
+        let remote_nat = self.nat_types.get(remote).copied().unwrap_or(NATType::Unknown);
+        connection_method(self.local_nat, remote_nat)
     }
     
     /// Check if IP is public
@@ -166,7 +230,7 @@ impl RelayManager {
         remote_peer: PeerId,
         relay_peer: PeerId,
     ) -> Result<ConnectionMethod> {
-        tracing::info!("🔨 Attempting hole punching: {} -> {}", local_peer, remote_peer);
+        tracing::info!("🔨 Attempting hole punching: {} -> {} via {}", local_peer, remote_peer, relay_peer);
         
         if !self.config.enable_hole_punching {
             tracing::debug!("   Hole punching disabled");
@@ -174,24 +238,19 @@ impl RelayManager {
         }
         
         // Get NAT types
-        let local_nat = self.nat_types.get(&local_peer).copied().unwrap_or(NATType::Unknown);
+        let local_nat = self.nat_types.get(&local_peer).copied().unwrap_or(self.local_nat);
         let remote_nat = self.nat_types.get(&remote_peer).copied().unwrap_or(NATType::Unknown);
         
         tracing::debug!("   Local NAT: {:?}, Remote NAT: {:?}", local_nat, remote_nat);
         
         // Check if hole punching is likely to succeed
-        let can_punch = match (local_nat, remote_nat) {
-            (NATType::None, _) | (_, NATType::None) => true,
-            (NATType::FullCone, _) | (_, NATType::FullCone) => true,
-            (NATType::RestrictedCone, NATType::RestrictedCone) => true,
-            (NATType::PortRestrictedCone, NATType::PortRestrictedCone) => true,
-            (NATType::Symmetric, NATType::Symmetric) => false, // Very difficult
-            _ => true, // Try anyway
-        };
-        
-        if !can_punch {
-            tracing::warn!("   ⚠️  Hole punching unlikely to succeed (symmetric NAT)");
-            return Ok(ConnectionMethod::Relayed);
+        match connection_method(local_nat, remote_nat) {
+            ConnectionMethod::Relayed => {
+                tracing::warn!("   ⚠️  Hole punching unlikely to succeed (symmetric NAT)");
+                return Ok(ConnectionMethod::Relayed);
+            }
+            ConnectionMethod::Direct => return Ok(ConnectionMethod::Direct),
+            _ => {}
         }
         
         // Perform hole punching
@@ -379,18 +438,27 @@ mod tests {
     
     #[test]
     fn test_nat_type_detection() {
-        let _manager = RelayManager::new(RelayConfig::default());
+        let mut manager = RelayManager::new(RelayConfig::default());
         
         // Test NAT type combinations for hole punching
         let test_cases = vec![
-            (NATType::None, NATType::FullCone, true),
-            (NATType::FullCone, NATType::RestrictedCone, true),
-            (NATType::Symmetric, NATType::Symmetric, false),
+            (NATType::None, NATType::Symmetric, ConnectionMethod::Direct),
+            (NATType::FullCone, NATType::RestrictedCone, ConnectionMethod::HolePunched),
+            (NATType::Symmetric, NATType::RestrictedCone, ConnectionMethod::HolePunched),
+            (NATType::PortRestrictedCone, NATType::Symmetric, ConnectionMethod::Relayed),
+            (NATType::Symmetric, NATType::Symmetric, ConnectionMethod::Relayed),
+            (NATType::Unknown, NATType::Unknown, ConnectionMethod::HolePunched),
         ];
         
-        for (local, remote, _expected) in test_cases {
-            // Test logic would go here
-            assert!(local != NATType::Unknown || remote != NATType::Unknown);
+        for (local, remote, expected) in test_cases {
+            assert_eq!(connection_method(local, remote), expected);
         }
+        
+        // The local type comes from detection, the remote one from what the peer reported
+        let peer = PeerId::random();
+        manager.local_nat = NATType::Symmetric;
+        manager.set_peer_nat_type(peer, NATType::Symmetric);
+        assert_eq!(manager.select_connection_method(&peer), ConnectionMethod::Relayed);
+        assert_eq!(manager.select_connection_method(&PeerId::random()), ConnectionMethod::HolePunched);
     }
 }
@@ -0,0 +1,237 @@
+// REPAIR DAEMON - keeps every uploaded block at full shard count
+// Periodically audits the recorded shard holders, rebuilds shards lost to departed
+// or wiped peers and pushes them to healthy peers, throttled to a byte budget
+
+use crate::error::Result;
+use crate::p2p_vfs::P2PVirtualFileSystem;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, Notify, RwLock};
+
+/// Repair scheduling and throttling
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepairConfig {
+    /// Run the background loop
+    pub enabled: bool,
+    /// Seconds between audit passes
+    pub interval_secs: u64,
+    /// Upper bound on repair traffic in bytes per second (0 = unlimited)
+    pub max_bytes_per_sec: u64,
+    /// Blocks audited per pass (0 = all)
+    pub max_blocks_per_pass: usize,
+}
+
+impl Default for RepairConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 600,
+            max_bytes_per_sec: 1024 * 1024,
+            max_blocks_per_pass: 0,
+        }
+    }
+}
+
+/// Outcome of one audit/repair pass
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepairReport {
+    pub blocks_audited: usize,
+    pub blocks_healthy: usize,
+    /// Blocks missing at least one shard
+    pub blocks_under_replicated: usize,
+    pub shards_repaired: usize,
+    pub bytes_repaired: u64,
+    /// Blocks with fewer than K live shards and no local copy
+    pub unrecoverable: Vec<String>,
+    pub duration_ms: u64,
+}
+
+/// Running totals exposed through the API
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepairMetrics {
+    pub passes: u64,
+    pub running: bool,
+    /// Blocks audited so far in the running pass
+    pub pass_progress: usize,
+    /// Blocks to audit in the running pass
+    pub pass_total: usize,
+    pub blocks_audited: u64,
+    pub shards_repaired: u64,
+    pub bytes_repaired: u64,
+    pub repair_failures: u64,
+    /// Unix time the last pass finished
+    pub last_pass_at: Option<u64>,
+    pub last_report: Option<RepairReport>,
+}
+
+/// Background re-replication of erasure-coded shards
+pub struct RepairDaemon {
+    vfs: Arc<P2PVirtualFileSystem>,
+    config: RepairConfig,
+    metrics: RwLock<RepairMetrics>,
+    /// Serializes passes (background and manual)
+    pass_lock: Mutex<()>,
+    /// Wakes the background loop early
+    trigger: Notify,
+    /// The background loop is running
+    looping: AtomicBool,
+    /// Next block to audit when passes are capped
+    cursor: Mutex<usize>,
+}
+
+impl RepairDaemon {
+    /// Create a daemon for `vfs`
+    pub fn new(vfs: Arc<P2PVirtualFileSystem>, config: RepairConfig) -> Self {
+        Self {
+            vfs,
+            config,
+            metrics: RwLock::new(RepairMetrics::default()),
+            pass_lock: Mutex::new(()),
+            trigger: Notify::new(),
+            looping: AtomicBool::new(false),
+            cursor: Mutex::new(0),
+        }
+    }
+
+    /// Start the periodic loop (no-op when disabled)
+    pub fn spawn(self: &Arc<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        if !self.config.enabled {
+            tracing::info!("🔧 Repair daemon disabled");
+            return None;
+        }
+        tracing::info!("🔧 Repair daemon: every {}s, {} bytes/s budget",
+            self.config.interval_secs, self.config.max_bytes_per_sec);
+
+        self.looping.store(true, Ordering::Relaxed);
+        let daemon = self.clone();
+        Some(tokio::spawn(async move {
+            let period = Duration::from_secs(daemon.config.interval_secs.max(1));
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(period) => {}
+                    _ = daemon.trigger.notified() => {}
+                }
+                if let Err(e) = daemon.run_pass().await {
+                    tracing::warn!("⚠️  Repair pass failed: {}", e);
+                }
+            }
+        }))
+    }
+
+    /// Run a pass now without waiting for it: wakes the background loop, or starts a single
+    /// pass when the loop is not running
+    pub fn wake(self: &Arc<Self>) {
+        if self.looping.load(Ordering::Relaxed) {
+            self.trigger.notify_one();
+            return;
+        }
+        let daemon = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = daemon.run_pass().await {
+                tracing::warn!("⚠️  Repair pass failed: {}", e);
+            }
+        });
+    }
+
+    /// Current metrics
+    pub async fn metrics(&self) -> RepairMetrics {
+        self.metrics.read().await.clone()
+    }
+
+    /// Audit the placed blocks and repair the under-replicated ones
+    pub async fn run_pass(&self) -> Result<RepairReport> {
+        let _guard = self.pass_lock.lock().await;
+        let started = Instant::now();
+        let blocks = self.next_batch().await;
+        let data_shards = self.vfs.erasure_data_shards();
+
+        {
+            let mut metrics = self.metrics.write().await;
+            metrics.running = true;
+            metrics.pass_progress = 0;
+            metrics.pass_total = blocks.len();
+        }
+
+        let mut report = RepairReport::default();
+        for block_id in &blocks {
+            let health = match self.vfs.audit_block_shards(block_id).await {
+                Ok(health) => health,
+                Err(e) => {
+                    tracing::debug!("   ⚠️  Audit of block {} failed: {}", block_id, e);
+                    self.metrics.write().await.repair_failures += 1;
+                    continue;
+                }
+            };
+            report.blocks_audited += 1;
+
+            if health.is_healthy() {
+                report.blocks_healthy += 1;
+            } else {
+                report.blocks_under_replicated += 1;
+                tracing::info!("🔧 Block {} has {} of {} shards live",
+                    block_id, health.live.len(), health.live.len() + health.missing.len());
+                match self.vfs.repair_block_shards(&health).await {
+                    Ok((shards, bytes)) => {
+                        report.shards_repaired += shards;
+                        report.bytes_repaired += bytes;
+                        let mut metrics = self.metrics.write().await;
+                        metrics.shards_repaired += shards as u64;
+                        metrics.bytes_repaired += bytes;
+                        drop(metrics);
+                        self.throttle(bytes).await;
+                    }
+                    Err(e) => {
+                        if !health.is_recoverable(data_shards) {
+                            report.unrecoverable.push(block_id.clone());
+                        }
+                        tracing::warn!("   ⚠️  Repair of block {} failed: {}", block_id, e);
+                        self.metrics.write().await.repair_failures += 1;
+                    }
+                }
+            }
+
+            let mut metrics = self.metrics.write().await;
+            metrics.pass_progress += 1;
+            metrics.blocks_audited += 1;
+        }
+
+        report.duration_ms = started.elapsed().as_millis() as u64;
+        if report.shards_repaired > 0 || !report.unrecoverable.is_empty() {
+            tracing::info!("✅ Repair pass: {} blocks audited, {} under-replicated, {} shards repaired, {} unrecoverable",
+                report.blocks_audited, report.blocks_under_replicated, report.shards_repaired, report.unrecoverable.len());
+        }
+
+        let mut metrics = self.metrics.write().await;
+        metrics.running = false;
+        metrics.passes += 1;
+        metrics.last_pass_at = Some(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+        metrics.last_report = Some(report.clone());
+        Ok(report)
+    }
+
+    /// Blocks to audit this pass, resuming where a capped pass stopped
+    async fn next_batch(&self) -> Vec<String> {
+        let blocks = self.vfs.placed_block_ids().await;
+        let limit = self.config.max_blocks_per_pass;
+        if limit == 0 || blocks.len() <= limit {
+            return blocks;
+        }
+
+        let mut cursor = self.cursor.lock().await;
+        let start = *cursor % blocks.len();
+        *cursor = start + limit;
+        blocks.iter().cycle().skip(start).take(limit).cloned().collect()
+    }
+
+    /// Sleep long enough to keep repair traffic under the byte budget
+    async fn throttle(&self, bytes: u64) {
+        if self.config.max_bytes_per_sec == 0 || bytes == 0 {
+            return;
+        }
+        let secs = bytes as f64 / self.config.max_bytes_per_sec as f64;
+        tokio::time::sleep(Duration::from_secs_f64(secs)).await;
+    }
+}
@@ -0,0 +1,156 @@
+// SHARD PLACEMENT - which peer holds each erasure-coded shard of a block
+// Shards of one block always go to distinct peers, so losing M hosts loses at most
+// M shards; the recorded locations tell a download where to ask for them
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Result of auditing the shards of one block
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardHealth {
+    pub block_id: String,
+    /// Shard indices confirmed on a reachable peer
+    pub live: Vec<usize>,
+    /// Shard indices that are unplaced, on a departed peer, or gone from their peer
+    pub missing: Vec<usize>,
+}
+
+impl ShardHealth {
+    /// All shards are live
+    pub fn is_healthy(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Enough shards are live to rebuild the block (`data_shards` = K)
+    pub fn is_recoverable(&self, data_shards: usize) -> bool {
+        self.live.len() >= data_shards
+    }
+}
+
+/// Shard locations of every uploaded block (block_id -> peer per shard index)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ShardLocations {
+    blocks: BTreeMap<String, Vec<Option<String>>>,
+}
+
+impl ShardLocations {
+    /// Create an empty location map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Choose a distinct peer for each of `shard_count` shards of `block_id`
+    /// Peers are taken in a rotation seeded by the block id to spread load; shards left
+    /// over when there are fewer peers than shards are not placed (None)
+    pub fn assign(peers: &[String], block_id: &str, shard_count: usize) -> Vec<Option<String>> {
+        let mut peers: Vec<&String> = peers.iter().collect();
+        peers.sort();
+        peers.dedup();
+        if peers.is_empty() {
+            return vec![None; shard_count];
+        }
+
+        let hash = blake3::hash(block_id.as_bytes());
+        let mut seed = [0u8; 8];
+        seed.copy_from_slice(&hash.as_bytes()[..8]);
+        let offset = (u64::from_le_bytes(seed) % peers.len() as u64) as usize;
+
+        (0..shard_count)
+            .map(|i| (i < peers.len()).then(|| peers[(offset + i) % peers.len()].clone()))
+            .collect()
+    }
+
+    /// Record where the shards of `block_id` were stored
+    pub fn record(&mut self, block_id: &str, locations: Vec<Option<String>>) {
+        self.blocks.insert(block_id.to_string(), locations);
+    }
+
+    /// Peer holding each shard of `block_id`
+    pub fn get(&self, block_id: &str) -> Option<&[Option<String>]> {
+        self.blocks.get(block_id).map(Vec::as_slice)
+    }
+
+    /// Move shard `index` of `block_id` to `peer`
+    pub fn set(&mut self, block_id: &str, index: usize, peer: String) {
+        let locations = self.blocks.entry(block_id.to_string()).or_default();
+        if locations.len() <= index {
+            locations.resize(index + 1, None);
+        }
+        locations[index] = Some(peer);
+    }
+
+    /// Ids of every block with recorded locations
+    pub fn block_ids(&self) -> Vec<String> {
+        self.blocks.keys().cloned().collect()
+    }
+
+    /// Forget the shards of `block_id`
+    pub fn remove(&mut self, block_id: &str) -> bool {
+        self.blocks.remove(block_id).is_some()
+    }
+
+    /// Drop the entries of every block not accepted by `keep`
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) -> usize {
+        let before = self.blocks.len();
+        self.blocks.retain(|block_id, _| keep(block_id));
+        before - self.blocks.len()
+    }
+
+    /// Number of shards of `block_id` placed on a peer
+    pub fn placed_shards(&self, block_id: &str) -> usize {
+        self.get(block_id)
+            .map(|locations| locations.iter().flatten().count())
+            .unwrap_or(0)
+    }
+
+    /// Number of blocks with recorded locations
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether no locations are recorded
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn peers(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("peer-{:02}", i)).collect()
+    }
+
+    #[test]
+    fn test_assign_distinct_peers() {
+        let placement = ShardLocations::assign(&peers(20), "block-a", 14);
+        let placed: HashSet<&String> = placement.iter().flatten().collect();
+        assert_eq!(placed.len(), 14);
+
+        // Deterministic per block, rotated across blocks
+        assert_eq!(placement, ShardLocations::assign(&peers(20), "block-a", 14));
+        let starts: HashSet<Option<String>> = (0..16)
+            .map(|i| ShardLocations::assign(&peers(20), &format!("block-{}", i), 14)[0].clone())
+            .collect();
+        assert!(starts.len() > 1);
+    }
+
+    #[test]
+    fn test_assign_never_doubles_up() {
+        let placement = ShardLocations::assign(&peers(3), "block-a", 14);
+        assert_eq!(placement.iter().flatten().count(), 3);
+        assert!(placement[3..].iter().all(Option::is_none));
+        assert!(ShardLocations::assign(&[], "block-a", 4).iter().all(Option::is_none));
+
+        let mut locations = ShardLocations::new();
+        locations.record("block-a", placement);
+        assert_eq!(locations.placed_shards("block-a"), 3);
+        locations.set("block-a", 5, "peer-99".to_string());
+        assert_eq!(locations.placed_shards("block-a"), 4);
+        assert_eq!(locations.block_ids(), vec!["block-a".to_string()]);
+        assert_eq!(locations.retain(|id| id != "block-a"), 1);
+        assert!(locations.is_empty());
+    }
+}
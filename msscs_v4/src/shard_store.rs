@@ -0,0 +1,211 @@
+// SHARD STORE
+// Erasure-coded shards and replicas held for other peers, kept in a BlockStore under per-peer and total quotas
+
+use crate::block_store::{open_block_store, BlockStore, MemoryBlockStore, StorageBackend};
+use crate::error::{MSSCSError, Result};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Manifest recording who owns each held shard
+const INDEX_MANIFEST: &str = "shard_index";
+
+/// Shard store location and quotas
+#[derive(Debug, Clone)]
+pub struct ShardStoreConfig {
+    /// Directory holding the shards (None = memory only)
+    pub dir: Option<PathBuf>,
+    /// Bytes held for all peers together (also capped by the advertised storage capacity)
+    pub max_bytes: u64,
+    /// Bytes held for any single peer
+    pub max_bytes_per_peer: u64,
+    /// Largest single shard accepted
+    pub max_shard_bytes: u64,
+}
+
+impl Default for ShardStoreConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_bytes: 1 << 30,
+            max_bytes_per_peer: 256 << 20,
+            max_shard_bytes: 4 << 20,
+        }
+    }
+}
+
+/// Owner and size of one held shard
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ShardEntry {
+    owner: String,
+    size: u64,
+}
+
+/// Shards held on behalf of other peers
+/// A shard can only be replaced by the peer that stored it
+pub struct ShardStore {
+    store: Arc<dyn BlockStore>,
+    index: RwLock<HashMap<String, ShardEntry>>,
+    config: ShardStoreConfig,
+}
+
+impl ShardStore {
+    /// Open the store, dropping index entries whose shard bytes were lost
+    pub async fn open(config: ShardStoreConfig) -> Result<Self> {
+        let store: Arc<dyn BlockStore> = match &config.dir {
+            Some(dir) => open_block_store(StorageBackend::Embedded, dir)?,
+            None => Arc::new(MemoryBlockStore::new()),
+        };
+        let mut index: HashMap<String, ShardEntry> = match store.get_manifest(INDEX_MANIFEST).await? {
+            Some(data) => serde_json::from_slice(&data)
+                .map_err(|e| MSSCSError::InvalidData(format!("Corrupted shard index: {}", e)))?,
+            None => HashMap::new(),
+        };
+        let held: std::collections::HashSet<String> = store.list().await?.into_iter().collect();
+        index.retain(|id, _| held.contains(id));
+
+        Ok(ShardStore { store, index: RwLock::new(index), config })
+    }
+
+    /// Hold `data` as shard `id` for `owner`, within the quotas
+    pub async fn put(&self, owner: &PeerId, id: &str, data: &[u8]) -> Result<()> {
+        let owner = owner.to_string();
+        let size = data.len() as u64;
+        if size > self.config.max_shard_bytes {
+            return Err(MSSCSError::Validation(format!(
+                "Shard {} has {} bytes, limit is {}", id, size, self.config.max_shard_bytes
+            )));
+        }
+
+        let mut index = self.index.write().await;
+        let replaced = match index.get(id) {
+            Some(entry) if entry.owner != owner => {
+                return Err(MSSCSError::PermissionDenied(format!("Shard {} is held for another peer", id)));
+            }
+            Some(entry) => entry.size,
+            None => 0,
+        };
+        let total: u64 = index.values().map(|entry| entry.size).sum();
+        let owned: u64 = index.values().filter(|entry| entry.owner == owner).map(|entry| entry.size).sum();
+        if owned - replaced + size > self.config.max_bytes_per_peer {
+            return Err(MSSCSError::Validation(format!(
+                "Peer quota of {} bytes exceeded", self.config.max_bytes_per_peer
+            )));
+        }
+        if total - replaced + size > self.config.max_bytes {
+            return Err(MSSCSError::Validation(format!(
+                "Shard storage full ({} of {} bytes used)", total, self.config.max_bytes
+            )));
+        }
+
+        self.store.put(id, data).await?;
+        index.insert(id.to_string(), ShardEntry { owner, size });
+        self.save_index(&index).await
+    }
+
+    /// Bytes of shard `id`, if held
+    pub async fn get(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        if !self.index.read().await.contains_key(id) {
+            return Ok(None);
+        }
+        self.store.get(id).await
+    }
+
+    /// Total size of shard `id` and up to `len` of its bytes from `offset`, if held
+    pub async fn get_range(&self, id: &str, offset: u64, len: usize) -> Result<Option<(u64, Vec<u8>)>> {
+        if !self.index.read().await.contains_key(id) {
+            return Ok(None);
+        }
+        self.store.get_range(id, offset, len).await
+    }
+
+    /// Whether shard `id` is held
+    pub async fn contains(&self, id: &str) -> bool {
+        self.index.read().await.contains_key(id)
+    }
+
+    /// Ids of every held shard
+    pub async fn ids(&self) -> Vec<String> {
+        self.index.read().await.keys().cloned().collect()
+    }
+
+    /// Whether `owner` has quota left and the store is not full
+    pub async fn has_room(&self, owner: &PeerId) -> bool {
+        let owner = owner.to_string();
+        let index = self.index.read().await;
+        let total: u64 = index.values().map(|entry| entry.size).sum();
+        let owned: u64 = index.values().filter(|entry| entry.owner == owner).map(|entry| entry.size).sum();
+        owned < self.config.max_bytes_per_peer && total < self.config.max_bytes
+    }
+
+    /// Bytes held for all peers together
+    pub async fn used_bytes(&self) -> u64 {
+        self.index.read().await.values().map(|entry| entry.size).sum()
+    }
+
+    async fn save_index(&self, index: &HashMap<String, ShardEntry>) -> Result<()> {
+        let data = serde_json::to_vec(index)
+            .map_err(|e| MSSCSError::InvalidData(format!("Failed to serialize shard index: {}", e)))?;
+        self.store.put_manifest(INDEX_MANIFEST, &data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn limits(dir: Option<PathBuf>) -> ShardStoreConfig {
+        ShardStoreConfig { dir, max_bytes: 300, max_bytes_per_peer: 200, max_shard_bytes: 150 }
+    }
+
+    #[tokio::test]
+    async fn test_quotas_are_enforced() {
+        let store = ShardStore::open(limits(None)).await.unwrap();
+        let (alice, bob, carol) = (PeerId::random(), PeerId::random(), PeerId::random());
+
+        assert!(store.put(&alice, "big", &[0u8; 151]).await.is_err());
+        store.put(&alice, "a1", &[1u8; 100]).await.unwrap();
+        store.put(&alice, "a2", &[2u8; 100]).await.unwrap();
+        assert!(store.put(&alice, "a3", &[3u8; 1]).await.is_err(), "per-peer quota");
+
+        // Replacing a shard only counts the difference
+        store.put(&alice, "a2", &[2u8; 90]).await.unwrap();
+        store.put(&bob, "b1", &[4u8; 100]).await.unwrap();
+        assert!(store.put(&carol, "c1", &[5u8; 20]).await.is_err(), "total quota");
+        assert_eq!(store.used_bytes().await, 290);
+        assert!(store.has_room(&carol).await);
+
+        store.put(&carol, "c1", &[5u8; 10]).await.unwrap();
+        assert!(!store.has_room(&carol).await, "store is full");
+        assert!(!store.has_room(&alice).await);
+    }
+
+    #[tokio::test]
+    async fn test_shards_cannot_be_overwritten_by_other_peers() {
+        let store = ShardStore::open(limits(None)).await.unwrap();
+        let (alice, mallory) = (PeerId::random(), PeerId::random());
+
+        store.put(&alice, "shard", b"alice").await.unwrap();
+        assert!(matches!(store.put(&mallory, "shard", b"mallory").await, Err(MSSCSError::PermissionDenied(_))));
+        assert_eq!(store.get("shard").await.unwrap().unwrap(), b"alice");
+    }
+
+    #[tokio::test]
+    async fn test_shards_survive_reopen() {
+        let dir = TempDir::new().unwrap();
+        let alice = PeerId::random();
+        {
+            let store = ShardStore::open(limits(Some(dir.path().to_path_buf()))).await.unwrap();
+            store.put(&alice, "shard", b"held").await.unwrap();
+        }
+
+        let store = ShardStore::open(limits(Some(dir.path().to_path_buf()))).await.unwrap();
+        assert!(store.contains("shard").await);
+        assert_eq!(store.get("shard").await.unwrap().unwrap(), b"held");
+        assert!(store.put(&PeerId::random(), "shard", b"other").await.is_err());
+    }
+}
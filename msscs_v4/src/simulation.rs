@@ -0,0 +1,589 @@
+// NETWORK SIMULATION - in-process swarms over the libp2p memory transport
+// Every node listens on a /memory address of a shared SimNetwork, which decides per link
+// whether a dial gets through, how long each write takes and when an established connection
+// is cut; SimCluster runs N P2PNodes on it and waits on conditions instead of fixed sleeps
+
+use crate::block::DataBlock;
+use crate::block_store::{BlockStore, MemoryBlockStore};
+use crate::content_addressing::ContentId;
+use crate::error::{MSSCSError, Result};
+use crate::p2p_network::{P2PConfig, P2PNode, P2PNodeCommand};
+use crate::shard_store::ShardStore;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::{
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, MemoryTransport},
+        upgrade, ConnectedPoint,
+    },
+    identity, multiaddr::Protocol, noise, yamux, Multiaddr, PeerId, Transport,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+/// Extra delay of a dial or write hit by packet loss; like TCP, loss surfaces as
+/// retransmission delay rather than as lost data
+const LOSS_RETRANSMIT_DELAY: Duration = Duration::from_millis(200);
+
+/// Interval at which `wait_until` re-checks its condition
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long cut connections may take to close
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Memory ports are process-wide, so parallel simulations draw from one counter
+static NEXT_MEMORY_PORT: AtomicU64 = AtomicU64::new(1 << 40);
+
+#[derive(Default)]
+struct SimState {
+    rng: Option<StdRng>,
+    /// Memory port each node listens on, and back
+    ports: HashMap<PeerId, u64>,
+    peers: HashMap<u64, PeerId>,
+    default_latency: Duration,
+    /// Per-link latency, keyed by the ordered pair
+    latency: HashMap<(PeerId, PeerId), Duration>,
+    /// Probability that a dial or a write has to be retransmitted
+    loss: f64,
+    /// Partition group of each listed peer (None = fully connected)
+    partitions: Option<HashMap<PeerId, usize>>,
+    crashed: HashSet<PeerId>,
+    /// Open connection ends: link and the waker of a pending read
+    sockets: HashMap<u64, (PeerId, PeerId, Option<Waker>)>,
+    next_socket: u64,
+}
+
+impl SimState {
+    fn link(a: PeerId, b: PeerId) -> (PeerId, PeerId) {
+        if a <= b { (a, b) } else { (b, a) }
+    }
+
+    fn reachable(&self, a: &PeerId, b: &PeerId) -> bool {
+        if self.crashed.contains(a) || self.crashed.contains(b) {
+            return false;
+        }
+        // Peers left out of every group form one more group of their own
+        self.partitions.as_ref().is_none_or(|groups| groups.get(a) == groups.get(b))
+    }
+
+    fn lost(&mut self) -> bool {
+        let loss = self.loss;
+        loss > 0.0 && self.rng.as_mut().is_some_and(|rng| rng.gen_bool(loss.min(1.0)))
+    }
+
+    fn latency(&self, a: PeerId, b: PeerId) -> Duration {
+        self.latency.get(&Self::link(a, b)).copied().unwrap_or(self.default_latency)
+    }
+
+    /// Link latency plus a retransmission if the packet is lost
+    fn delay(&mut self, a: PeerId, b: PeerId) -> Duration {
+        let retransmit = if self.lost() { LOSS_RETRANSMIT_DELAY } else { Duration::ZERO };
+        self.latency(a, b) + retransmit
+    }
+}
+
+/// Simulated network shared by every node of a simulation
+#[derive(Clone)]
+pub struct SimNetwork {
+    state: Arc<Mutex<SimState>>,
+}
+
+impl fmt::Debug for SimNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("SimNetwork")
+            .field("nodes", &state.ports.len())
+            .field("crashed", &state.crashed.len())
+            .field("partitioned", &state.partitions.is_some())
+            .finish()
+    }
+}
+
+impl SimNetwork {
+    /// Network without latency or loss; `seed` makes packet loss reproducible
+    pub fn new(seed: u64) -> Self {
+        let state = SimState { rng: Some(StdRng::seed_from_u64(seed)), ..Default::default() };
+        Self { state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Latency of every link without its own setting
+    pub fn set_latency(&self, latency: Duration) {
+        self.lock().default_latency = latency;
+    }
+
+    /// Latency of the link between `a` and `b`, both directions
+    pub fn set_link_latency(&self, a: PeerId, b: PeerId, latency: Duration) {
+        self.lock().latency.insert(SimState::link(a, b), latency);
+    }
+
+    /// Probability (0.0 - 1.0) that a dial or a write is retransmitted
+    pub fn set_loss(&self, rate: f64) {
+        self.lock().loss = rate;
+    }
+
+    /// Split the network: peers reach only peers of their own group, and connections
+    /// across groups are cut
+    pub fn partition(&self, groups: &[Vec<PeerId>]) {
+        let assignment = groups.iter().enumerate()
+            .flat_map(|(group, peers)| peers.iter().map(move |peer| (*peer, group)))
+            .collect();
+        self.lock().partitions = Some(assignment);
+        self.cut_unreachable();
+    }
+
+    /// Remove every partition
+    pub fn heal(&self) {
+        self.lock().partitions = None;
+    }
+
+    /// Take `peer` off the network: its connections are cut and dials to or from it fail
+    pub fn crash(&self, peer: PeerId) {
+        self.lock().crashed.insert(peer);
+        self.cut_unreachable();
+    }
+
+    /// Put a crashed peer back on the network
+    pub fn revive(&self, peer: PeerId) {
+        self.lock().crashed.remove(&peer);
+    }
+
+    pub fn is_crashed(&self, peer: &PeerId) -> bool {
+        self.lock().crashed.contains(peer)
+    }
+
+    /// Whether `a` can currently reach `b`
+    pub fn is_reachable(&self, a: &PeerId, b: &PeerId) -> bool {
+        self.lock().reachable(a, b)
+    }
+
+    /// Dialable address of `peer`, including its peer id
+    pub fn address(&self, peer: &PeerId) -> Option<Multiaddr> {
+        let port = *self.lock().ports.get(peer)?;
+        Some(Multiaddr::empty().with(Protocol::Memory(port)).with(Protocol::P2p(*peer)))
+    }
+
+    /// Address `peer` listens on
+    pub(crate) fn listen_address(&self, peer: &PeerId) -> Result<Multiaddr> {
+        let port = *self.lock().ports.get(peer)
+            .ok_or_else(|| MSSCSError::Network(format!("{} has no simulated transport", peer)))?;
+        Ok(Multiaddr::empty().with(Protocol::Memory(port)))
+    }
+
+    /// Memory transport for the node owning `keypair`, running through this network
+    pub(crate) fn transport(
+        &self,
+        keypair: &identity::Keypair,
+    ) -> std::result::Result<Boxed<(PeerId, StreamMuxerBox)>, Box<dyn std::error::Error + Send + Sync>> {
+        let local = keypair.public().to_peer_id();
+        let local_port = NEXT_MEMORY_PORT.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut state = self.lock();
+            state.ports.insert(local, local_port);
+            state.peers.insert(local_port, local);
+        }
+
+        let network = self.clone();
+        let noise = noise::Config::new(keypair)?;
+        Ok(MemoryTransport::default()
+            .and_then(move |mut socket, endpoint| {
+                let network = network.clone();
+                async move {
+                    // The dialer names its listen port first, so both ends know the link
+                    let remote = match &endpoint {
+                        ConnectedPoint::Dialer { address, .. } => {
+                            socket.write_all(&local_port.to_be_bytes()).await?;
+                            network.peer_at(address)
+                        }
+                        ConnectedPoint::Listener { .. } => {
+                            let mut port = [0u8; 8];
+                            socket.read_exact(&mut port).await?;
+                            network.lock().peers.get(&u64::from_be_bytes(port)).copied()
+                        }
+                    };
+                    let remote = remote.ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionRefused, "unknown simulated peer"))?;
+                    network.connect(local, remote).await?;
+                    Ok::<_, io::Error>(SimSocket::new(socket, local, remote, network))
+                }
+            })
+            .upgrade(upgrade::Version::V1Lazy)
+            .authenticate(noise)
+            .multiplex(yamux::Config::default())
+            .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+            .boxed())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SimState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn peer_at(&self, address: &Multiaddr) -> Option<PeerId> {
+        let port = address.iter().find_map(|p| match p {
+            Protocol::Memory(port) => Some(port),
+            _ => None,
+        })?;
+        self.lock().peers.get(&port).copied()
+    }
+
+    /// Admit a new connection: refused across a partition or to a crashed node,
+    /// otherwise delayed like a packet on the link
+    async fn connect(&self, local: PeerId, remote: PeerId) -> io::Result<()> {
+        let delay = {
+            let mut state = self.lock();
+            if !state.reachable(&local, &remote) {
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "simulated peer unreachable"));
+            }
+            state.delay(local, remote)
+        };
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+
+    /// Wake every socket whose link became unreachable so it fails instead of hanging
+    fn cut_unreachable(&self) {
+        let wakers: Vec<Waker> = {
+            let mut state = self.lock();
+            let cut: Vec<u64> = state.sockets.iter()
+                .filter(|(_, (a, b, _))| !state.reachable(a, b))
+                .map(|(id, _)| *id)
+                .collect();
+            cut.iter()
+                .filter_map(|id| state.sockets.get_mut(id).and_then(|(_, _, waker)| waker.take()))
+                .collect()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// One end of a simulated connection
+struct SimSocket<S> {
+    inner: S,
+    id: u64,
+    local: PeerId,
+    remote: PeerId,
+    network: SimNetwork,
+    /// Latency of the write in progress
+    delay: Option<Pin<Box<tokio::time::Sleep>>>,
+    /// The write in progress already waited out its latency
+    delayed: bool,
+}
+
+impl<S> SimSocket<S> {
+    fn new(inner: S, local: PeerId, remote: PeerId, network: SimNetwork) -> Self {
+        let id = {
+            let mut state = network.lock();
+            state.next_socket += 1;
+            let id = state.next_socket;
+            state.sockets.insert(id, (local, remote, None));
+            id
+        };
+        Self { inner, id, local, remote, network, delay: None, delayed: false }
+    }
+
+    fn check_link(&self) -> io::Result<()> {
+        if self.network.is_reachable(&self.local, &self.remote) {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::ConnectionReset, "simulated link cut"))
+        }
+    }
+}
+
+impl<S> Drop for SimSocket<S> {
+    fn drop(&mut self) {
+        self.network.lock().sockets.remove(&self.id);
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for SimSocket<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.check_link()?;
+        if let Some((_, _, waker)) = self.network.lock().sockets.get_mut(&self.id) {
+            *waker = Some(cx.waker().clone());
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for SimSocket<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.check_link()?;
+        if !self.delayed {
+            if self.delay.is_none() {
+                let delay = self.network.lock().delay(self.local, self.remote);
+                if delay.is_zero() {
+                    self.delayed = true;
+                } else {
+                    self.delay = Some(Box::pin(tokio::time::sleep(delay)));
+                }
+            }
+            if let Some(delay) = self.delay.as_mut() {
+                futures::ready!(delay.as_mut().poll(cx));
+                self.delay = None;
+                self.delayed = true;
+            }
+        }
+        let written = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, buf));
+        self.delayed = false;
+        Poll::Ready(written)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.check_link()?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// A node of a simulated cluster
+pub struct SimNode {
+    pub peer_id: PeerId,
+    pub address: Multiaddr,
+    commands: mpsc::UnboundedSender<P2PNodeCommand>,
+    /// The node's own blocks, as a VFS would keep them
+    store: Arc<dyn BlockStore>,
+    /// Replicas pulled from peers
+    replicas: Arc<ShardStore>,
+}
+
+impl SimNode {
+    pub fn commands(&self) -> mpsc::UnboundedSender<P2PNodeCommand> {
+        self.commands.clone()
+    }
+
+    pub async fn holds(&self, block_id: &Uuid) -> bool {
+        let key = block_id.to_string();
+        matches!(self.store.stat(&key).await, Ok(Some(_))) || self.replicas.contains(&key).await
+    }
+
+    pub async fn holds_chunk(&self, cid: &str) -> bool {
+        let key = format!("chunk-{}", cid);
+        matches!(self.store.stat(&key).await, Ok(Some(_))) || self.replicas.contains(&key).await
+    }
+
+    async fn request<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> P2PNodeCommand) -> Result<T> {
+        let (reply, response) = oneshot::channel();
+        self.commands.send(command(reply))
+            .map_err(|_| MSSCSError::Network(format!("Node {} stopped", self.peer_id)))?;
+        response.await
+            .map_err(|_| MSSCSError::Network(format!("Node {} dropped the request", self.peer_id)))
+    }
+}
+
+/// N P2PNodes on one SimNetwork, each bootstrapped from the first
+pub struct SimCluster {
+    pub network: SimNetwork,
+    pub nodes: Vec<SimNode>,
+}
+
+impl SimCluster {
+    /// Start `size` nodes with the default simulation config
+    pub async fn start(network: SimNetwork, size: usize) -> Result<Self> {
+        Self::start_with(network, size, |_, _| {}).await
+    }
+
+    /// Start `size` nodes, letting `configure` adjust each node's config by index
+    pub async fn start_with(
+        network: SimNetwork,
+        size: usize,
+        configure: impl Fn(usize, &mut P2PConfig),
+    ) -> Result<Self> {
+        let mut nodes: Vec<SimNode> = Vec::with_capacity(size);
+        for index in 0..size {
+            let store: Arc<dyn BlockStore> = Arc::new(MemoryBlockStore::new());
+            let mut config = P2PConfig {
+                bootstrap_peers: nodes.first().map(|seed| seed.address.clone()).into_iter().collect(),
+                enable_mdns: false,
+                enable_relay: false,
+                enable_autonat: false,
+                simulation: Some(network.clone()),
+                block_store: Some(store.clone()),
+                ..Default::default()
+            };
+            configure(index, &mut config);
+
+            let node = P2PNode::new(config.clone()).await?;
+            let peer_id = node.peer_id();
+            let commands = node.get_command_sender();
+            let (_events, replicas) = node.start(config).await?;
+            let address = network.address(&peer_id)
+                .ok_or_else(|| MSSCSError::Network(format!("{} did not join the simulation", peer_id)))?;
+            nodes.push(SimNode { peer_id, address, commands, store, replicas });
+        }
+        Ok(Self { network, nodes })
+    }
+
+    pub fn node(&self, index: usize) -> &SimNode {
+        &self.nodes[index]
+    }
+
+    /// Crash node `index` and wait until every other node noticed
+    pub async fn crash(&self, index: usize) -> Result<()> {
+        self.network.crash(self.nodes[index].peer_id);
+        self.wait_for_disconnects().await
+    }
+
+    /// Bring a crashed node back; it keeps the blocks it held, like a restart on persistent storage
+    pub fn revive(&self, index: usize) {
+        self.network.revive(self.nodes[index].peer_id);
+    }
+
+    /// Split the cluster into groups of node indices and wait until the connections
+    /// across groups are gone
+    pub async fn partition(&self, groups: &[&[usize]]) -> Result<()> {
+        let groups: Vec<Vec<PeerId>> = groups.iter()
+            .map(|group| group.iter().map(|index| self.nodes[*index].peer_id).collect())
+            .collect();
+        self.network.partition(&groups);
+        self.wait_for_disconnects().await
+    }
+
+    pub fn heal(&self) {
+        self.network.heal();
+    }
+
+    /// Indices of the nodes not crashed
+    pub fn live_nodes(&self) -> Vec<usize> {
+        (0..self.nodes.len()).filter(|index| !self.network.is_crashed(&self.nodes[*index].peer_id)).collect()
+    }
+
+    /// Store `block` on node `index` and push it to up to `replicas` peers
+    pub async fn replicate_block(&self, index: usize, block: &DataBlock, replicas: usize) -> Result<usize> {
+        let data = bincode::serialize(block)
+            .map_err(|e| MSSCSError::InvalidData(format!("Serialization error: {}", e)))?;
+        let node = &self.nodes[index];
+        node.store.put(&block.uuid.to_string(), &data).await?;
+        let block_id = block.uuid;
+        node
+            .request(|reply| P2PNodeCommand::ReplicateBlock { block_id, replicas, reply })
+            .await?
+            .map_err(MSSCSError::Network)
+    }
+
+    /// Fetch a block through node `index`, from its own store or the network
+    pub async fn get_block(&self, index: usize, block_id: Uuid) -> Result<DataBlock> {
+        let data = self.nodes[index]
+            .request(|reply| P2PNodeCommand::GetBlock { block_id: block_id.to_string(), expected_hash: None, reply })
+            .await?
+            .map_err(MSSCSError::Network)?;
+        bincode::deserialize(&data)
+            .map_err(|e| MSSCSError::InvalidData(format!("Deserialization error: {}", e)))
+    }
+
+    /// Store sealed chunk bytes on node `index` and push them to up to `replicas` peers;
+    /// returns the chunk's CID and the number of peers that accepted
+    pub async fn replicate_chunk(&self, index: usize, sealed: &[u8], replicas: usize) -> Result<(String, usize)> {
+        let cid = ContentId::from_data(sealed).to_hex();
+        let node = &self.nodes[index];
+        node.store.put(&format!("chunk-{}", cid), sealed).await?;
+        let request = cid.clone();
+        let accepted = node
+            .request(|reply| P2PNodeCommand::ReplicateChunk { cid: request, replicas, reply })
+            .await?
+            .map_err(MSSCSError::Network)?;
+        Ok((cid, accepted))
+    }
+
+    /// Fetch a chunk through node `index`, from its own store or the network
+    pub async fn get_chunk(&self, index: usize, cid: &str) -> Result<Vec<u8>> {
+        let cid = cid.to_string();
+        self.nodes[index]
+            .request(|reply| P2PNodeCommand::GetChunk { cid, reply })
+            .await?
+            .map_err(MSSCSError::Network)
+    }
+
+    /// Peers node `index` has a connection to
+    pub async fn connected_peers(&self, index: usize) -> Result<Vec<PeerId>> {
+        self.nodes[index].request(P2PNodeCommand::GetConnectedPeers).await
+    }
+
+    /// Peers in node `index`'s Kademlia routing table
+    pub async fn routing_table(&self, index: usize) -> Result<Vec<PeerId>> {
+        self.nodes[index].request(P2PNodeCommand::GetRoutingTable).await
+    }
+
+    /// Live nodes holding `block_id`
+    pub async fn holders(&self, block_id: &Uuid) -> Vec<usize> {
+        let mut holders = Vec::new();
+        for index in self.live_nodes() {
+            if self.nodes[index].holds(block_id).await {
+                holders.push(index);
+            }
+        }
+        holders
+    }
+
+    /// Wait until at least `copies` live nodes hold `block_id`; returns them
+    pub async fn wait_for_replication(&self, block_id: Uuid, copies: usize, timeout: Duration) -> Result<Vec<usize>> {
+        wait_until(timeout, || async { self.holders(&block_id).await.len() >= copies }).await
+            .map_err(|_| MSSCSError::Network(format!("Block {} did not reach {} copies", block_id, copies)))?;
+        Ok(self.holders(&block_id).await)
+    }
+
+    /// Wait until every live node's routing table lists every node it can reach
+    pub async fn wait_for_dht_convergence(&self, timeout: Duration) -> Result<()> {
+        wait_until(timeout, || async {
+            let live = self.live_nodes();
+            for &index in &live {
+                let Ok(table) = self.routing_table(index).await else { return false };
+                let local = self.nodes[index].peer_id;
+                let missing = live.iter()
+                    .map(|other| self.nodes[*other].peer_id)
+                    .filter(|peer| *peer != local && self.network.is_reachable(&local, peer))
+                    .any(|peer| !table.contains(&peer));
+                if missing {
+                    return false;
+                }
+            }
+            true
+        }).await
+        .map_err(|_| MSSCSError::Network("DHT did not converge".to_string()))
+    }
+
+    /// Wait until no live node keeps a connection the network no longer carries
+    async fn wait_for_disconnects(&self) -> Result<()> {
+        wait_until(DISCONNECT_TIMEOUT, || async {
+            for index in self.live_nodes() {
+                let local = self.nodes[index].peer_id;
+                let Ok(connected) = self.connected_peers(index).await else { return false };
+                if connected.iter().any(|peer| !self.network.is_reachable(&local, peer)) {
+                    return false;
+                }
+            }
+            true
+        }).await
+    }
+}
+
+/// Re-check `condition` until it holds or `timeout` passes
+pub async fn wait_until<F, Fut>(timeout: Duration, mut condition: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if condition().await {
+            return Ok(());
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(MSSCSError::Network(format!("Condition not met within {:?}", timeout)));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -4,7 +4,6 @@
 use sharks::{Sharks, Share};
 use blake3;
 use serde::{Serialize, Deserialize};
-use rand::{RngCore, rngs::OsRng};
 
 use crate::error::{MSSCSError, Result};
 
@@ -54,12 +53,6 @@ impl SingularityFragmentation {
             ));
         }
         
-        if total_shards > 255 {
-            return Err(MSSCSError::InvalidData(
-                "Total shards cannot exceed 255".into()
-            ));
-        }
-        
         Ok(Self {
             threshold,
             total_shards,
@@ -267,6 +260,7 @@ impl SingularitySecurity {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{RngCore, rngs::OsRng};
     
     #[test]
     fn test_singularity_fragmentation() {
@@ -0,0 +1,233 @@
+// SNAPSHOTS - named, read-only, point-in-time copies of the namespace
+// A snapshot only copies metadata; its files share block chains with the live tree
+// and its heads act as GC roots so orphan cleanup never deletes them
+
+use crate::error::{MSSCSError, Result};
+use crate::namespace::{Namespace, NamespaceDiff};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Frozen namespace plus bookkeeping
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Summary shown in listings
+    pub info: SnapshotInfo,
+    /// Directory tree at the time of the snapshot
+    pub namespace: Namespace,
+}
+
+/// Snapshot summary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    /// Snapshot name
+    pub name: String,
+    /// Creation timestamp
+    pub created_at: u64,
+    /// User who took the snapshot
+    pub author: String,
+    /// Hex-encoded Merkle root of the frozen tree
+    pub root_cid: String,
+    /// Number of files in the snapshot
+    pub file_count: usize,
+    /// Logical size of all files in bytes
+    pub total_size: u64,
+}
+
+/// Difference between two snapshots (or a snapshot and the live tree)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    pub from: String,
+    /// Target snapshot (None = live tree)
+    pub to: Option<String>,
+    #[serde(flatten)]
+    pub changes: NamespaceDiff,
+}
+
+/// All snapshots of one file system, keyed by name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotSet {
+    snapshots: BTreeMap<String, Snapshot>,
+}
+
+impl SnapshotSet {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Freeze `namespace` under `name`
+    pub fn create(&mut self, name: &str, namespace: &Namespace, author: &str) -> Result<SnapshotInfo> {
+        Self::validate_name(name)?;
+        if self.snapshots.contains_key(name) {
+            return Err(MSSCSError::InvalidData(format!("Snapshot '{}' already exists", name)));
+        }
+
+        let frozen = namespace.freeze();
+        let files = frozen.files();
+        let info = SnapshotInfo {
+            name: name.to_string(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            author: author.to_string(),
+            root_cid: frozen.merkle_root().cid.to_hex(),
+            file_count: files.len(),
+            total_size: files.iter().map(|(_, inode)| inode.size).sum(),
+        };
+
+        self.snapshots.insert(
+            name.to_string(),
+            Snapshot {
+                info: info.clone(),
+                namespace: frozen,
+            },
+        );
+        Ok(info)
+    }
+
+    /// Delete a snapshot, releasing its blocks to GC
+    pub fn delete(&mut self, name: &str) -> Result<SnapshotInfo> {
+        self.snapshots
+            .remove(name)
+            .map(|snapshot| snapshot.info)
+            .ok_or_else(|| MSSCSError::NotFound(format!("Snapshot '{}' not found", name)))
+    }
+
+    /// Snapshot summaries, oldest first
+    pub fn list(&self) -> Vec<SnapshotInfo> {
+        let mut infos: Vec<SnapshotInfo> = self.snapshots.values().map(|s| s.info.clone()).collect();
+        infos.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.name.cmp(&b.name)));
+        infos
+    }
+
+    /// Frozen tree of a snapshot
+    pub fn get(&self, name: &str) -> Result<&Namespace> {
+        self.snapshots
+            .get(name)
+            .map(|snapshot| &snapshot.namespace)
+            .ok_or_else(|| MSSCSError::NotFound(format!("Snapshot '{}' not found", name)))
+    }
+
+    /// Diff snapshot `from` against snapshot `to`, or against `live` when `to` is None
+    pub fn diff(&self, from: &str, to: Option<&str>, live: &Namespace) -> Result<SnapshotDiff> {
+        let old = self.get(from)?;
+        let new = match to {
+            Some(name) => self.get(name)?,
+            None => live,
+        };
+
+        Ok(SnapshotDiff {
+            from: from.to_string(),
+            to: to.map(str::to_string),
+            changes: old.diff(new),
+        })
+    }
+
+    /// Heads of every file held by any snapshot (GC roots)
+    pub fn referenced_heads(&self) -> Vec<Uuid> {
+        let mut heads: Vec<Uuid> = self
+            .snapshots
+            .values()
+            .flat_map(|snapshot| snapshot.namespace.referenced_heads())
+            .collect();
+        heads.sort();
+        heads.dedup();
+        heads
+    }
+
+    /// Record new head block hashes in every snapshot; returns how many versions were updated
+    /// Snapshot Merkle roots only cover heads, so they stay valid
+    pub fn update_root_hashes(&mut self, hashes: &HashMap<Uuid, [u8; 32]>) -> usize {
+        self.snapshots
+            .values_mut()
+            .map(|snapshot| snapshot.namespace.update_root_hashes(hashes))
+            .sum()
+    }
+
+    /// Number of snapshots
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Whether there are no snapshots
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    fn validate_name(name: &str) -> Result<()> {
+        let valid = !name.is_empty()
+            && name.len() <= 128
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(MSSCSError::Validation(format!(
+                "Invalid snapshot name '{}': use 1-128 letters, digits, '-', '_' or '.'",
+                name
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adaptive_compression::CompressionAlgorithm;
+    use crate::namespace::FileContent;
+
+    fn write(ns: &mut Namespace, path: &str, size: u64) -> Uuid {
+        let head = Uuid::new_v4();
+        let content = FileContent {
+            head,
+            size,
+            block_count: 1,
+            compression: CompressionAlgorithm::None,
+            compressed_per_block: false,
+            root_hash: [0u8; 32],
+            chunks: Vec::new(),
+        };
+        ns.write_file(path, content, "alice").unwrap();
+        head
+    }
+
+    #[test]
+    fn test_snapshot_lifecycle() {
+        let mut ns = Namespace::new();
+        let first = write(&mut ns, "docs/a.txt", 10);
+        let mut set = SnapshotSet::new();
+
+        let info = set.create("before-edit", &ns, "alice").unwrap();
+        assert_eq!(info.file_count, 1);
+        assert_eq!(info.total_size, 10);
+        assert!(set.create("before-edit", &ns, "alice").is_err());
+        assert!(set.create("bad/name", &ns, "alice").is_err());
+
+        // Live changes do not leak into the snapshot
+        let second = write(&mut ns, "docs/a.txt", 20);
+        write(&mut ns, "docs/b.txt", 5);
+        let frozen = set.get("before-edit").unwrap();
+        assert_eq!(frozen.get_file("docs/a.txt").unwrap().head, Some(first));
+        assert!(frozen.lookup("docs/b.txt").is_none());
+
+        // Only the snapshotted chain is a GC root, not the live one
+        assert_eq!(set.referenced_heads(), vec![first]);
+        assert_ne!(first, second);
+
+        let diff = set.diff("before-edit", None, &ns).unwrap();
+        assert_eq!(diff.changes.added, vec!["docs/b.txt"]);
+        assert_eq!(diff.changes.modified, vec!["docs/a.txt"]);
+
+        set.create("after-edit", &ns, "alice").unwrap();
+        let diff = set.diff("after-edit", Some("before-edit"), &ns).unwrap();
+        assert_eq!(diff.changes.removed, vec!["docs/b.txt"]);
+        assert_eq!(set.list().len(), 2);
+
+        set.delete("before-edit").unwrap();
+        assert!(set.get("before-edit").is_err());
+        assert_eq!(set.len(), 1);
+    }
+}
@@ -0,0 +1,501 @@
+// STUN CLIENT - RFC 5389 binding requests and RFC 5780 NAT behaviour discovery
+// The mapping test compares the address the NAT assigns towards the server's primary and
+// alternate addresses; the filtering test asks the server to answer from another IP/port
+// and checks which of those replies get through
+
+use crate::error::{MSSCSError, Result};
+use crate::relay::NATType;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Fixed value in every RFC 5389 header
+pub const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+const HEADER_LEN: usize = 20;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const BINDING_ERROR: u16 = 0x0111;
+
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_CHANGE_REQUEST: u16 = 0x0003;
+/// RFC 3489 predecessor of OTHER-ADDRESS, still sent by many servers
+const ATTR_CHANGED_ADDRESS: u16 = 0x0005;
+const ATTR_ERROR_CODE: u16 = 0x0009;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ATTR_RESPONSE_ORIGIN: u16 = 0x802b;
+const ATTR_OTHER_ADDRESS: u16 = 0x802c;
+
+/// Message type of a STUN message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StunMessageType {
+    BindingRequest,
+    BindingSuccess,
+    BindingError,
+}
+
+impl StunMessageType {
+    fn code(self) -> u16 {
+        match self {
+            StunMessageType::BindingRequest => BINDING_REQUEST,
+            StunMessageType::BindingSuccess => BINDING_SUCCESS,
+            StunMessageType::BindingError => BINDING_ERROR,
+        }
+    }
+
+    fn from_code(code: u16) -> Option<Self> {
+        match code {
+            BINDING_REQUEST => Some(StunMessageType::BindingRequest),
+            BINDING_SUCCESS => Some(StunMessageType::BindingSuccess),
+            BINDING_ERROR => Some(StunMessageType::BindingError),
+            _ => None,
+        }
+    }
+}
+
+/// Attributes understood by the client; anything else is kept undecoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StunAttribute {
+    MappedAddress(SocketAddr),
+    XorMappedAddress(SocketAddr),
+    /// RFC 5780: ask the server to answer from its alternate IP and/or port
+    ChangeRequest { change_ip: bool, change_port: bool },
+    /// RFC 5780: the server's alternate address (CHANGED-ADDRESS in RFC 3489)
+    OtherAddress(SocketAddr),
+    /// RFC 5780: address the response was sent from
+    ResponseOrigin(SocketAddr),
+    ErrorCode { code: u16, reason: String },
+    Unknown { kind: u16, value: Vec<u8> },
+}
+
+/// A STUN message (header plus attributes)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StunMessage {
+    pub message_type: StunMessageType,
+    pub transaction_id: [u8; 12],
+    pub attributes: Vec<StunAttribute>,
+}
+
+impl StunMessage {
+    /// Binding request with a fresh transaction id, optionally carrying CHANGE-REQUEST
+    pub fn binding_request(change: ChangeRequest) -> Self {
+        let mut transaction_id = [0u8; 12];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut transaction_id);
+        let mut attributes = Vec::new();
+        if change != ChangeRequest::None {
+            attributes.push(StunAttribute::ChangeRequest {
+                change_ip: change.changes_ip(),
+                change_port: change.changes_port(),
+            });
+        }
+        Self { message_type: StunMessageType::BindingRequest, transaction_id, attributes }
+    }
+
+    /// Mapped address, preferring XOR-MAPPED-ADDRESS over the legacy attribute
+    pub fn mapped_address(&self) -> Option<SocketAddr> {
+        let xor = self.attributes.iter().find_map(|attr| match attr {
+            StunAttribute::XorMappedAddress(addr) => Some(*addr),
+            _ => None,
+        });
+        xor.or_else(|| self.attributes.iter().find_map(|attr| match attr {
+            StunAttribute::MappedAddress(addr) => Some(*addr),
+            _ => None,
+        }))
+    }
+
+    pub fn other_address(&self) -> Option<SocketAddr> {
+        self.attributes.iter().find_map(|attr| match attr {
+            StunAttribute::OtherAddress(addr) => Some(*addr),
+            _ => None,
+        })
+    }
+
+    pub fn change_request(&self) -> ChangeRequest {
+        self.attributes.iter().find_map(|attr| match attr {
+            StunAttribute::ChangeRequest { change_ip, change_port } => {
+                Some(ChangeRequest::from_flags(*change_ip, *change_port))
+            }
+            _ => None,
+        }).unwrap_or(ChangeRequest::None)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for attr in &self.attributes {
+            let (kind, value) = match attr {
+                StunAttribute::MappedAddress(addr) => (ATTR_MAPPED_ADDRESS, encode_address(*addr)),
+                StunAttribute::XorMappedAddress(addr) => {
+                    (ATTR_XOR_MAPPED_ADDRESS, encode_address(xor_address(*addr, &self.transaction_id)))
+                }
+                StunAttribute::ChangeRequest { change_ip, change_port } => {
+                    let flags = (u32::from(*change_ip) << 2) | (u32::from(*change_port) << 1);
+                    (ATTR_CHANGE_REQUEST, flags.to_be_bytes().to_vec())
+                }
+                StunAttribute::OtherAddress(addr) => (ATTR_OTHER_ADDRESS, encode_address(*addr)),
+                StunAttribute::ResponseOrigin(addr) => (ATTR_RESPONSE_ORIGIN, encode_address(*addr)),
+                StunAttribute::ErrorCode { code, reason } => {
+                    let mut value = vec![0, 0, (code / 100) as u8, (code % 100) as u8];
+                    value.extend_from_slice(reason.as_bytes());
+                    (ATTR_ERROR_CODE, value)
+                }
+                StunAttribute::Unknown { kind, value } => (*kind, value.clone()),
+            };
+            body.extend_from_slice(&kind.to_be_bytes());
+            body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            body.extend_from_slice(&value);
+            body.resize(body.len().next_multiple_of(4), 0);
+        }
+
+        let mut out = Vec::with_capacity(HEADER_LEN + body.len());
+        out.extend_from_slice(&self.message_type.code().to_be_bytes());
+        out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        out.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        out.extend_from_slice(&self.transaction_id);
+        out.extend_from_slice(&body);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LEN || bytes[0] & 0xc0 != 0 {
+            return Err(MSSCSError::InvalidData("Not a STUN message".to_string()));
+        }
+        let code = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let length = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        if u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) != MAGIC_COOKIE {
+            return Err(MSSCSError::InvalidData("STUN magic cookie mismatch".to_string()));
+        }
+        if !length.is_multiple_of(4) || bytes.len() < HEADER_LEN + length {
+            return Err(MSSCSError::InvalidData("Truncated STUN message".to_string()));
+        }
+        let message_type = StunMessageType::from_code(code)
+            .ok_or_else(|| MSSCSError::InvalidData(format!("Unsupported STUN message type {:#06x}", code)))?;
+        let mut transaction_id = [0u8; 12];
+        transaction_id.copy_from_slice(&bytes[8..HEADER_LEN]);
+
+        let mut attributes = Vec::new();
+        let mut body = &bytes[HEADER_LEN..HEADER_LEN + length];
+        while !body.is_empty() {
+            if body.len() < 4 {
+                return Err(MSSCSError::InvalidData("Truncated STUN attribute".to_string()));
+            }
+            let kind = u16::from_be_bytes([body[0], body[1]]);
+            let len = u16::from_be_bytes([body[2], body[3]]) as usize;
+            let padded = 4 + len.next_multiple_of(4);
+            if body.len() < 4 + len {
+                return Err(MSSCSError::InvalidData("Truncated STUN attribute".to_string()));
+            }
+            let value = &body[4..4 + len];
+            attributes.push(match kind {
+                ATTR_MAPPED_ADDRESS => StunAttribute::MappedAddress(decode_address(value)?),
+                ATTR_XOR_MAPPED_ADDRESS => {
+                    StunAttribute::XorMappedAddress(xor_address(decode_address(value)?, &transaction_id))
+                }
+                ATTR_CHANGE_REQUEST if len == 4 => StunAttribute::ChangeRequest {
+                    change_ip: value[3] & 0x04 != 0,
+                    change_port: value[3] & 0x02 != 0,
+                },
+                ATTR_OTHER_ADDRESS | ATTR_CHANGED_ADDRESS => StunAttribute::OtherAddress(decode_address(value)?),
+                ATTR_RESPONSE_ORIGIN => StunAttribute::ResponseOrigin(decode_address(value)?),
+                ATTR_ERROR_CODE if len >= 4 => StunAttribute::ErrorCode {
+                    code: u16::from(value[2] & 0x07) * 100 + u16::from(value[3]),
+                    reason: String::from_utf8_lossy(&value[4..]).into_owned(),
+                },
+                _ => StunAttribute::Unknown { kind, value: value.to_vec() },
+            });
+            body = &body[padded.min(body.len())..];
+        }
+
+        Ok(Self { message_type, transaction_id, attributes })
+    }
+}
+
+fn encode_address(addr: SocketAddr) -> Vec<u8> {
+    let mut out = vec![0];
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            out.push(0x01);
+            out.extend_from_slice(&addr.port().to_be_bytes());
+            out.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            out.push(0x02);
+            out.extend_from_slice(&addr.port().to_be_bytes());
+            out.extend_from_slice(&ip.octets());
+        }
+    }
+    out
+}
+
+fn decode_address(value: &[u8]) -> Result<SocketAddr> {
+    let invalid = || MSSCSError::InvalidData("Malformed STUN address attribute".to_string());
+    if value.len() < 4 {
+        return Err(invalid());
+    }
+    let port = u16::from_be_bytes([value[2], value[3]]);
+    let ip = match (value[1], value.len()) {
+        (0x01, 8) => IpAddr::V4(Ipv4Addr::new(value[4], value[5], value[6], value[7])),
+        (0x02, 20) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&value[4..20]);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// XOR an address with the magic cookie (and transaction id for IPv6); its own inverse
+fn xor_address(addr: SocketAddr, transaction_id: &[u8; 12]) -> SocketAddr {
+    let port = addr.port() ^ (MAGIC_COOKIE >> 16) as u16;
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(u32::from(ip) ^ MAGIC_COOKIE)),
+        IpAddr::V6(ip) => {
+            let mut key = [0u8; 16];
+            key[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+            key[4..].copy_from_slice(transaction_id);
+            let mut octets = ip.octets();
+            for (byte, k) in octets.iter_mut().zip(key) {
+                *byte ^= k;
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+    };
+    SocketAddr::new(ip, port)
+}
+
+/// Which of its addresses the server should answer from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeRequest {
+    None,
+    Port,
+    IpAndPort,
+}
+
+impl ChangeRequest {
+    pub fn changes_ip(self) -> bool {
+        self == ChangeRequest::IpAndPort
+    }
+
+    pub fn changes_port(self) -> bool {
+        self != ChangeRequest::None
+    }
+
+    /// Changing only the IP is not a filtering test and is folded into IpAndPort
+    fn from_flags(change_ip: bool, change_port: bool) -> Self {
+        match (change_ip, change_port) {
+            (false, false) => ChangeRequest::None,
+            (false, true) => ChangeRequest::Port,
+            _ => ChangeRequest::IpAndPort,
+        }
+    }
+}
+
+/// Answer to one binding request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindingResponse {
+    /// Our address as seen by the server
+    pub mapped: SocketAddr,
+    /// The server's alternate address, if it supports RFC 5780
+    pub other: Option<SocketAddr>,
+}
+
+/// How the NAT assigns external addresses (RFC 4787 terms)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingBehavior {
+    /// Not behind a NAT: the mapped address is the local one
+    NoNat,
+    EndpointIndependent,
+    AddressDependent,
+    AddressAndPortDependent,
+}
+
+/// Which inbound packets the NAT lets through (RFC 4787 terms)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilteringBehavior {
+    EndpointIndependent,
+    AddressDependent,
+    AddressAndPortDependent,
+}
+
+/// Outcome of NAT behaviour discovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NatReport {
+    pub nat_type: NATType,
+    /// Public address of the probing socket
+    pub public_addr: SocketAddr,
+    pub mapping: MappingBehavior,
+    /// None when the server has no alternate address to test filtering with
+    pub filtering: Option<FilteringBehavior>,
+}
+
+impl NatReport {
+    fn new(public_addr: SocketAddr, mapping: MappingBehavior, filtering: Option<FilteringBehavior>) -> Self {
+        // A cone NAT whose filtering could not be measured is assumed to be the strictest cone
+        let nat_type = match (mapping, filtering) {
+            (MappingBehavior::NoNat, _) => NATType::None,
+            (MappingBehavior::AddressDependent | MappingBehavior::AddressAndPortDependent, _) => NATType::Symmetric,
+            (_, Some(FilteringBehavior::EndpointIndependent)) => NATType::FullCone,
+            (_, Some(FilteringBehavior::AddressDependent)) => NATType::RestrictedCone,
+            (_, _) => NATType::PortRestrictedCone,
+        };
+        Self { nat_type, public_addr, mapping, filtering }
+    }
+}
+
+/// STUN client bound to one UDP socket; all probes must share it, since the NAT
+/// mapping being measured belongs to that socket
+pub struct StunClient {
+    socket: UdpSocket,
+    /// Initial retransmission timeout, doubled per attempt (RFC 5389 RTO)
+    rto: Duration,
+    /// Requests sent before a test counts as unanswered
+    attempts: u32,
+}
+
+impl StunClient {
+    pub async fn bind(local_addr: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind(local_addr).await?;
+        Ok(Self { socket, rto: Duration::from_millis(500), attempts: 3 })
+    }
+
+    /// Override the retransmission schedule
+    pub fn with_timeouts(mut self, rto: Duration, attempts: u32) -> Self {
+        self.rto = rto;
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Send a binding request to `server`; Ok(None) when no answer arrived in time,
+    /// which for change requests is the expected result of a filtering NAT
+    pub async fn binding(&self, server: SocketAddr, change: ChangeRequest) -> Result<Option<BindingResponse>> {
+        let request = StunMessage::binding_request(change);
+        let bytes = request.encode();
+        let mut rto = self.rto;
+        let mut buf = [0u8; 1024];
+
+        for _ in 0..self.attempts {
+            self.socket.send_to(&bytes, server).await?;
+            let deadline = tokio::time::Instant::now() + rto;
+            // Replies to change requests come from other addresses, so match on the transaction id only
+            while let Ok(received) = tokio::time::timeout_at(deadline, self.socket.recv_from(&mut buf)).await {
+                let (len, _) = received?;
+                let Ok(response) = StunMessage::decode(&buf[..len]) else { continue };
+                if response.transaction_id != request.transaction_id {
+                    continue;
+                }
+                if response.message_type == StunMessageType::BindingError {
+                    return Err(MSSCSError::Network(format!("STUN server {} returned an error: {:?}", server, response.attributes)));
+                }
+                let mapped = response.mapped_address()
+                    .ok_or_else(|| MSSCSError::InvalidData(format!("STUN response from {} has no mapped address", server)))?;
+                return Ok(Some(BindingResponse { mapped, other: response.other_address() }));
+            }
+            rto *= 2;
+        }
+        Ok(None)
+    }
+
+    /// Classify the NAT in front of this socket (RFC 5780 section 4.3 and 4.4)
+    pub async fn classify(&self, server: SocketAddr) -> Result<NatReport> {
+        let first = self.binding(server, ChangeRequest::None).await?
+            .ok_or_else(|| MSSCSError::Network(format!("STUN server {} did not answer", server)))?;
+
+        let local = self.outbound_addr(server).await?;
+        if first.mapped == local {
+            return Ok(NatReport::new(first.mapped, MappingBehavior::NoNat, None));
+        }
+        let Some(other) = first.other else {
+            return Ok(NatReport::new(first.mapped, MappingBehavior::EndpointIndependent, None));
+        };
+
+        // Mapping: same mapping towards the alternate IP means endpoint-independent
+        let alternate_ip = SocketAddr::new(other.ip(), server.port());
+        let mapping = match self.binding(alternate_ip, ChangeRequest::None).await? {
+            None => return Err(MSSCSError::Network(format!("STUN alternate address {} did not answer", alternate_ip))),
+            Some(second) if second.mapped == first.mapped => MappingBehavior::EndpointIndependent,
+            Some(second) => match self.binding(other, ChangeRequest::None).await? {
+                Some(third) if third.mapped == second.mapped => MappingBehavior::AddressDependent,
+                _ => MappingBehavior::AddressAndPortDependent,
+            },
+        };
+        if mapping != MappingBehavior::EndpointIndependent {
+            return Ok(NatReport::new(first.mapped, mapping, None));
+        }
+
+        // Filtering: which of the server's other addresses may reach us
+        let filtering = if self.binding(server, ChangeRequest::IpAndPort).await?.is_some() {
+            FilteringBehavior::EndpointIndependent
+        } else if self.binding(server, ChangeRequest::Port).await?.is_some() {
+            FilteringBehavior::AddressDependent
+        } else {
+            FilteringBehavior::AddressAndPortDependent
+        };
+        Ok(NatReport::new(first.mapped, mapping, Some(filtering)))
+    }
+
+    /// Classify with a plain RFC 5389 server pair: differing mappings towards two
+    /// servers reveal a symmetric NAT, equal ones a cone of unknown filtering
+    pub async fn classify_with_pair(&self, primary: SocketAddr, secondary: SocketAddr) -> Result<NatReport> {
+        let report = self.classify(primary).await?;
+        if report.mapping != MappingBehavior::EndpointIndependent || report.filtering.is_some() {
+            return Ok(report);
+        }
+        match self.binding(secondary, ChangeRequest::None).await? {
+            Some(second) if second.mapped != report.public_addr => {
+                Ok(NatReport::new(report.public_addr, MappingBehavior::AddressAndPortDependent, None))
+            }
+            _ => Ok(report),
+        }
+    }
+
+    /// Local address packets to `server` leave from; resolves a wildcard bind
+    /// to the interface address by routing a throwaway socket
+    async fn outbound_addr(&self, server: SocketAddr) -> Result<SocketAddr> {
+        let local = self.socket.local_addr()?;
+        if !local.ip().is_unspecified() {
+            return Ok(local);
+        }
+        let probe = UdpSocket::bind(SocketAddr::new(local.ip(), 0)).await?;
+        probe.connect(server).await?;
+        Ok(SocketAddr::new(probe.local_addr()?.ip(), local.port()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let mut request = StunMessage::binding_request(ChangeRequest::Port);
+        assert_eq!(StunMessage::decode(&request.encode()).unwrap(), request);
+        assert_eq!(request.change_request(), ChangeRequest::Port);
+
+        request.message_type = StunMessageType::BindingSuccess;
+        request.attributes = vec![
+            StunAttribute::XorMappedAddress("203.0.113.7:40000".parse().unwrap()),
+            StunAttribute::XorMappedAddress("[2001:db8::1]:40000".parse().unwrap()),
+            StunAttribute::OtherAddress("198.51.100.2:3479".parse().unwrap()),
+            StunAttribute::Unknown { kind: 0x8022, value: b"msscs".to_vec() },
+        ];
+        let encoded = request.encode();
+        assert_eq!(encoded.len() % 4, 0);
+        // The XOR-encoded address must not appear in the clear on the wire
+        assert!(!encoded.windows(4).any(|w| w == [203, 0, 113, 7]));
+
+        let decoded = StunMessage::decode(&encoded).unwrap();
+        assert_eq!(decoded, request);
+        assert_eq!(decoded.mapped_address(), Some("203.0.113.7:40000".parse().unwrap()));
+        assert_eq!(decoded.other_address(), Some("198.51.100.2:3479".parse().unwrap()));
+
+        assert!(StunMessage::decode(&encoded[..encoded.len() - 2]).is_err());
+        let mut bad_cookie = encoded.clone();
+        bad_cookie[4] ^= 0xff;
+        assert!(StunMessage::decode(&bad_cookie).is_err());
+    }
+}
@@ -1,9 +1,12 @@
 // Unlocked identity with decrypted secret keys
-use crate::identity::QuantumIdentity;
+use crate::identity::{self, QuantumIdentity};
 use crate::error::Result;
+use crate::key_rotation::RotationStatement;
 use uuid::Uuid;
+use zeroize::Zeroize;
 
 /// Unlocked identity with access to secret keys
+/// The secret keys are pinned in RAM while unlocked and wiped when dropped
 pub struct UnlockedIdentity {
     pub identity: QuantumIdentity,
     pub(crate) ed25519_secret: Vec<u8>,
@@ -12,6 +15,24 @@ pub struct UnlockedIdentity {
 }
 
 impl UnlockedIdentity {
+    /// Wrap decrypted secret keys, pinning them in RAM so they are never swapped out
+    pub(crate) fn from_secrets(
+        identity: QuantumIdentity,
+        ed25519_secret: Vec<u8>,
+        kyber_secret: Vec<u8>,
+        dilithium_secret: Vec<u8>,
+    ) -> Self {
+        let unlocked = UnlockedIdentity { identity, ed25519_secret, kyber_secret, dilithium_secret };
+        for secret in unlocked.secrets() {
+            pin_memory(secret);
+        }
+        unlocked
+    }
+
+    fn secrets(&self) -> [&Vec<u8>; 3] {
+        [&self.ed25519_secret, &self.kyber_secret, &self.dilithium_secret]
+    }
+
     /// Get user ID
     pub fn user_id(&self) -> &Uuid {
         &self.identity.id
@@ -22,6 +43,51 @@ impl UnlockedIdentity {
         &self.identity.name
     }
 
+    /// Symmetric master key derived from the identity's Ed25519 secret
+    pub fn master_key(&self) -> [u8; 32] {
+        blake3::derive_key("MSSCS identity master key v1", &self.ed25519_secret)
+    }
+
+    /// Stored form of this identity with the secret keys encrypted under `passphrase` and a
+    /// fresh salt
+    pub fn seal(&self, passphrase: &str) -> Result<QuantumIdentity> {
+        let (salt, [ed25519, kyber, dilithium]) = identity::encrypt_secrets(
+            passphrase,
+            [&self.ed25519_secret, &self.kyber_secret, &self.dilithium_secret],
+        )?;
+        Ok(QuantumIdentity {
+            encrypted_ed25519_secret: ed25519,
+            encrypted_kyber_secret: kyber,
+            encrypted_dilithium_secret: dilithium,
+            salt,
+            ..self.identity.clone()
+        })
+    }
+
+    /// Successor of this identity with freshly generated keys sealed under `passphrase`
+    /// The successor keeps the id, bumps the key generation and carries a rotation statement
+    /// signed by both generations
+    pub fn rotate(&self, passphrase: &str) -> Result<UnlockedIdentity> {
+        let ([public_key, pq_public_key, dilithium_public_key], [ed25519_secret, kyber_secret, dilithium_secret]) =
+            identity::generate_keypairs()?;
+        let mut successor = UnlockedIdentity::from_secrets(
+            QuantumIdentity {
+                public_key,
+                pq_public_key,
+                dilithium_public_key,
+                version: self.identity.version + 1,
+                ..self.identity.clone()
+            },
+            ed25519_secret,
+            kyber_secret,
+            dilithium_secret,
+        );
+        let statement = RotationStatement::sign(self, &successor)?;
+        successor.identity.rotations.push(statement);
+        successor.identity = successor.seal(passphrase)?;
+        Ok(successor)
+    }
+
     /// Sign data with Ed25519
     pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
         use ed25519_dalek::{SigningKey, Signer};
@@ -74,3 +140,102 @@ impl UnlockedIdentity {
         Ok(public_bytes)
     }
 }
+
+impl Drop for UnlockedIdentity {
+    fn drop(&mut self) {
+        for secret in self.secrets() {
+            unpin_memory(secret);
+        }
+        self.ed25519_secret.zeroize();
+        self.kyber_secret.zeroize();
+        self.dilithium_secret.zeroize();
+    }
+}
+
+/// Pinned pages and how many secret buffers use each; `munlock` works on whole pages and is
+/// not reference-counted, so a page stays locked until the last secret on it is unpinned
+#[cfg(unix)]
+static PINNED_PAGES: std::sync::Mutex<std::collections::BTreeMap<usize, usize>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    // SAFETY: sysconf has no preconditions
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+/// Start addresses of the pages a buffer's allocation spans
+#[cfg(unix)]
+fn pages(buffer: &Vec<u8>) -> impl Iterator<Item = usize> {
+    let page = page_size();
+    let start = buffer.as_ptr() as usize;
+    (start / page * page..start + buffer.capacity()).step_by(page)
+}
+
+/// Lock the pages of a secret buffer in RAM; best effort, as RLIMIT_MEMLOCK may be too low
+#[cfg(unix)]
+fn pin_memory(buffer: &Vec<u8>) {
+    if buffer.capacity() == 0 {
+        return;
+    }
+    let mut pinned = PINNED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    for page in pages(buffer) {
+        let count = pinned.entry(page).or_insert(0);
+        *count += 1;
+        // SAFETY: the page holds part of the buffer's own allocation, which outlives the call
+        if *count == 1 && unsafe { libc::mlock(page as *const libc::c_void, page_size()) } != 0 {
+            tracing::debug!("Could not lock secret key memory: {}", std::io::Error::last_os_error());
+        }
+    }
+}
+
+/// Undo `pin_memory` before the buffer is freed, unlocking the pages no other secret uses
+#[cfg(unix)]
+fn unpin_memory(buffer: &Vec<u8>) {
+    if buffer.capacity() == 0 {
+        return;
+    }
+    let mut pinned = PINNED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    for page in pages(buffer) {
+        let Some(count) = pinned.get_mut(&page) else {
+            continue;
+        };
+        *count -= 1;
+        if *count == 0 {
+            pinned.remove(&page);
+            // SAFETY: as in `pin_memory`
+            unsafe {
+                libc::munlock(page as *const libc::c_void, page_size());
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn pin_memory(_buffer: &Vec<u8>) {}
+
+#[cfg(not(unix))]
+fn unpin_memory(_buffer: &Vec<u8>) {}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_pages_stay_pinned() {
+        // Small secrets are usually allocated on the same page
+        let first = vec![1u8; 32];
+        let second = vec![2u8; 32];
+        pin_memory(&first);
+        pin_memory(&second);
+        unpin_memory(&first);
+
+        let pinned = PINNED_PAGES.lock().unwrap();
+        assert!(pages(&second).all(|page| pinned.get(&page).is_some_and(|count| *count >= 1)));
+        drop(pinned);
+        unpin_memory(&second);
+    }
+}
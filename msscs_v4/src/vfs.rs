@@ -1,14 +1,30 @@
 // Virtual File System module
-use crate::block::DataBlock;
+use crate::adaptive_compression::{AdaptiveCompression, CompressionAlgorithm};
+use crate::block::{DataBlock, DataKey, IdentityKey, KeyWrapper, Keyring, WrappedKey};
+use crate::block_store::BlockStore;
+use crate::chunking::Chunking;
 use crate::config::Config;
+use crate::content_addressing::{chunk_list_cid, CASStats, ChunkIndex, ChunkRef, ContentId, ConvergenceKey};
 use crate::error::{MSSCSError, Result};
-use crate::network::Node;
+use crate::namespace::{DirEntry, FileContent, FileStat, FileVersion, Namespace};
+use crate::p2p_network::P2PNodeCommand;
 use crate::persistence::PersistenceManager;
+use crate::snapshot::{SnapshotDiff, SnapshotInfo, SnapshotSet};
+use crate::unlocked_identity::UnlockedIdentity;
+use crate::workspace::Workspace;
 use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
+/// Owner recorded for files written through the local node
+const LOCAL_OWNER: &str = "local";
+
+/// How long a block fetch from the network may take
+const NETWORK_FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
 /// File writing options
 #[derive(Debug, Clone)]
 pub struct FileWriteOptions {
@@ -16,6 +32,13 @@ pub struct FileWriteOptions {
     pub compress: bool,
     pub encrypt: bool,
     pub replication_factor: u32,
+    /// Author recorded in the version history (defaults to the local node)
+    pub author: Option<String>,
+    /// Chunk boundaries (defaults to fixed `chunk_size` chunks)
+    pub chunking: Option<Chunking>,
+    /// Store deduplicated, convergently encrypted chunks instead of a private block chain
+    /// (ignored while attached to the P2P network, which replicates chains)
+    pub dedup: bool,
 }
 
 impl Default for FileWriteOptions {
@@ -25,6 +48,9 @@ impl Default for FileWriteOptions {
             compress: true,
             encrypt: true,
             replication_factor: 3,
+            author: None,
+            chunking: None,
+            dedup: true,
         }
     }
 }
@@ -50,34 +76,178 @@ impl Default for FileReadOptions {
 /// Virtual File System for distributed storage
 pub struct VirtualFileSystem {
     pub local_blocks: HashMap<String, DataBlock>,
-    pub file_manifest: HashMap<String, Uuid>,
-    pub node: Option<Arc<Node>>,
+    pub namespace: Namespace,
+    pub snapshots: SnapshotSet,
+    pub chunks: ChunkIndex,
+    /// Command channel of the P2P node that replicates and fetches blocks
+    pub p2p_command_tx: Option<mpsc::UnboundedSender<P2PNodeCommand>>,
     pub persistence: Arc<PersistenceManager>,
     pub config: Arc<Config>,
+    /// Seals deduplicated chunks; loaded with the identity key on first use
+    convergence_key: Option<ConvergenceKey>,
+    /// Wraps the data keys of chained files and the convergence key (set once an identity is unlocked)
+    /// Retired generations still unwrap older data keys
+    keyring: Option<Keyring>,
+    /// Keys held elsewhere (the identity agent) that wrap and unwrap instead, so they never reach this process
+    held_keys: Option<Arc<dyn KeyWrapper>>,
+    compression: AdaptiveCompression,
 }
 
 impl VirtualFileSystem {
     /// Create new VFS instance
-    pub fn new(config: Arc<Config>, persistence: Arc<PersistenceManager>) -> Result<Self> {
+    pub async fn new(config: Arc<Config>, persistence: Arc<PersistenceManager>) -> Result<Self> {
         // Load existing blocks and manifest from disk
-        let local_blocks = persistence.load_all_blocks()?;
-        let file_manifest = persistence.load_manifest()?;
+        let local_blocks = persistence.load_all_blocks().await?;
+        let namespace = match persistence.load_namespace().await? {
+            Some(namespace) => namespace,
+            None => {
+                // Upgrade the flat manifest written by older versions
+                let manifest = persistence.load_manifest().await?;
+                let mut chains = HashMap::new();
+                for (path, head) in &manifest {
+                    match persistence.measure_chain(*head, CompressionAlgorithm::Huffman).await {
+                        Ok(stats) => {
+                            chains.insert(*head, stats);
+                        }
+                        Err(e) => tracing::warn!("Cannot measure migrated file '{}': {}", path, e),
+                    }
+                }
+                let namespace = Namespace::from_flat(&manifest, &chains, LOCAL_OWNER, CompressionAlgorithm::Huffman)?;
+                if !manifest.is_empty() {
+                    tracing::info!("Migrated {} files from flat manifest to directory tree", manifest.len());
+                    persistence.save_namespace(&namespace).await?;
+                }
+                namespace
+            }
+        };
         
-        tracing::info!("VFS initialized with {} blocks and {} files", 
-            local_blocks.len(), file_manifest.len());
+        let snapshots = persistence.load_snapshots().await?;
+        let chunks = persistence.load_chunk_index().await?;
+        
+        tracing::info!("VFS initialized with {} blocks, {} chunks, {} files and {} snapshots", 
+            local_blocks.len(), chunks.len(), namespace.file_count(), snapshots.len());
         
         Ok(VirtualFileSystem {
             local_blocks,
-            file_manifest,
-            node: None,
+            namespace,
+            snapshots,
+            chunks,
+            p2p_command_tx: None,
             persistence,
             config,
+            convergence_key: None,
+            keyring: None,
+            held_keys: None,
+            compression: AdaptiveCompression::default(),
         })
     }
     
-    /// Associate VFS with network node
-    pub fn set_node(&mut self, node: Arc<Node>) {
-        self.node = Some(node);
+    /// Create new VFS instance on top of any block store backend
+    pub async fn with_store(config: Arc<Config>, store: Arc<dyn BlockStore>) -> Result<Self> {
+        let persistence = Arc::new(PersistenceManager::with_store(config.data_dir.clone(), store)?);
+        Self::new(config, persistence).await
+    }
+    
+    /// Attach the VFS to a P2P node and announce the blocks already on disk, so data
+    /// written before the switch from the legacy TCP transport stays reachable
+    /// The node must serve this VFS's block store (`P2PConfig::block_store`); only ids are sent
+    pub fn set_network(&mut self, p2p_command_tx: mpsc::UnboundedSender<P2PNodeCommand>) {
+        let mut announced = 0;
+        for block in self.local_blocks.values() {
+            if p2p_command_tx.send(P2PNodeCommand::ProvideBlock { block_id: block.uuid }).is_ok() {
+                announced += 1;
+            }
+        }
+        for cid in self.chunks.cids() {
+            if p2p_command_tx.send(P2PNodeCommand::ProvideChunk { cid: cid.to_string() }).is_ok() {
+                announced += 1;
+            }
+        }
+        if announced > 0 {
+            tracing::info!("Announced {} existing blocks and chunks on the P2P network", announced);
+        }
+        self.p2p_command_tx = Some(p2p_command_tx);
+    }
+    
+    /// Encrypt chained files under data keys wrapped by `identity`
+    /// Required before writing or reading files
+    pub fn set_identity(&mut self, identity: &UnlockedIdentity) {
+        self.keyring = Some(Keyring::new(IdentityKey::from_identity(identity)));
+        self.held_keys = None;
+    }
+    
+    /// Keep reading files whose data keys a retired key generation of the identity wrapped
+    pub fn add_retired_identity(&mut self, identity: &UnlockedIdentity) {
+        let key = IdentityKey::from_identity(identity);
+        if let Some(keyring) = self.keyring.as_mut().filter(|keyring| !keyring.retired.contains(&key)) {
+            keyring.retired.push(key);
+        }
+    }
+    
+    /// Wrap and unwrap data keys through `keys`, typically the identity agent, instead of an
+    /// identity unlocked in this process
+    pub fn set_keys(&mut self, keys: Arc<dyn KeyWrapper>) {
+        self.held_keys = Some(keys);
+        self.keyring = None;
+    }
+    
+    /// Keys that wrap the data keys of chained files
+    fn keys(&self) -> Result<Arc<dyn KeyWrapper>> {
+        if let Some(keys) = &self.held_keys {
+            return Ok(keys.clone());
+        }
+        match &self.keyring {
+            Some(keyring) => Ok(Arc::new(keyring.clone())),
+            None => Err(MSSCSError::Crypto(
+                "Block chains and deduplicated chunks need an unlocked identity (VirtualFileSystem::set_identity)".to_string(),
+            )),
+        }
+    }
+    
+    /// Unwrap a file's data key with the current or a retired identity key
+    async fn unwrap_data_key(&self, wrapped: &WrappedKey) -> Result<DataKey> {
+        DataKey::unwrap_with(wrapped, self.keys()?.as_ref()).await
+    }
+    
+    /// Scope deduplication to a workspace by sealing chunks with its key
+    /// Only possible before the first content-addressed chunk is written
+    pub async fn set_convergence_key(&mut self, key: ConvergenceKey) -> Result<()> {
+        if !self.chunks.is_empty() {
+            if key == self.convergence_key().await? {
+                return Ok(());
+            }
+            return Err(MSSCSError::InvalidData(
+                "Cannot change the convergence key of a store that already holds chunks".to_string(),
+            ));
+        }
+        
+        self.persistence.save_convergence_key(&key, self.keys()?.as_ref()).await?;
+        self.convergence_key = Some(key);
+        Ok(())
+    }
+    
+    /// Seal deduplicated chunks with a workspace's convergence key
+    pub async fn use_workspace(&mut self, workspace: &Workspace) -> Result<()> {
+        let key = workspace.convergence_key(self.keys()?.as_ref()).await?;
+        self.set_convergence_key(key).await
+    }
+    
+    /// Key sealing deduplicated chunks, unwrapped by the identity key
+    async fn convergence_key(&mut self) -> Result<ConvergenceKey> {
+        if let Some(key) = &self.convergence_key {
+            return Ok(key.clone());
+        }
+        let key = self
+            .persistence
+            .load_or_create_convergence_key(self.keys()?.as_ref())
+            .await?;
+        self.convergence_key = Some(key.clone());
+        Ok(key)
+    }
+    
+    /// Chunk count, stored bytes and bytes saved by deduplication
+    pub fn dedup_stats(&self) -> CASStats {
+        self.chunks.stats()
     }
     
     /// Write file to distributed storage
@@ -101,6 +271,11 @@ impl VirtualFileSystem {
         ).await
     }
 
+    /// Write file with explicit options (chunking, compression, author)
+    pub async fn write_file_with_options(&mut self, path: &Path, data: &[u8], options: FileWriteOptions) -> Result<Uuid> {
+        self.write_file_with_options_internal(path, data, None, options).await
+    }
+
     /// Enhanced write file with options and progress callback (for new API)
     async fn write_file_with_options_internal(
         &mut self,
@@ -112,47 +287,131 @@ impl VirtualFileSystem {
         let path_str = path.to_string_lossy().to_string();
         tracing::info!("Writing file '{}' ({} bytes) with options", path_str, data.len());
 
-        let chunk_size = options.chunk_size.unwrap_or(self.config.chunk_size as u64) as usize;
+        let chunking = options.chunking.unwrap_or(Chunking::Fixed {
+            size: options.chunk_size.unwrap_or(self.config.chunk_size as u64) as usize,
+        });
+        chunking.validate()?;
 
-        // Compress data first using Huffman if enabled
-        let processed_data = if options.compress {
-            tracing::debug!("Compressing data with Huffman algorithm");
-            let mut compressor = crate::huffman::HuffmanCompressor::new();
-            compressor.compress(data)?
+        let content = if options.dedup {
+            self.write_chunks(data, &chunking, options.compress, &progress_callback).await?
         } else {
-            data.to_vec()
+            self.write_chain(data, &chunking, options.compress, &progress_callback).await?
         };
+        let head = content.head;
+        let chunk_count = content.block_count;
+        let new_chunks = !content.chunks.is_empty();
+        
+        let author = options.author.as_deref().unwrap_or(LOCAL_OWNER);
+        self.namespace.write_file(&path_str, content, author)?;
+        // The index goes first: a crash in between can only leak a reference, never lose a live chunk
+        if new_chunks {
+            self.persistence.save_chunk_index(&self.chunks).await?;
+        }
+        self.persistence.save_namespace(&self.namespace).await?;
+
+        tracing::info!("File '{}' written successfully with {} chunks", path_str, chunk_count);
+        Ok(head) // Return first chunk ID as file ID
+    }
+
+    /// Seal every chunk with the convergence key and store the ones not already present
+    async fn write_chunks(
+        &mut self,
+        data: &[u8],
+        chunking: &Chunking,
+        compress: bool,
+        progress_callback: &Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<FileContent> {
+        let convergence_key = self.convergence_key().await?;
+        let pieces = chunking.split(data);
+        let mut chunks = Vec::with_capacity(pieces.len());
+        let mut stored = 0;
+        let mut bytes_processed = 0u64;
+        for piece in pieces {
+            let (payload, compression) = if compress {
+                self.compression.compress(piece)?
+            } else {
+                (piece.to_vec(), CompressionAlgorithm::None)
+            };
+            let sealed = convergence_key.seal(&payload)?;
+            let cid = ContentId::from_data(&sealed).to_hex();
+            
+            if self.chunks.add_ref(&cid, sealed.len()) {
+                self.persistence.save_chunk(&cid, &sealed).await?;
+                self.replicate_chunk(&cid)?;
+                stored += 1;
+            }
+            chunks.push(ChunkRef {
+                cid,
+                size: piece.len() as u64,
+                compression,
+            });
+            
+            bytes_processed += piece.len() as u64;
+            if let Some(ref callback) = progress_callback {
+                callback(bytes_processed, data.len() as u64);
+            }
+        }
+        tracing::debug!("Stored {} new chunks, {} deduplicated", stored, chunks.len() - stored);
+        
+        // The version is identified by its chunk list
+        let root_hash = *chunk_list_cid(&chunks).as_bytes();
+        let head = Uuid::from_slice(&root_hash[..16])?;
+        Ok(FileContent {
+            head,
+            size: data.len() as u64,
+            block_count: chunks.len(),
+            compression: CompressionAlgorithm::None,
+            compressed_per_block: false,
+            root_hash,
+            chunks,
+        })
+    }
+
+    /// Write the file as a private chain of blocks (replicated to P2P peers)
+    async fn write_chain(
+        &mut self,
+        data: &[u8],
+        chunking: &Chunking,
+        compress: bool,
+        progress_callback: &Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<FileContent> {
+        // One data key per file, wrapped by the identity key and stored on every block
+        let data_key = DataKey::generate_with(self.keys()?.as_ref()).await?;
 
-        let total_chunks = (processed_data.len() as f64 / chunk_size as f64).ceil() as usize;
+        // Chunk boundaries are found in the plain data, then each chunk is compressed on its
+        // own; empty files still get a single (empty) block so they have a head
+        let pieces = chunking.split(data);
+        let total_chunks = pieces.len();
         let mut chunk_ids = Vec::new();
         let mut previous_hash = [0u8; 32];
         let mut previous_uuid: Option<Uuid> = None;
         let mut bytes_processed = 0u64;
 
         // Process chunks in reverse order for cryptographic chaining
-        for chunk_index in (0..total_chunks).rev() {
-            let start = chunk_index * chunk_size;
-            let end = std::cmp::min(start + chunk_size, processed_data.len());
-            let chunk_data = &processed_data[start..end];
+        for (chunk_index, &chunk_data) in pieces.iter().enumerate().rev() {
+            let payload = if compress {
+                crate::huffman::compress(chunk_data)?
+            } else {
+                chunk_data.to_vec()
+            };
 
             // Create data block with enhanced features
             let block = DataBlock::new(
-                chunk_data,
+                &payload,
                 chunk_index as u64,
                 previous_uuid, // Link to previous block in chain
                 previous_hash,
+                &data_key,
             )?;
 
             // Add to local storage
             self.local_blocks.insert(block.uuid.to_string(), block.clone());
 
             // Persist to disk
-            self.persistence.save_block(&block)?;
+            self.persistence.save_block(&block).await?;
 
             // Replicate to peers if enabled
-            if let Some(node) = &self.node {
-                node.replicate_block(&block).await?;
-            }
+            self.replicate_block(&block)?;
 
             // Update chain links for next iteration
             previous_hash = block.calculate_hash()?;
@@ -163,16 +422,25 @@ impl VirtualFileSystem {
             bytes_processed += chunk_data.len() as u64;
 
             if let Some(ref callback) = progress_callback {
-                callback(bytes_processed, processed_data.len() as u64);
+                callback(bytes_processed, data.len() as u64);
             }
         }
 
-        // Store file manifest
-        self.file_manifest.insert(path_str.clone(), Uuid::parse_str(&chunk_ids[0])?);
-        self.persistence.save_manifest(&self.file_manifest)?;
-
-        tracing::info!("File '{}' written successfully with {} chunks", path_str, total_chunks);
-        Ok(Uuid::parse_str(&chunk_ids[0])?) // Return first chunk ID as file ID
+        // Chunks were created back to front, so the head of the chain is chunk 0
+        let head = Uuid::parse_str(chunk_ids.last().expect("at least one chunk"))?;
+        Ok(FileContent {
+            head,
+            size: data.len() as u64,
+            block_count: total_chunks,
+            compression: if compress {
+                CompressionAlgorithm::Huffman
+            } else {
+                CompressionAlgorithm::None
+            },
+            compressed_per_block: compress,
+            root_hash: previous_hash,
+            chunks: Vec::new(),
+        })
     }
 
     /// Enhanced read file with options and progress callback (for new API)
@@ -187,8 +455,8 @@ impl VirtualFileSystem {
         // For now, use the standard read_file method
         // Full implementation would require FileMetadata struct
         let uuid = Uuid::parse_str(file_id)?;
-        let block = self.get_block(&uuid).await?;
-        let data = block.decode(block.node_index)?;
+        let block = self.get_block(&uuid, None).await?;
+        let data = block.decode_with(block.node_index, &self.unwrap_data_key(&block.wrapped_key).await?)?;
 
         tracing::info!("File '{}' read successfully ({} bytes)", file_id, data.len());
         Ok(data)
@@ -209,77 +477,407 @@ impl VirtualFileSystem {
         tracing::info!("Reading file '{}'", path_str);
         
         // Look up first block UUID
-        let first_uuid = self.file_manifest.get(&path_str)
-            .ok_or_else(|| MSSCSError::NotFound(format!("File '{}' not found", path_str)))?;
+        let inode = self.namespace.get_file(&path_str)?;
+        let compression = inode.compression;
+        let per_block = inode.compressed_per_block;
+        let chunks = inode.chunks.clone();
+        let root_hash = inode.head_hash();
+        let head = inode.head
+            .ok_or_else(|| MSSCSError::NotFound(format!("File '{}' has no data", path_str)))?;
+        
+        let file_data = self.read_content(head, root_hash, compression, per_block, &chunks, &mut progress_callback).await?;
+        
+        tracing::info!("File '{}' read successfully ({} bytes)", path_str, file_data.len());
+        Ok(file_data)
+    }
+    
+    /// Read `length` bytes starting at `offset` (clamped to the end of the file)
+    /// Chunked files only fetch the chunks overlapping the range
+    pub async fn read_range(&mut self, path: &Path, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let path_str = path.to_string_lossy().to_string();
+        let inode = self.namespace.get_file(&path_str)?;
+        if inode.chunks.is_empty() {
+            // Chains have no chunk index, so the file is read in full; its length is taken from
+            // the data since files imported from a flat namespace record no size
+            let data = self.read_file(path).await?;
+            let end = offset.saturating_add(length).min(data.len() as u64);
+            if offset >= end {
+                return Ok(Vec::new());
+            }
+            return Ok(data[offset as usize..end as usize].to_vec());
+        }
+        
+        let chunks = inode.chunks.clone();
+        let size: u64 = chunks.iter().map(|chunk| chunk.size).sum();
+        let end = offset.saturating_add(length).min(size);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        let convergence_key = self.convergence_key().await?;
+        let mut out = Vec::with_capacity((end - offset) as usize);
+        let mut chunk_start = 0u64;
+        for chunk in &chunks {
+            let chunk_end = chunk_start + chunk.size;
+            if chunk_end > offset && chunk_start < end {
+                let data = self.read_chunk(chunk, &convergence_key).await?;
+                let from = offset.saturating_sub(chunk_start) as usize;
+                let to = (end.min(chunk_end) - chunk_start) as usize;
+                out.extend_from_slice(&data[from..to]);
+            }
+            if chunk_end >= end {
+                break;
+            }
+            chunk_start = chunk_end;
+        }
+        
+        tracing::debug!("Read bytes {}..{} of '{}'", offset, end, path_str);
+        Ok(out)
+    }
+    
+    /// Read a specific version of a file
+    pub async fn read_version(&mut self, path: &Path, version: u64) -> Result<Vec<u8>> {
+        let path_str = path.to_string_lossy().to_string();
+        let entry = self.namespace.version(&path_str, version)?;
+        
+        let file_data = self.read_content(
+            entry.head, entry.head_hash(), entry.compression, entry.compressed_per_block, &entry.chunks, &mut |_, _| {},
+        ).await?;
+        
+        tracing::info!("Version {} of '{}' read successfully ({} bytes)", version, path_str, file_data.len());
+        Ok(file_data)
+    }
+    
+    /// Version history of a file, oldest first
+    pub fn list_versions(&self, path: &Path) -> Result<Vec<FileVersion>> {
+        self.namespace.versions(&path.to_string_lossy())
+    }
+    
+    /// Make an old version current again (recorded as a new version)
+    pub async fn restore_version(&mut self, path: &Path, version: u64) -> Result<FileVersion> {
+        let path_str = path.to_string_lossy().to_string();
+        let restored = self.namespace.restore_version(&path_str, version, LOCAL_OWNER)?;
+        if !restored.chunks.is_empty() {
+            self.chunks.retain_all(restored.chunks.iter().map(|chunk| chunk.cid.as_str()));
+            self.persistence.save_chunk_index(&self.chunks).await?;
+        }
+        self.persistence.save_namespace(&self.namespace).await?;
+        
+        tracing::info!("Restored version {} of '{}' as version {}", version, path_str, restored.version);
+        Ok(restored)
+    }
+    
+    /// Apply the retention policy, then delete zero-ref chunks and blocks no retained version references
+    pub async fn cleanup_orphaned_blocks(&mut self) -> Result<usize> {
+        let before: Vec<String> = self.namespace.chunk_refs().into_iter().map(str::to_string).collect();
+        let dropped = self.namespace.apply_retention(&self.config.retention);
+        if dropped > 0 {
+            tracing::info!("Retention policy dropped {} old versions", dropped);
+            self.persistence.save_namespace(&self.namespace).await?;
+            
+            self.chunks.release_all(before.iter().map(String::as_str));
+            self.chunks.retain_all(self.namespace.chunk_refs());
+        }
+        
+        // Chunks sealed before a crash may never have reached the index
+        self.chunks.garbage_collect();
+        let stale_chunks: Vec<String> = self.persistence.list_chunks().await?
+            .into_iter()
+            .filter(|cid| !self.chunks.contains(cid))
+            .collect();
+        self.persistence.delete_chunks(&stale_chunks).await?;
+        self.persistence.save_chunk_index(&self.chunks).await?;
+        
+        // Snapshot heads are GC roots alongside the live tree
+        let mut heads = self.namespace.referenced_heads();
+        heads.extend(self.snapshots.referenced_heads());
+        let deleted = self.persistence.cleanup_unreferenced_blocks(&heads).await?;
+        for key in &deleted {
+            self.local_blocks.remove(key);
+        }
+        
+        tracing::info!("Removed {} unreferenced chunks and {} orphaned blocks", stale_chunks.len(), deleted.len());
+        Ok(stale_chunks.len() + deleted.len())
+    }
+    
+    /// Freeze the current tree as a named read-only snapshot
+    pub async fn create_snapshot(&mut self, name: &str) -> Result<SnapshotInfo> {
+        let info = self.snapshots.create(name, &self.namespace, LOCAL_OWNER)?;
+        self.chunks.retain_all(self.snapshots.get(name)?.chunk_refs());
+        self.persistence.save_chunk_index(&self.chunks).await?;
+        self.persistence.save_snapshots(&self.snapshots).await?;
+        
+        tracing::info!("Snapshot '{}' created ({} files)", name, info.file_count);
+        Ok(info)
+    }
+    
+    /// Delete a snapshot; its blocks become eligible for orphan cleanup
+    pub async fn delete_snapshot(&mut self, name: &str) -> Result<()> {
+        let released: Vec<String> = self.snapshots.get(name)?.chunk_refs().into_iter().map(str::to_string).collect();
+        self.snapshots.delete(name)?;
+        self.persistence.save_snapshots(&self.snapshots).await?;
+        
+        self.chunks.release_all(released.iter().map(String::as_str));
+        self.persistence.save_chunk_index(&self.chunks).await?;
+        
+        tracing::info!("Snapshot '{}' deleted", name);
+        Ok(())
+    }
+    
+    /// All snapshots, oldest first
+    pub fn list_snapshots(&self) -> Vec<SnapshotInfo> {
+        self.snapshots.list()
+    }
+    
+    /// List a directory inside a snapshot
+    pub fn snapshot_list_dir(&self, name: &str, path: &Path, recursive: bool) -> Result<Vec<DirEntry>> {
+        self.snapshots.get(name)?.list(&path.to_string_lossy(), recursive)
+    }
+    
+    /// Stat a path inside a snapshot
+    pub fn snapshot_stat(&self, name: &str, path: &Path) -> Result<FileStat> {
+        self.snapshots.get(name)?.stat(&path.to_string_lossy())
+    }
+    
+    /// Read a file as it was when the snapshot was taken
+    pub async fn read_snapshot_file(&mut self, name: &str, path: &Path) -> Result<Vec<u8>> {
+        let path_str = path.to_string_lossy().to_string();
+        let (head, root_hash, compression, per_block, chunks) = {
+            let inode = self.snapshots.get(name)?.get_file(&path_str)?;
+            let head = inode.head
+                .ok_or_else(|| MSSCSError::NotFound(format!("File '{}' has no data", path_str)))?;
+            (head, inode.head_hash(), inode.compression, inode.compressed_per_block, inode.chunks.clone())
+        };
+        
+        self.read_content(head, root_hash, compression, per_block, &chunks, &mut |_, _| {}).await
+    }
+    
+    /// Paths added, removed or modified between two snapshots (`to` = None compares with the live tree)
+    pub fn diff_snapshots(&self, from: &str, to: Option<&str>) -> Result<SnapshotDiff> {
+        self.snapshots.diff(from, to, &self.namespace)
+    }
+    
+    /// Read a file version from its chunks, or from its chain if it has none
+    async fn read_content<F>(
+        &mut self,
+        head: Uuid,
+        root_hash: Option<[u8; 32]>,
+        compression: CompressionAlgorithm,
+        per_block: bool,
+        chunks: &[ChunkRef],
+        progress_callback: &mut F,
+    ) -> Result<Vec<u8>>
+    where
+        F: FnMut(usize, usize),
+    {
+        if chunks.is_empty() {
+            return self.read_chain(head, root_hash, compression, per_block, progress_callback).await;
+        }
+        
+        let convergence_key = self.convergence_key().await?;
+        let mut file_data = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            file_data.extend_from_slice(&self.read_chunk(chunk, &convergence_key).await?);
+            progress_callback(i + 1, chunks.len());
+        }
+        Ok(file_data)
+    }
+    
+    /// Load, verify, open and decompress one content-addressed chunk
+    async fn read_chunk(&self, chunk: &ChunkRef, convergence_key: &ConvergenceKey) -> Result<Vec<u8>> {
+        let (sealed, fetched) = match self.persistence.load_chunk(&chunk.cid).await {
+            Ok(sealed) => (sealed, false),
+            Err(MSSCSError::NotFound(_)) if self.p2p_command_tx.is_some() => (self.fetch_chunk(&chunk.cid).await?, true),
+            Err(e) => return Err(e),
+        };
+        if ContentId::from_data(&sealed).to_hex() != chunk.cid {
+            return Err(MSSCSError::InvalidData(format!("Chunk {} failed CID verification", chunk.cid)));
+        }
+        if fetched {
+            self.persistence.save_chunk(&chunk.cid, &sealed).await?;
+        }
+        
+        let payload = convergence_key.open(&sealed)?;
+        let data = self.compression.decompress(&payload, chunk.compression)?;
+        if data.len() as u64 != chunk.size {
+            return Err(MSSCSError::InvalidData(format!(
+                "Chunk {} has {} bytes, expected {}", chunk.cid, data.len(), chunk.size
+            )));
+        }
+        Ok(data)
+    }
+    
+    /// Fetch, decode and decompress the chain starting at `head`
+    /// Each block is checked against the hash held by the one before it, starting from `root_hash`
+    /// `per_block` chains are decompressed block by block, older ones as a single stream
+    async fn read_chain<F>(
+        &mut self,
+        head: Uuid,
+        root_hash: Option<[u8; 32]>,
+        compression: CompressionAlgorithm,
+        per_block: bool,
+        progress_callback: &mut F,
+    ) -> Result<Vec<u8>>
+    where
+        F: FnMut(usize, usize),
+    {
+        let mut current_uuid = head;
+        let mut expected_hash = root_hash;
         
         // Collect all blocks in chain
         let mut blocks = Vec::new();
-        let mut current_uuid = *first_uuid;
         
         loop {
             // Get block (local or network)
-            let block = self.get_block(&current_uuid).await?;
+            let block = self.get_block(&current_uuid, expected_hash).await?;
             blocks.push(block.clone());
             
             // Follow chain
             if let Some(prev_uuid) = block.previous_uuid {
                 current_uuid = prev_uuid;
+                expected_hash = Some(block.previous_hash);
             } else {
                 break; // Genesis block reached
             }
         }
         
         let total_blocks = blocks.len();
-        tracing::debug!("Retrieved {} blocks for file '{}'", total_blocks, path_str);
+        tracing::debug!("Retrieved {} blocks for chain {}", total_blocks, head);
         
-        // Decode and concatenate data with progress
+        // Decode and concatenate data with progress (blocks of one file share a data key)
+        let mut data_key: Option<DataKey> = None;
         let mut file_data = Vec::new();
         for (i, block) in blocks.iter().enumerate() {
-            let chunk_data = block.decode(block.node_index)?;
-            file_data.extend_from_slice(&chunk_data);
+            let key = match data_key.take() {
+                Some(key) if key.wrapped() == &block.wrapped_key => key,
+                _ => self.unwrap_data_key(&block.wrapped_key).await?,
+            };
+            let chunk_data = block.decode_with(block.node_index, &key)?;
+            data_key = Some(key);
+            if per_block && compression == CompressionAlgorithm::Huffman {
+                file_data.extend_from_slice(&crate::huffman::decompress(&chunk_data)?);
+            } else {
+                file_data.extend_from_slice(&chunk_data);
+            }
             
             // Report progress
             progress_callback(i + 1, total_blocks);
         }
         
-        tracing::info!("File '{}' read successfully ({} bytes)", path_str, file_data.len());
-        Ok(file_data)
+        match compression {
+            CompressionAlgorithm::Huffman if !per_block => crate::huffman::decompress(&file_data),
+            _ => Ok(file_data),
+        }
     }
     
+    /// Push `block` to `replication_factor` peers (no-op without a P2P node)
+    fn replicate_block(&self, block: &DataBlock) -> Result<()> {
+        let Some(tx) = &self.p2p_command_tx else {
+            return Ok(());
+        };
+        let (reply, rx) = tokio::sync::oneshot::channel();
+        tx.send(P2PNodeCommand::ReplicateBlock {
+            block_id: block.uuid,
+            replicas: self.config.replication_factor,
+            reply,
+        })
+        .map_err(|_| MSSCSError::Network("P2P node stopped".to_string()))?;
+
+        let block_id = block.uuid;
+        tokio::spawn(async move {
+            match rx.await {
+                Ok(Ok(accepted)) => tracing::debug!("Block {} replicated to {} peers", block_id, accepted),
+                Ok(Err(e)) => tracing::warn!("Failed to replicate block {}: {}", block_id, e),
+                Err(_) => {}
+            }
+        });
+        Ok(())
+    }
+
+    /// Push chunk `cid` to `replication_factor` peers (no-op without a P2P node)
+    fn replicate_chunk(&self, cid: &str) -> Result<()> {
+        let Some(tx) = &self.p2p_command_tx else {
+            return Ok(());
+        };
+        let (reply, rx) = tokio::sync::oneshot::channel();
+        tx.send(P2PNodeCommand::ReplicateChunk {
+            cid: cid.to_string(),
+            replicas: self.config.replication_factor,
+            reply,
+        })
+        .map_err(|_| MSSCSError::Network("P2P node stopped".to_string()))?;
+
+        let cid = cid.to_string();
+        tokio::spawn(async move {
+            match rx.await {
+                Ok(Ok(accepted)) => tracing::debug!("Chunk {} replicated to {} peers", cid, accepted),
+                Ok(Err(e)) => tracing::warn!("Failed to replicate chunk {}: {}", cid, e),
+                Err(_) => {}
+            }
+        });
+        Ok(())
+    }
+
+    /// Fetch a sealed chunk from its providers (the node checks it against `cid`)
+    async fn fetch_chunk(&self, cid: &str) -> Result<Vec<u8>> {
+        let tx = self.p2p_command_tx.as_ref()
+            .ok_or_else(|| MSSCSError::Network("No P2P node attached".to_string()))?;
+        let (reply, rx) = tokio::sync::oneshot::channel();
+        tx.send(P2PNodeCommand::GetChunk { cid: cid.to_string(), reply })
+            .map_err(|_| MSSCSError::Network("P2P node stopped".to_string()))?;
+        match tokio::time::timeout(NETWORK_FETCH_TIMEOUT, rx).await {
+            Ok(Ok(Ok(sealed))) => {
+                tracing::info!("Retrieved chunk {} from the network", cid);
+                Ok(sealed)
+            }
+            Ok(Ok(Err(e))) => Err(MSSCSError::NotFound(format!("Chunk {} not found locally or on network: {}", cid, e))),
+            Ok(Err(_)) => Err(MSSCSError::Network(format!("P2P node dropped request for chunk {}", cid))),
+            Err(_) => Err(MSSCSError::Network(format!("Timed out fetching chunk {} from network", cid))),
+        }
+    }
+
     /// Get block from local storage or network
-    async fn get_block(&mut self, uuid: &Uuid) -> Result<DataBlock> {
+    /// With `expected_hash`, copies that do not hash to it are ignored and never cached
+    async fn get_block(&mut self, uuid: &Uuid, expected_hash: Option<[u8; 32]>) -> Result<DataBlock> {
+        let trusted = |block: &DataBlock| expected_hash.is_none_or(|hash| block.matches_hash(&hash));
+        
         // Check local storage first
-        if let Some(block) = self.local_blocks.get(&uuid.to_string()) {
+        if let Some(block) = self.local_blocks.get(&uuid.to_string()).filter(|block| trusted(block)) {
             return Ok(block.clone());
         }
         
         // Try to load from disk
-        if let Ok(block) = self.persistence.load_block(uuid) {
-            self.local_blocks.insert(uuid.to_string(), block.clone());
-            return Ok(block);
+        if let Ok(block) = self.persistence.load_block(uuid).await {
+            if trusted(&block) {
+                self.local_blocks.insert(uuid.to_string(), block.clone());
+                return Ok(block);
+            }
+            tracing::warn!("Stored block {} does not match its chain link", uuid);
         }
         
-        // Query network peers
-        if let Some(node) = &self.node {
-            let peers = node.peers.read().await;
-            
-            for peer_addr in peers.iter() {
-                match node.get_block_from_peer(peer_addr, uuid).await {
-                    Ok(Some(block)) => {
-                        tracing::info!("Retrieved block {} from peer {}", uuid, peer_addr);
-                        
-                        // Cache locally
-                        self.local_blocks.insert(uuid.to_string(), block.clone());
-                        self.persistence.save_block(&block)?;
-                        
-                        return Ok(block);
-                    }
-                    Ok(None) => continue,
-                    Err(e) => {
-                        tracing::warn!("Failed to get block from {}: {}", peer_addr, e);
-                        continue;
+        // Ask the P2P network (provider lookup, then streamed from a provider)
+        if let Some(tx) = &self.p2p_command_tx {
+            let (reply, rx) = tokio::sync::oneshot::channel();
+            tx.send(P2PNodeCommand::GetBlock { block_id: uuid.to_string(), expected_hash, reply })
+                .map_err(|_| MSSCSError::Network("P2P node stopped".to_string()))?;
+            match tokio::time::timeout(NETWORK_FETCH_TIMEOUT, rx).await {
+                Ok(Ok(Ok(data))) => {
+                    let block: DataBlock = bincode::deserialize(&data)?;
+                    if block.uuid != *uuid || !trusted(&block) {
+                        return Err(MSSCSError::CorruptedData(format!(
+                            "Block {} from the network does not match its chain link", uuid
+                        )));
                     }
+                    tracing::info!("Retrieved block {} from the network", uuid);
+
+                    // Cache locally
+                    self.local_blocks.insert(uuid.to_string(), block.clone());
+                    self.persistence.save_block(&block).await?;
+
+                    return Ok(block);
                 }
+                Ok(Ok(Err(e))) => tracing::warn!("Failed to get block {} from network: {}", uuid, e),
+                Ok(Err(_)) => tracing::warn!("P2P node dropped request for block {}", uuid),
+                Err(_) => tracing::warn!("Timed out fetching block {} from network", uuid),
             }
         }
         
@@ -291,18 +889,63 @@ impl VirtualFileSystem {
         let path_str = path.to_string_lossy().to_string();
         tracing::info!("Deleting file '{}'", path_str);
         
-        self.file_manifest.remove(&path_str)
-            .ok_or_else(|| MSSCSError::NotFound(format!("File '{}' not found", path_str)))?;
+        let inode = self.namespace.remove_file(&path_str)?;
+        self.persistence.save_namespace(&self.namespace).await?;
         
-        self.persistence.save_manifest(&self.file_manifest)?;
+        // Released chunks are reclaimed by the next cleanup
+        if inode.chunk_refs().next().is_some() {
+            self.chunks.release_all(inode.chunk_refs());
+            self.persistence.save_chunk_index(&self.chunks).await?;
+        }
         
         tracing::info!("File '{}' deleted from manifest", path_str);
         Ok(())
     }
     
+    /// Create a directory (and any missing parents)
+    pub async fn mkdir(&mut self, path: &Path) -> Result<()> {
+        let path_str = path.to_string_lossy().to_string();
+        self.namespace.mkdir(&path_str, LOCAL_OWNER, true)?;
+        self.persistence.save_namespace(&self.namespace).await?;
+        
+        tracing::info!("Directory '{}' created", path_str);
+        Ok(())
+    }
+    
+    /// Remove an empty directory
+    pub async fn rmdir(&mut self, path: &Path) -> Result<()> {
+        let path_str = path.to_string_lossy().to_string();
+        self.namespace.rmdir(&path_str)?;
+        self.persistence.save_namespace(&self.namespace).await?;
+        
+        tracing::info!("Directory '{}' removed", path_str);
+        Ok(())
+    }
+    
+    /// Rename or move a file or directory (blocks are left untouched)
+    pub async fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        let from_str = from.to_string_lossy().to_string();
+        let to_str = to.to_string_lossy().to_string();
+        self.namespace.rename(&from_str, &to_str)?;
+        self.persistence.save_namespace(&self.namespace).await?;
+        
+        tracing::info!("Renamed '{}' to '{}'", from_str, to_str);
+        Ok(())
+    }
+    
+    /// List a directory, optionally recursively
+    pub fn list_dir(&self, path: &Path, recursive: bool) -> Result<Vec<DirEntry>> {
+        self.namespace.list(&path.to_string_lossy(), recursive)
+    }
+    
+    /// Size, block count, timestamps and owner of a file or directory
+    pub fn stat(&self, path: &Path) -> Result<FileStat> {
+        self.namespace.stat(&path.to_string_lossy())
+    }
+    
     /// List all files in manifest
     pub fn list_files(&self) -> Vec<String> {
-        self.file_manifest.keys().cloned().collect()
+        self.namespace.files().into_iter().map(|(path, _)| path).collect()
     }
     
     /// Get block count
@@ -319,43 +962,25 @@ impl VirtualFileSystem {
     
     /// Get metadata for all files
     pub fn get_all_metadata(&self) -> HashMap<String, FileMetadata> {
-        let mut result = HashMap::new();
-        for (path, uuid) in &self.file_manifest {
-            // Count blocks for this file
-            let blocks: Vec<_> = self.local_blocks.values()
-                .filter(|b| b.uuid == *uuid)
-                .collect();
-            
-            let size = blocks.iter()
-                .map(|b| b.get_encrypted_size() as u64)
-                .sum();
-            
-            result.insert(path.clone(), FileMetadata {
-                uuid: *uuid,
-                size,
-                blocks: blocks.len(),
-            });
-        }
-        result
+        self.namespace.files().into_iter()
+            .filter_map(|(path, inode)| {
+                Some((path, FileMetadata {
+                    uuid: inode.head?,
+                    size: inode.size,
+                    blocks: inode.block_count,
+                }))
+            })
+            .collect()
     }
     
     /// Get metadata for a specific file
     pub fn get_file_metadata(&self, path: &str) -> Option<FileMetadata> {
-        let uuid = self.file_manifest.get(path)?;
-        
-        // Count blocks for this file
-        let blocks: Vec<_> = self.local_blocks.values()
-            .filter(|b| b.uuid == *uuid)
-            .collect();
-        
-        let size = blocks.iter()
-            .map(|b| b.get_encrypted_size() as u64)
-            .sum();
+        let inode = self.namespace.get_file(path).ok()?;
         
         Some(FileMetadata {
-            uuid: *uuid,
-            size,
-            blocks: blocks.len(),
+            uuid: inode.head?,
+            size: inode.size,
+            blocks: inode.block_count,
         })
     }
 }
@@ -1,7 +1,10 @@
 // WORKSPACE COLLABORATION SYSTEM
 // Sistema de workspaces colaborativos com compartilhamento P2P
 
+use crate::block::{KeyWrapper, WrappedKey};
+use crate::content_addressing::ConvergenceKey;
 use crate::error::{MSSCSError, Result};
+use crate::key_rotation::{IdentityKeys, RotationChain};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -42,6 +45,9 @@ pub struct WorkspaceMember {
     pub permission: Permission,
     pub joined_at: u64,
     pub last_active: u64,
+    /// Chaves públicas conhecidas do membro (atualizadas seguindo suas rotações)
+    #[serde(default)]
+    pub keys: Option<IdentityKeys>,
 }
 
 /// Pasta compartilhada dentro de um workspace
@@ -69,11 +75,14 @@ pub struct Workspace {
     pub members: HashMap<Uuid, WorkspaceMember>,
     pub shared_folders: HashMap<Uuid, SharedFolder>,
     pub invites: HashMap<Uuid, WorkspaceInvite>,
+    /// Chave de criptografia convergente, cifrada pela chave de identidade de quem guarda o registro
+    /// Arquivos idênticos só são deduplicados dentro do workspace
+    pub convergence_key: WrappedKey,
 }
 
 impl Workspace {
-    /// Criar novo workspace
-    pub fn new(name: String, description: String, owner_id: Uuid, owner_email: String) -> Self {
+    /// Criar novo workspace com a chave convergente já cifrada
+    pub fn new(name: String, description: String, owner_id: Uuid, owner_email: String, convergence_key: WrappedKey) -> Self {
         let id = Uuid::new_v4();
         let now = current_timestamp();
         
@@ -84,6 +93,7 @@ impl Workspace {
             permission: Permission::Owner,
             joined_at: now,
             last_active: now,
+            keys: None,
         });
         
         Self {
@@ -95,9 +105,15 @@ impl Workspace {
             members,
             shared_folders: HashMap::new(),
             invites: HashMap::new(),
+            convergence_key,
         }
     }
     
+    /// Decifrar a chave convergente do workspace
+    pub async fn convergence_key(&self, keys: &dyn KeyWrapper) -> Result<ConvergenceKey> {
+        Ok(ConvergenceKey::from_bytes(keys.unwrap_key(&self.convergence_key).await?))
+    }
+    
     /// Convidar usuário por email
     pub fn invite_member(&mut self, email: String, permission: Permission, invited_by: Uuid) -> Result<Uuid> {
         // Verificar se quem convida tem permissão
@@ -150,6 +166,7 @@ impl Workspace {
             permission: invite.permission,
             joined_at: now,
             last_active: now,
+            keys: None,
         });
         
         invite.accepted = true;
@@ -233,6 +250,40 @@ impl Workspace {
         Ok(())
     }
     
+    /// Registrar as chaves públicas de um membro
+    /// Chaves já conhecidas só mudam através de uma rotação assinada (`apply_rotation`)
+    pub fn set_member_keys(&mut self, member_id: Uuid, keys: IdentityKeys) -> Result<()> {
+        let member = self.members.get_mut(&member_id)
+            .ok_or_else(|| MSSCSError::NotFound("Membro não encontrado".to_string()))?;
+        
+        match &member.keys {
+            Some(known) if *known != keys => Err(MSSCSError::Validation(format!(
+                "Membro {} já tem outras chaves registradas", member_id
+            ))),
+            _ => {
+                member.keys = Some(keys);
+                Ok(())
+            }
+        }
+    }
+    
+    /// Seguir a cadeia de rotação de um membro a partir das chaves conhecidas
+    /// Retorna true se as chaves do membro mudaram
+    pub fn apply_rotation(&mut self, chain: &RotationChain) -> Result<bool> {
+        let member = self.members.get_mut(&chain.identity_id)
+            .ok_or_else(|| MSSCSError::NotFound("Membro não encontrado".to_string()))?;
+        let known = member.keys.as_ref()
+            .ok_or_else(|| MSSCSError::NotFound(format!("Chaves do membro {} desconhecidas", chain.identity_id)))?;
+        
+        let latest = chain.follow(known)?;
+        if latest == *known {
+            return Ok(false);
+        }
+        tracing::info!("🔄 Membro {} rotacionou para a geração de chaves {}", chain.identity_id, latest.version);
+        member.keys = Some(latest);
+        Ok(true)
+    }
+    
     /// Verificar se usuário pode convidar
     fn can_invite(&self, user_id: &Uuid) -> bool {
         self.members.get(user_id)
@@ -287,9 +338,10 @@ impl WorkspaceManager {
         }
     }
     
-    /// Criar workspace
-    pub async fn create_workspace(&self, name: String, description: String, owner_id: Uuid, owner_email: String) -> Result<Uuid> {
-        let workspace = Workspace::new(name, description, owner_id, owner_email);
+    /// Criar workspace; a chave convergente é gerada e cifrada com `keys`
+    pub async fn create_workspace(&self, name: String, description: String, owner_id: Uuid, owner_email: String, keys: &dyn KeyWrapper) -> Result<Uuid> {
+        let convergence_key = keys.wrap_key(ConvergenceKey::generate().as_bytes()).await?;
+        let workspace = Workspace::new(name, description, owner_id, owner_email, convergence_key);
         let workspace_id = workspace.id;
         
         self.workspaces.write().await.insert(workspace_id, workspace);
@@ -346,6 +398,35 @@ impl WorkspaceManager {
         
         Ok(())
     }
+    
+    /// Registrar as chaves públicas de um membro
+    pub async fn set_member_keys(&self, workspace_id: Uuid, member_id: Uuid, keys: IdentityKeys) -> Result<()> {
+        let mut workspaces = self.workspaces.write().await;
+        let workspace = workspaces.get_mut(&workspace_id)
+            .ok_or_else(|| MSSCSError::NotFound("Workspace não encontrado".to_string()))?;
+        
+        workspace.set_member_keys(member_id, keys)
+    }
+    
+    /// Aplicar a rotação de chaves de um membro em todos os workspaces dele
+    /// Retorna quantos workspaces foram atualizados
+    pub async fn apply_rotation(&self, chain: &RotationChain) -> Result<usize> {
+        let workspace_ids = self.user_workspaces.read().await
+            .get(&chain.identity_id)
+            .cloned()
+            .unwrap_or_default();
+        
+        let mut workspaces = self.workspaces.write().await;
+        let mut updated = 0;
+        for workspace_id in workspace_ids {
+            let Some(workspace) = workspaces.get_mut(&workspace_id) else { continue };
+            let knows_keys = workspace.members.get(&chain.identity_id).is_some_and(|m| m.keys.is_some());
+            if knows_keys && workspace.apply_rotation(chain)? {
+                updated += 1;
+            }
+        }
+        Ok(updated)
+    }
 }
 
 fn current_timestamp() -> u64 {
@@ -1,8 +1,16 @@
 // Integration test for complete block processing pipeline
-use msscs_v4::block::DataBlock;
+use msscs_v4::block::{DataBlock, DataKey, IdentityKey};
+
+/// Identity key standing in for an unlocked identity, plus one file's data key
+fn keys() -> (IdentityKey, DataKey) {
+    let identity_key = IdentityKey::from_bytes([42u8; 32]);
+    let data_key = DataKey::generate(&identity_key).expect("Failed to generate data key");
+    (identity_key, data_key)
+}
 
 #[test]
 fn test_block_creation_and_decoding() {
+    let (identity_key, data_key) = keys();
     // Test data
     let original_data = b"Hello, MSSCS! This is a test of the complete pipeline.";
     
@@ -12,6 +20,7 @@ fn test_block_creation_and_decoding() {
         0,
         None,
         [0u8; 32],
+        &data_key,
     ).expect("Failed to create block");
     
     // Verify block was created
@@ -19,7 +28,7 @@ fn test_block_creation_and_decoding() {
     assert_eq!(block.previous_uuid, None);
     
     // Decode the block
-    let decoded_data = block.decode(0).expect("Failed to decode block");
+    let decoded_data = block.decode(0, &identity_key).expect("Failed to decode block");
     
     // Verify data matches
     assert_eq!(decoded_data, original_data);
@@ -27,23 +36,24 @@ fn test_block_creation_and_decoding() {
 
 #[test]
 fn test_block_chain() {
+    let (identity_key, data_key) = keys();
     // Create a chain of blocks
     let data1 = b"First block";
     let data2 = b"Second block";
     let data3 = b"Third block";
     
     // Create first block (genesis)
-    let block1 = DataBlock::new(data1, 0, None, [0u8; 32])
+    let block1 = DataBlock::new(data1, 0, None, [0u8; 32], &data_key)
         .expect("Failed to create block 1");
     let hash1 = block1.calculate_hash().expect("Failed to calculate hash 1");
     
     // Create second block (links to first)
-    let block2 = DataBlock::new(data2, 1, Some(block1.uuid), hash1)
+    let block2 = DataBlock::new(data2, 1, Some(block1.uuid), hash1, &data_key)
         .expect("Failed to create block 2");
     let hash2 = block2.calculate_hash().expect("Failed to calculate hash 2");
     
     // Create third block (links to second)
-    let block3 = DataBlock::new(data3, 2, Some(block2.uuid), hash2)
+    let block3 = DataBlock::new(data3, 2, Some(block2.uuid), hash2, &data_key)
         .expect("Failed to create block 3");
     
     // Verify chain structure
@@ -56,9 +66,9 @@ fn test_block_chain() {
     assert_eq!(block3.previous_hash, hash2);
     
     // Decode all blocks
-    let decoded1 = block1.decode(0).expect("Failed to decode block 1");
-    let decoded2 = block2.decode(1).expect("Failed to decode block 2");
-    let decoded3 = block3.decode(2).expect("Failed to decode block 3");
+    let decoded1 = block1.decode(0, &identity_key).expect("Failed to decode block 1");
+    let decoded2 = block2.decode(1, &identity_key).expect("Failed to decode block 2");
+    let decoded3 = block3.decode(2, &identity_key).expect("Failed to decode block 3");
     
     assert_eq!(decoded1, data1);
     assert_eq!(decoded2, data2);
@@ -67,31 +77,59 @@ fn test_block_chain() {
 
 #[test]
 fn test_block_encryption_security() {
+    let (identity_key, data_key) = keys();
     let data = b"Secret data";
     
     // Create block with node_index 0
-    let block = DataBlock::new(data, 0, None, [0u8; 32])
+    let block = DataBlock::new(data, 0, None, [0u8; 32], &data_key)
         .expect("Failed to create block");
     
     // Try to decode with correct node_index (should succeed)
-    let result = block.decode(0);
+    let result = block.decode(0, &identity_key);
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), data);
     
     // Try to decode with wrong node_index (should fail)
-    let result = block.decode(1);
+    let result = block.decode(1, &identity_key);
     assert!(result.is_err());
+    
+    // Another user's identity key cannot unwrap the data key
+    let other_key = IdentityKey::from_bytes([7u8; 32]);
+    assert!(block.decode(0, &other_key).is_err());
+    
+    // Nothing stored on the block is enough to decrypt it any more
+    let mut forged = block.clone();
+    forged.wrapped_key = DataKey::generate(&other_key).unwrap().wrapped().clone();
+    assert!(forged.decode(0, &other_key).is_err());
+}
+
+#[test]
+fn test_block_verify_without_key() {
+    let (identity_key, data_key) = keys();
+    let block = DataBlock::new(b"Integrity check", 0, None, [0u8; 32], &data_key)
+        .expect("Failed to create block");
+    assert!(block.verify());
+    
+    // A flipped payload bit is caught without the key
+    let mut serialized = bincode::serialize(&block).unwrap();
+    let payload = block.get_encrypted_payload();
+    let offset = serialized.windows(payload.len()).position(|window| window == payload).unwrap();
+    serialized[offset] ^= 1;
+    let corrupted: DataBlock = bincode::deserialize(&serialized).unwrap();
+    assert!(!corrupted.verify());
+    assert!(corrupted.decode(0, &identity_key).is_err());
 }
 
 #[test]
 fn test_block_with_large_data() {
+    let (identity_key, data_key) = keys();
     // Test with larger data (1MB)
     let large_data: Vec<u8> = (0..1024*1024).map(|i| (i % 256) as u8).collect();
     
-    let block = DataBlock::new(&large_data, 0, None, [0u8; 32])
+    let block = DataBlock::new(&large_data, 0, None, [0u8; 32], &data_key)
         .expect("Failed to create large block");
     
-    let decoded = block.decode(0).expect("Failed to decode large block");
+    let decoded = block.decode(0, &identity_key).expect("Failed to decode large block");
     
     assert_eq!(decoded.len(), large_data.len());
     assert_eq!(decoded, large_data);
@@ -99,34 +137,37 @@ fn test_block_with_large_data() {
 
 #[test]
 fn test_block_with_empty_data() {
+    let (identity_key, data_key) = keys();
     let empty_data = b"";
     
-    let block = DataBlock::new(empty_data, 0, None, [0u8; 32])
+    let block = DataBlock::new(empty_data, 0, None, [0u8; 32], &data_key)
         .expect("Failed to create empty block");
     
-    let decoded = block.decode(0).expect("Failed to decode empty block");
+    let decoded = block.decode(0, &identity_key).expect("Failed to decode empty block");
     
     assert_eq!(decoded, empty_data);
 }
 
 #[test]
 fn test_block_with_binary_data() {
+    let (identity_key, data_key) = keys();
     // Test with binary data (not just text)
     let binary_data: Vec<u8> = vec![0x00, 0xFF, 0xAA, 0x55, 0x12, 0x34, 0x56, 0x78];
     
-    let block = DataBlock::new(&binary_data, 0, None, [0u8; 32])
+    let block = DataBlock::new(&binary_data, 0, None, [0u8; 32], &data_key)
         .expect("Failed to create binary block");
     
-    let decoded = block.decode(0).expect("Failed to decode binary block");
+    let decoded = block.decode(0, &identity_key).expect("Failed to decode binary block");
     
     assert_eq!(decoded, binary_data);
 }
 
 #[test]
 fn test_block_serialization() {
+    let (identity_key, data_key) = keys();
     let data = b"Test serialization";
     
-    let block = DataBlock::new(data, 0, None, [0u8; 32])
+    let block = DataBlock::new(data, 0, None, [0u8; 32], &data_key)
         .expect("Failed to create block");
     
     // Serialize
@@ -142,15 +183,16 @@ fn test_block_serialization() {
     assert_eq!(deserialized.node_index, block.node_index);
     
     // Decode and verify data
-    let decoded = deserialized.decode(0).expect("Failed to decode deserialized block");
+    let decoded = deserialized.decode(0, &identity_key).expect("Failed to decode deserialized block");
     assert_eq!(decoded, data);
 }
 
 #[test]
 fn test_block_hash_consistency() {
+    let (_, data_key) = keys();
     let data = b"Hash test";
     
-    let block = DataBlock::new(data, 0, None, [0u8; 32])
+    let block = DataBlock::new(data, 0, None, [0u8; 32], &data_key)
         .expect("Failed to create block");
     
     // Calculate hash multiple times
@@ -165,10 +207,11 @@ fn test_block_hash_consistency() {
 
 #[test]
 fn test_compression_effectiveness() {
+    let (identity_key, data_key) = keys();
     // Test with highly compressible data (repeated pattern)
     let compressible_data = b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
     
-    let block = DataBlock::new(compressible_data, 0, None, [0u8; 32])
+    let block = DataBlock::new(compressible_data, 0, None, [0u8; 32], &data_key)
         .expect("Failed to create block");
     
     // Serialize to get actual storage size
@@ -181,6 +224,6 @@ fn test_compression_effectiveness() {
     println!("Stored size: {} bytes", serialized.len());
     
     // Verify data integrity
-    let decoded = block.decode(0).expect("Failed to decode");
+    let decoded = block.decode(0, &identity_key).expect("Failed to decode");
     assert_eq!(decoded, compressible_data);
 }
@@ -61,3 +61,40 @@ fn test_config_save_and_load() {
     assert_eq!(loaded_config.bootstrap_peers, vec!["127.0.0.1:8080"]);
     assert_eq!(loaded_config.api_keys, Some(vec!["test-key".to_string()]));
 }
+
+#[test]
+fn test_legacy_bootstrap_peers_translate() {
+    let mut config = Config::default();
+    config.bootstrap_peers = vec![
+        "127.0.0.1:8080".to_string(),
+        "node.example.com:9000".to_string(),
+        "/ip4/10.0.0.5/tcp/4001".to_string(),
+        "not an address".to_string(),
+    ];
+    
+    let addrs: Vec<String> = config.bootstrap_multiaddrs().iter().map(|a| a.to_string()).collect();
+    assert_eq!(addrs, vec![
+        "/ip4/127.0.0.1/tcp/8081",
+        "/dns/node.example.com/tcp/9001",
+        "/ip4/10.0.0.5/tcp/4001",
+    ]);
+    assert_eq!(config.p2p_listen_port(), 8081);
+    
+    config.p2p_port = Some(config.port);
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_recovery_api_needs_keys_or_loopback() {
+    let mut config = Config::default();
+    assert!(!config.allows_recovery_api());
+
+    config.api_host = "127.0.0.1".parse().unwrap();
+    assert!(config.allows_recovery_api());
+
+    config.api_host = "0.0.0.0".parse().unwrap();
+    config.api_keys = Some(Vec::new());
+    assert!(!config.allows_recovery_api());
+    config.api_keys = Some(vec!["secret".to_string()]);
+    assert!(config.allows_recovery_api());
+}
@@ -1,28 +1,52 @@
 // Integration tests for MSSCS v4.0
 use msscs_v4::Config;
-use msscs_v4::vfs::VirtualFileSystem;
+use msscs_v4::QuantumIdentity;
+use msscs_v4::UnlockedIdentity;
+use msscs_v4::block::{DataBlock, IdentityKey};
+use msscs_v4::block_store::StorageBackend;
+use msscs_v4::chunking::Chunking;
+use msscs_v4::adaptive_compression::CompressionAlgorithm;
+use msscs_v4::namespace::{Namespace, RetentionPolicy};
+use msscs_v4::vfs::{FileWriteOptions, VirtualFileSystem};
 use msscs_v4::persistence::PersistenceManager;
+use msscs_v4::workspace::{Workspace, WorkspaceManager};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::OnceLock;
 use tempfile::TempDir;
 
+/// Identity the VFS wraps its keys with; shared so reopened stores unlock again
+fn local_identity() -> &'static UnlockedIdentity {
+    static IDENTITY: OnceLock<UnlockedIdentity> = OnceLock::new();
+    IDENTITY.get_or_init(|| {
+        QuantumIdentity::new("local".to_string(), "passphrase")
+            .expect("Failed to create identity")
+            .unlock("passphrase")
+            .expect("Failed to unlock identity")
+    })
+}
+
+/// Single-replica config on the embedded store under `data_dir`
+fn test_config(data_dir: &std::path::Path) -> Config {
+    Config {
+        data_dir: data_dir.to_path_buf(),
+        replication_factor: 1,
+        storage_backend: StorageBackend::Embedded,
+        ..Config::default()
+    }
+}
+
 #[tokio::test]
 async fn test_vfs_write_and_read_file() {
     let temp_dir = TempDir::new().expect("Failed to create temp dir");
     let data_dir = temp_dir.path().to_path_buf();
     
-    let config = Arc::new(Config {
-        port: 8080,
-        data_dir: data_dir.clone(),
-        replication_factor: 1,
-        chunk_size: 1024,
-        log_level: "info".to_string(),
-        bootstrap_peers: vec![],
-        api_keys: None,
-    });
+    let config = Arc::new(test_config(&data_dir));
     
     let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
-    let mut vfs = VirtualFileSystem::new(config, persistence).expect("Failed to create VFS");
+    let mut vfs = VirtualFileSystem::new(config, persistence).await.expect("Failed to create VFS");
+    vfs.set_identity(local_identity());
     
     // Write file
     let test_data = b"Hello, MSSCS v4.0!";
@@ -40,18 +64,11 @@ async fn test_vfs_write_large_file() {
     let temp_dir = TempDir::new().expect("Failed to create temp dir");
     let data_dir = temp_dir.path().to_path_buf();
     
-    let config = Arc::new(Config {
-        port: 8080,
-        data_dir: data_dir.clone(),
-        replication_factor: 1,
-        chunk_size: 1024,
-        log_level: "info".to_string(),
-        bootstrap_peers: vec![],
-        api_keys: None,
-    });
+    let config = Arc::new(test_config(&data_dir));
     
     let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
-    let mut vfs = VirtualFileSystem::new(config, persistence).expect("Failed to create VFS");
+    let mut vfs = VirtualFileSystem::new(config, persistence).await.expect("Failed to create VFS");
+    vfs.set_identity(local_identity());
     
     // Create 10KB file
     let test_data = vec![0xAB; 10240];
@@ -70,18 +87,11 @@ async fn test_vfs_list_files() {
     let temp_dir = TempDir::new().expect("Failed to create temp dir");
     let data_dir = temp_dir.path().to_path_buf();
     
-    let config = Arc::new(Config {
-        port: 8080,
-        data_dir: data_dir.clone(),
-        replication_factor: 1,
-        chunk_size: 1024,
-        log_level: "info".to_string(),
-        bootstrap_peers: vec![],
-        api_keys: None,
-    });
+    let config = Arc::new(test_config(&data_dir));
     
     let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
-    let mut vfs = VirtualFileSystem::new(config, persistence).expect("Failed to create VFS");
+    let mut vfs = VirtualFileSystem::new(config, persistence).await.expect("Failed to create VFS");
+    vfs.set_identity(local_identity());
     
     // Write multiple files
     vfs.write_file(&PathBuf::from("file1.txt"), b"data1").await.expect("Failed to write file1");
@@ -102,18 +112,11 @@ async fn test_vfs_delete_file() {
     let temp_dir = TempDir::new().expect("Failed to create temp dir");
     let data_dir = temp_dir.path().to_path_buf();
     
-    let config = Arc::new(Config {
-        port: 8080,
-        data_dir: data_dir.clone(),
-        replication_factor: 1,
-        chunk_size: 1024,
-        log_level: "info".to_string(),
-        bootstrap_peers: vec![],
-        api_keys: None,
-    });
+    let config = Arc::new(test_config(&data_dir));
     
     let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
-    let mut vfs = VirtualFileSystem::new(config, persistence).expect("Failed to create VFS");
+    let mut vfs = VirtualFileSystem::new(config, persistence).await.expect("Failed to create VFS");
+    vfs.set_identity(local_identity());
     
     // Write file
     let path = PathBuf::from("delete_me.txt");
@@ -134,20 +137,13 @@ async fn test_vfs_persistence() {
     let temp_dir = TempDir::new().expect("Failed to create temp dir");
     let data_dir = temp_dir.path().to_path_buf();
     
-    let config = Arc::new(Config {
-        port: 8080,
-        data_dir: data_dir.clone(),
-        replication_factor: 1,
-        chunk_size: 1024,
-        log_level: "info".to_string(),
-        bootstrap_peers: vec![],
-        api_keys: None,
-    });
+    let config = Arc::new(test_config(&data_dir));
     
     // Create VFS and write file
     {
         let persistence = Arc::new(PersistenceManager::new(data_dir.clone()).expect("Failed to create persistence"));
-        let mut vfs = VirtualFileSystem::new(config.clone(), persistence).expect("Failed to create VFS");
+        let mut vfs = VirtualFileSystem::new(config.clone(), persistence).await.expect("Failed to create VFS");
+        vfs.set_identity(local_identity());
         
         vfs.write_file(&PathBuf::from("persistent.txt"), b"persistent data").await.expect("Failed to write file");
     }
@@ -155,7 +151,8 @@ async fn test_vfs_persistence() {
     // Create new VFS instance (simulating restart)
     {
         let persistence = Arc::new(PersistenceManager::new(data_dir.clone()).expect("Failed to create persistence"));
-        let mut vfs = VirtualFileSystem::new(config.clone(), persistence).expect("Failed to create VFS");
+        let mut vfs = VirtualFileSystem::new(config.clone(), persistence).await.expect("Failed to create VFS");
+        vfs.set_identity(local_identity());
         
         // File should still be accessible
         let files = vfs.list_files();
@@ -167,3 +164,492 @@ async fn test_vfs_persistence() {
         assert_eq!(data, b"persistent data");
     }
 }
+
+#[tokio::test]
+async fn test_vfs_directories() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let data_dir = temp_dir.path().to_path_buf();
+    
+    let config = Arc::new(test_config(&data_dir));
+    
+    let head = {
+        let persistence = Arc::new(PersistenceManager::new(data_dir.clone()).expect("Failed to create persistence"));
+        let mut vfs = VirtualFileSystem::new(config.clone(), persistence).await.expect("Failed to create VFS");
+        vfs.set_identity(local_identity());
+        
+        vfs.mkdir(&PathBuf::from("projects/alpha")).await.expect("Failed to create directory");
+        let head = vfs.write_file(&PathBuf::from("projects/alpha/notes.txt"), b"alpha notes").await.expect("Failed to write file");
+        vfs.mkdir(&PathBuf::from("archive")).await.expect("Failed to create directory");
+        
+        // Non-empty directories cannot be removed
+        assert!(vfs.rmdir(&PathBuf::from("projects")).await.is_err());
+        
+        vfs.rename(&PathBuf::from("projects/alpha"), &PathBuf::from("archive/alpha")).await.expect("Failed to move directory");
+        vfs.rmdir(&PathBuf::from("projects")).await.expect("Failed to remove directory");
+        head
+    };
+    
+    // Tree survives a restart and the moved file keeps its blocks
+    let persistence = Arc::new(PersistenceManager::new(data_dir.clone()).expect("Failed to create persistence"));
+    let mut vfs = VirtualFileSystem::new(config, persistence).await.expect("Failed to create VFS");
+    vfs.set_identity(local_identity());
+    
+    let entries: Vec<String> = vfs.list_dir(&PathBuf::from("/"), true).expect("Failed to list tree")
+        .into_iter().map(|e| e.path).collect();
+    assert_eq!(entries, vec!["archive", "archive/alpha", "archive/alpha/notes.txt"]);
+    
+    let stat = vfs.stat(&PathBuf::from("archive/alpha/notes.txt")).expect("Failed to stat file");
+    assert_eq!(stat.size, 11);
+    assert_eq!(stat.block_count, 1);
+    assert_eq!(stat.head, Some(head));
+    
+    let data = vfs.read_file(&PathBuf::from("archive/alpha/notes.txt")).await.expect("Failed to read moved file");
+    assert_eq!(data, b"alpha notes");
+}
+
+#[tokio::test]
+async fn test_vfs_versions_and_retention() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let data_dir = temp_dir.path().to_path_buf();
+    
+    let config = Arc::new(Config {
+        retention: RetentionPolicy { keep_versions: Some(1), keep_days: None },
+        ..test_config(&data_dir)
+    });
+    
+    let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
+    let mut vfs = VirtualFileSystem::new(config, persistence).await.expect("Failed to create VFS");
+    vfs.set_identity(local_identity());
+    
+    let path = PathBuf::from("docs/report.txt");
+    vfs.write_file(&path, b"first draft").await.expect("Failed to write v1");
+    vfs.write_file(&path, b"accidental overwrite").await.expect("Failed to write v2");
+    
+    let versions = vfs.list_versions(&path).expect("Failed to list versions");
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0].size, 11);
+    assert_ne!(versions[0].root_hash, versions[1].root_hash);
+    assert_eq!(vfs.read_version(&path, 1).await.expect("Failed to read v1"), b"first draft");
+    
+    // Restoring records a new version pointing at the old chain
+    let restored = vfs.restore_version(&path, 1).await.expect("Failed to restore v1");
+    assert_eq!(restored.version, 3);
+    assert_eq!(restored.head, versions[0].head);
+    assert_eq!(vfs.read_file(&path).await.expect("Failed to read file"), b"first draft");
+    
+    // Keeping a single version drops v1 and v2; only v2's chain becomes garbage
+    let deleted = vfs.cleanup_orphaned_blocks().await.expect("Failed to clean up");
+    assert_eq!(deleted, 1);
+    assert_eq!(vfs.list_versions(&path).expect("Failed to list versions").len(), 1);
+    assert!(vfs.read_version(&path, 2).await.is_err());
+    assert_eq!(vfs.read_file(&path).await.expect("Failed to read file"), b"first draft");
+}
+
+#[tokio::test]
+async fn test_vfs_snapshots() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let data_dir = temp_dir.path().to_path_buf();
+    
+    let config = Arc::new(Config {
+        retention: RetentionPolicy { keep_versions: Some(1), keep_days: None },
+        ..test_config(&data_dir)
+    });
+    
+    let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
+    let mut vfs = VirtualFileSystem::new(config.clone(), persistence.clone()).await.expect("Failed to create VFS");
+    vfs.set_identity(local_identity());
+    
+    let report = PathBuf::from("docs/report.txt");
+    let notes = PathBuf::from("docs/notes.txt");
+    vfs.write_file(&report, b"quarterly numbers").await.expect("Failed to write report");
+    vfs.write_file(&notes, b"todo").await.expect("Failed to write notes");
+    vfs.create_snapshot("q1").await.expect("Failed to create snapshot");
+    
+    vfs.write_file(&report, b"revised numbers").await.expect("Failed to overwrite report");
+    vfs.delete_file(&notes).await.expect("Failed to delete notes");
+    vfs.write_file(&PathBuf::from("docs/new.txt"), b"fresh").await.expect("Failed to write new file");
+    
+    let diff = vfs.diff_snapshots("q1", None).expect("Failed to diff");
+    assert_eq!(diff.changes.added, vec!["docs/new.txt"]);
+    assert_eq!(diff.changes.removed, vec!["docs/notes.txt"]);
+    assert_eq!(diff.changes.modified, vec!["docs/report.txt"]);
+    
+    // Retention would drop the old report version, but the snapshot keeps its blocks alive
+    let deleted = vfs.cleanup_orphaned_blocks().await.expect("Failed to clean up");
+    assert_eq!(deleted, 0);
+    drop(vfs);
+    
+    let mut vfs = VirtualFileSystem::new(config, persistence).await.expect("Failed to reopen VFS");
+    vfs.set_identity(local_identity());
+    assert_eq!(vfs.list_snapshots().len(), 1);
+    assert_eq!(vfs.snapshot_list_dir("q1", &PathBuf::from("docs"), false).expect("Failed to list").len(), 2);
+    assert_eq!(vfs.read_snapshot_file("q1", &report).await.expect("Failed to read"), b"quarterly numbers");
+    assert_eq!(vfs.read_snapshot_file("q1", &notes).await.expect("Failed to read"), b"todo");
+    assert_eq!(vfs.read_file(&report).await.expect("Failed to read"), b"revised numbers");
+    
+    // Dropping the snapshot releases the old report and the deleted notes
+    vfs.delete_snapshot("q1").await.expect("Failed to delete snapshot");
+    let deleted = vfs.cleanup_orphaned_blocks().await.expect("Failed to clean up");
+    assert_eq!(deleted, 2);
+    assert!(vfs.read_snapshot_file("q1", &report).await.is_err());
+}
+
+#[tokio::test]
+async fn test_vfs_deduplicates_chunks() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let data_dir = temp_dir.path().to_path_buf();
+    
+    let config = Arc::new(Config {
+        retention: RetentionPolicy { keep_versions: Some(1), keep_days: None },
+        ..test_config(&data_dir)
+    });
+    
+    let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
+    let mut vfs = VirtualFileSystem::new(config.clone(), persistence.clone()).await.expect("Failed to create VFS");
+    vfs.set_identity(local_identity());
+    
+    // Three distinct 1 KiB chunks, written under two names
+    let data: Vec<u8> = (0..3000u32).map(|i| (i * 7 % 251) as u8).collect();
+    vfs.write_file(&PathBuf::from("a.bin"), &data).await.expect("Failed to write a");
+    vfs.write_file(&PathBuf::from("copies/b.bin"), &data).await.expect("Failed to write b");
+    
+    let stats = vfs.dedup_stats();
+    assert_eq!(stats.total_blocks, 3);
+    assert!(stats.dedup_savings > 0);
+    assert_eq!(persistence.list_chunks().await.expect("Failed to list chunks").len(), 3);
+    
+    // Deleting one copy keeps the shared chunks alive
+    vfs.delete_file(&PathBuf::from("a.bin")).await.expect("Failed to delete a");
+    assert_eq!(vfs.cleanup_orphaned_blocks().await.expect("Failed to clean up"), 0);
+    drop(vfs);
+    
+    let mut vfs = VirtualFileSystem::new(config, persistence.clone()).await.expect("Failed to reopen VFS");
+    vfs.set_identity(local_identity());
+    assert_eq!(vfs.read_file(&PathBuf::from("copies/b.bin")).await.expect("Failed to read b"), data);
+    
+    // The last reference goes away and garbage collection reclaims every chunk
+    vfs.delete_file(&PathBuf::from("copies/b.bin")).await.expect("Failed to delete b");
+    assert_eq!(vfs.cleanup_orphaned_blocks().await.expect("Failed to clean up"), 3);
+    assert!(persistence.list_chunks().await.expect("Failed to list chunks").is_empty());
+}
+
+#[tokio::test]
+async fn test_vfs_seals_chunks_with_workspace_key() {
+    let identity_key = IdentityKey::from_identity(local_identity());
+    let owner = *local_identity().user_id();
+    let workspaces = WorkspaceManager::new();
+    let workspace_id = workspaces.create_workspace("team".to_string(), String::new(), owner, "owner@example.com".to_string(), &identity_key)
+        .await
+        .expect("Failed to create workspace");
+    let workspace = workspaces.get_workspace(&workspace_id).await.expect("Workspace not found");
+    
+    // The record only carries the wrapped key, and a record without one is refused
+    let mut record = serde_json::to_value(&workspace).expect("Failed to serialize workspace");
+    assert!(!record.to_string().contains(&hex::encode(workspace.convergence_key(&identity_key).await.unwrap().as_bytes())));
+    record.as_object_mut().unwrap().remove("convergence_key");
+    assert!(serde_json::from_value::<Workspace>(record).is_err());
+    
+    // Two stores writing into the workspace seal identical chunks identically
+    let data: Vec<u8> = (0..3000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut cids = Vec::new();
+    for _ in 0..2 {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Arc::new(test_config(temp_dir.path()));
+        let persistence = Arc::new(PersistenceManager::new(temp_dir.path().to_path_buf()).expect("Failed to create persistence"));
+        let mut vfs = VirtualFileSystem::new(config, persistence.clone()).await.expect("Failed to create VFS");
+        vfs.set_identity(local_identity());
+        vfs.use_workspace(&workspace).await.expect("Failed to use workspace");
+        vfs.write_file(&PathBuf::from("shared.bin"), &data).await.expect("Failed to write");
+        assert_eq!(vfs.read_file(&PathBuf::from("shared.bin")).await.expect("Failed to read"), data);
+        let mut chunks = persistence.list_chunks().await.expect("Failed to list chunks");
+        chunks.sort();
+        cids.push(chunks);
+    }
+    assert_eq!(cids[0], cids[1]);
+    
+    // Another identity cannot unwrap the workspace key
+    let other = IdentityKey::from_bytes([9u8; 32]);
+    assert!(workspace.convergence_key(&other).await.is_err());
+}
+
+#[tokio::test]
+async fn test_vfs_content_defined_chunking_and_ranges() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let data_dir = temp_dir.path().to_path_buf();
+    
+    let config = Arc::new(test_config(&data_dir));
+    
+    let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
+    let mut vfs = VirtualFileSystem::new(config, persistence).await.expect("Failed to create VFS");
+    vfs.set_identity(local_identity());
+    
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let data: Vec<u8> = (0..64 * 1024).map(|_| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed as u8
+    }).collect();
+    let cdc = || FileWriteOptions {
+        chunking: Some(Chunking::content_defined(2048)),
+        compress: false,
+        ..Default::default()
+    };
+    
+    let path = PathBuf::from("data/original.bin");
+    vfs.write_file_with_options(&path, &data, cdc()).await.expect("Failed to write file");
+    let chunks_before = vfs.dedup_stats().total_blocks;
+    
+    // Prepending a few bytes only adds the chunks around the edit
+    let mut edited = b"header\n".to_vec();
+    edited.extend_from_slice(&data);
+    vfs.write_file_with_options(&PathBuf::from("data/edited.bin"), &edited, cdc()).await.expect("Failed to write edit");
+    let added = vfs.dedup_stats().total_blocks - chunks_before;
+    assert!(added <= 3, "{} new chunks for a 7 byte insert", added);
+    
+    assert_eq!(vfs.read_file(&path).await.expect("Failed to read file"), data);
+    let range = vfs.read_range(&path, 10_000, 5_000).await.expect("Failed to read range");
+    assert_eq!(range, &data[10_000..15_000]);
+    let tail = vfs.read_range(&path, 60_000, u64::MAX).await.expect("Failed to read tail");
+    assert_eq!(tail, &data[60_000..]);
+    assert!(vfs.read_range(&path, 70_000, 10).await.expect("Failed to read past end").is_empty());
+    
+    assert!(vfs.write_file_with_options(&path, &data, FileWriteOptions {
+        chunking: Some(Chunking::Cdc { min_size: 0, avg_size: 0, max_size: 0 }),
+        ..Default::default()
+    }).await.is_err());
+}
+
+#[tokio::test]
+async fn test_vfs_compressed_chains_and_unmeasured_ranges() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let data_dir = temp_dir.path().to_path_buf();
+    
+    let config = Arc::new(test_config(&data_dir));
+    let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
+    let mut vfs = VirtualFileSystem::new(config.clone(), persistence.clone()).await.expect("Failed to create VFS");
+    vfs.set_identity(local_identity());
+    
+    // Chain boundaries come from the plain text; every block decompresses on its own
+    let text: Vec<u8> = (0..2000).flat_map(|i| format!("line {} of the log\n", i % 37).into_bytes()).collect();
+    let chain = |chunking| FileWriteOptions {
+        chunking: Some(chunking),
+        compress: true,
+        dedup: false,
+        ..Default::default()
+    };
+    let log = PathBuf::from("logs/app.log");
+    vfs.write_file_with_options(&log, &text, chain(Chunking::content_defined(2048))).await.expect("Failed to write chain");
+    assert_eq!(vfs.read_file(&log).await.expect("Failed to read chain"), text);
+    assert_eq!(vfs.read_range(&log, 5_000, 100).await.expect("Failed to read range"), &text[5_000..5_100]);
+    
+    // A single-block chain migrated from the flat manifest without being measured has no size
+    let note = b"migrated from the flat manifest".to_vec();
+    let path = PathBuf::from("note.txt");
+    let head = vfs.write_file_with_options(&path, &note, chain(Chunking::Fixed { size: 4096 })).await.expect("Failed to write note");
+    drop(vfs);
+    let manifest = [("note.txt".to_string(), head)].into_iter().collect();
+    let namespace = Namespace::from_flat(&manifest, &HashMap::new(), "local", CompressionAlgorithm::Huffman)
+        .expect("Failed to migrate manifest");
+    persistence.save_namespace(&namespace).await.expect("Failed to save namespace");
+    
+    let mut vfs = VirtualFileSystem::new(config, persistence).await.expect("Failed to reopen VFS");
+    vfs.set_identity(local_identity());
+    assert_eq!(vfs.stat(&path).expect("Failed to stat note").size, 0);
+    assert_eq!(vfs.read_range(&path, 9, 4).await.expect("Failed to read range"), b"from");
+    assert_eq!(vfs.read_range(&path, 23, u64::MAX).await.expect("Failed to read tail"), b"manifest");
+}
+
+#[tokio::test]
+async fn test_vfs_replicates_over_p2p_node() {
+    use msscs_v4::p2p_network::P2PNodeCommand;
+    
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let data_dir = temp_dir.path().to_path_buf();
+    
+    let config = Arc::new(Config {
+        replication_factor: 2,
+        ..test_config(&data_dir)
+    });
+    
+    let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
+    
+    // Stand-in for the P2P node: it reads replicated blocks from the VFS's store into
+    // `network` and serves fetches from there
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let network = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+    let replicas = network.clone();
+    let store = persistence.store();
+    tokio::spawn(async move {
+        while let Some(cmd) = rx.recv().await {
+            match cmd {
+                P2PNodeCommand::ReplicateBlock { block_id, replicas: count, reply } => {
+                    assert_eq!(count, 2);
+                    let data = store.get(&block_id.to_string()).await.unwrap().expect("replicated block is not stored");
+                    replicas.lock().await.insert(block_id.to_string(), data);
+                    let _ = reply.send(Ok(count));
+                }
+                P2PNodeCommand::GetBlock { block_id, reply, .. } => {
+                    let data = replicas.lock().await.get(&block_id).cloned();
+                    let _ = reply.send(data.ok_or_else(|| "not found".to_string()));
+                }
+                P2PNodeCommand::StoreBlock { reply, .. } => {
+                    let _ = reply.send(Ok(()));
+                }
+                _ => {}
+            }
+        }
+    });
+    
+    let mut vfs = VirtualFileSystem::new(config, persistence.clone()).await.expect("Failed to create VFS");
+    vfs.set_network(tx.clone());
+    let identity = QuantumIdentity::new("replicator".to_string(), "passphrase").expect("Failed to create identity");
+    vfs.set_identity(&identity.unlock("passphrase").expect("Failed to unlock identity"));
+    
+    let test_data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    let path = PathBuf::from("replicated.bin");
+    vfs.write_file_with_options(&path, &test_data, FileWriteOptions {
+        dedup: false,
+        ..Default::default()
+    }).await.expect("Failed to write file");
+    let block_ids: Vec<String> = vfs.local_blocks.keys().cloned().collect();
+    assert!(!block_ids.is_empty());
+    
+    // Commands are handled in order, so one round trip flushes the replications
+    let (reply, done) = tokio::sync::oneshot::channel();
+    tx.send(P2PNodeCommand::StoreBlock { block_id: String::new(), data: Vec::new(), reply }).unwrap();
+    done.await.unwrap().unwrap();
+    for block_id in &block_ids {
+        assert!(network.lock().await.contains_key(block_id), "block {} not replicated", block_id);
+    }
+    
+    // Lose every local copy: the blocks come back from the network
+    for block_id in &block_ids {
+        persistence.delete_block(&block_id.parse().unwrap()).await.expect("Failed to delete block");
+    }
+    vfs.local_blocks.clear();
+    assert_eq!(vfs.read_file(&path).await.expect("Failed to read file"), test_data);
+    
+    // A provider answering with another intact block under the same ID is caught by the chain link
+    let (target, other) = (&block_ids[0], &block_ids[1]);
+    let mut forged: DataBlock = bincode::deserialize(&network.lock().await[other]).unwrap();
+    forged.uuid = target.parse().unwrap();
+    assert!(forged.verify());
+    network.lock().await.insert(target.clone(), bincode::serialize(&forged).unwrap());
+    for block_id in &block_ids {
+        persistence.delete_block(&block_id.parse().unwrap()).await.expect("Failed to delete block");
+    }
+    vfs.local_blocks.clear();
+    assert!(vfs.read_file(&path).await.is_err());
+    assert!(persistence.load_block(&target.parse().unwrap()).await.is_err(), "forged block was cached");
+}
+
+#[tokio::test]
+async fn test_vfs_dedups_chunks_over_p2p_node() {
+    use msscs_v4::p2p_network::P2PNodeCommand;
+    
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let data_dir = temp_dir.path().to_path_buf();
+    let config = Arc::new(test_config(&data_dir));
+    let persistence = Arc::new(PersistenceManager::new(data_dir).expect("Failed to create persistence"));
+    
+    // Stand-in for the P2P node: replicated chunks are copied from the VFS's store into
+    // `network`, fetches are served from there
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let network = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+    let replicas = network.clone();
+    let store = persistence.store();
+    tokio::spawn(async move {
+        while let Some(cmd) = rx.recv().await {
+            match cmd {
+                P2PNodeCommand::ReplicateChunk { cid, replicas: count, reply } => {
+                    let data = store.get(&format!("chunk-{}", cid)).await.unwrap().expect("replicated chunk is not stored");
+                    assert!(replicas.lock().await.insert(cid, data).is_none(), "chunk replicated twice");
+                    let _ = reply.send(Ok(count));
+                }
+                P2PNodeCommand::GetChunk { cid, reply } => {
+                    let data = replicas.lock().await.get(&cid).cloned();
+                    let _ = reply.send(data.ok_or_else(|| "not found".to_string()));
+                }
+                P2PNodeCommand::StoreBlock { reply, .. } => {
+                    let _ = reply.send(Ok(()));
+                }
+                _ => {}
+            }
+        }
+    });
+    
+    let mut vfs = VirtualFileSystem::new(config, persistence.clone()).await.expect("Failed to create VFS");
+    vfs.set_network(tx.clone());
+    let identity = QuantumIdentity::new("deduplicator".to_string(), "passphrase").expect("Failed to create identity");
+    vfs.set_identity(&identity.unlock("passphrase").expect("Failed to unlock identity"));
+    
+    // The same content under two paths is stored once and reused
+    let test_data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    vfs.write_file(&PathBuf::from("a.bin"), &test_data).await.expect("Failed to write file");
+    let chunk_count = vfs.chunks.len();
+    assert!(chunk_count > 0);
+    assert!(vfs.local_blocks.is_empty(), "networked writes should be deduplicated");
+    vfs.write_file(&PathBuf::from("b.bin"), &test_data).await.expect("Failed to write file");
+    assert_eq!(vfs.chunks.len(), chunk_count);
+    let cids: Vec<String> = vfs.chunks.cids().map(str::to_string).collect();
+    assert!(cids.iter().all(|cid| vfs.chunks.ref_count(cid) == 2));
+    
+    // Commands are handled in order, so one round trip flushes the replications
+    let (reply, done) = tokio::sync::oneshot::channel();
+    tx.send(P2PNodeCommand::StoreBlock { block_id: String::new(), data: Vec::new(), reply }).unwrap();
+    done.await.unwrap().unwrap();
+    assert_eq!(network.lock().await.len(), chunk_count);
+    
+    // Lose every local chunk: they come back from the network by CID
+    for cid in persistence.list_chunks().await.expect("Failed to list chunks") {
+        persistence.store().delete(&format!("chunk-{}", cid)).await.expect("Failed to delete chunk");
+    }
+    assert_eq!(vfs.read_file(&PathBuf::from("b.bin")).await.expect("Failed to read file"), test_data);
+    assert_eq!(persistence.list_chunks().await.expect("Failed to list chunks").len(), chunk_count);
+    
+    // A provider answering with other bytes fails CID verification
+    let cid = &cids[0];
+    network.lock().await.insert(cid.clone(), b"forged".to_vec());
+    persistence.store().delete(&format!("chunk-{}", cid)).await.expect("Failed to delete chunk");
+    assert!(vfs.read_file(&PathBuf::from("a.bin")).await.is_err());
+}
+
+#[tokio::test]
+async fn test_vfs_reads_files_across_key_rotation() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let data_dir = temp_dir.path().to_path_buf();
+    
+    let config = Arc::new(test_config(&data_dir));
+    let retired = QuantumIdentity::new("rotating".to_string(), "passphrase")
+        .expect("Failed to create identity")
+        .unlock("passphrase")
+        .expect("Failed to unlock identity");
+    let current = retired.rotate("passphrase").expect("Failed to rotate identity");
+    let path = PathBuf::from("private.bin");
+    let data: Vec<u8> = (0..3000u32).map(|i| (i % 199) as u8).collect();
+    let options = FileWriteOptions { dedup: false, ..Default::default() };
+    
+    let persistence = Arc::new(PersistenceManager::new(data_dir.clone()).expect("Failed to create persistence"));
+    let mut vfs = VirtualFileSystem::new(config.clone(), persistence.clone()).await.expect("Failed to create VFS");
+    vfs.set_identity(&retired);
+    vfs.write_file_with_options(&path, &data, options).await.expect("Failed to write file");
+    
+    // The new keys alone cannot unwrap the old data key; the retired generation can
+    let mut vfs = VirtualFileSystem::new(config.clone(), persistence.clone()).await.expect("Failed to create VFS");
+    vfs.set_identity(&current);
+    assert!(vfs.read_file(&path).await.is_err());
+    vfs.add_retired_identity(&retired);
+    assert_eq!(vfs.read_file(&path).await.expect("Failed to read file"), data);
+    
+    // Once re-wrapped, the retired keys are no longer needed
+    let report = persistence
+        .rewrap_data_keys(&[IdentityKey::from_identity(&retired)], &IdentityKey::from_identity(&current))
+        .await
+        .expect("Failed to re-wrap data keys");
+    assert_eq!(report.rewrapped, persistence.load_all_blocks().await.expect("Failed to load blocks").len());
+    assert_eq!(report.unknown, 0);
+    let mut vfs = VirtualFileSystem::new(config, persistence).await.expect("Failed to create VFS");
+    vfs.set_identity(&current);
+    assert_eq!(vfs.read_file(&path).await.expect("Failed to read file"), data);
+}
@@ -0,0 +1,259 @@
+// Multi-node integration tests on the simulated network
+// Nodes run the real P2PNode over the in-process memory transport; every assertion waits on
+// a condition with a deadline rather than on a fixed sleep
+use msscs_v4::block::{DataBlock, DataKey, IdentityKey};
+use msscs_v4::identity::QuantumIdentity;
+use msscs_v4::key_rotation::{self, IdentityKeys, RotationStatement};
+use msscs_v4::p2p_network::P2PNodeCommand;
+use msscs_v4::recovery::{self, RecoveryContact, RecoveryStatus, RecoverySession, RecoveryVault};
+use msscs_v4::shard_store::ShardStoreConfig;
+use msscs_v4::simulation::{wait_until, SimCluster, SimNetwork};
+use msscs_v4::unlocked_identity::UnlockedIdentity;
+use msscs_v4::workspace::WorkspaceManager;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn block(payload: &[u8]) -> DataBlock {
+    let data_key = DataKey::generate(&IdentityKey::from_bytes([1u8; 32])).unwrap();
+    DataBlock::new(payload, 0, None, [0u8; 32], &data_key).expect("Failed to create block")
+}
+
+fn unlocked(name: &str) -> UnlockedIdentity {
+    QuantumIdentity::new(name.to_string(), "passphrase")
+        .unwrap()
+        .unlock("passphrase")
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_dht_converges() {
+    let cluster = SimCluster::start(SimNetwork::new(1), 5).await.unwrap();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+
+    for index in 0..5 {
+        assert_eq!(cluster.routing_table(index).await.unwrap().len(), 4);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_upload_replicates() {
+    let cluster = SimCluster::start(SimNetwork::new(2), 5).await.unwrap();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+
+    let block = block(b"replicated across the simulated swarm");
+    assert_eq!(cluster.replicate_block(0, &block, 2).await.unwrap(), 2);
+    let holders = cluster.wait_for_replication(block.uuid, 3, TIMEOUT).await.unwrap();
+    assert!(holders.contains(&0));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_replicas_respect_peer_quota() {
+    let first = block(b"fills the quota");
+    let second = block(b"over the quota!");
+    let size = bincode::serialize(&first).unwrap().len() as u64;
+    // Node 1 keeps exactly one block's worth of replicas for each peer
+    let cluster = SimCluster::start_with(SimNetwork::new(23), 2, |index, config| {
+        if index == 1 {
+            config.replica_store = ShardStoreConfig { max_bytes_per_peer: size, ..config.replica_store.clone() };
+        }
+    })
+    .await
+    .unwrap();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+
+    assert_eq!(cluster.replicate_block(0, &first, 1).await.unwrap(), 1);
+    wait_until(TIMEOUT, || async { cluster.node(1).holds(&first.uuid).await }).await.unwrap();
+
+    assert_eq!(cluster.replicate_block(0, &second, 1).await.unwrap(), 0);
+    assert!(!cluster.node(1).holds(&second.uuid).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_download_after_node_loss() {
+    let cluster = SimCluster::start(SimNetwork::new(3), 5).await.unwrap();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+
+    let block = block(b"survives the loss of its uploader");
+    cluster.replicate_block(1, &block, 2).await.unwrap();
+    let holders = cluster.wait_for_replication(block.uuid, 3, TIMEOUT).await.unwrap();
+
+    // Crash the uploader and one replica; the last copy still serves the block
+    let survivor = *holders.iter().find(|index| **index != 1).unwrap();
+    for index in holders.iter().filter(|index| **index != survivor) {
+        cluster.crash(*index).await.unwrap();
+    }
+    let reader = cluster.live_nodes().into_iter().find(|index| *index != survivor).unwrap();
+    let fetched = cluster.get_block(reader, block.uuid).await.unwrap();
+    assert_eq!(fetched.uuid, block.uuid);
+    assert!(fetched.verify());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_chunks_replicate_by_cid() {
+    let cluster = SimCluster::start(SimNetwork::new(7), 4).await.unwrap();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+
+    let sealed = b"sealed chunk shared by every file that contains it".to_vec();
+    let (cid, accepted) = cluster.replicate_chunk(0, &sealed, 2).await.unwrap();
+    assert_eq!(accepted, 2);
+    wait_until(TIMEOUT, || async {
+        let mut copies = 0;
+        for node in &cluster.nodes {
+            if node.holds_chunk(&cid).await {
+                copies += 1;
+            }
+        }
+        copies >= 3
+    }).await.unwrap();
+
+    // The uploader goes away; a node without a copy finds one through the DHT
+    cluster.crash(0).await.unwrap();
+    let mut readers = Vec::new();
+    for index in cluster.live_nodes() {
+        if !cluster.node(index).holds_chunk(&cid).await {
+            readers.push(index);
+        }
+    }
+    assert_eq!(cluster.get_chunk(readers[0], &cid).await.unwrap(), sealed);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_chunk_replicas_respect_peer_quota() {
+    let (first, second) = (b"sealed chunk fills the quota".to_vec(), b"sealed chunk over the quota".to_vec());
+    // Node 1 keeps exactly one chunk's worth of replicas for each peer
+    let cluster = SimCluster::start_with(SimNetwork::new(24), 2, |index, config| {
+        if index == 1 {
+            config.replica_store = ShardStoreConfig { max_bytes_per_peer: first.len() as u64, ..config.replica_store.clone() };
+        }
+    })
+    .await
+    .unwrap();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+
+    let (cid, accepted) = cluster.replicate_chunk(0, &first, 1).await.unwrap();
+    assert_eq!(accepted, 1);
+    wait_until(TIMEOUT, || async { cluster.node(1).holds_chunk(&cid).await }).await.unwrap();
+
+    let (cid, accepted) = cluster.replicate_chunk(0, &second, 1).await.unwrap();
+    assert_eq!(accepted, 0);
+    assert!(!cluster.node(1).holds_chunk(&cid).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_partition_and_heal() {
+    let cluster = SimCluster::start(SimNetwork::new(4), 5).await.unwrap();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+
+    cluster.partition(&[&[0, 1], &[2, 3, 4]]).await.unwrap();
+    let block = block(b"only on one side of the partition");
+    cluster.replicate_block(2, &block, 2).await.unwrap();
+    cluster.wait_for_replication(block.uuid, 3, TIMEOUT).await.unwrap();
+    assert!(!cluster.node(0).holds(&block.uuid).await);
+    assert!(cluster.get_block(0, block.uuid).await.is_err());
+
+    cluster.heal();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+    let fetched = cluster.get_block(0, block.uuid).await.unwrap();
+    assert_eq!(fetched.uuid, block.uuid);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_latency_and_loss() {
+    let network = SimNetwork::new(5);
+    network.set_latency(Duration::from_millis(20));
+    network.set_loss(0.02);
+    let cluster = SimCluster::start(network, 4).await.unwrap();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+
+    let block = block(&vec![7u8; 64 * 1024]);
+    cluster.replicate_block(0, &block, 1).await.unwrap();
+    let holders = cluster.wait_for_replication(block.uuid, 2, TIMEOUT).await.unwrap();
+    let reader = (0..4).find(|index| !holders.contains(index)).unwrap();
+    assert_eq!(cluster.get_block(reader, block.uuid).await.unwrap().uuid, block.uuid);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_social_recovery_over_network() {
+    let owner = unlocked("owner");
+    let contacts: Vec<UnlockedIdentity> = ["alice", "bob", "carol"].into_iter().map(unlocked).collect();
+    let vaults: Vec<RecoveryVault> = contacts.iter().map(|c| RecoveryVault::new(*c.user_id())).collect();
+    // Node 0 is the owner's (and later the new device's), nodes 1-3 the contacts'
+    let cluster = SimCluster::start_with(SimNetwork::new(6), 4, |index, config| {
+        config.recovery_vault = index.checked_sub(1).map(|contact| vaults[contact].clone());
+    })
+    .await
+    .unwrap();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+
+    let commands = cluster.node(0).commands();
+    let cards: Vec<RecoveryContact> = contacts
+        .iter()
+        .zip(&cluster.nodes[1..])
+        .map(|(contact, node)| RecoveryContact::new(&contact.identity, &node.address).unwrap())
+        .collect();
+    for (card, share) in cards.iter().zip(recovery::split_identity(&owner, &cards, 2).unwrap()) {
+        recovery::send_share(&commands, &card.address().unwrap(), share).await.unwrap();
+    }
+
+    let mut session = RecoverySession::new(*owner.user_id(), "new phone").unwrap();
+    let request = session.request().clone();
+    for card in &cards {
+        let status = recovery::ask_contact(&commands, &card.address().unwrap(), &request).await.unwrap();
+        assert_eq!(status, RecoveryStatus::Pending);
+    }
+
+    // Two contacts compare the code and approve; the third never answers
+    for index in [0, 2] {
+        assert_eq!(vaults[index].requests()[0].request.fingerprint(), request.fingerprint());
+        vaults[index].approve(&request.request_id, &contacts[index]).unwrap();
+        match recovery::ask_contact(&commands, &cards[index].address().unwrap(), &request).await.unwrap() {
+            RecoveryStatus::Approved(released) => {
+                session.add_share(&released).unwrap();
+            }
+            other => panic!("expected an approved share, got {:?}", other),
+        }
+    }
+
+    let recovered = session.recover("new passphrase").unwrap();
+    assert_eq!(recovered.unlock("new passphrase").unwrap().master_key(), owner.master_key());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_rotation_chain_is_followed_over_dht() {
+    let cluster = SimCluster::start(SimNetwork::new(7), 4).await.unwrap();
+    cluster.wait_for_dht_convergence(TIMEOUT).await.unwrap();
+
+    // A workspace knows the member's first key generation
+    let first = unlocked("member");
+    let workspaces = WorkspaceManager::new();
+    let workspace = workspaces.create_workspace("team".to_string(), String::new(), *first.user_id(), "member@example.com".to_string(), &IdentityKey::from_identity(&first)).await.unwrap();
+    workspaces.set_member_keys(workspace, *first.user_id(), IdentityKeys::of(&first.identity)).await.unwrap();
+
+    let current = first.rotate("passphrase").unwrap().rotate("passphrase").unwrap();
+    key_rotation::publish_rotations(&cluster.node(0).commands(), &current.identity).await.unwrap();
+
+    let known = IdentityKeys::of(&first.identity);
+    let chain = key_rotation::lookup_rotations(&cluster.node(3).commands(), *first.user_id(), &known).await.unwrap();
+    assert_eq!(chain.follow(&known).unwrap(), IdentityKeys::of(&current.identity));
+    assert_eq!(workspaces.apply_rotation(&chain).await.unwrap(), 1);
+    let member = &workspaces.get_workspace(&workspace).await.unwrap().members[first.user_id()];
+    assert_eq!(member.keys.as_ref().unwrap().version, 3);
+
+    // A statement forged under the key of the member's first generation is refused by the
+    // nodes storing it and ignored by the lookup
+    let mut forged = unlocked("mallory").rotate("passphrase").unwrap().identity.rotations.remove(0);
+    forged.identity_id = *first.user_id();
+    forged.retired = known.clone();
+    let (reply, response) = tokio::sync::oneshot::channel();
+    cluster.node(1).commands().send(P2PNodeCommand::PutRecord {
+        key: RotationStatement::record_key(first.user_id(), &known),
+        value: bincode::serialize(&forged).unwrap(),
+        reply,
+    }).unwrap();
+    let _ = response.await.unwrap();
+    for index in [1, 2] {
+        let chain = key_rotation::lookup_rotations(&cluster.node(index).commands(), *first.user_id(), &known).await.unwrap();
+        assert_eq!(chain.follow(&known).unwrap(), IdentityKeys::of(&current.identity));
+    }
+}
@@ -0,0 +1,175 @@
+// Integration tests for STUN NAT classification against a local STUN server stand-in
+// The stand-in listens on two loopback IPs x two ports (RFC 5780 layout) and plays the NAT
+// itself: it reports the mapped address the simulated NAT would assign and drops the replies
+// the simulated NAT would filter
+use msscs_v4::relay::{NATType, RelayConfig, RelayManager};
+use msscs_v4::stun::{
+    FilteringBehavior, MappingBehavior, StunAttribute, StunClient, StunMessage, StunMessageType,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const PUBLIC_IP: [u8; 4] = [203, 0, 113, 10];
+
+#[derive(Clone, Copy, PartialEq)]
+enum SimulatedNat {
+    None,
+    FullCone,
+    RestrictedCone,
+    PortRestrictedCone,
+    Symmetric,
+}
+
+struct StunStandIn {
+    /// [primary, primary IP + alternate port, alternate IP + primary port, alternate IP + alternate port]
+    addrs: [SocketAddr; 4],
+}
+
+impl StunStandIn {
+    async fn start(nat: SimulatedNat, rfc5780: bool) -> Self {
+        let sockets = Arc::new(bind_quad().await);
+        let addrs = [0, 1, 2, 3].map(|i| sockets[i].local_addr().unwrap());
+        for index in 0..4 {
+            let sockets = sockets.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                loop {
+                    let Ok((len, src)) = sockets[index].recv_from(&mut buf).await else { return };
+                    let Ok(request) = StunMessage::decode(&buf[..len]) else { continue };
+                    let change = request.change_request();
+                    // Bit 1 of the index selects the IP, bit 0 the port
+                    let from = index ^ (usize::from(change.changes_ip()) << 1) ^ usize::from(change.changes_port());
+                    let filtered = match nat {
+                        SimulatedNat::None | SimulatedNat::FullCone | SimulatedNat::Symmetric => false,
+                        SimulatedNat::RestrictedCone => change.changes_ip(),
+                        SimulatedNat::PortRestrictedCone => change.changes_port(),
+                    };
+                    if filtered {
+                        continue;
+                    }
+                    let mapped = match nat {
+                        SimulatedNat::None => src,
+                        SimulatedNat::Symmetric => SocketAddr::from((PUBLIC_IP, 50000 + index as u16)),
+                        _ => SocketAddr::from((PUBLIC_IP, 50000)),
+                    };
+                    let mut attributes = vec![StunAttribute::XorMappedAddress(mapped)];
+                    if rfc5780 {
+                        attributes.push(StunAttribute::OtherAddress(addrs[index ^ 3]));
+                        attributes.push(StunAttribute::ResponseOrigin(addrs[from]));
+                    }
+                    let response = StunMessage {
+                        message_type: StunMessageType::BindingSuccess,
+                        transaction_id: request.transaction_id,
+                        attributes,
+                    };
+                    let _ = sockets[from].send_to(&response.encode(), src).await;
+                }
+            });
+        }
+        Self { addrs }
+    }
+
+    fn primary(&self) -> SocketAddr {
+        self.addrs[0]
+    }
+}
+
+/// Bind 127.0.0.1 and 127.0.0.2 on the same two ports
+async fn bind_quad() -> [UdpSocket; 4] {
+    loop {
+        let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (pa, pb) = (a.local_addr().unwrap().port(), b.local_addr().unwrap().port());
+        let (Ok(c), Ok(d)) = (
+            UdpSocket::bind(("127.0.0.2", pa)).await,
+            UdpSocket::bind(("127.0.0.2", pb)).await,
+        ) else {
+            continue;
+        };
+        return [a, b, c, d];
+    }
+}
+
+async fn client() -> StunClient {
+    StunClient::bind("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap()
+        .with_timeouts(Duration::from_millis(50), 2)
+}
+
+#[tokio::test]
+async fn test_stun_classifies_nat_types() {
+    let cases = [
+        (SimulatedNat::None, NATType::None),
+        (SimulatedNat::FullCone, NATType::FullCone),
+        (SimulatedNat::RestrictedCone, NATType::RestrictedCone),
+        (SimulatedNat::PortRestrictedCone, NATType::PortRestrictedCone),
+        (SimulatedNat::Symmetric, NATType::Symmetric),
+    ];
+
+    for (nat, expected) in cases {
+        let server = StunStandIn::start(nat, true).await;
+        let client = client().await;
+        let report = client.classify(server.primary()).await.unwrap();
+        assert_eq!(report.nat_type, expected);
+
+        if nat == SimulatedNat::None {
+            assert_eq!(report.public_addr, client.local_addr().unwrap());
+        } else {
+            assert_eq!(report.public_addr.ip(), std::net::IpAddr::from(PUBLIC_IP));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_stun_reports_mapping_and_filtering() {
+    let server = StunStandIn::start(SimulatedNat::RestrictedCone, true).await;
+    let report = client().await.classify(server.primary()).await.unwrap();
+    assert_eq!(report.mapping, MappingBehavior::EndpointIndependent);
+    assert_eq!(report.filtering, Some(FilteringBehavior::AddressDependent));
+
+    let server = StunStandIn::start(SimulatedNat::Symmetric, true).await;
+    let report = client().await.classify(server.primary()).await.unwrap();
+    assert_eq!(report.mapping, MappingBehavior::AddressAndPortDependent);
+    assert_eq!(report.filtering, None);
+}
+
+#[tokio::test]
+async fn test_stun_server_pair_without_rfc5780() {
+    // Plain RFC 5389 servers cannot test filtering, but two of them still expose a symmetric NAT
+    let first = StunStandIn::start(SimulatedNat::Symmetric, false).await;
+    let second = StunStandIn::start(SimulatedNat::Symmetric, false).await;
+    let client = client().await;
+    let report = client.classify_with_pair(first.primary(), second.addrs[1]).await.unwrap();
+    assert_eq!(report.nat_type, NATType::Symmetric);
+
+    let first = StunStandIn::start(SimulatedNat::FullCone, false).await;
+    let second = StunStandIn::start(SimulatedNat::FullCone, false).await;
+    let report = client.classify_with_pair(first.primary(), second.primary()).await.unwrap();
+    assert_eq!(report.nat_type, NATType::PortRestrictedCone);
+    assert_eq!(report.filtering, None);
+}
+
+#[tokio::test]
+async fn test_relay_manager_detects_nat_type() {
+    let server = StunStandIn::start(SimulatedNat::FullCone, true).await;
+    let mut manager = RelayManager::new(RelayConfig::default());
+    let stun_servers = vec!["unresolvable.invalid:3478".to_string(), server.primary().to_string()];
+
+    let nat = manager
+        .detect_nat_type("127.0.0.1:0".parse().unwrap(), &stun_servers)
+        .await
+        .unwrap();
+    assert_eq!(nat, NATType::FullCone);
+    assert_eq!(manager.local_nat_type(), NATType::FullCone);
+    assert_eq!(manager.public_addr(), Some(SocketAddr::from((PUBLIC_IP, 50000))));
+
+    // Without STUN servers only a public local address can be recognized
+    let nat = manager
+        .detect_nat_type("10.0.0.1:0".parse().unwrap(), &[])
+        .await
+        .unwrap();
+    assert_eq!(nat, NATType::Unknown);
+}